@@ -2,9 +2,13 @@
 
 use futures::future::join_all;
 use futures::stream::{self, StreamExt};
-use reqwest::Client;
+use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeMap;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 use thiserror::Error;
 use tracing::{info, instrument, warn};
 
@@ -25,6 +29,9 @@ pub enum ApiError {
 
     #[error("Failed to parse response: {0}")]
     ParseError(String),
+
+    #[error("Download budget of {budget} bytes exceeded")]
+    BudgetExceeded { budget: u64 },
 }
 
 // ============================================================================
@@ -80,12 +87,146 @@ pub struct AggregatedData {
     pub fetch_stats: FetchStats,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Default, Serialize)]
 pub struct FetchStats {
     pub total_requests: usize,
     pub successful: usize,
     pub failed: usize,
     pub total_duration_ms: u128,
+    /// Body bytes read across all endpoints
+    pub bytes_downloaded: u64,
+    /// What each endpoint downloaded, keyed by its path (e.g. "users")
+    pub endpoints: BTreeMap<String, Transfer>,
+}
+
+/// Bytes downloaded by one request, over all its attempts
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+pub struct Transfer {
+    /// Size announced by the last attempt's Content-Length header, if the
+    /// server sent one
+    pub content_length: Option<u64>,
+    /// Size of the bodies actually read, counting every attempt
+    pub body_bytes: u64,
+}
+
+/// The endpoints every aggregation fetches, as paths under the base URL
+pub const ENDPOINTS: [&str; 4] = ["users", "posts", "todos", "comments"];
+
+/// Each endpoint's transfer keyed by its path, given in `ENDPOINTS` order
+fn by_endpoint(transfers: [Transfer; ENDPOINTS.len()]) -> BTreeMap<String, Transfer> {
+    ENDPOINTS
+        .into_iter()
+        .map(String::from)
+        .zip(transfers)
+        .collect()
+}
+
+// ============================================================================
+// Health Checks
+// ============================================================================
+
+/// How one endpoint answered a health check
+#[derive(Debug, Clone, Serialize)]
+pub struct EndpointHealth {
+    pub endpoint: String,
+    /// Whether it answered with a success status
+    pub healthy: bool,
+    /// The status it answered with; none if it could not be reached
+    pub status: Option<u16>,
+    pub latency_ms: u128,
+    /// Why it could not be reached, or the status it answered with was not
+    /// a success
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthReport {
+    pub endpoints: Vec<EndpointHealth>,
+    pub total_duration_ms: u128,
+}
+
+impl HealthReport {
+    /// Whether every endpoint is healthy, for a readiness probe
+    pub fn is_healthy(&self) -> bool {
+        self.endpoints.iter().all(|e| e.healthy)
+    }
+}
+
+// ============================================================================
+// Retries
+// ============================================================================
+
+/// When and how long to wait before trying a failed request again. Requests
+/// that fail to send or to arrive are retried, as are those answered with a
+/// status in `retry_on_status`; other failures are returned at once.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts at most, including the first; 1 never retries
+    pub max_attempts: u32,
+    /// Wait before the first retry, doubled before each one after it
+    pub base_delay: Duration,
+    /// Longest wait between attempts, however many have failed
+    pub max_delay: Duration,
+    /// Fraction of each wait, from 0 to 1, to take off at random so that
+    /// clients failing together do not all retry together
+    pub jitter: f64,
+    /// Statuses worth trying again
+    pub retry_on_status: Vec<u16>,
+}
+
+impl Default for RetryPolicy {
+    /// Three attempts, 200ms apart and then 400ms, half jittered, retrying
+    /// timeouts, rate limits and server errors
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(10),
+            jitter: 0.5,
+            retry_on_status: vec![408, 429, 500, 502, 503, 504],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Try every request once only
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Whether a request that failed with `error` is worth trying again
+    pub fn should_retry(&self, error: &ApiError) -> bool {
+        match error {
+            ApiError::RequestFailed(_) | ApiError::Timeout(_) => true,
+            ApiError::ApiError { status, .. } => self.retry_on_status.contains(status),
+            ApiError::ParseError(_) | ApiError::BudgetExceeded { .. } => false,
+        }
+    }
+
+    /// How long to wait after the `attempt`th attempt (from 1) fails, before
+    /// jitter
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let doublings = attempt.saturating_sub(1).min(31);
+        self.base_delay
+            .saturating_mul(1 << doublings)
+            .min(self.max_delay)
+    }
+
+    /// `delay`, less a random part of `jitter` of it
+    fn jittered_delay(&self, attempt: u32) -> Duration {
+        let jitter = self.jitter.clamp(0.0, 1.0) * random_fraction();
+        self.delay(attempt).mul_f64(1.0 - jitter)
+    }
+}
+
+/// A number from 0 up to 1, random enough to spread retries out: each
+/// `RandomState` is seeded differently
+fn random_fraction() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
 }
 
 // ============================================================================
@@ -95,6 +236,11 @@ pub struct FetchStats {
 pub struct ApiAggregator {
     client: Client,
     base_url: String,
+    /// Most body bytes all requests together may download, if limited
+    download_budget: Option<u64>,
+    downloaded: AtomicU64,
+    /// How every fetch retries, unless given its own policy
+    retry_policy: RetryPolicy,
 }
 
 impl ApiAggregator {
@@ -108,24 +254,137 @@ impl ApiAggregator {
         Ok(Self {
             client,
             base_url: base_url.to_string(),
+            download_budget: None,
+            downloaded: AtomicU64::new(0),
+            retry_policy: RetryPolicy::default(),
         })
     }
 
+    /// Retry failed requests by `policy` rather than `RetryPolicy::default()`
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Limit the bytes all requests together may download, for metered
+    /// connections. A request that would go over fails with
+    /// `ApiError::BudgetExceeded`, as does every request after it.
+    pub fn with_download_budget(mut self, bytes: u64) -> Self {
+        self.download_budget = Some(bytes);
+        self
+    }
+
+    /// Body bytes downloaded so far, by every request
+    pub fn bytes_downloaded(&self) -> u64 {
+        self.downloaded.load(Ordering::Relaxed)
+    }
+
+    /// Fail if downloading `more` bytes would go over the budget
+    fn check_budget(&self, more: u64) -> Result<(), ApiError> {
+        match self.download_budget {
+            Some(budget) if self.bytes_downloaded() + more > budget => {
+                Err(ApiError::BudgetExceeded { budget })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Count `len` more downloaded bytes, or fail if they would go over the
+    /// budget. Checking and counting are one atomic step, so concurrent
+    /// requests cannot all pass the check and overshoot the budget together.
+    fn reserve(&self, len: u64) -> Result<(), ApiError> {
+        let budget = self.download_budget;
+        self.downloaded
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |done| match budget {
+                Some(budget) if done + len > budget => None,
+                _ => Some(done + len),
+            })
+            .map(|_| ())
+            .map_err(|_| ApiError::BudgetExceeded {
+                budget: budget.unwrap_or(u64::MAX),
+            })
+    }
+
     /// Generic fetch function for any deserializable type
-    #[instrument(skip(self), fields(url = %url))]
     async fn fetch<T: for<'de> Deserialize<'de>>(&self, url: &str) -> Result<T, ApiError> {
-        info!("Fetching from {}", url);
+        self.fetch_measured(url, &self.retry_policy).await.0
+    }
+
+    /// Fetch `url`, retrying by `policy` instead of the aggregator's own
+    pub async fn fetch_with_retry<T: for<'de> Deserialize<'de>>(
+        &self,
+        url: &str,
+        policy: &RetryPolicy,
+    ) -> Result<T, ApiError> {
+        self.fetch_measured(url, policy).await.0
+    }
 
-        let response = self.client.get(url).send().await?;
+    /// Fetch `url`, retrying by `policy`, also returning how many bytes it
+    /// downloaded, even if it failed
+    #[instrument(skip(self, policy), fields(url = %url))]
+    async fn fetch_measured<T: for<'de> Deserialize<'de>>(
+        &self,
+        url: &str,
+        policy: &RetryPolicy,
+    ) -> (Result<T, ApiError>, Transfer) {
+        let mut transfer = Transfer::default();
+        let mut attempt = 1;
+        loop {
+            let result = self.fetch_counting(url, &mut transfer).await;
+            match result {
+                Err(e) if attempt < policy.max_attempts && policy.should_retry(&e) => {
+                    let delay = policy.jittered_delay(attempt);
+                    warn!(
+                        "Attempt {} of {} failed: {}; retrying in {:?}",
+                        attempt, policy.max_attempts, e, delay
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return (result, transfer),
+            }
+        }
+    }
+
+    /// Download `url` a chunk at a time, counting each against the budget
+    async fn fetch_counting<T: for<'de> Deserialize<'de>>(
+        &self,
+        url: &str,
+        transfer: &mut Transfer,
+    ) -> Result<T, ApiError> {
+        info!("Fetching from {}", url);
+        transfer.content_length = None;
+        self.check_budget(0)?;
+
+        let mut response = self.client.get(url).send().await?;
+        transfer.content_length = response.content_length();
+        self.check_budget(transfer.content_length.unwrap_or(0))?;
+
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            let len = chunk.len() as u64;
+            self.reserve(len)?;
+            transfer.body_bytes += len;
+            body.extend_from_slice(&chunk);
+        }
 
         if !response.status().is_success() {
             return Err(ApiError::ApiError {
                 status: response.status().as_u16(),
-                message: response.text().await.unwrap_or_default(),
+                message: String::from_utf8_lossy(&body).into_owned(),
             });
         }
 
-        response.json::<T>().await.map_err(ApiError::from)
+        serde_json::from_slice(&body).map_err(|e| ApiError::ParseError(e.to_string()))
+    }
+
+    /// Fetch one of the base URL's endpoints, measuring it
+    async fn fetch_endpoint<T: for<'de> Deserialize<'de>>(
+        &self,
+        path: &str,
+    ) -> (Result<T, ApiError>, Transfer) {
+        let url = format!("{}/{}", self.base_url, path);
+        self.fetch_measured(&url, &self.retry_policy).await
     }
 
     /// Fetch users from the API
@@ -160,30 +419,34 @@ impl ApiAggregator {
     /// Use when all data is required
     #[instrument(skip(self))]
     pub async fn fetch_all_or_nothing(&self) -> Result<AggregatedData, ApiError> {
-        let start = std::time::Instant::now();
+        let start = Instant::now();
 
         // tokio::join! runs all futures concurrently and returns a tuple
         // This is the idiomatic way when futures return different types
+        let [users_path, posts_path, todos_path, comments_path] = ENDPOINTS;
         let (users, posts, todos, comments) = tokio::join!(
-            self.fetch_users(),
-            self.fetch_posts(),
-            self.fetch_todos(),
-            self.fetch_comments(),
+            self.fetch_endpoint::<Vec<User>>(users_path),
+            self.fetch_endpoint::<Vec<Post>>(posts_path),
+            self.fetch_endpoint::<Vec<Todo>>(todos_path),
+            self.fetch_endpoint::<Vec<Comment>>(comments_path),
         );
 
         let duration = start.elapsed();
+        let endpoints = by_endpoint([users.1, posts.1, todos.1, comments.1]);
 
         // Propagate any errors with ?
         Ok(AggregatedData {
-            users: users?,
-            posts: posts?,
-            todos: todos?,
-            comments: comments?,
+            users: users.0?,
+            posts: posts.0?,
+            todos: todos.0?,
+            comments: comments.0?,
             fetch_stats: FetchStats {
-                total_requests: 4,
-                successful: 4,
+                total_requests: ENDPOINTS.len(),
+                successful: ENDPOINTS.len(),
                 failed: 0,
                 total_duration_ms: duration.as_millis(),
+                bytes_downloaded: endpoints.values().map(|t| t.body_bytes).sum(),
+                endpoints,
             },
         })
     }
@@ -192,16 +455,19 @@ impl ApiAggregator {
     /// Use when partial data is acceptable
     #[instrument(skip(self))]
     pub async fn fetch_best_effort(&self) -> AggregatedData {
-        let start = std::time::Instant::now();
+        let start = Instant::now();
 
+        let [users_path, posts_path, todos_path, comments_path] = ENDPOINTS;
         let (users, posts, todos, comments) = tokio::join!(
-            self.fetch_users(),
-            self.fetch_posts(),
-            self.fetch_todos(),
-            self.fetch_comments(),
+            self.fetch_endpoint::<Vec<User>>(users_path),
+            self.fetch_endpoint::<Vec<Post>>(posts_path),
+            self.fetch_endpoint::<Vec<Todo>>(todos_path),
+            self.fetch_endpoint::<Vec<Comment>>(comments_path),
         );
 
         let duration = start.elapsed();
+        let endpoints = by_endpoint([users.1, posts.1, todos.1, comments.1]);
+        let (users, posts, todos, comments) = (users.0, posts.0, todos.0, comments.0);
 
         let mut successful = 0;
         let mut failed = 0;
@@ -209,7 +475,7 @@ impl ApiAggregator {
         let users = users
             .inspect(|_| successful += 1)
             .inspect_err(|e| {
-                warn!("Failed to fetch users: {}", e);
+                warn!("Failed to fetch {}: {}", users_path, e);
                 failed += 1;
             })
             .unwrap_or_default();
@@ -217,7 +483,7 @@ impl ApiAggregator {
         let posts = posts
             .inspect(|_| successful += 1)
             .inspect_err(|e| {
-                warn!("Failed to fetch posts: {}", e);
+                warn!("Failed to fetch {}: {}", posts_path, e);
                 failed += 1;
             })
             .unwrap_or_default();
@@ -225,7 +491,7 @@ impl ApiAggregator {
         let todos = todos
             .inspect(|_| successful += 1)
             .inspect_err(|e| {
-                warn!("Failed to fetch todos: {}", e);
+                warn!("Failed to fetch {}: {}", todos_path, e);
                 failed += 1;
             })
             .unwrap_or_default();
@@ -233,7 +499,7 @@ impl ApiAggregator {
         let comments = comments
             .inspect(|_| successful += 1)
             .inspect_err(|e| {
-                warn!("Failed to fetch comments: {}", e);
+                warn!("Failed to fetch {}: {}", comments_path, e);
                 failed += 1;
             })
             .unwrap_or_default();
@@ -244,14 +510,70 @@ impl ApiAggregator {
             todos,
             comments,
             fetch_stats: FetchStats {
-                total_requests: 4,
+                total_requests: ENDPOINTS.len(),
                 successful,
                 failed,
                 total_duration_ms: duration.as_millis(),
+                bytes_downloaded: endpoints.values().map(|t| t.body_bytes).sum(),
+                endpoints,
             },
         }
     }
 
+    // ========================================================================
+    // Health Checks
+    // ========================================================================
+
+    /// Probe every endpoint concurrently, once each with no retries, and
+    /// report how each answered and how long it took. Probes send HEAD,
+    /// falling back to a GET whose body is never read for servers that do
+    /// not support HEAD, so they cost next to nothing against the download
+    /// budget.
+    #[instrument(skip(self))]
+    pub async fn health_check(&self) -> HealthReport {
+        let start = Instant::now();
+        let endpoints = join_all(ENDPOINTS.iter().map(|path| self.probe(path))).await;
+
+        HealthReport {
+            endpoints,
+            total_duration_ms: start.elapsed().as_millis(),
+        }
+    }
+
+    async fn probe(&self, path: &str) -> EndpointHealth {
+        let url = format!("{}/{}", self.base_url, path);
+        let start = Instant::now();
+        let mut response = self.client.head(&url).send().await;
+        if let Ok(head) = &response {
+            if matches!(
+                head.status(),
+                StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED
+            ) {
+                response = self.client.get(&url).send().await;
+            }
+        }
+        let latency_ms = start.elapsed().as_millis();
+
+        let (status, error) = match response {
+            Ok(response) if response.status().is_success() => (Some(response.status()), None),
+            Ok(response) => (
+                Some(response.status()),
+                Some(format!("status {}", response.status())),
+            ),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        if let Some(error) = &error {
+            warn!("Health check of {} failed: {}", path, error);
+        }
+        EndpointHealth {
+            endpoint: path.to_string(),
+            healthy: error.is_none(),
+            status: status.map(|s| s.as_u16()),
+            latency_ms,
+            error,
+        }
+    }
+
     /// Strategy 3: Fetch from multiple URLs of the same type concurrently
     /// Useful for paginated APIs or multiple similar endpoints
     #[instrument(skip(self, urls))]
@@ -319,7 +641,8 @@ impl AggregatedData {
              - Posts: {}\n\
              - Todos: {} ({} completed)\n\
              - Comments: {}\n\
-             - Fetch time: {}ms ({} successful, {} failed)",
+             - Fetch time: {}ms ({} successful, {} failed)\n\
+             - Downloaded: {} bytes",
             self.users.len(),
             self.posts.len(),
             self.todos.len(),
@@ -328,6 +651,7 @@ impl AggregatedData {
             self.fetch_stats.total_duration_ms,
             self.fetch_stats.successful,
             self.fetch_stats.failed,
+            self.fetch_stats.bytes_downloaded,
         )
     }
 }
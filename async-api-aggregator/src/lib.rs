@@ -5,6 +5,7 @@
 //! - Proper error handling with custom error types
 //! - Timeouts and retries
 //! - Result aggregation with partial failure support
+//! - Health checks of every endpoint, for readiness probes
 
 mod aggregator;
 
@@ -1,9 +1,14 @@
 //! Integration tests for async-api-aggregator
 //!
-//! These tests hit the live JSONPlaceholder API.
+//! These tests hit the live JSONPlaceholder API, except those under "Local
+//! Server Tests", which serve canned responses from 127.0.0.1.
 //! Run with: cargo test -- --nocapture
 
 use async_api_aggregator::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
 
 // ============================================================================
 // API Fetch Tests
@@ -164,6 +169,7 @@ fn test_aggregated_data_summary() {
             successful: 4,
             failed: 0,
             total_duration_ms: 100,
+            ..Default::default()
         },
     };
 
@@ -186,6 +192,7 @@ fn test_empty_user_completion_rate() {
             successful: 0,
             failed: 0,
             total_duration_ms: 0,
+            ..Default::default()
         },
     };
 
@@ -199,3 +206,262 @@ fn test_api_aggregator_creation() {
     let aggregator = ApiAggregator::new("https://example.com", 30);
     assert!(aggregator.is_ok());
 }
+
+// ============================================================================
+// Local Server Tests
+// ============================================================================
+
+/// Serve `respond(method, path)` as a JSON response with that status and
+/// body to every request, returning the base URL to reach it at
+async fn serve(respond: fn(&str, &str) -> (u16, String)) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let Ok((mut socket, _)) = listener.accept().await else {
+                break;
+            };
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match socket.read(&mut buf).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let request = String::from_utf8_lossy(&request);
+                let mut words = request.split_whitespace();
+                let method = words.next().unwrap_or("GET");
+                let path = words.next().unwrap_or("/");
+                let (status, body) = respond(method, path);
+                let response = format!(
+                    "HTTP/1.1 {} X\r\nContent-Type: application/json\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    if method == "HEAD" { "" } else { &body }
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+            });
+        }
+    });
+    format!("http://{}", addr)
+}
+
+/// An empty list from every endpoint
+fn empty_lists(_method: &str, _path: &str) -> (u16, String) {
+    (200, "[]".to_string())
+}
+
+#[tokio::test]
+async fn test_bytes_downloaded_per_endpoint() {
+    let base_url = serve(empty_lists).await;
+    let aggregator = ApiAggregator::new(&base_url, 5).unwrap();
+    let data = aggregator.fetch_all_or_nothing().await.unwrap();
+
+    let stats = &data.fetch_stats;
+    assert_eq!(stats.endpoints.len(), 4);
+    for transfer in stats.endpoints.values() {
+        assert_eq!(transfer.content_length, Some(2));
+        assert_eq!(transfer.body_bytes, 2);
+    }
+    assert_eq!(stats.bytes_downloaded, 8);
+    assert_eq!(aggregator.bytes_downloaded(), 8);
+    assert!(data.summary().contains("Downloaded: 8 bytes"));
+}
+
+#[tokio::test]
+async fn test_download_budget_aborts_aggregation() {
+    let base_url = serve(empty_lists).await;
+    let aggregator = ApiAggregator::new(&base_url, 5)
+        .unwrap()
+        .with_download_budget(5);
+    let result = aggregator.fetch_all_or_nothing().await;
+
+    assert!(matches!(
+        result,
+        Err(ApiError::BudgetExceeded { budget: 5 })
+    ));
+    assert!(aggregator.bytes_downloaded() <= 5);
+}
+
+#[tokio::test]
+async fn test_download_budget_counts_failed_endpoints() {
+    let base_url = serve(empty_lists).await;
+    let aggregator = ApiAggregator::new(&base_url, 5)
+        .unwrap()
+        .with_download_budget(4);
+    let data = aggregator.fetch_best_effort().await;
+
+    assert_eq!(data.fetch_stats.successful, 2);
+    assert_eq!(data.fetch_stats.failed, 2);
+    assert_eq!(data.fetch_stats.bytes_downloaded, 4);
+}
+
+/// Retry immediately, up to `max_attempts` times
+fn quick_retries(max_attempts: u32) -> RetryPolicy {
+    RetryPolicy {
+        max_attempts,
+        base_delay: Duration::from_millis(1),
+        jitter: 0.0,
+        ..RetryPolicy::default()
+    }
+}
+
+static FLAKY_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+/// 503 twice, then an empty list
+fn flaky(_method: &str, _path: &str) -> (u16, String) {
+    match FLAKY_CALLS.fetch_add(1, Ordering::SeqCst) {
+        0 | 1 => (503, "unavailable".to_string()),
+        _ => (200, "[]".to_string()),
+    }
+}
+
+#[tokio::test]
+async fn test_retry_until_success() {
+    let base_url = serve(flaky).await;
+    let aggregator = ApiAggregator::new(&base_url, 5)
+        .unwrap()
+        .with_retry_policy(quick_retries(3));
+    let users = aggregator.fetch_users().await.unwrap();
+
+    assert!(users.is_empty());
+    assert_eq!(FLAKY_CALLS.load(Ordering::SeqCst), 3);
+}
+
+static DOWN_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+fn down(_method: &str, _path: &str) -> (u16, String) {
+    DOWN_CALLS.fetch_add(1, Ordering::SeqCst);
+    (503, "unavailable".to_string())
+}
+
+#[tokio::test]
+async fn test_retry_gives_up_after_max_attempts() {
+    let base_url = serve(down).await;
+    let aggregator = ApiAggregator::new(&base_url, 5)
+        .unwrap()
+        .with_retry_policy(quick_retries(2));
+    let result = aggregator.fetch_users().await;
+
+    assert!(matches!(
+        result,
+        Err(ApiError::ApiError { status: 503, .. })
+    ));
+    assert_eq!(DOWN_CALLS.load(Ordering::SeqCst), 2);
+}
+
+static MISSING_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+fn missing(_method: &str, _path: &str) -> (u16, String) {
+    MISSING_CALLS.fetch_add(1, Ordering::SeqCst);
+    (404, "{}".to_string())
+}
+
+#[tokio::test]
+async fn test_no_retry_on_unlisted_status() {
+    let base_url = serve(missing).await;
+    let aggregator = ApiAggregator::new(&base_url, 5)
+        .unwrap()
+        .with_retry_policy(quick_retries(3));
+    let result = aggregator.fetch_users().await;
+
+    assert!(matches!(
+        result,
+        Err(ApiError::ApiError { status: 404, .. })
+    ));
+    assert_eq!(MISSING_CALLS.load(Ordering::SeqCst), 1);
+}
+
+static OVERRIDE_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+/// 503 once, then an empty list
+fn flaky_once(_method: &str, _path: &str) -> (u16, String) {
+    match OVERRIDE_CALLS.fetch_add(1, Ordering::SeqCst) {
+        0 => (503, "unavailable".to_string()),
+        _ => (200, "[]".to_string()),
+    }
+}
+
+#[tokio::test]
+async fn test_retry_policy_per_request_override() {
+    let base_url = serve(flaky_once).await;
+    let aggregator = ApiAggregator::new(&base_url, 5)
+        .unwrap()
+        .with_retry_policy(RetryPolicy::none());
+    let url = format!("{}/users", base_url);
+
+    assert!(aggregator.fetch_users().await.is_err());
+    let users: Vec<User> = aggregator
+        .fetch_with_retry(&url, &quick_retries(2))
+        .await
+        .unwrap();
+    assert!(users.is_empty());
+    assert_eq!(OVERRIDE_CALLS.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn test_retry_delay_backs_off_exponentially() {
+    let policy = RetryPolicy {
+        base_delay: Duration::from_millis(100),
+        max_delay: Duration::from_millis(500),
+        ..RetryPolicy::default()
+    };
+
+    assert_eq!(policy.delay(1), Duration::from_millis(100));
+    assert_eq!(policy.delay(2), Duration::from_millis(200));
+    assert_eq!(policy.delay(3), Duration::from_millis(400));
+    assert_eq!(policy.delay(4), Duration::from_millis(500));
+    assert_eq!(policy.delay(100), Duration::from_millis(500));
+}
+
+/// Posts are down; HEAD is not allowed on todos, which GET still serves
+fn partly_down(method: &str, path: &str) -> (u16, String) {
+    match (method, path) {
+        (_, "/posts") => (503, "unavailable".to_string()),
+        ("HEAD", "/todos") => (405, String::new()),
+        _ => (200, "[]".to_string()),
+    }
+}
+
+#[tokio::test]
+async fn test_health_check_reports_each_endpoint() {
+    let base_url = serve(partly_down).await;
+    let aggregator = ApiAggregator::new(&base_url, 5).unwrap();
+    let report = aggregator.health_check().await;
+
+    assert!(!report.is_healthy());
+    let names: Vec<&str> = report
+        .endpoints
+        .iter()
+        .map(|e| e.endpoint.as_str())
+        .collect();
+    assert_eq!(names, ENDPOINTS);
+    for health in &report.endpoints {
+        let healthy = health.endpoint != "posts";
+        assert_eq!(health.healthy, healthy, "{}", health.endpoint);
+        assert_eq!(health.status, Some(if healthy { 200 } else { 503 }));
+        assert_eq!(health.error.is_none(), healthy);
+    }
+    assert_eq!(aggregator.bytes_downloaded(), 0);
+}
+
+#[tokio::test]
+async fn test_health_check_unreachable() {
+    // Bind a port, then free it, so nothing is listening there
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    drop(listener);
+
+    let aggregator = ApiAggregator::new(&base_url, 5).unwrap();
+    let report = aggregator.health_check().await;
+
+    assert!(!report.is_healthy());
+    assert_eq!(report.endpoints.len(), 4);
+    for health in &report.endpoints {
+        assert_eq!(health.status, None);
+        assert!(health.error.is_some());
+    }
+}
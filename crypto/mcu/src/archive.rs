@@ -0,0 +1,181 @@
+// Directory mode: a directory is packed into a tar archive file by file,
+// straight into the encryptor, so the archive is never held in memory or
+// written out in the clear. Extraction reads it back from the decryptor,
+// which checks each segment before tar sees it. Entries are extracted into a
+// temporary directory next to the output one and only moved into it once the
+// decryptor has checked the end of the file, so an altered file leaves no
+// plaintext behind.
+
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use tar::{Archive, Builder};
+use tempfile::TempDir;
+
+use crate::{McuError, Result};
+
+/// Tar `dir` recursively into `sink`, with its entries under the directory's
+/// own name
+pub fn pack(dir: &str, sink: impl Write) -> Result<()> {
+    let path = Path::new(dir);
+    if !path.is_dir() {
+        return Err(McuError::Input(format!("{} is not a directory", dir)));
+    }
+    let root = path
+        .canonicalize()
+        .map_err(McuError::io(dir))?
+        .file_name()
+        .map(|name| name.to_os_string())
+        .ok_or_else(|| McuError::Input(format!("cannot archive {}", dir)))?;
+
+    let mut builder = Builder::new(sink);
+    // Store symlinks as links rather than archiving what they point to
+    builder.follow_symlinks(false);
+    builder
+        .append_dir_all(&root, path)
+        .map_err(McuError::io(dir))?;
+    builder.into_inner().map_err(McuError::io(dir))?;
+    Ok(())
+}
+
+/// Reads through to a decryptor, keeping back the `McuError` it fails with,
+/// which tar would otherwise bury in its own error
+struct Checked<R> {
+    inner: R,
+    failure: Option<McuError>,
+}
+
+impl<R: Read> Read for Checked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf).or_else(|e| {
+            let failure = McuError::carried(e)?;
+            let e = io::Error::other(failure.to_string());
+            self.failure = Some(failure);
+            Err(e)
+        })
+    }
+}
+
+/// Extract a tar made by `pack` from `tarball` into `outdir`, then read the
+/// rest of it so the decryptor checks it all; entries that would land
+/// outside `outdir` (absolute paths, `..`) are skipped by `tar`
+///
+/// Nothing appears in `outdir` unless the whole tarball checks out: it is
+/// extracted into a temporary directory beside `outdir`, removed again on
+/// failure, and its top-level entries are moved into `outdir` at the end.
+/// Entries that already exist in `outdir` are not overwritten.
+pub fn unpack(tarball: impl Read, outdir: &str) -> Result<()> {
+    let beside = Path::new(outdir)
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    fs::create_dir_all(beside).map_err(McuError::io(outdir))?;
+    let staging = TempDir::new_in(beside).map_err(McuError::io(outdir))?;
+
+    let mut tarball = Checked {
+        inner: tarball,
+        failure: None,
+    };
+    let unpacked = Archive::new(&mut tarball)
+        .unpack(staging.path())
+        .and_then(|()| io::copy(&mut tarball, &mut io::sink()));
+    if let Some(failure) = tarball.failure {
+        return Err(failure);
+    }
+    unpacked.map_err(McuError::io(outdir))?;
+
+    let entries = fs::read_dir(staging.path())
+        .and_then(|entries| entries.map(|entry| Ok(entry?.file_name())).collect())
+        .map_err(McuError::io(outdir))?;
+    fs::create_dir_all(outdir).map_err(McuError::io(outdir))?;
+    move_into(staging.path(), entries, Path::new(outdir))
+}
+
+/// Rename each of `names` from `from` into `to`, after checking none of them
+/// is there already so a clash moves nothing
+fn move_into(from: &Path, names: Vec<OsString>, to: &Path) -> Result<()> {
+    if let Some(name) = names
+        .iter()
+        .find(|name| to.join(name).symlink_metadata().is_ok())
+    {
+        return Err(McuError::Input(format!(
+            "{} already exists",
+            to.join(name).display()
+        )));
+    }
+    for name in names {
+        let target = to.join(&name);
+        fs::rename(from.join(&name), &target)
+            .map_err(McuError::io(&target.display().to_string()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::{Decryptor, Encryptor, SEGMENT};
+
+    const KEY: [u8; 32] = [7u8; 32];
+
+    /// A directory `src` with a small file and one of three segments, packed
+    /// and encrypted
+    fn encrypted_tree(base: &Path) -> Vec<u8> {
+        let src = base.join("src");
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::write(src.join("sub/small"), b"hello").unwrap();
+        fs::write(src.join("big"), vec![1u8; 3 * SEGMENT]).unwrap();
+        let mut encryptor = Encryptor::new(&KEY, b"", Vec::new()).unwrap();
+        pack(src.to_str().unwrap(), &mut encryptor).unwrap();
+        encryptor.finish().unwrap()
+    }
+
+    fn extract(body: &[u8], outdir: &Path) -> Result<()> {
+        let decryptor = Decryptor::new(&KEY, b"", body).unwrap();
+        unpack(decryptor, outdir.to_str().unwrap())
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let base = tempfile::tempdir().unwrap();
+        let body = encrypted_tree(base.path());
+        let out = base.path().join("out");
+        extract(&body, &out).unwrap();
+        assert_eq!(fs::read(out.join("src/sub/small")).unwrap(), b"hello");
+        assert_eq!(
+            fs::read(out.join("src/big")).unwrap(),
+            vec![1u8; 3 * SEGMENT]
+        );
+    }
+
+    #[test]
+    fn test_tampered_archive_leaves_nothing() {
+        let base = tempfile::tempdir().unwrap();
+        let mut body = encrypted_tree(base.path());
+        // In the last segment, so the ones before it are extracted first
+        let len = body.len();
+        body[len - 20] ^= 1;
+        let out = base.path().join("out");
+        assert!(matches!(extract(&body, &out), Err(McuError::Decrypt)));
+        assert!(!out.exists());
+        let mut left: Vec<_> = fs::read_dir(base.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["src"]);
+    }
+
+    #[test]
+    fn test_existing_entries_are_kept() {
+        let base = tempfile::tempdir().unwrap();
+        let body = encrypted_tree(base.path());
+        let out = base.path().join("out");
+        fs::create_dir_all(out.join("src")).unwrap();
+        fs::write(out.join("src/big"), b"mine").unwrap();
+        assert!(matches!(extract(&body, &out), Err(McuError::Input(_))));
+        assert_eq!(fs::read(out.join("src/big")).unwrap(), b"mine");
+        assert!(!out.join("src/sub").exists());
+    }
+}
@@ -0,0 +1,151 @@
+// Throughput of the ciphers and hashes on this machine, for choosing between
+// them: AES-256-GCM is fastest with AES-NI or ARMv8 crypto extensions, and
+// ChaCha20-Poly1305 usually wins without them.
+
+use aes_gcm::Aes256Gcm;
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::ChaCha20Poly1305;
+use sha2::{Digest, Sha256};
+use std::hint::black_box;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use crate::{McuError, Result, keys};
+
+/// One measured operation over a whole buffer
+type Op<'a> = Box<dyn Fn(&[u8]) + 'a>;
+
+/// Parse a buffer size such as `4096`, `64K` or `1M` (binary units)
+pub fn parse_size(size: &str) -> std::result::Result<usize, String> {
+    let size = size.trim();
+    let (digits, unit) = match size.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((i, _)) => size.split_at(i),
+        None => (size, ""),
+    };
+    let scale = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        _ => return Err(format!("unknown size unit in {}", size)),
+    };
+    let count: usize = digits
+        .parse()
+        .map_err(|_| format!("invalid size {}", size))?;
+    match count.checked_mul(scale) {
+        Some(0) | None => Err(format!("size {} out of range", size)),
+        Some(bytes) => Ok(bytes),
+    }
+}
+
+/// `1024` as `1K`, `1048576` as `1M`
+fn format_size(bytes: usize) -> String {
+    match bytes {
+        b if b >= 1 << 30 && b % (1 << 30) == 0 => format!("{}G", b >> 30),
+        b if b >= 1 << 20 && b % (1 << 20) == 0 => format!("{}M", b >> 20),
+        b if b >= 1 << 10 && b % (1 << 10) == 0 => format!("{}K", b >> 10),
+        b => format!("{}", b),
+    }
+}
+
+/// Run `op` over `buffer` `warmup` times untimed, then as often as fits in
+/// `duration`, returning MB/s
+fn throughput(buffer: &[u8], warmup: usize, duration: Duration, mut op: impl FnMut(&[u8])) -> f64 {
+    for _ in 0..warmup {
+        op(black_box(buffer));
+    }
+    let start = Instant::now();
+    let mut passes = 0u64;
+    while passes == 0 || start.elapsed() < duration {
+        op(black_box(buffer));
+        passes += 1;
+    }
+    (passes * buffer.len() as u64) as f64 / start.elapsed().as_secs_f64() / 1e6
+}
+
+/// Measure every algorithm on a buffer of each size and print a table of
+/// MB/s, one row per algorithm
+pub fn run(sizes: &[usize], warmup: usize, seconds: f64) -> Result<()> {
+    let duration = Duration::try_from_secs_f64(seconds)
+        .map_err(|e| McuError::Input(format!("invalid --seconds: {}", e)))?;
+    let key = keys::generate_key()?;
+    let nonce = [0u8; 12];
+    let aes = Aes256Gcm::new_from_slice(&key).map_err(|_| McuError::Encrypt)?;
+    let chacha = ChaCha20Poly1305::new_from_slice(&key).map_err(|_| McuError::Encrypt)?;
+
+    // A fixed nonce is fine here: nothing encrypted is kept
+    let ciphers: [(&str, Op); 2] = [
+        (
+            "aes-256-gcm",
+            Box::new(|msg| {
+                black_box(aes.encrypt(&nonce.into(), Payload { msg, aad: &[] }).ok());
+            }),
+        ),
+        (
+            "chacha20-poly1305",
+            Box::new(|msg| {
+                black_box(
+                    chacha
+                        .encrypt(&nonce.into(), Payload { msg, aad: &[] })
+                        .ok(),
+                );
+            }),
+        ),
+    ];
+    let hashes: [(&str, Op); 2] = [
+        (
+            "blake3",
+            Box::new(|data| {
+                black_box(blake3::hash(data));
+            }),
+        ),
+        (
+            "sha256",
+            Box::new(|data| {
+                black_box(Sha256::digest(data));
+            }),
+        ),
+    ];
+
+    print!("{:<20}", "MB/s");
+    for &size in sizes {
+        print!("{:>12}", format_size(size));
+    }
+    println!();
+    let cipher = table(&ciphers, sizes, warmup, duration)?;
+    let hash = table(&hashes, sizes, warmup, duration)?;
+    println!(
+        "Fastest at {}: {} and {}",
+        format_size(sizes[sizes.len() - 1]),
+        cipher,
+        hash
+    );
+    Ok(())
+}
+
+/// Print one row of MB/s per algorithm, returning the fastest at the
+/// largest size
+fn table<'a>(
+    algorithms: &[(&'a str, Op)],
+    sizes: &[usize],
+    warmup: usize,
+    duration: Duration,
+) -> Result<&'a str> {
+    let mut fastest = ("", 0.0);
+    for (name, op) in algorithms {
+        print!("{:<20}", name);
+        let mut last = 0.0;
+        for &size in sizes {
+            let buffer = vec![0xA5u8; size];
+            last = throughput(&buffer, warmup, duration, op);
+            print!("{:>12.1}", last);
+            // Show each result as it comes in; the row takes a while
+            io::stdout().flush().map_err(McuError::io("stdout"))?;
+        }
+        println!();
+        if last > fastest.1 {
+            fastest = (name, last);
+        }
+    }
+    Ok(fastest.0)
+}
@@ -0,0 +1,87 @@
+// Everything that can go wrong, grouped by what the user should do about it;
+// `exit_code` turns each group into the process exit status.
+
+use std::io;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum McuError {
+    #[error("{path}: {source}")]
+    Io { path: String, source: io::Error },
+    #[error(transparent)]
+    Walk(#[from] walkdir::Error),
+    /// Input that is not what the command expects (not a directory, wrong kind of file)
+    #[error("{0}")]
+    Input(String),
+    /// A file that is truncated, malformed or of an unknown version
+    #[error("{0}")]
+    Format(String),
+    #[error("invalid hex: {0}")]
+    Hex(#[from] hex::FromHexError),
+    /// A key file that is missing, the wrong size or does not unwrap
+    #[error("{0}")]
+    Key(String),
+    #[error("{0}")]
+    Password(String),
+    #[error("encryption failed")]
+    Encrypt,
+    /// The AEAD tag did not match: wrong key, or the file was altered
+    #[error("decryption failed: wrong key or corrupted file")]
+    Decrypt,
+    #[error("BAD signature on {file} for key {key}")]
+    BadSignature { file: String, key: String },
+    #[error("{0} file(s) did not match")]
+    Mismatch(usize),
+}
+
+impl McuError {
+    /// Wrap an I/O error with the path it happened on, for `map_err`; an
+    /// `McuError` a reader or writer passed up inside it, like a segment that
+    /// failed to decrypt, comes back out as it was
+    pub fn io(path: &str) -> impl FnOnce(io::Error) -> McuError + '_ {
+        move |source| {
+            McuError::carried(source).unwrap_or_else(|source| McuError::Io {
+                path: path.to_string(),
+                source,
+            })
+        }
+    }
+
+    /// The `McuError` a reader or writer passed up inside `error`, or the
+    /// error back when it is a plain I/O error
+    pub fn carried(error: io::Error) -> Result<McuError, io::Error> {
+        if !error.get_ref().is_some_and(|inner| inner.is::<McuError>()) {
+            return Err(error);
+        }
+        let inner = error.into_inner().expect("checked above");
+        Ok(*inner.downcast::<McuError>().expect("checked above"))
+    }
+
+    /// Like `io`, for reading part of a header: running out of file there
+    /// means the file is truncated
+    pub fn short<'a>(path: &'a str, what: &'a str) -> impl FnOnce(io::Error) -> McuError + 'a {
+        move |source| match source.kind() {
+            io::ErrorKind::UnexpectedEof => {
+                McuError::Format(format!("file too short for {}", what))
+            }
+            _ => McuError::io(path)(source),
+        }
+    }
+
+    /// 1: I/O or other failure, 3: authentication failed, 4: key or
+    /// password problem, 5: malformed input (2 is clap's usage error)
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            McuError::Io { .. } | McuError::Walk(_) | McuError::Encrypt | McuError::Input(_) => 1,
+            McuError::Decrypt | McuError::BadSignature { .. } | McuError::Mismatch(_) => 3,
+            McuError::Key(_) | McuError::Password(_) => 4,
+            McuError::Format(_) | McuError::Hex(_) => 5,
+        }
+    }
+}
+
+impl From<ed25519_dalek::SignatureError> for McuError {
+    fn from(e: ed25519_dalek::SignatureError) -> Self {
+        McuError::Key(format!("invalid ed25519 key or signature: {}", e))
+    }
+}
@@ -0,0 +1,165 @@
+// File hashing for the Hash command: several algorithms, whole directory
+// trees, and checksum files in the `sha256sum` format ("<hex>  <path>").
+
+use clap::ValueEnum;
+use indicatif::ProgressBar;
+use sha2::{Digest, Sha256, Sha512};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use walkdir::WalkDir;
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::{McuError, Result, progress};
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Algo {
+    Blake3,
+    Sha256,
+    Sha512,
+    Xxh3,
+}
+
+/// Running hash of one algorithm
+enum State {
+    Blake3(Box<blake3::Hasher>),
+    Sha256(Sha256),
+    Sha512(Sha512),
+    Xxh3(Box<Xxh3>),
+}
+
+impl State {
+    fn new(algo: Algo) -> Self {
+        match algo {
+            Algo::Blake3 => State::Blake3(Box::new(blake3::Hasher::new())),
+            Algo::Sha256 => State::Sha256(Sha256::new()),
+            Algo::Sha512 => State::Sha512(Sha512::new()),
+            Algo::Xxh3 => State::Xxh3(Box::new(Xxh3::new())),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            State::Blake3(hasher) => {
+                hasher.update(data);
+            }
+            State::Sha256(hasher) => hasher.update(data),
+            State::Sha512(hasher) => hasher.update(data),
+            State::Xxh3(hasher) => hasher.update(data),
+        }
+    }
+
+    fn finish_hex(self) -> String {
+        match self {
+            State::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+            State::Sha256(hasher) => hex::encode(hasher.finalize()),
+            State::Sha512(hasher) => hex::encode(hasher.finalize()),
+            // 128-bit variant: 64 bits is too few for a checksum file
+            State::Xxh3(hasher) => format!("{:032x}", hasher.digest128()),
+        }
+    }
+}
+
+pub fn hash_file(file_path: &str, algo: Algo) -> Result<String, io::Error> {
+    let len = fs::metadata(file_path)?.len();
+    let bar = progress::bar(len, file_path);
+    let hash = hash_with_progress(file_path, algo, &bar);
+    bar.finish_and_clear();
+    hash
+}
+
+/// Hash one file, advancing `bar` by the bytes read
+fn hash_with_progress(file_path: &str, algo: Algo, bar: &ProgressBar) -> Result<String, io::Error> {
+    let file = File::open(file_path)?;
+
+    let mut reader = BufReader::new(file);
+
+    let mut state = State::new(algo);
+
+    const BUFFER_SIZE: usize = 4096;
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+
+    loop {
+        let bytes_read = reader.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        state.update(&buffer[..bytes_read]);
+        bar.inc(bytes_read as u64);
+    }
+    Ok(state.finish_hex())
+}
+
+/// Hash every file under `dir`, in path order, as `(hash, path)` pairs,
+/// with one progress bar over the whole tree
+pub fn hash_tree(dir: &str, algo: Algo) -> Result<Vec<(String, String)>> {
+    let mut files = Vec::new();
+    let mut total = 0;
+    for entry in WalkDir::new(dir).sort_by_file_name() {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        total += entry.metadata()?.len();
+        files.push(entry.path().to_string_lossy().into_owned());
+    }
+
+    let bar = progress::bar(total, dir);
+    let mut sums = Vec::new();
+    for path in files {
+        let hash = hash_with_progress(&path, algo, &bar).map_err(McuError::io(&path))?;
+        sums.push((hash, path));
+    }
+    bar.finish_and_clear();
+    Ok(sums)
+}
+
+/// Hash `path`, a file or a directory tree, as `(hash, path)` pairs
+pub fn hash_path(path: &str, algo: Algo) -> Result<Vec<(String, String)>> {
+    if Path::new(path).is_dir() {
+        return hash_tree(path, algo);
+    }
+    let hash = hash_file(path, algo).map_err(McuError::io(path))?;
+    Ok(vec![(hash, path.to_string())])
+}
+
+/// Lines of a checksum file listing `sums`
+pub fn manifest(sums: &[(String, String)]) -> String {
+    sums.iter()
+        .map(|(hash, path)| format!("{}  {}\n", hash, path))
+        .collect()
+}
+
+/// Check every file listed in the checksum file `sums_path` against its
+/// hash, printing OK or FAILED per file like `sha256sum -c`
+pub fn check(sums_path: &str, algo: Algo) -> Result<()> {
+    let sums = fs::read_to_string(sums_path).map_err(McuError::io(sums_path))?;
+    let mut failed = 0;
+    for (number, line) in sums.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        // "<hex>  <path>", or "<hex> *<path>" for binary mode
+        let (expected, path) = line
+            .split_once("  ")
+            .or_else(|| line.split_once(" *"))
+            .ok_or_else(|| {
+                McuError::Format(format!("{}:{}: not a checksum line", sums_path, number + 1))
+            })?;
+        match hash_file(path, algo) {
+            Ok(actual) if actual.eq_ignore_ascii_case(expected) => println!("{}: OK", path),
+            Ok(_) => {
+                println!("{}: FAILED", path);
+                failed += 1;
+            }
+            Err(e) => {
+                println!("{}: FAILED open or read ({})", path, e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(McuError::Mismatch(failed));
+    }
+    Ok(())
+}
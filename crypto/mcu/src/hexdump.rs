@@ -0,0 +1,104 @@
+// Hex dumps: a short preview of a file for `--verbose`, and the Hexdump
+// command for any range of a file, paged when stdout is a terminal.
+
+use std::fs::File;
+use std::io::{self, BufRead, IsTerminal, Read, Seek, SeekFrom, Write};
+
+use crate::{McuError, Result};
+
+/// Bytes per dump line
+const LINE: usize = 16;
+
+/// Most bytes a `--verbose` preview shows, so a large file does not end up
+/// in the terminal scrollback in full
+const PREVIEW_LIMIT: usize = 256;
+
+/// One dump line for `chunk`, which starts `offset` bytes into its file:
+/// offset, hex bytes and their printable ASCII
+fn line(offset: u64, chunk: &[u8]) -> String {
+    let hex: Vec<_> = chunk.iter().map(|b| format!("{:02X}", b)).collect();
+    let ascii: String = chunk
+        .iter()
+        .map(|&b| if b.is_ascii_graphic() { b as char } else { '.' })
+        .collect();
+    format!("  {:08X}: {:48} |{:16}|", offset, hex.join(" "), ascii)
+}
+
+/// Print the first `PREVIEW_LIMIT` bytes of the file at `path` under `label`
+pub fn preview(label: &str, path: &str) -> Result<()> {
+    let file = File::open(path).map_err(McuError::io(path))?;
+    let len = file.metadata().map_err(McuError::io(path))?.len();
+    let mut shown = Vec::with_capacity(PREVIEW_LIMIT);
+    file.take(PREVIEW_LIMIT as u64)
+        .read_to_end(&mut shown)
+        .map_err(McuError::io(path))?;
+    println!("{} ({} bytes):", label, len);
+    for (i, chunk) in shown.chunks(LINE).enumerate() {
+        println!("{}", line((i * LINE) as u64, chunk));
+    }
+    if len > shown.len() as u64 {
+        println!(
+            "  ... {} more bytes; see `mcu hexdump`",
+            len - shown.len() as u64
+        );
+    }
+    Ok(())
+}
+
+/// Dump `length` bytes of `path` from `offset` (to the end if `None`),
+/// pausing every `page` lines when stdout is a terminal (0: never)
+pub fn dump_file(path: &str, offset: u64, length: Option<u64>, page: usize) -> Result<()> {
+    let mut file = File::open(path).map_err(McuError::io(path))?;
+    file.seek(SeekFrom::Start(offset))
+        .map_err(McuError::io(path))?;
+    let mut reader = file.take(length.unwrap_or(u64::MAX));
+
+    let page = if io::stdout().is_terminal() { page } else { 0 };
+    let mut stdout = io::stdout().lock();
+    let mut buffer = [0u8; LINE];
+    let mut position = offset;
+    let mut lines = 0;
+    loop {
+        let read = read_line(&mut reader, &mut buffer).map_err(McuError::io(path))?;
+        if read == 0 {
+            break;
+        }
+        let printed = writeln!(stdout, "{}", line(position, &buffer[..read]));
+        if printed.is_err() {
+            // The reader went away, as with `mcu hexdump ... | head`
+            break;
+        }
+        position += read as u64;
+        lines += 1;
+        if page > 0 && lines % page == 0 && !more()? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Fill `buffer` as far as the reader allows, so every line but the last
+/// is a whole one
+fn read_line(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..])? {
+            0 => break,
+            read => filled += read,
+        }
+    }
+    Ok(filled)
+}
+
+/// Ask whether to show another page: Enter continues, q (or end of input)
+/// stops
+fn more() -> Result<bool> {
+    eprint!("-- more (Enter to continue, q to quit) --");
+    io::stderr().flush().map_err(McuError::io("stderr"))?;
+    let mut answer = String::new();
+    let read = io::stdin()
+        .lock()
+        .read_line(&mut answer)
+        .map_err(McuError::io("stdin"))?;
+    Ok(read > 0 && !answer.trim().eq_ignore_ascii_case("q"))
+}
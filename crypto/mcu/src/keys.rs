@@ -0,0 +1,197 @@
+// Key files: a raw 32-byte AES key, or the same key wrapped (encrypted)
+// under a passphrase so a stolen key.bin is useless on its own. `inspect`
+// also recognizes the signing and public keys of `sign` and `recipient`.
+//
+// Wrapped layout: "MCUK" || version || salt || nonce || encrypted key, with
+// everything before the nonce authenticated along with the key
+
+use aes_gcm::aead::{OsRng, rand_core::RngCore};
+use std::fs;
+use zeroize::Zeroizing;
+
+use crate::{
+    KEY_LEN, KeyBytes, McuError, Result, SALT_LEN, derive_key, open, prompt_new_password,
+    prompt_password, recipient, seal, sign, write_atomic,
+};
+
+/// Magic bytes at the start of a passphrase-wrapped key file
+const WRAPPED_MAGIC: &[u8; 4] = b"MCUK";
+
+/// Version of the wrapped key layout
+const WRAPPED_VERSION: u8 = 1;
+
+/// Read the key in `path`, asking for its passphrase if it is wrapped
+pub fn load_key(path: &str) -> Result<KeyBytes> {
+    let data = Zeroizing::new(fs::read(path).map_err(|e| {
+        McuError::Key(format!(
+            "cannot read {} ({}); create one with `mcu keygen` or use --password",
+            path, e
+        ))
+    })?);
+    if data.starts_with(WRAPPED_MAGIC) {
+        let passphrase = prompt_password(&format!("Passphrase for {}: ", path))?;
+        return unwrap_key(&data, &passphrase);
+    }
+    if data.len() != KEY_LEN {
+        return Err(McuError::Key(format!(
+            "{} must hold {} bytes, found {}",
+            path,
+            KEY_LEN,
+            data.len()
+        )));
+    }
+    KeyBytes::new(data.to_vec())
+}
+
+/// Write `key` to `path`, wrapped under a new passphrase if `passphrase` is set
+pub fn save_key(path: &str, key: &[u8], passphrase: bool) -> Result<()> {
+    let data = Zeroizing::new(if passphrase {
+        wrap_key(key, &prompt_new_password()?)?
+    } else {
+        key.to_vec()
+    });
+    write_atomic(path, &data)
+}
+
+/// A fresh random key
+pub fn generate_key() -> Result<KeyBytes> {
+    let mut key = KeyBytes::zeroed(KEY_LEN)?;
+    OsRng.fill_bytes(key.as_mut_slice());
+    Ok(key)
+}
+
+/// Short identifier of a key that does not reveal it: the first 8 bytes of
+/// its blake3 hash
+pub fn fingerprint(key: &[u8]) -> String {
+    hex::encode(&blake3::hash(key).as_bytes()[..8])
+}
+
+fn wrap_key(key: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let mut out = WRAPPED_MAGIC.to_vec();
+    out.push(WRAPPED_VERSION);
+    out.extend_from_slice(&salt);
+    let sealed = seal(&derive_key(passphrase, &salt)?, key, &out)?;
+    out.extend_from_slice(&sealed);
+    Ok(out)
+}
+
+fn unwrap_key(data: &[u8], passphrase: &str) -> Result<KeyBytes> {
+    let rest = &data[WRAPPED_MAGIC.len()..];
+    let truncated = || McuError::Format("key file truncated".into());
+    let (&version, rest) = rest.split_first().ok_or_else(truncated)?;
+    if version != WRAPPED_VERSION {
+        return Err(McuError::Format(format!(
+            "unsupported key file version {}",
+            version
+        )));
+    }
+    if rest.len() < SALT_LEN {
+        return Err(truncated());
+    }
+    let (salt, sealed) = rest.split_at(SALT_LEN);
+    let header = &data[..data.len() - sealed.len()];
+    let key_bytes = derive_key(passphrase, salt)?;
+    let key = KeyBytes::new(
+        open(&key_bytes, sealed, header).map_err(|_| McuError::Key("wrong passphrase".into()))?,
+    )?;
+    if key.len() != KEY_LEN {
+        return Err(McuError::Key("wrapped key has the wrong size".into()));
+    }
+    Ok(key)
+}
+
+/// Print what kind of key `path` holds and its fingerprint
+pub fn inspect(path: &str) -> Result<()> {
+    let data = fs::read(path).map_err(McuError::io(path))?;
+    if data.starts_with(sign::SIGNING_MAGIC) {
+        let public = sign::load_signing_key(path)?.verifying_key();
+        println!("{}: ed25519 signing key", path);
+        println!("Public key fingerprint: {}", sign::fingerprint(&public));
+        return Ok(());
+    }
+    if let Ok(public) = sign::load_public_key(path) {
+        println!("{}: ed25519 public key", path);
+        println!("Fingerprint: {}", sign::fingerprint(&public));
+        return Ok(());
+    }
+    if data.starts_with(recipient::EXCHANGE_MAGIC) {
+        let public = x25519_dalek::PublicKey::from(&recipient::load_secret(path)?);
+        println!("{}: X25519 private key", path);
+        println!("Public key fingerprint: {}", fingerprint(public.as_bytes()));
+        return Ok(());
+    }
+    if let Ok(public) = recipient::load_public_key(path) {
+        println!("{}: X25519 public key", path);
+        println!("Fingerprint: {}", fingerprint(public.as_bytes()));
+        return Ok(());
+    }
+    let kind = if data.starts_with(WRAPPED_MAGIC) {
+        "wrapped with a passphrase (Argon2id + AES-256-GCM)"
+    } else {
+        "raw AES-256 key"
+    };
+    println!("{}: {}, {} bytes on disk", path, kind, data.len());
+    let key = load_key(path)?;
+    println!("Fingerprint: {}", fingerprint(&key));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap_round_trip() {
+        let key = generate_key().unwrap();
+        let wrapped = wrap_key(&key, "hunter2").unwrap();
+        assert!(wrapped.starts_with(WRAPPED_MAGIC));
+        assert!(!wrapped.windows(KEY_LEN).any(|window| window == &key[..]));
+        assert_eq!(&unwrap_key(&wrapped, "hunter2").unwrap()[..], &key[..]);
+    }
+
+    #[test]
+    fn test_wrong_passphrase() {
+        let wrapped = wrap_key(&generate_key().unwrap(), "hunter2").unwrap();
+        assert!(matches!(
+            unwrap_key(&wrapped, "hunter3"),
+            Err(McuError::Key(_))
+        ));
+    }
+
+    #[test]
+    fn test_tampered_or_truncated_wrapping() {
+        let mut wrapped = wrap_key(&generate_key().unwrap(), "hunter2").unwrap();
+        assert!(matches!(
+            unwrap_key(&wrapped[..WRAPPED_MAGIC.len() + 3], "hunter2"),
+            Err(McuError::Format(_))
+        ));
+        // An unknown version is refused before the passphrase is tried
+        wrapped[WRAPPED_MAGIC.len()] = 9;
+        assert!(matches!(
+            unwrap_key(&wrapped, "hunter2"),
+            Err(McuError::Format(_))
+        ));
+    }
+
+    #[test]
+    fn test_load_raw_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("key.bin");
+        let path = path.to_str().unwrap();
+        let key = generate_key().unwrap();
+        save_key(path, &key, false).unwrap();
+        assert_eq!(&load_key(path).unwrap()[..], &key[..]);
+
+        fs::write(path, [1u8; 16]).unwrap();
+        assert!(matches!(load_key(path), Err(McuError::Key(_))));
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let zeros = fingerprint(&[0u8; KEY_LEN]);
+        assert_eq!(zeros.len(), 16);
+        assert_ne!(zeros, fingerprint(&[1u8; KEY_LEN]));
+    }
+}
@@ -8,44 +8,57 @@
 */
 
 use aes_gcm::{
-    aead::{Aead, KeyInit, OsRng, rand_core::RngCore},  // Add RngCore
+    aead::{Aead, KeyInit, OsRng, Payload, rand_core::RngCore},
     Aes256Gcm, Key, Nonce,
 };
-use std::{fs};
+use argon2::Argon2;
 
+mod archive;
+mod bench;
+mod error;
+mod hash;
+mod hexdump;
+mod keys;
+mod metadata;
+mod progress;
+mod recipient;
+mod secret;
+mod sign;
+mod stream;
 
+use error::McuError;
+use metadata::Metadata;
+use secret::KeyBytes;
+use subtle::ConstantTimeEq;
+use zeroize::Zeroizing;
 
-use blake3::Hasher;
+type Result<T, E = McuError> = std::result::Result<T, E>;
+use std::{fs};
 use std::fs::File;
-use std::io::{self, BufReader, Read};
-
-fn hash_file(file_path: &str) -> Result<String, io::Error> {
-    let file = File::open(file_path)?;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::ExitCode;
 
-    let mut reader = BufReader::new(file);
+use indicatif::ProgressBar;
+use tempfile::NamedTempFile;
 
-    let mut hasher = Hasher::new();
 
-    const BUFFER_SIZE: usize = 4096;
-    let mut buffer = vec![0u8; BUFFER_SIZE];
-
-    loop {
-        let bytes_read = reader.read(&mut buffer)?;
-        println!("Read: {} bytes", bytes_read);
-        if bytes_read == 0 {
-            break;
-        }
-        hasher.update(&buffer[..bytes_read]);
-    }
-    let hash = hasher.finalize();
-    Ok(hash.to_hex().to_string())
-}
 
 use clap::{Parser, Subcommand};
 
 #[derive(Parser)]
 #[command(name = "mcu", author, version)]
 struct Cli {
+    /// Do not show progress bars
+    #[arg(short, long, global = true)]
+    quiet: bool,
+    /// Show a hex preview of the plaintext and ciphertext; beware that this
+    /// puts file contents in the terminal scrollback
+    #[arg(short, long, global = true)]
+    verbose: bool,
+    /// Lock key material in memory so it cannot be swapped out (Unix)
+    #[arg(long, global = true)]
+    paranoid: bool,
     #[command(subcommand)]
     command: Commands,
 }
@@ -53,100 +66,774 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     Encrypt {
+        /// File to encrypt, or - for stdin
+        #[arg(short, long, required_unless_present_any = ["indir", "input"])]
+        infile: Option<String>,
+        /// Encrypt a whole directory, packed as a tar archive
+        #[arg(short = 'd', long, conflicts_with_all = ["infile", "input"])]
+        indir: Option<String>,
+        /// Encrypted file to write, or - for stdout
+        #[arg(short, long, required_unless_present = "output")]
+        outfile: Option<String>,
+        /// Same as --infile, for pipelines: `tar c dir | mcu encrypt - - > out.enc`
+        #[arg(conflicts_with = "infile")]
+        input: Option<String>,
+        /// Same as --outfile
+        #[arg(conflicts_with = "outfile")]
+        output: Option<String>,
+        /// Derive the key from a password (Argon2id) instead of a key file
+        #[arg(short, long)]
+        password: bool,
+        /// Encrypt for the holder of this X25519 public key (PEM), made by
+        /// `mcu keygen --x25519`, instead of with a key file
+        #[arg(short, long, conflicts_with = "password")]
+        recipient: Option<String>,
+        /// Key file made by `mcu keygen`
+        #[arg(short, long, default_value = "key.bin")]
+        key: String,
+    },
+    Decrypt {
+        /// Encrypted file, or - for stdin
+        #[arg(short, long, required_unless_present = "input")]
+        infile: Option<String>,
+        /// Plaintext file to write, or - for stdout
+        #[arg(short, long, required_unless_present_any = ["outdir", "output"])]
+        outfile: Option<String>,
+        /// Extract a file made with `encrypt --indir` into this directory
+        #[arg(short = 'd', long, conflicts_with_all = ["outfile", "output"])]
+        outdir: Option<String>,
+        /// Same as --infile, for pipelines: `mcu decrypt - - < in.enc | tar x`
+        #[arg(conflicts_with = "infile")]
+        input: Option<String>,
+        /// Same as --outfile
+        #[arg(conflicts_with = "outfile")]
+        output: Option<String>,
+        /// Key file, or X25519 private key for a file encrypted to a
+        /// recipient, when the file was not encrypted with a password
+        #[arg(short, long, default_value = "key.bin")]
+        key: String,
+    },
+    /// Check an encrypted file is intact and show its metadata, without
+    /// writing the plaintext; or with --signature, check a detached signature
+    Verify {
         #[arg(short, long)]
         infile: String,
+        /// Key file, when the file was not encrypted with a password
+        #[arg(short, long, default_value = "key.bin")]
+        key: String,
+        /// Signature made by `mcu sign` to check instead
         #[arg(short, long)]
-        outfile: String,
+        signature: Option<String>,
+        /// Public key the signature must be from
+        #[arg(long, default_value = "sign.key.pub")]
+        pubkey: String,
     },
-    Decrypt {
+    /// Sign the blake3 hash of a file with an ed25519 key
+    Sign {
         #[arg(short, long)]
         infile: String,
+        /// Signing key made by `mcu keygen --signing`
+        #[arg(short, long, default_value = "sign.key")]
+        key: String,
+        /// Signature file; <infile>.sig by default
         #[arg(short, long)]
-        outfile: String,
+        out: Option<String>,
     },
+    /// Hash a file, or every file under a directory
     Hash {
+        #[arg(short, long, required_unless_present = "check")]
+        infile: Option<String>,
+        #[arg(short, long, value_enum, default_value_t = hash::Algo::Blake3)]
+        algo: hash::Algo,
+        /// Check the files listed in a checksum file instead
+        #[arg(short, long, conflicts_with = "infile")]
+        check: Option<String>,
+        /// Write the hashes to a checksum file
+        #[arg(short, long)]
+        manifest: Option<String>,
+    },
+    /// Generate a random key file
+    Keygen {
+        /// Key file; key.bin, sign.key with --signing, or x25519.key with --x25519
+        #[arg(short, long)]
+        out: Option<String>,
+        /// Wrap the key under a passphrase, asked for whenever it is used
+        #[arg(short, long)]
+        passphrase: bool,
+        /// Generate an ed25519 signing keypair instead, the public key in <out>.pub
+        #[arg(short, long, conflicts_with = "passphrase")]
+        signing: bool,
+        /// Generate an X25519 keypair to receive files encrypted with
+        /// --recipient instead, the public key in <out>.pem
+        #[arg(short, long, conflicts_with_all = ["passphrase", "signing"])]
+        x25519: bool,
+    },
+    /// Measure cipher and hash throughput on this machine
+    Bench {
+        /// Buffer sizes to measure, like 4K,64K,1M
+        #[arg(short, long, value_delimiter = ',', value_parser = bench::parse_size,
+              default_value = "1K,64K,1M,16M")]
+        sizes: Vec<usize>,
+        /// Untimed passes before measuring each size
+        #[arg(short, long, default_value_t = 3)]
+        warmup: usize,
+        /// Seconds to measure each algorithm at each size
+        #[arg(long, default_value_t = 1.0)]
+        seconds: f64,
+    },
+    /// Print a hex dump of part of a file
+    Hexdump {
         #[arg(short, long)]
         infile: String,
+        /// Byte to start at
+        #[arg(long, default_value_t = 0)]
+        offset: u64,
+        /// Bytes to dump; to the end of the file by default
+        #[arg(long)]
+        length: Option<u64>,
+        /// Lines per page on a terminal, 0 for no paging
+        #[arg(long, default_value_t = 32)]
+        page: usize,
+    },
+    /// Manage key files
+    Key {
+        #[command(subcommand)]
+        action: KeyCommands,
     },
 }
 
-fn print_hex_dump(label: &str, data: &[u8]) {
-    println!("{} ({} bytes):", label, data.len());
-    
-    for (i, chunk) in data.chunks(16).enumerate() {
-        let hex: Vec<_> = chunk.iter().map(|b| format!("{:02X}", b)).collect();
-        let hex_str = hex.join(" ");
-        
-        let ascii: String = chunk.iter()
-            .map(|&b| if b.is_ascii_graphic() { b as char } else { '.' })
-            .collect();
-        
-        println!("  {:08X}: {:48} |{:16}|", i * 16, hex_str, ascii);
+#[derive(Subcommand)]
+enum KeyCommands {
+    /// Show the kind and fingerprint of a key file
+    Inspect {
+        #[arg(default_value = "key.bin")]
+        keyfile: String,
+    },
+    /// Re-encrypt a file from one key to another
+    Rotate {
+        #[arg(long)]
+        old: String,
+        #[arg(long)]
+        new: String,
+        #[arg(short, long)]
+        infile: String,
+        /// Where to write the re-encrypted file; the input is replaced by default
+        #[arg(short, long)]
+        outfile: Option<String>,
+    },
+}
+
+/// Leading byte of a file encrypted with a key file, followed by the metadata
+/// and segments
+const FORMAT_KEY_FILE: u8 = 1;
+
+/// Leading byte of a file encrypted with a password, followed by the salt,
+/// metadata and segments
+const FORMAT_PASSWORD: u8 = 2;
+
+/// AES-256 key size
+const KEY_LEN: usize = 32;
+
+/// AES-GCM nonce size: 96 bits
+const NONCE_LEN: usize = 12;
+
+/// Leading byte of a file encrypted to a recipient's X25519 public key,
+/// followed by the ephemeral public key, metadata and segments
+const FORMAT_RECIPIENT: u8 = 3;
+
+/// Argon2 salt size, as the PHC string format recommends
+const SALT_LEN: usize = 16;
+
+/// Where the key for a new file comes from
+enum KeySource<'a> {
+    /// A key file made by `mcu keygen`
+    KeyFile(&'a str),
+    /// A password, asked for on the terminal
+    Password,
+    /// A recipient's X25519 public key file
+    Recipient(&'a str),
+}
+
+/// Derive an AES key from `password` with Argon2id (default parameters:
+/// 19 MiB, 2 passes, 1 lane)
+fn derive_key(password: &str, salt: &[u8]) -> Result<KeyBytes> {
+    let mut key = KeyBytes::zeroed(KEY_LEN)?;
+    Argon2::default()
+        .hash_password_into(password.as_bytes(), salt, key.as_mut_slice())
+        .map_err(|e| McuError::Key(format!("key derivation failed: {}", e)))?;
+    Ok(key)
+}
+
+/// Read a password from the terminal without echoing it
+fn prompt_password(prompt: &str) -> Result<Zeroizing<String>> {
+    rpassword::prompt_password(prompt)
+        .map(Zeroizing::new)
+        .map_err(|e| McuError::Password(format!("cannot read password: {}", e)))
+}
+
+/// Ask for a new password twice, refusing an empty one or a mismatch
+fn prompt_new_password() -> Result<Zeroizing<String>> {
+    let password = prompt_password("Password: ")?;
+    if password.is_empty() {
+        return Err(McuError::Password("password must not be empty".into()));
+    }
+    let confirm = prompt_password("Confirm password: ")?;
+    if !bool::from(confirm.as_bytes().ct_eq(password.as_bytes())) {
+        return Err(McuError::Password("passwords do not match".into()));
+    }
+    Ok(password)
+}
+
+/// A temporary file in the same directory as `path`, to write an output to
+/// and `persist` once complete, so a failure never leaves a partial file
+/// behind. The file is readable by the owner only.
+fn temp_file(path: &str) -> Result<NamedTempFile> {
+    let dir = Path::new(path)
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    NamedTempFile::new_in(dir).map_err(McuError::io(path))
+}
+
+/// Put a complete file from `temp_file` in place at `path`
+fn persist(temp: NamedTempFile, path: &str) -> Result<()> {
+    temp.as_file().sync_all().map_err(McuError::io(path))?;
+    temp.persist(path).map_err(|e| McuError::io(path)(e.error))?;
+    Ok(())
+}
+
+/// Write `data` to `path` through `temp_file`
+fn write_atomic(path: &str, data: &[u8]) -> Result<()> {
+    let mut temp = temp_file(path)?;
+    progress::write_all(&mut temp, data).map_err(McuError::io(path))?;
+    persist(temp, path)
+}
+
+/// File name that stands for stdin or stdout
+const STDIO: &str = "-";
+
+/// Print a status line, on stderr instead of stdout when stdout carries the
+/// output file
+macro_rules! status {
+    ($piped:expr, $($arg:tt)*) => {
+        if $piped {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Open `path` with its length, or stdin for `-`, read as it comes in
+fn open_input(path: &str) -> Result<(Box<dyn Read>, Option<u64>)> {
+    if path == STDIO {
+        return Ok((Box::new(io::stdin().lock()), None));
     }
-    
+    let file = File::open(path).map_err(McuError::io(path))?;
+    let len = file.metadata().map_err(McuError::io(path))?.len();
+    Ok((Box::new(file), Some(len)))
 }
 
-fn get_key() -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-    if fs::metadata("key.bin").is_ok() {
-        let key = fs::read("key.bin")?;
-        if key.len() == 32 { return Ok(key); }
+/// Where an output goes: a `temp_file` put in place once complete, or
+/// stdout for `-`
+enum Output {
+    File(NamedTempFile),
+    Stdout(io::StdoutLock<'static>),
+}
+
+impl Output {
+    fn create(path: &str) -> Result<Self> {
+        if path == STDIO {
+            return Ok(Output::Stdout(io::stdout().lock()));
+        }
+        Ok(Output::File(temp_file(path)?))
+    }
+
+    /// Put the complete output in place at `path`
+    fn finish(self, path: &str) -> Result<()> {
+        match self {
+            Output::File(temp) => persist(temp, path),
+            Output::Stdout(mut stdout) => stdout.flush().map_err(McuError::io("stdout")),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::File(temp) => temp.write(data),
+            Output::Stdout(stdout) => stdout.write(data),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::File(temp) => temp.flush(),
+            Output::Stdout(stdout) => stdout.flush(),
+        }
     }
-    // Default
-    Ok(hex::decode("0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef")? )
 }
 
-fn encrypt_file(infile: &str, outfile: &str) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Encrypting {} → {}", infile, outfile);
-    let key_bytes = get_key()?;
-    let nonce = Nonce::from_slice(b"mcu_nonce_12");  // Fixed 12 bytes
-    
-    let plaintext = fs::read(&infile)?;
-    print_hex_dump("plaintext", &plaintext);
-    
-    let key = Key::<Aes256Gcm>::from_slice(&key_bytes);
+/// Encrypt `plaintext` under a fresh random nonce, authenticating `aad`
+/// along with it: nonce || ciphertext
+fn seal(key_bytes: &[u8], plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+    let mut nonce_bytes = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce_bytes);
+    let nonce = Nonce::from_slice(&nonce_bytes);
+
+    let key = Key::<Aes256Gcm>::from_slice(key_bytes);
     let cipher = Aes256Gcm::new(key);
-    let ciphertext = cipher.encrypt(&nonce, plaintext.as_ref()).unwrap();
-    
-    print_hex_dump("ciphertext", &ciphertext);
-    fs::write(&outfile, &ciphertext).unwrap();
-    println!("✓ Encrypted: {} bytes", ciphertext.len());
-    Ok(())
+    let ciphertext = cipher
+        .encrypt(nonce, Payload { msg: plaintext, aad })
+        .map_err(|_| McuError::Encrypt)?;
+
+    let mut out = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+    out.extend_from_slice(&nonce_bytes);
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
 }
 
-fn decrypt_file(infile: &str, outfile: &str) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Decrypting {} → {}", infile, outfile);
-    let key_bytes = get_key()?;
-    let nonce = Nonce::from_slice(b"mcu_nonce_12");
-    
-    let ciphertext = fs::read(&infile)?;
-    print_hex_dump("ciphertext", &ciphertext);
-    
-    let key = Key::<Aes256Gcm>::from_slice(&key_bytes);
+/// Split off the nonce written by `seal` and decrypt the rest, checking it
+/// was sealed with the same `aad`
+fn open(key_bytes: &[u8], data: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+    if data.len() < NONCE_LEN {
+        return Err(McuError::Format("file too short for a nonce".into()));
+    }
+    let (nonce_bytes, ciphertext) = data.split_at(NONCE_LEN);
+    let nonce = Nonce::from_slice(nonce_bytes);
+
+    let key = Key::<Aes256Gcm>::from_slice(key_bytes);
     let cipher = Aes256Gcm::new(key);
-    let plaintext = cipher.decrypt(&nonce, ciphertext.as_ref()).unwrap();
-    
-    print_hex_dump("plaintext", &plaintext);
-    fs::write(&outfile, &plaintext).unwrap();
-    println!("✓ Decrypted: {} bytes", plaintext.len());
+    let plaintext = cipher
+        .decrypt(nonce, Payload { msg: ciphertext, aad })
+        .map_err(|_| McuError::Decrypt)?;
+    Ok(plaintext)
+}
+
+/// Header and key for a new file: the format byte, plus a random salt when
+/// the key comes from a password or the ephemeral public key when it is for
+/// a recipient
+fn new_header(source: &KeySource) -> Result<(Vec<u8>, KeyBytes)> {
+    match *source {
+        KeySource::KeyFile(key_path) => Ok((vec![FORMAT_KEY_FILE], keys::load_key(key_path)?)),
+        KeySource::Password => {
+            let mut salt = [0u8; SALT_LEN];
+            OsRng.fill_bytes(&mut salt);
+            let key = derive_key(&prompt_new_password()?, &salt)?;
+            let mut header = vec![FORMAT_PASSWORD];
+            header.extend_from_slice(&salt);
+            Ok((header, key))
+        }
+        KeySource::Recipient(public_path) => {
+            let recipient = recipient::load_public_key(public_path)?;
+            let (ephemeral, key) = recipient::encapsulate(&recipient)?;
+            let mut header = vec![FORMAT_RECIPIENT];
+            header.extend_from_slice(ephemeral.as_bytes());
+            Ok((header, key))
+        }
+    }
+}
+
+/// Read the key part of an encrypted file's header from `input`, the file
+/// at `path`, returning the key and the bytes read
+fn read_header(input: &mut impl Read, path: &str, key_path: &str) -> Result<(KeyBytes, Vec<u8>)> {
+    let mut format = [0u8; 1];
+    input
+        .read_exact(&mut format)
+        .map_err(McuError::short(path, "a header"))?;
+    let mut header = format.to_vec();
+    let key = match format[0] {
+        FORMAT_KEY_FILE => keys::load_key(key_path)?,
+        FORMAT_PASSWORD => {
+            let mut salt = [0u8; SALT_LEN];
+            input
+                .read_exact(&mut salt)
+                .map_err(McuError::short(path, "a salt"))?;
+            header.extend_from_slice(&salt);
+            let password = prompt_password("Password: ")?;
+            derive_key(&password, &salt)?
+        }
+        FORMAT_RECIPIENT => {
+            let mut ephemeral = [0u8; recipient::PUBLIC_LEN];
+            input
+                .read_exact(&mut ephemeral)
+                .map_err(McuError::short(path, "an ephemeral public key"))?;
+            header.extend_from_slice(&ephemeral);
+            let secret = recipient::load_secret(key_path)?;
+            recipient::decapsulate(&secret, &ephemeral.into())?
+        }
+        format => {
+            return Err(McuError::Format(format!(
+                "unsupported format version {}",
+                format
+            )));
+        }
+    };
+    Ok((key, header))
+}
+
+/// Write a new file's header to `output`, the file at `path`: the key part
+/// from `new_header`, then `metadata`. The plaintext goes through the
+/// returned encryptor, which seals every segment with the header as
+/// associated data.
+fn start_encrypting<W: Write>(
+    mut output: W,
+    path: &str,
+    metadata: &Metadata,
+    source: &KeySource,
+) -> Result<stream::Encryptor<W>> {
+    let (mut header, key_bytes) = new_header(source)?;
+    header.extend_from_slice(&metadata.to_bytes()?);
+    output.write_all(&header).map_err(McuError::io(path))?;
+    stream::Encryptor::new(&key_bytes, &header, output).map_err(McuError::io(path))
+}
+
+/// Read an encrypted file's header and metadata from `input`, the file at
+/// `path`, returning the metadata and a reader of the plaintext that checks
+/// each segment before giving it out. `bar` follows `input` from there on.
+fn start_decrypting<'a>(
+    mut input: impl Read + 'a,
+    path: &str,
+    key_path: &str,
+    bar: &ProgressBar,
+) -> Result<(Metadata, Box<dyn Read + 'a>)> {
+    let (key_bytes, mut header) = read_header(&mut input, path, key_path)?;
+    let (metadata, raw) = Metadata::read(&mut input, path)?;
+    header.extend_from_slice(&raw);
+    bar.set_position(header.len() as u64);
+    let input = bar.wrap_read(input);
+    let plaintext =
+        stream::Decryptor::new(&key_bytes, &header, input).map_err(McuError::io(path))?;
+    Ok((metadata, Box::new(plaintext)))
+}
+
+/// Fail unless `length` bytes of plaintext is what `metadata` says, when it
+/// says
+fn check_length(metadata: &Metadata, length: u64) -> Result<()> {
+    if metadata.length.is_some_and(|expected| expected != length) {
+        return Err(McuError::Format(
+            "plaintext length does not match the metadata".into(),
+        ));
+    }
+    Ok(())
+}
+
+/// Encrypt what `input`, read from `from`, yields to `to`, under a header
+/// for `metadata`, failing if that is not as long as the metadata says.
+/// Returns the plaintext length.
+fn encrypt_stream(
+    input: impl Read,
+    from: &str,
+    to: &str,
+    metadata: &Metadata,
+    source: &KeySource,
+) -> Result<u64> {
+    let mut output = Output::create(to)?;
+    let mut encryptor = start_encrypting(&mut output, to, metadata, source)?;
+    let bar = progress::bar_or_counter(metadata.length, "encrypting");
+    let copied = progress::copy(&mut bar.wrap_read(input), from, &mut encryptor, to);
+    bar.finish_and_clear();
+    let length = copied?;
+    check_length(metadata, length)?;
+    encryptor.finish().map_err(McuError::io(to))?;
+    output.finish(to)?;
+    Ok(length)
+}
+
+/// Decrypt the encrypted file on `input`, `len` bytes if known, read from
+/// `from`, to `to`, returning its metadata and plaintext length
+fn decrypt_stream(
+    input: impl Read,
+    len: Option<u64>,
+    from: &str,
+    to: &str,
+    key_path: &str,
+) -> Result<(Metadata, u64)> {
+    let bar = progress::bar_or_counter(len, "decrypting");
+    let (metadata, mut plaintext) = start_decrypting(input, from, key_path, &bar)?;
+    let mut output = Output::create(to)?;
+    let copied = progress::copy(&mut plaintext, from, &mut output, to);
+    bar.finish_and_clear();
+    let length = copied?;
+    check_length(&metadata, length)?;
+    output.finish(to)?;
+    Ok((metadata, length))
+}
+
+fn print_metadata(metadata: &Metadata, piped: bool) {
+    let length = match metadata.length {
+        Some(length) => format!("{} bytes", length),
+        None => "length not recorded".to_string(),
+    };
+    status!(
+        piped,
+        "  original name: {}, {}, encrypted at {} (unix time)",
+        metadata.name, length, metadata.timestamp
+    );
+}
+
+/// Encrypt `infile` to `outfile`, either of which may be `-`; with stdio
+/// there is no hex preview, since the data itself is on a pipe
+fn encrypt_file(infile: &str, outfile: &str, source: &KeySource, verbose: bool) -> Result<()> {
+    let piped = outfile == STDIO;
+    let verbose = verbose && !piped && infile != STDIO;
+    status!(piped, "Encrypting {} → {}", infile, outfile);
+    if verbose {
+        hexdump::preview("plaintext", infile)?;
+    }
+
+    let (input, len) = open_input(infile)?;
+    let metadata = Metadata::new(infile, len);
+    let length = encrypt_stream(input, infile, outfile, &metadata, source)?;
+
+    if verbose {
+        hexdump::preview("ciphertext", outfile)?;
+    }
+    status!(piped, "✓ Encrypted: {} bytes", length);
+    Ok(())
+}
+
+/// Decrypt `infile` to `outfile`, either of which may be `-`. Plaintext is
+/// written a segment at a time as each one checks out, so to stdout a file
+/// that turns out to be cut short fails after the rest has gone out.
+fn decrypt_file(infile: &str, outfile: &str, key_path: &str, verbose: bool) -> Result<()> {
+    let piped = outfile == STDIO;
+    let verbose = verbose && !piped && infile != STDIO;
+    status!(piped, "Decrypting {} → {}", infile, outfile);
+    if verbose {
+        hexdump::preview("ciphertext", infile)?;
+    }
+
+    let (input, len) = open_input(infile)?;
+    let (metadata, length) = decrypt_stream(input, len, infile, outfile, key_path)?;
+    print_metadata(&metadata, piped);
+
+    if verbose {
+        hexdump::preview("plaintext", outfile)?;
+    }
+    status!(piped, "✓ Decrypted: {} bytes", length);
+    Ok(())
+}
+
+/// Pack `indir` into a tar archive, encrypted as one file as it is packed
+fn encrypt_dir(indir: &str, outfile: &str, source: &KeySource) -> Result<()> {
+    let piped = outfile == STDIO;
+    status!(piped, "Archiving {} → {}", indir, outfile);
+    let metadata = Metadata::new(indir, None);
+    let mut output = Output::create(outfile)?;
+    let mut encryptor = start_encrypting(&mut output, outfile, &metadata, source)?;
+    let bar = progress::bar_or_counter(None, "archiving");
+    let packed = archive::pack(indir, bar.wrap_write(&mut encryptor));
+    bar.finish_and_clear();
+    packed?;
+    encryptor.finish().map_err(McuError::io(outfile))?;
+    output.finish(outfile)?;
+    status!(piped, "✓ Encrypted: {} bytes of archive", bar.position());
+    Ok(())
+}
+
+/// Decrypt a file made by `encrypt_dir` and extract it as it is decrypted,
+/// moving what it holds into `outdir` once the whole file checks out
+fn decrypt_dir(infile: &str, outdir: &str, key_path: &str) -> Result<()> {
+    println!("Extracting {} → {}", infile, outdir);
+    let (input, len) = open_input(infile)?;
+    let bar = progress::bar_or_counter(len, "extracting");
+    let extracted =
+        start_decrypting(input, infile, key_path, &bar).and_then(|(metadata, plaintext)| {
+            archive::unpack(plaintext, outdir)?;
+            Ok(metadata)
+        });
+    bar.finish_and_clear();
+    let metadata = extracted?;
+    print_metadata(&metadata, false);
+    println!("✓ Extracted into {}", outdir);
+    Ok(())
+}
+
+/// Check every segment of an encrypted file without writing its plaintext
+/// anywhere
+fn verify_file(infile: &str, key_path: &str) -> Result<()> {
+    println!("Verifying {}", infile);
+    let (input, len) = open_input(infile)?;
+    let bar = progress::bar_or_counter(len, "verifying");
+    let (metadata, mut plaintext) = start_decrypting(input, infile, key_path, &bar)?;
+    let copied = progress::copy(&mut plaintext, infile, &mut io::sink(), "-");
+    bar.finish_and_clear();
+    check_length(&metadata, copied?)?;
+    print_metadata(&metadata, false);
+    println!("✓ Authentic");
+    Ok(())
+}
+
+/// Re-encrypt a key-file encrypted file under the key in `new_path`, keeping
+/// its metadata, without writing the plaintext anywhere
+fn rotate_file(infile: &str, outfile: &str, old_path: &str, new_path: &str) -> Result<()> {
+    let mut input = File::open(infile).map_err(McuError::io(infile))?;
+    let mut format = [0u8; 1];
+    input
+        .read_exact(&mut format)
+        .map_err(McuError::short(infile, "a header"))?;
+    if !matches!(format[0], FORMAT_KEY_FILE) {
+        return Err(McuError::Input(format!(
+            "{} was not encrypted with a key file",
+            infile
+        )));
+    }
+    let input = format.as_slice().chain(input);
+    let (metadata, plaintext) = start_decrypting(input, infile, old_path, &ProgressBar::hidden())?;
+    let source = KeySource::KeyFile(new_path);
+    encrypt_stream(plaintext, infile, outfile, &metadata, &source)?;
+    println!("✓ Rotated {} from {} to {}", outfile, old_path, new_path);
     Ok(())
 }
 // fn read_buf()
 
-fn main() -> Result<(), Box<dyn std::error::Error>>  {
+fn main() -> ExitCode {
     let cli = Cli::parse();
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<()> {
+    progress::set_quiet(cli.quiet);
+    secret::set_paranoid(cli.paranoid);
     match cli.command {
-        Commands::Encrypt { infile, outfile } => {
-            encrypt_file(infile.as_str(), outfile.as_str());
+        Commands::Encrypt {
+            infile,
+            indir,
+            outfile,
+            input,
+            output,
+            password,
+            recipient,
+            key,
+        } => {
+            let infile = infile.or(input);
+            let outfile = outfile.or(output).unwrap_or_default();
+            let source = match &recipient {
+                Some(recipient) => KeySource::Recipient(recipient),
+                None if password => KeySource::Password,
+                None => KeySource::KeyFile(&key),
+            };
+            if let Some(indir) = indir {
+                encrypt_dir(&indir, &outfile, &source)?;
+            } else if let Some(infile) = infile {
+                encrypt_file(infile.as_str(), outfile.as_str(), &source, cli.verbose)?;
+            }
         }
         
-        Commands::Decrypt { infile, outfile } => {
-            decrypt_file(infile.as_str(), outfile.as_str());
+        Commands::Decrypt {
+            infile,
+            outfile,
+            outdir,
+            input,
+            output,
+            key,
+        } => {
+            let infile = infile.or(input).unwrap_or_default();
+            let outfile = outfile.or(output);
+            if let Some(outdir) = outdir {
+                decrypt_dir(&infile, &outdir, &key)?;
+            } else if let Some(outfile) = outfile {
+                decrypt_file(infile.as_str(), outfile.as_str(), &key, cli.verbose)?;
+            }
         }
+
+        Commands::Verify {
+            infile,
+            key,
+            signature,
+            pubkey,
+        } => match signature {
+            Some(signature) => sign::verify_file(&infile, &pubkey, &signature)?,
+            None => verify_file(&infile, &key)?,
+        },
+
+        Commands::Sign { infile, key, out } => {
+            let out = out.unwrap_or_else(|| format!("{}.sig", infile));
+            sign::sign_file(&infile, &key, &out)?;
+        }
+
+        Commands::Keygen {
+            out,
+            passphrase,
+            signing,
+            x25519,
+        } => {
+            if x25519 {
+                let out = out.unwrap_or_else(|| "x25519.key".to_string());
+                let public = recipient::generate(&out)?;
+                println!(
+                    "✓ Wrote X25519 private key to {} and public key {} to {}",
+                    out,
+                    keys::fingerprint(public.as_bytes()),
+                    recipient::public_path(&out)
+                );
+            } else if signing {
+                let out = out.unwrap_or_else(|| "sign.key".to_string());
+                let public = sign::generate(&out)?;
+                println!(
+                    "✓ Wrote signing key to {} and public key {} to {}",
+                    out,
+                    sign::fingerprint(&public),
+                    sign::public_path(&out)
+                );
+            } else {
+                let out = out.unwrap_or_else(|| "key.bin".to_string());
+                let key = keys::generate_key()?;
+                keys::save_key(&out, &key, passphrase)?;
+                println!("✓ Wrote key to {}", out);
+            }
+        }
+
+        Commands::Bench {
+            sizes,
+            warmup,
+            seconds,
+        } => bench::run(&sizes, warmup, seconds)?,
+
+        Commands::Hexdump {
+            infile,
+            offset,
+            length,
+            page,
+        } => hexdump::dump_file(&infile, offset, length, page)?,
+
+        Commands::Key { action } => match action {
+            KeyCommands::Inspect { keyfile } => keys::inspect(&keyfile)?,
+            KeyCommands::Rotate {
+                old,
+                new,
+                infile,
+                outfile,
+            } => {
+                let outfile = outfile.unwrap_or_else(|| infile.clone());
+                rotate_file(&infile, &outfile, &old, &new)?;
+            }
+        },
         
-        Commands::Hash { infile } => {
-            println!("Hashing {}", infile);
-            let hash = hash_file(&infile)?;
-            println!("Blake3: {}", hash);
+        Commands::Hash {
+            infile,
+            algo,
+            check,
+            manifest,
+        } => {
+            if let Some(sums) = check {
+                hash::check(&sums, algo)?;
+            } else if let Some(infile) = infile {
+                println!("Hashing {}", infile);
+                let sums = hash::hash_path(&infile, algo)?;
+                print!("{}", hash::manifest(&sums));
+                if let Some(manifest) = manifest {
+                    fs::write(&manifest, hash::manifest(&sums)).map_err(McuError::io(&manifest))?;
+                    println!("✓ Wrote {} hashes to {}", sums.len(), manifest);
+                }
+            }
         }
     }
 
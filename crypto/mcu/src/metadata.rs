@@ -0,0 +1,140 @@
+// What an encrypted file says about its plaintext, stored in the clear in
+// the header but bound into the AEAD tag as associated data, so editing it
+// (or swapping the ciphertext under another header) fails decryption.
+//
+// Layout: name length (u16) || name || plaintext length (u64, all ones when
+// it was not known) || unix time (u64), all big-endian.
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Cursor, Read};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{McuError, Result};
+
+/// Plaintext length stored when it was not known
+const UNKNOWN_LENGTH: u64 = u64::MAX;
+
+pub struct Metadata {
+    /// File name of the plaintext, without its directory
+    pub name: String,
+    /// Plaintext size in bytes, unless it was not known before encrypting,
+    /// as for a pipe or a directory's archive
+    pub length: Option<u64>,
+    /// When it was encrypted, in seconds since the Unix epoch
+    pub timestamp: u64,
+}
+
+impl Metadata {
+    /// Metadata for `length` bytes of plaintext, if known, read from `path`
+    /// and encrypted now
+    pub fn new(path: &str, length: Option<u64>) -> Self {
+        let name = Path::new(path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_secs())
+            .unwrap_or(0);
+        Metadata {
+            name,
+            length,
+            timestamp,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let name = self.name.as_bytes();
+        let name_len = u16::try_from(name.len())
+            .map_err(|_| McuError::Input(format!("file name too long: {}", self.name)))?;
+        // Writing to a Vec cannot fail
+        let mut out = Vec::with_capacity(2 + name.len() + 16);
+        out.write_u16::<BigEndian>(name_len).unwrap();
+        out.extend_from_slice(name);
+        out.write_u64::<BigEndian>(self.length.unwrap_or(UNKNOWN_LENGTH))
+            .unwrap();
+        out.write_u64::<BigEndian>(self.timestamp).unwrap();
+        Ok(out)
+    }
+
+    /// Read metadata from `input`, the file at `path`, returning it along
+    /// with its bytes
+    pub fn read(input: &mut impl Read, path: &str) -> Result<(Self, Vec<u8>)> {
+        let mut raw = vec![0u8; 2];
+        input
+            .read_exact(&mut raw)
+            .map_err(McuError::short(path, "its metadata"))?;
+        let name_len = u16::from_be_bytes([raw[0], raw[1]]) as usize;
+        raw.resize(2 + name_len + 16, 0);
+        input
+            .read_exact(&mut raw[2..])
+            .map_err(McuError::short(path, "its metadata"))?;
+        let (metadata, _) = Metadata::parse(&raw)?;
+        Ok((metadata, raw))
+    }
+
+    /// Parse metadata at the start of `data`, returning it and what follows
+    fn parse(data: &[u8]) -> Result<(Self, &[u8])> {
+        let truncated = |_| McuError::Format("file too short for its metadata".into());
+        let mut cursor = Cursor::new(data);
+        let name_len = cursor.read_u16::<BigEndian>().map_err(truncated)?;
+        let mut name = vec![0u8; name_len as usize];
+        cursor.read_exact(&mut name).map_err(truncated)?;
+        let length = cursor.read_u64::<BigEndian>().map_err(truncated)?;
+        let timestamp = cursor.read_u64::<BigEndian>().map_err(truncated)?;
+        let metadata = Metadata {
+            name: String::from_utf8(name)
+                .map_err(|_| McuError::Format("file name is not UTF-8".into()))?,
+            length: (length != UNKNOWN_LENGTH).then_some(length),
+            timestamp,
+        };
+        Ok((metadata, &data[cursor.position() as usize..]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        for length in [Some(0), Some(1 << 40), None] {
+            let metadata = Metadata {
+                name: "notes.txt".into(),
+                length,
+                timestamp: 1_700_000_000,
+            };
+            let mut bytes = metadata.to_bytes().unwrap();
+            bytes.extend_from_slice(b"body");
+            let (read, raw) = Metadata::read(&mut bytes.as_slice(), "f").unwrap();
+            assert_eq!(raw, &bytes[..bytes.len() - 4]);
+            assert_eq!(read.name, "notes.txt");
+            assert_eq!(read.length, length);
+            assert_eq!(read.timestamp, 1_700_000_000);
+        }
+    }
+
+    #[test]
+    fn test_name_without_directory() {
+        let metadata = Metadata::new("some/dir/report.pdf", Some(3));
+        assert_eq!(metadata.name, "report.pdf");
+    }
+
+    #[test]
+    fn test_truncated_is_a_format_error() {
+        let bytes = Metadata::new("a.txt", None).to_bytes().unwrap();
+        for len in [1, 4, bytes.len() - 1] {
+            let read = Metadata::read(&mut &bytes[..len], "f");
+            assert!(matches!(read, Err(McuError::Format(_))));
+        }
+    }
+
+    #[test]
+    fn test_name_must_be_utf8() {
+        let mut bytes = Metadata::new("ab", None).to_bytes().unwrap();
+        bytes[2] = 0xff;
+        let read = Metadata::read(&mut bytes.as_slice(), "f");
+        assert!(matches!(read, Err(McuError::Format(_))));
+    }
+}
@@ -0,0 +1,78 @@
+// Progress bars on stderr for encrypting, hashing and writing large files, so
+// multi-GB operations do not look frozen. `--quiet` turns them all off, and
+// indicatif hides them by itself when stderr is not a terminal.
+
+use indicatif::{ProgressBar, ProgressStyle};
+use std::io::{ErrorKind, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{McuError, Result};
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Size of the reads and writes between progress updates
+const CHUNK: usize = 1 << 20;
+
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+/// A bar over `len` bytes showing bytes done, throughput and ETA
+pub fn bar(len: u64, message: &str) -> ProgressBar {
+    if QUIET.load(Ordering::Relaxed) {
+        return ProgressBar::hidden();
+    }
+    let style = ProgressStyle::with_template(
+        "{msg} [{bar:30}] {bytes}/{total_bytes} {binary_bytes_per_sec} ETA {eta}",
+    )
+    .expect("valid template")
+    .progress_chars("=> ");
+    ProgressBar::new(len)
+        .with_style(style)
+        .with_message(message.to_string())
+}
+
+/// `bar` over `len` bytes, or when the length is not known, as for a pipe,
+/// a count of the bytes done and the throughput
+pub fn bar_or_counter(len: Option<u64>, message: &str) -> ProgressBar {
+    let Some(len) = len else {
+        if QUIET.load(Ordering::Relaxed) {
+            return ProgressBar::hidden();
+        }
+        let style = ProgressStyle::with_template("{msg} {spinner} {bytes} {binary_bytes_per_sec}")
+            .expect("valid template");
+        return ProgressBar::no_length()
+            .with_style(style)
+            .with_message(message.to_string());
+    };
+    bar(len, message)
+}
+
+/// Copy `reader`, reading `from`, to `writer`, writing `to`, a chunk at a
+/// time, returning the bytes copied; an error is put down to whichever side
+/// it happened on
+pub fn copy(reader: &mut impl Read, from: &str, writer: &mut impl Write, to: &str) -> Result<u64> {
+    let mut buffer = vec![0u8; CHUNK];
+    let mut copied = 0;
+    loop {
+        let len = match reader.read(&mut buffer) {
+            Ok(0) => return Ok(copied),
+            Ok(len) => len,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(McuError::io(from)(e)),
+        };
+        writer.write_all(&buffer[..len]).map_err(McuError::io(to))?;
+        copied += len as u64;
+    }
+}
+
+/// `write_all` in chunks, with a progress bar
+pub fn write_all(writer: &mut impl Write, data: &[u8]) -> std::io::Result<()> {
+    let bar = bar(data.len() as u64, "writing");
+    for chunk in data.chunks(CHUNK) {
+        writer.write_all(chunk)?;
+        bar.inc(chunk.len() as u64);
+    }
+    bar.finish_and_clear();
+    Ok(())
+}
@@ -0,0 +1,171 @@
+// Hybrid encryption to someone else's X25519 public key: each file gets a
+// fresh ephemeral keypair, and the AES key is derived with HKDF-SHA256 from
+// the Diffie-Hellman shared secret. Only the ephemeral public key goes in the
+// header, so nothing symmetric has to be shared beforehand.
+//
+// Private key file: "MCUX" || 32-byte secret (owner-readable only)
+// Public key file: PEM SubjectPublicKeyInfo, the same as
+// `openssl pkey -pubout` prints for an X25519 key
+
+use aes_gcm::aead::OsRng;
+use hkdf::Hkdf;
+use pem_rfc7468::LineEnding;
+use sha2::Sha256;
+use std::fs;
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+use crate::{KEY_LEN, KeyBytes, McuError, Result, write_atomic};
+
+/// Magic bytes at the start of an X25519 private key file
+pub const EXCHANGE_MAGIC: &[u8; 4] = b"MCUX";
+
+/// X25519 public key size
+pub const PUBLIC_LEN: usize = 32;
+
+/// DER SubjectPublicKeyInfo for id-X25519 (1.3.101.110), up to the key
+const SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x6e, 0x03, 0x21, 0x00,
+];
+
+const PEM_LABEL: &str = "PUBLIC KEY";
+
+/// HKDF info string, so the derived key is bound to this use
+const HKDF_INFO: &[u8] = b"mcu x25519 aes-256-gcm v1";
+
+/// Public key file written next to the private key in `path`
+pub fn public_path(path: &str) -> String {
+    format!("{}.pem", path)
+}
+
+/// Generate a keypair: the private key in `path`, the public key next to it
+pub fn generate(path: &str) -> Result<PublicKey> {
+    let secret = StaticSecret::random_from_rng(OsRng);
+    let mut data = Zeroizing::new(EXCHANGE_MAGIC.to_vec());
+    data.extend_from_slice(secret.as_bytes());
+    write_atomic(path, &data)?;
+    let public = PublicKey::from(&secret);
+    let public_path = public_path(path);
+    fs::write(&public_path, to_pem(&public)?).map_err(McuError::io(&public_path))?;
+    Ok(public)
+}
+
+fn to_pem(public: &PublicKey) -> Result<String> {
+    let mut der = SPKI_PREFIX.to_vec();
+    der.extend_from_slice(public.as_bytes());
+    pem_rfc7468::encode_string(PEM_LABEL, LineEnding::LF, &der)
+        .map_err(|e| McuError::Key(format!("cannot encode public key: {}", e)))
+}
+
+pub fn load_secret(path: &str) -> Result<StaticSecret> {
+    let data = Zeroizing::new(fs::read(path).map_err(McuError::io(path))?);
+    let secret = data
+        .strip_prefix(EXCHANGE_MAGIC)
+        .and_then(|secret| <[u8; 32]>::try_from(secret).ok())
+        .map(Zeroizing::new)
+        .ok_or_else(|| {
+            McuError::Key(format!(
+                "{} is not a private key made by `mcu keygen --x25519`; \
+                 the file was encrypted to a recipient",
+                path
+            ))
+        })?;
+    Ok(StaticSecret::from(*secret))
+}
+
+pub fn load_public_key(path: &str) -> Result<PublicKey> {
+    let pem = fs::read(path).map_err(McuError::io(path))?;
+    let not_a_key = || McuError::Key(format!("{} is not an X25519 public key", path));
+    let (label, der) = pem_rfc7468::decode_vec(&pem).map_err(|_| not_a_key())?;
+    if label != PEM_LABEL {
+        return Err(not_a_key());
+    }
+    let bytes = der
+        .strip_prefix(&SPKI_PREFIX[..])
+        .and_then(|bytes| <[u8; PUBLIC_LEN]>::try_from(bytes).ok())
+        .ok_or_else(not_a_key)?;
+    Ok(PublicKey::from(bytes))
+}
+
+/// A fresh AES key for `recipient`, with the ephemeral public key that lets
+/// them derive it again
+pub fn encapsulate(recipient: &PublicKey) -> Result<(PublicKey, KeyBytes)> {
+    let ephemeral = EphemeralSecret::random_from_rng(OsRng);
+    let ephemeral_public = PublicKey::from(&ephemeral);
+    let shared = ephemeral.diffie_hellman(recipient);
+    if !shared.was_contributory() {
+        return Err(McuError::Key(
+            "recipient public key is a low-order point".into(),
+        ));
+    }
+    let key = derive(shared.as_bytes(), &ephemeral_public, recipient)?;
+    Ok((ephemeral_public, key))
+}
+
+/// The AES key `encapsulate` made for the owner of `secret`
+pub fn decapsulate(secret: &StaticSecret, ephemeral_public: &PublicKey) -> Result<KeyBytes> {
+    let shared = secret.diffie_hellman(ephemeral_public);
+    if !shared.was_contributory() {
+        return Err(McuError::Format("invalid ephemeral public key".into()));
+    }
+    derive(
+        shared.as_bytes(),
+        ephemeral_public,
+        &PublicKey::from(secret),
+    )
+}
+
+/// HKDF-SHA256 over the shared secret, salted with both public keys
+fn derive(shared: &[u8], ephemeral: &PublicKey, recipient: &PublicKey) -> Result<KeyBytes> {
+    let mut salt = ephemeral.as_bytes().to_vec();
+    salt.extend_from_slice(recipient.as_bytes());
+    let mut key = KeyBytes::zeroed(KEY_LEN)?;
+    Hkdf::<Sha256>::new(Some(&salt), shared)
+        .expand(HKDF_INFO, key.as_mut_slice())
+        .map_err(|e| McuError::Key(format!("key derivation failed: {}", e)))?;
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decapsulate_recovers_the_key() {
+        let secret = StaticSecret::random_from_rng(OsRng);
+        let (ephemeral, key) = encapsulate(&PublicKey::from(&secret)).unwrap();
+        assert_eq!(&decapsulate(&secret, &ephemeral).unwrap()[..], &key[..]);
+
+        let other = StaticSecret::random_from_rng(OsRng);
+        assert_ne!(&decapsulate(&other, &ephemeral).unwrap()[..], &key[..]);
+    }
+
+    #[test]
+    fn test_low_order_points_are_refused() {
+        let secret = StaticSecret::random_from_rng(OsRng);
+        let zero = PublicKey::from([0u8; PUBLIC_LEN]);
+        assert!(matches!(encapsulate(&zero), Err(McuError::Key(_))));
+        assert!(matches!(
+            decapsulate(&secret, &zero),
+            Err(McuError::Format(_))
+        ));
+    }
+
+    #[test]
+    fn test_generated_files_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("x25519.key");
+        let path = path.to_str().unwrap();
+        let public = generate(path).unwrap();
+        let pem = fs::read_to_string(public_path(path)).unwrap();
+        assert!(pem.starts_with("-----BEGIN PUBLIC KEY-----\n"));
+        assert_eq!(load_public_key(&public_path(path)).unwrap(), public);
+        assert_eq!(PublicKey::from(&load_secret(path).unwrap()), public);
+
+        assert!(matches!(
+            load_secret(&public_path(path)),
+            Err(McuError::Key(_))
+        ));
+        assert!(matches!(load_public_key(path), Err(McuError::Key(_))));
+    }
+}
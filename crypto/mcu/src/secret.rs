@@ -0,0 +1,96 @@
+// Key material in memory: every key buffer is zeroed when dropped, and with
+// `--paranoid` it is also mlocked so it never reaches swap. Locking needs
+// RLIMIT_MEMLOCK headroom (`ulimit -l`); it is only supported on Unix.
+
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, Ordering};
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::{McuError, Result};
+
+static PARANOID: AtomicBool = AtomicBool::new(false);
+
+pub fn set_paranoid(paranoid: bool) {
+    PARANOID.store(paranoid, Ordering::Relaxed);
+}
+
+/// A key, zeroed on drop, and locked in memory under `--paranoid`
+pub struct KeyBytes {
+    bytes: Zeroizing<Vec<u8>>,
+    locked: bool,
+}
+
+impl KeyBytes {
+    /// Take ownership of `bytes`, locking them if `--paranoid` is on
+    pub fn new(bytes: Vec<u8>) -> Result<Self> {
+        let mut key = KeyBytes {
+            bytes: Zeroizing::new(bytes),
+            locked: false,
+        };
+        if PARANOID.load(Ordering::Relaxed) && !key.bytes.is_empty() {
+            lock(&key.bytes)?;
+            key.locked = true;
+        }
+        Ok(key)
+    }
+
+    /// `len` zero bytes to fill in place, so the key is never copied out
+    /// of locked memory
+    pub fn zeroed(len: usize) -> Result<Self> {
+        KeyBytes::new(vec![0u8; len])
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.bytes
+    }
+}
+
+impl Deref for KeyBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl Drop for KeyBytes {
+    fn drop(&mut self) {
+        // Zero before unlocking, so the pages cannot be swapped out with
+        // the key still in them
+        let (ptr, len) = (self.bytes.as_ptr(), self.bytes.len());
+        self.bytes.as_mut_slice().zeroize();
+        if self.locked {
+            unlock(ptr, len);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn lock(bytes: &[u8]) -> Result<()> {
+    // SAFETY: the range is one live allocation owned by the caller
+    if unsafe { libc::mlock(bytes.as_ptr().cast(), bytes.len()) } != 0 {
+        return Err(McuError::Key(format!(
+            "cannot lock key memory for --paranoid: {} (raise `ulimit -l`)",
+            std::io::Error::last_os_error()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn unlock(ptr: *const u8, len: usize) {
+    // SAFETY: the range was locked by `lock` and is still allocated
+    unsafe {
+        libc::munlock(ptr.cast(), len);
+    }
+}
+
+#[cfg(not(unix))]
+fn lock(_: &[u8]) -> Result<()> {
+    Err(McuError::Key(
+        "--paranoid memory locking is not supported on this platform".into(),
+    ))
+}
+
+#[cfg(not(unix))]
+fn unlock(_: *const u8, _: usize) {}
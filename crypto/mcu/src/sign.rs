@@ -0,0 +1,111 @@
+// Detached ed25519 signatures for release files: the signature covers the
+// blake3 hash of the file, so signing a large image only reads it once.
+//
+// Signing key file: "MCUS" || 32-byte seed (owner-readable only)
+// Public key file and signature file: one line of hex, easy to publish
+
+use aes_gcm::aead::{OsRng, rand_core::RngCore};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use std::fs;
+use zeroize::Zeroizing;
+
+use crate::hash::{self, Algo};
+use crate::{McuError, Result, keys, write_atomic};
+
+/// Magic bytes at the start of a signing key file
+pub const SIGNING_MAGIC: &[u8; 4] = b"MCUS";
+
+/// Public key file written next to the signing key in `path`
+pub fn public_path(path: &str) -> String {
+    format!("{}.pub", path)
+}
+
+/// Generate a keypair: the signing key in `path`, the public key next to it
+pub fn generate(path: &str) -> Result<VerifyingKey> {
+    let mut seed = Zeroizing::new([0u8; 32]);
+    OsRng.fill_bytes(seed.as_mut());
+    let signing = SigningKey::from_bytes(&seed);
+    let mut data = Zeroizing::new(SIGNING_MAGIC.to_vec());
+    data.extend_from_slice(seed.as_ref());
+    write_atomic(path, &data)?;
+    let public = signing.verifying_key();
+    let public_path = public_path(path);
+    fs::write(
+        &public_path,
+        format!("{}\n", hex::encode(public.as_bytes())),
+    )
+    .map_err(McuError::io(&public_path))?;
+    Ok(public)
+}
+
+pub fn load_signing_key(path: &str) -> Result<SigningKey> {
+    let data = Zeroizing::new(fs::read(path).map_err(McuError::io(path))?);
+    let seed = data
+        .strip_prefix(SIGNING_MAGIC)
+        .and_then(|seed| <[u8; 32]>::try_from(seed).ok())
+        .map(Zeroizing::new)
+        .ok_or_else(|| {
+            McuError::Key(format!(
+                "{} is not a signing key made by `mcu keygen --signing`",
+                path
+            ))
+        })?;
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+pub fn load_public_key(path: &str) -> Result<VerifyingKey> {
+    let bytes = hex::decode(fs::read_to_string(path).map_err(McuError::io(path))?.trim())?;
+    let bytes = <[u8; 32]>::try_from(bytes.as_slice())
+        .map_err(|_| McuError::Key(format!("{} does not hold a 32-byte public key", path)))?;
+    Ok(VerifyingKey::from_bytes(&bytes)?)
+}
+
+/// Short identifier of a public key, to compare against a published one
+pub fn fingerprint(public: &VerifyingKey) -> String {
+    keys::fingerprint(public.as_bytes())
+}
+
+fn file_hash(path: &str) -> Result<Vec<u8>> {
+    let hash = hash::hash_file(path, Algo::Blake3).map_err(McuError::io(path))?;
+    Ok(hex::decode(hash)?)
+}
+
+/// Sign the blake3 hash of `infile` with the key in `key_path`, writing the
+/// signature as hex to `sig_path`
+pub fn sign_file(infile: &str, key_path: &str, sig_path: &str) -> Result<()> {
+    let signing = load_signing_key(key_path)?;
+    let signature = signing.sign(&file_hash(infile)?);
+    fs::write(sig_path, format!("{}\n", hex::encode(signature.to_bytes())))
+        .map_err(McuError::io(sig_path))?;
+    println!(
+        "✓ Signed {} with key {} → {}",
+        infile,
+        fingerprint(&signing.verifying_key()),
+        sig_path
+    );
+    Ok(())
+}
+
+/// Check the detached signature in `sig_path` over `infile` against the
+/// public key in `pub_path`
+pub fn verify_file(infile: &str, pub_path: &str, sig_path: &str) -> Result<()> {
+    let public = load_public_key(pub_path)?;
+    let bytes = hex::decode(
+        fs::read_to_string(sig_path)
+            .map_err(McuError::io(sig_path))?
+            .trim(),
+    )?;
+    let signature = Signature::from_slice(&bytes)?;
+    public
+        .verify(&file_hash(infile)?, &signature)
+        .map_err(|_| McuError::BadSignature {
+            file: infile.to_string(),
+            key: fingerprint(&public),
+        })?;
+    println!(
+        "✓ Good signature on {} by key {}",
+        infile,
+        fingerprint(&public)
+    );
+    Ok(())
+}
@@ -0,0 +1,261 @@
+// Segmented encryption, so a file of any size goes through in constant
+// memory with a progress bar over its bytes. The plaintext is cut into
+// SEGMENT-byte segments, each sealed with AES-256-GCM under a random nonce
+// prefix, its position and a last-segment flag (the STREAM construction),
+// with the file's header as associated data. A segment that is edited,
+// moved or dropped fails to open, and so does a file cut short before its
+// last segment.
+//
+// Layout after the header: nonce prefix (7 bytes) || segments, each
+// ciphertext || tag and all SEGMENT + 16 bytes long but the last.
+
+use aes_gcm::aead::stream::{DecryptorBE32, EncryptorBE32};
+use aes_gcm::aead::{KeyInit, OsRng, Payload, rand_core::RngCore};
+use aes_gcm::{Aes256Gcm, Key};
+use std::io::{self, Read, Write};
+use std::mem;
+
+use crate::McuError;
+
+/// Plaintext bytes per segment
+pub const SEGMENT: usize = 64 * 1024;
+
+/// AES-GCM tag size
+const TAG_LEN: usize = 16;
+
+/// Random part of each segment's nonce; the other 5 bytes are the segment's
+/// position and whether it is the last
+const PREFIX_LEN: usize = 7;
+
+/// Carry `error` through the `io::Error` a reader or writer must return;
+/// `McuError::io` takes it back out
+fn carry(error: McuError) -> io::Error {
+    io::Error::other(error)
+}
+
+/// A writer that encrypts what is written to it onto the wrapped writer, a
+/// segment at a time; `finish` seals the last segment
+pub struct Encryptor<W: Write> {
+    inner: W,
+    stream: EncryptorBE32<Aes256Gcm>,
+    aad: Vec<u8>,
+    /// Plaintext of the segment being filled
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> Encryptor<W> {
+    /// Start a body sealed under `key` with `aad` on `inner`, writing the
+    /// nonce prefix
+    pub fn new(key: &[u8], aad: &[u8], mut inner: W) -> io::Result<Self> {
+        let mut prefix = [0u8; PREFIX_LEN];
+        OsRng.fill_bytes(&mut prefix);
+        inner.write_all(&prefix)?;
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+        Ok(Encryptor {
+            inner,
+            stream: EncryptorBE32::from_aead(cipher, &prefix.into()),
+            aad: aad.to_vec(),
+            buffer: Vec::with_capacity(SEGMENT),
+        })
+    }
+
+    /// Seal what is left as the last segment, returning the wrapped writer
+    pub fn finish(self) -> io::Result<W> {
+        let Encryptor {
+            mut inner,
+            stream,
+            aad,
+            buffer,
+        } = self;
+        let sealed = stream
+            .encrypt_last(Payload {
+                msg: &buffer,
+                aad: &aad,
+            })
+            .map_err(|_| carry(McuError::Encrypt))?;
+        inner.write_all(&sealed)?;
+        Ok(inner)
+    }
+}
+
+impl<W: Write> Write for Encryptor<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        // A full segment is only sealed once more follows it, since the last
+        // one is sealed differently
+        if self.buffer.len() == SEGMENT && !data.is_empty() {
+            let sealed = self
+                .stream
+                .encrypt_next(Payload {
+                    msg: &self.buffer,
+                    aad: &self.aad,
+                })
+                .map_err(|_| carry(McuError::Encrypt))?;
+            self.inner.write_all(&sealed)?;
+            self.buffer.clear();
+        }
+        let taken = data.len().min(SEGMENT - self.buffer.len());
+        self.buffer.extend_from_slice(&data[..taken]);
+        Ok(taken)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A reader that decrypts the body on the wrapped reader, giving out each
+/// segment's plaintext only once its tag has been checked. Reading to the
+/// end checks the file was not cut short.
+pub struct Decryptor<R: Read> {
+    inner: R,
+    /// `None` once the last segment has been opened
+    stream: Option<DecryptorBE32<Aes256Gcm>>,
+    aad: Vec<u8>,
+    /// The next sealed segment, read ahead to tell whether it is the last
+    sealed: Vec<u8>,
+    /// Plaintext of the segment being read, and how much of it has been
+    /// read
+    plaintext: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read> Decryptor<R> {
+    /// Start on a body sealed under `key` with `aad` on `inner`, reading the
+    /// nonce prefix
+    pub fn new(key: &[u8], aad: &[u8], mut inner: R) -> io::Result<Self> {
+        let mut prefix = [0u8; PREFIX_LEN];
+        inner.read_exact(&mut prefix).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => {
+                carry(McuError::Format("file too short for a nonce".into()))
+            }
+            _ => e,
+        })?;
+        let sealed = read_segment(&mut inner)?;
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+        Ok(Decryptor {
+            inner,
+            stream: Some(DecryptorBE32::from_aead(cipher, &prefix.into())),
+            aad: aad.to_vec(),
+            sealed,
+            plaintext: Vec::new(),
+            position: 0,
+        })
+    }
+
+    /// Open the segment read ahead, reading the one after it
+    fn open_next(&mut self) -> io::Result<()> {
+        let next = read_segment(&mut self.inner)?;
+        let sealed = mem::replace(&mut self.sealed, next);
+        let payload = Payload {
+            msg: &sealed,
+            aad: &self.aad,
+        };
+        let opened = if self.sealed.is_empty() {
+            let stream = self.stream.take().expect("not past the last segment");
+            stream.decrypt_last(payload)
+        } else {
+            let stream = self.stream.as_mut().expect("not past the last segment");
+            stream.decrypt_next(payload)
+        };
+        self.plaintext = opened.map_err(|_| carry(McuError::Decrypt))?;
+        self.position = 0;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Decryptor<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.plaintext.len() {
+            if self.stream.is_none() {
+                return Ok(0);
+            }
+            self.open_next()?;
+        }
+        let rest = &self.plaintext[self.position..];
+        let len = rest.len().min(buf.len());
+        buf[..len].copy_from_slice(&rest[..len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+/// Read up to one sealed segment; less means it is the last, and nothing
+/// means the one before was
+fn read_segment(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut sealed = Vec::with_capacity(SEGMENT + TAG_LEN);
+    reader
+        .take((SEGMENT + TAG_LEN) as u64)
+        .read_to_end(&mut sealed)?;
+    Ok(sealed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [7u8; 32];
+    const AAD: &[u8] = b"header";
+
+    fn encrypt(key: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut encryptor = Encryptor::new(key, AAD, Vec::new()).unwrap();
+        encryptor.write_all(data).unwrap();
+        encryptor.finish().unwrap()
+    }
+
+    fn decrypt(key: &[u8], aad: &[u8], body: &[u8]) -> Result<Vec<u8>, McuError> {
+        let mut plaintext = Vec::new();
+        Decryptor::new(key, aad, body)
+            .and_then(|mut decryptor| decryptor.read_to_end(&mut plaintext))
+            .map_err(|e| McuError::carried(e).unwrap())?;
+        Ok(plaintext)
+    }
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_round_trip_across_segment_boundaries() {
+        for len in [0, 1, SEGMENT - 1, SEGMENT, SEGMENT + 1, 2 * SEGMENT + 5] {
+            let body = encrypt(&KEY, &data(len));
+            let segments = len.div_ceil(SEGMENT).max(1);
+            assert_eq!(body.len(), PREFIX_LEN + len + segments * TAG_LEN);
+            assert_eq!(decrypt(&KEY, AAD, &body).unwrap(), data(len));
+        }
+    }
+
+    #[test]
+    fn test_flipped_byte_fails() {
+        let mut body = encrypt(&KEY, &data(SEGMENT + 100));
+        body[PREFIX_LEN + SEGMENT + 50] ^= 1;
+        assert!(matches!(decrypt(&KEY, AAD, &body), Err(McuError::Decrypt)));
+    }
+
+    #[test]
+    fn test_dropped_last_segment_fails() {
+        let body = encrypt(&KEY, &data(2 * SEGMENT + 5));
+        let cut = &body[..PREFIX_LEN + 2 * (SEGMENT + TAG_LEN)];
+        assert!(matches!(decrypt(&KEY, AAD, cut), Err(McuError::Decrypt)));
+    }
+
+    #[test]
+    fn test_wrong_key_or_header_fails() {
+        let body = encrypt(&KEY, b"secret");
+        assert!(matches!(
+            decrypt(&[8u8; 32], AAD, &body),
+            Err(McuError::Decrypt)
+        ));
+        assert!(matches!(
+            decrypt(&KEY, b"other", &body),
+            Err(McuError::Decrypt)
+        ));
+    }
+
+    #[test]
+    fn test_missing_nonce_is_a_format_error() {
+        assert!(matches!(
+            decrypt(&KEY, AAD, &[0u8; 3]),
+            Err(McuError::Format(_))
+        ));
+    }
+}
@@ -1,8 +1,16 @@
-use csv::{ReaderBuilder, Writer};
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use clap::{Parser, Subcommand, ValueEnum};
+use csv::{ErrorKind, Reader, ReaderBuilder, StringRecord, Writer};
 use rayon::prelude::*;
-use serde::Serialize;
-use std::{error::Error, io, process, thread::AccessError};
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::process;
+
+/// Products transformed at a time with --parallel
+const CHUNK_SIZE: usize = 8192;
 
 #[derive(Debug, Serialize, Clone)]
 struct Product {
@@ -12,25 +20,110 @@ struct Product {
     price: f64,
 }
 
-fn apply_discounts(products: impl IntoIterator<Item = Product>) -> impl Iterator<Item = Product> {
-    products.into_iter().map(|mut p| {
-        p.price *= 0.9;
+/// Price products from a CSV file whose columns after the first are name,
+/// quantity and price
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// CSV file to read; stdin when omitted
+    #[arg(short, long, global = true)]
+    input: Option<String>,
+    /// File to write to; stdout when omitted
+    #[arg(short, long, global = true)]
+    output: Option<String>,
+    /// Transform products in chunks spread over all CPU cores, keeping their
+    /// order; worth it for big files
+    #[arg(long, global = true)]
+    parallel: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Take a percentage off the price of every product, or of those at or
+    /// above --min-price, and write them all out
+    Discount {
+        #[arg(short, long, default_value_t = 10.0)]
+        percent: f64,
+        #[arg(short, long, default_value_t = 0.0)]
+        min_price: f64,
+    },
+    /// Print the value of all the products: price times quantity
+    Total,
+    /// Add up the rows for each product name: quantity, value and price
+    /// range, most valuable first
+    Report {
+        /// Only list this many products
+        #[arg(short, long)]
+        top: Option<usize>,
+        #[arg(short, long, value_enum, default_value_t = Format::Table)]
+        format: Format,
+    },
+    /// Write out only the products that match every condition given
+    Filter {
+        #[arg(long)]
+        min_price: Option<f64>,
+        #[arg(long)]
+        max_price: Option<f64>,
+        #[arg(long)]
+        min_quantity: Option<u32>,
+        /// Only products whose name contains this, ignoring case
+        #[arg(long)]
+        name: Option<String>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum Format {
+    /// Aligned columns, with a total at the end
+    Table,
+    /// A header row, then one row per product
+    Csv,
+}
+
+/// Takes a percentage off the price of products costing at least a minimum
+struct Discount {
+    factor: f64,
+    min_price: f64,
+}
+
+impl Discount {
+    fn new(percent: f64, min_price: f64) -> Self {
+        Discount {
+            factor: 1.0 - percent / 100.0,
+            min_price,
+        }
+    }
+
+    /// `p` with its price discounted if it qualifies, rounded to the cent
+    fn apply(&self, mut p: Product) -> Product {
+        if p.price >= self.min_price {
+            p.price = (p.price * self.factor * 100.0).round() / 100.0;
+        }
         p
-    })
+    }
 }
 
-fn apply_discount_pure(mut p: Product) -> Product {
-    p.price *= 0.9;
-    p
+/// Conditions a product must all meet; `None` for any product
+struct Filter {
+    min_price: Option<f64>,
+    max_price: Option<f64>,
+    min_quantity: Option<u32>,
+    /// Lowercase, to match names ignoring case
+    name: Option<String>,
 }
 
-fn expensive_items(
-    products: impl IntoIterator<Item = Product>,
-) -> impl Iterator<Item = (String, f64)> {
-    products
-        .into_iter()
-        .filter(|p| p.price > 100.0)
-        .map(|p| (p.name, p.price * 0.9))
+impl Filter {
+    fn matches(&self, p: &Product) -> bool {
+        self.min_price.is_none_or(|min| p.price >= min)
+            && self.max_price.is_none_or(|max| p.price <= max)
+            && self.min_quantity.is_none_or(|min| p.quantity >= min)
+            && self
+                .name
+                .as_ref()
+                .is_none_or(|n| p.name.to_lowercase().contains(n))
+    }
 }
 
 fn total_value(products: impl IntoIterator<Item = Product>) -> f64 {
@@ -39,197 +132,446 @@ fn total_value(products: impl IntoIterator<Item = Product>) -> f64 {
         .fold(0.0, |acc, p| acc + p.price * p.quantity as f64)
 }
 
-fn write_products(file_name: String, products: Vec<Product>) -> Result<(), Box<dyn Error>> {
-    let mut wtr = Writer::from_path(file_name)?;
+/// `products` through `step`, which changes each one or drops it. With
+/// `parallel`, chunks of them go through it on rayon's threads, so at most
+/// a chunk is held in memory either way.
+fn pipeline<'a>(
+    mut products: impl Iterator<Item = Product> + 'a,
+    step: impl Fn(Product) -> Option<Product> + Send + Sync + 'a,
+    parallel: bool,
+) -> Box<dyn Iterator<Item = Product> + 'a> {
+    if !parallel {
+        return Box::new(products.filter_map(step));
+    }
+    let chunks = std::iter::from_fn(move || {
+        let chunk: Vec<Product> = products.by_ref().take(CHUNK_SIZE).collect();
+        (!chunk.is_empty()).then(|| chunk.into_par_iter().filter_map(&step).collect::<Vec<_>>())
+    });
+    Box::new(chunks.flatten())
+}
 
-    // wtr.write_record(&["nr", "name", "quantity", "price"])?;
+/// Every row of one product, added up
+#[derive(Debug, Serialize)]
+struct ProductTotals {
+    name: String,
+    rows: u64,
+    quantity: u64,
+    /// Price times quantity, over all the rows
+    value: f64,
+    min_price: f64,
+    max_price: f64,
+    /// Over the rows, not weighted by quantity
+    mean_price: f64,
+}
 
-    for product in products {
-        wtr.serialize(product)?;
+impl ProductTotals {
+    fn new(name: String) -> Self {
+        ProductTotals {
+            name,
+            rows: 0,
+            quantity: 0,
+            value: 0.0,
+            min_price: f64::INFINITY,
+            max_price: f64::NEG_INFINITY,
+            mean_price: 0.0,
+        }
     }
 
-    wtr.flush()?;
-    Ok(())
+    fn add(&mut self, p: &Product) {
+        self.rows += 1;
+        self.quantity += u64::from(p.quantity);
+        self.value += p.price * p.quantity as f64;
+        self.min_price = self.min_price.min(p.price);
+        self.max_price = self.max_price.max(p.price);
+        self.mean_price += (p.price - self.mean_price) / self.rows as f64;
+    }
 }
 
-fn read_products() -> Result<Vec<Product>, Box<dyn Error>> {
-    let mut rdr = ReaderBuilder::new()
-        .has_headers(true)
-        .from_reader(std::io::stdin());
-
-    let mut products: Vec<Product> = Vec::new();
+/// `products` added up by name, most valuable first, ties by name
+fn report(products: impl IntoIterator<Item = Product>) -> Vec<ProductTotals> {
+    let mut totals: HashMap<String, ProductTotals> = HashMap::new();
+    for p in products {
+        match totals.get_mut(&p.name) {
+            Some(product) => product.add(&p),
+            None => {
+                let mut product = ProductTotals::new(p.name.clone());
+                product.add(&p);
+                totals.insert(p.name, product);
+            }
+        }
+    }
+    let mut totals: Vec<ProductTotals> = totals.into_values().collect();
+    totals.sort_by(|a, b| {
+        b.value
+            .total_cmp(&a.value)
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    totals
+}
 
-    let mut i = 1;
+/// Write the `top` products of `totals`; a table ends with the total of all
+/// of them, listed or not
+fn write_report(
+    mut out: impl Write,
+    totals: &[ProductTotals],
+    top: usize,
+    format: Format,
+) -> Result<(), Box<dyn Error>> {
+    if format == Format::Csv {
+        let mut wtr = Writer::from_writer(out);
+        for product in totals.iter().take(top) {
+            wtr.serialize(product)?;
+        }
+        wtr.flush()?;
+        return Ok(());
+    }
 
-    for result in rdr.records() {
-        let record = result?;
-        let name = &record[1];
-        let price: f64 = record[3].parse().unwrap();
+    let width = totals
+        .iter()
+        .take(top)
+        .map(|p| p.name.chars().count())
+        .chain(["Product".len()])
+        .max()
+        .unwrap_or(0);
+    writeln!(
+        out,
+        "{:<width$}  {:>6}  {:>10}  {:>14}  {:>10}  {:>10}  {:>10}",
+        "Product", "Rows", "Quantity", "Value", "Min price", "Max price", "Mean price"
+    )?;
+    for p in totals.iter().take(top) {
+        writeln!(
+            out,
+            "{:<width$}  {:>6}  {:>10}  {:>14.2}  {:>10.2}  {:>10.2}  {:>10.2}",
+            p.name, p.rows, p.quantity, p.value, p.min_price, p.max_price, p.mean_price
+        )?;
+    }
+    let value: f64 = totals.iter().map(|p| p.value).sum();
+    let quantity: u64 = totals.iter().map(|p| p.quantity).sum();
+    writeln!(
+        out,
+        "{:<width$}  {:>6}  {:>10}  {:>14.2}",
+        "Total", "", quantity, value
+    )?;
+    Ok(())
+}
 
-        products.push(Product {
-            nr: i,
-            name: name.to_string(),
-            quantity: record[2].parse().unwrap(),
-            price,
-        });
+fn write_products(
+    out: impl Write,
+    products: impl IntoIterator<Item = Product>,
+) -> Result<(), Box<dyn Error>> {
+    let mut wtr = Writer::from_writer(out);
 
-        i += 1;
+    for product in products {
+        wtr.serialize(product)?;
     }
 
-    Ok(products)
+    wtr.flush()?;
+    Ok(())
 }
 
-#[derive(Debug, Serialize, Clone)]
-struct Order {
-    items: Vec<Product>,
+/// A row that could not be read as a product
+#[derive(Debug)]
+struct RowError {
+    line: u64,
+    message: String,
 }
 
-fn all_discounted_items(orders: impl IntoIterator<Item = Order>) -> impl Iterator<Item = Product> {
-    orders
-        .into_iter()
-        .flat_map(|order| order.items)
-        .map(|mut p| {
-            p.price *= 0.9;
-            p
-        })
+/// What went wrong with a row, naming the column if it was one field
+fn describe(error: &csv::Error, headers: &StringRecord) -> String {
+    match error.kind() {
+        ErrorKind::Deserialize { err, .. } => match err.field() {
+            Some(field) => format!(
+                "{}: {}",
+                headers.get(field as usize).unwrap_or("?"),
+                err.kind()
+            ),
+            None => err.kind().to_string(),
+        },
+        ErrorKind::UnequalLengths {
+            expected_len, len, ..
+        } => format!("expected {} fields, found {}", expected_len, len),
+        _ => error.to_string(),
+    }
 }
-// Moves items out of Vecs — no cloning, no extra alloc.
 
-fn main() {
-    if let Ok(products) = read_products() {
-        println!("{:?}", products);
-        // 1.
-        let discounted: Vec<Product> = products
-            .clone()
-            .into_iter()
-            .map(|p| Product {
-                price: p.price * 0.9,
-                ..p
-            })
-            .collect();
-        _ = write_products("01_into_ter_map_classic.csv".to_string(), discounted);
+/// A product row by column position, whatever the header row calls them: a
+/// first column that is ignored, then name, quantity and price
+#[derive(Deserialize)]
+struct Row(IgnoredAny, String, u32, f64);
+
+/// Products read a row at a time, numbered by their row. Rows that do not
+/// parse are skipped and kept in `errors`; an error reading the input itself
+/// ends the products, and is kept in `failed`.
+struct Products<R> {
+    rdr: Reader<R>,
+    headers: StringRecord,
+    record: StringRecord,
+    /// Rows read so far, good or bad
+    rows: u32,
+    errors: Vec<RowError>,
+    failed: Option<csv::Error>,
+}
 
-        // 2.
-        _ = write_products(
-            "02_impl_trait_apply.csv".to_string(),
-            apply_discounts(products.clone()).collect(),
-        );
+impl<R: Read> Products<R> {
+    fn new(input: R) -> Result<Self, csv::Error> {
+        let mut rdr = ReaderBuilder::new().has_headers(true).from_reader(input);
+        let headers = rdr.headers()?.clone();
+        Ok(Products {
+            rdr,
+            headers,
+            record: StringRecord::new(),
+            rows: 0,
+            errors: Vec::new(),
+            failed: None,
+        })
+    }
+}
 
-        // 3.
+impl<R: Read> Iterator for Products<R> {
+    type Item = Product;
 
-        _ = write_products(
-            "03_apply_pure.csv".to_string(),
-            products
-                .clone()
-                .into_iter()
-                .map(apply_discount_pure)
-                .collect(),
-        );
+    fn next(&mut self) -> Option<Product> {
+        loop {
+            let read = match self.rdr.read_record(&mut self.record) {
+                Ok(false) => return None,
+                Ok(true) => {
+                    self.rows += 1;
+                    self.record.deserialize(None)
+                }
+                Err(e) if e.is_io_error() => {
+                    self.failed = Some(e);
+                    return None;
+                }
+                Err(e) => {
+                    self.rows += 1;
+                    Err(e)
+                }
+            };
+            match read {
+                Ok(Row(_, name, quantity, price)) => {
+                    return Some(Product {
+                        nr: self.rows,
+                        name,
+                        quantity,
+                        price,
+                    });
+                }
+                Err(e) => self.errors.push(RowError {
+                    line: e
+                        .position()
+                        .or(self.record.position())
+                        .map_or(0, |position| position.line()),
+                    message: describe(&e, &self.headers),
+                }),
+            }
+        }
+    }
+}
 
-        // 4. iter() + cloned()
-        _ = write_products(
-            "04_iter_cloned.csv".to_string(),
-            products
-                .iter()
-                .cloned()
-                .map(|mut p| {
-                    p.price *= 0.9;
-                    p
-                })
-                .collect(),
+/// Report the rows that were left out on stderr, and whether there were any
+fn report_errors(errors: &[RowError]) -> bool {
+    for error in errors {
+        eprintln!("Skipped line {}: {}", error.line, error.message);
+    }
+    if !errors.is_empty() {
+        eprintln!(
+            "Skipped {} bad rows; the output leaves them out",
+            errors.len()
         );
+    }
+    !errors.is_empty()
+}
 
-        // 5. for_each
-
-        let mut discount = products.clone();
-        discount.iter_mut().for_each(|p| p.price *= 0.9);
-        _ = write_products("05_for_each.csv".to_string(), discount);
-
-        // 6. fold pervertion
-
-        let discounted: Vec<Product> =
-            products
-                .clone()
-                .into_iter()
-                .fold(Vec::new(), |mut acc, mut p| {
-                    p.price *= 0.9;
-                    acc.push(p);
-                    acc
-                });
-        _ = write_products("06_fold.csv".to_string(), discounted);
-
-        // 7 Scan pervertion
-        let discounted: Vec<Product> = products
-            .clone()
-            .into_iter()
-            .scan((), |_, mut p| {
-                p.price *= 0.9;
-                Some(p)
-            })
-            .collect();
-        _ = write_products("07_scan.csv".to_string(), discounted);
-
-        // 8 Conditional discount
+/// Run the command, and say whether any rows were skipped
+fn run(cli: &Cli) -> Result<bool, Box<dyn Error>> {
+    let input: Box<dyn Read> = match &cli.input {
+        Some(path) => Box::new(File::open(path).map_err(|e| format!("{}: {}", path, e))?),
+        None => Box::new(io::stdin().lock()),
+    };
+    let mut output: Box<dyn Write> = match &cli.output {
+        Some(path) => Box::new(File::create(path).map_err(|e| format!("{}: {}", path, e))?),
+        None => Box::new(io::stdout().lock()),
+    };
+    let mut products = Products::new(input)?;
+
+    match &cli.command {
+        Command::Discount { percent, min_price } => {
+            let discount = Discount::new(*percent, *min_price);
+            let discounted = pipeline(
+                products.by_ref(),
+                move |p| Some(discount.apply(p)),
+                cli.parallel,
+            );
+            write_products(output, discounted)?
+        }
+        Command::Report { top, format } => {
+            let totals = report(products.by_ref());
+            write_report(output, &totals, top.unwrap_or(totals.len()), *format)?
+        }
+        Command::Total => {
+            writeln!(output, "{:.2}", total_value(products.by_ref()))?;
+        }
+        Command::Filter {
+            min_price,
+            max_price,
+            min_quantity,
+            name,
+        } => {
+            let filter = Filter {
+                min_price: *min_price,
+                max_price: *max_price,
+                min_quantity: *min_quantity,
+                name: name.as_ref().map(|n| n.to_lowercase()),
+            };
+            let matching = pipeline(
+                products.by_ref(),
+                move |p| filter.matches(&p).then_some(p),
+                cli.parallel,
+            );
+            write_products(output, matching)?
+        }
+    }
+    if let Some(e) = products.failed {
+        return Err(e.into());
+    }
+    Ok(report_errors(&products.errors))
+}
 
-        let discounted: Vec<Product> = products
-            .clone()
-            .into_iter()
-            .map(|mut p| {
-                if p.price >= 100.0 {
-                    p.price *= 0.9;
-                }
-                p
-            })
-            .collect();
-        _ = write_products("08_conditional_map.csv".to_string(), discounted);
+fn main() {
+    let cli = Cli::parse();
+    match run(&cli) {
+        Ok(false) => {}
+        Ok(true) => process::exit(1),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    }
+}
 
-        // 9 Zip
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &str = "\
+id,name,quantity,price
+1,Apple,10,0.5
+2,Pear,lots,0.75
+3,Apple,4,0.8
+4,Plum,3
+5,Pear,2,1.25
+";
+
+    fn product(nr: u32, name: &str, quantity: u32, price: f64) -> Product {
+        Product {
+            nr,
+            name: name.to_string(),
+            quantity,
+            price,
+        }
+    }
 
-        let prices = products.iter().map(|p| p.price * 0.9);
+    #[test]
+    fn test_bad_rows_are_skipped_with_their_line() {
+        let mut products = Products::new(INPUT.as_bytes()).unwrap();
+        let read: Vec<(u32, String)> = products.by_ref().map(|p| (p.nr, p.name)).collect();
+        assert_eq!(
+            read,
+            [(1, "Apple".into()), (3, "Apple".into()), (5, "Pear".into())]
+        );
+        assert!(products.failed.is_none());
 
-        let discounted: Vec<Product> = products
-            .clone()
-            .into_iter()
-            .zip(prices)
-            .map(|(mut p, new_price)| {
-                p.price = new_price;
-                p
-            })
+        let errors: Vec<(u64, &str)> = products
+            .errors
+            .iter()
+            .map(|e| (e.line, e.message.as_str()))
             .collect();
-        _ = write_products("09_zip.csv".to_string(), discounted);
-
-        // 10 conditional impl
-
-        for (name, disc) in expensive_items(products.clone()) {
-            println!("{} {:4.2}", name, disc);
-        }
-
-        // 11 Total value
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].0, 3);
+        assert!(errors[0].1.starts_with("quantity: "), "{}", errors[0].1);
+        assert_eq!(errors[1], (5, "expected 4 fields, found 3"));
+        assert!(report_errors(&products.errors));
+    }
 
-        println!("Total value: {}", total_value(products.clone()));
+    #[test]
+    fn test_columns_by_position() {
+        let input = "sku,title,qty,cost\nA-1,Fig,2,3.5\n";
+        let read: Vec<Product> = Products::new(input.as_bytes()).unwrap().collect();
+        assert_eq!(read.len(), 1);
+        assert_eq!((read[0].name.as_str(), read[0].quantity), ("Fig", 2));
+        assert_eq!(read[0].price, 3.5);
+    }
 
-        // 11 FlatMap
+    #[test]
+    fn test_report_totals() {
+        let totals = report(Products::new(INPUT.as_bytes()).unwrap());
+        let names: Vec<&str> = totals.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["Apple", "Pear"]);
+
+        let apple = &totals[0];
+        assert_eq!((apple.rows, apple.quantity), (2, 14));
+        assert!((apple.value - 8.2).abs() < 1e-9);
+        assert_eq!((apple.min_price, apple.max_price), (0.5, 0.8));
+        assert!((apple.mean_price - 0.65).abs() < 1e-9);
+        let pear = &totals[1];
+        assert_eq!((pear.rows, pear.quantity, pear.value), (1, 2, 2.5));
+
+        let mut table = Vec::new();
+        write_report(&mut table, &totals, 1, Format::Table).unwrap();
+        let table = String::from_utf8(table).unwrap();
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with("Apple"));
+        // The total covers the products left out of the listing too
+        assert!(lines[2].starts_with("Total"));
+        assert!(lines[2].ends_with(" 16           10.70"), "{}", lines[2]);
+
+        let mut csv = Vec::new();
+        write_report(&mut csv, &totals, 5, Format::Csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), 3);
+        assert!(csv.starts_with("name,rows,quantity,value,min_price,max_price,mean_price\n"));
+    }
 
-        let order = Order {
-            items: products.clone(),
+    #[test]
+    fn test_discount_and_filter() {
+        let discount = Discount::new(10.0, 1.0);
+        assert_eq!(discount.apply(product(1, "a", 1, 2.35)).price, 2.12);
+        assert_eq!(discount.apply(product(1, "a", 1, 0.99)).price, 0.99);
+
+        let filter = Filter {
+            min_price: Some(1.0),
+            max_price: None,
+            min_quantity: Some(2),
+            name: Some("pe".into()),
         };
-        let orders = vec![order];
-        println!(
-            "Discounted: {:?}",
-            all_discounted_items(orders).collect::<Vec<_>>()
-        );
-
-        let many_products: Vec<_> = products.clone().iter().cycle().cloned().take(5).collect();
-
-        let part_sum: Vec<_> = many_products
-            .clone()
-            .par_iter()
-            .fold(|| 0f64, |acc, p| acc + (p.price * (p.quantity as f64)))
-            .collect();
+        assert!(filter.matches(&product(1, "Pear", 2, 1.25)));
+        assert!(!filter.matches(&product(1, "Pear", 1, 1.25)));
+        assert!(!filter.matches(&product(1, "Apple", 2, 1.25)));
+        assert!(!filter.matches(&product(1, "Pear", 2, 0.5)));
+    }
 
-        let totsl_sum: f64 = part_sum.into_par_iter().sum();
+    #[test]
+    fn test_parallel_pipeline_keeps_order() {
+        let products = || (1..=3 * CHUNK_SIZE as u32 + 7).map(|nr| product(nr, "p", nr, 1.0));
+        let step = |p: Product| (!p.nr.is_multiple_of(3)).then_some(p);
+        let sequential: Vec<u32> = pipeline(products(), step, false).map(|p| p.nr).collect();
+        let parallel: Vec<u32> = pipeline(products(), step, true).map(|p| p.nr).collect();
+        assert_eq!(sequential.len(), 2 * CHUNK_SIZE + 5);
+        assert_eq!(parallel, sequential);
+    }
 
-        println!("{:?}", &many_products);
-        println!("Total sum:{}", totsl_sum);
+    #[test]
+    fn test_unreadable_input_fails() {
+        struct Broken;
+        impl Read for Broken {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::other("disk gone"))
+            }
+        }
+        let input = INPUT.as_bytes().chain(Broken);
+        let mut products = Products::new(input).unwrap();
+        assert_eq!(products.by_ref().count(), 3);
+        assert!(products.failed.is_some());
     }
 }
@@ -0,0 +1,32 @@
+//! Loading the YAML or JSON files that configure the pipeline, like the
+//! transformation rules and the schema.
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::path::Path;
+
+use crate::EtlError;
+
+/// Parse a `.json`, `.yaml` or `.yml` file; `what` names it in errors
+pub fn load<T: DeserializeOwned>(path: &str, what: &str) -> Result<T, EtlError> {
+    let text = std::fs::read_to_string(path).map_err(|e| EtlError::Open {
+        target: path.to_string(),
+        reason: e.to_string(),
+    })?;
+    let invalid = |reason: String| EtlError::Config(format!("invalid {what} in {path}: {reason}"));
+    let extension = Path::new(path).extension().and_then(|e| e.to_str());
+    match extension {
+        Some("json") => serde_json::from_str(&text).map_err(|e| invalid(e.to_string())),
+        Some("yaml" | "yml") => from_yaml(&text).map_err(invalid),
+        _ => Err(EtlError::Config(format!(
+            "{what} file {path} must end in .json, .yaml or .yml"
+        ))),
+    }
+}
+
+/// serde_yaml reads enums as `!tag`s; go through a JSON value so files are
+/// written the same way (`- clamp: {...}`) in both formats
+pub fn from_yaml<T: DeserializeOwned>(text: &str) -> Result<T, String> {
+    let value: Value = serde_yaml::from_str(text).map_err(|e| e.to_string())?;
+    serde_json::from_value(value).map_err(|e| e.to_string())
+}
@@ -1,159 +1,585 @@
 use log::{info, warn};
 
-use csv::Writer;
-use serde::Serialize;
-use std::error::Error;
+use clap::{Parser, ValueEnum};
 use std::path::Path;
+use std::time::{Duration, Instant};
+
+mod config;
+mod manifest;
+#[cfg(feature = "postgres")]
+mod postgres;
+mod report;
+mod schema;
+mod sink;
+mod source;
+mod state;
+#[cfg(feature = "metrics")]
+mod telemetry;
+mod transform;
+
+use manifest::Manifest;
+use report::{DeadLetter, EtlReport};
+use schema::Schema;
+use sink::{Retrying, Sink};
+use source::Source;
+use state::Watermark;
+use transform::TransformPipeline;
+
+/// One record: field names to values, in the order they were read
+type Record = serde_json::Map<String, serde_json::Value>;
 
 #[derive(Debug, thiserror::Error)]
 enum EtlError {
     #[error("Invalid data point: id={0}, reason: {1}")]
-    InvalidData(u32, String),
+    InvalidData(String, String),
+    #[error("Cannot read {target}: {reason}")]
+    Open { target: String, reason: String },
+    #[error("Malformed record: {0}")]
+    Parse(String),
+    #[error("Invalid field {field} for id={id}: {violation}")]
+    Schema {
+        id: String,
+        field: String,
+        violation: schema::Violation,
+    },
+    #[error("Cannot write {target}: {reason}")]
+    Write { target: String, reason: String },
+    /// A failure that may go away on retry, like a dropped connection
+    #[error("Cannot reach {target}: {reason}")]
+    Unavailable { target: String, reason: String },
+    #[error("{0}")]
+    Config(String),
 }
 
-#[derive(Debug)]
-struct RawData {
-    id: u32,
-    value: i32,
+impl EtlError {
+    /// Whether retrying the operation may succeed
+    fn is_transient(&self) -> bool {
+        matches!(self, EtlError::Unavailable { .. })
+    }
+}
+
+/// How records are stored at an input or output
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum Format {
+    Csv,
+    /// Newline-delimited JSON
+    Ndjson,
+    Postgres,
+}
+
+impl Format {
+    /// The format a `postgres://` URL or a file extension names
+    fn detect(target: &str) -> Result<Format, EtlError> {
+        if target.starts_with("postgres://") || target.starts_with("postgresql://") {
+            return Ok(Format::Postgres);
+        }
+        let extension = Path::new(target)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("csv") => Ok(Format::Csv),
+            Some("ndjson" | "jsonl") => Ok(Format::Ndjson),
+            _ => Err(EtlError::Config(format!(
+                "cannot tell the format of {target}; pass --input-format or --output-format"
+            ))),
+        }
+    }
 }
 
-#[derive(Debug, Clone, Serialize)]
-struct CleanData {
-    id: u32,
-    value: i32,
+#[derive(Parser)]
+#[command(
+    name = "etl-processor",
+    version,
+    about = "Extract, clean and load records"
+)]
+struct Cli {
+    /// CSV or NDJSON file, glob pattern of files (quoted, like 'data/*.csv')
+    /// or postgres:// URL to read; the built-in sample records when omitted
+    #[arg(short, long)]
+    input: Option<String>,
+    /// Format of --input, when its extension does not say
+    #[arg(long, value_enum)]
+    input_format: Option<Format>,
+    /// Table to read from a postgres input
+    #[arg(long, default_value = "raw_data")]
+    input_table: String,
+    /// Files to read at once when --input is a glob pattern
+    #[arg(long, default_value_t = 4)]
+    workers: usize,
+    /// CSV or NDJSON file, or postgres:// URL, to write; output.csv in /data
+    /// (in Docker) or the working directory when omitted
+    #[arg(short, long)]
+    output: Option<String>,
+    /// Format of --output, when its extension does not say
+    #[arg(long, value_enum)]
+    output_format: Option<Format>,
+    /// Table to write to a postgres output, created if missing
+    #[arg(long, default_value = "clean_data")]
+    output_table: String,
+    /// YAML or JSON file of transformation rules; the default cleaning
+    /// (integer ids and values, no id 0, values clamped to 0-100) when omitted
+    #[arg(short, long)]
+    rules: Option<String>,
+    /// YAML or JSON file of field constraints to validate records against
+    /// before transforming them; no validation when omitted
+    #[arg(short, long)]
+    schema: Option<String>,
+    /// File or postgres:// URL to write records that could not be read or
+    /// were dropped by a rule, with the reason; they are only logged when
+    /// omitted
+    #[arg(long)]
+    dead_letter: Option<String>,
+    /// Format of --dead-letter, when its extension does not say
+    #[arg(long, value_enum)]
+    dead_letter_format: Option<Format>,
+    /// Table to write to a postgres dead-letter output, created if missing
+    #[arg(long, default_value = "dead_letters")]
+    dead_letter_table: String,
+    /// Threads to transform on; 0 for one per CPU core
+    #[arg(long, default_value_t = 0)]
+    threads: usize,
+    /// Records each thread transforms at a time
+    #[arg(long, default_value_t = transform::DEFAULT_CHUNK_SIZE)]
+    chunk_size: usize,
+    /// Times to retry writing an output after a transient failure, like a
+    /// dropped database connection
+    #[arg(long, default_value_t = 3)]
+    retries: u32,
+    /// Milliseconds to wait before the first retry, doubling for each one
+    /// after (up to 30 seconds)
+    #[arg(long, default_value_t = 500)]
+    retry_delay: u64,
+    /// JSON file or postgres:// URL keeping the watermark between runs, so
+    /// each run only processes records past it; every run processes every
+    /// record when omitted
+    #[arg(long)]
+    state: Option<String>,
+    /// Table to keep the watermark in for a postgres state, created if
+    /// missing
+    #[arg(long, default_value = "etl_state")]
+    state_table: String,
+    /// Field that orders the records, like an id or an ISO 8601 timestamp
+    #[arg(long, default_value = "id", requires = "state")]
+    watermark: String,
+    /// Ignore the saved watermark and process every record, then save the
+    /// new one
+    #[arg(long, requires = "state")]
+    full_refresh: bool,
+    /// Write a JSON manifest of the run (counts, stage durations, output
+    /// checksums) to this file, whether it succeeds or fails
+    #[arg(long)]
+    manifest: Option<String>,
+    /// Write the run's metrics in the Prometheus text format to this file
+    #[cfg(feature = "metrics")]
+    #[arg(long)]
+    metrics: Option<String>,
 }
 
-fn total_value(data: &[CleanData]) -> f64 {
-    data.iter().fold(0.0, |acc, p| acc + p.value as f64)
+/// Sum of the numeric `value` fields; records without one count as zero
+fn total_value(data: &[Record]) -> f64 {
+    data.iter().fold(0.0, |acc, p| {
+        acc + p.get("value").and_then(|v| v.as_f64()).unwrap_or(0.0)
+    })
 }
 
 fn main() {
     env_logger::init();
-    
-    info!("Starting ETL process");
-    
-    let raw = vec![
-        RawData { id: 1, value: 1000 },
-        RawData { id: 2, value: -5 },
-        RawData { id: 3, value: 50 },
-        RawData { id: 4, value: 75 },
-    ];
+    let cli = Cli::parse();
 
-    info!("Extracted {} raw records", raw.len());
-    
-    let cleaned = transform(extract(raw));
+    info!("Starting ETL process");
 
-    info!("Transformed to {} clean records", cleaned.len());
-    
-    summary(&cleaned);
+    let mut manifest = Manifest::start();
+    let result = run(&cli, &mut manifest);
+    if let Err(e) = finish(&cli, &mut manifest, &result) {
+        log::error!("Cannot record the run: {e}");
+    }
 
-    if let Err(e) = load(&cleaned) {
-        log::error!("Failed to load data: {e}");
-        std::process::exit(1);
+    match result {
+        Ok(report) => report.log(),
+        Err(e) => {
+            log::error!("ETL process failed: {e}");
+            std::process::exit(1);
+        }
     }
-    
+
     info!("ETL process completed successfully");
 }
 
-fn summary(data: &[CleanData]) {
-    let total = total_value(data);
-    let mean = total / data.len() as f64;
-    info!("Summary - Total: {total}, Mean: {mean:.2}, Count: {}", data.len());
+/// Complete the manifest and write it and the metrics where asked
+fn finish(
+    cli: &Cli,
+    manifest: &mut Manifest,
+    result: &Result<EtlReport, EtlError>,
+) -> Result<(), EtlError> {
+    manifest.finish(result)?;
+    if let Some(path) = &cli.manifest {
+        manifest.write(path)?;
+        info!("Wrote the run manifest to {path}");
+    }
+    #[cfg(feature = "metrics")]
+    if let Some(path) = &cli.metrics {
+        let handle = telemetry::install()?;
+        telemetry::record(manifest);
+        telemetry::write(&handle, path)?;
+        info!("Wrote metrics to {path}");
+    }
+    Ok(())
 }
 
-fn write_data(filename: &str, data: &[CleanData]) -> Result<(), Box<dyn Error>> {
-    let mut wrt = Writer::from_path(filename)?;
-    for el in data {
-        wrt.serialize(el)?;
+fn run(cli: &Cli, manifest: &mut Manifest) -> Result<EtlReport, EtlError> {
+    let pipeline = match &cli.rules {
+        Some(path) => TransformPipeline::from_file(path)?,
+        None => TransformPipeline::default(),
     }
-    wrt.flush()?;
-    info!("Wrote {} records to {}", data.len(), filename);
-    Ok(())
+    .chunk_size(cli.chunk_size)
+    .threads(cli.threads)?;
+    let schema = cli.schema.as_deref().map(Schema::from_file).transpose()?;
+    let source = source::open(
+        cli.input.as_deref(),
+        cli.input_format,
+        &cli.input_table,
+        cli.workers,
+    )?;
+    let output = cli.output.clone().unwrap_or_else(sink::default_output);
+    let retry = sink::Retry {
+        retries: cli.retries,
+        delay: Duration::from_millis(cli.retry_delay),
+    };
+    let sink = Retrying::new(
+        sink::open(&output, cli.output_format, &cli.output_table)?,
+        retry,
+    );
+    let dead_letter = match &cli.dead_letter {
+        Some(target) => Some(Retrying::new(
+            sink::open(target, cli.dead_letter_format, &cli.dead_letter_table)?,
+            retry,
+        )),
+        None => None,
+    };
+    manifest.input = Some(source.describe());
+    manifest.output = Some(sink.describe());
+    manifest.dead_letter = dead_letter.as_ref().map(|d| d.describe());
+    manifest.writes(sink.file());
+    manifest.writes(dead_letter.as_ref().and_then(|d| d.file()));
+    let mut state = match &cli.state {
+        Some(target) => Some(state::open(target, &cli.state_table, &sink.describe())?),
+        None => None,
+    };
+    let mut watermark = match &mut state {
+        Some(state) => Some(load_watermark(state, &cli.watermark, cli.full_refresh)?),
+        None => None,
+    };
+
+    let report = process(
+        source,
+        schema.as_ref(),
+        &pipeline,
+        sink,
+        dead_letter,
+        watermark.as_mut(),
+    )?;
+    if let (Some(state), Some(watermark)) = (&mut state, &watermark) {
+        state.save(watermark)?;
+        info!("Saved watermark {} to {}", watermark, state.describe());
+    }
+    Ok(report)
 }
 
-fn extract(raw: Vec<RawData>) -> Vec<RawData> {
-    raw
+/// The saved watermark on `field`, or a fresh one for the first run or a
+/// full refresh
+fn load_watermark(
+    state: &mut impl state::StateStore,
+    field: &str,
+    full_refresh: bool,
+) -> Result<Watermark, EtlError> {
+    if full_refresh {
+        info!(
+            "Full refresh: ignoring the watermark in {}",
+            state.describe()
+        );
+        return Ok(Watermark::new(field));
+    }
+    match state.load()? {
+        Some(saved) if saved.field != field => Err(EtlError::Config(format!(
+            "{} tracks field {:?}, not {field:?}; pass --full-refresh to start over",
+            state.describe(),
+            saved.field
+        ))),
+        Some(saved) => {
+            info!("Resuming from watermark {}", saved);
+            Ok(saved)
+        }
+        None => Ok(Watermark::new(field)),
+    }
 }
 
-fn transform(raw: Vec<RawData>) -> Vec<CleanData> {
-    raw.into_iter()
-        .filter_map(|r| {
-            if r.id == 0 {
-                warn!("Skipping record with id=0");
-                return None;
-            }
+/// Extract, validate against `schema` if there is one, transform and load,
+/// sending every record that does not make it to `dead_letter` if there is
+/// one. With a `watermark`, only records past it are processed, and it is
+/// advanced over them.
+fn process(
+    mut source: impl Source,
+    schema: Option<&Schema>,
+    pipeline: &TransformPipeline,
+    sink: impl Sink,
+    dead_letter: Option<impl Sink>,
+    watermark: Option<&mut Watermark>,
+) -> Result<EtlReport, EtlError> {
+    let clock = Instant::now();
+    info!("Reading {}", source.describe());
+    let (mut raw, mut letters) = extract(&mut source)?;
+
+    info!("Extracted {} raw records", raw.len());
+
+    let mut report = EtlReport {
+        extracted: raw.len(),
+        malformed: letters.len(),
+        files: source.files(),
+        ..EtlReport::default()
+    };
+    report.durations.extract = clock.elapsed().as_secs_f64();
+    if let Some(watermark) = watermark {
+        raw.retain(|record| watermark.is_new(record));
+        report.skipped = report.extracted - raw.len();
+        info!("Skipped {} records already loaded", report.skipped);
+        watermark.advance(&raw);
+    }
+    if let Some(schema) = schema {
+        let clock = Instant::now();
+        let (valid, invalid, stats) = schema.run(raw);
+        stats.log();
+        info!(
+            "Validated {} records, {} invalid",
+            valid.len(),
+            invalid.len()
+        );
+        report.invalid = invalid.len();
+        report.violations = stats.fields;
+        letters.extend(invalid);
+        raw = valid;
+        report.durations.validate = clock.elapsed().as_secs_f64();
+    }
+    let clock = Instant::now();
+    let (cleaned, rejected, stats) = pipeline.run(raw);
+    stats.log();
+    report.rejected = rejected.len();
+    report.rules = stats.rules;
+    letters.extend(rejected);
+    report.durations.transform = clock.elapsed().as_secs_f64();
+
+    info!("Transformed to {} clean records", cleaned.len());
+
+    summary(&cleaned);
+
+    let clock = Instant::now();
+    if let Some(dead_letter) = dead_letter {
+        report.dead_letters = write_dead_letters(dead_letter, &letters)?;
+    }
+    report.loaded = load(sink, &cleaned)?;
+    report.durations.load = clock.elapsed().as_secs_f64();
+    Ok(report)
+}
 
-            let original_value = r.value;
-            let clamped = r.value.clamp(0, 100);
+fn summary(data: &[Record]) {
+    let total = total_value(data);
+    let mean = total / data.len() as f64;
+    info!(
+        "Summary - Total: {total}, Mean: {mean:.2}, Count: {}",
+        data.len()
+    );
+}
 
-            if clamped != original_value {
-                warn!("Value clamped for id={}: {} -> {}", r.id, original_value, clamped);
+/// Read every record from `source`, setting aside (and logging) malformed
+/// ones as dead letters
+fn extract(source: &mut impl Source) -> Result<(Vec<Record>, Vec<DeadLetter>), EtlError> {
+    let mut raw = Vec::new();
+    let mut malformed = Vec::new();
+    for record in source.records()? {
+        match record {
+            Ok(record) => raw.push(record),
+            Err(e) => {
+                warn!("Skipping record: {e}");
+                malformed.push(DeadLetter::extract(e));
             }
+        }
+    }
+    Ok((raw, malformed))
+}
 
-            Some(CleanData {
-                id: r.id,
-                value: clamped,
-            })
-        })
-        .collect()
+fn load(mut sink: impl Sink, data: &[Record]) -> Result<usize, EtlError> {
+    let written = sink.write(data)?;
+    info!("Wrote {} records to {}", written, sink.describe());
+    Ok(written)
 }
 
-fn load(data: &[CleanData]) -> Result<(), Box<dyn Error>> {
-    // Use /data directory for output (mounted volume in Docker)
-    let output_dir = if Path::new("/data").exists() {
-        "/data"
-    } else {
-        "."
-    };
-    
-    let filename = format!("{}/output.csv", output_dir);
-    write_data(&filename, data)?;
-    Ok(())
+fn write_dead_letters(mut sink: impl Sink, letters: &[DeadLetter]) -> Result<usize, EtlError> {
+    let records: Vec<Record> = letters.iter().map(DeadLetter::to_record).collect();
+    let written = sink.write(&records)?;
+    info!("Wrote {} dead letters to {}", written, sink.describe());
+    Ok(written)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serde_json::json;
+
+    fn record(id: i64, value: i64) -> Record {
+        json!({ "id": id, "value": value })
+            .as_object()
+            .unwrap()
+            .clone()
+    }
+
+    fn clean(raw: Vec<Record>) -> Vec<Record> {
+        let mut cleaned = Vec::new();
+        process(
+            raw,
+            None,
+            &TransformPipeline::default(),
+            &mut cleaned,
+            None::<Vec<Record>>,
+            None,
+        )
+        .unwrap();
+        cleaned
+    }
 
     #[test]
     fn test_clamp_high() {
-        let raw = vec![RawData { id: 1, value: 1000 }];
-        let cleaned = transform(extract(raw));
-        assert_eq!(cleaned[0].value, 100);
+        let cleaned = clean(vec![record(1, 1000)]);
+        assert_eq!(cleaned[0]["value"], 100);
     }
 
     #[test]
     fn test_clamp_low() {
-        let raw = vec![RawData { id: 1, value: -5 }];
-        let cleaned = transform(extract(raw));
-        assert_eq!(cleaned[0].value, 0);
+        let cleaned = clean(vec![record(1, -5)]);
+        assert_eq!(cleaned[0]["value"], 0);
     }
 
     #[test]
     fn test_no_clamp_needed() {
-        let raw = vec![RawData { id: 1, value: 50 }];
-        let cleaned = transform(extract(raw));
-        assert_eq!(cleaned[0].value, 50);
+        let cleaned = clean(vec![record(1, 50)]);
+        assert_eq!(cleaned[0]["value"], 50);
     }
 
     #[test]
     fn test_summary_calculation() {
-        let data = vec![
-            CleanData { id: 1, value: 100 },
-            CleanData { id: 2, value: 0 },
-        ];
+        let data = vec![record(1, 100), record(2, 0)];
         let total = total_value(&data);
         assert_eq!(total, 100.0);
     }
-    
+
     #[test]
     fn test_skip_zero_id() {
-        let raw = vec![
-            RawData { id: 0, value: 50 },
-            RawData { id: 1, value: 50 },
-        ];
-        let cleaned = transform(extract(raw));
+        let cleaned = clean(vec![record(0, 50), record(1, 50)]);
         assert_eq!(cleaned.len(), 1);
-        assert_eq!(cleaned[0].id, 1);
+        assert_eq!(cleaned[0]["id"], 1);
+    }
+
+    #[test]
+    fn test_report_and_dead_letters() {
+        let mut cleaned = Vec::new();
+        let mut dead = Vec::new();
+        let report = process(
+            vec![record(0, 50), record(1, 500), record(2, 5)],
+            None,
+            &TransformPipeline::default(),
+            &mut cleaned,
+            Some(&mut dead),
+            None,
+        )
+        .unwrap();
+        assert_eq!(report.extracted, 3);
+        assert_eq!(report.rejected, 1);
+        assert_eq!(report.loaded, 2);
+        assert_eq!(report.dead_letters, 1);
+        assert_eq!(report.rules[2].dropped, 1);
+        assert_eq!(report.rules[3].changed, 1);
+        assert_eq!(dead[0]["stage"], "transform");
+        assert_eq!(dead[0]["record"]["id"], 0);
+    }
+
+    #[test]
+    fn test_schema_rejects_before_transform() {
+        let schema = Schema::new(vec![schema::FieldSchema {
+            name: "value".into(),
+            kind: None,
+            required: false,
+            min: Some(0.0),
+            max: None,
+            pattern: None,
+        }])
+        .unwrap();
+        let mut cleaned = Vec::new();
+        let mut dead = Vec::new();
+        let report = process(
+            vec![record(1, -5), record(2, 500)],
+            Some(&schema),
+            &TransformPipeline::default(),
+            &mut cleaned,
+            Some(&mut dead),
+            None,
+        )
+        .unwrap();
+        assert_eq!((report.invalid, report.loaded), (1, 1));
+        assert_eq!(report.violations[0].out_of_range, 1);
+        assert_eq!(dead[0]["stage"], "validate");
+        assert_eq!(cleaned[0]["value"], 100);
+    }
+
+    #[test]
+    fn test_watermark_skips_loaded_records() {
+        let mut watermark = Watermark::new("id");
+        let mut cleaned = Vec::new();
+        let batch = vec![record(1, 10), record(2, 20)];
+        let run = |batch, watermark: &mut Watermark, cleaned: &mut Vec<Record>| {
+            let pipeline = TransformPipeline::default();
+            process(
+                batch,
+                None,
+                &pipeline,
+                cleaned,
+                None::<Vec<Record>>,
+                Some(watermark),
+            )
+            .unwrap()
+        };
+
+        let report = run(batch.clone(), &mut watermark, &mut cleaned);
+        assert_eq!((report.skipped, report.loaded), (0, 2));
+        assert_eq!(watermark.value, Some(json!(2)));
+
+        let mut next = batch;
+        next.push(record(3, 30));
+        let report = run(next, &mut watermark, &mut cleaned);
+        assert_eq!((report.skipped, report.loaded), (2, 1));
+        assert_eq!(cleaned.last().unwrap()["id"], 3);
+        assert_eq!(watermark.value, Some(json!(3)));
+    }
+
+    #[test]
+    fn test_watermark_on_another_field_needs_full_refresh() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = state::FileState::new(dir.path().join("state.json").to_str().unwrap());
+        state::StateStore::save(
+            &mut state,
+            &Watermark {
+                field: "id".into(),
+                value: Some(json!(5)),
+            },
+        )
+        .unwrap();
+        assert!(matches!(
+            load_watermark(&mut state, "updated_at", false),
+            Err(EtlError::Config(_))
+        ));
+        let fresh = load_watermark(&mut state, "updated_at", true).unwrap();
+        assert_eq!(fresh, Watermark::new("updated_at"));
+        assert_eq!(
+            load_watermark(&mut state, "id", false).unwrap().value,
+            Some(json!(5))
+        );
     }
 }
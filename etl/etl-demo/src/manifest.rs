@@ -0,0 +1,161 @@
+//! The run manifest: a JSON record of what one run read and wrote, how many
+//! records each stage let through and how long it took, and checksums of
+//! the files it produced, for monitoring and audits.
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io;
+use std::time::Instant;
+
+use crate::report::EtlReport;
+use crate::EtlError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Succeeded,
+    Failed,
+}
+
+/// A file the run wrote
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OutputFile {
+    pub path: String,
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Manifest {
+    /// Version of etl-processor that ran
+    pub version: &'static str,
+    /// RFC 3339 timestamps
+    pub started_at: String,
+    pub finished_at: Option<String>,
+    pub duration_seconds: f64,
+    pub status: Status,
+    pub error: Option<String>,
+    pub input: Option<String>,
+    pub output: Option<String>,
+    pub dead_letter: Option<String>,
+    /// Record counts and stage durations; `None` if the run failed
+    pub report: Option<EtlReport>,
+    /// Checksums of the local files written, if the run succeeded
+    pub files: Vec<OutputFile>,
+    #[serde(skip)]
+    clock: Instant,
+    #[serde(skip)]
+    written: Vec<String>,
+}
+
+impl Manifest {
+    /// A manifest for a run starting now, marked failed until it finishes
+    pub fn start() -> Self {
+        Manifest {
+            version: env!("CARGO_PKG_VERSION"),
+            started_at: jiff::Timestamp::now().to_string(),
+            finished_at: None,
+            duration_seconds: 0.0,
+            status: Status::Failed,
+            error: None,
+            input: None,
+            output: None,
+            dead_letter: None,
+            report: None,
+            files: Vec::new(),
+            clock: Instant::now(),
+            written: Vec::new(),
+        }
+    }
+
+    /// Note a local file the run writes, to checksum once it has
+    pub fn writes(&mut self, path: Option<&str>) {
+        self.written.extend(path.map(str::to_string));
+    }
+
+    /// Record how the run ended, checksumming its files if it succeeded
+    pub fn finish(&mut self, result: &Result<EtlReport, EtlError>) -> Result<(), EtlError> {
+        self.finished_at = Some(jiff::Timestamp::now().to_string());
+        self.duration_seconds = self.clock.elapsed().as_secs_f64();
+        match result {
+            Ok(report) => {
+                self.status = Status::Succeeded;
+                self.report = Some(report.clone());
+                self.files = self
+                    .written
+                    .iter()
+                    .map(|path| {
+                        checksum(path).map_err(|e| EtlError::Open {
+                            target: path.clone(),
+                            reason: e.to_string(),
+                        })
+                    })
+                    .collect::<Result<_, _>>()?;
+            }
+            Err(e) => {
+                self.status = Status::Failed;
+                self.error = Some(e.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), EtlError> {
+        let text = serde_json::to_string_pretty(self).expect("manifests serialize");
+        std::fs::write(path, text + "\n").map_err(|e| EtlError::Write {
+            target: path.to_string(),
+            reason: e.to_string(),
+        })
+    }
+}
+
+fn checksum(path: &str) -> io::Result<OutputFile> {
+    let mut hasher = Sha256::new();
+    let bytes = io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(OutputFile {
+        path: path.to_string(),
+        bytes,
+        sha256: hex::encode(hasher.finalize()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_finish_checksums_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.csv");
+        std::fs::write(&path, "abc").unwrap();
+
+        let mut manifest = Manifest::start();
+        manifest.writes(path.to_str());
+        manifest.writes(None);
+        manifest.finish(&Ok(EtlReport::default())).unwrap();
+        assert_eq!(manifest.status, Status::Succeeded);
+        assert_eq!(
+            manifest.files,
+            vec![OutputFile {
+                path: path.to_str().unwrap().to_string(),
+                bytes: 3,
+                sha256: "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_failed_run() {
+        let mut manifest = Manifest::start();
+        manifest.writes(Some("/nonexistent/out.csv"));
+        manifest
+            .finish(&Err(EtlError::Config("no input".into())))
+            .unwrap();
+        let json = serde_json::to_value(&manifest).unwrap();
+        assert_eq!(json["status"], "failed");
+        assert_eq!(json["error"], "no input");
+        assert_eq!(json["report"], serde_json::Value::Null);
+        assert!(manifest.files.is_empty());
+    }
+}
@@ -0,0 +1,470 @@
+//! PostgreSQL source and sink over sqlx. The pipeline is synchronous, so
+//! each one drives its queries on a small current-thread Tokio runtime.
+//!
+//! The source reads every column of its table; integer, float, text and
+//! boolean columns become fields of the same name. The sink creates its table
+//! if it does not exist, with a column per field of the records (`id` as the
+//! primary key when present), and upserts in one transaction: a record whose
+//! `id` is already in the table replaces that row, so rerunning a load after
+//! a failure never duplicates rows.
+//!
+//! The state store keeps each watermark as a row of `name`, `field` and the
+//! JSON-encoded `watermark`.
+
+use serde_json::Value;
+use sqlx::postgres::{PgArguments, PgRow};
+use sqlx::query::Query;
+use sqlx::{Column, Connection, PgConnection, Postgres, Row, TypeInfo};
+use tokio::runtime::Runtime;
+
+use crate::sink::{self, Sink};
+use crate::source::{Records, Source};
+use crate::state::{StateStore, Watermark};
+use crate::{EtlError, Record};
+
+fn runtime() -> Result<Runtime, EtlError> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| EtlError::Config(format!("cannot start the async runtime: {e}")))
+}
+
+/// Table names are spliced into SQL, so allow only plain (optionally
+/// schema-qualified) identifiers
+fn check_table(table: &str) -> Result<(), EtlError> {
+    let identifier = |part: &str| {
+        part.chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    };
+    if table.split('.').count() <= 2 && table.split('.').all(identifier) {
+        Ok(())
+    } else {
+        Err(EtlError::Config(format!("invalid table name {table:?}")))
+    }
+}
+
+/// The URL without its password, for logs
+fn redact(url: &str) -> String {
+    match (url.find("://"), url.rfind('@')) {
+        (Some(scheme), Some(at)) if at > scheme => {
+            let credentials = &url[scheme + 3..at];
+            let user = credentials.split(':').next().unwrap_or_default();
+            format!("{}{}@{}", &url[..scheme + 3], user, &url[at + 1..])
+        }
+        _ => url.to_string(),
+    }
+}
+
+/// Column names come from the records, so quote them
+fn quote(column: &str) -> String {
+    format!("\"{}\"", column.replace('"', "\"\""))
+}
+
+/// One column of a fetched row as a JSON value
+fn decode(row: &PgRow, index: usize) -> Result<Value, String> {
+    let column = &row.columns()[index];
+    let value = match column.type_info().name() {
+        "INT2" => row
+            .try_get::<Option<i16>, _>(index)
+            .map(|v| v.map(Value::from)),
+        "INT4" => row
+            .try_get::<Option<i32>, _>(index)
+            .map(|v| v.map(Value::from)),
+        "INT8" => row
+            .try_get::<Option<i64>, _>(index)
+            .map(|v| v.map(Value::from)),
+        "FLOAT4" => row
+            .try_get::<Option<f32>, _>(index)
+            .map(|v| v.map(Value::from)),
+        "FLOAT8" => row
+            .try_get::<Option<f64>, _>(index)
+            .map(|v| v.map(Value::from)),
+        "BOOL" => row
+            .try_get::<Option<bool>, _>(index)
+            .map(|v| v.map(Value::from)),
+        "TEXT" | "VARCHAR" | "BPCHAR" | "NAME" => row
+            .try_get::<Option<String>, _>(index)
+            .map(|v| v.map(Value::from)),
+        other => {
+            return Err(format!(
+                "column {} has unsupported type {other}",
+                column.name()
+            ))
+        }
+    };
+    value
+        .map(|v| v.unwrap_or(Value::Null))
+        .map_err(|e| e.to_string())
+}
+
+/// The SQL type for a column holding `values`: the widest of them, TEXT
+/// when they disagree
+fn sql_type<'a>(values: impl Iterator<Item = &'a Value>) -> &'static str {
+    let mut sql_type = None;
+    for value in values {
+        let this = match value {
+            Value::Null => continue,
+            Value::Bool(_) => "BOOLEAN",
+            Value::Number(n) if n.is_i64() || n.is_u64() => "BIGINT",
+            Value::Number(_) => "DOUBLE PRECISION",
+            _ => "TEXT",
+        };
+        sql_type = match (sql_type, this) {
+            (None, this) => Some(this),
+            (Some(a), b) if a == b => Some(a),
+            (Some("BIGINT" | "DOUBLE PRECISION"), "BIGINT" | "DOUBLE PRECISION") => {
+                Some("DOUBLE PRECISION")
+            }
+            _ => Some("TEXT"),
+        };
+    }
+    sql_type.unwrap_or("TEXT")
+}
+
+/// Bind `value` as a parameter of a column of `sql_type`
+fn bind<'q>(
+    query: Query<'q, Postgres, PgArguments>,
+    value: Option<&Value>,
+    sql_type: &str,
+) -> Query<'q, Postgres, PgArguments> {
+    let value = value.filter(|v| !v.is_null());
+    match sql_type {
+        "BIGINT" => query.bind(value.and_then(Value::as_i64)),
+        "DOUBLE PRECISION" => query.bind(value.and_then(Value::as_f64)),
+        "BOOLEAN" => query.bind(value.and_then(Value::as_bool)),
+        _ => query.bind(value.map(|v| match v {
+            Value::String(text) => text.clone(),
+            other => other.to_string(),
+        })),
+    }
+}
+
+/// Errors that may go away if the operation is retried: lost or refused
+/// connections, a server shutting down or out of connections, and
+/// serialization failures and deadlocks
+fn transient(e: &sqlx::Error) -> bool {
+    match e {
+        sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut | sqlx::Error::WorkerCrashed => true,
+        sqlx::Error::Database(db) => db.code().is_some_and(|code| {
+            code.starts_with("08")
+                || matches!(
+                    &*code,
+                    "40001" | "40P01" | "53300" | "57P01" | "57P02" | "57P03"
+                )
+        }),
+        _ => false,
+    }
+}
+
+/// `e` as an [`EtlError::Unavailable`] if it is transient, else as `error`
+fn classify(target: String, e: sqlx::Error, error: fn(String, String) -> EtlError) -> EtlError {
+    if transient(&e) {
+        EtlError::Unavailable {
+            target,
+            reason: e.to_string(),
+        }
+    } else {
+        error(target, e.to_string())
+    }
+}
+
+fn open_error(target: String, reason: String) -> EtlError {
+    EtlError::Open { target, reason }
+}
+
+fn write_error(target: String, reason: String) -> EtlError {
+    EtlError::Write { target, reason }
+}
+
+fn connect(runtime: &Runtime, url: &str) -> Result<PgConnection, EtlError> {
+    runtime
+        .block_on(PgConnection::connect(url))
+        .map_err(|e| classify(redact(url), e, open_error))
+}
+
+/// An insert of `columns` into `table` that replaces the row with the same
+/// `id`, if there is an `id` column
+fn upsert_sql(table: &str, columns: &[&str]) -> String {
+    let names: Vec<String> = columns.iter().map(|name| quote(name)).collect();
+    let placeholders: Vec<String> = (1..=columns.len()).map(|i| format!("${i}")).collect();
+    let mut sql = format!(
+        "INSERT INTO {table} ({}) VALUES ({})",
+        names.join(", "),
+        placeholders.join(", ")
+    );
+    if columns.contains(&"id") {
+        let updates: Vec<String> = columns
+            .iter()
+            .filter(|name| **name != "id")
+            .map(|name| format!("{0} = EXCLUDED.{0}", quote(name)))
+            .collect();
+        if updates.is_empty() {
+            sql.push_str(" ON CONFLICT (\"id\") DO NOTHING");
+        } else {
+            sql.push_str(&format!(
+                " ON CONFLICT (\"id\") DO UPDATE SET {}",
+                updates.join(", ")
+            ));
+        }
+    }
+    sql
+}
+
+pub struct PostgresSource {
+    url: String,
+    table: String,
+}
+
+impl PostgresSource {
+    pub fn new(url: &str, table: &str) -> Result<Self, EtlError> {
+        check_table(table)?;
+        Ok(PostgresSource {
+            url: url.to_string(),
+            table: table.to_string(),
+        })
+    }
+}
+
+impl Source for PostgresSource {
+    fn describe(&self) -> String {
+        format!("{} table {}", redact(&self.url), self.table)
+    }
+
+    fn records(&mut self) -> Result<Records<'_>, EtlError> {
+        let runtime = runtime()?;
+        let mut conn = connect(&runtime, &self.url)?;
+        let sql = format!("SELECT * FROM {}", self.table);
+        let rows = runtime
+            .block_on(sqlx::query(&sql).fetch_all(&mut conn))
+            .map_err(|e| EtlError::Open {
+                target: self.describe(),
+                reason: e.to_string(),
+            })?;
+        Ok(Box::new(rows.into_iter().map(|row| {
+            (0..row.columns().len())
+                .map(|index| {
+                    Ok((
+                        row.columns()[index].name().to_string(),
+                        decode(&row, index)?,
+                    ))
+                })
+                .collect::<Result<Record, String>>()
+                .map_err(EtlError::Parse)
+        })))
+    }
+}
+
+pub struct PostgresSink {
+    url: String,
+    table: String,
+}
+
+impl PostgresSink {
+    pub fn new(url: &str, table: &str) -> Result<Self, EtlError> {
+        check_table(table)?;
+        Ok(PostgresSink {
+            url: url.to_string(),
+            table: table.to_string(),
+        })
+    }
+
+    async fn insert(&self, conn: &mut PgConnection, data: &[Record]) -> Result<(), sqlx::Error> {
+        let columns: Vec<(&str, &str)> = sink::columns(data)
+            .into_iter()
+            .map(|name| (name, sql_type(data.iter().filter_map(|r| r.get(name)))))
+            .collect();
+        if columns.is_empty() {
+            return Ok(());
+        }
+        let definitions: Vec<String> = columns
+            .iter()
+            .map(|(name, sql_type)| match *name {
+                "id" => format!("{} {sql_type} PRIMARY KEY", quote(name)),
+                _ => format!("{} {sql_type}", quote(name)),
+            })
+            .collect();
+        let names: Vec<&str> = columns.iter().map(|(name, _)| *name).collect();
+
+        let mut tx = conn.begin().await?;
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {} ({})",
+            self.table,
+            definitions.join(", ")
+        ))
+        .execute(&mut *tx)
+        .await?;
+        let insert = upsert_sql(&self.table, &names);
+        for el in data {
+            let query = columns
+                .iter()
+                .fold(sqlx::query(&insert), |query, (name, sql_type)| {
+                    bind(query, el.get(*name), sql_type)
+                });
+            query.execute(&mut *tx).await?;
+        }
+        tx.commit().await
+    }
+}
+
+impl Sink for PostgresSink {
+    fn describe(&self) -> String {
+        format!("{} table {}", redact(&self.url), self.table)
+    }
+
+    fn write(&mut self, data: &[Record]) -> Result<usize, EtlError> {
+        let runtime = runtime()?;
+        let mut conn = connect(&runtime, &self.url)?;
+        runtime
+            .block_on(self.insert(&mut conn, data))
+            .map_err(|e| classify(self.describe(), e, write_error))?;
+        Ok(data.len())
+    }
+}
+
+pub struct PostgresState {
+    url: String,
+    table: String,
+    key: String,
+}
+
+impl PostgresState {
+    pub fn new(url: &str, table: &str, key: &str) -> Result<Self, EtlError> {
+        check_table(table)?;
+        Ok(PostgresState {
+            url: url.to_string(),
+            table: table.to_string(),
+            key: key.to_string(),
+        })
+    }
+
+    async fn create(&self, conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {} \
+             (name TEXT PRIMARY KEY, field TEXT NOT NULL, watermark TEXT)",
+            self.table
+        ))
+        .execute(conn)
+        .await
+        .map(|_| ())
+    }
+
+    async fn select(
+        &self,
+        conn: &mut PgConnection,
+    ) -> Result<Option<(String, Option<String>)>, sqlx::Error> {
+        self.create(conn).await?;
+        let sql = format!(
+            "SELECT field, watermark FROM {} WHERE name = $1",
+            self.table
+        );
+        sqlx::query_as(&sql)
+            .bind(&self.key)
+            .fetch_optional(conn)
+            .await
+    }
+
+    async fn upsert(
+        &self,
+        conn: &mut PgConnection,
+        watermark: &Watermark,
+    ) -> Result<(), sqlx::Error> {
+        self.create(conn).await?;
+        let sql = format!(
+            "INSERT INTO {} (name, field, watermark) VALUES ($1, $2, $3) \
+             ON CONFLICT (name) DO UPDATE SET field = $2, watermark = $3",
+            self.table
+        );
+        sqlx::query(&sql)
+            .bind(&self.key)
+            .bind(&watermark.field)
+            .bind(watermark.value.as_ref().map(Value::to_string))
+            .execute(conn)
+            .await
+            .map(|_| ())
+    }
+}
+
+impl StateStore for PostgresState {
+    fn describe(&self) -> String {
+        format!("{} table {} ({})", redact(&self.url), self.table, self.key)
+    }
+
+    fn load(&mut self) -> Result<Option<Watermark>, EtlError> {
+        let runtime = runtime()?;
+        let mut conn = connect(&runtime, &self.url)?;
+        let row = runtime
+            .block_on(self.select(&mut conn))
+            .map_err(|e| EtlError::Open {
+                target: self.describe(),
+                reason: e.to_string(),
+            })?;
+        let Some((field, watermark)) = row else {
+            return Ok(None);
+        };
+        let value = watermark
+            .map(|text| serde_json::from_str(&text))
+            .transpose()
+            .map_err(|e| EtlError::Config(format!("invalid state in {}: {e}", self.describe())))?;
+        Ok(Some(Watermark { field, value }))
+    }
+
+    fn save(&mut self, watermark: &Watermark) -> Result<(), EtlError> {
+        let runtime = runtime()?;
+        let mut conn = connect(&runtime, &self.url)?;
+        runtime
+            .block_on(self.upsert(&mut conn, watermark))
+            .map_err(|e| classify(self.describe(), e, write_error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_table() {
+        assert!(check_table("clean_data").is_ok());
+        assert!(check_table("etl.clean_data").is_ok());
+        assert!(check_table("clean_data; DROP TABLE x").is_err());
+        assert!(check_table("1table").is_err());
+        assert!(check_table("a.b.c").is_err());
+    }
+
+    #[test]
+    fn test_sql_type() {
+        let values = [Value::from(1), Value::Null, Value::from(2.5)];
+        assert_eq!(sql_type(values.iter()), "DOUBLE PRECISION");
+        let values = [Value::from(1), Value::from("a")];
+        assert_eq!(sql_type(values.iter()), "TEXT");
+        assert_eq!(sql_type([Value::Bool(true)].iter()), "BOOLEAN");
+        assert_eq!(quote("a\"b"), "\"a\"\"b\"");
+    }
+
+    #[test]
+    fn test_upsert_sql() {
+        assert_eq!(
+            upsert_sql("clean_data", &["id", "value"]),
+            "INSERT INTO clean_data (\"id\", \"value\") VALUES ($1, $2) \
+             ON CONFLICT (\"id\") DO UPDATE SET \"value\" = EXCLUDED.\"value\""
+        );
+        assert_eq!(
+            upsert_sql("t", &["id"]),
+            "INSERT INTO t (\"id\") VALUES ($1) ON CONFLICT (\"id\") DO NOTHING"
+        );
+        assert_eq!(
+            upsert_sql("dead_letters", &["stage", "reason"]),
+            "INSERT INTO dead_letters (\"stage\", \"reason\") VALUES ($1, $2)"
+        );
+    }
+
+    #[test]
+    fn test_redact() {
+        assert_eq!(
+            redact("postgres://etl:secret@db:5432/etl"),
+            "postgres://etl@db:5432/etl"
+        );
+        assert_eq!(redact("postgres://db/etl"), "postgres://db/etl");
+    }
+}
@@ -0,0 +1,177 @@
+//! What a run did: an [`EtlReport`] of counts for tests and orchestrators,
+//! and the [`DeadLetter`]s for records that did not make it through.
+
+use log::info;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::schema::FieldViolations;
+use crate::transform::RuleCounter;
+use crate::{EtlError, Record};
+
+/// The pipeline stage that turned a record away
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// It could not be read
+    Extract,
+    /// It broke the schema
+    Validate,
+    /// A rule dropped or rejected it
+    Transform,
+}
+
+/// A record that was not loaded, and why
+#[derive(Debug)]
+pub struct DeadLetter {
+    pub stage: Stage,
+    /// The rule that turned it away, for the transform stage
+    pub rule: Option<String>,
+    pub reason: EtlError,
+    /// The record as it was when turned away; `None` if it never parsed
+    pub record: Option<Record>,
+}
+
+impl DeadLetter {
+    pub fn extract(reason: EtlError) -> Self {
+        DeadLetter {
+            stage: Stage::Extract,
+            rule: None,
+            reason,
+            record: None,
+        }
+    }
+
+    pub fn validate(reason: EtlError, record: Record) -> Self {
+        DeadLetter {
+            stage: Stage::Validate,
+            rule: None,
+            reason,
+            record: Some(record),
+        }
+    }
+
+    pub fn transform(rule: String, reason: EtlError, record: Record) -> Self {
+        DeadLetter {
+            stage: Stage::Transform,
+            rule: Some(rule),
+            reason,
+            record: Some(record),
+        }
+    }
+
+    /// The dead letter as a record for a [`Sink`](crate::sink::Sink):
+    /// `stage`, `rule`, `reason` and the rejected `record`
+    pub fn to_record(&self) -> Record {
+        let mut fields = Record::new();
+        fields.insert("stage".into(), serde_json::to_value(self.stage).unwrap());
+        fields.insert(
+            "rule".into(),
+            self.rule.clone().map_or(Value::Null, Value::from),
+        );
+        fields.insert("reason".into(), Value::from(self.reason.to_string()));
+        fields.insert(
+            "record".into(),
+            self.record.clone().map_or(Value::Null, Value::Object),
+        );
+        fields
+    }
+}
+
+/// How reading one file of a glob input went
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileOutcome {
+    pub path: String,
+    /// Records read from the file
+    pub records: usize,
+    /// Records in it that could not be parsed
+    pub malformed: usize,
+    /// Why the file could not be read at all; its records are then skipped
+    pub error: Option<String>,
+}
+
+/// Seconds spent in each stage; writing dead letters counts as loading
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct StageDurations {
+    pub extract: f64,
+    pub validate: f64,
+    pub transform: f64,
+    pub load: f64,
+}
+
+/// Counts for one run of the pipeline
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct EtlReport {
+    /// Records read from the source
+    pub extracted: usize,
+    /// Records the source could not parse
+    pub malformed: usize,
+    /// Records at or below the watermark, loaded by an earlier run
+    pub skipped: usize,
+    /// Records that broke the schema
+    pub invalid: usize,
+    /// Records dropped or rejected by a rule
+    pub rejected: usize,
+    /// Records written to the sink
+    pub loaded: usize,
+    /// Dead letters written to `--dead-letter`
+    pub dead_letters: usize,
+    /// What each rule did, in rule order
+    pub rules: Vec<RuleCounter>,
+    /// How often each schema field was violated, in schema order
+    pub violations: Vec<FieldViolations>,
+    /// How each file went, when the input is a glob pattern
+    pub files: Vec<FileOutcome>,
+    pub durations: StageDurations,
+}
+
+impl EtlReport {
+    pub fn log(&self) {
+        info!(
+            "Report - Extracted: {}, Malformed: {}, Skipped: {}, Invalid: {}, Rejected: {}, Loaded: {}, \
+             Dead letters: {}",
+            self.extracted,
+            self.malformed,
+            self.skipped,
+            self.invalid,
+            self.rejected,
+            self.loaded,
+            self.dead_letters
+        );
+        let failed = self.files.iter().filter(|f| f.error.is_some()).count();
+        if !self.files.is_empty() {
+            info!(
+                "Files - Read: {}, Failed: {}",
+                self.files.len() - failed,
+                failed
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_dead_letter_record() {
+        let record = json!({"id": 0}).as_object().unwrap().clone();
+        let letter = DeadLetter::transform(
+            "drop id".into(),
+            EtlError::InvalidData("0".into(), "id must not be 0".into()),
+            record,
+        );
+        assert_eq!(
+            Value::Object(letter.to_record()),
+            json!({
+                "stage": "transform",
+                "rule": "drop id",
+                "reason": "Invalid data point: id=0, reason: id must not be 0",
+                "record": {"id": 0},
+            })
+        );
+        let letter = DeadLetter::extract(EtlError::Parse("in.ndjson:3: EOF".into()));
+        assert_eq!(letter.to_record()["record"], Value::Null);
+    }
+}
@@ -0,0 +1,335 @@
+//! The validation stage: a [`Schema`] of per-field constraints, loaded from
+//! a YAML or JSON file, checked between extract and transform. A record that
+//! breaks any constraint is rejected before the rules see it.
+//!
+//! ```yaml
+//! fields:
+//!   - { name: id, type: integer, required: true, min: 1 }
+//!   - { name: value, type: integer, min: -1000, max: 1000 }
+//!   - { name: email, type: string, pattern: "^[^@]+@[^@]+$" }
+//! ```
+
+use log::{info, warn};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::config;
+use crate::report::DeadLetter;
+use crate::transform::{id_of, Type};
+use crate::{EtlError, Record};
+
+/// Constraints on one field
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldSchema {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: Option<Type>,
+    /// Absent or null values are violations; otherwise they pass
+    #[serde(default)]
+    pub required: bool,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    /// A regex that string values must match
+    pub pattern: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SchemaFile {
+    fields: Vec<FieldSchema>,
+}
+
+/// How a value broke its field's constraints
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum Violation {
+    #[error("is required")]
+    Missing,
+    #[error("must be {expected}, not {found}")]
+    WrongType { expected: Type, found: &'static str },
+    #[error("{value} is below the minimum {min}")]
+    BelowMin { value: f64, min: f64 },
+    #[error("{value} is above the maximum {max}")]
+    AboveMax { value: f64, max: f64 },
+    #[error("{value:?} does not match {pattern}")]
+    Pattern { value: String, pattern: String },
+}
+
+/// How often each field broke its constraints, by kind
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FieldViolations {
+    pub field: String,
+    pub missing: usize,
+    pub wrong_type: usize,
+    pub out_of_range: usize,
+    pub pattern: usize,
+}
+
+impl FieldViolations {
+    fn count(&mut self, violation: &Violation) {
+        match violation {
+            Violation::Missing => self.missing += 1,
+            Violation::WrongType { .. } => self.wrong_type += 1,
+            Violation::BelowMin { .. } | Violation::AboveMax { .. } => self.out_of_range += 1,
+            Violation::Pattern { .. } => self.pattern += 1,
+        }
+    }
+}
+
+/// One counter per field of a schema, in schema order
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SchemaStats {
+    pub fields: Vec<FieldViolations>,
+}
+
+impl SchemaStats {
+    pub fn log(&self) {
+        for counter in &self.fields {
+            info!(
+                "Field '{}' - Missing: {}, Wrong type: {}, Out of range: {}, Pattern: {}",
+                counter.field,
+                counter.missing,
+                counter.wrong_type,
+                counter.out_of_range,
+                counter.pattern
+            );
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Schema {
+    fields: Vec<(FieldSchema, Option<Regex>)>,
+}
+
+impl Schema {
+    /// Compile the patterns of `fields`
+    pub fn new(fields: Vec<FieldSchema>) -> Result<Self, EtlError> {
+        let fields = fields
+            .into_iter()
+            .map(|field| {
+                let pattern = field
+                    .pattern
+                    .as_deref()
+                    .map(Regex::new)
+                    .transpose()
+                    .map_err(|e| {
+                        EtlError::Config(format!("invalid pattern for {}: {e}", field.name))
+                    })?;
+                Ok((field, pattern))
+            })
+            .collect::<Result<_, EtlError>>()?;
+        Ok(Schema { fields })
+    }
+
+    /// Load the schema in a `.json`, `.yaml` or `.yml` file
+    pub fn from_file(path: &str) -> Result<Self, EtlError> {
+        let file: SchemaFile = config::load(path, "schema")?;
+        Schema::new(file.fields)
+    }
+
+    /// Zeroed counters for this schema's fields
+    pub fn stats(&self) -> SchemaStats {
+        SchemaStats {
+            fields: self
+                .fields
+                .iter()
+                .map(|(field, _)| FieldViolations {
+                    field: field.name.clone(),
+                    ..FieldViolations::default()
+                })
+                .collect(),
+        }
+    }
+
+    /// Every violation in `record`, counting each into `stats`
+    pub fn check(&self, record: &Record, stats: &mut SchemaStats) -> Vec<(String, Violation)> {
+        let mut violations = Vec::new();
+        for ((field, pattern), counter) in self.fields.iter().zip(&mut stats.fields) {
+            if let Some(violation) = check_field(field, pattern.as_ref(), record.get(&field.name)) {
+                counter.count(&violation);
+                violations.push((field.name.clone(), violation));
+            }
+        }
+        violations
+    }
+
+    /// Split `records` into the valid ones and dead letters for the rest,
+    /// each in order
+    pub fn run(&self, records: Vec<Record>) -> (Vec<Record>, Vec<DeadLetter>, SchemaStats) {
+        let mut stats = self.stats();
+        let mut valid = Vec::new();
+        let mut invalid = Vec::new();
+        for record in records {
+            let violations = self.check(&record, &mut stats);
+            let Some((field, violation)) = violations.into_iter().next() else {
+                valid.push(record);
+                continue;
+            };
+            let reason = EtlError::Schema {
+                id: id_of(&record),
+                field,
+                violation,
+            };
+            warn!("Rejecting record: {reason}");
+            invalid.push(DeadLetter::validate(reason, record));
+        }
+        (valid, invalid, stats)
+    }
+}
+
+/// The first constraint `value` breaks, checked in the order missing, type,
+/// range, pattern
+fn check_field(
+    field: &FieldSchema,
+    pattern: Option<&Regex>,
+    value: Option<&Value>,
+) -> Option<Violation> {
+    let value = match value {
+        None | Some(Value::Null) if field.required => return Some(Violation::Missing),
+        None | Some(Value::Null) => return None,
+        Some(value) => value,
+    };
+    if let Some(expected) = field.kind {
+        if !has_type(value, expected) {
+            return Some(Violation::WrongType {
+                expected,
+                found: type_name(value),
+            });
+        }
+    }
+    if let Some(number) = value.as_f64() {
+        if let Some(min) = field.min.filter(|min| number < *min) {
+            return Some(Violation::BelowMin { value: number, min });
+        }
+        if let Some(max) = field.max.filter(|max| number > *max) {
+            return Some(Violation::AboveMax { value: number, max });
+        }
+    }
+    if let (Some(pattern), Value::String(text)) = (pattern, value) {
+        if !pattern.is_match(text) {
+            return Some(Violation::Pattern {
+                value: text.clone(),
+                pattern: pattern.as_str().to_string(),
+            });
+        }
+    }
+    None
+}
+
+/// Integers are floats too, but not the other way round
+fn has_type(value: &Value, expected: Type) -> bool {
+    match expected {
+        Type::Integer => value.is_i64() || value.is_u64(),
+        Type::Float => value.is_number(),
+        Type::String => value.is_string(),
+        Type::Boolean => value.is_boolean(),
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_f64() => "float",
+        Value::Number(_) => "integer",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn record(value: Value) -> Record {
+        value.as_object().unwrap().clone()
+    }
+
+    fn schema() -> Schema {
+        let yaml = r#"
+fields:
+  - { name: id, type: integer, required: true, min: 1 }
+  - { name: value, type: float, max: 100 }
+  - { name: code, type: string, pattern: "^[A-Z]{3}$" }
+"#;
+        let file: SchemaFile = config::from_yaml(yaml).unwrap();
+        Schema::new(file.fields).unwrap()
+    }
+
+    #[test]
+    fn test_violations() {
+        let schema = schema();
+        let mut stats = schema.stats();
+        let check = |value: Value, stats: &mut SchemaStats| schema.check(&record(value), stats);
+
+        assert!(check(json!({"id": 1, "value": 2.5, "code": "ABC"}), &mut stats).is_empty());
+        assert!(check(json!({"id": 1}), &mut stats).is_empty());
+        assert_eq!(
+            check(json!({"value": 1}), &mut stats),
+            vec![("id".to_string(), Violation::Missing)]
+        );
+        assert_eq!(
+            check(json!({"id": "7", "value": 101, "code": "abc"}), &mut stats),
+            vec![
+                (
+                    "id".to_string(),
+                    Violation::WrongType {
+                        expected: Type::Integer,
+                        found: "string"
+                    }
+                ),
+                (
+                    "value".to_string(),
+                    Violation::AboveMax {
+                        value: 101.0,
+                        max: 100.0
+                    }
+                ),
+                (
+                    "code".to_string(),
+                    Violation::Pattern {
+                        value: "abc".into(),
+                        pattern: "^[A-Z]{3}$".into()
+                    }
+                ),
+            ]
+        );
+        assert_eq!(check(json!({"id": 0}), &mut stats).len(), 1);
+
+        assert_eq!(stats.fields[0].missing, 1);
+        assert_eq!(stats.fields[0].wrong_type, 1);
+        assert_eq!(stats.fields[0].out_of_range, 1);
+        assert_eq!(stats.fields[1].out_of_range, 1);
+        assert_eq!(stats.fields[2].pattern, 1);
+    }
+
+    #[test]
+    fn test_run_rejects_invalid_records() {
+        let (valid, invalid, _) = schema().run(vec![
+            record(json!({"id": 1, "value": 5})),
+            record(json!({"id": -1, "value": 5})),
+        ]);
+        assert_eq!(valid.len(), 1);
+        assert!(matches!(
+            invalid[0].reason,
+            EtlError::Schema { ref field, violation: Violation::BelowMin { .. }, .. } if field == "id"
+        ));
+    }
+
+    #[test]
+    fn test_invalid_pattern_is_a_config_error() {
+        let field = FieldSchema {
+            name: "code".into(),
+            kind: None,
+            required: false,
+            min: None,
+            max: None,
+            pattern: Some("(".into()),
+        };
+        assert!(matches!(Schema::new(vec![field]), Err(EtlError::Config(_))));
+    }
+}
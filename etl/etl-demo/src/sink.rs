@@ -0,0 +1,422 @@
+//! Where clean records go. Every output implements [`Sink`]; the CLI picks
+//! one from the `--output` path or URL.
+//!
+//! File sinks write a temporary file and rename it into place, so a reader
+//! sees either the previous output or the complete new one. [`Retrying`]
+//! wraps any sink to retry transient failures with exponential backoff.
+
+use log::warn;
+use serde_json::Value;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, ErrorKind, Write};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use crate::{EtlError, Format, Record};
+
+pub trait Sink {
+    /// Short description for log lines, like the file path
+    fn describe(&self) -> String;
+
+    /// Write every record, returning how many were written
+    fn write(&mut self, data: &[Record]) -> Result<usize, EtlError>;
+
+    /// The local file this writes, if it writes one
+    fn file(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// An in-memory batch, used in tests
+impl Sink for Vec<Record> {
+    fn describe(&self) -> String {
+        "memory".to_string()
+    }
+
+    fn write(&mut self, data: &[Record]) -> Result<usize, EtlError> {
+        self.extend_from_slice(data);
+        Ok(data.len())
+    }
+}
+
+impl<S: Sink + ?Sized> Sink for Box<S> {
+    fn describe(&self) -> String {
+        (**self).describe()
+    }
+
+    fn write(&mut self, data: &[Record]) -> Result<usize, EtlError> {
+        (**self).write(data)
+    }
+
+    fn file(&self) -> Option<&str> {
+        (**self).file()
+    }
+}
+
+impl<S: Sink + ?Sized> Sink for &mut S {
+    fn describe(&self) -> String {
+        (**self).describe()
+    }
+
+    fn write(&mut self, data: &[Record]) -> Result<usize, EtlError> {
+        (**self).write(data)
+    }
+
+    fn file(&self) -> Option<&str> {
+        (**self).file()
+    }
+}
+
+/// How many times, and how patiently, to retry a write that failed
+/// transiently
+#[derive(Debug, Clone, Copy)]
+pub struct Retry {
+    /// Retries after the first attempt
+    pub retries: u32,
+    /// Wait before the first retry, doubling for each one after
+    pub delay: Duration,
+}
+
+/// The longest wait between two attempts
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+impl Retry {
+    fn backoff(&self, retry: u32) -> Duration {
+        self.delay
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(MAX_DELAY)
+    }
+}
+
+/// A sink that retries its inner sink's transient failures. Every sink
+/// replaces its output as a whole (files by rename, Postgres in one
+/// transaction of upserts), so a retried write never duplicates records.
+pub struct Retrying<S> {
+    inner: S,
+    retry: Retry,
+}
+
+impl<S: Sink> Retrying<S> {
+    pub fn new(inner: S, retry: Retry) -> Self {
+        Retrying { inner, retry }
+    }
+}
+
+impl<S: Sink> Sink for Retrying<S> {
+    fn describe(&self) -> String {
+        self.inner.describe()
+    }
+
+    fn write(&mut self, data: &[Record]) -> Result<usize, EtlError> {
+        let mut retry = 0;
+        loop {
+            match self.inner.write(data) {
+                Err(e) if e.is_transient() && retry < self.retry.retries => {
+                    let delay = self.retry.backoff(retry);
+                    retry += 1;
+                    warn!("{e}; retry {retry} of {} in {delay:?}", self.retry.retries);
+                    thread::sleep(delay);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn file(&self) -> Option<&str> {
+        self.inner.file()
+    }
+}
+
+/// I/O errors that may clear up on their own count as transient
+fn write_error(path: &str) -> impl Fn(io::Error) -> EtlError + '_ {
+    move |e| {
+        let target = path.to_string();
+        let reason = e.to_string();
+        match e.kind() {
+            ErrorKind::Interrupted | ErrorKind::TimedOut | ErrorKind::WouldBlock => {
+                EtlError::Unavailable { target, reason }
+            }
+            _ => EtlError::Write { target, reason },
+        }
+    }
+}
+
+/// Write `path` by filling a temporary file beside it, syncing it and
+/// renaming it over `path`; the temporary file is removed on failure
+fn replace(
+    path: &str,
+    fill: impl FnOnce(&mut BufWriter<File>) -> Result<(), EtlError>,
+) -> Result<(), EtlError> {
+    let temporary = format!("{path}.tmp");
+    let result = File::create(&temporary)
+        .map_err(write_error(path))
+        .and_then(|file| {
+            let mut wrt = BufWriter::new(file);
+            fill(&mut wrt)?;
+            let file = wrt
+                .into_inner()
+                .map_err(|e| write_error(path)(e.into_error()))?;
+            file.sync_all().map_err(write_error(path))
+        })
+        .and_then(|()| fs::rename(&temporary, path).map_err(write_error(path)));
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    result
+}
+
+/// Every field name in `data`, in the order they first appear
+pub fn columns(data: &[Record]) -> Vec<&str> {
+    let mut columns: Vec<&str> = Vec::new();
+    for name in data.iter().flat_map(|record| record.keys()) {
+        if !columns.contains(&name.as_str()) {
+            columns.push(name);
+        }
+    }
+    columns
+}
+
+/// A CSV cell: strings unquoted, missing fields and nulls empty
+fn cell(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(text)) => text.clone(),
+        Some(value) => value.to_string(),
+    }
+}
+
+/// A CSV file with a header of every field name in the records
+pub struct CsvSink {
+    path: String,
+}
+
+impl CsvSink {
+    pub fn new(path: &str) -> Self {
+        CsvSink {
+            path: path.to_string(),
+        }
+    }
+}
+
+impl Sink for CsvSink {
+    fn describe(&self) -> String {
+        self.path.clone()
+    }
+
+    fn file(&self) -> Option<&str> {
+        Some(&self.path)
+    }
+
+    fn write(&mut self, data: &[Record]) -> Result<usize, EtlError> {
+        let csv_error = |e: csv::Error| EtlError::Write {
+            target: self.path.clone(),
+            reason: e.to_string(),
+        };
+        let columns = columns(data);
+        replace(&self.path, |file| {
+            let mut wrt = csv::Writer::from_writer(file);
+            if !columns.is_empty() {
+                wrt.write_record(&columns).map_err(csv_error)?;
+            }
+            for el in data {
+                let row = columns.iter().map(|name| cell(el.get(*name)));
+                wrt.write_record(row).map_err(csv_error)?;
+            }
+            wrt.flush().map_err(write_error(&self.path))
+        })?;
+        Ok(data.len())
+    }
+}
+
+/// Newline-delimited JSON, one object per record
+pub struct NdjsonSink {
+    path: String,
+}
+
+impl NdjsonSink {
+    pub fn new(path: &str) -> Self {
+        NdjsonSink {
+            path: path.to_string(),
+        }
+    }
+}
+
+impl Sink for NdjsonSink {
+    fn describe(&self) -> String {
+        self.path.clone()
+    }
+
+    fn file(&self) -> Option<&str> {
+        Some(&self.path)
+    }
+
+    fn write(&mut self, data: &[Record]) -> Result<usize, EtlError> {
+        replace(&self.path, |wrt| {
+            for el in data {
+                serde_json::to_writer(&mut *wrt, el).map_err(|e| EtlError::Write {
+                    target: self.path.clone(),
+                    reason: e.to_string(),
+                })?;
+                wrt.write_all(b"\n").map_err(write_error(&self.path))?;
+            }
+            Ok(())
+        })?;
+        Ok(data.len())
+    }
+}
+
+/// Output when `--output` is not given: output.csv in the /data volume when
+/// running in Docker, or in the working directory
+pub fn default_output() -> String {
+    let output_dir = if Path::new("/data").exists() {
+        "/data"
+    } else {
+        "."
+    };
+    format!("{}/output.csv", output_dir)
+}
+
+/// The sink for `--output`: a database for a `postgres://` URL, otherwise a
+/// file in `format` or the format its extension names
+pub fn open(output: &str, format: Option<Format>, table: &str) -> Result<Box<dyn Sink>, EtlError> {
+    match format.map_or_else(|| Format::detect(output), Ok)? {
+        Format::Csv => Ok(Box::new(CsvSink::new(output))),
+        Format::Ndjson => Ok(Box::new(NdjsonSink::new(output))),
+        #[cfg(feature = "postgres")]
+        Format::Postgres => Ok(Box::new(crate::postgres::PostgresSink::new(output, table)?)),
+        #[cfg(not(feature = "postgres"))]
+        Format::Postgres => {
+            let _ = table;
+            Err(EtlError::Config(
+                "built without the `postgres` feature".into(),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_csv_and_ndjson_sinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let data: Vec<Record> = [json!({"id": 1, "value": 10}), json!({"id": 2, "value": 0})]
+            .into_iter()
+            .map(|value| value.as_object().unwrap().clone())
+            .collect();
+
+        let csv_path = dir.path().join("out.csv");
+        let written = CsvSink::new(csv_path.to_str().unwrap())
+            .write(&data)
+            .unwrap();
+        assert_eq!(written, 2);
+        assert_eq!(
+            std::fs::read_to_string(&csv_path).unwrap(),
+            "id,value\n1,10\n2,0\n"
+        );
+
+        let ndjson_path = dir.path().join("out.ndjson");
+        NdjsonSink::new(ndjson_path.to_str().unwrap())
+            .write(&data)
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&ndjson_path).unwrap(),
+            "{\"id\":1,\"value\":10}\n{\"id\":2,\"value\":0}\n"
+        );
+    }
+
+    /// Fails transiently `failures` times, then writes to `written`
+    struct Flaky {
+        failures: u32,
+        written: Vec<Record>,
+    }
+
+    impl Sink for Flaky {
+        fn describe(&self) -> String {
+            "flaky".to_string()
+        }
+
+        fn write(&mut self, data: &[Record]) -> Result<usize, EtlError> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(EtlError::Unavailable {
+                    target: "flaky".into(),
+                    reason: "connection reset".into(),
+                });
+            }
+            self.written.write(data)
+        }
+    }
+
+    #[test]
+    fn test_retrying_sink() {
+        let retry = Retry {
+            retries: 2,
+            delay: Duration::from_millis(1),
+        };
+        let data = vec![Record::new()];
+        let mut sink = Retrying::new(
+            Flaky {
+                failures: 2,
+                written: Vec::new(),
+            },
+            retry,
+        );
+        assert_eq!(sink.write(&data).unwrap(), 1);
+        assert_eq!(sink.inner.written.len(), 1);
+
+        let mut sink = Retrying::new(
+            Flaky {
+                failures: 3,
+                written: Vec::new(),
+            },
+            retry,
+        );
+        assert!(matches!(
+            sink.write(&data),
+            Err(EtlError::Unavailable { .. })
+        ));
+        assert_eq!(retry.backoff(1), Duration::from_millis(2));
+        assert_eq!(retry.backoff(40), MAX_DELAY);
+    }
+
+    #[test]
+    fn test_file_sinks_replace_atomically() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.ndjson");
+        std::fs::write(&path, "old\n").unwrap();
+        let data = vec![Record::new()];
+        NdjsonSink::new(path.to_str().unwrap())
+            .write(&data)
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{}\n");
+        assert!(!dir.path().join("out.ndjson.tmp").exists());
+
+        let missing = dir.path().join("missing/out.csv");
+        let result = CsvSink::new(missing.to_str().unwrap()).write(&data);
+        assert!(matches!(result, Err(EtlError::Write { .. })));
+    }
+
+    #[test]
+    fn test_csv_sink_writes_every_field() {
+        let dir = tempfile::tempdir().unwrap();
+        let data: Vec<Record> = [
+            json!({"id": 1, "name": "a,b"}),
+            json!({"id": 2, "score": 0.5}),
+        ]
+        .into_iter()
+        .map(|value| value.as_object().unwrap().clone())
+        .collect();
+        let csv_path = dir.path().join("out.csv");
+        CsvSink::new(csv_path.to_str().unwrap())
+            .write(&data)
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&csv_path).unwrap(),
+            "id,name,score\n1,\"a,b\",\n2,,0.5\n"
+        );
+    }
+}
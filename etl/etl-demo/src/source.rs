@@ -0,0 +1,411 @@
+//! Where raw records come from. Every input implements [`Source`]; the CLI
+//! picks one from the `--input` path or URL.
+
+use log::{info, warn};
+use rayon::prelude::*;
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use crate::report::FileOutcome;
+use crate::{EtlError, Format, Record};
+
+/// Records as they are read, each one possibly malformed
+pub type Records<'a> = Box<dyn Iterator<Item = Result<Record, EtlError>> + 'a>;
+
+pub trait Source {
+    /// Short description for log lines, like the file path
+    fn describe(&self) -> String;
+
+    /// Open the input and iterate its records. Failing to open is an error
+    /// for the whole run; a record that fails to parse is an error for that
+    /// record only.
+    fn records(&mut self) -> Result<Records<'_>, EtlError>;
+
+    /// How each file went, for sources that read several; call after
+    /// iterating the records
+    fn files(&self) -> Vec<FileOutcome> {
+        Vec::new()
+    }
+}
+
+/// An in-memory batch, used for the built-in sample and in tests
+impl Source for Vec<Record> {
+    fn describe(&self) -> String {
+        format!("{} in-memory records", self.len())
+    }
+
+    fn records(&mut self) -> Result<Records<'_>, EtlError> {
+        Ok(Box::new(self.drain(..).map(Ok)))
+    }
+}
+
+impl<S: Source + ?Sized> Source for Box<S> {
+    fn describe(&self) -> String {
+        (**self).describe()
+    }
+
+    fn records(&mut self) -> Result<Records<'_>, EtlError> {
+        (**self).records()
+    }
+
+    fn files(&self) -> Vec<FileOutcome> {
+        (**self).files()
+    }
+}
+
+/// The records the demo ran on before it had inputs
+pub fn sample() -> Vec<Record> {
+    [(1, 1000), (2, -5), (3, 50), (4, 75)]
+        .into_iter()
+        .map(|(id, value)| as_record(json!({ "id": id, "value": value })).unwrap())
+        .collect()
+}
+
+/// The fields of a JSON object
+fn as_record(value: Value) -> Option<Record> {
+    match value {
+        Value::Object(record) => Some(record),
+        _ => None,
+    }
+}
+
+/// A CSV cell as the JSON value it looks like: a number if it parses as
+/// one, null if empty, a string otherwise. Coerce rules can override this.
+fn cell(text: &str) -> Value {
+    if text.is_empty() {
+        return Value::Null;
+    }
+    if let Ok(integer) = text.parse::<i64>() {
+        return Value::from(integer);
+    }
+    match text
+        .parse::<f64>()
+        .ok()
+        .and_then(serde_json::Number::from_f64)
+    {
+        Some(number) => Value::Number(number),
+        None => Value::from(text),
+    }
+}
+
+/// A CSV file with a header row naming the fields
+pub struct CsvSource {
+    path: String,
+}
+
+impl CsvSource {
+    pub fn new(path: &str) -> Self {
+        CsvSource {
+            path: path.to_string(),
+        }
+    }
+}
+
+impl Source for CsvSource {
+    fn describe(&self) -> String {
+        self.path.clone()
+    }
+
+    fn records(&mut self) -> Result<Records<'_>, EtlError> {
+        let open_error = |e: csv::Error| EtlError::Open {
+            target: self.path.clone(),
+            reason: e.to_string(),
+        };
+        let mut reader = csv::Reader::from_path(&self.path).map_err(open_error)?;
+        let headers = reader.headers().map_err(open_error)?.clone();
+        let path = &self.path;
+        Ok(Box::new(reader.into_records().map(move |row| {
+            let row = row.map_err(|e| EtlError::Parse(format!("{path}: {e}")))?;
+            Ok(headers
+                .iter()
+                .zip(row.iter())
+                .map(|(name, text)| (name.to_string(), cell(text)))
+                .collect())
+        })))
+    }
+}
+
+/// Newline-delimited JSON: one object per line
+pub struct NdjsonSource {
+    path: String,
+}
+
+impl NdjsonSource {
+    pub fn new(path: &str) -> Self {
+        NdjsonSource {
+            path: path.to_string(),
+        }
+    }
+}
+
+impl Source for NdjsonSource {
+    fn describe(&self) -> String {
+        self.path.clone()
+    }
+
+    fn records(&mut self) -> Result<Records<'_>, EtlError> {
+        let file = File::open(&self.path).map_err(|e| EtlError::Open {
+            target: self.path.clone(),
+            reason: e.to_string(),
+        })?;
+        let path = &self.path;
+        let lines = BufReader::new(file).lines().enumerate();
+        Ok(Box::new(lines.filter_map(move |(number, line)| {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => return Some(Err(EtlError::Parse(format!("{path}:{}: {e}", number + 1)))),
+            };
+            if line.trim().is_empty() {
+                return None;
+            }
+            let parsed = serde_json::from_str(&line)
+                .map_err(|e| e.to_string())
+                .and_then(|value| as_record(value).ok_or_else(|| "not a JSON object".to_string()));
+            Some(parsed.map_err(|e| EtlError::Parse(format!("{path}:{}: {e}", number + 1))))
+        })))
+    }
+}
+
+/// Every file matching a glob pattern, read `workers` at a time. A file
+/// that cannot be opened is logged and skipped rather than failing the run,
+/// unless every file fails.
+pub struct GlobSource {
+    pattern: String,
+    paths: Vec<String>,
+    format: Option<Format>,
+    workers: usize,
+    outcomes: Vec<FileOutcome>,
+}
+
+impl GlobSource {
+    /// Expand `pattern`, in path order; matching nothing is an error
+    pub fn new(pattern: &str, format: Option<Format>, workers: usize) -> Result<Self, EtlError> {
+        let paths = glob::glob(pattern)
+            .map_err(|e| EtlError::Config(format!("invalid pattern {pattern}: {e}")))?
+            .filter_map(Result::ok)
+            .filter(|path| path.is_file())
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        if paths.is_empty() {
+            return Err(EtlError::Config(format!("no files match {pattern}")));
+        }
+        Ok(GlobSource {
+            pattern: pattern.to_string(),
+            paths,
+            format,
+            workers: workers.max(1),
+            outcomes: Vec::new(),
+        })
+    }
+}
+
+impl Source for GlobSource {
+    fn describe(&self) -> String {
+        format!("{} ({} files)", self.pattern, self.paths.len())
+    }
+
+    /// Read the files in parallel, then yield their records in path order
+    fn records(&mut self) -> Result<Records<'_>, EtlError> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.workers)
+            .build()
+            .map_err(|e| EtlError::Config(format!("cannot start {} workers: {e}", self.workers)))?;
+        let format = self.format;
+        let results: Vec<_> = pool.install(|| {
+            self.paths
+                .par_iter()
+                .map(|path| read_file(path, format))
+                .collect()
+        });
+
+        let mut all = Vec::new();
+        self.outcomes.clear();
+        for (path, result) in self.paths.iter().zip(results) {
+            let outcome = match result {
+                Ok(records) => {
+                    let malformed = records.iter().filter(|r| r.is_err()).count();
+                    info!(
+                        "Read {path}: {} records, {malformed} malformed",
+                        records.len() - malformed
+                    );
+                    let outcome = FileOutcome {
+                        path: path.clone(),
+                        records: records.len() - malformed,
+                        malformed,
+                        error: None,
+                    };
+                    all.extend(records);
+                    outcome
+                }
+                Err(e) => {
+                    warn!("Skipping {path}: {e}");
+                    FileOutcome {
+                        path: path.clone(),
+                        records: 0,
+                        malformed: 0,
+                        error: Some(e.to_string()),
+                    }
+                }
+            };
+            self.outcomes.push(outcome);
+        }
+        if self.outcomes.iter().all(|outcome| outcome.error.is_some()) {
+            return Err(EtlError::Open {
+                target: self.pattern.clone(),
+                reason: "no matching file could be read".into(),
+            });
+        }
+        Ok(Box::new(all.into_iter()))
+    }
+
+    fn files(&self) -> Vec<FileOutcome> {
+        self.outcomes.clone()
+    }
+}
+
+/// Every record of one file of a glob input
+fn read_file(
+    path: &str,
+    format: Option<Format>,
+) -> Result<Vec<Result<Record, EtlError>>, EtlError> {
+    let mut source: Box<dyn Source> = match format.map_or_else(|| Format::detect(path), Ok)? {
+        Format::Csv => Box::new(CsvSource::new(path)),
+        Format::Ndjson => Box::new(NdjsonSource::new(path)),
+        Format::Postgres => {
+            return Err(EtlError::Config(
+                "a glob pattern matches files, not a database".into(),
+            ))
+        }
+    };
+    let records = source.records()?.collect();
+    Ok(records)
+}
+
+/// Whether `--input` is a glob pattern rather than a single path
+fn is_pattern(input: &str) -> bool {
+    input.contains(['*', '?', '['])
+}
+
+/// The source for `--input`: the sample when there is none, a database for
+/// a `postgres://` URL, every matching file for a glob pattern (read
+/// `workers` at a time), otherwise a file in `format` or the format its
+/// extension names
+pub fn open(
+    input: Option<&str>,
+    format: Option<Format>,
+    table: &str,
+    workers: usize,
+) -> Result<Box<dyn Source>, EtlError> {
+    let Some(input) = input else {
+        return Ok(Box::new(sample()));
+    };
+    let database = input.starts_with("postgres://") || input.starts_with("postgresql://");
+    if is_pattern(input) && !database {
+        return Ok(Box::new(GlobSource::new(input, format, workers)?));
+    }
+    match format.map_or_else(|| Format::detect(input), Ok)? {
+        Format::Csv => Ok(Box::new(CsvSource::new(input))),
+        Format::Ndjson => Ok(Box::new(NdjsonSource::new(input))),
+        #[cfg(feature = "postgres")]
+        Format::Postgres => Ok(Box::new(crate::postgres::PostgresSource::new(
+            input, table,
+        )?)),
+        #[cfg(not(feature = "postgres"))]
+        Format::Postgres => {
+            let _ = table;
+            Err(EtlError::Config(
+                "built without the `postgres` feature".into(),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn collect(source: &mut dyn Source) -> Vec<Result<Record, EtlError>> {
+        source.records().unwrap().collect()
+    }
+
+    #[test]
+    fn test_csv_source() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "id,value,name\n1,10,a\n2,2.5,\n3,30").unwrap();
+        let records = collect(&mut CsvSource::new(file.path().to_str().unwrap()));
+        assert_eq!(records.len(), 3);
+        let first = records[0].as_ref().unwrap();
+        assert_eq!(
+            Value::Object(first.clone()),
+            json!({"id": 1, "value": 10, "name": "a"})
+        );
+        let second = records[1].as_ref().unwrap();
+        assert_eq!(
+            Value::Object(second.clone()),
+            json!({"id": 2, "value": 2.5, "name": null})
+        );
+        assert!(matches!(records[2], Err(EtlError::Parse(_))));
+    }
+
+    #[test]
+    fn test_ndjson_source_skips_blank_lines() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "{{\"id\": 1, \"value\": 10}}\n\n[2]\n{{\"id\": 3").unwrap();
+        let records = collect(&mut NdjsonSource::new(file.path().to_str().unwrap()));
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].as_ref().unwrap()["id"], 1);
+        assert!(matches!(records[1], Err(EtlError::Parse(_))));
+        assert!(matches!(records[2], Err(EtlError::Parse(_))));
+    }
+
+    #[test]
+    fn test_missing_file_fails_to_open() {
+        let mut source = CsvSource::new("/nonexistent/input.csv");
+        assert!(matches!(source.records(), Err(EtlError::Open { .. })));
+    }
+
+    #[test]
+    fn test_glob_source_skips_unreadable_files() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.csv"), "id,value\n1,10\n2,20\n").unwrap();
+        std::fs::write(dir.path().join("b.csv"), b"\xff\xfe,value\n3,30\n").unwrap();
+        std::fs::write(dir.path().join("c.ndjson"), "{\"id\": 4}\nnot json\n").unwrap();
+        std::fs::write(dir.path().join("d.txt"), "ignored").unwrap();
+
+        let pattern = format!("{}/*.*", dir.path().display());
+        let mut source = GlobSource::new(&pattern, None, 2).unwrap();
+        let records = collect(&mut source);
+        let ids: Vec<_> = records
+            .iter()
+            .filter_map(|r| r.as_ref().ok())
+            .map(|r| r["id"].clone())
+            .collect();
+        assert_eq!(ids, vec![json!(1), json!(2), json!(4)]);
+
+        let files = source.files();
+        let counts: Vec<_> = files
+            .iter()
+            .map(|f| (f.records, f.malformed, f.error.is_some()))
+            .collect();
+        assert_eq!(
+            counts,
+            vec![(2, 0, false), (0, 0, true), (1, 1, false), (0, 0, true)]
+        );
+    }
+
+    #[test]
+    fn test_glob_source_needs_a_match() {
+        let dir = tempfile::tempdir().unwrap();
+        let pattern = format!("{}/*.csv", dir.path().display());
+        assert!(matches!(
+            GlobSource::new(&pattern, None, 2),
+            Err(EtlError::Config(_))
+        ));
+
+        std::fs::write(dir.path().join("a.csv"), b"\xff,value\n").unwrap();
+        let mut source = GlobSource::new(&pattern, None, 2).unwrap();
+        assert!(matches!(source.records(), Err(EtlError::Open { .. })));
+    }
+}
@@ -0,0 +1,204 @@
+//! Incremental loading: a [`Watermark`] records the highest value of one
+//! field (an id or a timestamp) that has been loaded, and a [`StateStore`]
+//! keeps it between runs so the next run only processes newer records.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::{EtlError, Record};
+
+/// How far previous runs got
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Watermark {
+    /// The field records are ordered by
+    pub field: String,
+    /// Its highest loaded value; `None` before the first run
+    pub value: Option<Value>,
+}
+
+impl Watermark {
+    pub fn new(field: &str) -> Self {
+        Watermark {
+            field: field.to_string(),
+            value: None,
+        }
+    }
+
+    /// Whether `record` is past the watermark. Records without the field,
+    /// or with a value that does not compare (a string against a number),
+    /// are always processed.
+    pub fn is_new(&self, record: &Record) -> bool {
+        let (Some(value), Some(mark)) = (record.get(&self.field), &self.value) else {
+            return true;
+        };
+        !matches!(compare(value, mark), Some(Ordering::Less | Ordering::Equal))
+    }
+
+    /// Move the watermark up to the highest value in `records`
+    pub fn advance(&mut self, records: &[Record]) {
+        for value in records.iter().filter_map(|r| r.get(&self.field)) {
+            let higher = match &self.value {
+                None => !value.is_null(),
+                Some(mark) => compare(value, mark) == Some(Ordering::Greater),
+            };
+            if higher {
+                self.value = Some(value.clone());
+            }
+        }
+    }
+}
+
+impl fmt::Display for Watermark {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "{}={}", self.field, value),
+            None => write!(f, "{} (none)", self.field),
+        }
+    }
+}
+
+/// Numbers compare by value and strings lexically, so ISO 8601 timestamps
+/// order by time; anything else does not compare
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => match (a.as_i64(), b.as_i64()) {
+            (Some(a), Some(b)) => Some(a.cmp(&b)),
+            _ => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        },
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+pub trait StateStore {
+    /// Short description for log lines, like the file path
+    fn describe(&self) -> String;
+
+    /// The saved watermark, if a run has saved one
+    fn load(&mut self) -> Result<Option<Watermark>, EtlError>;
+
+    fn save(&mut self, watermark: &Watermark) -> Result<(), EtlError>;
+}
+
+impl<S: StateStore + ?Sized> StateStore for Box<S> {
+    fn describe(&self) -> String {
+        (**self).describe()
+    }
+
+    fn load(&mut self) -> Result<Option<Watermark>, EtlError> {
+        (**self).load()
+    }
+
+    fn save(&mut self, watermark: &Watermark) -> Result<(), EtlError> {
+        (**self).save(watermark)
+    }
+}
+
+/// A JSON file holding the watermark
+pub struct FileState {
+    path: String,
+}
+
+impl FileState {
+    pub fn new(path: &str) -> Self {
+        FileState {
+            path: path.to_string(),
+        }
+    }
+}
+
+impl StateStore for FileState {
+    fn describe(&self) -> String {
+        self.path.clone()
+    }
+
+    fn load(&mut self) -> Result<Option<Watermark>, EtlError> {
+        if !Path::new(&self.path).exists() {
+            return Ok(None);
+        }
+        let text = fs::read_to_string(&self.path).map_err(|e| EtlError::Open {
+            target: self.path.clone(),
+            reason: e.to_string(),
+        })?;
+        serde_json::from_str(&text)
+            .map(Some)
+            .map_err(|e| EtlError::Config(format!("invalid state in {}: {e}", self.path)))
+    }
+
+    /// Write a temporary file and rename it over the old one, so a crash
+    /// never leaves a half-written state behind
+    fn save(&mut self, watermark: &Watermark) -> Result<(), EtlError> {
+        let write_error = |e: std::io::Error| EtlError::Write {
+            target: self.path.clone(),
+            reason: e.to_string(),
+        };
+        let temporary = format!("{}.tmp", self.path);
+        let text = serde_json::to_string_pretty(watermark).expect("watermarks serialize");
+        fs::write(&temporary, text + "\n").map_err(write_error)?;
+        fs::rename(&temporary, &self.path).map_err(write_error)
+    }
+}
+
+/// The store for `--state`: a table for a `postgres://` URL, keeping one
+/// row per `key`, otherwise a JSON file
+pub fn open(target: &str, table: &str, key: &str) -> Result<Box<dyn StateStore>, EtlError> {
+    if target.starts_with("postgres://") || target.starts_with("postgresql://") {
+        #[cfg(feature = "postgres")]
+        return Ok(Box::new(crate::postgres::PostgresState::new(
+            target, table, key,
+        )?));
+        #[cfg(not(feature = "postgres"))]
+        {
+            let _ = (table, key);
+            return Err(EtlError::Config(
+                "built without the `postgres` feature".into(),
+            ));
+        }
+    }
+    Ok(Box::new(FileState::new(target)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn record(value: Value) -> Record {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn test_watermark_filters_and_advances() {
+        let mut watermark = Watermark::new("id");
+        assert!(watermark.is_new(&record(json!({"id": 1}))));
+        watermark.advance(&[record(json!({"id": 3})), record(json!({"id": 7}))]);
+        assert_eq!(watermark.value, Some(json!(7)));
+        assert!(!watermark.is_new(&record(json!({"id": 7}))));
+        assert!(watermark.is_new(&record(json!({"id": 7.5}))));
+        assert!(watermark.is_new(&record(json!({"value": 1}))));
+
+        let mut watermark = Watermark::new("updated_at");
+        watermark.advance(&[record(json!({"updated_at": "2024-05-01T10:00:00Z"}))]);
+        assert!(!watermark.is_new(&record(json!({"updated_at": "2024-04-30T23:59:59Z"}))));
+        assert!(watermark.is_new(&record(json!({"updated_at": "2024-05-01T10:00:01Z"}))));
+    }
+
+    #[test]
+    fn test_file_state_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let mut store = FileState::new(path.to_str().unwrap());
+        assert_eq!(store.load().unwrap(), None);
+        let watermark = Watermark {
+            field: "id".into(),
+            value: Some(json!(42)),
+        };
+        store.save(&watermark).unwrap();
+        assert_eq!(store.load().unwrap(), Some(watermark));
+        assert!(!dir.path().join("state.json.tmp").exists());
+    }
+}
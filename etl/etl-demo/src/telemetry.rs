@@ -0,0 +1,97 @@
+//! Run metrics through the `metrics` facade, rendered in the Prometheus text
+//! format to a file for node_exporter's textfile collector (or anything else
+//! that scrapes files). Only built with the `metrics` feature.
+
+use metrics::{counter, gauge};
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+
+use crate::manifest::{Manifest, Status};
+use crate::EtlError;
+
+/// Install the Prometheus recorder as the global one
+pub fn install() -> Result<PrometheusHandle, EtlError> {
+    let recorder = PrometheusBuilder::new().build_recorder();
+    let handle = recorder.handle();
+    metrics::set_global_recorder(recorder)
+        .map_err(|e| EtlError::Config(format!("cannot install the metrics recorder: {e}")))?;
+    Ok(handle)
+}
+
+/// Emit the counts, durations and outcome of a finished run
+pub fn record(manifest: &Manifest) {
+    let status = match manifest.status {
+        Status::Succeeded => "succeeded",
+        Status::Failed => "failed",
+    };
+    counter!("etl_runs_total", "status" => status).increment(1);
+    gauge!("etl_run_duration_seconds").set(manifest.duration_seconds);
+    let Some(report) = &manifest.report else {
+        return;
+    };
+    gauge!("etl_last_success_timestamp_seconds").set(jiff::Timestamp::now().as_second() as f64);
+
+    let records = [
+        ("extracted", report.extracted),
+        ("malformed", report.malformed),
+        ("skipped", report.skipped),
+        ("invalid", report.invalid),
+        ("rejected", report.rejected),
+        ("loaded", report.loaded),
+        ("dead_letters", report.dead_letters),
+    ];
+    for (outcome, count) in records {
+        counter!("etl_records_total", "outcome" => outcome).increment(count as u64);
+    }
+
+    for file in &report.files {
+        let status = if file.error.is_some() {
+            "failed"
+        } else {
+            "read"
+        };
+        counter!("etl_files_total", "status" => status).increment(1);
+    }
+
+    let durations = &report.durations;
+    let stages = [
+        ("extract", durations.extract),
+        ("validate", durations.validate),
+        ("transform", durations.transform),
+        ("load", durations.load),
+    ];
+    for (stage, seconds) in stages {
+        gauge!("etl_stage_duration_seconds", "stage" => stage).set(seconds);
+    }
+
+    for rule in &report.rules {
+        let labels = [("rule", rule.rule.clone())];
+        counter!("etl_rule_changed_total", &labels).increment(rule.changed as u64);
+        counter!("etl_rule_dropped_total", &labels).increment(rule.dropped as u64);
+        counter!("etl_rule_failed_total", &labels).increment(rule.failed as u64);
+    }
+
+    for field in &report.violations {
+        let kinds = [
+            ("missing", field.missing),
+            ("wrong_type", field.wrong_type),
+            ("out_of_range", field.out_of_range),
+            ("pattern", field.pattern),
+        ];
+        for (kind, count) in kinds {
+            let labels = [("field", field.field.clone()), ("kind", kind.to_string())];
+            counter!("etl_field_violations_total", &labels).increment(count as u64);
+        }
+    }
+}
+
+/// Write everything recorded so far, via a temporary file so a scraper never
+/// reads half of it
+pub fn write(handle: &PrometheusHandle, path: &str) -> Result<(), EtlError> {
+    let write_error = |e: std::io::Error| EtlError::Write {
+        target: path.to_string(),
+        reason: e.to_string(),
+    };
+    let temporary = format!("{path}.tmp");
+    std::fs::write(&temporary, handle.render()).map_err(write_error)?;
+    std::fs::rename(&temporary, path).map_err(write_error)
+}
@@ -0,0 +1,623 @@
+//! The cleaning logic as data: a [`TransformPipeline`] of rules loaded from
+//! a YAML or JSON file, applied to each record in order.
+//!
+//! ```yaml
+//! rules:
+//!   - coerce: { field: id, to: integer }
+//!   - drop: { field: id, equals: 0 }
+//!   - clamp: { field: value, min: 0, max: 100 }
+//!   - rename: { from: value, to: score }
+//!   - derive: { field: percent, op: divide, left: score, right: 100 }
+//! ```
+//!
+//! Without a rules file the pipeline is [`TransformPipeline::default`], the
+//! demo's original cleaning: integer ids and values, no id 0, values 0-100.
+//!
+//! Records are transformed in chunks on a rayon thread pool; the chunks are
+//! put back together in input order.
+
+use log::{debug, info, warn};
+use rayon::prelude::*;
+use rayon::ThreadPool;
+use serde::{Deserialize, Serialize};
+use serde_json::{Number, Value};
+use std::fmt;
+use std::sync::Arc;
+
+use crate::config;
+use crate::report::DeadLetter;
+use crate::{EtlError, Record};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum Rule {
+    /// Limit a numeric field to `min..=max`
+    Clamp {
+        field: String,
+        min: Option<f64>,
+        max: Option<f64>,
+    },
+    /// Drop the record when the field equals `equals`, is below `below`,
+    /// above `above`, or (with `missing: true`) is absent or null
+    Drop {
+        field: String,
+        equals: Option<Value>,
+        below: Option<f64>,
+        above: Option<f64>,
+        #[serde(default)]
+        missing: bool,
+    },
+    /// Rename a field, keeping its position
+    Rename { from: String, to: String },
+    /// Set `field` to `left op right`, each a field name or a number
+    Derive {
+        field: String,
+        op: Op,
+        left: Operand,
+        right: Operand,
+    },
+    /// Convert a field to another type, rejecting the record if it cannot be
+    Coerce { field: String, to: Type },
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Op {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Operand {
+    Number(f64),
+    Field(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Type {
+    Integer,
+    Float,
+    String,
+    Boolean,
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Type::Integer => "integer",
+            Type::Float => "float",
+            Type::String => "string",
+            Type::Boolean => "boolean",
+        })
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rule::Clamp { field, .. } => write!(f, "clamp {field}"),
+            Rule::Drop { field, .. } => write!(f, "drop {field}"),
+            Rule::Rename { from, to } => write!(f, "rename {from} -> {to}"),
+            Rule::Derive { field, .. } => write!(f, "derive {field}"),
+            Rule::Coerce { field, to } => write!(f, "coerce {field} to {to}"),
+        }
+    }
+}
+
+/// What a rule did to one record
+enum Effect {
+    Unchanged,
+    Changed,
+    Dropped(String),
+    Failed(String),
+}
+
+/// How often each rule changed, dropped or rejected a record
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RuleCounter {
+    pub rule: String,
+    pub changed: usize,
+    pub dropped: usize,
+    pub failed: usize,
+}
+
+/// One counter per rule of a pipeline, in rule order
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RuleStats {
+    pub rules: Vec<RuleCounter>,
+}
+
+impl RuleStats {
+    /// Add the counts of another run of the same pipeline
+    fn merge(&mut self, other: RuleStats) {
+        for (counter, other) in self.rules.iter_mut().zip(other.rules) {
+            counter.changed += other.changed;
+            counter.dropped += other.dropped;
+            counter.failed += other.failed;
+        }
+    }
+
+    pub fn log(&self) {
+        for counter in &self.rules {
+            info!(
+                "Rule '{}' - Changed: {}, Dropped: {}, Failed: {}",
+                counter.rule, counter.changed, counter.dropped, counter.failed
+            );
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    rules: Vec<Rule>,
+}
+
+/// Records per chunk when none is set
+pub const DEFAULT_CHUNK_SIZE: usize = 10_000;
+
+#[derive(Debug, Clone)]
+pub struct TransformPipeline {
+    rules: Vec<Rule>,
+    chunk_size: usize,
+    /// `None` runs on rayon's global pool, one thread per core
+    pool: Option<Arc<ThreadPool>>,
+}
+
+impl Default for TransformPipeline {
+    fn default() -> Self {
+        let field = |name: &str| name.to_string();
+        TransformPipeline::new(vec![
+            Rule::Coerce {
+                field: field("id"),
+                to: Type::Integer,
+            },
+            Rule::Coerce {
+                field: field("value"),
+                to: Type::Integer,
+            },
+            Rule::Drop {
+                field: field("id"),
+                equals: Some(Value::from(0)),
+                below: None,
+                above: None,
+                missing: false,
+            },
+            Rule::Clamp {
+                field: field("value"),
+                min: Some(0.0),
+                max: Some(100.0),
+            },
+        ])
+    }
+}
+
+impl TransformPipeline {
+    pub fn new(rules: Vec<Rule>) -> Self {
+        TransformPipeline {
+            rules,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            pool: None,
+        }
+    }
+
+    /// Transform `chunk_size` records at a time per thread
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Run on a pool of `threads` threads; 0 means one per core
+    pub fn threads(mut self, threads: usize) -> Result<Self, EtlError> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .map_err(|e| EtlError::Config(format!("cannot start {threads} threads: {e}")))?;
+        self.pool = Some(Arc::new(pool));
+        Ok(self)
+    }
+
+    /// Load the rules in a `.json`, `.yaml` or `.yml` file
+    pub fn from_file(path: &str) -> Result<Self, EtlError> {
+        let file: RulesFile = config::load(path, "rules")?;
+        Ok(TransformPipeline::new(file.rules))
+    }
+
+    /// Zeroed counters for this pipeline's rules
+    pub fn stats(&self) -> RuleStats {
+        RuleStats {
+            rules: self
+                .rules
+                .iter()
+                .map(|rule| RuleCounter {
+                    rule: rule.to_string(),
+                    ..RuleCounter::default()
+                })
+                .collect(),
+        }
+    }
+
+    /// Run every rule over `record`, counting into `stats`; a dead letter
+    /// if a rule dropped or rejected it
+    pub fn apply(
+        &self,
+        mut record: Record,
+        stats: &mut RuleStats,
+    ) -> Result<Record, Box<DeadLetter>> {
+        for (rule, counter) in self.rules.iter().zip(&mut stats.rules) {
+            let reason = match apply_rule(rule, &mut record) {
+                Effect::Unchanged => continue,
+                Effect::Changed => {
+                    counter.changed += 1;
+                    continue;
+                }
+                Effect::Dropped(reason) => {
+                    counter.dropped += 1;
+                    let reason = EtlError::InvalidData(id_of(&record), reason);
+                    warn!("Skipping record: {reason}");
+                    reason
+                }
+                Effect::Failed(reason) => {
+                    counter.failed += 1;
+                    let reason = EtlError::InvalidData(id_of(&record), reason);
+                    warn!("Rejecting record: {reason}");
+                    reason
+                }
+            };
+            return Err(Box::new(DeadLetter::transform(
+                rule.to_string(),
+                reason,
+                record,
+            )));
+        }
+        Ok(record)
+    }
+
+    /// Apply the pipeline to every record, chunk by chunk in parallel,
+    /// keeping the survivors and the dead letters each in input order
+    pub fn run(&self, records: Vec<Record>) -> (Vec<Record>, Vec<DeadLetter>, RuleStats) {
+        let mut records = records.into_iter().peekable();
+        let mut chunks = Vec::new();
+        while records.peek().is_some() {
+            chunks.push(records.by_ref().take(self.chunk_size).collect::<Vec<_>>());
+        }
+        let transform = || -> Vec<_> {
+            chunks
+                .into_par_iter()
+                .map(|chunk| self.run_chunk(chunk))
+                .collect()
+        };
+        let results = match &self.pool {
+            Some(pool) => pool.install(transform),
+            None => transform(),
+        };
+
+        let mut stats = self.stats();
+        let mut cleaned = Vec::new();
+        let mut rejected = Vec::new();
+        for (chunk_cleaned, chunk_rejected, chunk_stats) in results {
+            cleaned.extend(chunk_cleaned);
+            rejected.extend(chunk_rejected);
+            stats.merge(chunk_stats);
+        }
+        (cleaned, rejected, stats)
+    }
+
+    fn run_chunk(&self, records: Vec<Record>) -> (Vec<Record>, Vec<DeadLetter>, RuleStats) {
+        let mut stats = self.stats();
+        let mut cleaned = Vec::new();
+        let mut rejected = Vec::new();
+        for record in records {
+            match self.apply(record, &mut stats) {
+                Ok(record) => cleaned.push(record),
+                Err(letter) => rejected.push(*letter),
+            }
+        }
+        (cleaned, rejected, stats)
+    }
+}
+
+/// The record's id for log lines, or `?` if it has none
+pub fn id_of(record: &Record) -> String {
+    match record.get("id") {
+        Some(Value::String(id)) => id.clone(),
+        Some(id) => id.to_string(),
+        None => "?".to_string(),
+    }
+}
+
+fn apply_rule(rule: &Rule, record: &mut Record) -> Effect {
+    match rule {
+        Rule::Clamp { field, min, max } => {
+            let number = match record.get(field) {
+                None | Some(Value::Null) => return Effect::Unchanged,
+                Some(Value::Number(number)) => number,
+                Some(_) => return Effect::Failed(format!("{field} is not a number")),
+            };
+            let clamped = clamp(number, *min, *max);
+            if clamped == *number {
+                return Effect::Unchanged;
+            }
+            warn!(
+                "Value clamped for id={}: {} -> {}",
+                id_of(record),
+                number,
+                clamped
+            );
+            record.insert(field.clone(), Value::Number(clamped));
+            Effect::Changed
+        }
+        Rule::Drop {
+            field,
+            equals,
+            below,
+            above,
+            missing,
+        } => {
+            let value = record.get(field).filter(|v| !v.is_null());
+            let number = value.and_then(Value::as_f64);
+            let reason = if *missing && value.is_none() {
+                Some(format!("{field} is missing"))
+            } else if equals
+                .as_ref()
+                .is_some_and(|equals| value.is_some_and(|v| same(v, equals)))
+            {
+                Some(format!("{field} must not be {}", equals.as_ref().unwrap()))
+            } else if below.is_some_and(|below| number.is_some_and(|n| n < below)) {
+                Some(format!("{field} is below {}", below.unwrap()))
+            } else if above.is_some_and(|above| number.is_some_and(|n| n > above)) {
+                Some(format!("{field} is above {}", above.unwrap()))
+            } else {
+                None
+            };
+            reason.map_or(Effect::Unchanged, Effect::Dropped)
+        }
+        Rule::Rename { from, to } => {
+            if !record.contains_key(from) {
+                return Effect::Unchanged;
+            }
+            let renamed = std::mem::take(record)
+                .into_iter()
+                .map(|(name, value)| {
+                    if name == *from {
+                        (to.clone(), value)
+                    } else {
+                        (name, value)
+                    }
+                })
+                .collect();
+            *record = renamed;
+            debug!("Renamed {from} to {to}");
+            Effect::Changed
+        }
+        Rule::Derive {
+            field,
+            op,
+            left,
+            right,
+        } => match (operand(record, left), operand(record, right)) {
+            (Ok(left), Ok(right)) => match compute(*op, &left, &right) {
+                Some(result) => {
+                    record.insert(field.clone(), Value::Number(result));
+                    Effect::Changed
+                }
+                None => Effect::Failed(format!("cannot compute {field}: {left} {op:?} {right}")),
+            },
+            (Err(reason), _) | (_, Err(reason)) => Effect::Failed(reason),
+        },
+        Rule::Coerce { field, to } => {
+            let Some(value) = record.get(field) else {
+                return Effect::Unchanged;
+            };
+            match coerce(value, *to) {
+                Some(coerced) if coerced == *value => Effect::Unchanged,
+                Some(coerced) => {
+                    record.insert(field.clone(), coerced);
+                    Effect::Changed
+                }
+                None => Effect::Failed(format!("{field}={value} is not a valid {to}")),
+            }
+        }
+    }
+}
+
+/// Equal as JSON, except that numbers compare by value (`0` equals `0.0`)
+fn same(a: &Value, b: &Value) -> bool {
+    match (a.as_f64(), b.as_f64()) {
+        (Some(a), Some(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Integers stay integers when the bounds are whole numbers
+fn clamp(number: &Number, min: Option<f64>, max: Option<f64>) -> Number {
+    let whole = |bound: Option<f64>| bound.is_none_or(|b| b.fract() == 0.0);
+    if let (Some(n), true) = (number.as_i64(), whole(min) && whole(max)) {
+        let min = min.map_or(i64::MIN, |b| b as i64);
+        let max = max.map_or(i64::MAX, |b| b as i64);
+        return Number::from(n.clamp(min, max));
+    }
+    let n = number.as_f64().unwrap_or_default();
+    let n = min.map_or(n, |min| n.max(min));
+    let n = max.map_or(n, |max| n.min(max));
+    Number::from_f64(n).unwrap_or_else(|| number.clone())
+}
+
+fn operand(record: &Record, operand: &Operand) -> Result<Number, String> {
+    match operand {
+        Operand::Number(n) => {
+            Number::from_f64(*n).ok_or_else(|| format!("{n} is not a finite number"))
+        }
+        Operand::Field(name) => match record.get(name) {
+            Some(Value::Number(n)) => Ok(n.clone()),
+            Some(_) => Err(format!("{name} is not a number")),
+            None => Err(format!("{name} is missing")),
+        },
+    }
+}
+
+/// Integer arithmetic while both sides are integers (except division),
+/// floating point otherwise; `None` on overflow or division by zero
+fn compute(op: Op, left: &Number, right: &Number) -> Option<Number> {
+    if let (Some(a), Some(b), false) = (left.as_i64(), right.as_i64(), matches!(op, Op::Divide)) {
+        let result = match op {
+            Op::Add => a.checked_add(b),
+            Op::Subtract => a.checked_sub(b),
+            Op::Multiply => a.checked_mul(b),
+            Op::Divide => unreachable!(),
+        };
+        return result.map(Number::from);
+    }
+    let (a, b) = (left.as_f64()?, right.as_f64()?);
+    let result = match op {
+        Op::Add => a + b,
+        Op::Subtract => a - b,
+        Op::Multiply => a * b,
+        Op::Divide if b == 0.0 => return None,
+        Op::Divide => a / b,
+    };
+    Number::from_f64(result)
+}
+
+/// `value` as type `to`, or `None` if it does not convert; null stays null
+fn coerce(value: &Value, to: Type) -> Option<Value> {
+    if value.is_null() {
+        return Some(Value::Null);
+    }
+    match (to, value) {
+        (Type::Integer, Value::Number(n)) => match n.as_i64() {
+            Some(i) => Some(Value::from(i)),
+            None => n
+                .as_f64()
+                .filter(|f| f.fract() == 0.0)
+                .map(|f| Value::from(f as i64)),
+        },
+        (Type::Integer, Value::String(s)) => s.trim().parse::<i64>().ok().map(Value::from),
+        (Type::Integer, Value::Bool(b)) => Some(Value::from(i64::from(*b))),
+        (Type::Float, Value::Number(n)) => n.as_f64().map(Value::from),
+        (Type::Float, Value::String(s)) => s
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .and_then(|f| Number::from_f64(f).map(Value::Number)),
+        (Type::String, Value::String(_)) => Some(value.clone()),
+        (Type::String, Value::Number(_) | Value::Bool(_)) => Some(Value::from(value.to_string())),
+        (Type::Boolean, Value::Bool(_)) => Some(value.clone()),
+        (Type::Boolean, Value::Number(n)) => match n.as_i64() {
+            Some(0) => Some(Value::Bool(false)),
+            Some(1) => Some(Value::Bool(true)),
+            _ => None,
+        },
+        (Type::Boolean, Value::String(s)) => match s.trim().to_ascii_lowercase().as_str() {
+            "true" | "yes" | "1" => Some(Value::Bool(true)),
+            "false" | "no" | "0" => Some(Value::Bool(false)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn record(value: Value) -> Record {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn test_yaml_rules() {
+        let yaml = r#"
+rules:
+  - coerce: { field: value, to: float }
+  - rename: { from: value, to: score }
+  - derive: { field: percent, op: divide, left: score, right: 200 }
+  - drop: { field: percent, above: 0.4 }
+"#;
+        let file: RulesFile = config::from_yaml(yaml).unwrap();
+        let pipeline = TransformPipeline::new(file.rules);
+        let (cleaned, rejected, stats) = pipeline.run(vec![
+            record(json!({"id": 1, "value": "50"})),
+            record(json!({"id": 2, "value": 100})),
+        ]);
+        assert_eq!(
+            cleaned,
+            vec![record(json!({"id": 1, "score": 50.0, "percent": 0.25}))]
+        );
+        assert_eq!(stats.rules[0].changed, 2);
+        assert_eq!(stats.rules[3].dropped, 1);
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].rule.as_deref(), Some("drop percent"));
+        assert_eq!(rejected[0].record.as_ref().unwrap()["percent"], 0.5);
+    }
+
+    #[test]
+    fn test_rename_keeps_position() {
+        let pipeline = TransformPipeline::new(vec![Rule::Rename {
+            from: "a".into(),
+            to: "z".into(),
+        }]);
+        let (cleaned, _, _) = pipeline.run(vec![record(json!({"a": 1, "b": 2}))]);
+        let keys: Vec<_> = cleaned[0].keys().collect();
+        assert_eq!(keys, ["z", "b"]);
+    }
+
+    #[test]
+    fn test_failed_coercion_rejects_record() {
+        let (cleaned, rejected, stats) = TransformPipeline::default().run(vec![
+            record(json!({"id": 1, "value": "x"})),
+            record(json!({"id": "2", "value": "7"})),
+        ]);
+        assert_eq!(cleaned, vec![record(json!({"id": 2, "value": 7}))]);
+        assert_eq!(stats.rules[1].failed, 1);
+        assert!(matches!(rejected[0].reason, EtlError::InvalidData(ref id, _) if id == "1"));
+        assert_eq!(stats.rules[0].changed, 1);
+    }
+
+    #[test]
+    fn test_chunks_keep_input_order() {
+        let records: Vec<Record> = (0..100)
+            .map(|id| record(json!({"id": id, "value": id * 3})))
+            .collect();
+        let (sequential, _, sequential_stats) = TransformPipeline::default()
+            .chunk_size(usize::MAX)
+            .run(records.clone());
+        let (parallel, rejected, parallel_stats) = TransformPipeline::default()
+            .chunk_size(7)
+            .threads(4)
+            .unwrap()
+            .run(records);
+        assert_eq!(parallel, sequential);
+        assert_eq!(parallel_stats, sequential_stats);
+        assert_eq!(parallel.len(), 99);
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(parallel_stats.rules[3].changed, 66);
+    }
+
+    #[test]
+    fn test_derive_integer_arithmetic() {
+        assert_eq!(
+            compute(Op::Multiply, &Number::from(6), &Number::from(7)),
+            Some(Number::from(42))
+        );
+        assert_eq!(
+            compute(Op::Divide, &Number::from(1), &Number::from(0)),
+            None
+        );
+        assert_eq!(
+            compute(Op::Add, &Number::from(i64::MAX), &Number::from(1)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_unknown_rule_is_an_error() {
+        let result: Result<RulesFile, _> = serde_json::from_str(r#"{"rules": [{"explode": {}}]}"#);
+        assert!(result.is_err());
+    }
+}
@@ -1,24 +1,682 @@
-use std::collections::HashMap;
-use std::env;
-use std::fs;
-
-fn main() {
-    let filename = "data.txt";
-    let content = fs::read_to_string(filename).unwrap();
-    let lines: Vec<_> = content.split("\n").collect();
-    let mut word_freq: HashMap<String, usize> = HashMap::new();
-    lines.iter().for_each(|&s| {
-        println!("{}", s);
-        let words: Vec<_> = s.split(|c: char| c.is_whitespace() || c.is_ascii_punctuation()  ).filter(|&s| !s.is_empty()).collect();
-        words.iter().for_each(|key| {
-            let lc_word = key.to_lowercase().to_string();
-
-            *word_freq.entry(lc_word).or_insert(0 as usize) += 1;
-        });
-    });
-
-    let mut sorted_vec: Vec<(_, _)> = word_freq.iter().collect();
-    sorted_vec.sort_by(|a, b| b.1.cmp(a.1));
-
-    println!("{:?}", sorted_vec);
+use clap::{Parser, ValueEnum};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::hash::Hash;
+use std::io::{self, BufRead, BufReader, Write};
+use std::ops::RangeInclusive;
+use std::process::ExitCode;
+use std::time::Instant;
+use unicode_normalization::{IsNormalized, UnicodeNormalization, is_nfc_quick};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Roughly how much text each thread counts at a time in parallel mode
+const CHUNK_SIZE: usize = 1 << 20;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Count how often each word appears in text files", long_about = None)]
+struct Args {
+    /// Files to read, or glob patterns matching them (quoted, like 'logs/*.txt')
+    #[arg(default_value = "data.txt")]
+    files: Vec<String>,
+    /// Only print this many of the most frequent words; all of them when omitted
+    #[arg(short, long)]
+    top: Option<usize>,
+    /// Ignore words shorter than this many characters
+    #[arg(short, long, default_value_t = 1)]
+    min_len: usize,
+    /// File of words to ignore, separated by whitespace or one per line
+    #[arg(short, long)]
+    stopwords: Option<String>,
+    /// Count words regardless of case (the default)
+    #[arg(short, long, overrides_with = "no_ignore_case")]
+    ignore_case: bool,
+    /// Count "The" and "the" as different words
+    #[arg(long, overrides_with = "ignore_case")]
+    no_ignore_case: bool,
+    /// Count chunks of the input on several threads, each into its own map,
+    /// and merge the maps at the end
+    #[arg(short, long)]
+    parallel: bool,
+    /// Threads for --parallel; one per CPU core when 0
+    #[arg(short = 'j', long, default_value_t = 0)]
+    threads: usize,
+    /// Count everything both ways, check they agree, and print how long
+    /// each took to stderr
+    #[arg(long)]
+    bench: bool,
+    /// Normalize text to NFC first, so a letter typed with a combining accent
+    /// counts the same as its precomposed form
+    #[arg(long)]
+    nfc: bool,
+    /// Count words by their stem in this language, so "runs" and "running"
+    /// add up together
+    #[cfg(feature = "stem")]
+    #[arg(long, value_enum)]
+    stem: Option<Language>,
+    /// Count runs of this many consecutive words instead of single words:
+    /// a length like 2, or a range like 2..3 with both ends included.
+    /// Runs do not cross line breaks, and runs of only ignored words
+    /// (stopwords, or shorter than --min-len) are left out.
+    #[arg(short, long, value_parser = parse_ngrams, default_value = "1")]
+    ngrams: RangeInclusive<usize>,
+    /// List pairs of words that appear within this many words of each other
+    /// on a line, and how often, instead of the words themselves
+    #[arg(short = 'w', long, value_name = "WINDOW")]
+    cooccur: Option<usize>,
+    #[arg(short, long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum Format {
+    /// Counts and words, aligned
+    Text,
+    /// A header row, then one row per word or pair
+    Csv,
+    /// One JSON object per line (NDJSON)
+    Json,
+}
+
+/// `2` or `2..3` (same as `2..=3`) as a range of n-gram lengths
+fn parse_ngrams(s: &str) -> Result<RangeInclusive<usize>, String> {
+    let number = |s: &str| {
+        s.trim()
+            .parse::<usize>()
+            .map_err(|e| format!("{}: {}", s, e))
+    };
+    let (low, high) = match s.split_once("..") {
+        Some((low, high)) => (number(low)?, number(high.trim_start_matches('='))?),
+        None => (number(s)?, number(s)?),
+    };
+    if low == 0 || low > high {
+        return Err(format!("{} is not a range of lengths from 1 up", s));
+    }
+    Ok(low..=high)
+}
+
+/// Languages --stem knows
+#[cfg(feature = "stem")]
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum Language {
+    Danish,
+    Dutch,
+    English,
+    Finnish,
+    French,
+    German,
+    Italian,
+    Norwegian,
+    Portuguese,
+    Russian,
+    Spanish,
+    Swedish,
+}
+
+#[cfg(feature = "stem")]
+impl Language {
+    fn stemmer(self) -> rust_stemmers::Stemmer {
+        use rust_stemmers::Algorithm;
+        rust_stemmers::Stemmer::create(match self {
+            Language::Danish => Algorithm::Danish,
+            Language::Dutch => Algorithm::Dutch,
+            Language::English => Algorithm::English,
+            Language::Finnish => Algorithm::Finnish,
+            Language::French => Algorithm::French,
+            Language::German => Algorithm::German,
+            Language::Italian => Algorithm::Italian,
+            Language::Norwegian => Algorithm::Norwegian,
+            Language::Portuguese => Algorithm::Portuguese,
+            Language::Russian => Algorithm::Russian,
+            Language::Spanish => Algorithm::Spanish,
+            Language::Swedish => Algorithm::Swedish,
+        })
+    }
+}
+
+/// An IO error that says which file it happened to
+fn context(path: &str) -> impl Fn(io::Error) -> io::Error + '_ {
+    move |e| io::Error::new(e.kind(), format!("{}: {}", path, e))
+}
+
+/// Every file `patterns` name: each pattern's matches in path order, or the
+/// pattern itself if it is a plain path
+fn expand(patterns: &[String]) -> io::Result<Vec<String>> {
+    let mut files = Vec::new();
+    for pattern in patterns {
+        if !pattern.contains(['*', '?', '[']) {
+            files.push(pattern.clone());
+            continue;
+        }
+        let matches: Vec<String> = glob::glob(pattern)
+            .map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", pattern, e))
+            })?
+            .filter_map(Result::ok)
+            .filter(|path| path.is_file())
+            .map(|path| path.display().to_string())
+            .collect();
+        if matches.is_empty() {
+            eprintln!("No files match {}", pattern);
+        }
+        files.extend(matches);
+    }
+    Ok(files)
+}
+
+type Freq = HashMap<String, usize>;
+
+/// Counts of word pairs, each with the words in order
+type Pairs = HashMap<(String, String), usize>;
+
+/// Everything counted so far
+#[derive(Debug, Default, PartialEq)]
+struct Counts {
+    /// Words, or n-grams with their words joined by spaces
+    terms: Freq,
+    /// Co-occurring words, if asked for
+    pairs: Pairs,
+}
+
+impl Counts {
+    fn merge(self, other: Counts) -> Counts {
+        Counts {
+            terms: merge(self.terms, other.terms),
+            pairs: merge(self.pairs, other.pairs),
+        }
+    }
+}
+
+/// What counts as a word
+struct Rules {
+    ignore_case: bool,
+    min_len: usize,
+    stopwords: HashSet<String>,
+    nfc: bool,
+    #[cfg(feature = "stem")]
+    stemmer: Option<rust_stemmers::Stemmer>,
+    ngrams: RangeInclusive<usize>,
+    window: Option<usize>,
+}
+
+impl Rules {
+    fn fold(&self, word: &str) -> String {
+        if self.ignore_case {
+            word.to_lowercase()
+        } else {
+            word.to_string()
+        }
+    }
+
+    /// The word `word` counts as, once stemmed if asked to
+    #[cfg(feature = "stem")]
+    fn stem(&self, word: String) -> String {
+        match &self.stemmer {
+            Some(stemmer) => stemmer.stem(&word).into_owned(),
+            None => word,
+        }
+    }
+
+    #[cfg(not(feature = "stem"))]
+    fn stem(&self, word: String) -> String {
+        word
+    }
+
+    /// Count the words of `line`, split at Unicode word boundaries so
+    /// punctuation and spacing in any script are left out
+    fn count_line(&self, line: &str, counts: &mut Counts) {
+        let normalized;
+        let line = if self.nfc && is_nfc_quick(line.chars()) != IsNormalized::Yes {
+            normalized = line.nfc().collect::<String>();
+            &normalized
+        } else {
+            line
+        };
+        // Every word stays in place, so runs and windows only join words that
+        // are next to each other in the text; ignored ones are just marked
+        let words: Vec<(String, bool)> = line
+            .unicode_words()
+            .map(|word| {
+                let long = word.chars().count() >= self.min_len.max(1);
+                let word = self.fold(word);
+                if long && !self.stopwords.contains(&word) {
+                    (self.stem(word), true)
+                } else {
+                    (word, false)
+                }
+            })
+            .collect();
+
+        for n in self.ngrams.clone() {
+            for ngram in words.windows(n) {
+                if ngram.iter().any(|(_, counted)| *counted) {
+                    let words: Vec<&str> = ngram.iter().map(|(word, _)| word.as_str()).collect();
+                    *counts.terms.entry(words.join(" ")).or_insert(0) += 1;
+                }
+            }
+        }
+        if let Some(window) = self.window {
+            // Counted words with their places on the line
+            let counted: Vec<(usize, &String)> = words
+                .iter()
+                .enumerate()
+                .filter(|(_, (_, counted))| *counted)
+                .map(|(i, (word, _))| (i, word))
+                .collect();
+            for (k, &(i, word)) in counted.iter().enumerate() {
+                let near = counted[k + 1..]
+                    .iter()
+                    .take_while(|&&(j, _)| j - i <= window);
+                for &(_, other) in near {
+                    let pair = if word <= other {
+                        (word.clone(), other.clone())
+                    } else {
+                        (other.clone(), word.clone())
+                    };
+                    *counts.pairs.entry(pair).or_insert(0) += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Count `reader` a line at a time, reusing one buffer, so memory stays flat
+/// however big the input is
+fn count_sequential(
+    rules: &Rules,
+    mut reader: impl BufRead,
+    counts: &mut Counts,
+) -> io::Result<()> {
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 {
+        rules.count_line(&line, counts);
+        line.clear();
+    }
+    Ok(())
+}
+
+/// Count `reader` in chunks of whole lines spread over rayon's threads, each
+/// folding into a map of its own, then merge the maps. Only a few chunks
+/// are in memory at once.
+fn count_parallel(
+    rules: &Rules,
+    reader: impl BufRead + Send,
+    counts: &mut Counts,
+) -> io::Result<()> {
+    let counted = Chunks { reader }
+        .par_bridge()
+        .try_fold(Counts::default, |mut counts, chunk| {
+            for line in chunk?.lines() {
+                rules.count_line(line, &mut counts);
+            }
+            Ok::<_, io::Error>(counts)
+        })
+        .try_reduce(Counts::default, |a, b| Ok(a.merge(b)))?;
+    *counts = std::mem::take(counts).merge(counted);
+    Ok(())
+}
+
+/// Add the smaller map's counts into the larger one's
+fn merge<K: Eq + Hash>(a: HashMap<K, usize>, b: HashMap<K, usize>) -> HashMap<K, usize> {
+    let (mut into, from) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    for (word, count) in from {
+        *into.entry(word).or_insert(0) += count;
+    }
+    into
+}
+
+/// Whole lines of a reader, about [`CHUNK_SIZE`] bytes at a time
+struct Chunks<R> {
+    reader: R,
+}
+
+impl<R: BufRead> Iterator for Chunks<R> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut chunk = String::with_capacity(CHUNK_SIZE + 256);
+        while chunk.len() < CHUNK_SIZE {
+            match self.reader.read_line(&mut chunk) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        (!chunk.is_empty()).then_some(Ok(chunk))
+    }
+}
+
+/// Count every file one way or the other
+fn count_files(files: &[String], rules: &Rules, parallel: bool) -> io::Result<Counts> {
+    let mut counts = Counts::default();
+    for filename in files {
+        let reader = BufReader::new(File::open(filename).map_err(context(filename))?);
+        if parallel {
+            count_parallel(rules, reader, &mut counts)
+        } else {
+            count_sequential(rules, reader, &mut counts)
+        }
+        .map_err(context(filename))?;
+    }
+    Ok(counts)
+}
+
+/// Count `files` sequentially and in parallel, report the times, and return
+/// the counts if both ways agree
+fn bench(files: &[String], rules: &Rules) -> io::Result<Counts> {
+    let clock = Instant::now();
+    let sequential = count_files(files, rules, false)?;
+    let sequential_time = clock.elapsed();
+    let clock = Instant::now();
+    let parallel = count_files(files, rules, true)?;
+    let parallel_time = clock.elapsed();
+
+    eprintln!("sequential: {:.3}s", sequential_time.as_secs_f64());
+    eprintln!(
+        "parallel:   {:.3}s on {} threads ({:.1}x)",
+        parallel_time.as_secs_f64(),
+        rayon::current_num_threads(),
+        sequential_time.as_secs_f64() / parallel_time.as_secs_f64()
+    );
+    if sequential != parallel {
+        return Err(io::Error::other("sequential and parallel counts differ"));
+    }
+    Ok(parallel)
+}
+
+fn run(args: &Args) -> io::Result<()> {
+    let mut rules = Rules {
+        ignore_case: !args.no_ignore_case,
+        min_len: args.min_len,
+        stopwords: HashSet::new(),
+        nfc: args.nfc,
+        #[cfg(feature = "stem")]
+        stemmer: args.stem.map(Language::stemmer),
+        ngrams: args.ngrams.clone(),
+        window: args.cooccur,
+    };
+    if let Some(path) = &args.stopwords {
+        let mut text = fs::read_to_string(path).map_err(context(path))?;
+        if args.nfc {
+            text = text.nfc().collect();
+        }
+        rules.stopwords = text.split_whitespace().map(|w| rules.fold(w)).collect();
+    }
+
+    let files = expand(&args.files)?;
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.threads)
+        .build()
+        .map_err(io::Error::other)?;
+    let counts = pool.install(|| {
+        if args.bench {
+            bench(&files, &rules)
+        } else {
+            count_files(&files, &rules, args.parallel)
+        }
+    })?;
+
+    let out = io::stdout().lock();
+    if args.cooccur.is_some() {
+        let pairs = ranked(&counts.pairs, args.top);
+        write_pairs(out, &pairs, args.format)
+    } else {
+        let terms = ranked(&counts.terms, args.top);
+        write_terms(out, &terms, args.format)
+    }
+}
+
+/// The most frequent of `counts` first, ties in key order, cut to `top`
+fn ranked<K: Ord>(counts: &HashMap<K, usize>, top: Option<usize>) -> Vec<(&K, usize)> {
+    let mut sorted: Vec<_> = counts.iter().map(|(key, &count)| (key, count)).collect();
+    sorted.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+    if let Some(top) = top {
+        sorted.truncate(top);
+    }
+    sorted
+}
+
+/// A row of CSV or JSON output for a word or n-gram
+#[derive(Serialize)]
+struct TermRecord<'a> {
+    term: &'a str,
+    count: usize,
+}
+
+/// A row of CSV or JSON output for a pair of words
+#[derive(Serialize)]
+struct PairRecord<'a> {
+    word: &'a str,
+    other: &'a str,
+    count: usize,
+}
+
+fn write_terms(mut out: impl Write, terms: &[(&String, usize)], format: Format) -> io::Result<()> {
+    let records = terms
+        .iter()
+        .map(|&(term, count)| TermRecord { term, count });
+    match format {
+        Format::Text => {
+            for (term, count) in terms {
+                writeln!(out, "{:>8}  {}", count, term)?;
+            }
+            Ok(())
+        }
+        Format::Csv => write_csv(out, records),
+        Format::Json => write_json(out, records),
+    }
+}
+
+fn write_pairs(
+    mut out: impl Write,
+    pairs: &[(&(String, String), usize)],
+    format: Format,
+) -> io::Result<()> {
+    let records = pairs
+        .iter()
+        .map(|&((word, other), count)| PairRecord { word, other, count });
+    match format {
+        Format::Text => {
+            for ((word, other), count) in pairs {
+                writeln!(out, "{:>8}  {}  {}", count, word, other)?;
+            }
+            Ok(())
+        }
+        Format::Csv => write_csv(out, records),
+        Format::Json => write_json(out, records),
+    }
+}
+
+fn write_csv(out: impl Write, records: impl Iterator<Item = impl Serialize>) -> io::Result<()> {
+    let mut writer = csv::Writer::from_writer(out);
+    for record in records {
+        writer.serialize(record)?;
+    }
+    writer.flush()
+}
+
+fn write_json(
+    mut out: impl Write,
+    records: impl Iterator<Item = impl Serialize>,
+) -> io::Result<()> {
+    for record in records {
+        serde_json::to_writer(&mut out, &record)?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules() -> Rules {
+        Rules {
+            ignore_case: true,
+            min_len: 0,
+            stopwords: HashSet::new(),
+            nfc: false,
+            #[cfg(feature = "stem")]
+            stemmer: None,
+            ngrams: 1..=1,
+            window: None,
+        }
+    }
+
+    fn with_stopwords(words: &[&str]) -> Rules {
+        Rules {
+            stopwords: words.iter().map(|word| word.to_string()).collect(),
+            ..rules()
+        }
+    }
+
+    fn count(rules: &Rules, text: &str) -> Counts {
+        let mut counts = Counts::default();
+        for line in text.lines() {
+            rules.count_line(line, &mut counts);
+        }
+        counts
+    }
+
+    fn terms(counts: &Counts) -> Vec<(&str, usize)> {
+        let mut terms: Vec<_> = counts.terms.iter().map(|(t, &n)| (t.as_str(), n)).collect();
+        terms.sort();
+        terms
+    }
+
+    fn pairs(counts: &Counts) -> Vec<(&str, &str, usize)> {
+        let mut pairs: Vec<_> = counts
+            .pairs
+            .iter()
+            .map(|((a, b), &n)| (a.as_str(), b.as_str(), n))
+            .collect();
+        pairs.sort();
+        pairs
+    }
+
+    #[test]
+    fn test_words() {
+        let counts = count(&rules(), "Hello, hello WORLD!\n— don't stop —");
+        assert_eq!(
+            terms(&counts),
+            [("don't", 1), ("hello", 2), ("stop", 1), ("world", 1)]
+        );
+        let rules = Rules {
+            ignore_case: false,
+            min_len: 5,
+            ..with_stopwords(&["world"])
+        };
+        let counts = count(&rules, "Hello, hello WORLD world!");
+        assert_eq!(terms(&counts), [("Hello", 1), ("WORLD", 1), ("hello", 1)]);
+    }
+
+    #[test]
+    fn test_nfc() {
+        let text = "cafe\u{301} café";
+        assert_eq!(count(&rules(), text).terms.len(), 2);
+        let rules = Rules {
+            nfc: true,
+            ..rules()
+        };
+        assert_eq!(terms(&count(&rules, text)), [("café", 2)]);
+    }
+
+    #[test]
+    fn test_ngrams() {
+        let rules = Rules {
+            ngrams: 1..=3,
+            ..with_stopwords(&["the", "on"])
+        };
+        let counts = count(&rules, "The cat sat on the mat\nthe cat");
+        assert_eq!(
+            terms(&counts),
+            [
+                ("cat", 2),
+                ("cat sat", 1),
+                ("cat sat on", 1),
+                ("mat", 1),
+                ("on the mat", 1),
+                ("sat", 1),
+                ("sat on", 1),
+                ("sat on the", 1),
+                ("the cat", 2),
+                ("the cat sat", 1),
+                ("the mat", 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ngrams_stay_within_a_line() {
+        let rules = Rules {
+            ngrams: 2..=2,
+            ..rules()
+        };
+        assert_eq!(terms(&count(&rules, "a b\nc")), [("a b", 1)]);
+    }
+
+    #[test]
+    fn test_window() {
+        let stopwords = with_stopwords(&["the", "and"]);
+        let text = "the cat and the dog";
+        let rules = Rules {
+            window: Some(2),
+            ..stopwords
+        };
+        assert!(count(&rules, text).pairs.is_empty());
+        let rules = Rules {
+            window: Some(3),
+            ..rules
+        };
+        let counts = count(&rules, text);
+        assert_eq!(pairs(&counts), [("cat", "dog", 1)]);
+        assert_eq!(terms(&counts), [("cat", 1), ("dog", 1)]);
+        // Each pair has its words in order, whichever came first
+        let counts = count(&rules, "zebra apple zebra");
+        assert_eq!(
+            pairs(&counts),
+            [("apple", "zebra", 2), ("zebra", "zebra", 1)]
+        );
+    }
+
+    #[test]
+    fn test_parallel_matches_sequential() {
+        let words = ["alpha", "beta", "the", "gamma", "delta", "and", "epsilon"];
+        let mut text = String::new();
+        let mut i = 0usize;
+        while text.len() < 3 * CHUNK_SIZE {
+            for _ in 0..(i % 9) {
+                i = i.wrapping_mul(31).wrapping_add(7) % 1_000_003;
+                text.push_str(words[i % words.len()]);
+                text.push(' ');
+            }
+            text.push('\n');
+            i += 1;
+        }
+        let rules = Rules {
+            ngrams: 1..=2,
+            window: Some(3),
+            ..with_stopwords(&["the", "and"])
+        };
+
+        let mut sequential = Counts::default();
+        count_sequential(&rules, text.as_bytes(), &mut sequential).unwrap();
+        let mut parallel = Counts::default();
+        count_parallel(&rules, text.as_bytes(), &mut parallel).unwrap();
+        assert!(!sequential.pairs.is_empty());
+        assert_eq!(parallel, sequential);
+        assert_eq!(sequential, count(&rules, &text));
+    }
 }
@@ -0,0 +1,289 @@
+//! `--export`: the graph as GraphViz DOT or GeoJSON, with the route found
+//! highlighted, to render in Graphviz or on a web map.
+
+use petgraph::graph::{EdgeReference, NodeIndex};
+use petgraph::visit::EdgeRef;
+use serde_json::{Value, json};
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::load::Network;
+use crate::route::Route;
+
+const ROUTE_COLOR: &str = "red";
+
+/// Write `network` to `path` as DOT or GeoJSON, by its extension
+pub fn export(path: &Path, network: &Network, route: Option<&Route>) -> Result<(), Box<dyn Error>> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_lowercase);
+    let as_dot = match extension.as_deref() {
+        Some("dot" | "gv") => true,
+        Some("geojson" | "json") => false,
+        _ => return Err("export to a .dot, .gv, .geojson or .json file".into()),
+    };
+    let mut out = BufWriter::new(File::create(path)?);
+    if as_dot {
+        dot(&mut out, network, route)?;
+    } else {
+        geojson(&mut out, network, route)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// The edges and nodes along `route`
+struct OnRoute {
+    edges: HashSet<(NodeIndex, NodeIndex)>,
+    nodes: HashSet<NodeIndex>,
+    directed: bool,
+}
+
+impl OnRoute {
+    fn new(network: &Network, route: Option<&Route>) -> Self {
+        let legs = route.map_or(&[][..], |route| &route.legs);
+        OnRoute {
+            edges: legs.iter().map(|leg| (leg.from, leg.to)).collect(),
+            nodes: legs.iter().flat_map(|leg| [leg.from, leg.to]).collect(),
+            directed: network.directed,
+        }
+    }
+
+    fn edge(&self, edge: EdgeReference<f64>) -> bool {
+        let (a, b) = (edge.source(), edge.target());
+        self.edges.contains(&(a, b)) || (!self.directed && self.edges.contains(&(b, a)))
+    }
+}
+
+/// Each edge once: undirected edges are stored both ways, so only the way
+/// from the lower node index is kept
+fn edges(network: &Network) -> impl Iterator<Item = EdgeReference<'_, f64>> {
+    network
+        .graph
+        .edge_references()
+        .filter(|e| network.directed || e.source() <= e.target())
+}
+
+fn quote(id: &str) -> String {
+    format!("\"{}\"", id.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// A DOT graph whose edges are labelled with their weights, which `load`
+/// reads back; placed nodes are pinned where they are, and the route is
+/// drawn thick and red
+fn dot(
+    out: &mut dyn Write,
+    network: &Network,
+    route: Option<&Route>,
+) -> Result<(), Box<dyn Error>> {
+    let graph = &network.graph;
+    let on_route = OnRoute::new(network, route);
+    let (kind, op) = if network.directed {
+        ("digraph", "->")
+    } else {
+        ("graph", "--")
+    };
+
+    writeln!(out, "{} {{", kind)?;
+    for node in graph.node_indices() {
+        let mut attributes = Vec::new();
+        if let Some([x, y]) = network.positions.get(&node) {
+            attributes.push(format!("pos=\"{},{}!\"", x, y));
+        }
+        if on_route.nodes.contains(&node) {
+            attributes.push(format!("color={}, penwidth=2", ROUTE_COLOR));
+        }
+        if attributes.is_empty() {
+            writeln!(out, "    {};", quote(&graph[node]))?;
+        } else {
+            writeln!(
+                out,
+                "    {} [{}];",
+                quote(&graph[node]),
+                attributes.join(", ")
+            )?;
+        }
+    }
+    for edge in edges(network) {
+        let style = if on_route.edge(edge) {
+            format!(", color={}, penwidth=3", ROUTE_COLOR)
+        } else {
+            String::new()
+        };
+        writeln!(
+            out,
+            "    {} {} {} [label=\"{}\"{}];",
+            quote(&graph[edge.source()]),
+            op,
+            quote(&graph[edge.target()]),
+            edge.weight(),
+            style
+        )?;
+    }
+    writeln!(out, "}}")?;
+    Ok(())
+}
+
+/// A GeoJSON FeatureCollection of the placed nodes as points and the edges
+/// between them as lines, each with an `on_route` property, and the route
+/// as one more line
+fn geojson(
+    out: &mut dyn Write,
+    network: &Network,
+    route: Option<&Route>,
+) -> Result<(), Box<dyn Error>> {
+    if network.positions.is_empty() {
+        return Err("GeoJSON needs node coordinates, and the graph has none".into());
+    }
+    let graph = &network.graph;
+    let on_route = OnRoute::new(network, route);
+    let mut features: Vec<Value> = Vec::new();
+
+    for node in graph.node_indices() {
+        let Some(position) = network.positions.get(&node) else {
+            continue;
+        };
+        features.push(json!({
+            "type": "Feature",
+            "geometry": { "type": "Point", "coordinates": position },
+            "properties": { "name": graph[node], "on_route": on_route.nodes.contains(&node) },
+        }));
+    }
+    for edge in edges(network) {
+        let (Some(a), Some(b)) = (
+            network.positions.get(&edge.source()),
+            network.positions.get(&edge.target()),
+        ) else {
+            continue;
+        };
+        features.push(json!({
+            "type": "Feature",
+            "geometry": { "type": "LineString", "coordinates": [a, b] },
+            "properties": {
+                "from": graph[edge.source()],
+                "to": graph[edge.target()],
+                "weight": edge.weight(),
+                "on_route": on_route.edge(edge),
+            },
+        }));
+    }
+    if let Some(route) = route.filter(|route| !route.legs.is_empty()) {
+        let nodes = std::iter::once(route.legs[0].from).chain(route.legs.iter().map(|leg| leg.to));
+        let coordinates: Option<Vec<[f64; 2]>> = nodes
+            .map(|node| network.positions.get(&node).copied())
+            .collect();
+        if let Some(coordinates) = coordinates {
+            features.push(json!({
+                "type": "Feature",
+                "geometry": { "type": "LineString", "coordinates": coordinates },
+                "properties": { "route": true, "cost": route.cost },
+            }));
+        }
+    }
+
+    let collection = json!({ "type": "FeatureCollection", "features": features });
+    serde_json::to_writer_pretty(&mut *out, &collection)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load::{InputFormat, load};
+    use crate::route;
+
+    const SOURCE: &str = r#"graph {
+        "Saint \"Louis\"" [pos="1,2"]
+        b [pos="3.5,-4"]
+        "Saint \"Louis\"" -- b [weight=2.5]
+        b -- c [weight=4]
+    }"#;
+
+    /// `text` loaded as DOT from a file in `dir`
+    fn load_dot(dir: &Path, text: &str) -> Network {
+        let path = dir.join("source.dot");
+        std::fs::write(&path, text).unwrap();
+        load(&path, InputFormat::Dot, false).unwrap()
+    }
+
+    /// Every edge as (from, to, weight), sorted
+    fn edge_list(network: &Network) -> Vec<(String, String, f64)> {
+        let graph = &network.graph;
+        let mut edges: Vec<_> = graph
+            .edge_references()
+            .map(|e| {
+                (
+                    graph[e.source()].clone(),
+                    graph[e.target()].clone(),
+                    *e.weight(),
+                )
+            })
+            .collect();
+        edges.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+        edges
+    }
+
+    #[test]
+    fn test_dot_reads_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let network = load_dot(dir.path(), SOURCE);
+        let (a, c) = (
+            network.find("Saint \"Louis\"").unwrap(),
+            network.find("c").unwrap(),
+        );
+        let route = route::dijkstra(&network.graph, a, c).unwrap();
+        let path = dir.path().join("out.dot");
+        export(&path, &network, Some(&route)).unwrap();
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(text.matches(" -- ").count(), 2);
+        assert_eq!(text.matches("color=red, penwidth=3").count(), 2);
+        let again = load(&path, InputFormat::Dot, false).unwrap();
+        assert!(!again.directed);
+        assert_eq!(edge_list(&again), edge_list(&network));
+        let at =
+            |network: &Network, name| network.positions.get(&network.find(name).unwrap()).copied();
+        assert_eq!(at(&again, "Saint \"Louis\""), Some([1.0, 2.0]));
+        assert_eq!(at(&again, "b"), Some([3.5, -4.0]));
+        assert_eq!(at(&again, "c"), None);
+    }
+
+    #[test]
+    fn test_directed_dot_reads_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let network = load_dot(
+            dir.path(),
+            "digraph { a -> b [weight=1]; b -> a [weight=2] }",
+        );
+        let path = dir.path().join("out.gv");
+        export(&path, &network, None).unwrap();
+        let again = load(&path, InputFormat::Dot, false).unwrap();
+        assert!(again.directed);
+        assert_eq!(edge_list(&again), edge_list(&network));
+    }
+
+    #[test]
+    fn test_geojson() {
+        let dir = tempfile::tempdir().unwrap();
+        let network = load_dot(dir.path(), SOURCE);
+        let path = dir.path().join("out.geojson");
+        export(&path, &network, None).unwrap();
+        let json: Value = serde_json::from_reader(File::open(&path).unwrap()).unwrap();
+        let features = json["features"].as_array().unwrap();
+        // Two placed nodes and the one edge between them
+        assert_eq!(features.len(), 3);
+        assert_eq!(
+            features[2]["geometry"]["coordinates"],
+            json!([[1.0, 2.0], [3.5, -4.0]])
+        );
+
+        let unplaced = load_dot(dir.path(), "graph { a -- b }");
+        assert!(export(&path, &unplaced, None).is_err());
+        assert!(export(&dir.path().join("out.txt"), &network, None).is_err());
+    }
+}
@@ -0,0 +1,471 @@
+//! Reading graphs from CSV, JSON or GraphViz DOT files into one shape: a
+//! directed graph of named nodes, with undirected edges added both ways.
+
+use clap::ValueEnum;
+use petgraph::graph::{DiGraph, NodeIndex};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::iter::Peekable;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum InputFormat {
+    /// A header row naming from, to and weight columns, then one edge a row;
+    /// optional from_x, from_y, to_x and to_y columns place the nodes
+    Csv,
+    /// An object with an "edges" list of {"from", "to", "weight"}, optionally
+    /// "directed": true, and optionally a "nodes" list of {"name", "x", "y"}
+    Json,
+    /// A GraphViz graph or digraph; edge weights come from their weight or
+    /// label attribute, and are 1 without either, and a node's pos="x,y"
+    /// attribute places it
+    Dot,
+}
+
+impl InputFormat {
+    /// The format a file's extension suggests
+    pub fn guess(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "csv" => Some(InputFormat::Csv),
+            "json" => Some(InputFormat::Json),
+            "dot" | "gv" => Some(InputFormat::Dot),
+            _ => None,
+        }
+    }
+}
+
+/// A graph with its nodes findable by name
+pub struct Network {
+    pub graph: DiGraph<String, f64>,
+    pub directed: bool,
+    /// Where the nodes that the file placed are, as x and y (or longitude
+    /// and latitude)
+    pub positions: HashMap<NodeIndex, [f64; 2]>,
+    nodes: HashMap<String, NodeIndex>,
+}
+
+impl Network {
+    fn new(directed: bool) -> Self {
+        Network {
+            graph: DiGraph::new(),
+            directed,
+            positions: HashMap::new(),
+            nodes: HashMap::new(),
+        }
+    }
+
+    pub fn find(&self, name: &str) -> Option<NodeIndex> {
+        self.nodes.get(name).copied()
+    }
+
+    /// The node called `name`, added if it is new
+    fn node(&mut self, name: &str) -> NodeIndex {
+        if let Some(&index) = self.nodes.get(name) {
+            return index;
+        }
+        let index = self.graph.add_node(name.to_string());
+        self.nodes.insert(name.to_string(), index);
+        index
+    }
+
+    /// Place the node called `name` at `x`, `y`, if both are given
+    fn place(&mut self, name: &str, x: Option<f64>, y: Option<f64>) {
+        if let (Some(x), Some(y)) = (x, y) {
+            let index = self.node(name);
+            self.positions.insert(index, [x, y]);
+        }
+    }
+
+    /// Join `from` to `to`, and back again unless the graph is directed
+    fn connect(&mut self, from: &str, to: &str, weight: f64) -> Result<(), String> {
+        if weight.is_nan() {
+            return Err(format!("{} - {}: weight is not a number", from, to));
+        }
+        let (a, b) = (self.node(from), self.node(to));
+        self.graph.add_edge(a, b, weight);
+        if !self.directed && a != b {
+            self.graph.add_edge(b, a, weight);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Edge {
+    from: String,
+    to: String,
+    weight: f64,
+    #[serde(default)]
+    from_x: Option<f64>,
+    #[serde(default)]
+    from_y: Option<f64>,
+    #[serde(default)]
+    to_x: Option<f64>,
+    #[serde(default)]
+    to_y: Option<f64>,
+}
+
+impl Edge {
+    fn add_to(&self, network: &mut Network) -> Result<(), String> {
+        network.connect(&self.from, &self.to, self.weight)?;
+        network.place(&self.from, self.from_x, self.from_y);
+        network.place(&self.to, self.to_x, self.to_y);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonNode {
+    name: String,
+    x: f64,
+    y: f64,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonGraph {
+    #[serde(default)]
+    directed: bool,
+    #[serde(default)]
+    nodes: Vec<JsonNode>,
+    edges: Vec<Edge>,
+}
+
+/// Read the graph in `path`. Edges go both ways unless `directed`, or the
+/// file itself says the graph is directed.
+pub fn load(path: &Path, format: InputFormat, directed: bool) -> Result<Network, Box<dyn Error>> {
+    match format {
+        InputFormat::Csv => {
+            let mut network = Network::new(directed);
+            let mut rdr = csv::Reader::from_reader(File::open(path)?);
+            for edge in rdr.deserialize() {
+                let edge: Edge = edge?;
+                edge.add_to(&mut network)?;
+            }
+            Ok(network)
+        }
+        InputFormat::Json => {
+            let json: JsonGraph = serde_json::from_reader(File::open(path)?)?;
+            let mut network = Network::new(directed || json.directed);
+            for node in &json.nodes {
+                network.place(&node.name, Some(node.x), Some(node.y));
+            }
+            for edge in &json.edges {
+                edge.add_to(&mut network)?;
+            }
+            Ok(network)
+        }
+        InputFormat::Dot => Ok(parse_dot(&fs::read_to_string(path)?, directed)?),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Id(String),
+    /// `->` or `--`
+    EdgeOp,
+    LBracket,
+    RBracket,
+    LBrace,
+    RBrace,
+    Equals,
+    /// `;` or `,`, which DOT lets you leave out
+    Sep,
+}
+
+/// Split DOT source into tokens, dropping comments
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '/' if chars.peek() == Some(&'/') => {
+                chars.by_ref().find(|&c| c == '\n');
+            }
+            '#' => {
+                chars.by_ref().find(|&c| c == '\n');
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                for c in chars.by_ref() {
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+            }
+            '-' if matches!(chars.peek(), Some('>' | '-')) => {
+                chars.next();
+                tokens.push(Token::EdgeOp);
+            }
+            '[' => tokens.push(Token::LBracket),
+            ']' => tokens.push(Token::RBracket),
+            '{' => tokens.push(Token::LBrace),
+            '}' => tokens.push(Token::RBrace),
+            '=' => tokens.push(Token::Equals),
+            ';' | ',' => tokens.push(Token::Sep),
+            '"' => {
+                let mut id = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') if chars.peek() == Some(&'"') => id.push(chars.next().unwrap()),
+                        Some(c) => id.push(c),
+                        None => return Err("unterminated string".to_string()),
+                    }
+                }
+                tokens.push(Token::Id(id));
+            }
+            c if c.is_alphanumeric() || matches!(c, '_' | '.' | '-') => {
+                let mut id = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_alphanumeric() || matches!(c, '_' | '.')) {
+                        break;
+                    }
+                    id.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Id(id));
+            }
+            c => return Err(format!("unexpected {:?}", c)),
+        }
+    }
+    Ok(tokens)
+}
+
+fn expect_id(tokens: &mut impl Iterator<Item = Token>) -> Result<String, String> {
+    match tokens.next() {
+        Some(Token::Id(id)) => Ok(id),
+        other => Err(format!("expected a name, found {:?}", other)),
+    }
+}
+
+/// `[key=value, ...]`
+fn attributes(
+    tokens: &mut Peekable<impl Iterator<Item = Token>>,
+) -> Result<HashMap<String, String>, String> {
+    let mut attributes = HashMap::new();
+    if tokens.next_if_eq(&Token::LBracket).is_none() {
+        return Ok(attributes);
+    }
+    loop {
+        match tokens.next() {
+            Some(Token::RBracket) => return Ok(attributes),
+            Some(Token::Sep) => {}
+            Some(Token::Id(key)) => {
+                if tokens.next() != Some(Token::Equals) {
+                    return Err(format!("expected = after {}", key));
+                }
+                attributes.insert(key, expect_id(tokens)?);
+            }
+            other => return Err(format!("unexpected {:?} in attributes", other)),
+        }
+    }
+}
+
+/// Read the common subset of DOT: node and edge statements, including
+/// chains like `a -> b -> c`, with attributes; graph, node and edge
+/// defaults are skipped, and subgraphs are not supported
+fn parse_dot(text: &str, directed: bool) -> Result<Network, String> {
+    let mut tokens = tokenize(text)?.into_iter().peekable();
+    let mut kind = expect_id(&mut tokens)?.to_lowercase();
+    if kind == "strict" {
+        kind = expect_id(&mut tokens)?.to_lowercase();
+    }
+    let mut network = match kind.as_str() {
+        "digraph" => Network::new(true),
+        "graph" => Network::new(directed),
+        _ => return Err(format!("expected graph or digraph, found {}", kind)),
+    };
+    if matches!(tokens.peek(), Some(Token::Id(_))) {
+        tokens.next();
+    }
+    if tokens.next() != Some(Token::LBrace) {
+        return Err("expected { after the graph name".to_string());
+    }
+
+    loop {
+        let first = match tokens.next() {
+            Some(Token::RBrace) => return Ok(network),
+            Some(Token::Sep) => continue,
+            Some(Token::LBrace) => return Err("subgraphs are not supported".to_string()),
+            Some(Token::Id(id)) => id,
+            other => return Err(format!("unexpected {:?}", other)),
+        };
+        if tokens.next_if_eq(&Token::Equals).is_some() {
+            expect_id(&mut tokens)?;
+            continue;
+        }
+        if matches!(first.as_str(), "graph" | "node" | "edge") {
+            attributes(&mut tokens)?;
+            continue;
+        }
+        let mut chain = vec![first];
+        while tokens.next_if_eq(&Token::EdgeOp).is_some() {
+            chain.push(expect_id(&mut tokens)?);
+        }
+        let attributes = attributes(&mut tokens)?;
+        if chain.len() == 1 {
+            network.node(&chain[0]);
+            if let Some(pos) = attributes.get("pos") {
+                let [x, y] =
+                    parse_pos(pos).ok_or_else(|| format!("{}: bad pos {}", chain[0], pos))?;
+                network.place(&chain[0], Some(x), Some(y));
+            }
+            continue;
+        }
+        let weight = match attributes.get("weight").or(attributes.get("label")) {
+            Some(weight) => weight
+                .parse()
+                .map_err(|e| format!("{} - {}: weight {}: {}", chain[0], chain[1], weight, e))?,
+            None => 1.0,
+        };
+        for pair in chain.windows(2) {
+            network.connect(&pair[0], &pair[1], weight)?;
+        }
+    }
+}
+
+/// GraphViz's `"x,y"` position, perhaps with a `!` to pin it
+fn parse_pos(pos: &str) -> Option<[f64; 2]> {
+    let (x, y) = pos.trim_end_matches('!').split_once(',')?;
+    Some([x.trim().parse().ok()?, y.trim().parse().ok()?])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// Load `text` as a file in `format`
+    fn load_text(text: &str, format: InputFormat, directed: bool) -> Network {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+        load(file.path(), format, directed).unwrap()
+    }
+
+    /// Weight of the edge from `a` to `b`, if there is one
+    fn weight(network: &Network, a: &str, b: &str) -> Option<f64> {
+        let (a, b) = (network.find(a)?, network.find(b)?);
+        let edge = network.graph.find_edge(a, b)?;
+        Some(network.graph[edge])
+    }
+
+    #[test]
+    fn test_dot_chains_and_attributes() {
+        let network = parse_dot(
+            r#"digraph roads {
+                rankdir = LR
+                node [shape=box]
+                a -> b -> c [weight=2.5];
+                "New York" -> c [label="7"]
+                c -> a
+            }"#,
+            false,
+        )
+        .unwrap();
+        assert!(network.directed);
+        assert_eq!(network.graph.node_count(), 4);
+        assert_eq!(weight(&network, "a", "b"), Some(2.5));
+        assert_eq!(weight(&network, "b", "c"), Some(2.5));
+        assert_eq!(weight(&network, "New York", "c"), Some(7.0));
+        assert_eq!(weight(&network, "c", "a"), Some(1.0));
+        assert_eq!(weight(&network, "b", "a"), None);
+    }
+
+    #[test]
+    fn test_dot_undirected_edges_go_both_ways() {
+        let network = parse_dot("graph { a -- b [weight=3] }", false).unwrap();
+        assert!(!network.directed);
+        assert_eq!(weight(&network, "a", "b"), Some(3.0));
+        assert_eq!(weight(&network, "b", "a"), Some(3.0));
+        assert!(parse_dot("graph { a -- b }", true).unwrap().directed);
+    }
+
+    #[test]
+    fn test_dot_comments() {
+        let network = parse_dot(
+            "graph {\n  // a -- x\n  # b -- x\n  a /* -- y */ -- b\n}",
+            false,
+        )
+        .unwrap();
+        assert_eq!(network.graph.node_count(), 2);
+        assert_eq!(weight(&network, "a", "b"), Some(1.0));
+    }
+
+    #[test]
+    fn test_dot_positions() {
+        let network = parse_dot(
+            r#"graph { a [pos="1.5,-2!"]; b [pos="3, 4"]; c; a -- b -- c }"#,
+            false,
+        )
+        .unwrap();
+        let at = |name| network.positions.get(&network.find(name).unwrap()).copied();
+        assert_eq!(at("a"), Some([1.5, -2.0]));
+        assert_eq!(at("b"), Some([3.0, 4.0]));
+        assert_eq!(at("c"), None);
+        assert!(parse_dot(r#"graph { a [pos="1"] }"#, false).is_err());
+    }
+
+    #[test]
+    fn test_dot_errors() {
+        assert!(parse_dot("tree { }", false).is_err());
+        assert!(parse_dot("graph { a -- b [weight=heavy] }", false).is_err());
+        assert!(parse_dot("graph { subgraph { a } }", false).is_err());
+        assert!(parse_dot("graph { \"a }", false).is_err());
+    }
+
+    #[test]
+    fn test_csv() {
+        let network = load_text(
+            "from,to,weight,from_x,from_y,to_x,to_y\n\
+             a,b,2,0,0,3,4\n\
+             b,c,5,,,,\n",
+            InputFormat::Csv,
+            true,
+        );
+        assert_eq!(weight(&network, "a", "b"), Some(2.0));
+        assert_eq!(weight(&network, "b", "a"), None);
+        assert_eq!(weight(&network, "b", "c"), Some(5.0));
+        assert_eq!(network.positions[&network.find("b").unwrap()], [3.0, 4.0]);
+        assert!(!network.positions.contains_key(&network.find("c").unwrap()));
+    }
+
+    #[test]
+    fn test_json() {
+        let network = load_text(
+            r#"{
+                "nodes": [{"name": "a", "x": 1, "y": 2}],
+                "edges": [{"from": "a", "to": "b", "weight": 4}]
+            }"#,
+            InputFormat::Json,
+            false,
+        );
+        assert_eq!(weight(&network, "b", "a"), Some(4.0));
+        assert_eq!(network.positions[&network.find("a").unwrap()], [1.0, 2.0]);
+
+        let directed = load_text(
+            r#"{"directed": true, "edges": [{"from": "a", "to": "b", "weight": 4}]}"#,
+            InputFormat::Json,
+            false,
+        );
+        assert_eq!(weight(&directed, "b", "a"), None);
+    }
+
+    #[test]
+    fn test_guess() {
+        assert_eq!(
+            InputFormat::guess(Path::new("x.CSV")),
+            Some(InputFormat::Csv)
+        );
+        assert_eq!(
+            InputFormat::guess(Path::new("x.gv")),
+            Some(InputFormat::Dot)
+        );
+        assert_eq!(InputFormat::guess(Path::new("x")), None);
+    }
+}
@@ -1,47 +1,316 @@
-// use std::ffi::IntoStringError;
-
-use petgraph::{Graph,};
-use petgraph::algo::dijkstra;
-use petgraph::prelude::*;
-
-
-fn main() {
-    let mut graph: Graph<&str, i32, petgraph::Undirected> = Graph::new_undirected();
-
-    let munich = graph.add_node("Munich");
-    let innsbruck = graph.add_node("Innsbruck");
-    let salzburg = graph.add_node("Salzburg");
-    let bolzano = graph.add_node("Bolzano");
-    let trento = graph.add_node("Trento");
-    let vienna = graph.add_node("Vienna");
-    let bratislava = graph.add_node("Bratislava");
-    let budapest = graph.add_node("Budapest");
-
-    graph.add_edge(munich, innsbruck, 142);
-    graph.add_edge(munich, salzburg,    150);
-    graph.add_edge(innsbruck, salzburg,    189);
-    graph.add_edge(innsbruck, bolzano,    121);
-    graph.add_edge(bolzano, trento,    58);
-
-    graph.add_edge(salzburg, vienna,    296);
-
-    graph.add_edge(vienna,  bratislava,    80);
-    graph.add_edge(vienna, budapest,    290);
-    graph.add_edge(bratislava, budapest,    200);
-
-    let path = dijkstra(&graph, munich, Some(trento), |w| *w.weight(), );
-    
-    // match path {
-    //     Some((cost, path)) => {
-    //         println!("Total distance: {}: {:?}", cost, path);
-    //     } 
-    //     None => println!("No path")
-    // }
-    println!("{:?}", path);
-
-    path.iter().for_each(|n| {
-        let city  = graph.node_weight(*n.0).unwrap();
-        println!("{:?} : {}", city, n.1);
-    })
+mod export;
+mod load;
+mod route;
 
+use clap::builder::RangedU64ValueParser;
+use clap::{Parser, ValueEnum};
+use petgraph::algo::{bellman_ford, dijkstra};
+use petgraph::graph::{DiGraph, NodeIndex};
+use serde::Serialize;
+use std::error::Error;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Instant;
+
+use load::{InputFormat, Network};
+use route::{Algorithm, Heuristic, Route};
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum Format {
+    /// Distances and node names nearest first, or a route's legs in order
+    Text,
+    /// A JSON object with the source and a list of distances, or the route
+    Json,
+}
+
+/// Shortest distances through a weighted graph read from a file
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Graph file: CSV or JSON edges with from, to and weight, or GraphViz DOT
+    graph: PathBuf,
+    /// Node to measure distances from
+    #[arg(short, long)]
+    from: String,
+    /// Show the shortest route to this node, leg by leg; the distance to
+    /// every reachable node when omitted
+    #[arg(short, long)]
+    to: Option<String>,
+    /// Edges only lead from their first node to their second; DOT digraphs
+    /// and JSON with "directed": true always do
+    #[arg(short, long)]
+    directed: bool,
+    /// How to read the graph file; guessed from its extension when omitted
+    #[arg(short, long, value_enum)]
+    input_format: Option<InputFormat>,
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+    /// How to find the way; A* and Yen's need --to
+    #[arg(short, long, value_enum, default_value_t = Algorithm::Dijkstra)]
+    algo: Algorithm,
+    /// How A* estimates the distance left to --to; haversine suits
+    /// longitude/latitude coordinates with weights in kilometres
+    #[arg(long, value_enum, default_value_t = Heuristic::Euclidean)]
+    heuristic: Heuristic,
+    /// How many routes Yen's algorithm finds
+    #[arg(short, default_value_t = 3, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    k: usize,
+    /// Time every algorithm finding the way to --to, instead of printing it
+    #[arg(long, requires = "to")]
+    bench: bool,
+    /// How many times --bench runs each algorithm
+    #[arg(long, default_value_t = 100)]
+    runs: u32,
+    /// Also write the graph to this file, with the route to --to
+    /// highlighted: GraphViz DOT for .dot or .gv, GeoJSON of the placed
+    /// nodes for .geojson or .json
+    #[arg(short, long)]
+    export: Option<PathBuf>,
+}
+
+#[derive(Serialize)]
+struct Distance<'a> {
+    node: &'a str,
+    cost: f64,
+}
+
+#[derive(Serialize)]
+struct Distances<'a> {
+    from: &'a str,
+    distances: Vec<Distance<'a>>,
+}
+
+#[derive(Serialize)]
+struct LegJson<'a> {
+    from: &'a str,
+    to: &'a str,
+    weight: f64,
+}
+
+#[derive(Serialize)]
+struct RouteJson<'a> {
+    from: &'a str,
+    to: &'a str,
+    cost: f64,
+    legs: Vec<LegJson<'a>>,
+}
+
+/// Print the distances or the route, and say whether `--to` was reached
+fn run(args: &Args) -> Result<bool, Box<dyn Error>> {
+    let format = match args
+        .input_format
+        .or_else(|| InputFormat::guess(&args.graph))
+    {
+        Some(format) => format,
+        None => return Err("cannot tell the graph's format; pass --input-format".into()),
+    };
+    let network = load::load(&args.graph, format, args.directed)
+        .map_err(|e| format!("{}: {}", args.graph.display(), e))?;
+    let graph = &network.graph;
+
+    let negative = graph.edge_indices().find(|&e| graph[e] < 0.0);
+    if let Some(edge) = negative.filter(|_| args.algo != Algorithm::BellmanFord || args.bench) {
+        let (a, b) = graph.edge_endpoints(edge).unwrap();
+        return Err(format!(
+            "{} - {} weighs {}; only --algo bellman-ford allows weights below 0",
+            graph[a], graph[b], graph[edge]
+        )
+        .into());
+    }
+    let source = network
+        .find(&args.from)
+        .ok_or_else(|| format!("no node called {}", args.from))?;
+    let target = match &args.to {
+        Some(name) => Some(
+            network
+                .find(name)
+                .ok_or_else(|| format!("no node called {}", name))?,
+        ),
+        None => None,
+    };
+
+    let routes = match target {
+        Some(target) if args.bench => return bench(&network, source, target, args),
+        Some(target) => show_routes(&network, source, target, args)?,
+        None => {
+            show_distances(graph, source, args.algo, args.format)?;
+            Vec::new()
+        }
+    };
+    if let Some(path) = &args.export {
+        export::export(path, &network, routes.first())
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    Ok(target.is_none() || !routes.is_empty())
+}
+
+/// Print how far every node reachable from `source` is, nearest first
+fn show_distances(
+    graph: &DiGraph<String, f64>,
+    source: NodeIndex,
+    algo: Algorithm,
+    format: Format,
+) -> Result<(), Box<dyn Error>> {
+    let mut distances: Vec<Distance> = match algo {
+        Algorithm::Dijkstra => dijkstra(graph, source, None, |e| *e.weight())
+            .into_iter()
+            .map(|(node, cost)| Distance {
+                node: &graph[node],
+                cost,
+            })
+            .collect(),
+        Algorithm::BellmanFord => bellman_ford(graph, source)
+            .map_err(|_| "the graph has a cycle of negative weight")?
+            .distances
+            .into_iter()
+            .enumerate()
+            .filter(|(_, cost)| cost.is_finite())
+            .map(|(node, cost)| Distance {
+                node: &graph[NodeIndex::new(node)],
+                cost,
+            })
+            .collect(),
+        Algorithm::Astar | Algorithm::YenK => {
+            return Err("A* and Yen's find routes to a node; pass --to".into());
+        }
+    };
+    distances.sort_by(|a, b| a.cost.total_cmp(&b.cost).then_with(|| a.node.cmp(b.node)));
+
+    match format {
+        Format::Text => {
+            for distance in &distances {
+                println!("{:>10}  {}", distance.cost, distance.node);
+            }
+        }
+        Format::Json => {
+            let distances = Distances {
+                from: &graph[source],
+                distances,
+            };
+            println!("{}", serde_json::to_string_pretty(&distances)?);
+        }
+    }
+    Ok(())
+}
+
+/// Print the shortest routes from `source` to `target` leg by leg, and
+/// return them; none if there is no way there
+fn show_routes(
+    network: &Network,
+    source: NodeIndex,
+    target: NodeIndex,
+    args: &Args,
+) -> Result<Vec<Route>, Box<dyn Error>> {
+    let graph = &network.graph;
+    let routes = route::find(network, source, target, args.algo, args.heuristic, args.k)?;
+    if routes.is_empty() {
+        eprintln!("No path from {} to {}", graph[source], graph[target]);
+        return Ok(routes);
+    }
+    if args.algo == Algorithm::YenK && args.format == Format::Json {
+        let json: Vec<RouteJson> = routes
+            .iter()
+            .map(|route| route_json(graph, source, target, route))
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&json)?);
+        return Ok(routes);
+    }
+    for (i, route) in routes.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        write_route(graph, source, target, route, args.format)?;
+    }
+    Ok(routes)
+}
+
+/// Time each algorithm over `args.runs` searches from `source` to `target`,
+/// with the cost each found, which should agree
+fn bench(
+    network: &Network,
+    source: NodeIndex,
+    target: NodeIndex,
+    args: &Args,
+) -> Result<bool, Box<dyn Error>> {
+    let runs = args.runs.max(1);
+    println!("{:<14}  {:>12}  {:>10}", "algorithm", "per run", "cost");
+    for algo in Algorithm::value_variants() {
+        let clock = Instant::now();
+        let mut routes = Vec::new();
+        for _ in 0..runs {
+            routes = route::find(network, source, target, *algo, args.heuristic, args.k)?;
+        }
+        let per_run = clock.elapsed() / runs;
+        let name = algo.to_possible_value().unwrap();
+        let cost = routes
+            .first()
+            .map_or("no path".to_string(), |route| route.cost.to_string());
+        println!("{:<14}  {:>12.3?}  {:>10}", name.get_name(), per_run, cost);
+    }
+    Ok(true)
+}
+
+fn route_json<'a>(
+    graph: &'a DiGraph<String, f64>,
+    source: NodeIndex,
+    target: NodeIndex,
+    route: &Route,
+) -> RouteJson<'a> {
+    RouteJson {
+        from: &graph[source],
+        to: &graph[target],
+        cost: route.cost,
+        legs: route
+            .legs
+            .iter()
+            .map(|leg| LegJson {
+                from: &graph[leg.from],
+                to: &graph[leg.to],
+                weight: leg.weight,
+            })
+            .collect(),
+    }
+}
+
+fn write_route(
+    graph: &DiGraph<String, f64>,
+    source: NodeIndex,
+    target: NodeIndex,
+    route: &Route,
+    format: Format,
+) -> Result<(), Box<dyn Error>> {
+    match format {
+        Format::Text => {
+            for leg in &route.legs {
+                println!(
+                    "{:>10}  {} -> {}",
+                    leg.weight, graph[leg.from], graph[leg.to]
+                );
+            }
+            println!(
+                "{:>10}  total, {} legs from {} to {}",
+                route.cost,
+                route.legs.len(),
+                graph[source],
+                graph[target]
+            );
+        }
+        Format::Json => {
+            let route = route_json(graph, source, target, route);
+            println!("{}", serde_json::to_string_pretty(&route)?);
+        }
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    match run(&args) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
 }
@@ -0,0 +1,321 @@
+//! Shortest paths as the legs they take, not just their total cost, found
+//! with whichever algorithm `--algo` picks.
+
+use clap::ValueEnum;
+use petgraph::algo::{astar, bellman_ford};
+use petgraph::graph::{DiGraph, NodeIndex};
+use petgraph::visit::{EdgeFiltered, EdgeRef};
+use std::collections::{HashMap, HashSet};
+
+use crate::load::Network;
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Algorithm {
+    Dijkstra,
+    /// Dijkstra guided toward the target by --heuristic; needs node
+    /// coordinates to help
+    Astar,
+    /// Slower, but allows negative weights (without negative cycles)
+    BellmanFord,
+    /// Yen's algorithm: the -k shortest routes that visit no node twice
+    YenK,
+}
+
+/// How A* estimates the distance left from a node to the target; nodes
+/// without coordinates are estimated at 0
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Heuristic {
+    /// Straight-line distance between x, y coordinates, in their units; for
+    /// weights measured in those units too
+    Euclidean,
+    /// Great-circle distance in kilometres, reading x as longitude and y as
+    /// latitude in degrees
+    Haversine,
+}
+
+impl Heuristic {
+    fn estimate(self, a: [f64; 2], b: [f64; 2]) -> f64 {
+        match self {
+            Heuristic::Euclidean => (a[0] - b[0]).hypot(a[1] - b[1]),
+            Heuristic::Haversine => {
+                let (lat1, lat2) = (a[1].to_radians(), b[1].to_radians());
+                let (dlat, dlon) = (lat2 - lat1, (b[0] - a[0]).to_radians());
+                let h = (dlat / 2.0).sin().powi(2)
+                    + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+                2.0 * EARTH_RADIUS_KM * h.sqrt().asin()
+            }
+        }
+    }
+}
+
+/// One edge of a route
+#[derive(Clone, Copy, Debug)]
+pub struct Leg {
+    pub from: NodeIndex,
+    pub to: NodeIndex,
+    pub weight: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Route {
+    pub cost: f64,
+    pub legs: Vec<Leg>,
+}
+
+impl Route {
+    /// The route visiting `nodes` in order, over the lightest edge between
+    /// each pair of them
+    pub fn through(graph: &DiGraph<String, f64>, nodes: &[NodeIndex]) -> Route {
+        let legs: Vec<Leg> = nodes
+            .windows(2)
+            .map(|pair| {
+                let weight = graph
+                    .edges_connecting(pair[0], pair[1])
+                    .map(|edge| *edge.weight())
+                    .min_by(f64::total_cmp)
+                    .expect("consecutive route nodes are joined by an edge");
+                Leg {
+                    from: pair[0],
+                    to: pair[1],
+                    weight,
+                }
+            })
+            .collect();
+        Route {
+            cost: legs.iter().fold(0.0, |cost, leg| cost + leg.weight),
+            legs,
+        }
+    }
+}
+
+/// The shortest routes from `source` to `target` by `algorithm`: up to `k`
+/// of them for Yen's, otherwise at most one
+pub fn find(
+    network: &Network,
+    source: NodeIndex,
+    target: NodeIndex,
+    algorithm: Algorithm,
+    heuristic: Heuristic,
+    k: usize,
+) -> Result<Vec<Route>, String> {
+    let graph = &network.graph;
+    let route = match algorithm {
+        Algorithm::Dijkstra => dijkstra(graph, source, target),
+        Algorithm::Astar => {
+            let goal = network.positions.get(&target).copied();
+            let estimate = |node: NodeIndex| match (network.positions.get(&node), goal) {
+                (Some(&at), Some(goal)) => heuristic.estimate(at, goal),
+                _ => 0.0,
+            };
+            astar(
+                graph,
+                source,
+                |node| node == target,
+                |e| *e.weight(),
+                estimate,
+            )
+            .map(|(_, nodes)| Route::through(graph, &nodes))
+        }
+        Algorithm::BellmanFord => {
+            let paths = bellman_ford(graph, source)
+                .map_err(|_| "the graph has a cycle of negative weight".to_string())?;
+            let mut nodes = vec![target];
+            while let Some(previous) = paths.predecessors[nodes[nodes.len() - 1].index()] {
+                nodes.push(previous);
+            }
+            nodes.reverse();
+            (nodes[0] == source).then(|| Route::through(graph, &nodes))
+        }
+        Algorithm::YenK => return Ok(yen(graph, source, target, k)),
+    };
+    Ok(route.into_iter().collect())
+}
+
+/// The shortest route from `source` to `target`, found with Dijkstra's
+/// algorithm (A* with no heuristic, which keeps track of the path)
+pub fn dijkstra(
+    graph: &DiGraph<String, f64>,
+    source: NodeIndex,
+    target: NodeIndex,
+) -> Option<Route> {
+    let (_, nodes) = astar(
+        graph,
+        source,
+        |node| node == target,
+        |e| *e.weight(),
+        |_| 0.0,
+    )?;
+    Some(Route::through(graph, &nodes))
+}
+
+/// Up to `k` shortest routes from `source` to `target` that visit no node
+/// twice, shortest first, by Yen's algorithm: each next route leaves one of
+/// the routes found so far somewhere along it, by a way none of them take
+/// from there
+fn yen(graph: &DiGraph<String, f64>, source: NodeIndex, target: NodeIndex, k: usize) -> Vec<Route> {
+    let shortest = |from: NodeIndex,
+                    blocked_nodes: &HashSet<NodeIndex>,
+                    blocked_edges: &HashSet<(NodeIndex, NodeIndex)>| {
+        let filtered = EdgeFiltered::from_fn(graph, |e| {
+            !blocked_nodes.contains(&e.target())
+                && !blocked_edges.contains(&(e.source(), e.target()))
+        });
+        astar(
+            &filtered,
+            from,
+            |node| node == target,
+            |e| *e.weight(),
+            |_| 0.0,
+        )
+    };
+
+    if k == 0 {
+        return Vec::new();
+    }
+    let Some((_, first)) = shortest(source, &HashSet::new(), &HashSet::new()) else {
+        return Vec::new();
+    };
+    let mut found: Vec<Vec<NodeIndex>> = vec![first];
+    let mut candidates: HashMap<Vec<NodeIndex>, f64> = HashMap::new();
+    while found.len() < k {
+        let last = &found[found.len() - 1];
+        for spur in 0..last.len() - 1 {
+            let root = &last[..=spur];
+            let blocked_edges = found
+                .iter()
+                .filter(|path| path.len() > spur + 1 && path[..=spur] == *root)
+                .map(|path| (path[spur], path[spur + 1]))
+                .collect();
+            let blocked_nodes = root[..spur].iter().copied().collect();
+            if let Some((_, rest)) = shortest(last[spur], &blocked_nodes, &blocked_edges) {
+                let path: Vec<NodeIndex> = root.iter().chain(&rest[1..]).copied().collect();
+                if !found.contains(&path) {
+                    let cost = Route::through(graph, &path).cost;
+                    candidates.insert(path, cost);
+                }
+            }
+        }
+        let Some(next) = candidates
+            .iter()
+            .min_by(|a, b| a.1.total_cmp(b.1).then_with(|| a.0.cmp(b.0)))
+            .map(|(path, _)| path.clone())
+        else {
+            break;
+        };
+        candidates.remove(&next);
+        found.push(next);
+    }
+    found
+        .iter()
+        .map(|path| Route::through(graph, path))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load::{self, InputFormat};
+    use std::io::Write;
+
+    /// The directed example graph of Yen's algorithm on Wikipedia
+    const YEN: &str = "digraph {
+        C -> D [weight=3]; C -> E [weight=2]; D -> F [weight=4]
+        E -> D [weight=1]; E -> F [weight=2]; E -> G [weight=3]
+        F -> G [weight=2]; F -> H [weight=1]; G -> H [weight=2]
+    }";
+
+    fn from_dot(dot: &str) -> Network {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(dot.as_bytes()).unwrap();
+        load::load(file.path(), InputFormat::Dot, false).unwrap()
+    }
+
+    fn names(network: &Network, route: &Route) -> Vec<String> {
+        let graph = &network.graph;
+        std::iter::once(route.legs[0].from)
+            .chain(route.legs.iter().map(|leg| leg.to))
+            .map(|node| graph[node].clone())
+            .collect()
+    }
+
+    fn routes(network: &Network, algorithm: Algorithm, k: usize) -> Vec<Route> {
+        let (c, h) = (network.find("C").unwrap(), network.find("H").unwrap());
+        find(network, c, h, algorithm, Heuristic::Euclidean, k).unwrap()
+    }
+
+    #[test]
+    fn test_every_algorithm_finds_the_shortest_route() {
+        let network = from_dot(YEN);
+        for algorithm in [
+            Algorithm::Dijkstra,
+            Algorithm::Astar,
+            Algorithm::BellmanFord,
+            Algorithm::YenK,
+        ] {
+            let routes = routes(&network, algorithm, 1);
+            assert_eq!(routes.len(), 1);
+            assert_eq!(names(&network, &routes[0]), ["C", "E", "F", "H"]);
+            assert_eq!(routes[0].cost, 5.0);
+            let weights: Vec<f64> = routes[0].legs.iter().map(|leg| leg.weight).collect();
+            assert_eq!(weights, [2.0, 2.0, 1.0]);
+        }
+    }
+
+    #[test]
+    fn test_through_takes_the_lightest_parallel_edge() {
+        let network = from_dot("digraph { a -> b [weight=5]; a -> b [weight=2] }");
+        let nodes = [network.find("a").unwrap(), network.find("b").unwrap()];
+        assert_eq!(Route::through(&network.graph, &nodes).cost, 2.0);
+    }
+
+    #[test]
+    fn test_yen_routes_are_distinct_and_in_order() {
+        let network = from_dot(YEN);
+        let routes = routes(&network, Algorithm::YenK, 10);
+        assert_eq!(routes.len(), 7);
+        let paths: Vec<Vec<String>> = routes.iter().map(|r| names(&network, r)).collect();
+        assert_eq!(paths[0], ["C", "E", "F", "H"]);
+        assert_eq!(paths[1], ["C", "E", "G", "H"]);
+        assert!(routes.windows(2).all(|pair| pair[0].cost <= pair[1].cost));
+        assert_eq!(routes[2].cost, 8.0);
+        let distinct: HashSet<_> = paths.iter().collect();
+        assert_eq!(distinct.len(), paths.len());
+        for path in &paths {
+            assert_eq!(path.iter().collect::<HashSet<_>>().len(), path.len());
+        }
+    }
+
+    #[test]
+    fn test_no_route() {
+        let network = from_dot("digraph { C -> D; H -> C }");
+        for algorithm in [Algorithm::Dijkstra, Algorithm::BellmanFord, Algorithm::YenK] {
+            assert!(routes(&network, algorithm, 3).is_empty());
+        }
+        assert!(routes(&from_dot(YEN), Algorithm::YenK, 0).is_empty());
+    }
+
+    #[test]
+    fn test_negative_cycle() {
+        let network = from_dot("digraph { C -> D [weight=-2]; D -> C [weight=1]; D -> H }");
+        let (c, h) = (network.find("C").unwrap(), network.find("H").unwrap());
+        let found = find(
+            &network,
+            c,
+            h,
+            Algorithm::BellmanFord,
+            Heuristic::Euclidean,
+            1,
+        );
+        assert!(found.is_err());
+    }
+
+    #[test]
+    fn test_haversine() {
+        // Munich to Vienna, about 355 km as the crow flies
+        let km = Heuristic::Haversine.estimate([11.58, 48.14], [16.37, 48.21]);
+        assert!((km - 355.0).abs() < 5.0, "{}", km);
+        assert_eq!(Heuristic::Euclidean.estimate([0.0, 0.0], [3.0, 4.0]), 5.0);
+    }
+}
@@ -0,0 +1,12 @@
+-- Users table: sensitive columns hold client-side ciphertext only
+-- (MySQL / MariaDB dialect of postgres/0001 + 0002; there is no legacy schema to adopt)
+CREATE TABLE IF NOT EXISTS users (
+    id INT AUTO_INCREMENT PRIMARY KEY,
+    username VARCHAR(255) NOT NULL UNIQUE,
+    encrypted_email TEXT NOT NULL,
+    encrypted_ssn TEXT,
+    encrypted_phone TEXT,
+    encrypted_address TEXT,
+    version INT NOT NULL DEFAULT 1,
+    created_at TIMESTAMP(6) NOT NULL DEFAULT CURRENT_TIMESTAMP(6)
+);
@@ -0,0 +1,13 @@
+-- Users table: sensitive columns hold client-side ciphertext only
+CREATE TABLE IF NOT EXISTS users (
+    id SERIAL PRIMARY KEY,
+    username VARCHAR(255) NOT NULL UNIQUE,
+    encrypted_email TEXT NOT NULL,
+    encrypted_ssn TEXT,
+    encrypted_phone TEXT,
+    encrypted_address TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Index on username (the only searchable plaintext field)
+CREATE INDEX IF NOT EXISTS idx_users_username ON users(username);
@@ -0,0 +1,2 @@
+-- Row version for optimistic locking in UserRepository::update
+ALTER TABLE users ADD COLUMN IF NOT EXISTS version INTEGER NOT NULL DEFAULT 1;
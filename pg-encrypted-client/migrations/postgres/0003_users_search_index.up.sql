@@ -0,0 +1,9 @@
+-- Blind trigram index for searchable encryption (stays empty unless enabled)
+CREATE TABLE IF NOT EXISTS users_search_index (
+    row_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    column_name VARCHAR(64) NOT NULL,
+    token TEXT NOT NULL,
+    PRIMARY KEY (column_name, token, row_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_users_search_index_row ON users_search_index(row_id);
@@ -0,0 +1,13 @@
+-- Progress of online key rotations (see src/rotate.rs)
+-- watermark: highest users.id already processed; the worker resumes after it
+CREATE TABLE IF NOT EXISTS key_rotations (
+    name TEXT PRIMARY KEY,
+    watermark INTEGER NOT NULL DEFAULT 0,
+    rows_done BIGINT NOT NULL DEFAULT 0,
+    rows_rewritten BIGINT NOT NULL DEFAULT 0,
+    rows_total BIGINT NOT NULL DEFAULT 0,
+    rows_per_sec INTEGER NOT NULL,
+    started_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    finished_at TIMESTAMPTZ
+);
@@ -0,0 +1,350 @@
+//! Benchmark Harness - what client-side encryption costs
+//!
+//! Every configuration (pool size × batch size) runs the same workload twice
+//! against a scratch table: once storing plaintext, once through the Enhanced
+//! Client Driver. Inserts time row generation, encryption and the INSERTs;
+//! selects time reading every row back and, for the encrypted run,
+//! decrypting it. Batches are sent concurrently, up to the pool size.
+//!
+//! The scratch table `bench_records` is dropped when the run finishes.
+
+use crate::crypto::{CryptoError, EncryptedClientDriver};
+use crate::repository::SENSITIVE_COLUMNS;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use sqlx::postgres::PgPoolOptions;
+use sqlx::{PgPool, Postgres, QueryBuilder, Row};
+use std::fmt::Write;
+use std::ops::Range;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// PostgreSQL accepts at most this many bind parameters per statement
+const MAX_BIND_PARAMS: usize = 65_535;
+
+/// Benchmark errors
+#[derive(Error, Debug)]
+pub enum BenchError {
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+
+    #[error("Encryption error: {0}")]
+    Crypto(#[from] CryptoError),
+
+    #[error("Invalid benchmark configuration: {0}")]
+    InvalidConfig(String),
+}
+
+/// Workload shape
+#[derive(Debug, Clone)]
+pub struct BenchConfig {
+    /// Rows inserted and read back per run
+    pub rows: usize,
+    pub batch_sizes: Vec<usize>,
+    pub pool_sizes: Vec<u32>,
+}
+
+impl BenchConfig {
+    fn validate(&self) -> Result<(), BenchError> {
+        if self.rows == 0 || self.batch_sizes.is_empty() || self.pool_sizes.is_empty() {
+            return Err(BenchError::InvalidConfig(
+                "rows, batch sizes and pool sizes must not be empty".into(),
+            ));
+        }
+        let max_batch = MAX_BIND_PARAMS / SENSITIVE_COLUMNS.len();
+        if let Some(batch) = self.batch_sizes.iter().find(|&&b| b == 0 || b > max_batch) {
+            return Err(BenchError::InvalidConfig(format!(
+                "batch size {batch} must be between 1 and {max_batch}"
+            )));
+        }
+        if self.pool_sizes.contains(&0) {
+            return Err(BenchError::InvalidConfig(
+                "pool size must be at least 1".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Insert,
+    Select,
+}
+
+impl Operation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Insert => "insert",
+            Self::Select => "select",
+        }
+    }
+}
+
+/// Throughput of one operation in one configuration
+#[derive(Debug, Clone)]
+pub struct BenchResult {
+    pub operation: Operation,
+    pub pool_size: u32,
+    pub batch_size: usize,
+    pub plain_rows_per_sec: f64,
+    pub encrypted_rows_per_sec: f64,
+}
+
+impl BenchResult {
+    /// Extra time encryption adds, relative to plaintext
+    pub fn overhead_percent(&self) -> f64 {
+        (self.plain_rows_per_sec / self.encrypted_rows_per_sec - 1.0) * 100.0
+    }
+}
+
+/// Run every configuration; `on_result` sees each result as it completes
+pub async fn run(
+    pool: &PgPool,
+    driver: &EncryptedClientDriver,
+    config: &BenchConfig,
+    mut on_result: impl FnMut(&BenchResult),
+) -> Result<Vec<BenchResult>, BenchError> {
+    config.validate()?;
+
+    // Derive (and cache) the CEK up front so key derivation is not timed
+    driver.ready().await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS bench_records (
+            id SERIAL PRIMARY KEY,
+            email TEXT,
+            ssn TEXT,
+            phone TEXT,
+            address TEXT
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    let mut results = Vec::new();
+    for &pool_size in &config.pool_sizes {
+        let bench_pool = PgPoolOptions::new()
+            .max_connections(pool_size)
+            .connect_with((*pool.connect_options()).clone())
+            .await?;
+        let runner = Runner {
+            pool: &bench_pool,
+            concurrency: pool_size as usize,
+            rows: config.rows,
+        };
+
+        for &batch_size in &config.batch_sizes {
+            let plain = runner.measure(None, batch_size).await?;
+            let encrypted = runner.measure(Some(driver), batch_size).await?;
+
+            for (operation, plain, encrypted) in [
+                (Operation::Insert, plain.0, encrypted.0),
+                (Operation::Select, plain.1, encrypted.1),
+            ] {
+                let result = BenchResult {
+                    operation,
+                    pool_size,
+                    batch_size,
+                    plain_rows_per_sec: config.rows as f64 / plain.as_secs_f64(),
+                    encrypted_rows_per_sec: config.rows as f64 / encrypted.as_secs_f64(),
+                };
+                on_result(&result);
+                results.push(result);
+            }
+        }
+        bench_pool.close().await;
+    }
+
+    sqlx::query("DROP TABLE IF EXISTS bench_records")
+        .execute(pool)
+        .await?;
+    Ok(results)
+}
+
+/// Results as an aligned text table
+pub fn render_table(results: &[BenchResult]) -> String {
+    let mut out = format!(
+        "{:<8} {:>5} {:>6} {:>14} {:>14} {:>9}\n",
+        "op", "pool", "batch", "plain rows/s", "enc rows/s", "overhead"
+    );
+    for r in results {
+        let _ = writeln!(
+            out,
+            "{:<8} {:>5} {:>6} {:>14.0} {:>14.0} {:>+8.1}%",
+            r.operation.name(),
+            r.pool_size,
+            r.batch_size,
+            r.plain_rows_per_sec,
+            r.encrypted_rows_per_sec,
+            r.overhead_percent()
+        );
+    }
+    out
+}
+
+struct Runner<'a> {
+    pool: &'a PgPool,
+    concurrency: usize,
+    rows: usize,
+}
+
+impl Runner<'_> {
+    /// Insert then select all rows; returns (insert time, select time)
+    async fn measure(
+        &self,
+        driver: Option<&EncryptedClientDriver>,
+        batch_size: usize,
+    ) -> Result<(Duration, Duration), BenchError> {
+        sqlx::query("TRUNCATE bench_records RESTART IDENTITY")
+            .execute(self.pool)
+            .await?;
+
+        let start = Instant::now();
+        stream::iter(batches(self.rows, batch_size))
+            .map(|range| self.insert_batch(driver, range))
+            .buffer_unordered(self.concurrency)
+            .try_collect::<()>()
+            .await?;
+        let insert = start.elapsed();
+
+        let start = Instant::now();
+        stream::iter(batches(self.rows, batch_size))
+            .map(|range| self.select_batch(driver, range))
+            .buffer_unordered(self.concurrency)
+            .try_collect::<()>()
+            .await?;
+        let select = start.elapsed();
+
+        Ok((insert, select))
+    }
+
+    async fn insert_batch(
+        &self,
+        driver: Option<&EncryptedClientDriver>,
+        range: Range<usize>,
+    ) -> Result<(), BenchError> {
+        if let Some(driver) = driver {
+            driver.ready().await?;
+        }
+        let mut rows = Vec::with_capacity(range.len());
+        for i in range {
+            let mut row = sample_row(i);
+            if let Some(driver) = driver {
+                for (column, value) in SENSITIVE_COLUMNS.iter().zip(row.iter_mut()) {
+                    *value = driver.encrypt_column(column, value)?;
+                }
+            }
+            rows.push(row);
+        }
+
+        let mut query = QueryBuilder::<Postgres>::new(
+            "INSERT INTO bench_records (email, ssn, phone, address) ",
+        );
+        query.push_values(rows, |mut b, row| {
+            for value in row {
+                b.push_bind(value);
+            }
+        });
+        query.build().execute(self.pool).await?;
+        Ok(())
+    }
+
+    async fn select_batch(
+        &self,
+        driver: Option<&EncryptedClientDriver>,
+        range: Range<usize>,
+    ) -> Result<(), BenchError> {
+        // The table was truncated with RESTART IDENTITY, so the ids are
+        // 1..=rows. Batches insert concurrently, so an id range is not the
+        // rows of one insert batch, but it is as many rows.
+        let rows = sqlx::query(
+            "SELECT email, ssn, phone, address FROM bench_records WHERE id > $1 AND id <= $2",
+        )
+        .bind(range.start as i32)
+        .bind(range.end as i32)
+        .fetch_all(self.pool)
+        .await?;
+
+        if let Some(driver) = driver {
+            driver.ready().await?;
+        }
+        for row in rows {
+            for index in 0..SENSITIVE_COLUMNS.len() {
+                let value: String = row.try_get(index)?;
+                if let Some(driver) = driver {
+                    driver.decrypt(&value)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Row index ranges of `batch_size` covering `0..rows`
+fn batches(rows: usize, batch_size: usize) -> Vec<Range<usize>> {
+    (0..rows)
+        .step_by(batch_size)
+        .map(|start| start..(start + batch_size).min(rows))
+        .collect()
+}
+
+/// Synthetic but realistically sized values for the sensitive columns
+fn sample_row(i: usize) -> [String; 4] {
+    [
+        format!("user{i}@example.com"),
+        format!("{:03}-{:02}-{:04}", i % 1000, i % 100, i % 10_000),
+        format!("+1-555-{:04}", i % 10_000),
+        format!(
+            "{} Main St, Springfield, ST {:05}",
+            i % 9_999 + 1,
+            i % 100_000
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(plain: f64, encrypted: f64) -> BenchResult {
+        BenchResult {
+            operation: Operation::Insert,
+            pool_size: 5,
+            batch_size: 100,
+            plain_rows_per_sec: plain,
+            encrypted_rows_per_sec: encrypted,
+        }
+    }
+
+    #[test]
+    fn test_overhead_percent() {
+        assert!((result(1000.0, 800.0).overhead_percent() - 25.0).abs() < 1e-9);
+        assert!(result(1000.0, 1000.0).overhead_percent().abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_batches_cover_all_rows() {
+        assert_eq!(batches(5, 2), vec![0..2, 2..4, 4..5]);
+        assert_eq!(batches(4, 10), vec![0..4]);
+    }
+
+    #[test]
+    fn test_validate_rejects_oversized_batches() {
+        let config = BenchConfig {
+            rows: 10,
+            batch_sizes: vec![100_000],
+            pool_sizes: vec![1],
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_render_table() {
+        let table = render_table(&[result(1000.0, 800.0)]);
+        let line = table.lines().nth(1).unwrap();
+        assert!(line.starts_with("insert"));
+        assert!(line.ends_with("+25.0%"));
+    }
+}
@@ -0,0 +1,228 @@
+//! Cipher Suites - the AEAD algorithms available to the driver
+//!
+//! Every ciphertext records its suite in a short text header, so columns
+//! written with different suites (or rows written before a column switched
+//! suite) always decrypt with the right algorithm:
+//!
+//! ```text
+//! <tag>:<base64(nonce || ciphertext || auth_tag)>
+//! ```
+//!
+//! Values without a header predate cipher suites and are AES-256-GCM. The
+//! header cannot be confused with them because `:` is not in the base64
+//! alphabet.
+//!
+//! | Suite               | Tag    | Nonce    | Use when                                   |
+//! |---------------------|--------|----------|--------------------------------------------|
+//! | AES-256-GCM         | `gcm`  | 12 bytes | Default; fast with AES-NI                  |
+//! | XChaCha20-Poly1305  | `xc20` | 24 bytes | Very many encryptions under one key        |
+//! | AES-256-GCM-SIV     | `siv`  | 12 bytes | Nonce reuse must not be catastrophic       |
+//!
+//! AES-256-GCM uses the CEK directly (so existing data stays readable); the
+//! other suites use a subkey `HMAC-SHA256(CEK, suite name)` so one key is
+//! never shared between algorithms.
+
+use crate::crypto::CryptoError;
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::Aes256Gcm;
+use aes_gcm_siv::Aes256GcmSiv;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use chacha20poly1305::XChaCha20Poly1305;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+use std::str::FromStr;
+use zeroize::Zeroizing;
+
+/// AEAD algorithm used for a column
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum CipherSuite {
+    #[default]
+    Aes256Gcm,
+    XChaCha20Poly1305,
+    Aes256GcmSiv,
+}
+
+impl CipherSuite {
+    pub const ALL: [CipherSuite; 3] = [
+        CipherSuite::Aes256Gcm,
+        CipherSuite::XChaCha20Poly1305,
+        CipherSuite::Aes256GcmSiv,
+    ];
+
+    /// Name used in configuration (`CIPHER_SUITE`, `COLUMN_CIPHER_SUITES`)
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Aes256Gcm => "aes256gcm",
+            Self::XChaCha20Poly1305 => "xchacha20poly1305",
+            Self::Aes256GcmSiv => "aes256gcmsiv",
+        }
+    }
+
+    /// Short identifier written in the ciphertext header
+    fn tag(&self) -> &'static str {
+        match self {
+            Self::Aes256Gcm => "gcm",
+            Self::XChaCha20Poly1305 => "xc20",
+            Self::Aes256GcmSiv => "siv",
+        }
+    }
+
+    pub fn nonce_len(&self) -> usize {
+        match self {
+            Self::Aes256Gcm | Self::Aes256GcmSiv => 12,
+            Self::XChaCha20Poly1305 => 24,
+        }
+    }
+
+    /// Key actually fed to the algorithm, derived from the CEK bytes
+    fn subkey(&self, cek: &[u8; 32]) -> Zeroizing<[u8; 32]> {
+        let mut key = Zeroizing::new([0u8; 32]);
+        match self {
+            Self::Aes256Gcm => key.copy_from_slice(cek),
+            _ => {
+                let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(cek)
+                    .expect("HMAC accepts keys of any length");
+                mac.update(b"pg-encrypted-client/suite/");
+                mac.update(self.name().as_bytes());
+                key.copy_from_slice(&mac.finalize().into_bytes());
+            }
+        }
+        key
+    }
+
+    /// Encrypt `plaintext` under the CEK with the given nonce
+    pub(crate) fn seal(
+        &self,
+        cek: &[u8; 32],
+        nonce: &[u8],
+        plaintext: &[u8],
+    ) -> Result<Vec<u8>, CryptoError> {
+        let key = self.subkey(cek);
+        let result = match self {
+            Self::Aes256Gcm => Aes256Gcm::new(key.as_ref().into()).encrypt(nonce.into(), plaintext),
+            Self::XChaCha20Poly1305 => {
+                XChaCha20Poly1305::new(key.as_ref().into()).encrypt(nonce.into(), plaintext)
+            }
+            Self::Aes256GcmSiv => {
+                Aes256GcmSiv::new(key.as_ref().into()).encrypt(nonce.into(), plaintext)
+            }
+        };
+        result.map_err(|e| CryptoError::EncryptionFailed(e.to_string()))
+    }
+
+    /// Decrypt and authenticate `ciphertext` under the CEK
+    pub(crate) fn open(
+        &self,
+        cek: &[u8; 32],
+        nonce: &[u8],
+        ciphertext: &[u8],
+    ) -> Result<Vec<u8>, CryptoError> {
+        let key = self.subkey(cek);
+        let result = match self {
+            Self::Aes256Gcm => {
+                Aes256Gcm::new(key.as_ref().into()).decrypt(nonce.into(), ciphertext)
+            }
+            Self::XChaCha20Poly1305 => {
+                XChaCha20Poly1305::new(key.as_ref().into()).decrypt(nonce.into(), ciphertext)
+            }
+            Self::Aes256GcmSiv => {
+                Aes256GcmSiv::new(key.as_ref().into()).decrypt(nonce.into(), ciphertext)
+            }
+        };
+        result.map_err(|e| CryptoError::DecryptionFailed(e.to_string()))
+    }
+
+    /// Wrap `nonce || ciphertext` into the stored text form
+    pub(crate) fn encode(&self, payload: &[u8]) -> String {
+        format!("{}:{}", self.tag(), BASE64.encode(payload))
+    }
+
+    /// Split a stored value into its suite and decoded `nonce || ciphertext`
+    pub(crate) fn decode(encrypted: &str) -> Result<(Self, Vec<u8>), CryptoError> {
+        let (suite, body) = match encrypted.split_once(':') {
+            Some((tag, body)) => {
+                let suite = Self::ALL
+                    .into_iter()
+                    .find(|s| s.tag() == tag)
+                    .ok_or_else(|| {
+                        CryptoError::InvalidFormat(format!("Unknown cipher suite '{tag}'"))
+                    })?;
+                (suite, body)
+            }
+            // No header: written before cipher suites existed
+            None => (Self::Aes256Gcm, encrypted),
+        };
+
+        let payload = BASE64
+            .decode(body)
+            .map_err(|e| CryptoError::InvalidFormat(e.to_string()))?;
+        Ok((suite, payload))
+    }
+}
+
+impl fmt::Display for CipherSuite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for CipherSuite {
+    type Err = CryptoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalized = s.to_lowercase().replace(['-', '_'], "");
+        Self::ALL
+            .into_iter()
+            .find(|suite| suite.name() == normalized)
+            .ok_or_else(|| CryptoError::InvalidFormat(format!("Unknown cipher suite '{s}'")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_suite_names() {
+        assert_eq!(
+            "XChaCha20-Poly1305".parse::<CipherSuite>().unwrap(),
+            CipherSuite::XChaCha20Poly1305
+        );
+        assert_eq!(
+            "aes256gcmsiv".parse::<CipherSuite>().unwrap(),
+            CipherSuite::Aes256GcmSiv
+        );
+        assert!("rot13".parse::<CipherSuite>().is_err());
+    }
+
+    #[test]
+    fn test_seal_open_every_suite() {
+        let cek = [3u8; 32];
+        for suite in CipherSuite::ALL {
+            let nonce = vec![9u8; suite.nonce_len()];
+            let sealed = suite.seal(&cek, &nonce, b"123-45-6789").unwrap();
+            assert_eq!(suite.open(&cek, &nonce, &sealed).unwrap(), b"123-45-6789");
+
+            let (decoded_suite, payload) = CipherSuite::decode(&suite.encode(&sealed)).unwrap();
+            assert_eq!(decoded_suite, suite);
+            assert_eq!(payload, sealed);
+        }
+    }
+
+    #[test]
+    fn test_suites_use_distinct_keys() {
+        let cek = [3u8; 32];
+        let nonce = [0u8; 12];
+        let gcm = CipherSuite::Aes256Gcm.seal(&cek, &nonce, b"x").unwrap();
+        assert!(CipherSuite::Aes256GcmSiv.open(&cek, &nonce, &gcm).is_err());
+    }
+
+    #[test]
+    fn test_legacy_values_decode_as_gcm() {
+        let (suite, payload) = CipherSuite::decode(&BASE64.encode([1u8; 20])).unwrap();
+        assert_eq!(suite, CipherSuite::Aes256Gcm);
+        assert_eq!(payload, [1u8; 20]);
+        assert!(CipherSuite::decode("rot13:AAAA").is_err());
+    }
+}
@@ -9,8 +9,15 @@ use aes_gcm::{
 };
 use argon2::Argon2;
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use hmac::{Hmac, Mac};
+use crate::cipher::CipherSuite;
+use crate::keycache::KeyCache;
 use rand::RngCore;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::Arc;
 use thiserror::Error;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
 
 /// Encryption errors
 #[derive(Error, Debug)]
@@ -26,10 +33,17 @@ pub enum CryptoError {
 
     #[error("Invalid data format: {0}")]
     InvalidFormat(String),
+
+    #[error("Keys are locked - unlock with the master key first")]
+    KeysLocked,
 }
 
 /// Column Encryption Key (CEK) - encrypts actual data
 /// This mirrors the "Database Encryption Key" from slide 1
+///
+/// Key bytes are wiped when the key is dropped. Keys are filled in place so no
+/// unzeroized copy is left behind on the stack.
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
 pub struct ColumnEncryptionKey {
     key: [u8; 32],
 }
@@ -37,24 +51,25 @@ pub struct ColumnEncryptionKey {
 impl ColumnEncryptionKey {
     /// Create a new random CEK
     pub fn generate() -> Self {
-        let mut key = [0u8; 32];
-        OsRng.fill_bytes(&mut key);
-        Self { key }
+        let mut cek = Self { key: [0u8; 32] };
+        OsRng.fill_bytes(&mut cek.key);
+        cek
     }
 
     /// Derive CEK from a master key and column name (deterministic)
     pub fn derive(master_key: &MasterKey, column_name: &str) -> Result<Self, CryptoError> {
-        let mut key = [0u8; 32];
+        let mut cek = Self { key: [0u8; 32] };
         Argon2::default()
             .hash_password_into(
                 column_name.as_bytes(),
                 &master_key.key,
-                &mut key,
+                &mut cek.key,
             )
             .map_err(|e| CryptoError::KeyDerivationFailed(e.to_string()))?;
-        Ok(Self { key })
+        Ok(cek)
     }
 
+
     /// Export as base64 (for storing encrypted in database)
     pub fn to_base64(&self) -> String {
         BASE64.encode(self.key)
@@ -62,22 +77,25 @@ impl ColumnEncryptionKey {
 
     /// Import from base64
     pub fn from_base64(encoded: &str) -> Result<Self, CryptoError> {
-        let bytes = BASE64
-            .decode(encoded)
-            .map_err(|e| CryptoError::InvalidFormat(e.to_string()))?;
+        let bytes = Zeroizing::new(
+            BASE64
+                .decode(encoded)
+                .map_err(|e| CryptoError::InvalidFormat(e.to_string()))?,
+        );
 
         if bytes.len() != 32 {
             return Err(CryptoError::InvalidFormat("Key must be 32 bytes".into()));
         }
 
-        let mut key = [0u8; 32];
-        key.copy_from_slice(&bytes);
-        Ok(Self { key })
+        let mut cek = Self { key: [0u8; 32] };
+        cek.key.copy_from_slice(&bytes);
+        Ok(cek)
     }
 }
 
 /// Column Master Key (CMK) - encrypts Column Encryption Keys
-
+/// Wiped on drop, like the CEK
+#[derive(Zeroize, ZeroizeOnDrop)]
 pub struct MasterKey {
     key: [u8; 32],
 }
@@ -85,11 +103,11 @@ pub struct MasterKey {
 impl MasterKey {
     /// Derive master key from password (like DPAPI deriving from Windows credentials)
     pub fn from_password(password: &str, salt: &[u8]) -> Result<Self, CryptoError> {
-        let mut key = [0u8; 32];
+        let mut master = Self { key: [0u8; 32] };
         Argon2::default()
-            .hash_password_into(password.as_bytes(), salt, &mut key)
+            .hash_password_into(password.as_bytes(), salt, &mut master.key)
             .map_err(|e| CryptoError::KeyDerivationFailed(e.to_string()))?;
-        Ok(Self { key })
+        Ok(master)
     }
 
     /// Create from raw bytes (e.g., loaded from secure storage)
@@ -97,6 +115,18 @@ impl MasterKey {
         Self { key: *bytes }
     }
 
+    /// Create a new random master key (see `keyfile` for storing it)
+    pub fn generate() -> Self {
+        let mut master = Self { key: [0u8; 32] };
+        OsRng.fill_bytes(&mut master.key);
+        master
+    }
+
+    /// Raw key bytes, for wrapping into a key file
+    pub(crate) fn as_bytes(&self) -> &[u8; 32] {
+        &self.key
+    }
+
     /// Encrypt a Column Encryption Key
     pub fn encrypt_cek(&self, cek: &ColumnEncryptionKey) -> Result<String, CryptoError> {
         let cipher = Aes256Gcm::new_from_slice(&self.key)
@@ -132,72 +162,201 @@ impl MasterKey {
         let (nonce_bytes, ciphertext) = combined.split_at(12);
         let nonce = Nonce::from_slice(nonce_bytes);
 
-        let plaintext = cipher
-            .decrypt(nonce, ciphertext)
-            .map_err(|e| CryptoError::DecryptionFailed(e.to_string()))?;
+        let plaintext = Zeroizing::new(
+            cipher
+                .decrypt(nonce, ciphertext)
+                .map_err(|e| CryptoError::DecryptionFailed(e.to_string()))?,
+        );
 
         if plaintext.len() != 32 {
             return Err(CryptoError::InvalidFormat("Decrypted key wrong size".into()));
         }
 
-        let mut key = [0u8; 32];
-        key.copy_from_slice(&plaintext);
-        Ok(ColumnEncryptionKey { key })
+        let mut cek = ColumnEncryptionKey { key: [0u8; 32] };
+        cek.key.copy_from_slice(&plaintext);
+        Ok(cek)
     }
 }
 
+/// Blind Index Key - keys the HMAC used for searchable encryption
+///
+/// Kept separate from the CEK so that the search index can be rebuilt or
+/// dropped without touching the encrypted columns themselves.
+#[derive(Zeroize, ZeroizeOnDrop)]
+pub struct IndexKey {
+    key: [u8; 32],
+}
+
+impl IndexKey {
+    /// Derive the index key from a master key and a purpose label (deterministic)
+    pub fn derive(master_key: &MasterKey, label: &str) -> Result<Self, CryptoError> {
+        let mut index_key = Self { key: [0u8; 32] };
+        Argon2::default()
+            .hash_password_into(label.as_bytes(), &master_key.key, &mut index_key.key)
+            .map_err(|e| CryptoError::KeyDerivationFailed(e.to_string()))?;
+        Ok(index_key)
+    }
+
+    /// Compute a deterministic token for `value` scoped to `column`
+    /// Returns base64-encoded HMAC-SHA256
+    pub fn token(&self, column: &str, value: &str) -> String {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.key)
+            .expect("HMAC accepts keys of any length");
+        mac.update(column.as_bytes());
+        mac.update(b":");
+        mac.update(value.as_bytes());
+        BASE64.encode(mac.finalize().into_bytes())
+    }
+}
+
+/// Where the driver gets its Column Encryption Key from
+enum KeySource {
+    /// CEK held for the driver's lifetime
+    Fixed(ColumnEncryptionKey),
+    /// CEK looked up in a shared cache on every operation (honours TTL and lock)
+    Cached { cache: Arc<KeyCache>, label: String },
+}
+
 /// The Enhanced Client Driver - handles all encryption/decryption
 pub struct EncryptedClientDriver {
-    cipher: Aes256Gcm,
+    source: KeySource,
+    default_suite: CipherSuite,
+    column_suites: HashMap<String, CipherSuite>,
+    /// Driver of the key being rotated away from, tried when `source` fails
+    previous: Option<Box<EncryptedClientDriver>>,
 }
 
 impl EncryptedClientDriver {
     /// Create driver with a Column Encryption Key
     pub fn new(cek: &ColumnEncryptionKey) -> Self {
-        let cipher = Aes256Gcm::new_from_slice(&cek.key)
-            .expect("Invalid key length - should never happen with 32-byte key");
-        Self { cipher }
+        Self::with_source(KeySource::Fixed(cek.clone()))
+    }
+
+    /// Create driver whose CEK (derived for `label`) comes from a key cache
+    /// Operations fail with `CryptoError::KeysLocked` while the cache is locked
+    pub fn cached(cache: Arc<KeyCache>, label: &str) -> Self {
+        Self::with_source(KeySource::Cached {
+            cache,
+            label: label.to_string(),
+        })
+    }
+
+    fn with_source(source: KeySource) -> Self {
+        Self {
+            source,
+            default_suite: CipherSuite::default(),
+            column_suites: HashMap::new(),
+            previous: None,
+        }
+    }
+
+    /// Fall back to `previous` for values this driver's key cannot decrypt
+    /// Used during key rotation, while some rows are still under the old key.
+    /// Encryption always uses this driver's own key.
+    pub fn with_previous(mut self, previous: EncryptedClientDriver) -> Self {
+        self.previous = Some(Box::new(previous));
+        self
+    }
+
+    /// Cipher suite for columns without an override
+    pub fn with_suite(mut self, suite: CipherSuite) -> Self {
+        self.default_suite = suite;
+        self
+    }
+
+    /// Cipher suite for one column, used by `encrypt_column`
+    pub fn with_column_suite(mut self, column: &str, suite: CipherSuite) -> Self {
+        self.column_suites.insert(column.to_string(), suite);
+        self
+    }
+
+    /// Cipher suite new values of `column` are encrypted with
+    pub fn suite_for(&self, column: &str) -> CipherSuite {
+        self.column_suites
+            .get(column)
+            .copied()
+            .unwrap_or(self.default_suite)
+    }
+
+    /// Derive this driver's cached keys ahead of use, off the async
+    /// runtime's worker threads. Fixed keys, keys already cached and a
+    /// locked cache have nothing to derive.
+    pub async fn ready(&self) -> Result<(), CryptoError> {
+        if let KeySource::Cached { cache, label } = &self.source {
+            cache.prefetch(label).await?;
+        }
+        if let Some(previous) = &self.previous {
+            Box::pin(previous.ready()).await?;
+        }
+        Ok(())
+    }
+
+    fn with_key<T>(
+        &self,
+        f: impl FnOnce(&ColumnEncryptionKey) -> Result<T, CryptoError>,
+    ) -> Result<T, CryptoError> {
+        match &self.source {
+            KeySource::Fixed(cek) => f(cek),
+            KeySource::Cached { cache, label } => cache.with_cek(label, f),
+        }
     }
 
     /// Encrypt plaintext data before sending to database
-    /// Returns base64-encoded ciphertext (nonce prepended)
+    /// Returns the suite header followed by base64 ciphertext (nonce prepended)
     pub fn encrypt(&self, plaintext: &str) -> Result<String, CryptoError> {
-        let mut nonce_bytes = [0u8; 12];
+        self.encrypt_with(self.default_suite, plaintext)
+    }
+
+    /// Encrypt a value of `column` with that column's cipher suite
+    pub fn encrypt_column(&self, column: &str, plaintext: &str) -> Result<String, CryptoError> {
+        self.encrypt_with(self.suite_for(column), plaintext)
+    }
+
+    fn encrypt_with(&self, suite: CipherSuite, plaintext: &str) -> Result<String, CryptoError> {
+        let mut nonce_bytes = vec![0u8; suite.nonce_len()];
         OsRng.fill_bytes(&mut nonce_bytes);
-        let nonce = Nonce::from_slice(&nonce_bytes);
 
-        let ciphertext = self
-            .cipher
-            .encrypt(nonce, plaintext.as_bytes())
-            .map_err(|e| CryptoError::EncryptionFailed(e.to_string()))?;
+        let ciphertext =
+            self.with_key(|cek| suite.seal(&cek.key, &nonce_bytes, plaintext.as_bytes()))?;
 
         // Prepend nonce to ciphertext
-        let mut combined = nonce_bytes.to_vec();
+        let mut combined = nonce_bytes;
         combined.extend(ciphertext);
 
-        Ok(BASE64.encode(combined))
+        Ok(suite.encode(&combined))
     }
 
     /// Decrypt ciphertext received from database
-    /// Expects base64-encoded data with nonce prepended
+    /// The suite is taken from the header; values without one are AES-256-GCM
+    /// Values this key cannot open are retried with the `with_previous` driver
     pub fn decrypt(&self, encrypted: &str) -> Result<String, CryptoError> {
-        let combined = BASE64
-            .decode(encrypted)
-            .map_err(|e| CryptoError::InvalidFormat(e.to_string()))?;
-
-        if combined.len() < 12 {
-            return Err(CryptoError::InvalidFormat(
-                "Ciphertext too short - must include 12-byte nonce".into(),
-            ));
+        let result = self.decrypt_current(encrypted);
+        match (&result, &self.previous) {
+            (Err(CryptoError::DecryptionFailed(_)), Some(previous)) => previous.decrypt(encrypted),
+            _ => result,
         }
+    }
 
-        let (nonce_bytes, ciphertext) = combined.split_at(12);
-        let nonce = Nonce::from_slice(nonce_bytes);
+    /// Whether `encrypted` is already under this driver's key and `column`'s
+    /// suite, i.e. key rotation has nothing left to do for it
+    pub fn is_current(&self, column: &str, encrypted: &str) -> bool {
+        matches!(CipherSuite::decode(encrypted), Ok((suite, _)) if suite == self.suite_for(column))
+            && self.decrypt_current(encrypted).is_ok()
+    }
 
-        let plaintext = self
-            .cipher
-            .decrypt(nonce, ciphertext)
-            .map_err(|e| CryptoError::DecryptionFailed(e.to_string()))?;
+    /// Decrypt with this driver's own key only
+    fn decrypt_current(&self, encrypted: &str) -> Result<String, CryptoError> {
+        let (suite, combined) = CipherSuite::decode(encrypted)?;
+        let nonce_len = suite.nonce_len();
+
+        if combined.len() < nonce_len {
+            return Err(CryptoError::InvalidFormat(format!(
+                "Ciphertext too short - must include {nonce_len}-byte nonce"
+            )));
+        }
+
+        let (nonce_bytes, ciphertext) = combined.split_at(nonce_len);
+        let plaintext = self.with_key(|cek| suite.open(&cek.key, nonce_bytes, ciphertext))?;
 
         String::from_utf8(plaintext)
             .map_err(|e| CryptoError::DecryptionFailed(format!("Invalid UTF-8: {e}")))
@@ -208,6 +367,15 @@ impl EncryptedClientDriver {
         plaintext.map(|p| self.encrypt(p)).transpose()
     }
 
+    /// Encrypt optional field of `column` with that column's cipher suite
+    pub fn encrypt_column_optional(
+        &self,
+        column: &str,
+        plaintext: Option<&str>,
+    ) -> Result<Option<String>, CryptoError> {
+        plaintext.map(|p| self.encrypt_column(column, p)).transpose()
+    }
+
     /// Decrypt optional field
     pub fn decrypt_optional(&self, encrypted: Option<&str>) -> Result<Option<String>, CryptoError> {
         encrypted.map(|e| self.decrypt(e)).transpose()
@@ -269,4 +437,133 @@ mod tests {
         let cek3 = ColumnEncryptionKey::derive(&master_key, "users.ssn").unwrap();
         assert_ne!(cek1.to_base64(), cek3.to_base64());
     }
+
+    #[test]
+    fn test_cached_driver_respects_lock() {
+        let master_key = MasterKey::from_password("password", b"saltsaltsaltsalt").unwrap();
+        let fixed = EncryptedClientDriver::new(
+            &ColumnEncryptionKey::derive(&master_key, "users.sensitive_columns").unwrap(),
+        );
+
+        let cache = Arc::new(KeyCache::new(master_key, std::time::Duration::from_secs(60)));
+        let cached = EncryptedClientDriver::cached(cache.clone(), "users.sensitive_columns");
+
+        // Same label, same CEK: the two drivers are interchangeable
+        let encrypted = fixed.encrypt("Test data").unwrap();
+        assert_eq!(cached.decrypt(&encrypted).unwrap(), "Test data");
+
+        cache.lock();
+        assert!(matches!(
+            cached.decrypt(&encrypted),
+            Err(CryptoError::KeysLocked)
+        ));
+    }
+
+    #[test]
+    fn test_index_token_scoped_by_column() {
+        let master_key = MasterKey::from_password("password", b"saltsaltsaltsalt").unwrap();
+        let index_key = IndexKey::derive(&master_key, "users.search_index").unwrap();
+
+        // Same input gives the same token, so the database can match on it
+        assert_eq!(
+            index_key.token("email", "exa"),
+            index_key.token("email", "exa")
+        );
+
+        // The same n-gram in another column must not be linkable
+        assert_ne!(
+            index_key.token("email", "exa"),
+            index_key.token("address", "exa")
+        );
+    }
+
+    #[test]
+    fn test_column_suites_and_legacy_values() {
+        let cek = ColumnEncryptionKey::generate();
+        let driver = EncryptedClientDriver::new(&cek)
+            .with_column_suite("ssn", CipherSuite::Aes256GcmSiv)
+            .with_column_suite("address", CipherSuite::XChaCha20Poly1305);
+
+        assert_eq!(driver.suite_for("email"), CipherSuite::Aes256Gcm);
+        for column in ["email", "ssn", "address"] {
+            let encrypted = driver.encrypt_column(column, "Test data").unwrap();
+            assert_eq!(driver.decrypt(&encrypted).unwrap(), "Test data");
+        }
+
+        // Values written before cipher suites: bare base64(nonce || ciphertext)
+        let nonce = [5u8; 12];
+        let mut legacy = nonce.to_vec();
+        legacy.extend(CipherSuite::Aes256Gcm.seal(&cek.key, &nonce, b"Old data").unwrap());
+        assert_eq!(driver.decrypt(&BASE64.encode(legacy)).unwrap(), "Old data");
+    }
+
+    #[test]
+    fn test_previous_key_fallback() {
+        let old = EncryptedClientDriver::new(&ColumnEncryptionKey::generate());
+        let written_before = old.encrypt_column("ssn", "123-45-6789").unwrap();
+
+        let new = EncryptedClientDriver::new(&ColumnEncryptionKey::generate())
+            .with_column_suite("ssn", CipherSuite::Aes256GcmSiv)
+            .with_previous(old);
+        assert_eq!(new.decrypt(&written_before).unwrap(), "123-45-6789");
+        assert!(!new.is_current("ssn", &written_before));
+
+        let rotated = new.encrypt_column("ssn", "123-45-6789").unwrap();
+        assert!(new.is_current("ssn", &rotated));
+        // Right key, but not the column's suite
+        assert!(!new.is_current("email", &rotated));
+    }
+
+    // Property tests of the ciphertext envelope `<tag>:base64(nonce || ciphertext || tag)`
+    mod envelope {
+        use super::*;
+        use proptest::prelude::*;
+
+        fn suite() -> impl Strategy<Value = CipherSuite> {
+            prop::sample::select(CipherSuite::ALL.to_vec())
+        }
+
+        fn driver(key: [u8; 32], suite: CipherSuite) -> EncryptedClientDriver {
+            EncryptedClientDriver::new(&ColumnEncryptionKey { key }).with_suite(suite)
+        }
+
+        proptest! {
+            #![proptest_config(ProptestConfig::with_cases(64))]
+
+            #[test]
+            fn roundtrip(key: [u8; 32], suite in suite(), plaintext in ".*") {
+                let driver = driver(key, suite);
+                let encrypted = driver.encrypt(&plaintext).unwrap();
+                prop_assert_eq!(driver.decrypt(&encrypted).unwrap(), plaintext);
+                prop_assert!(driver.is_current("email", &encrypted));
+            }
+
+            #[test]
+            fn tampering_is_detected(
+                key: [u8; 32],
+                suite in suite(),
+                plaintext in ".{1,64}",
+                position: prop::sample::Index,
+                flip in 1u8..=255,
+            ) {
+                let driver = driver(key, suite);
+                let (_, mut payload) = CipherSuite::decode(&driver.encrypt(&plaintext).unwrap()).unwrap();
+                let i = position.index(payload.len());
+                payload[i] ^= flip;
+                prop_assert!(driver.decrypt(&suite.encode(&payload)).is_err());
+            }
+
+            #[test]
+            fn wrong_key_fails(
+                key: [u8; 32],
+                other: [u8; 32],
+                suite in suite(),
+                plaintext in ".*",
+            ) {
+                prop_assume!(key != other);
+                let encrypted = driver(key, suite).encrypt(&plaintext).unwrap();
+                prop_assert!(driver(other, suite).decrypt(&encrypted).is_err());
+            }
+        }
+    }
 }
@@ -0,0 +1,102 @@
+//! Database Backends - which server the repository talks to
+//!
+//! PostgreSQL is always available. MySQL and MariaDB are behind the `mysql`
+//! feature; the backend is picked from the `DATABASE_URL` scheme
+//! (`postgres://`, `mysql://` or `mariadb://`).
+//!
+//! Encryption happens entirely in the client, so the Always-Encrypted
+//! pattern itself is the same on every backend - only the SQL differs. Some
+//! features are built on PostgreSQL-specific SQL and report `Unsupported`
+//! elsewhere: the blind search index, in-place migration of plaintext
+//! tables, generic encrypted tables and the benchmark.
+
+use sqlx::postgres::PgPoolOptions;
+use sqlx::PgPool;
+use thiserror::Error;
+
+#[cfg(feature = "mysql")]
+use sqlx::mysql::{MySqlPool, MySqlPoolOptions};
+
+/// A feature used on a backend that does not implement it
+#[derive(Error, Debug)]
+#[error("{feature} is not supported on {backend}")]
+pub struct Unsupported {
+    pub feature: &'static str,
+    pub backend: &'static str,
+}
+
+/// Connection pool for one of the supported backends
+#[derive(Debug, Clone)]
+pub enum Db {
+    Postgres(PgPool),
+    #[cfg(feature = "mysql")]
+    MySql(MySqlPool),
+}
+
+impl Db {
+    /// Connect to the backend named by the URL scheme
+    pub async fn connect(url: &str, max_connections: u32) -> Result<Self, sqlx::Error> {
+        let scheme = url.split_once("://").map_or("", |(scheme, _)| scheme);
+        match scheme {
+            "mysql" | "mariadb" => Self::connect_mysql(url, max_connections).await,
+            _ => Ok(Self::Postgres(
+                PgPoolOptions::new()
+                    .max_connections(max_connections)
+                    .connect(url)
+                    .await?,
+            )),
+        }
+    }
+
+    #[cfg(feature = "mysql")]
+    async fn connect_mysql(url: &str, max_connections: u32) -> Result<Self, sqlx::Error> {
+        Ok(Self::MySql(
+            MySqlPoolOptions::new()
+                .max_connections(max_connections)
+                .connect(url)
+                .await?,
+        ))
+    }
+
+    #[cfg(not(feature = "mysql"))]
+    async fn connect_mysql(_url: &str, _max_connections: u32) -> Result<Self, sqlx::Error> {
+        Err(sqlx::Error::Configuration(
+            "MySQL / MariaDB support requires building with `--features mysql`".into(),
+        ))
+    }
+
+    /// Human-readable backend name
+    pub fn backend(&self) -> &'static str {
+        match self {
+            Self::Postgres(_) => "PostgreSQL",
+            #[cfg(feature = "mysql")]
+            Self::MySql(_) => "MySQL",
+        }
+    }
+
+    /// The PostgreSQL pool, for features that exist only there
+    #[cfg_attr(not(feature = "mysql"), allow(unused_variables))]
+    pub fn postgres(&self, feature: &'static str) -> Result<&PgPool, Unsupported> {
+        match self {
+            Self::Postgres(pool) => Ok(pool),
+            #[cfg(feature = "mysql")]
+            Self::MySql(_) => Err(Unsupported {
+                feature,
+                backend: self.backend(),
+            }),
+        }
+    }
+}
+
+impl From<PgPool> for Db {
+    fn from(pool: PgPool) -> Self {
+        Self::Postgres(pool)
+    }
+}
+
+#[cfg(feature = "mysql")]
+impl From<MySqlPool> for Db {
+    fn from(pool: MySqlPool) -> Self {
+        Self::MySql(pool)
+    }
+}
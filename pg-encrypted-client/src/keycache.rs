@@ -0,0 +1,304 @@
+//! Key Cache - derived keys held in memory for a bounded time
+//!
+//! Deriving a CEK runs Argon2, which is deliberately slow, so long-running
+//! processes cache derived keys. Entries expire after a TTL and are
+//! re-derived on next use. `purge` drops every cached CEK (the master key is
+//! kept), while `lock` also wipes the master key so nothing can be decrypted
+//! until `unlock` is called. All key material is zeroized when dropped.
+//!
+//! The cache lock is only held to look up a key, never while one is derived
+//! or used, so encryption and decryption on many tasks run in parallel and a
+//! miss on one label does not hold up hits on the others. Callers missing
+//! the same label wait for one derivation rather than each running Argon2.
+//! Async callers should `prefetch` first, which derives on a blocking thread
+//! rather than stalling the runtime on Argon2.
+
+use crate::crypto::{ColumnEncryptionKey, CryptoError, MasterKey};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+
+/// A label's CEK, empty until the first caller to miss has derived it
+#[derive(Default)]
+struct Slot {
+    /// Shared with callers still using it; wiped when the last one drops it
+    cek: OnceLock<Arc<ColumnEncryptionKey>>,
+    /// Held while deriving, so others missing the same label wait for it
+    deriving: Mutex<()>,
+}
+
+struct CacheEntry {
+    slot: Arc<Slot>,
+    expires_at: Instant,
+}
+
+impl CacheEntry {
+    fn cek(&self) -> Option<&Arc<ColumnEncryptionKey>> {
+        self.slot.cek.get()
+    }
+}
+
+struct CacheState {
+    /// Shared with derivations running outside the lock, so after `lock` it
+    /// is wiped once the last of them finishes
+    master: Option<Arc<MasterKey>>,
+    entries: HashMap<String, CacheEntry>,
+}
+
+/// Snapshot of the cache for status output
+#[derive(Debug, Clone, Copy)]
+pub struct CacheStatus {
+    pub locked: bool,
+    pub cached_keys: usize,
+    pub ttl: Duration,
+}
+
+/// In-memory cache of derived CEKs with TTL and explicit lock/purge
+pub struct KeyCache {
+    ttl: Duration,
+    state: Mutex<CacheState>,
+}
+
+impl KeyCache {
+    pub fn new(master: MasterKey, ttl: Duration) -> Self {
+        Self {
+            ttl,
+            state: Mutex::new(CacheState {
+                master: Some(Arc::new(master)),
+                entries: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Start locked, without a master key (see `unlock`)
+    pub fn locked(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            state: Mutex::new(CacheState {
+                master: None,
+                entries: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Run `f` with the CEK derived under `label`, deriving it on a miss
+    ///
+    /// The cache is unlocked again before `f` runs.
+    pub(crate) fn with_cek<T>(
+        &self,
+        label: &str,
+        f: impl FnOnce(&ColumnEncryptionKey) -> Result<T, CryptoError>,
+    ) -> Result<T, CryptoError> {
+        let cek = self.cek(label)?;
+        f(&cek)
+    }
+
+    /// The CEK derived under `label`, deriving and caching it on a miss
+    fn cek(&self, label: &str) -> Result<Arc<ColumnEncryptionKey>, CryptoError> {
+        let (slot, master) = {
+            let mut state = self.state();
+            let now = Instant::now();
+            state.entries.retain(|_, e| e.expires_at > now);
+
+            if let Some(cek) = state.entries.get(label).and_then(CacheEntry::cek) {
+                return Ok(Arc::clone(cek));
+            }
+
+            let master = Arc::clone(state.master.as_ref().ok_or(CryptoError::KeysLocked)?);
+            let entry = state
+                .entries
+                .entry(label.to_string())
+                .or_insert_with(|| CacheEntry {
+                    slot: Arc::default(),
+                    expires_at: now + self.ttl,
+                });
+            (Arc::clone(&entry.slot), master)
+        };
+
+        // Only callers missing this same label wait here
+        let _deriving = slot.deriving.lock().expect("key cache slot mutex poisoned");
+        if let Some(cek) = slot.cek.get() {
+            return Ok(Arc::clone(cek));
+        }
+        let cek = Arc::new(ColumnEncryptionKey::derive(&master, label)?);
+        tracing::debug!(label, "CEK derived and cached");
+        // Only set while `deriving` is held, so this cannot already be set
+        let _ = slot.cek.set(Arc::clone(&cek));
+        Ok(cek)
+    }
+
+    /// Make sure the CEK for `label` is cached, deriving it on a blocking
+    /// thread on a miss; does nothing while the cache is locked
+    pub async fn prefetch(self: &Arc<Self>, label: &str) -> Result<(), CryptoError> {
+        if self.is_cached(label) || self.is_locked() {
+            return Ok(());
+        }
+        let cache = Arc::clone(self);
+        let label = label.to_string();
+        tokio::task::spawn_blocking(move || cache.cek(&label).map(drop))
+            .await
+            .map_err(|e| CryptoError::KeyDerivationFailed(e.to_string()))?
+    }
+
+    fn is_cached(&self, label: &str) -> bool {
+        let now = Instant::now();
+        self.state()
+            .entries
+            .get(label)
+            .is_some_and(|e| e.expires_at > now && e.cek().is_some())
+    }
+
+    /// Run `f` with the master key, e.g. to derive a non-CEK key
+    pub fn with_master<T>(
+        &self,
+        f: impl FnOnce(&MasterKey) -> Result<T, CryptoError>,
+    ) -> Result<T, CryptoError> {
+        let state = self.state();
+        let master = state.master.as_deref().ok_or(CryptoError::KeysLocked)?;
+        f(master)
+    }
+
+    /// Wipe the master key and all cached CEKs
+    pub fn lock(&self) {
+        let mut state = self.state();
+        state.master = None;
+        state.entries.clear();
+        tracing::info!("Key cache locked");
+    }
+
+    /// Install a master key again after `lock`
+    pub fn unlock(&self, master: MasterKey) {
+        let mut state = self.state();
+        state.master = Some(Arc::new(master));
+        tracing::info!("Key cache unlocked");
+    }
+
+    /// Drop all cached CEKs; returns how many were removed
+    pub fn purge(&self) -> usize {
+        let mut state = self.state();
+        let count = state.entries.len();
+        state.entries.clear();
+        tracing::info!(count, "Key cache purged");
+        count
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.state().master.is_none()
+    }
+
+    pub fn status(&self) -> CacheStatus {
+        let state = self.state();
+        let now = Instant::now();
+        CacheStatus {
+            locked: state.master.is_none(),
+            cached_keys: state
+                .entries
+                .values()
+                .filter(|e| e.expires_at > now && e.cek().is_some())
+                .count(),
+            ttl: self.ttl,
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, CacheState> {
+        self.state.lock().expect("key cache mutex poisoned")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn master() -> MasterKey {
+        MasterKey::from_bytes(&[7u8; 32])
+    }
+
+    #[test]
+    fn test_cache_hit_and_purge() {
+        let cache = KeyCache::new(master(), Duration::from_secs(60));
+        cache
+            .with_cek("users.sensitive_columns", |_| Ok(()))
+            .unwrap();
+        cache
+            .with_cek("users.sensitive_columns", |_| Ok(()))
+            .unwrap();
+        assert_eq!(cache.status().cached_keys, 1);
+
+        assert_eq!(cache.purge(), 1);
+        assert_eq!(cache.status().cached_keys, 0);
+        assert!(!cache.is_locked());
+    }
+
+    #[test]
+    fn test_expired_entries_not_counted() {
+        let cache = KeyCache::new(master(), Duration::ZERO);
+        cache
+            .with_cek("users.sensitive_columns", |_| Ok(()))
+            .unwrap();
+        assert_eq!(cache.status().cached_keys, 0);
+    }
+
+    #[test]
+    fn test_lock_blocks_until_unlock() {
+        let cache = KeyCache::new(master(), Duration::from_secs(60));
+        cache
+            .with_cek("users.sensitive_columns", |_| Ok(()))
+            .unwrap();
+
+        cache.lock();
+        assert!(cache.is_locked());
+        assert!(matches!(
+            cache.with_cek("users.sensitive_columns", |_| Ok(())),
+            Err(CryptoError::KeysLocked)
+        ));
+
+        cache.unlock(master());
+        assert!(cache
+            .with_cek("users.sensitive_columns", |_| Ok(()))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_key_used_without_holding_lock() {
+        let cache = KeyCache::new(master(), Duration::from_secs(60));
+        // Re-entering the cache from inside the closure would deadlock if
+        // the lock were still held
+        cache
+            .with_cek("users.sensitive_columns", |_| {
+                assert_eq!(cache.status().cached_keys, 1);
+                Ok(())
+            })
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_prefetch() {
+        let cache = Arc::new(KeyCache::new(master(), Duration::from_secs(60)));
+        cache.prefetch("users.sensitive_columns").await.unwrap();
+        assert_eq!(cache.status().cached_keys, 1);
+
+        cache.lock();
+        cache.prefetch("users.sensitive_columns").await.unwrap();
+        assert_eq!(cache.status().cached_keys, 0);
+    }
+
+    #[test]
+    fn test_concurrent_misses_derive_once() {
+        let cache = KeyCache::new(master(), Duration::from_secs(60));
+        let ceks: Vec<_> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| scope.spawn(|| cache.cek("users.sensitive_columns").unwrap()))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        assert!(ceks.iter().all(|cek| Arc::ptr_eq(cek, &ceks[0])));
+        assert_eq!(cache.status().cached_keys, 1);
+    }
+
+    #[test]
+    fn test_locked_constructor() {
+        let cache = KeyCache::locked(Duration::from_secs(60));
+        assert!(cache.is_locked());
+        assert!(cache.with_master(|_| Ok(())).is_err());
+    }
+}
@@ -0,0 +1,307 @@
+//! Key File - the master key at rest, wrapped under a passphrase
+//!
+//! Replaces deriving the master key straight from `MASTER_KEY_PASSWORD` and a
+//! fixed `MASTER_KEY_SALT`. The master key is 32 random bytes; the file stores
+//! it encrypted (AES-256-GCM) under a key-encryption key derived from the
+//! passphrase with Argon2id. Salt and Argon2id parameters live in the file
+//! header, so every file gets its own salt and the cost can be raised later
+//! without losing access to older files:
+//!
+//! ```text
+//! {
+//!   "format": "pg-encrypted-client/keyfile", "version": 1,
+//!   "kdf": { "algorithm": "argon2id", "memory_kib": 65536, "iterations": 3,
+//!            "parallelism": 1, "salt": "<base64>" },
+//!   "nonce": "<base64>", "wrapped_key": "<base64>"
+//! }
+//! ```
+//!
+//! Changing the passphrase re-wraps the same master key, so no data has to be
+//! re-encrypted.
+
+use crate::crypto::{CryptoError, MasterKey};
+use aes_gcm::aead::{Aead, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+const FORMAT: &str = "pg-encrypted-client/keyfile";
+const VERSION: u32 = 1;
+const SALT_LEN: usize = 16;
+
+/// Key file errors
+#[derive(Error, Debug)]
+pub enum KeyFileError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Malformed key file: {0}")]
+    Malformed(String),
+
+    #[error("Unsupported key file version {0}")]
+    UnsupportedVersion(u32),
+
+    #[error("Invalid Argon2id parameters: {0}")]
+    InvalidParams(String),
+
+    #[error("Wrong passphrase (or the key file was modified)")]
+    WrongPassphrase,
+
+    #[error("Encryption error: {0}")]
+    Crypto(#[from] CryptoError),
+}
+
+impl From<serde_json::Error> for KeyFileError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Malformed(e.to_string())
+    }
+}
+
+/// Argon2id cost parameters, stored in the key file header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Argon2Params {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for Argon2Params {
+    /// 64 MiB, 3 passes, 1 lane - roughly a quarter second on a laptop
+    fn default() -> Self {
+        Self {
+            memory_kib: 64 * 1024,
+            iterations: 3,
+            parallelism: 1,
+        }
+    }
+}
+
+impl Argon2Params {
+    fn argon2(&self) -> Result<Argon2<'static>, KeyFileError> {
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, Some(32))
+            .map_err(|e| KeyFileError::InvalidParams(e.to_string()))?;
+        Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct KdfHeader {
+    algorithm: String,
+    #[serde(flatten)]
+    params: Argon2Params,
+    salt: String,
+}
+
+/// Passphrase-protected master key, as stored on disk
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyFile {
+    format: String,
+    version: u32,
+    kdf: KdfHeader,
+    nonce: String,
+    wrapped_key: String,
+}
+
+impl KeyFile {
+    /// Wrap `master` under `passphrase` with a fresh random salt
+    pub fn wrap(
+        master: &MasterKey,
+        passphrase: &str,
+        params: Argon2Params,
+    ) -> Result<Self, KeyFileError> {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let mut nonce = [0u8; 12];
+        OsRng.fill_bytes(&mut nonce);
+
+        let mut file = Self {
+            format: FORMAT.to_string(),
+            version: VERSION,
+            kdf: KdfHeader {
+                algorithm: "argon2id".to_string(),
+                params,
+                salt: BASE64.encode(salt),
+            },
+            nonce: BASE64.encode(nonce),
+            wrapped_key: String::new(),
+        };
+
+        let kek = file.key_encryption_key(passphrase)?;
+        let aad = file.associated_data()?;
+        let wrapped = kek
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: master.as_bytes(),
+                    aad: &aad,
+                },
+            )
+            .map_err(|e| CryptoError::EncryptionFailed(e.to_string()))?;
+        file.wrapped_key = BASE64.encode(wrapped);
+        Ok(file)
+    }
+
+    /// Recover the master key with `passphrase`
+    pub fn unwrap_key(&self, passphrase: &str) -> Result<MasterKey, KeyFileError> {
+        self.check_header()?;
+
+        let nonce = decode_field("nonce", &self.nonce)?;
+        if nonce.len() != 12 {
+            return Err(KeyFileError::Malformed("nonce must be 12 bytes".into()));
+        }
+        let wrapped = decode_field("wrapped_key", &self.wrapped_key)?;
+
+        let kek = self.key_encryption_key(passphrase)?;
+        let aad = self.associated_data()?;
+        let bytes = Zeroizing::new(
+            kek.decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &wrapped,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| KeyFileError::WrongPassphrase)?,
+        );
+
+        let bytes: &[u8; 32] = bytes
+            .as_slice()
+            .try_into()
+            .map_err(|_| KeyFileError::Malformed("wrapped key must be 32 bytes".into()))?;
+        Ok(MasterKey::from_bytes(bytes))
+    }
+
+    /// Argon2id parameters the file was written with
+    pub fn params(&self) -> Argon2Params {
+        self.kdf.params
+    }
+
+    pub fn load(path: &Path) -> Result<Self, KeyFileError> {
+        let file: Self = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        file.check_header()?;
+        Ok(file)
+    }
+
+    /// Write the key file, refusing to overwrite an existing one
+    /// On Unix the file is created readable by the owner only
+    pub fn save(&self, path: &Path) -> Result<(), KeyFileError> {
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        let mut out = options.open(path)?;
+        out.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        out.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn check_header(&self) -> Result<(), KeyFileError> {
+        if self.format != FORMAT {
+            return Err(KeyFileError::Malformed(format!(
+                "unexpected format '{}'",
+                self.format
+            )));
+        }
+        if self.version != VERSION {
+            return Err(KeyFileError::UnsupportedVersion(self.version));
+        }
+        if self.kdf.algorithm != "argon2id" {
+            return Err(KeyFileError::InvalidParams(format!(
+                "unsupported KDF '{}'",
+                self.kdf.algorithm
+            )));
+        }
+        Ok(())
+    }
+
+    /// Key-encryption key: Argon2id(passphrase, salt) with the header's parameters
+    fn key_encryption_key(&self, passphrase: &str) -> Result<Aes256Gcm, KeyFileError> {
+        let salt = decode_field("salt", &self.kdf.salt)?;
+        let mut kek = Zeroizing::new([0u8; 32]);
+        self.kdf
+            .params
+            .argon2()?
+            .hash_password_into(passphrase.as_bytes(), &salt, kek.as_mut())
+            .map_err(|e| CryptoError::KeyDerivationFailed(e.to_string()))?;
+        Ok(Aes256Gcm::new(kek.as_ref().into()))
+    }
+
+    /// The header is authenticated, so edited parameters fail like a wrong passphrase
+    fn associated_data(&self) -> Result<Vec<u8>, KeyFileError> {
+        Ok(serde_json::to_vec(&(
+            &self.format,
+            self.version,
+            &self.kdf,
+        ))?)
+    }
+}
+
+fn decode_field(name: &str, value: &str) -> Result<Vec<u8>, KeyFileError> {
+    BASE64
+        .decode(value)
+        .map_err(|e| KeyFileError::Malformed(format!("{name}: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Cheap parameters so the tests run quickly
+    const TEST_PARAMS: Argon2Params = Argon2Params {
+        memory_kib: 1024,
+        iterations: 1,
+        parallelism: 1,
+    };
+
+    #[test]
+    fn test_wrap_unwrap_roundtrip() {
+        let master = MasterKey::generate();
+        let file = KeyFile::wrap(&master, "correct horse", TEST_PARAMS).unwrap();
+
+        let json = serde_json::to_string(&file).unwrap();
+        let loaded: KeyFile = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.params(), TEST_PARAMS);
+
+        let unwrapped = loaded.unwrap_key("correct horse").unwrap();
+        assert_eq!(unwrapped.as_bytes(), master.as_bytes());
+    }
+
+    #[test]
+    fn test_wrong_passphrase_rejected() {
+        let file = KeyFile::wrap(&MasterKey::generate(), "correct horse", TEST_PARAMS).unwrap();
+        assert!(matches!(
+            file.unwrap_key("battery staple"),
+            Err(KeyFileError::WrongPassphrase)
+        ));
+    }
+
+    #[test]
+    fn test_tampered_header_rejected() {
+        let mut file = KeyFile::wrap(&MasterKey::generate(), "correct horse", TEST_PARAMS).unwrap();
+        file.kdf.params.iterations = 2;
+        assert!(file.unwrap_key("correct horse").is_err());
+
+        file.version = 2;
+        assert!(matches!(
+            file.unwrap_key("correct horse"),
+            Err(KeyFileError::UnsupportedVersion(2))
+        ));
+    }
+
+    #[test]
+    fn test_salt_is_random() {
+        let master = MasterKey::generate();
+        let a = KeyFile::wrap(&master, "pw", TEST_PARAMS).unwrap();
+        let b = KeyFile::wrap(&master, "pw", TEST_PARAMS).unwrap();
+        assert_ne!(a.kdf.salt, b.kdf.salt);
+        assert_ne!(a.wrapped_key, b.wrapped_key);
+    }
+}
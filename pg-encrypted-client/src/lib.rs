@@ -0,0 +1,19 @@
+//! PostgreSQL Encrypted Client - library exports
+//!
+//! The CLI in `main.rs` is a thin layer over these modules; they can also be
+//! used directly by other Rust applications.
+
+pub mod bench;
+pub mod cipher;
+pub mod crypto;
+pub mod db;
+pub mod keycache;
+pub mod keyfile;
+pub mod migrate;
+pub mod repository;
+pub mod rotate;
+pub mod schema;
+pub mod search;
+pub mod server;
+pub mod table;
+pub mod transfer;
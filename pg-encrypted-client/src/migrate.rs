@@ -0,0 +1,334 @@
+//! Plaintext → Encrypted migration
+//!
+//! Brings an existing table with plaintext sensitive columns into the
+//! "Always Encrypted" layout used by the repository:
+//!
+//! 1. Add an `encrypted_<column>` TEXT column next to each plaintext column
+//! 2. Backfill in batches, encrypting client-side (resumable: only rows whose
+//!    encrypted column is still NULL are picked up, so an interrupted run can
+//!    simply be restarted)
+//! 3. Verify every encrypted value decrypts back to its plaintext
+//! 4. Optionally drop the plaintext columns
+//!
+//! The table must have an integer `id` primary key.
+
+use crate::crypto::{CryptoError, EncryptedClientDriver};
+use sqlx::{PgPool, Row};
+use thiserror::Error;
+
+/// Migration errors
+#[derive(Error, Debug)]
+pub enum MigrateError {
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+
+    #[error("Encryption error: {0}")]
+    Crypto(#[from] CryptoError),
+
+    #[error("Invalid identifier: {0}")]
+    InvalidIdentifier(String),
+
+    #[error("Verification failed: {0} values do not match their plaintext")]
+    VerificationFailed(u64),
+}
+
+/// Backfill progress, reported after every batch
+#[derive(Debug, Clone, Copy)]
+pub struct MigrationProgress {
+    pub processed: u64,
+    pub total: u64,
+}
+
+impl MigrationProgress {
+    pub fn percent(&self) -> f64 {
+        if self.total == 0 {
+            100.0
+        } else {
+            self.processed as f64 * 100.0 / self.total as f64
+        }
+    }
+}
+
+/// Outcome of a completed migration
+#[derive(Debug)]
+pub struct MigrationReport {
+    pub encrypted_rows: u64,
+    pub verified_values: u64,
+    pub dropped_plaintext: bool,
+}
+
+/// Encrypts the plaintext columns of one table
+pub struct PlaintextMigration<'a> {
+    pool: &'a PgPool,
+    driver: &'a EncryptedClientDriver,
+    table: String,
+    columns: Vec<String>,
+    batch_size: i64,
+}
+
+impl<'a> PlaintextMigration<'a> {
+    pub fn new(
+        pool: &'a PgPool,
+        driver: &'a EncryptedClientDriver,
+        table: &str,
+        columns: &[String],
+        batch_size: i64,
+    ) -> Result<Self, MigrateError> {
+        validate_identifier(table)?;
+        for column in columns {
+            validate_identifier(column)?;
+        }
+        if columns.is_empty() {
+            return Err(MigrateError::InvalidIdentifier("no columns given".into()));
+        }
+
+        Ok(Self {
+            pool,
+            driver,
+            table: table.to_string(),
+            columns: columns.to_vec(),
+            batch_size: batch_size.max(1),
+        })
+    }
+
+    /// Run all steps; the plaintext columns are only dropped if verification passes
+    pub async fn run(
+        &self,
+        drop_plaintext: bool,
+        mut on_progress: impl FnMut(MigrationProgress),
+    ) -> Result<MigrationReport, MigrateError> {
+        self.add_encrypted_columns().await?;
+        let encrypted_rows = self.backfill(&mut on_progress).await?;
+        let verified_values = self.verify().await?;
+
+        if drop_plaintext {
+            self.drop_plaintext_columns().await?;
+        }
+
+        Ok(MigrationReport {
+            encrypted_rows,
+            verified_values,
+            dropped_plaintext: drop_plaintext,
+        })
+    }
+
+    /// Step 1: add `encrypted_<column>` columns (idempotent)
+    pub async fn add_encrypted_columns(&self) -> Result<(), MigrateError> {
+        for column in &self.columns {
+            let sql = format!(
+                r#"ALTER TABLE "{}" ADD COLUMN IF NOT EXISTS "encrypted_{}" TEXT"#,
+                self.table, column
+            );
+            sqlx::query(&sql).execute(self.pool).await?;
+        }
+        tracing::info!(table = %self.table, "Encrypted columns ready");
+        Ok(())
+    }
+
+    /// Step 2: encrypt rows that have not been migrated yet, batch by batch
+    pub async fn backfill(
+        &self,
+        on_progress: &mut impl FnMut(MigrationProgress),
+    ) -> Result<u64, MigrateError> {
+        let pending = self.pending_condition();
+
+        let total: i64 = sqlx::query_scalar(&format!(
+            r#"SELECT COUNT(*) FROM "{}" WHERE {}"#,
+            self.table, pending
+        ))
+        .fetch_one(self.pool)
+        .await?;
+        let total = total as u64;
+
+        let select_cols = self
+            .columns
+            .iter()
+            .map(|c| format!(r#""{c}"::TEXT AS "{c}""#))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let select = format!(
+            r#"SELECT "id"::BIGINT AS id, {} FROM "{}" WHERE "id" > $1 AND ({}) ORDER BY "id" LIMIT $2"#,
+            select_cols, self.table, pending
+        );
+
+        // COALESCE keeps values encrypted by a previous (interrupted) run
+        let assignments = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, c)| format!(r#""encrypted_{c}" = COALESCE("encrypted_{c}", ${})"#, i + 2))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let update = format!(
+            r#"UPDATE "{}" SET {} WHERE "id" = $1"#,
+            self.table, assignments
+        );
+
+        let mut processed = 0u64;
+        let mut last_id = i64::MIN;
+        on_progress(MigrationProgress { processed, total });
+
+        loop {
+            let rows = sqlx::query(&select)
+                .bind(last_id)
+                .bind(self.batch_size)
+                .fetch_all(self.pool)
+                .await?;
+            if rows.is_empty() {
+                break;
+            }
+
+            let mut tx = self.pool.begin().await?;
+            for row in &rows {
+                let id: i64 = row.try_get("id")?;
+                let mut query = sqlx::query(&update).bind(id);
+                for column in &self.columns {
+                    let plaintext: Option<String> = row.try_get(column.as_str())?;
+                    query = query.bind(
+                        self.driver
+                            .encrypt_column_optional(column, plaintext.as_deref())?,
+                    );
+                }
+                query.execute(&mut *tx).await?;
+                last_id = id;
+            }
+            tx.commit().await?;
+
+            processed += rows.len() as u64;
+            on_progress(MigrationProgress { processed, total });
+        }
+
+        Ok(processed)
+    }
+
+    /// Step 3: decrypt every encrypted value and compare with its plaintext
+    pub async fn verify(&self) -> Result<u64, MigrateError> {
+        let mut verified = 0u64;
+        let mut mismatches = 0u64;
+
+        for column in &self.columns {
+            let select = format!(
+                r#"SELECT "id"::BIGINT AS id, "{c}"::TEXT AS plain, "encrypted_{c}" AS enc
+                   FROM "{t}" WHERE "id" > $1 ORDER BY "id" LIMIT $2"#,
+                c = column,
+                t = self.table
+            );
+
+            let mut last_id = i64::MIN;
+            loop {
+                let rows = sqlx::query(&select)
+                    .bind(last_id)
+                    .bind(self.batch_size)
+                    .fetch_all(self.pool)
+                    .await?;
+                if rows.is_empty() {
+                    break;
+                }
+
+                for row in &rows {
+                    last_id = row.try_get("id")?;
+                    let plain: Option<String> = row.try_get("plain")?;
+                    let enc: Option<String> = row.try_get("enc")?;
+                    let decrypted = self.driver.decrypt_optional(enc.as_deref()).ok().flatten();
+                    if decrypted == plain {
+                        verified += 1;
+                    } else {
+                        tracing::warn!(table = %self.table, %column, id = last_id, "Verification mismatch");
+                        mismatches += 1;
+                    }
+                }
+            }
+        }
+
+        if mismatches > 0 {
+            return Err(MigrateError::VerificationFailed(mismatches));
+        }
+        Ok(verified)
+    }
+
+    /// Step 4: drop the plaintext columns in a single transaction
+    pub async fn drop_plaintext_columns(&self) -> Result<(), MigrateError> {
+        let mut tx = self.pool.begin().await?;
+        for column in &self.columns {
+            let sql = format!(r#"ALTER TABLE "{}" DROP COLUMN "{}""#, self.table, column);
+            sqlx::query(&sql).execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+        tracing::info!(table = %self.table, "Plaintext columns dropped");
+        Ok(())
+    }
+
+    /// Rows where some plaintext value has no encrypted counterpart yet
+    fn pending_condition(&self) -> String {
+        self.columns
+            .iter()
+            .map(|c| format!(r#"("{c}" IS NOT NULL AND "encrypted_{c}" IS NULL)"#))
+            .collect::<Vec<_>>()
+            .join(" OR ")
+    }
+}
+
+/// Table and column names are interpolated into SQL, so only allow plain identifiers
+pub(crate) fn is_valid_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && name.len() <= MAX_IDENTIFIER_LEN
+}
+
+/// Postgres truncates identifiers past 63 bytes, so cap names at what still
+/// fits there behind the 10-byte "encrypted_" prefix
+const MAX_IDENTIFIER_LEN: usize = 63 - "encrypted_".len();
+
+fn validate_identifier(name: &str) -> Result<(), MigrateError> {
+    if is_valid_identifier(name) {
+        Ok(())
+    } else {
+        Err(MigrateError::InvalidIdentifier(name.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_identifier() {
+        assert!(validate_identifier("users").is_ok());
+        assert!(validate_identifier("_legacy_ssn2").is_ok());
+
+        assert!(validate_identifier("").is_err());
+        assert!(validate_identifier("2fa").is_err());
+        assert!(validate_identifier("users; DROP TABLE users").is_err());
+        assert!(validate_identifier("email\"").is_err());
+    }
+
+    #[test]
+    fn test_validate_identifier_length() {
+        assert_eq!(MAX_IDENTIFIER_LEN, 53);
+        assert!(validate_identifier(&"a".repeat(53)).is_ok());
+        assert!(validate_identifier(&"a".repeat(54)).is_err());
+        // The encrypted column name of the longest allowed one still fits
+        assert_eq!(format!("encrypted_{}", "a".repeat(53)).len(), 63);
+    }
+
+    #[test]
+    fn test_progress_percent() {
+        assert_eq!(
+            MigrationProgress {
+                processed: 0,
+                total: 0
+            }
+            .percent(),
+            100.0
+        );
+        assert_eq!(
+            MigrationProgress {
+                processed: 25,
+                total: 100
+            }
+            .percent(),
+            25.0
+        );
+    }
+}
@@ -4,10 +4,18 @@
 //! data is encrypted before storage and decrypted after retrieval.
 
 use crate::crypto::{CryptoError, EncryptedClientDriver};
+use crate::db::{Db, Unsupported};
+use crate::schema;
+use crate::search::{SearchIndex, NGRAM_SIZE};
+use futures::stream::BoxStream;
 use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, PgPool};
+use sqlx::{FromRow, PgConnection};
+use std::collections::BTreeMap;
 use thiserror::Error;
 
+/// Sensitive columns of `users`, by logical name (stored as `encrypted_<name>`)
+pub const SENSITIVE_COLUMNS: [&str; 4] = ["email", "ssn", "phone", "address"];
+
 /// Repository errors
 #[derive(Error, Debug)]
 pub enum RepositoryError {
@@ -17,12 +25,39 @@ pub enum RepositoryError {
     #[error("Encryption error: {0}")]
     Crypto(#[from] CryptoError),
 
+    #[error("Migration error: {0}")]
+    Migration(#[from] sqlx::migrate::MigrateError),
+
     #[error("User not found: {0}")]
     NotFound(i32),
+
+    #[error("Searchable encryption is not enabled")]
+    SearchDisabled,
+
+    #[error("Invalid search: {0}")]
+    InvalidSearch(String),
+
+    #[error("Invalid table definition: {0}")]
+    InvalidTable(String),
+
+    #[error("Unknown column: {0}")]
+    UnknownColumn(String),
+
+    #[error("Row not found in {table}: {id}")]
+    RowNotFound { table: String, id: i32 },
+
+    #[error("User {id} was modified concurrently: expected version {expected}, found {actual}")]
+    Conflict { id: i32, expected: i32, actual: i32 },
+
+    #[error("Decryption is disabled for this client (ciphertext-only access)")]
+    DecryptionDisabled,
+
+    #[error("{0}")]
+    Unsupported(#[from] Unsupported),
 }
 
 /// Raw database row - contains encrypted data
-/// This is what's actually stored in PostgreSQL
+/// This is what's actually stored in the database
 #[derive(Debug, FromRow)]
 pub struct UserRow {
     pub id: i32,
@@ -31,6 +66,88 @@ pub struct UserRow {
     pub encrypted_ssn: Option<String>,
     pub encrypted_phone: Option<String>,
     pub encrypted_address: Option<String>,
+    pub version: i32,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl UserRow {
+    /// Stored ciphertext of a sensitive column, by logical column name
+    pub fn encrypted_field(&self, column: &str) -> Option<&str> {
+        match column {
+            "email" => Some(&self.encrypted_email),
+            "ssn" => self.encrypted_ssn.as_deref(),
+            "phone" => self.encrypted_phone.as_deref(),
+            "address" => self.encrypted_address.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+/// Which sensitive columns a read returns, and whether they are decrypted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Projection {
+    columns: Vec<&'static str>,
+    decrypt: bool,
+}
+
+impl Default for Projection {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+impl Projection {
+    /// Every sensitive column, decrypted
+    pub fn all() -> Self {
+        Self {
+            columns: SENSITIVE_COLUMNS.to_vec(),
+            decrypt: true,
+        }
+    }
+
+    /// Only the named sensitive columns; the others are neither read nor decrypted
+    pub fn columns<S: AsRef<str>>(
+        names: impl IntoIterator<Item = S>,
+    ) -> Result<Self, RepositoryError> {
+        let mut columns = Vec::new();
+        for name in names {
+            let name = name.as_ref();
+            let column = SENSITIVE_COLUMNS
+                .into_iter()
+                .find(|c| *c == name)
+                .ok_or_else(|| RepositoryError::UnknownColumn(name.to_string()))?;
+            if !columns.contains(&column) {
+                columns.push(column);
+            }
+        }
+        Ok(Self {
+            columns,
+            decrypt: true,
+        })
+    }
+
+    /// Return the stored ciphertext instead of decrypting
+    pub fn ciphertext(mut self) -> Self {
+        self.decrypt = false;
+        self
+    }
+
+    pub fn decrypts(&self) -> bool {
+        self.decrypt
+    }
+}
+
+/// User read through a `Projection`
+///
+/// `fields` holds only the projected columns: keyed by logical name when
+/// decrypted (`email`), by stored name when ciphertext (`encrypted_email`).
+#[derive(Debug, Clone, Serialize)]
+pub struct UserView {
+    pub id: i32,
+    pub username: String,
+    #[serde(flatten)]
+    pub fields: BTreeMap<String, Option<String>>,
+    pub version: i32,
     pub created_at: chrono::DateTime<chrono::Utc>,
 }
 
@@ -43,9 +160,23 @@ pub struct User {
     pub ssn: Option<String>,
     pub phone: Option<String>,
     pub address: Option<String>,
+    pub version: i32,
     pub created_at: chrono::DateTime<chrono::Utc>,
 }
 
+impl User {
+    /// Decrypted value of a sensitive column, by column name
+    pub fn sensitive_field(&self, column: &str) -> Option<&str> {
+        match column {
+            "email" => Some(&self.email),
+            "ssn" => self.ssn.as_deref(),
+            "phone" => self.phone.as_deref(),
+            "address" => self.address.as_deref(),
+            _ => None,
+        }
+    }
+}
+
 /// Input for creating a new user
 #[derive(Debug, Deserialize)]
 pub struct CreateUserInput {
@@ -56,6 +187,23 @@ pub struct CreateUserInput {
     pub address: Option<String>,
 }
 
+/// Input for restoring a user from already-encrypted values (e.g. a ciphertext export)
+#[derive(Debug, Deserialize)]
+pub struct EncryptedUserInput {
+    pub username: String,
+    pub encrypted_email: String,
+    pub encrypted_ssn: Option<String>,
+    pub encrypted_phone: Option<String>,
+    pub encrypted_address: Option<String>,
+}
+
+/// A user for `create_batch`, in either layout
+#[derive(Debug)]
+pub enum NewUser {
+    Plain(CreateUserInput),
+    Encrypted(EncryptedUserInput),
+}
+
 /// Input for updating user
 #[derive(Debug, Deserialize)]
 pub struct UpdateUserInput {
@@ -63,6 +211,29 @@ pub struct UpdateUserInput {
     pub ssn: Option<String>,
     pub phone: Option<String>,
     pub address: Option<String>,
+    /// Version the client last read; the update fails with `Conflict` if the
+    /// row has changed since. `None` only guards against races inside `update`.
+    #[serde(default)]
+    pub version: Option<i32>,
+}
+
+/// `SELECT` of every `UserRow` column, followed by the given clause
+macro_rules! select_users {
+    ($clause:literal) => {
+        concat!(
+            "SELECT id, username, encrypted_email, encrypted_ssn, encrypted_phone, ",
+            "encrypted_address, version, created_at FROM users ",
+            $clause
+        )
+    };
+}
+
+/// Ciphertext of the sensitive columns, ready to be written
+struct EncryptedColumns {
+    email: String,
+    ssn: Option<String>,
+    phone: Option<String>,
+    address: Option<String>,
 }
 
 /// User Repository - handles all database operations with encryption
@@ -71,105 +242,167 @@ pub struct UpdateUserInput {
 /// - Client encrypts data BEFORE sending to database
 /// - Database only ever sees ciphertext
 /// - Client decrypts data AFTER receiving from database
+///
+/// Works against any backend in `Db`; the blind search index needs PostgreSQL.
 pub struct UserRepository {
-    pool: PgPool,
+    db: Db,
     driver: EncryptedClientDriver,
+    search: Option<SearchIndex>,
+    ciphertext_only: bool,
 }
 
 impl UserRepository {
-    pub fn new(pool: PgPool, driver: EncryptedClientDriver) -> Self {
-        Self { pool, driver }
+    pub fn new(db: impl Into<Db>, driver: EncryptedClientDriver) -> Self {
+        Self {
+            db: db.into(),
+            driver,
+            search: None,
+            ciphertext_only: false,
+        }
     }
 
-    /// Initialize the database schema
-    pub async fn initialize(&self) -> Result<(), RepositoryError> {
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS users (
-                id SERIAL PRIMARY KEY,
-                username VARCHAR(255) NOT NULL UNIQUE,
-                encrypted_email TEXT NOT NULL,
-                encrypted_ssn TEXT,
-                encrypted_phone TEXT,
-                encrypted_address TEXT,
-                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
-            )
-            "#,
-        )
-        .execute(&self.pool)
-        .await?;
+    /// Never decrypt: projections return ciphertext, and every operation
+    /// that would return decrypted data fails with `DecryptionDisabled`
+    /// For operators who may inspect rows but must not see plaintext.
+    pub fn ciphertext_only(mut self) -> Self {
+        self.ciphertext_only = true;
+        self
+    }
 
-        // Create index on username (the only searchable field)
-        sqlx::query(
-            r#"
-            CREATE INDEX IF NOT EXISTS idx_users_username ON users(username)
-            "#,
-        )
-        .execute(&self.pool)
-        .await?;
+    pub fn is_ciphertext_only(&self) -> bool {
+        self.ciphertext_only
+    }
 
-        tracing::info!("Database schema initialized");
+    /// Enable searchable encryption, maintaining a blind index on writes
+    pub fn with_search_index(mut self, index: SearchIndex) -> Self {
+        self.search = Some(index);
+        self
+    }
+
+    /// Initialize the database schema by applying pending migrations
+    pub async fn initialize(&self) -> Result<(), RepositoryError> {
+        schema::run(&self.db).await?;
+        tracing::info!(backend = self.db.backend(), "Database schema initialized");
         Ok(())
     }
 
     /// Create a new user - encrypts all sensitive fields client-side
     pub async fn create(&self, input: CreateUserInput) -> Result<User, RepositoryError> {
+        self.driver.ready().await?;
         tracing::debug!("Creating user: {}", input.username);
 
-        // Encrypt all sensitive fields BEFORE sending to database
-        let encrypted_email = self.driver.encrypt(&input.email)?;
-        let encrypted_ssn = self.driver.encrypt_optional(input.ssn.as_deref())?;
-        let encrypted_phone = self.driver.encrypt_optional(input.phone.as_deref())?;
-        let encrypted_address = self.driver.encrypt_optional(input.address.as_deref())?;
+        let columns = self.encrypt_input(&input)?;
+        self.insert(&input.username, &columns).await
+    }
 
-        let row: UserRow = sqlx::query_as(
-            r#"
-            INSERT INTO users (username, encrypted_email, encrypted_ssn, encrypted_phone, encrypted_address)
-            VALUES ($1, $2, $3, $4, $5)
-            RETURNING id, username, encrypted_email, encrypted_ssn, encrypted_phone, encrypted_address, created_at
-            "#,
-        )
-        .bind(&input.username)
-        .bind(&encrypted_email)
-        .bind(&encrypted_ssn)
-        .bind(&encrypted_phone)
-        .bind(&encrypted_address)
-        .fetch_one(&self.pool)
-        .await?;
+    /// Create a user from ciphertext produced by this key (restore from export)
+    ///
+    /// Every value is decrypted first, so ciphertext from another key (or a
+    /// tampered file) is rejected instead of being stored unreadable.
+    pub async fn create_encrypted(&self, input: EncryptedUserInput) -> Result<User, RepositoryError> {
+        self.driver.ready().await?;
+        let (username, columns) = self.verify_input(input)?;
+        self.insert(&username, &columns).await
+    }
 
-        // Decrypt for return
-        self.decrypt_row(row)
+    /// Create `users` in one transaction: either all of them or none
+    ///
+    /// Each user is encrypted, or verified like `create_encrypted`, before
+    /// anything is written. Returns how many were created.
+    pub async fn create_batch(&self, users: Vec<NewUser>) -> Result<usize, RepositoryError> {
+        self.driver.ready().await?;
+        let rows = users
+            .into_iter()
+            .map(|user| match user {
+                NewUser::Plain(input) => {
+                    let columns = self.encrypt_input(&input)?;
+                    Ok((input.username, columns))
+                }
+                NewUser::Encrypted(input) => self.verify_input(input),
+            })
+            .collect::<Result<Vec<_>, RepositoryError>>()?;
+
+        match &self.db {
+            Db::Postgres(pool) => {
+                let mut tx = pool.begin().await?;
+                for (username, columns) in &rows {
+                    self.insert_postgres(&mut tx, username, columns).await?;
+                }
+                tx.commit().await?;
+            }
+            #[cfg(feature = "mysql")]
+            Db::MySql(pool) => {
+                let mut tx = pool.begin().await?;
+                for (username, columns) in &rows {
+                    self.insert_mysql(&mut tx, username, columns).await?;
+                }
+                tx.commit().await?;
+            }
+        }
+        Ok(rows.len())
+    }
+
+    /// Encrypt all sensitive fields BEFORE sending to database
+    fn encrypt_input(&self, input: &CreateUserInput) -> Result<EncryptedColumns, RepositoryError> {
+        Ok(EncryptedColumns {
+            email: self.driver.encrypt_column("email", &input.email)?,
+            ssn: self
+                .driver
+                .encrypt_column_optional("ssn", input.ssn.as_deref())?,
+            phone: self
+                .driver
+                .encrypt_column_optional("phone", input.phone.as_deref())?,
+            address: self
+                .driver
+                .encrypt_column_optional("address", input.address.as_deref())?,
+        })
+    }
+
+    /// Check that `input` decrypts under this key, returning its username and
+    /// ciphertext
+    fn verify_input(
+        &self,
+        input: EncryptedUserInput,
+    ) -> Result<(String, EncryptedColumns), RepositoryError> {
+        self.driver.decrypt(&input.encrypted_email)?;
+        self.driver.decrypt_optional(input.encrypted_ssn.as_deref())?;
+        self.driver.decrypt_optional(input.encrypted_phone.as_deref())?;
+        self.driver.decrypt_optional(input.encrypted_address.as_deref())?;
+
+        let columns = EncryptedColumns {
+            email: input.encrypted_email,
+            ssn: input.encrypted_ssn,
+            phone: input.encrypted_phone,
+            address: input.encrypted_address,
+        };
+        Ok((input.username, columns))
     }
 
     /// Get user by ID - decrypts all sensitive fields client-side
     pub async fn get_by_id(&self, id: i32) -> Result<User, RepositoryError> {
-        let row: UserRow = sqlx::query_as(
-            r#"
-            SELECT id, username, encrypted_email, encrypted_ssn, encrypted_phone, encrypted_address, created_at
-            FROM users
-            WHERE id = $1
-            "#,
-        )
-        .bind(id)
-        .fetch_optional(&self.pool)
-        .await?
-        .ok_or(RepositoryError::NotFound(id))?;
-
+        self.driver.ready().await?;
+        let row = self.get_raw_encrypted(id).await?;
         self.decrypt_row(row)
     }
 
     /// Get user by username
     pub async fn get_by_username(&self, username: &str) -> Result<Option<User>, RepositoryError> {
-        let row: Option<UserRow> = sqlx::query_as(
-            r#"
-            SELECT id, username, encrypted_email, encrypted_ssn, encrypted_phone, encrypted_address, created_at
-            FROM users
-            WHERE username = $1
-            "#,
-        )
-        .bind(username)
-        .fetch_optional(&self.pool)
-        .await?;
+        self.driver.ready().await?;
+        let row: Option<UserRow> = match &self.db {
+            Db::Postgres(pool) => {
+                sqlx::query_as(select_users!("WHERE username = $1"))
+                    .bind(username)
+                    .fetch_optional(pool)
+                    .await?
+            }
+            #[cfg(feature = "mysql")]
+            Db::MySql(pool) => {
+                sqlx::query_as(select_users!("WHERE username = ?"))
+                    .bind(username)
+                    .fetch_optional(pool)
+                    .await?
+            }
+        };
 
         match row {
             Some(r) => Ok(Some(self.decrypt_row(r)?)),
@@ -179,79 +412,272 @@ impl UserRepository {
 
     /// List all users (with decryption)
     pub async fn list_all(&self) -> Result<Vec<User>, RepositoryError> {
-        let rows: Vec<UserRow> = sqlx::query_as(
-            r#"
-            SELECT id, username, encrypted_email, encrypted_ssn, encrypted_phone, encrypted_address, created_at
-            FROM users
-            ORDER BY created_at DESC
-            "#,
-        )
-        .fetch_all(&self.pool)
-        .await?;
-
+        self.driver.ready().await?;
+        let rows = self.fetch_all_rows().await?;
         rows.into_iter().map(|r| self.decrypt_row(r)).collect()
     }
 
+    /// Get user by ID, decrypting only the projected columns
+    pub async fn get_by_id_with(
+        &self,
+        id: i32,
+        projection: &Projection,
+    ) -> Result<UserView, RepositoryError> {
+        self.driver.ready().await?;
+        let row = self.get_raw_encrypted(id).await?;
+        self.project_row(row, projection)
+    }
+
+    /// List all users, decrypting only the projected columns
+    pub async fn list_all_with(
+        &self,
+        projection: &Projection,
+    ) -> Result<Vec<UserView>, RepositoryError> {
+        self.driver.ready().await?;
+        let rows = self.fetch_all_rows().await?;
+        rows.into_iter()
+            .map(|r| self.project_row(r, projection))
+            .collect()
+    }
+
     /// Update user - re-encrypts any changed sensitive fields
+    ///
+    /// Compare-and-swap on `version`: the write only applies if the row is
+    /// still at the version read (or the one given in `input.version`), and
+    /// bumps it. Otherwise nothing is written and `Conflict` is returned.
     pub async fn update(&self, id: i32, input: UpdateUserInput) -> Result<User, RepositoryError> {
+        self.driver.ready().await?;
         // First get current row
-        let current = self.get_by_id(id).await?;
+        let row = self.get_raw_encrypted(id).await?;
+
+        let expected = input.version.unwrap_or(row.version);
+        if expected != row.version {
+            return Err(RepositoryError::Conflict {
+                id,
+                expected,
+                actual: row.version,
+            });
+        }
+        let current = self.decrypt_row(row)?;
 
         // Determine new values, encrypting as needed
         let new_email = match input.email {
-            Some(email) => self.driver.encrypt(&email)?,
-            None => self.driver.encrypt(&current.email)?,
+            Some(email) => self.driver.encrypt_column("email", &email)?,
+            None => self.driver.encrypt_column("email", &current.email)?,
         };
 
         let new_ssn = match input.ssn {
-            Some(ssn) => self.driver.encrypt_optional(Some(&ssn))?,
-            None => self.driver.encrypt_optional(current.ssn.as_deref())?,
+            Some(ssn) => self.driver.encrypt_column_optional("ssn", Some(&ssn))?,
+            None => self.driver.encrypt_column_optional("ssn", current.ssn.as_deref())?,
         };
 
         let new_phone = match input.phone {
-            Some(phone) => self.driver.encrypt_optional(Some(&phone))?,
-            None => self.driver.encrypt_optional(current.phone.as_deref())?,
+            Some(phone) => self.driver.encrypt_column_optional("phone", Some(&phone))?,
+            None => self.driver.encrypt_column_optional("phone", current.phone.as_deref())?,
         };
 
         let new_address = match input.address {
-            Some(address) => self.driver.encrypt_optional(Some(&address))?,
-            None => self.driver.encrypt_optional(current.address.as_deref())?,
+            Some(address) => self.driver.encrypt_column_optional("address", Some(&address))?,
+            None => self
+                .driver
+                .encrypt_column_optional("address", current.address.as_deref())?,
         };
 
-        let row: UserRow = sqlx::query_as(
-            r#"
-            UPDATE users
-            SET encrypted_email = $2,
-                encrypted_ssn = $3,
-                encrypted_phone = $4,
-                encrypted_address = $5
-            WHERE id = $1
-            RETURNING id, username, encrypted_email, encrypted_ssn, encrypted_phone, encrypted_address, created_at
-            "#,
-        )
-        .bind(id)
-        .bind(&new_email)
-        .bind(&new_ssn)
-        .bind(&new_phone)
-        .bind(&new_address)
-        .fetch_one(&self.pool)
-        .await?;
+        match &self.db {
+            Db::Postgres(pool) => {
+                let mut tx = pool.begin().await?;
+                let row: Option<UserRow> = sqlx::query_as(
+                    r#"
+                    UPDATE users
+                    SET encrypted_email = $2,
+                        encrypted_ssn = $3,
+                        encrypted_phone = $4,
+                        encrypted_address = $5,
+                        version = version + 1
+                    WHERE id = $1 AND version = $6
+                    RETURNING id, username, encrypted_email, encrypted_ssn, encrypted_phone, encrypted_address, version, created_at
+                    "#,
+                )
+                .bind(id)
+                .bind(&new_email)
+                .bind(&new_ssn)
+                .bind(&new_phone)
+                .bind(&new_address)
+                .bind(expected)
+                .fetch_optional(&mut *tx)
+                .await?;
+
+                // Another client committed (or deleted the row) between our read and write
+                let Some(row) = row else {
+                    return Err(self.conflict(id, expected).await);
+                };
+
+                let user = self.decrypt_row(row)?;
+                self.index_user(&mut tx, &user).await?;
+                tx.commit().await?;
+                Ok(user)
+            }
+            #[cfg(feature = "mysql")]
+            Db::MySql(pool) => {
+                let mut tx = pool.begin().await?;
+                let result = sqlx::query(
+                    r#"
+                    UPDATE users
+                    SET encrypted_email = ?,
+                        encrypted_ssn = ?,
+                        encrypted_phone = ?,
+                        encrypted_address = ?,
+                        version = version + 1
+                    WHERE id = ? AND version = ?
+                    "#,
+                )
+                .bind(&new_email)
+                .bind(&new_ssn)
+                .bind(&new_phone)
+                .bind(&new_address)
+                .bind(id)
+                .bind(expected)
+                .execute(&mut *tx)
+                .await?;
+
+                // `version` always changes, so zero affected rows means no match
+                if result.rows_affected() == 0 {
+                    return Err(self.conflict(id, expected).await);
+                }
+
+                // No RETURNING on MySQL: read the new row inside the transaction
+                let row: UserRow = sqlx::query_as(select_users!("WHERE id = ?"))
+                    .bind(id)
+                    .fetch_one(&mut *tx)
+                    .await?;
+                let user = self.decrypt_row(row)?;
+                tx.commit().await?;
+                Ok(user)
+            }
+        }
+    }
 
-        self.decrypt_row(row)
+    /// Find users whose `column` contains `query` (case-insensitive)
+    ///
+    /// Candidates come from the blind index; they are decrypted and checked
+    /// client-side so the result has no false positives.
+    pub async fn search(&self, column: &str, query: &str) -> Result<Vec<User>, RepositoryError> {
+        self.driver.ready().await?;
+        let index = self
+            .search
+            .as_ref()
+            .ok_or(RepositoryError::SearchDisabled)?;
+        let pool = self.db.postgres("encrypted search")?;
+
+        if !index.is_indexed(column) {
+            return Err(RepositoryError::InvalidSearch(format!(
+                "column '{column}' is not indexed"
+            )));
+        }
+        if query.chars().count() < NGRAM_SIZE {
+            return Err(RepositoryError::InvalidSearch(format!(
+                "query must be at least {NGRAM_SIZE} characters"
+            )));
+        }
+
+        let ids = index.candidates(pool, column, query).await?;
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let rows: Vec<UserRow> =
+            sqlx::query_as(select_users!("WHERE id = ANY($1) ORDER BY created_at DESC"))
+                .bind(&ids)
+                .fetch_all(pool)
+                .await?;
+
+        let needle = query.to_lowercase();
+        let mut matches = Vec::new();
+        for row in rows {
+            let user = self.decrypt_row(row)?;
+            let hit = user
+                .sensitive_field(column)
+                .is_some_and(|v| v.to_lowercase().contains(&needle));
+            if hit {
+                matches.push(user);
+            }
+        }
+
+        tracing::debug!(
+            column,
+            candidates = ids.len(),
+            matches = matches.len(),
+            "Encrypted search"
+        );
+        Ok(matches)
+    }
+
+    /// Rebuild the blind index from existing rows
+    /// Needed for rows written before searchable encryption was enabled
+    pub async fn rebuild_search_index(&self) -> Result<usize, RepositoryError> {
+        if self.search.is_none() {
+            return Err(RepositoryError::SearchDisabled);
+        }
+        let pool = self.db.postgres("encrypted search")?;
+
+        let users = self.list_all().await?;
+        let mut tx = pool.begin().await?;
+        for user in &users {
+            self.index_user(&mut tx, user).await?;
+        }
+        tx.commit().await?;
+        Ok(users.len())
+    }
+
+    /// Helper to refresh the blind index entries for a user
+    async fn index_user(
+        &self,
+        conn: &mut PgConnection,
+        user: &User,
+    ) -> Result<(), RepositoryError> {
+        if let Some(index) = &self.search {
+            for column in index.columns() {
+                index
+                    .index_value(conn, user.id, column, user.sensitive_field(column))
+                    .await?;
+            }
+        }
+        Ok(())
     }
 
     /// Delete user by ID
     pub async fn delete(&self, id: i32) -> Result<bool, RepositoryError> {
-        let result = sqlx::query("DELETE FROM users WHERE id = $1")
-            .bind(id)
-            .execute(&self.pool)
-            .await?;
+        let rows_affected = match &self.db {
+            Db::Postgres(pool) => sqlx::query("DELETE FROM users WHERE id = $1")
+                .bind(id)
+                .execute(pool)
+                .await?
+                .rows_affected(),
+            #[cfg(feature = "mysql")]
+            Db::MySql(pool) => sqlx::query("DELETE FROM users WHERE id = ?")
+                .bind(id)
+                .execute(pool)
+                .await?
+                .rows_affected(),
+        };
+
+        Ok(rows_affected > 0)
+    }
 
-        Ok(result.rows_affected() > 0)
+    /// Stream raw encrypted rows in ID order without loading the table into memory
+    pub fn stream_raw_encrypted(&self) -> BoxStream<'_, Result<UserRow, sqlx::Error>> {
+        match &self.db {
+            Db::Postgres(pool) => sqlx::query_as(select_users!("ORDER BY id")).fetch(pool),
+            #[cfg(feature = "mysql")]
+            Db::MySql(pool) => sqlx::query_as(select_users!("ORDER BY id")).fetch(pool),
+        }
     }
 
-    /// Helper to decrypt a row
-    fn decrypt_row(&self, row: UserRow) -> Result<User, RepositoryError> {
+    /// Decrypt a raw row
+    pub fn decrypt_row(&self, row: UserRow) -> Result<User, RepositoryError> {
+        if self.ciphertext_only {
+            return Err(RepositoryError::DecryptionDisabled);
+        }
         Ok(User {
             id: row.id,
             username: row.username,
@@ -259,22 +685,179 @@ impl UserRepository {
             ssn: self.driver.decrypt_optional(row.encrypted_ssn.as_deref())?,
             phone: self.driver.decrypt_optional(row.encrypted_phone.as_deref())?,
             address: self.driver.decrypt_optional(row.encrypted_address.as_deref())?,
+            version: row.version,
+            created_at: row.created_at,
+        })
+    }
+
+    /// Apply a projection to a raw row
+    /// A ciphertext-only repository downgrades every projection to ciphertext
+    fn project_row(
+        &self,
+        row: UserRow,
+        projection: &Projection,
+    ) -> Result<UserView, RepositoryError> {
+        let decrypt = projection.decrypt && !self.ciphertext_only;
+
+        let mut fields = BTreeMap::new();
+        for column in &projection.columns {
+            let stored = row.encrypted_field(column);
+            if decrypt {
+                fields.insert(column.to_string(), self.driver.decrypt_optional(stored)?);
+            } else {
+                fields.insert(format!("encrypted_{column}"), stored.map(str::to_string));
+            }
+        }
+
+        Ok(UserView {
+            id: row.id,
+            username: row.username,
+            fields,
+            version: row.version,
             created_at: row.created_at,
         })
     }
 
     /// Get raw encrypted data (for demonstration/debugging)
     pub async fn get_raw_encrypted(&self, id: i32) -> Result<UserRow, RepositoryError> {
-        sqlx::query_as(
+        let row: Option<UserRow> = match &self.db {
+            Db::Postgres(pool) => {
+                sqlx::query_as(select_users!("WHERE id = $1"))
+                    .bind(id)
+                    .fetch_optional(pool)
+                    .await?
+            }
+            #[cfg(feature = "mysql")]
+            Db::MySql(pool) => {
+                sqlx::query_as(select_users!("WHERE id = ?"))
+                    .bind(id)
+                    .fetch_optional(pool)
+                    .await?
+            }
+        };
+        row.ok_or(RepositoryError::NotFound(id))
+    }
+
+    /// Insert a row of already-encrypted columns and return it decrypted
+    async fn insert(
+        &self,
+        username: &str,
+        columns: &EncryptedColumns,
+    ) -> Result<User, RepositoryError> {
+        match &self.db {
+            Db::Postgres(pool) => {
+                // Row and blind index entries are written together or not at all
+                let mut tx = pool.begin().await?;
+                let user = self.insert_postgres(&mut tx, username, columns).await?;
+                tx.commit().await?;
+                Ok(user)
+            }
+            #[cfg(feature = "mysql")]
+            Db::MySql(pool) => {
+                let mut tx = pool.begin().await?;
+                let user = self.insert_mysql(&mut tx, username, columns).await?;
+                tx.commit().await?;
+                Ok(user)
+            }
+        }
+    }
+
+    /// Insert a row and its blind index entries on `conn`
+    async fn insert_postgres(
+        &self,
+        conn: &mut PgConnection,
+        username: &str,
+        columns: &EncryptedColumns,
+    ) -> Result<User, RepositoryError> {
+        let row: UserRow = sqlx::query_as(
+            r#"
+            INSERT INTO users (username, encrypted_email, encrypted_ssn, encrypted_phone, encrypted_address)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, username, encrypted_email, encrypted_ssn, encrypted_phone, encrypted_address, version, created_at
+            "#,
+        )
+        .bind(username)
+        .bind(&columns.email)
+        .bind(&columns.ssn)
+        .bind(&columns.phone)
+        .bind(&columns.address)
+        .fetch_one(&mut *conn)
+        .await?;
+
+        // Decrypt for return
+        let user = self.decrypt_row(row)?;
+        self.index_user(conn, &user).await?;
+        Ok(user)
+    }
+
+    /// Insert a row on `conn` (no blind index outside PostgreSQL)
+    #[cfg(feature = "mysql")]
+    async fn insert_mysql(
+        &self,
+        conn: &mut sqlx::MySqlConnection,
+        username: &str,
+        columns: &EncryptedColumns,
+    ) -> Result<User, RepositoryError> {
+        let id = sqlx::query(
             r#"
-            SELECT id, username, encrypted_email, encrypted_ssn, encrypted_phone, encrypted_address, created_at
-            FROM users
-            WHERE id = $1
+            INSERT INTO users (username, encrypted_email, encrypted_ssn, encrypted_phone, encrypted_address)
+            VALUES (?, ?, ?, ?, ?)
             "#,
         )
-        .bind(id)
-        .fetch_optional(&self.pool)
+        .bind(username)
+        .bind(&columns.email)
+        .bind(&columns.ssn)
+        .bind(&columns.phone)
+        .bind(&columns.address)
+        .execute(&mut *conn)
         .await?
-        .ok_or(RepositoryError::NotFound(id))
+        .last_insert_id();
+
+        // No RETURNING on MySQL: read the row back by its generated id
+        let row: UserRow = sqlx::query_as(select_users!("WHERE id = ?"))
+            .bind(id)
+            .fetch_one(&mut *conn)
+            .await?;
+        self.decrypt_row(row)
+    }
+
+    /// Every row, newest first
+    async fn fetch_all_rows(&self) -> Result<Vec<UserRow>, RepositoryError> {
+        let query = select_users!("ORDER BY created_at DESC");
+        Ok(match &self.db {
+            Db::Postgres(pool) => sqlx::query_as(query).fetch_all(pool).await?,
+            #[cfg(feature = "mysql")]
+            Db::MySql(pool) => sqlx::query_as(query).fetch_all(pool).await?,
+        })
+    }
+
+    /// Why a compare-and-swap on `version` matched no row
+    async fn conflict(&self, id: i32, expected: i32) -> RepositoryError {
+        match self.get_raw_encrypted(id).await {
+            Ok(row) => RepositoryError::Conflict {
+                id,
+                expected,
+                actual: row.version,
+            },
+            Err(e) => e,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_projection_columns() {
+        let projection = Projection::columns(["phone", "email", "phone"]).unwrap();
+        assert_eq!(projection.columns, ["phone", "email"]);
+        assert!(projection.decrypts());
+        assert!(!projection.ciphertext().decrypts());
+
+        assert!(matches!(
+            Projection::columns(["username"]),
+            Err(RepositoryError::UnknownColumn(_))
+        ));
     }
 }
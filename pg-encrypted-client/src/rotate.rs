@@ -0,0 +1,376 @@
+//! Key Rotation Worker - online re-encryption of `users`, row by row
+//!
+//! Rotation runs while clients keep working:
+//!
+//! 1. Clients switch to the new key (`MASTER_KEY_FILE`) and keep the old one
+//!    as `PREVIOUS_MASTER_KEY_FILE`: writes use the new key, reads of rows
+//!    not yet rotated fall back to the old one (`with_previous`)
+//! 2. This worker walks `users` in `id` order and re-encrypts every value
+//!    that is not yet under the current key and column cipher suite, at most
+//!    `rows_per_sec` rows per second
+//! 3. Once it has finished, the previous key can be retired
+//!
+//! Without a previous key the same worker moves existing values to new
+//! cipher suites (`CIPHER_SUITE`, `COLUMN_CIPHER_SUITES`).
+//!
+//! Progress lives in the `key_rotations` table. Its `watermark` is the highest
+//! `id` processed, so a stopped worker resumes where it left off, and
+//! `rows_done` / `rows_total` can be watched from any session. Rows are
+//! written with the same compare-and-swap on `version` as
+//! `UserRepository::update`, so a concurrent client write is never lost: the
+//! row is re-read and retried. Re-processing a row is harmless, since values
+//! already under the current key are skipped.
+
+use crate::crypto::{CryptoError, EncryptedClientDriver};
+use crate::repository::{UserRow, SENSITIVE_COLUMNS};
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::time::{self, Instant};
+
+/// Attempts per row before giving up on a row that keeps changing
+const MAX_ATTEMPTS: usize = 5;
+
+/// Rotation errors
+#[derive(Error, Debug)]
+pub enum RotationError {
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+
+    #[error("Encryption error: {0}")]
+    Crypto(#[from] CryptoError),
+
+    #[error("Invalid rotation configuration: {0}")]
+    InvalidConfig(String),
+
+    #[error("User {0} kept changing during rotation; try again later")]
+    Contended(i32),
+}
+
+/// How a rotation runs
+#[derive(Debug, Clone)]
+pub struct RotationConfig {
+    /// Identifies the run in `key_rotations`; reusing a name resumes it
+    pub name: String,
+    /// Upper bound on rows processed per second (0 = unlimited)
+    pub rows_per_sec: u32,
+    pub batch_size: i64,
+    /// Start again from the first row, even if the run finished
+    pub restart: bool,
+}
+
+impl RotationConfig {
+    fn validate(&self) -> Result<(), RotationError> {
+        if self.name.trim().is_empty() {
+            return Err(RotationError::InvalidConfig(
+                "rotation name must not be empty".into(),
+            ));
+        }
+        if self.batch_size < 1 {
+            return Err(RotationError::InvalidConfig(
+                "batch size must be at least 1".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Rows fetched per batch: never more than one second's worth
+    fn effective_batch_size(&self) -> i64 {
+        match self.rows_per_sec {
+            0 => self.batch_size,
+            rate => self.batch_size.min(rate as i64),
+        }
+    }
+}
+
+/// One row of `key_rotations`
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct RotationProgress {
+    pub name: String,
+    pub watermark: i32,
+    pub rows_done: i64,
+    /// Rows that actually needed re-encrypting
+    pub rows_rewritten: i64,
+    pub rows_total: i64,
+    pub rows_per_sec: i32,
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+    pub finished_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl RotationProgress {
+    pub fn percent(&self) -> f64 {
+        if self.rows_total == 0 {
+            100.0
+        } else {
+            (self.rows_done as f64 * 100.0 / self.rows_total as f64).min(100.0)
+        }
+    }
+}
+
+/// Re-encrypts `users` under the driver's current key and cipher suites
+pub struct RotationWorker<'a> {
+    pool: &'a PgPool,
+    driver: &'a EncryptedClientDriver,
+    config: RotationConfig,
+}
+
+impl<'a> RotationWorker<'a> {
+    pub fn new(
+        pool: &'a PgPool,
+        driver: &'a EncryptedClientDriver,
+        config: RotationConfig,
+    ) -> Result<Self, RotationError> {
+        config.validate()?;
+        Ok(Self {
+            pool,
+            driver,
+            config,
+        })
+    }
+
+    /// Run (or resume) the rotation until every row has been processed
+    /// `on_progress` sees the recorded progress after every batch.
+    pub async fn run(
+        &self,
+        mut on_progress: impl FnMut(&RotationProgress),
+    ) -> Result<RotationProgress, RotationError> {
+        let mut progress = self.start().await?;
+        if progress.finished_at.is_some() {
+            return Ok(progress);
+        }
+        on_progress(&progress);
+
+        let batch_size = self.config.effective_batch_size();
+        let started = Instant::now();
+        let mut processed = 0u64;
+
+        loop {
+            let rows: Vec<UserRow> = sqlx::query_as(
+                r#"
+                SELECT id, username, encrypted_email, encrypted_ssn, encrypted_phone, encrypted_address, version, created_at
+                FROM users
+                WHERE id > $1
+                ORDER BY id
+                LIMIT $2
+                "#,
+            )
+            .bind(progress.watermark)
+            .bind(batch_size)
+            .fetch_all(self.pool)
+            .await?;
+
+            let Some(last) = rows.last() else {
+                break;
+            };
+            let watermark = last.id;
+            let count = rows.len();
+
+            let mut rewritten = 0i64;
+            for row in rows {
+                if self.rotate_row(row).await? {
+                    rewritten += 1;
+                }
+            }
+
+            progress = sqlx::query_as(
+                r#"
+                UPDATE key_rotations
+                SET watermark = $2,
+                    rows_done = rows_done + $3,
+                    rows_rewritten = rows_rewritten + $4,
+                    rows_total = GREATEST(rows_total, rows_done + $3),
+                    updated_at = NOW()
+                WHERE name = $1
+                RETURNING *
+                "#,
+            )
+            .bind(&self.config.name)
+            .bind(watermark)
+            .bind(count as i64)
+            .bind(rewritten)
+            .fetch_one(self.pool)
+            .await?;
+            on_progress(&progress);
+
+            processed += count as u64;
+            if let Some(due) = pace(started, processed, self.config.rows_per_sec) {
+                time::sleep_until(due).await;
+            }
+        }
+
+        let progress = sqlx::query_as(
+            "UPDATE key_rotations SET finished_at = NOW(), updated_at = NOW() WHERE name = $1 RETURNING *",
+        )
+        .bind(&self.config.name)
+        .fetch_one(self.pool)
+        .await?;
+        tracing::info!(name = %self.config.name, "Key rotation finished");
+        Ok(progress)
+    }
+
+    /// Create or resume the `key_rotations` row, refreshing the row count
+    async fn start(&self) -> Result<RotationProgress, RotationError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            r#"
+            INSERT INTO key_rotations (name, rows_per_sec)
+            VALUES ($1, $2)
+            ON CONFLICT (name) DO UPDATE SET rows_per_sec = EXCLUDED.rows_per_sec
+            "#,
+        )
+        .bind(&self.config.name)
+        .bind(self.config.rows_per_sec as i32)
+        .execute(&mut *tx)
+        .await?;
+
+        if self.config.restart {
+            sqlx::query(
+                r#"
+                UPDATE key_rotations
+                SET watermark = 0, rows_done = 0, rows_rewritten = 0,
+                    started_at = NOW(), finished_at = NULL
+                WHERE name = $1
+                "#,
+            )
+            .bind(&self.config.name)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        // Done so far plus what is left past the watermark
+        let progress = sqlx::query_as(
+            r#"
+            UPDATE key_rotations
+            SET rows_total = rows_done + (SELECT COUNT(*) FROM users WHERE id > watermark),
+                updated_at = NOW()
+            WHERE name = $1
+            RETURNING *
+            "#,
+        )
+        .bind(&self.config.name)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(progress)
+    }
+
+    /// Re-encrypt one row unless it is already current
+    /// Returns whether anything was written.
+    async fn rotate_row(&self, mut row: UserRow) -> Result<bool, RotationError> {
+        for _ in 0..MAX_ATTEMPTS {
+            let is_current = SENSITIVE_COLUMNS.iter().all(|column| {
+                row.encrypted_field(column)
+                    .is_none_or(|value| self.driver.is_current(column, value))
+            });
+            if is_current {
+                return Ok(false);
+            }
+
+            let email = self.reencrypt("email", Some(&row.encrypted_email))?;
+            let ssn = self.reencrypt("ssn", row.encrypted_ssn.as_deref())?;
+            let phone = self.reencrypt("phone", row.encrypted_phone.as_deref())?;
+            let address = self.reencrypt("address", row.encrypted_address.as_deref())?;
+
+            let result = sqlx::query(
+                r#"
+                UPDATE users
+                SET encrypted_email = $2,
+                    encrypted_ssn = $3,
+                    encrypted_phone = $4,
+                    encrypted_address = $5,
+                    version = version + 1
+                WHERE id = $1 AND version = $6
+                "#,
+            )
+            .bind(row.id)
+            .bind(email)
+            .bind(ssn)
+            .bind(phone)
+            .bind(address)
+            .bind(row.version)
+            .execute(self.pool)
+            .await?;
+            if result.rows_affected() == 1 {
+                return Ok(true);
+            }
+
+            // A client wrote the row meanwhile: start over from its new state
+            let current: Option<UserRow> = sqlx::query_as(
+                r#"
+                SELECT id, username, encrypted_email, encrypted_ssn, encrypted_phone, encrypted_address, version, created_at
+                FROM users
+                WHERE id = $1
+                "#,
+            )
+            .bind(row.id)
+            .fetch_optional(self.pool)
+            .await?;
+            match current {
+                Some(current) => row = current,
+                None => return Ok(false),
+            }
+        }
+        Err(RotationError::Contended(row.id))
+    }
+
+    /// Value under the current key and suite (decrypting with the previous key if needed)
+    fn reencrypt(&self, column: &str, value: Option<&str>) -> Result<Option<String>, CryptoError> {
+        match value {
+            Some(value) if !self.driver.is_current(column, value) => {
+                let plaintext = self.driver.decrypt(value)?;
+                self.driver.encrypt_column(column, &plaintext).map(Some)
+            }
+            value => Ok(value.map(str::to_string)),
+        }
+    }
+}
+
+/// Every rotation recorded in `key_rotations`, oldest first
+pub async fn status(pool: &PgPool) -> Result<Vec<RotationProgress>, RotationError> {
+    Ok(
+        sqlx::query_as("SELECT * FROM key_rotations ORDER BY started_at")
+            .fetch_all(pool)
+            .await?,
+    )
+}
+
+/// When the next batch may start so that `processed` rows took at least
+/// `processed / rows_per_sec` seconds; `None` when unlimited
+fn pace(started: Instant, processed: u64, rows_per_sec: u32) -> Option<Instant> {
+    (rows_per_sec > 0)
+        .then(|| started + Duration::from_secs_f64(processed as f64 / rows_per_sec as f64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(rows_per_sec: u32, batch_size: i64) -> RotationConfig {
+        RotationConfig {
+            name: "users".into(),
+            rows_per_sec,
+            batch_size,
+            restart: false,
+        }
+    }
+
+    #[test]
+    fn test_batch_never_exceeds_rate() {
+        assert_eq!(config(10, 500).effective_batch_size(), 10);
+        assert_eq!(config(1000, 500).effective_batch_size(), 500);
+        assert_eq!(config(0, 500).effective_batch_size(), 500);
+        assert!(config(10, 0).validate().is_err());
+    }
+
+    #[test]
+    fn test_pace() {
+        let started = Instant::now();
+        assert_eq!(
+            pace(started, 50, 100),
+            Some(started + Duration::from_millis(500))
+        );
+        assert_eq!(pace(started, 50, 0), None);
+    }
+}
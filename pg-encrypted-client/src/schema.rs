@@ -0,0 +1,177 @@
+//! Schema Migrations - versioned DDL embedded from `migrations/<backend>/`
+//!
+//! Each backend has its own dialect of the DDL (`migrations/postgres`,
+//! `migrations/mysql`) with its own version history. Each schema change is a
+//! numbered `<version>_<name>.up.sql` / `.down.sql` pair, applied in order and
+//! recorded by sqlx in `_sqlx_migrations`. The files are compiled into the
+//! binary, so a build always carries the schema it expects.
+//!
+//! PostgreSQL up migrations are idempotent (`IF NOT EXISTS`), so databases
+//! created by the old ad-hoc `initialize()` DDL adopt the migration history
+//! cleanly.
+
+use crate::db::Db;
+use sqlx::migrate::{Migrate, MigrateError, Migrator};
+use sqlx::{Database, Pool};
+use std::collections::HashMap;
+
+/// PostgreSQL migrations, embedded from `./migrations/postgres`
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations/postgres");
+
+/// MySQL / MariaDB migrations, embedded from `./migrations/mysql`
+#[cfg(feature = "mysql")]
+pub static MYSQL_MIGRATOR: Migrator = sqlx::migrate!("./migrations/mysql");
+
+/// State of one migration relative to the database
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationState {
+    Applied,
+    Pending,
+    /// Applied, but the embedded SQL has changed since
+    Modified,
+}
+
+/// One row of `migrate status` output
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub state: MigrationState,
+}
+
+/// Apply all pending migrations
+pub async fn run(db: &Db) -> Result<(), MigrateError> {
+    match db {
+        Db::Postgres(pool) => MIGRATOR.run(pool).await,
+        #[cfg(feature = "mysql")]
+        Db::MySql(pool) => MYSQL_MIGRATOR.run(pool).await,
+    }
+}
+
+/// Every known migration with whether it has been applied
+pub async fn status(db: &Db) -> Result<Vec<MigrationStatus>, MigrateError> {
+    match db {
+        Db::Postgres(pool) => status_of(&MIGRATOR, pool).await,
+        #[cfg(feature = "mysql")]
+        Db::MySql(pool) => status_of(&MYSQL_MIGRATOR, pool).await,
+    }
+}
+
+/// Revert the last `steps` applied migrations, newest first
+/// Returns the versions that were reverted
+pub async fn rollback(db: &Db, steps: usize) -> Result<Vec<i64>, MigrateError> {
+    match db {
+        Db::Postgres(pool) => rollback_on(&MIGRATOR, pool, steps).await,
+        #[cfg(feature = "mysql")]
+        Db::MySql(pool) => rollback_on(&MYSQL_MIGRATOR, pool, steps).await,
+    }
+}
+
+async fn status_of<DB: Database>(
+    migrator: &Migrator,
+    pool: &Pool<DB>,
+) -> Result<Vec<MigrationStatus>, MigrateError>
+where
+    DB::Connection: Migrate,
+{
+    let applied: HashMap<i64, Vec<u8>> = applied_migrations(pool).await?.into_iter().collect();
+
+    Ok(migrator
+        .iter()
+        .filter(|m| m.migration_type.is_up_migration())
+        .map(|m| MigrationStatus {
+            version: m.version,
+            description: m.description.to_string(),
+            state: state_of(&m.checksum, applied.get(&m.version).map(Vec::as_slice)),
+        })
+        .collect())
+}
+
+async fn rollback_on<DB: Database>(
+    migrator: &Migrator,
+    pool: &Pool<DB>,
+    steps: usize,
+) -> Result<Vec<i64>, MigrateError>
+where
+    DB::Connection: Migrate,
+{
+    let mut applied: Vec<i64> = applied_migrations(pool)
+        .await?
+        .into_iter()
+        .map(|(version, _)| version)
+        .collect();
+    applied.sort_unstable();
+
+    let keep = applied.len().saturating_sub(steps);
+    let reverted: Vec<i64> = applied.split_off(keep).into_iter().rev().collect();
+    if reverted.is_empty() {
+        return Ok(reverted);
+    }
+
+    // `undo` reverts every applied migration newer than the target
+    let target = applied.last().copied().unwrap_or(0);
+    migrator.undo(pool, target).await?;
+    Ok(reverted)
+}
+
+/// Applied versions and their checksums
+async fn applied_migrations<DB: Database>(
+    pool: &Pool<DB>,
+) -> Result<Vec<(i64, Vec<u8>)>, MigrateError>
+where
+    DB::Connection: Migrate,
+{
+    let mut conn = pool.acquire().await?;
+    conn.ensure_migrations_table().await?;
+    Ok(conn
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|m| (m.version, m.checksum.into_owned()))
+        .collect())
+}
+
+fn state_of(checksum: &[u8], applied: Option<&[u8]>) -> MigrationState {
+    match applied {
+        None => MigrationState::Pending,
+        Some(applied) if applied == checksum => MigrationState::Applied,
+        Some(_) => MigrationState::Modified,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::migrate::MigrationType;
+
+    fn assert_reversible(migrator: &Migrator) {
+        let ups: Vec<_> = migrator
+            .iter()
+            .filter(|m| m.migration_type.is_up_migration())
+            .collect();
+        assert!(!ups.is_empty());
+
+        for up in ups {
+            assert!(
+                migrator.iter().any(|m| m.version == up.version
+                    && m.migration_type == MigrationType::ReversibleDown),
+                "migration {} has no down script",
+                up.version
+            );
+        }
+    }
+
+    #[test]
+    fn test_every_migration_is_reversible() {
+        assert_reversible(&MIGRATOR);
+        #[cfg(feature = "mysql")]
+        assert_reversible(&MYSQL_MIGRATOR);
+    }
+
+    #[test]
+    fn test_state_of_checksum() {
+        assert_eq!(state_of(b"abc", None), MigrationState::Pending);
+        assert_eq!(state_of(b"abc", Some(b"abc")), MigrationState::Applied);
+        assert_eq!(state_of(b"abc", Some(b"xyz")), MigrationState::Modified);
+    }
+}
@@ -0,0 +1,166 @@
+//! Searchable Encryption - blind trigram index
+//!
+//! Encrypted columns use randomized AES-GCM, so the database cannot compare
+//! them. To support `contains`-style lookups, every indexed value is split
+//! into lowercase trigrams and each trigram is stored as an HMAC token in a
+//! side table (`users_search_index`). A query is tokenized the same way; rows
+//! holding all of the query's tokens are candidates, which the client then
+//! decrypts and filters to remove false positives.
+//!
+//! Security tradeoffs (this mode is opt-in for a reason):
+//! - Equal trigrams produce equal tokens, so the database learns which rows
+//!   share substrings and how many distinct trigrams a value has.
+//! - Token frequencies can be matched against known distributions (e.g. the
+//!   trigrams of common email domains) to guess plaintext fragments.
+//! - Observed queries reveal which rows match, and repeated queries are
+//!   linkable to each other.
+//!
+//! Only index columns where substring search is worth this leakage.
+
+use crate::crypto::IndexKey;
+use sqlx::{PgConnection, PgPool};
+use std::collections::BTreeSet;
+
+/// Length of the n-grams stored in the index
+pub const NGRAM_SIZE: usize = 3;
+
+/// Split a value into its distinct lowercase n-grams
+///
+/// Values shorter than `NGRAM_SIZE` are indexed as a single gram so they can
+/// still be found by an exact-length query.
+pub fn ngrams(value: &str) -> Vec<String> {
+    let chars: Vec<char> = value.to_lowercase().chars().collect();
+    if chars.is_empty() {
+        return Vec::new();
+    }
+    if chars.len() < NGRAM_SIZE {
+        return vec![chars.into_iter().collect()];
+    }
+
+    let grams: BTreeSet<String> = chars
+        .windows(NGRAM_SIZE)
+        .map(|w| w.iter().collect())
+        .collect();
+    grams.into_iter().collect()
+}
+
+/// Blind index over a set of encrypted columns
+pub struct SearchIndex {
+    key: IndexKey,
+    columns: Vec<String>,
+}
+
+impl SearchIndex {
+    pub fn new(key: IndexKey, columns: Vec<String>) -> Self {
+        Self { key, columns }
+    }
+
+    /// Whether `column` is maintained in the index
+    pub fn is_indexed(&self, column: &str) -> bool {
+        self.columns.iter().any(|c| c == column)
+    }
+
+    /// Columns maintained in the index
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    /// HMAC tokens for every n-gram of `value`
+    pub fn tokens(&self, column: &str, value: &str) -> Vec<String> {
+        ngrams(value)
+            .iter()
+            .map(|gram| self.key.token(column, gram))
+            .collect()
+    }
+
+    /// Replace the tokens stored for one column of one row
+    /// Takes a connection so callers can index inside the row's own transaction
+    pub async fn index_value(
+        &self,
+        conn: &mut PgConnection,
+        row_id: i32,
+        column: &str,
+        value: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM users_search_index WHERE row_id = $1 AND column_name = $2")
+            .bind(row_id)
+            .bind(column)
+            .execute(&mut *conn)
+            .await?;
+
+        let tokens = value.map(|v| self.tokens(column, v)).unwrap_or_default();
+        if tokens.is_empty() {
+            return Ok(());
+        }
+
+        sqlx::query(
+            r#"
+            INSERT INTO users_search_index (row_id, column_name, token)
+            SELECT $1, $2, UNNEST($3::text[])
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(row_id)
+        .bind(column)
+        .bind(&tokens)
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// IDs of rows whose `column` contains every n-gram of `query`
+    ///
+    /// The result may contain false positives (the grams can appear in a
+    /// different order), so callers must verify after decryption.
+    pub async fn candidates(
+        &self,
+        pool: &PgPool,
+        column: &str,
+        query: &str,
+    ) -> Result<Vec<i32>, sqlx::Error> {
+        let tokens = self.tokens(column, query);
+        if tokens.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        sqlx::query_scalar(
+            r#"
+            SELECT row_id
+            FROM users_search_index
+            WHERE column_name = $1 AND token = ANY($2)
+            GROUP BY row_id
+            HAVING COUNT(DISTINCT token) = $3
+            "#,
+        )
+        .bind(column)
+        .bind(&tokens)
+        .bind(tokens.len() as i64)
+        .fetch_all(pool)
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ngrams_lowercase_and_distinct() {
+        assert_eq!(ngrams("AbAbA"), vec!["aba", "bab"]);
+    }
+
+    #[test]
+    fn test_ngrams_short_values() {
+        assert_eq!(ngrams("Jo"), vec!["jo"]);
+        assert!(ngrams("").is_empty());
+    }
+
+    #[test]
+    fn test_query_grams_subset_of_value_grams() {
+        let value = ngrams("john.doe@example.com");
+        for gram in ngrams("example") {
+            assert!(value.contains(&gram));
+        }
+    }
+}
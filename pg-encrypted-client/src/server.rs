@@ -0,0 +1,202 @@
+//! REST Service - the repository behind an HTTP API
+//!
+//! The Enhanced Client Driver runs inside this service, so HTTP clients send
+//! and receive plaintext JSON while PostgreSQL only ever stores ciphertext.
+//! Run it on a trusted network segment (or behind TLS + auth): the API itself
+//! returns decrypted data.
+//!
+//! Routes:
+//! - `POST   /users`      create (body: `CreateUserInput`)
+//! - `GET    /users`      list
+//! - `GET    /users/{id}` get
+//! - `PUT    /users/{id}` update (body: `UpdateUserInput`; 409 on version conflict)
+//! - `DELETE /users/{id}` delete
+//!
+//! Both GET routes take `?columns=email,phone` to decrypt only some sensitive
+//! columns and `?decrypt=false` for ciphertext. With a ciphertext-only
+//! repository (`--no-decrypt`) reads always return ciphertext and routes that
+//! return plaintext respond 403.
+
+use crate::crypto::CryptoError;
+use crate::repository::{
+    CreateUserInput, Projection, RepositoryError, UpdateUserInput, User, UserRepository, UserView,
+};
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+
+type AppState = Arc<UserRepository>;
+
+/// Repository error mapped onto an HTTP response
+pub struct ApiError(RepositoryError);
+
+impl From<RepositoryError> for ApiError {
+    fn from(e: RepositoryError) -> Self {
+        Self(e)
+    }
+}
+
+impl ApiError {
+    fn status(&self) -> StatusCode {
+        match &self.0 {
+            RepositoryError::NotFound(_) | RepositoryError::RowNotFound { .. } => {
+                StatusCode::NOT_FOUND
+            }
+            RepositoryError::Crypto(CryptoError::KeysLocked) => StatusCode::SERVICE_UNAVAILABLE,
+            RepositoryError::Database(sqlx::Error::Database(db)) if db.is_unique_violation() => {
+                StatusCode::CONFLICT
+            }
+            RepositoryError::Conflict { .. } => StatusCode::CONFLICT,
+            RepositoryError::DecryptionDisabled => StatusCode::FORBIDDEN,
+            RepositoryError::Unsupported(_) => StatusCode::NOT_IMPLEMENTED,
+            RepositoryError::SearchDisabled
+            | RepositoryError::InvalidSearch(_)
+            | RepositoryError::InvalidTable(_)
+            | RepositoryError::UnknownColumn(_) => StatusCode::BAD_REQUEST,
+            RepositoryError::Database(_)
+            | RepositoryError::Crypto(_)
+            | RepositoryError::Migration(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Query string of the read routes
+#[derive(Debug, Deserialize)]
+struct ReadParams {
+    /// Comma-separated sensitive columns (default: all)
+    columns: Option<String>,
+    /// `false` returns ciphertext (default: true)
+    decrypt: Option<bool>,
+}
+
+impl ReadParams {
+    fn projection(&self) -> Result<Projection, RepositoryError> {
+        let projection = match &self.columns {
+            Some(columns) => Projection::columns(columns.split(',').map(str::trim))?,
+            None => Projection::all(),
+        };
+        Ok(if self.decrypt == Some(false) {
+            projection.ciphertext()
+        } else {
+            projection
+        })
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        // Internal errors are logged, not echoed: they can include SQL or crypto details
+        let message = if status == StatusCode::INTERNAL_SERVER_ERROR {
+            tracing::error!(error = %self.0, "Request failed");
+            "internal error".to_string()
+        } else {
+            self.0.to_string()
+        };
+        (status, Json(json!({ "error": message }))).into_response()
+    }
+}
+
+/// Build the router over a shared repository
+pub fn router(repo: Arc<UserRepository>) -> Router {
+    Router::new()
+        .route("/users", get(list_users).post(create_user))
+        .route(
+            "/users/{id}",
+            get(get_user).put(update_user).delete(delete_user),
+        )
+        .with_state(repo)
+}
+
+/// Serve the API on `addr` until the process is stopped
+pub async fn serve(repo: UserRepository, addr: &str) -> std::io::Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    tracing::info!(
+        "Serving encrypted repository on http://{}",
+        listener.local_addr()?
+    );
+    axum::serve(listener, router(Arc::new(repo))).await
+}
+
+async fn create_user(
+    State(repo): State<AppState>,
+    Json(input): Json<CreateUserInput>,
+) -> Result<(StatusCode, Json<User>), ApiError> {
+    let user = repo.create(input).await?;
+    Ok((StatusCode::CREATED, Json(user)))
+}
+
+async fn list_users(
+    State(repo): State<AppState>,
+    Query(params): Query<ReadParams>,
+) -> Result<Json<Vec<UserView>>, ApiError> {
+    Ok(Json(repo.list_all_with(&params.projection()?).await?))
+}
+
+async fn get_user(
+    State(repo): State<AppState>,
+    Path(id): Path<i32>,
+    Query(params): Query<ReadParams>,
+) -> Result<Json<UserView>, ApiError> {
+    Ok(Json(repo.get_by_id_with(id, &params.projection()?).await?))
+}
+
+async fn update_user(
+    State(repo): State<AppState>,
+    Path(id): Path<i32>,
+    Json(input): Json<UpdateUserInput>,
+) -> Result<Json<User>, ApiError> {
+    Ok(Json(repo.update(id, input).await?))
+}
+
+async fn delete_user(
+    State(repo): State<AppState>,
+    Path(id): Path<i32>,
+) -> Result<StatusCode, ApiError> {
+    if repo.delete(id).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(RepositoryError::NotFound(id).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_status_mapping() {
+        let status = |e: RepositoryError| ApiError(e).status();
+
+        assert_eq!(status(RepositoryError::NotFound(1)), StatusCode::NOT_FOUND);
+        assert_eq!(
+            status(RepositoryError::Crypto(CryptoError::KeysLocked)),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            status(RepositoryError::Conflict {
+                id: 1,
+                expected: 1,
+                actual: 2
+            }),
+            StatusCode::CONFLICT
+        );
+        assert_eq!(
+            status(RepositoryError::DecryptionDisabled),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(RepositoryError::InvalidSearch("too short".into())),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status(RepositoryError::Database(sqlx::Error::RowNotFound)),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+}
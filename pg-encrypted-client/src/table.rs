@@ -0,0 +1,379 @@
+//! Generic encrypted tables
+//!
+//! `UserRepository` is written by hand for the `users` table. For other
+//! tables, describe the columns once and get CRUD with the same
+//! encrypt-before-write / decrypt-after-read behaviour:
+//!
+//! ```text
+//! let patients = EncryptedTable::new("patients")
+//!     .plain("name")
+//!     .encrypted("diagnosis")
+//!     .repository(pool, driver)?;
+//! ```
+//!
+//! Encrypted columns are stored as `encrypted_<name>`; records always use the
+//! logical (plaintext) column names. All columns are nullable TEXT.
+
+use crate::crypto::EncryptedClientDriver;
+use crate::migrate::is_valid_identifier;
+use crate::repository::RepositoryError;
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgRow;
+use sqlx::{PgPool, Row};
+use std::collections::BTreeMap;
+
+/// Column values keyed by logical column name
+pub type Fields = BTreeMap<String, Option<String>>;
+
+/// Decrypted row of a generic encrypted table
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    pub id: i32,
+    #[serde(flatten)]
+    pub fields: Fields,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColumnKind {
+    Plain,
+    Encrypted,
+}
+
+#[derive(Debug, Clone)]
+struct ColumnSpec {
+    name: String,
+    kind: ColumnKind,
+}
+
+impl ColumnSpec {
+    /// Column name as stored in the database
+    fn db_name(&self) -> String {
+        match self.kind {
+            ColumnKind::Plain => self.name.clone(),
+            ColumnKind::Encrypted => format!("encrypted_{}", self.name),
+        }
+    }
+}
+
+/// Table definition - builder for a `TableRepository`
+#[derive(Debug, Clone)]
+pub struct EncryptedTable {
+    name: String,
+    columns: Vec<ColumnSpec>,
+}
+
+impl EncryptedTable {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            columns: Vec::new(),
+        }
+    }
+
+    /// Add a column that is encrypted client-side
+    pub fn encrypted(mut self, column: &str) -> Self {
+        self.columns.push(ColumnSpec {
+            name: column.to_string(),
+            kind: ColumnKind::Encrypted,
+        });
+        self
+    }
+
+    /// Add a column that is stored as plaintext (searchable by the database)
+    pub fn plain(mut self, column: &str) -> Self {
+        self.columns.push(ColumnSpec {
+            name: column.to_string(),
+            kind: ColumnKind::Plain,
+        });
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Validate the definition and bind it to a pool and driver
+    pub fn repository(
+        self,
+        pool: PgPool,
+        driver: EncryptedClientDriver,
+    ) -> Result<TableRepository, RepositoryError> {
+        self.validate()?;
+        Ok(TableRepository {
+            pool,
+            driver,
+            table: self,
+        })
+    }
+
+    fn validate(&self) -> Result<(), RepositoryError> {
+        if !is_valid_identifier(&self.name) {
+            return Err(RepositoryError::InvalidTable(format!(
+                "invalid table name '{}'",
+                self.name
+            )));
+        }
+        if self.columns.is_empty() {
+            return Err(RepositoryError::InvalidTable(format!(
+                "table '{}' has no columns",
+                self.name
+            )));
+        }
+
+        let mut seen = Vec::new();
+        for column in &self.columns {
+            if !is_valid_identifier(&column.name) || column.name == "id" {
+                return Err(RepositoryError::InvalidTable(format!(
+                    "invalid column name '{}'",
+                    column.name
+                )));
+            }
+            if seen.contains(&column.name) {
+                return Err(RepositoryError::InvalidTable(format!(
+                    "duplicate column '{}'",
+                    column.name
+                )));
+            }
+            seen.push(column.name.clone());
+        }
+        Ok(())
+    }
+
+    fn column(&self, name: &str) -> Result<&ColumnSpec, RepositoryError> {
+        self.columns
+            .iter()
+            .find(|c| c.name == name)
+            .ok_or_else(|| RepositoryError::UnknownColumn(name.to_string()))
+    }
+
+    /// CREATE TABLE statement for this definition
+    fn create_sql(&self) -> String {
+        let columns = self
+            .columns
+            .iter()
+            .map(|c| format!(r#""{}" TEXT"#, c.db_name()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            r#"CREATE TABLE IF NOT EXISTS "{}" (id SERIAL PRIMARY KEY, {})"#,
+            self.name, columns
+        )
+    }
+
+    /// Column list used by every SELECT / RETURNING clause
+    fn select_list(&self) -> String {
+        std::iter::once("id".to_string())
+            .chain(self.columns.iter().map(|c| format!(r#""{}""#, c.db_name())))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// CRUD over an `EncryptedTable` with automatic encrypt/decrypt mapping
+pub struct TableRepository {
+    pool: PgPool,
+    driver: EncryptedClientDriver,
+    table: EncryptedTable,
+}
+
+impl TableRepository {
+    /// Create the table if it does not exist
+    pub async fn initialize(&self) -> Result<(), RepositoryError> {
+        sqlx::query(&self.table.create_sql())
+            .execute(&self.pool)
+            .await?;
+        tracing::info!(table = %self.table.name, "Encrypted table initialized");
+        Ok(())
+    }
+
+    /// Insert a record - encrypted columns are encrypted client-side
+    pub async fn insert(&self, fields: &Fields) -> Result<Record, RepositoryError> {
+        let (names, values) = self.encode(fields)?;
+
+        let sql = if names.is_empty() {
+            format!(
+                r#"INSERT INTO "{}" DEFAULT VALUES RETURNING {}"#,
+                self.table.name,
+                self.table.select_list()
+            )
+        } else {
+            let placeholders = (1..=names.len())
+                .map(|i| format!("${i}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!(
+                r#"INSERT INTO "{}" ({}) VALUES ({}) RETURNING {}"#,
+                self.table.name,
+                names.join(", "),
+                placeholders,
+                self.table.select_list()
+            )
+        };
+
+        let mut query = sqlx::query(&sql);
+        for value in values {
+            query = query.bind(value);
+        }
+        let row = query.fetch_one(&self.pool).await?;
+        self.decode(&row)
+    }
+
+    /// Get record by ID - decrypts all encrypted columns
+    pub async fn get(&self, id: i32) -> Result<Record, RepositoryError> {
+        let sql = format!(
+            r#"SELECT {} FROM "{}" WHERE id = $1"#,
+            self.table.select_list(),
+            self.table.name
+        );
+        let row = sqlx::query(&sql)
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or_else(|| self.not_found(id))?;
+        self.decode(&row)
+    }
+
+    /// List all records (with decryption)
+    pub async fn list(&self) -> Result<Vec<Record>, RepositoryError> {
+        let sql = format!(
+            r#"SELECT {} FROM "{}" ORDER BY id"#,
+            self.table.select_list(),
+            self.table.name
+        );
+        let rows = sqlx::query(&sql).fetch_all(&self.pool).await?;
+        rows.iter().map(|r| self.decode(r)).collect()
+    }
+
+    /// Update the given fields; fields not present are left untouched
+    pub async fn update(&self, id: i32, fields: &Fields) -> Result<Record, RepositoryError> {
+        let (names, values) = self.encode(fields)?;
+        if names.is_empty() {
+            return self.get(id).await;
+        }
+
+        let assignments = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| format!("{} = ${}", name, i + 2))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let sql = format!(
+            r#"UPDATE "{}" SET {} WHERE id = $1 RETURNING {}"#,
+            self.table.name,
+            assignments,
+            self.table.select_list()
+        );
+
+        let mut query = sqlx::query(&sql).bind(id);
+        for value in values {
+            query = query.bind(value);
+        }
+        let row = query
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or_else(|| self.not_found(id))?;
+        self.decode(&row)
+    }
+
+    /// Delete record by ID
+    pub async fn delete(&self, id: i32) -> Result<bool, RepositoryError> {
+        let sql = format!(r#"DELETE FROM "{}" WHERE id = $1"#, self.table.name);
+        let result = sqlx::query(&sql).bind(id).execute(&self.pool).await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    fn not_found(&self, id: i32) -> RepositoryError {
+        RepositoryError::RowNotFound {
+            table: self.table.name.clone(),
+            id,
+        }
+    }
+
+    /// Map logical fields to quoted DB column names and (encrypted) values
+    fn encode(
+        &self,
+        fields: &Fields,
+    ) -> Result<(Vec<String>, Vec<Option<String>>), RepositoryError> {
+        let mut names = Vec::with_capacity(fields.len());
+        let mut values = Vec::with_capacity(fields.len());
+
+        for (name, value) in fields {
+            let column = self.table.column(name)?;
+            let value = match column.kind {
+                ColumnKind::Plain => value.clone(),
+                ColumnKind::Encrypted => self
+                    .driver
+                    .encrypt_column_optional(&column.name, value.as_deref())?,
+            };
+            names.push(format!(r#""{}""#, column.db_name()));
+            values.push(value);
+        }
+
+        Ok((names, values))
+    }
+
+    /// Helper to decrypt a row into a record
+    fn decode(&self, row: &PgRow) -> Result<Record, RepositoryError> {
+        let mut fields = Fields::new();
+        for column in &self.table.columns {
+            let raw: Option<String> = row.try_get(column.db_name().as_str())?;
+            let value = match column.kind {
+                ColumnKind::Plain => raw,
+                ColumnKind::Encrypted => self.driver.decrypt_optional(raw.as_deref())?,
+            };
+            fields.insert(column.name.clone(), value);
+        }
+
+        Ok(Record {
+            id: row.try_get("id")?,
+            fields,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patients() -> EncryptedTable {
+        EncryptedTable::new("patients")
+            .plain("name")
+            .encrypted("diagnosis")
+    }
+
+    #[test]
+    fn test_create_sql_maps_encrypted_columns() {
+        assert_eq!(
+            patients().create_sql(),
+            r#"CREATE TABLE IF NOT EXISTS "patients" (id SERIAL PRIMARY KEY, "name" TEXT, "encrypted_diagnosis" TEXT)"#
+        );
+        assert_eq!(
+            patients().select_list(),
+            r#"id, "name", "encrypted_diagnosis""#
+        );
+    }
+
+    #[test]
+    fn test_validate_rejects_bad_definitions() {
+        assert!(patients().validate().is_ok());
+        assert!(EncryptedTable::new("empty").validate().is_err());
+        assert!(EncryptedTable::new("bad name")
+            .plain("a")
+            .validate()
+            .is_err());
+        assert!(EncryptedTable::new("t").plain("id").validate().is_err());
+        assert!(EncryptedTable::new("t")
+            .plain("a")
+            .encrypted("a")
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn test_unknown_column() {
+        assert!(matches!(
+            patients().column("ssn"),
+            Err(RepositoryError::UnknownColumn(_))
+        ));
+    }
+}
@@ -0,0 +1,577 @@
+//! Import / Export - move users between PostgreSQL and CSV or Parquet files
+//!
+//! Rows are streamed from the database and encrypted or decrypted as they pass
+//! through, so the plaintext table never exists in memory or on the server:
+//!
+//! - `export --decrypt` writes plaintext columns (`email`, `ssn`, ...)
+//! - `export` without `--decrypt` writes the ciphertext (`encrypted_email`, ...)
+//!   as stored, which is safe to hand to backup systems
+//! - `import` accepts either layout: plaintext files are encrypted on the way
+//!   in, ciphertext files are verified against the current key and stored as-is
+//!
+//! Exported files are readable by their owner only. `import` commits every
+//! `batch_size` rows in one transaction and reports progress after each
+//! commit, so an import that stops part way can be resumed with `skip`.
+//!
+//! The format is chosen by file extension (`.csv` or `.parquet`). Parquet
+//! needs the `parquet` feature; every column is written as a UTF-8 string.
+//! Parquet import reads the whole file before inserting. CSV has no NULL, so
+//! it is written `\N` as in PostgreSQL's text `COPY` format, and an empty
+//! field is an empty string.
+
+use crate::repository::{
+    CreateUserInput, EncryptedUserInput, NewUser, RepositoryError, User, UserRepository, UserRow,
+};
+use futures::TryStreamExt;
+use std::fs::{File, OpenOptions};
+use std::path::Path;
+use thiserror::Error;
+
+/// Column layout of a plaintext file
+pub const PLAIN_COLUMNS: [&str; 7] = [
+    "id",
+    "username",
+    "email",
+    "ssn",
+    "phone",
+    "address",
+    "created_at",
+];
+
+/// Column layout of a ciphertext file
+pub const ENCRYPTED_COLUMNS: [&str; 7] = [
+    "id",
+    "username",
+    "encrypted_email",
+    "encrypted_ssn",
+    "encrypted_phone",
+    "encrypted_address",
+    "created_at",
+];
+
+/// How a CSV file spells NULL
+const CSV_NULL: &str = "\\N";
+
+/// Import / export errors
+#[derive(Error, Debug)]
+pub enum TransferError {
+    #[error("Repository error: {0}")]
+    Repository(#[from] RepositoryError),
+
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+
+    #[error("CSV error: {0}")]
+    Csv(#[from] csv::Error),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[cfg(feature = "parquet")]
+    #[error("Parquet error: {0}")]
+    Parquet(#[from] polars::error::PolarsError),
+
+    #[error("Unsupported file format: {0}")]
+    UnsupportedFormat(String),
+
+    #[error("Missing column: {0}")]
+    MissingColumn(String),
+
+    #[error("Record {record}: missing value for '{column}'")]
+    MissingValue { record: u64, column: String },
+}
+
+/// File format, chosen by extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    Csv,
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+impl FileFormat {
+    pub fn from_path(path: &Path) -> Result<Self, TransferError> {
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_lowercase)
+            .unwrap_or_default();
+
+        match ext.as_str() {
+            "csv" => Ok(Self::Csv),
+            #[cfg(feature = "parquet")]
+            "parquet" => Ok(Self::Parquet),
+            #[cfg(not(feature = "parquet"))]
+            "parquet" => Err(TransferError::UnsupportedFormat(
+                "parquet support requires building with --features parquet".into(),
+            )),
+            other => Err(TransferError::UnsupportedFormat(format!(
+                "'.{other}' (expected .csv or .parquet)"
+            ))),
+        }
+    }
+}
+
+/// One file row, values in header order (`None` = NULL)
+type Record = Vec<Option<String>>;
+
+/// Export every user to `path`; returns the number of rows written
+///
+/// `on_progress` is called with the running count every `batch_size` rows.
+pub async fn export(
+    repo: &UserRepository,
+    path: &Path,
+    decrypt: bool,
+    batch_size: usize,
+    mut on_progress: impl FnMut(u64),
+) -> Result<u64, TransferError> {
+    let format = FileFormat::from_path(path)?;
+    let batch_size = batch_size.max(1);
+    let columns: &[&str] = if decrypt {
+        &PLAIN_COLUMNS
+    } else {
+        &ENCRYPTED_COLUMNS
+    };
+    let mut sink = create_sink(path, format, columns, batch_size)?;
+
+    let mut rows = repo.stream_raw_encrypted();
+    let mut count = 0u64;
+    while let Some(row) = rows.try_next().await? {
+        let record = if decrypt {
+            plain_record(&repo.decrypt_row(row)?)
+        } else {
+            encrypted_record(&row)
+        };
+        sink.write(record)?;
+
+        count += 1;
+        if count.is_multiple_of(batch_size as u64) {
+            on_progress(count);
+        }
+    }
+
+    sink.finish()?;
+    on_progress(count);
+    Ok(count)
+}
+
+/// Import users from `path`, committing every `batch_size` rows; returns
+/// the number of rows inserted
+///
+/// The first `skip` records are passed over. `on_progress` is called with the
+/// number of records done, skipped ones included, after every commit, so a
+/// failed import resumes by skipping the last count reported.
+pub async fn import(
+    repo: &UserRepository,
+    path: &Path,
+    batch_size: usize,
+    skip: u64,
+    mut on_progress: impl FnMut(u64),
+) -> Result<u64, TransferError> {
+    let format = FileFormat::from_path(path)?;
+    let batch_size = batch_size.max(1);
+    let mut source = Source::open(path, format)?;
+    let headers = source.headers().to_vec();
+
+    let encrypted = headers.iter().any(|h| h == "encrypted_email");
+    let email_column = if encrypted {
+        "encrypted_email"
+    } else {
+        "email"
+    };
+    for required in ["username", email_column] {
+        if !headers.iter().any(|h| h == required) {
+            return Err(TransferError::MissingColumn(required.to_string()));
+        }
+    }
+
+    let mut position = 0u64;
+    let mut batch = Vec::with_capacity(batch_size);
+    while let Some(record) = source.next_record()? {
+        position += 1;
+        if position <= skip {
+            continue;
+        }
+        let get = |name: &str| field(&headers, &record, name);
+        let require = |name: &str| {
+            get(name)
+                .filter(|value| !value.is_empty())
+                .ok_or_else(|| TransferError::MissingValue {
+                    record: position,
+                    column: name.to_string(),
+                })
+        };
+
+        batch.push(if encrypted {
+            // Ciphertext is never empty, so an empty field is a NULL too
+            let get = |name: &str| get(name).filter(|value| !value.is_empty());
+            NewUser::Encrypted(EncryptedUserInput {
+                username: require("username")?,
+                encrypted_email: require("encrypted_email")?,
+                encrypted_ssn: get("encrypted_ssn"),
+                encrypted_phone: get("encrypted_phone"),
+                encrypted_address: get("encrypted_address"),
+            })
+        } else {
+            NewUser::Plain(CreateUserInput {
+                username: require("username")?,
+                email: require("email")?,
+                ssn: get("ssn"),
+                phone: get("phone"),
+                address: get("address"),
+            })
+        });
+
+        if batch.len() == batch_size {
+            repo.create_batch(std::mem::take(&mut batch)).await?;
+            on_progress(position);
+        }
+    }
+
+    if !batch.is_empty() {
+        repo.create_batch(batch).await?;
+    }
+    on_progress(position);
+    Ok(position.saturating_sub(skip))
+}
+
+fn plain_record(user: &User) -> Record {
+    vec![
+        Some(user.id.to_string()),
+        Some(user.username.clone()),
+        Some(user.email.clone()),
+        user.ssn.clone(),
+        user.phone.clone(),
+        user.address.clone(),
+        Some(user.created_at.to_rfc3339()),
+    ]
+}
+
+fn encrypted_record(row: &UserRow) -> Record {
+    vec![
+        Some(row.id.to_string()),
+        Some(row.username.clone()),
+        Some(row.encrypted_email.clone()),
+        row.encrypted_ssn.clone(),
+        row.encrypted_phone.clone(),
+        row.encrypted_address.clone(),
+        Some(row.created_at.to_rfc3339()),
+    ]
+}
+
+/// Value of column `name` in `record`, if the column exists and is not NULL
+fn field(headers: &[String], record: &[Option<String>], name: &str) -> Option<String> {
+    headers
+        .iter()
+        .position(|h| h == name)
+        .and_then(|i| record.get(i).cloned().flatten())
+}
+
+/// Create `path` for an export, or truncate it, readable by its owner only
+fn create_private(path: &Path) -> Result<File, TransferError> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let file = options.open(path)?;
+    // The mode only applies to a new file
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    Ok(file)
+}
+
+/// Output file, written one record at a time
+trait Sink {
+    fn write(&mut self, record: Record) -> Result<(), TransferError>;
+    fn finish(self: Box<Self>) -> Result<(), TransferError>;
+}
+
+#[cfg_attr(not(feature = "parquet"), allow(unused_variables))]
+fn create_sink(
+    path: &Path,
+    format: FileFormat,
+    columns: &[&str],
+    batch_size: usize,
+) -> Result<Box<dyn Sink>, TransferError> {
+    match format {
+        FileFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(create_private(path)?);
+            writer.write_record(columns)?;
+            Ok(Box::new(CsvSink(writer)))
+        }
+        #[cfg(feature = "parquet")]
+        FileFormat::Parquet => Ok(Box::new(parquet::ParquetSink::create(
+            path, columns, batch_size,
+        )?)),
+    }
+}
+
+struct CsvSink(csv::Writer<File>);
+
+impl Sink for CsvSink {
+    fn write(&mut self, record: Record) -> Result<(), TransferError> {
+        self.0
+            .write_record(record.iter().map(|v| v.as_deref().unwrap_or(CSV_NULL)))?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), TransferError> {
+        Ok(self.0.flush()?)
+    }
+}
+
+/// Input file
+enum Source {
+    Csv {
+        headers: Vec<String>,
+        records: csv::StringRecordsIntoIter<File>,
+    },
+    #[cfg(feature = "parquet")]
+    Parquet(parquet::ParquetSource),
+}
+
+impl Source {
+    fn open(path: &Path, format: FileFormat) -> Result<Self, TransferError> {
+        match format {
+            FileFormat::Csv => {
+                let mut reader = csv::Reader::from_path(path)?;
+                let headers = reader.headers()?.iter().map(str::to_string).collect();
+                Ok(Self::Csv {
+                    headers,
+                    records: reader.into_records(),
+                })
+            }
+            #[cfg(feature = "parquet")]
+            FileFormat::Parquet => Ok(Self::Parquet(parquet::ParquetSource::open(path)?)),
+        }
+    }
+
+    fn headers(&self) -> &[String] {
+        match self {
+            Self::Csv { headers, .. } => headers,
+            #[cfg(feature = "parquet")]
+            Self::Parquet(source) => source.headers(),
+        }
+    }
+
+    fn next_record(&mut self) -> Result<Option<Record>, TransferError> {
+        match self {
+            Self::Csv { records, .. } => match records.next() {
+                Some(record) => Ok(Some(
+                    record?
+                        .iter()
+                        .map(|v| (v != CSV_NULL).then(|| v.to_string()))
+                        .collect(),
+                )),
+                None => Ok(None),
+            },
+            #[cfg(feature = "parquet")]
+            Self::Parquet(source) => Ok(source.next_record()),
+        }
+    }
+}
+
+#[cfg(feature = "parquet")]
+mod parquet {
+    use super::{create_private, Record, Sink, TransferError};
+    use polars::prelude::*;
+    use std::fs::File;
+    use std::path::Path;
+
+    /// Buffers rows column-wise and writes one row group per batch
+    pub struct ParquetSink {
+        writer: BatchedWriter<File>,
+        names: Vec<PlSmallStr>,
+        buffer: Vec<Vec<Option<String>>>,
+        batch_size: usize,
+    }
+
+    impl ParquetSink {
+        pub fn create(
+            path: &Path,
+            columns: &[&str],
+            batch_size: usize,
+        ) -> Result<Self, TransferError> {
+            let names: Vec<PlSmallStr> = columns.iter().map(|c| PlSmallStr::from(*c)).collect();
+            let schema = Schema::from_iter(
+                names
+                    .iter()
+                    .map(|n| Field::new(n.clone(), DataType::String)),
+            );
+            let writer = ParquetWriter::new(create_private(path)?).batched(&schema)?;
+
+            Ok(Self {
+                writer,
+                buffer: vec![Vec::with_capacity(batch_size); names.len()],
+                names,
+                batch_size,
+            })
+        }
+
+        fn flush(&mut self) -> Result<(), TransferError> {
+            if self.buffer[0].is_empty() {
+                return Ok(());
+            }
+            let columns = self
+                .names
+                .iter()
+                .zip(self.buffer.iter_mut())
+                .map(|(name, values)| Column::new(name.clone(), std::mem::take(values)))
+                .collect();
+            self.writer.write_batch(&DataFrame::new(columns)?)?;
+            Ok(())
+        }
+    }
+
+    impl Sink for ParquetSink {
+        fn write(&mut self, record: Record) -> Result<(), TransferError> {
+            for (column, value) in self.buffer.iter_mut().zip(record) {
+                column.push(value);
+            }
+            if self.buffer[0].len() >= self.batch_size {
+                self.flush()?;
+            }
+            Ok(())
+        }
+
+        fn finish(mut self: Box<Self>) -> Result<(), TransferError> {
+            self.flush()?;
+            self.writer.finish()?;
+            Ok(())
+        }
+    }
+
+    /// Whole file read into string columns, handed out row by row
+    pub struct ParquetSource {
+        headers: Vec<String>,
+        columns: Vec<Vec<Option<String>>>,
+        next: usize,
+        len: usize,
+    }
+
+    impl ParquetSource {
+        pub fn open(path: &Path) -> Result<Self, TransferError> {
+            let df = ParquetReader::new(File::open(path)?).finish()?;
+
+            let mut headers = Vec::new();
+            let mut columns = Vec::new();
+            for column in df.get_columns() {
+                let values = column
+                    .cast(&DataType::String)?
+                    .str()?
+                    .into_iter()
+                    .map(|v| v.map(str::to_string))
+                    .collect();
+                headers.push(column.name().to_string());
+                columns.push(values);
+            }
+
+            Ok(Self {
+                headers,
+                columns,
+                next: 0,
+                len: df.height(),
+            })
+        }
+
+        pub fn headers(&self) -> &[String] {
+            &self.headers
+        }
+
+        pub fn next_record(&mut self) -> Option<Record> {
+            if self.next >= self.len {
+                return None;
+            }
+            let record = self.columns.iter().map(|c| c[self.next].clone()).collect();
+            self.next += 1;
+            Some(record)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_from_extension() {
+        assert_eq!(
+            FileFormat::from_path(Path::new("users.CSV")).unwrap(),
+            FileFormat::Csv
+        );
+        assert!(matches!(
+            FileFormat::from_path(Path::new("users.xlsx")),
+            Err(TransferError::UnsupportedFormat(_))
+        ));
+        assert_eq!(
+            FileFormat::from_path(Path::new("users.parquet")).is_ok(),
+            cfg!(feature = "parquet")
+        );
+    }
+
+    fn roundtrip(path: &Path) {
+        let records = vec![
+            vec![
+                Some("jane".to_string()),
+                Some("jane@example.com".to_string()),
+                None,
+            ],
+            vec![
+                Some("john".to_string()),
+                None,
+                Some("123-45-6789".to_string()),
+            ],
+            // Not NULL, and read back as such
+            vec![Some("joan".to_string()), Some(String::new()), None],
+        ];
+        let format = FileFormat::from_path(path).unwrap();
+
+        let mut sink = create_sink(path, format, &["username", "email", "ssn"], 1).unwrap();
+        for record in records.clone() {
+            sink.write(record).unwrap();
+        }
+        sink.finish().unwrap();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let mut source = Source::open(path, format).unwrap();
+        assert_eq!(source.headers(), ["username", "email", "ssn"]);
+        let mut read = Vec::new();
+        while let Some(record) = source.next_record().unwrap() {
+            read.push(record);
+        }
+        assert_eq!(read, records);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_csv_roundtrip() {
+        roundtrip(&std::env::temp_dir().join(format!("pgec-{}.csv", std::process::id())));
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_parquet_roundtrip() {
+        roundtrip(&std::env::temp_dir().join(format!("pgec-{}.parquet", std::process::id())));
+    }
+
+    #[test]
+    fn test_field_lookup_treats_missing_as_none() {
+        let headers = vec!["username".to_string(), "email".to_string()];
+        let record = vec![Some("jane".to_string()), None];
+
+        assert_eq!(
+            field(&headers, &record, "username").as_deref(),
+            Some("jane")
+        );
+        assert_eq!(field(&headers, &record, "email"), None);
+        assert_eq!(field(&headers, &record, "ssn"), None);
+    }
+}
@@ -0,0 +1,277 @@
+//! End-to-end tests against a real PostgreSQL
+//!
+//! Every test runs in a schema of its own, so tests can share one server and
+//! run in parallel. The server is `TEST_DATABASE_URL` when set; otherwise each
+//! test starts a throwaway container with testcontainers, which needs Docker.
+//! The tests are ignored by default so a plain `cargo test` stays
+//! self-contained:
+//!
+//! ```text
+//! cargo test --test end_to_end -- --ignored
+//! TEST_DATABASE_URL=postgres://postgres@localhost/postgres cargo test --test end_to_end -- --ignored
+//! ```
+
+use pg_encrypted_client::crypto::{
+    ColumnEncryptionKey, EncryptedClientDriver, IndexKey, MasterKey,
+};
+use pg_encrypted_client::db::Db;
+use pg_encrypted_client::repository::{
+    CreateUserInput, RepositoryError, UpdateUserInput, UserRepository,
+};
+use pg_encrypted_client::rotate::{RotationConfig, RotationWorker};
+use pg_encrypted_client::schema;
+use pg_encrypted_client::search::SearchIndex;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::PgPool;
+use std::env;
+use std::str::FromStr;
+use testcontainers_modules::postgres::Postgres;
+use testcontainers_modules::testcontainers::runners::AsyncRunner;
+use testcontainers_modules::testcontainers::ContainerAsync;
+
+/// A migrated, empty schema on a test server
+struct TestDb {
+    pool: PgPool,
+    url: String,
+    schema: String,
+    _container: Option<ContainerAsync<Postgres>>,
+}
+
+impl TestDb {
+    async fn start() -> Self {
+        let (url, container) = match env::var("TEST_DATABASE_URL") {
+            Ok(url) => (url, None),
+            Err(_) => {
+                let container = Postgres::default()
+                    .start()
+                    .await
+                    .expect("failed to start a Postgres container (is Docker running?)");
+                let url = format!(
+                    "postgres://postgres:postgres@{}:{}/postgres",
+                    container.get_host().await.unwrap(),
+                    container.get_host_port_ipv4(5432).await.unwrap()
+                );
+                (url, Some(container))
+            }
+        };
+
+        let schema = format!("e2e_{:08x}", rand::random::<u32>());
+        let admin = PgPool::connect(&url).await.unwrap();
+        sqlx::query(&format!("CREATE SCHEMA {schema}"))
+            .execute(&admin)
+            .await
+            .unwrap();
+        admin.close().await;
+
+        let options = PgConnectOptions::from_str(&url)
+            .unwrap()
+            .options([("search_path", schema.as_str())]);
+        let pool = PgPoolOptions::new()
+            .max_connections(5)
+            .connect_with(options)
+            .await
+            .unwrap();
+        schema::run(&Db::Postgres(pool.clone())).await.unwrap();
+
+        Self {
+            pool,
+            url,
+            schema,
+            _container: container,
+        }
+    }
+
+    fn repository(&self, driver: EncryptedClientDriver) -> UserRepository {
+        UserRepository::new(self.pool.clone(), driver)
+    }
+
+    /// Drop the schema (a failed test leaves it behind for inspection)
+    async fn teardown(self) {
+        self.pool.close().await;
+        let admin = PgPool::connect(&self.url).await.unwrap();
+        sqlx::query(&format!("DROP SCHEMA {} CASCADE", self.schema))
+            .execute(&admin)
+            .await
+            .unwrap();
+        admin.close().await;
+    }
+}
+
+fn driver() -> EncryptedClientDriver {
+    EncryptedClientDriver::new(&ColumnEncryptionKey::generate())
+}
+
+fn input(username: &str, email: &str) -> CreateUserInput {
+    CreateUserInput {
+        username: username.to_string(),
+        email: email.to_string(),
+        ssn: Some("123-45-6789".to_string()),
+        phone: None,
+        address: Some("1 Main St, Springfield".to_string()),
+    }
+}
+
+fn update(email: &str, version: Option<i32>) -> UpdateUserInput {
+    UpdateUserInput {
+        email: Some(email.to_string()),
+        ssn: None,
+        phone: None,
+        address: None,
+        version,
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs Docker or TEST_DATABASE_URL"]
+async fn crud_roundtrip_stores_only_ciphertext() {
+    let db = TestDb::start().await;
+    let repo = db.repository(driver());
+
+    let user = repo
+        .create(input("alice", "alice@example.com"))
+        .await
+        .unwrap();
+    assert_eq!(user.email, "alice@example.com");
+    assert_eq!(user.phone, None);
+
+    // What the database holds is ciphertext
+    let raw = repo.get_raw_encrypted(user.id).await.unwrap();
+    assert_ne!(raw.encrypted_email, "alice@example.com");
+    assert!(!raw.encrypted_ssn.unwrap().contains("6789"));
+    assert_eq!(raw.encrypted_phone, None);
+
+    let updated = repo
+        .update(user.id, update("alice@example.org", Some(user.version)))
+        .await
+        .unwrap();
+    assert_eq!(updated.version, user.version + 1);
+    assert_eq!(updated.ssn.as_deref(), Some("123-45-6789"));
+    assert_eq!(
+        repo.get_by_id(user.id).await.unwrap().email,
+        "alice@example.org"
+    );
+
+    // A writer holding the old version loses
+    let stale = repo
+        .update(user.id, update("mallory@example.com", Some(user.version)))
+        .await;
+    assert!(matches!(stale, Err(RepositoryError::Conflict { .. })));
+
+    // Another key cannot read the row
+    assert!(db.repository(driver()).get_by_id(user.id).await.is_err());
+
+    assert!(repo.delete(user.id).await.unwrap());
+    assert!(matches!(
+        repo.get_by_id(user.id).await,
+        Err(RepositoryError::NotFound(_))
+    ));
+
+    db.teardown().await;
+}
+
+#[tokio::test]
+#[ignore = "needs Docker or TEST_DATABASE_URL"]
+async fn key_rotation_reencrypts_every_row() {
+    let db = TestDb::start().await;
+    let old_cek = ColumnEncryptionKey::generate();
+    let new_cek = ColumnEncryptionKey::generate();
+
+    let old_repo = db.repository(EncryptedClientDriver::new(&old_cek));
+    for i in 0..7 {
+        old_repo
+            .create(input(&format!("user{i}"), &format!("user{i}@example.com")))
+            .await
+            .unwrap();
+    }
+
+    // New key, old key still readable while the worker runs
+    let rotating =
+        EncryptedClientDriver::new(&new_cek).with_previous(EncryptedClientDriver::new(&old_cek));
+    let config = RotationConfig {
+        name: "e2e".to_string(),
+        rows_per_sec: 0,
+        batch_size: 3,
+        restart: false,
+    };
+    let mut batches = 0;
+    let progress = RotationWorker::new(&db.pool, &rotating, config.clone())
+        .unwrap()
+        .run(|_| batches += 1)
+        .await
+        .unwrap();
+    assert_eq!(progress.rows_done, 7);
+    assert_eq!(progress.rows_rewritten, 7);
+    assert!(progress.finished_at.is_some());
+    assert_eq!(batches, 4); // initial report + 3 batches
+
+    // Everything reads with the new key alone; the old key is retired
+    let new_repo = db.repository(EncryptedClientDriver::new(&new_cek));
+    let users = new_repo.list_all().await.unwrap();
+    assert_eq!(users.len(), 7);
+    assert!(users
+        .iter()
+        .all(|u| u.ssn.as_deref() == Some("123-45-6789")));
+    assert!(old_repo.list_all().await.is_err());
+
+    // Restarting finds nothing left to do
+    let rerun = RotationWorker::new(
+        &db.pool,
+        &rotating,
+        RotationConfig {
+            restart: true,
+            ..config
+        },
+    )
+    .unwrap()
+    .run(|_| {})
+    .await
+    .unwrap();
+    assert_eq!(rerun.rows_done, 7);
+    assert_eq!(rerun.rows_rewritten, 0);
+
+    db.teardown().await;
+}
+
+#[tokio::test]
+#[ignore = "needs Docker or TEST_DATABASE_URL"]
+async fn blind_index_search_follows_writes() {
+    let db = TestDb::start().await;
+    let index_key = IndexKey::derive(&MasterKey::generate(), "users.search_index").unwrap();
+    let repo = db
+        .repository(driver())
+        .with_search_index(SearchIndex::new(index_key, vec!["email".to_string()]));
+
+    let alice = repo
+        .create(input("alice", "alice@wonderland.example"))
+        .await
+        .unwrap();
+    repo.create(input("bob", "bob@builder.example"))
+        .await
+        .unwrap();
+
+    let hits = repo.search("email", "WONDER").await.unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].username, "alice");
+
+    // The index only holds HMAC tokens, never plaintext
+    let leaked: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM users_search_index WHERE token LIKE '%won%'")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+    assert_eq!(leaked, 0);
+
+    // Updates replace the old tokens
+    repo.update(alice.id, update("alice@looking-glass.example", None))
+        .await
+        .unwrap();
+    assert!(repo.search("email", "wonder").await.unwrap().is_empty());
+    assert_eq!(repo.search("email", "glass").await.unwrap().len(), 1);
+
+    assert!(matches!(
+        repo.search("ssn", "123").await,
+        Err(RepositoryError::InvalidSearch(_))
+    ));
+
+    db.teardown().await;
+}
@@ -0,0 +1,106 @@
+//! `--by-extension` and `--by-age`: each directory's files added up by
+//! extension and by how long ago they were modified.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
+
+use crate::tree::Metric;
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Totals for a group of files
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct Usage {
+    pub apparent_size: u64,
+    pub disk_usage: u64,
+    pub files: u64,
+}
+
+impl Usage {
+    pub fn total(&self, metric: Metric) -> u64 {
+        match metric {
+            Metric::Disk => self.disk_usage,
+            Metric::Apparent => self.apparent_size,
+        }
+    }
+
+    fn add(&mut self, other: &Usage) {
+        self.apparent_size += other.apparent_size;
+        self.disk_usage += other.disk_usage;
+        self.files += other.files;
+    }
+}
+
+/// How long ago a file was last modified
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum Age {
+    #[serde(rename = "<30d")]
+    Recent,
+    #[serde(rename = "30-365d")]
+    Months,
+    #[serde(rename = ">1y")]
+    Old,
+}
+
+impl Age {
+    /// The bucket for a file modified at `modified`, as of `now`; files
+    /// from the future count as recent
+    pub fn of(modified: SystemTime, now: SystemTime) -> Age {
+        let age = now.duration_since(modified).unwrap_or_default();
+        if age < 30 * DAY {
+            Age::Recent
+        } else if age < 365 * DAY {
+            Age::Months
+        } else {
+            Age::Old
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Age::Recent => "<30d",
+            Age::Months => "30-365d",
+            Age::Old => ">1y",
+        }
+    }
+}
+
+/// A directory's files by extension and by age; either is empty unless the
+/// scan was asked for it
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Breakdown {
+    /// Keyed by lowercase extension, `(none)` for files without one
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub extensions: BTreeMap<String, Usage>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub ages: BTreeMap<Age, Usage>,
+}
+
+impl Breakdown {
+    /// Count a file, under `extension` and `age` if they were asked for
+    pub fn add_file(&mut self, extension: Option<&str>, age: Option<Age>, usage: &Usage) {
+        if let Some(extension) = extension {
+            self.extensions
+                .entry(extension.to_string())
+                .or_default()
+                .add(usage);
+        }
+        if let Some(age) = age {
+            self.ages.entry(age).or_default().add(usage);
+        }
+    }
+
+    /// Count everything in a subdirectory's breakdown
+    pub fn merge(&mut self, other: &Breakdown) {
+        for (extension, usage) in &other.extensions {
+            self.extensions
+                .entry(extension.clone())
+                .or_default()
+                .add(usage);
+        }
+        for (age, usage) in &other.ages {
+            self.ages.entry(*age).or_default().add(usage);
+        }
+    }
+}
@@ -1,84 +1,345 @@
-use clap::Parser;
-use std::fs;
-use std::io::{self, BufReader, Read};
-use std::os::unix::fs::MetadataExt;
-use std::sync::atomic::{AtomicUsize};
+mod breakdown;
+mod output;
+mod progress;
+mod snapshot;
+mod tree;
+mod tui;
 
+use clap::{Parser, ValueEnum};
+use glob::Pattern;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::atomic::Ordering;
 
-static FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
-static DIR_COUNTER: AtomicUsize = AtomicUsize::new(0);
+use breakdown::Breakdown;
+use output::{Format, fmt_bytes};
+use progress::Progress;
+use tree::{DIR_COUNTER, Dir, FILE_COUNTER, Filter, Links, Metric, ScanError, Scanned};
 
 
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum SortBy {
+    /// Largest first
+    Size,
+    /// By path
+    Name,
+}
 
 
-fn finc(){
-    FILE_COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+/// Which paths to scan, and how
+#[derive(clap::Args, Debug)]
+struct ScanArgs {
+    /// Directories or files to scan, each with its own totals; the working
+    /// directory when omitted
+    paths: Vec<String>,
+    /// Same as a path argument, for scripts that pass it this way
+    #[arg(short, long = "path", hide = true)]
+    path_flags: Vec<String>,
+    /// Skip entries whose name or path matches this glob, like
+    /// 'node_modules' or '*/.cache'; repeatable
+    #[arg(short, long)]
+    exclude: Vec<Pattern>,
+    /// Skip hidden entries (names starting with a dot)
+    #[arg(long)]
+    skip_hidden: bool,
+    /// Stay on the filesystem of the path, skipping mount points below it
+    #[arg(short = 'x', long)]
+    one_file_system: bool,
+    /// Count hardlinked files once per link instead of once overall
+    #[arg(short = 'l', long)]
+    count_links: bool,
+    /// Scan what symlinks below the path point to, entering each directory
+    /// once; by default a symlink counts as the small file it is
+    #[arg(short = 'L', long)]
+    follow_symlinks: bool,
+    /// Do not show a progress line while scanning
+    #[arg(short, long)]
+    quiet: bool,
 }
 
 
-fn dinc(){
-    DIR_COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Scan and save every directory's totals to a JSON file, to diff later
+    Snapshot {
+        #[command(flatten)]
+        scan: ScanArgs,
+        /// File to write the snapshot to
+        #[arg(short, long)]
+        out: String,
+    },
+    /// Compare two snapshots, listing the directories that grew or shrank
+    /// the most
+    Diff {
+        old: String,
+        new: String,
+        /// Only list this many directories
+        #[arg(short, long, default_value_t = 20)]
+        top: usize,
+        #[arg(short, long, value_enum, default_value_t = Metric::Disk)]
+        metric: Metric,
+    },
 }
 
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    scan: ScanArgs,
+    /// List directories down to this many levels below the path (0 for just
+    /// the path itself); every directory when omitted
     #[arg(short, long)]
-    path: String,
+    depth: Option<usize>,
+    #[arg(short, long, value_enum, default_value_t = SortBy::Size)]
+    sort: SortBy,
+    /// Only list this many directories, after sorting
+    #[arg(short, long)]
+    top: Option<usize>,
+    #[arg(short, long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+    /// Size that totals, --sort size and text output go by
+    #[arg(short, long, value_enum, default_value_t = Metric::Disk)]
+    metric: Metric,
+    /// Add up each directory's files by extension, as a table for the path
+    /// in text output and per directory in JSON
+    #[arg(long)]
+    by_extension: bool,
+    /// Add up each directory's files by how long ago they were modified
+    /// (under 30 days, 30-365 days, over a year), like --by-extension
+    #[arg(long)]
+    by_age: bool,
+    /// Browse the scanned tree interactively, with the option to delete
+    /// entries, instead of printing it
+    #[arg(long, conflicts_with_all = ["format", "top"])]
+    tui: bool,
     // #[arg(short, long)]
     // outfile: String,
 }
 
 
-fn fmt_bytes(bytes: u64) -> String {
-    let units = ["B", "KB", "MB", "GB", "TB"];
-    if bytes < 1024 {
-        return format!("{} {}", bytes, units[0]);
+/// The directories to print for `args`, du-style: down to `--depth` in every
+/// root, sorted together, and cut to `--top`
+fn listing<'a>(roots: &'a [Dir], args: &Args) -> Vec<&'a Dir> {
+    let mut dirs: Vec<&Dir> = roots.iter().flat_map(|root| root.walk(args.depth)).collect();
+    match args.sort {
+        SortBy::Size => dirs.sort_by(|a, b| {
+            let (a_size, b_size) = (a.total(args.metric), b.total(args.metric));
+            b_size.cmp(&a_size).then_with(|| a.path.cmp(&b.path))
+        }),
+        SortBy::Name => dirs.sort_by(|a, b| a.path.cmp(&b.path)),
+    }
+    if let Some(top) = args.top {
+        dirs.truncate(top);
+    }
+    dirs
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    match &args.command {
+        None => show(&args),
+        Some(Command::Snapshot { scan, out }) => snapshot(scan, out),
+        Some(Command::Diff {
+            old,
+            new,
+            top,
+            metric,
+        }) => diff(old, new, *top, *metric),
+    }
+}
+
+impl ScanArgs {
+    fn paths(&self) -> Vec<&str> {
+        let paths: Vec<&str> = self
+            .paths
+            .iter()
+            .chain(&self.path_flags)
+            .map(String::as_str)
+            .collect();
+        if paths.is_empty() { vec!["."] } else { paths }
     }
-    let mut size = bytes as f64;
-    let mut unit_idx = 0;
-    while size >= 1024.0 && unit_idx < units.len() - 1 {
-        size /= 1024.0;
-        unit_idx += 1;
+
+    /// What a scan by these arguments leaves out and adds up
+    fn filter(&self, breakdowns: Breakdowns) -> Filter {
+        Filter {
+            exclude: self.exclude.clone(),
+            skip_hidden: self.skip_hidden,
+            one_file_system: self.one_file_system,
+            count_links: self.count_links,
+            follow_symlinks: self.follow_symlinks,
+            by_extension: breakdowns.by_extension,
+            by_age: breakdowns.by_age,
+        }
     }
-    format!("{:.1} {}", size, units[unit_idx])
 }
 
+/// Which breakdowns a scan adds up
+#[derive(Clone, Copy, Debug, Default)]
+struct Breakdowns {
+    by_extension: bool,
+    by_age: bool,
+}
 
-fn tree_size(path: &str) -> Result<u64, io::Error> {
-    let mut ts = 0;
-    let metadata = fs::metadata(path)?;
+/// The scans of every root that could be read
+struct Scans {
+    roots: Vec<Dir>,
+    errors: Vec<ScanError>,
+    /// Whether a root could not be read at all
+    failed: bool,
+}
 
+/// Scan the paths `args` name in turn, with a progress line unless they say
+/// not to, breaking sizes down as `breakdowns` says. A root that cannot be
+/// read is reported and left out, and fails the run once the rest are done.
+fn scan(args: &ScanArgs, breakdowns: Breakdowns) -> Scans {
+    let filter = args.filter(breakdowns);
+    let progress = if args.quiet { None } else { Progress::start() };
+    let mut scans = Scans {
+        roots: Vec::new(),
+        errors: Vec::new(),
+        failed: false,
+    };
+    let mut unreadable = Vec::new();
+    let links = Links::default();
+    for path in args.paths() {
+        match tree::scan(Path::new(path), &filter, &links) {
+            Ok(Scanned { root, errors }) => {
+                scans.roots.push(root);
+                scans.errors.extend(errors);
+            }
+            Err(e) => unreadable.push(format!("Error reading {}: {}", path, e)),
+        }
+    }
+    if let Some(progress) = progress {
+        progress.finish();
+    }
+    for message in &unreadable {
+        eprintln!("{}", message);
+    }
+    scans.failed = !unreadable.is_empty();
+    scans
+}
 
-    if metadata.is_dir() {
-        dinc();
-        let paths = fs::read_dir(path).unwrap();
-        for p in paths {
-            let new_path =  p.unwrap().path();
-            let np = new_path.to_str().unwrap();
-            ts += tree_size(np).unwrap();
+/// Scan and print the trees, or browse one with `--tui`
+fn show(args: &Args) -> ExitCode {
+    if args.tui && args.scan.paths().len() > 1 {
+        eprintln!("--tui browses one path at a time");
+        return ExitCode::FAILURE;
+    }
+    let breakdowns = Breakdowns {
+        by_extension: args.by_extension,
+        by_age: args.by_age,
+    };
+    let scans = scan(&args.scan, breakdowns);
+    if args.tui {
+        let Some(root) = scans.roots.into_iter().next() else {
+            return ExitCode::FAILURE;
+        };
+        if let Err(e) = tui::run(root, args.metric, &args.scan.filter(breakdowns)) {
+            eprintln!("Error running the browser: {}", e);
+            return ExitCode::FAILURE;
         }
-        println!("Path:{} : {}",path, fmt_bytes(ts));
-    }else{
-        finc();
-        ts = metadata.size();
-        println!("File:{} : {}",path, fmt_bytes(ts));
-        return Ok(ts);
-    }
-    Ok(ts)
+        return report_errors(&scans.errors, scans.failed);
+    }
+    let dirs = listing(&scans.roots, args);
+    if let Err(e) = output::write(io::stdout().lock(), &dirs, args.format, args.metric) {
+        eprintln!("Error writing output: {}", e);
+        return ExitCode::FAILURE;
+    }
+    if args.format == Format::Text
+        && let Err(e) = summary(&scans.roots, args.metric)
+    {
+        eprintln!("Error writing output: {}", e);
+        return ExitCode::FAILURE;
+    }
+
+    report_errors(&scans.errors, scans.failed)
 }
 
-fn main() {
-    // let args = Args::parse();
-    // match tree_size(&args.path) {
-    match tree_size("./") {
-        Ok(sz) => println!("treesize: {}", sz),
-        Err(e) => eprintln!("Error reading file: {}", e),
-    }
-    println!("Total files:{}   dirs: {}", 
-        FILE_COUNTER.load(std::sync::atomic::Ordering::Relaxed),
-        DIR_COUNTER.load(std::sync::atomic::Ordering::Relaxed)
-    
+/// The totals after a text listing: per root when there are several, then
+/// overall, then the breakdowns of everything scanned
+fn summary(roots: &[Dir], metric: Metric) -> io::Result<()> {
+    let mut out = io::stdout().lock();
+    let (mut size, mut disk) = (0, 0);
+    let mut breakdown = Breakdown::default();
+    for root in roots {
+        size += root.size;
+        disk += root.disk;
+        breakdown.merge(&root.breakdown);
+    }
+    if roots.len() > 1 {
+        output::write_roots(&mut out, roots, metric)?;
+    }
+    let total = match metric {
+        Metric::Disk => disk,
+        Metric::Apparent => size,
+    };
+    writeln!(
+        out,
+        "treesize: {} apparent, {} on disk ({} bytes)",
+        fmt_bytes(size),
+        fmt_bytes(disk),
+        total
+    )?;
+    writeln!(out, "Total files:{}   dirs: {}",
+        FILE_COUNTER.load(Ordering::Relaxed),
+        DIR_COUNTER.load(Ordering::Relaxed)
+    )?;
+    output::write_breakdown(&mut out, &breakdown, metric)
+}
+
+fn snapshot(args: &ScanArgs, out: &str) -> ExitCode {
+    let scans = scan(args, Breakdowns::default());
+    if scans.roots.is_empty() {
+        return ExitCode::FAILURE;
+    }
+    if let Err(e) = snapshot::save(&scans.roots, out) {
+        eprintln!("Error writing {}: {}", out, e);
+        return ExitCode::FAILURE;
+    }
+    println!(
+        "Saved {} directories under {} to {}",
+        DIR_COUNTER.load(Ordering::Relaxed),
+        args.paths().join(", "),
+        out
     );
+    report_errors(&scans.errors, scans.failed)
+}
+
+fn diff(old: &str, new: &str, top: usize, metric: Metric) -> ExitCode {
+    let snapshots = snapshot::load(old).and_then(|old| Ok((old, snapshot::load(new)?)));
+    let (old, new) = match snapshots {
+        Ok(snapshots) => snapshots,
+        Err(e) => {
+            eprintln!("Error reading snapshot: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut changes = snapshot::diff(&old, &new, metric);
+    changes.truncate(top);
+    if let Err(e) = output::write_changes(io::stdout().lock(), &changes) {
+        eprintln!("Error writing output: {}", e);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+/// Like du: report what was left out, and fail so scripts notice the totals
+/// are short. `failed` is whether a whole root was left out.
+fn report_errors(errors: &[ScanError], failed: bool) -> ExitCode {
+    for skipped in errors {
+        eprintln!("Skipped {}: {}", skipped.path.display(), skipped.error);
+    }
+    if !errors.is_empty() {
+        eprintln!("Skipped {} unreadable paths; totals leave them out", errors.len());
+        return ExitCode::FAILURE;
+    }
+    if failed {
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
 }
@@ -0,0 +1,185 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::io::{self, Write};
+
+use crate::breakdown::{Breakdown, Usage};
+use crate::snapshot::Change;
+use crate::tree::{Dir, Metric};
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Format {
+    /// Aligned sizes and paths, with totals at the end
+    Text,
+    /// One JSON object per line (NDJSON)
+    Json,
+    /// A header row, then one row per directory
+    Csv,
+}
+
+/// One line of machine-readable output, and one directory of a snapshot
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Record<'a> {
+    pub path: Cow<'a, str>,
+    pub apparent_size: u64,
+    pub disk_usage: u64,
+    pub files: u64,
+}
+
+impl<'a> Record<'a> {
+    pub fn new(dir: &'a Dir) -> Self {
+        Record {
+            path: dir.path.to_string_lossy(),
+            apparent_size: dir.size,
+            disk_usage: dir.disk,
+            files: dir.files,
+        }
+    }
+}
+
+pub fn fmt_bytes(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} {}", bytes, units[0]);
+    }
+    let mut size = bytes as f64;
+    let mut unit_idx = 0;
+    while size >= 1024.0 && unit_idx < units.len() - 1 {
+        size /= 1024.0;
+        unit_idx += 1;
+    }
+    format!("{:.1} {}", size, units[unit_idx])
+}
+
+/// Write `dirs` to `out` in `format`; text shows sizes by `metric`, the
+/// other formats both
+pub fn write(out: impl Write, dirs: &[&Dir], format: Format, metric: Metric) -> io::Result<()> {
+    match format {
+        Format::Text => text(out, dirs, metric),
+        Format::Json => json(out, dirs),
+        Format::Csv => csv(out, dirs),
+    }
+}
+
+fn text(mut out: impl Write, dirs: &[&Dir], metric: Metric) -> io::Result<()> {
+    for dir in dirs {
+        let size = fmt_bytes(dir.total(metric));
+        writeln!(out, "{:>10}  {}", size, dir.path.display())?;
+    }
+    Ok(())
+}
+
+/// A directory's record with its breakdowns, if the scan made any
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    #[serde(flatten)]
+    record: Record<'a>,
+    #[serde(flatten)]
+    breakdown: &'a Breakdown,
+}
+
+fn json(mut out: impl Write, dirs: &[&Dir]) -> io::Result<()> {
+    for dir in dirs {
+        let record = JsonRecord {
+            record: Record::new(dir),
+            breakdown: &dir.breakdown,
+        };
+        serde_json::to_writer(&mut out, &record)?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+fn csv(out: impl Write, dirs: &[&Dir]) -> io::Result<()> {
+    let mut writer = csv::Writer::from_writer(out);
+    for dir in dirs {
+        writer.serialize(Record::new(dir))?;
+    }
+    writer.flush()
+}
+
+/// Most extensions to list in a text breakdown
+const EXTENSIONS_SHOWN: usize = 10;
+
+/// Write `breakdown` as tables, largest extensions first (only the top few)
+/// and ages from newest to oldest
+pub fn write_breakdown(
+    mut out: impl Write,
+    breakdown: &Breakdown,
+    metric: Metric,
+) -> io::Result<()> {
+    let row = |out: &mut dyn Write, usage: &Usage, label: &str| {
+        writeln!(
+            out,
+            "{:>10}  {:>8} files  {}",
+            fmt_bytes(usage.total(metric)),
+            usage.files,
+            label
+        )
+    };
+    if !breakdown.extensions.is_empty() {
+        let mut extensions: Vec<_> = breakdown.extensions.iter().collect();
+        extensions.sort_by(|a, b| b.1.total(metric).cmp(&a.1.total(metric)).then(a.0.cmp(b.0)));
+        writeln!(out, "By extension:")?;
+        for (extension, usage) in extensions.iter().take(EXTENSIONS_SHOWN) {
+            row(&mut out, usage, extension)?;
+        }
+        if extensions.len() > EXTENSIONS_SHOWN {
+            writeln!(
+                out,
+                "{:>10}  and {} more",
+                "",
+                extensions.len() - EXTENSIONS_SHOWN
+            )?;
+        }
+    }
+    if !breakdown.ages.is_empty() {
+        writeln!(out, "By age:")?;
+        for (age, usage) in &breakdown.ages {
+            row(&mut out, usage, age.label())?;
+        }
+    }
+    Ok(())
+}
+
+/// Write each root's total by `metric` and its share of all of them,
+/// largest first
+pub fn write_roots(mut out: impl Write, roots: &[Dir], metric: Metric) -> io::Result<()> {
+    let total: u64 = roots.iter().map(|root| root.total(metric)).sum();
+    let mut roots: Vec<&Dir> = roots.iter().collect();
+    roots.sort_by(|a, b| {
+        b.total(metric)
+            .cmp(&a.total(metric))
+            .then(a.path.cmp(&b.path))
+    });
+    writeln!(out, "By root:")?;
+    for root in roots {
+        let share = root.total(metric) as f64 * 100.0 / total.max(1) as f64;
+        writeln!(
+            out,
+            "{:>10}  {:>5.1}%  {:>8} files  {}",
+            fmt_bytes(root.total(metric)),
+            share,
+            root.files,
+            root.path.display()
+        )?;
+    }
+    Ok(())
+}
+
+/// Write the changes between two snapshots, biggest first as they come
+pub fn write_changes(mut out: impl Write, changes: &[Change]) -> io::Result<()> {
+    for change in changes {
+        let sign = if change.new < change.old { "-" } else { "+" };
+        let delta = fmt_bytes(change.old.abs_diff(change.new));
+        writeln!(
+            out,
+            "{:>11}  {}  ({} -> {})",
+            format!("{sign}{delta}"),
+            change.path,
+            fmt_bytes(change.old),
+            fmt_bytes(change.new)
+        )?;
+    }
+    Ok(())
+}
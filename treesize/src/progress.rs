@@ -0,0 +1,48 @@
+//! A live progress line on stderr while a scan runs, read off the scan's
+//! counters.
+
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::output::fmt_bytes;
+use crate::tree::{BYTES_COUNTER, DIR_COUNTER, FILE_COUNTER};
+
+const INTERVAL: Duration = Duration::from_millis(200);
+
+pub struct Progress {
+    stop: Sender<()>,
+    printer: JoinHandle<()>,
+}
+
+impl Progress {
+    /// Start updating the line, unless stderr is not a terminal (a log file
+    /// does not want hundreds of carriage returns)
+    pub fn start() -> Option<Progress> {
+        if !io::stderr().is_terminal() {
+            return None;
+        }
+        let (stop, stopped) = mpsc::channel();
+        let printer = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(INTERVAL) {
+                eprint!(
+                    "\r\x1b[2KScanning: {} dirs, {} files, {}",
+                    DIR_COUNTER.load(Ordering::Relaxed),
+                    FILE_COUNTER.load(Ordering::Relaxed),
+                    fmt_bytes(BYTES_COUNTER.load(Ordering::Relaxed))
+                );
+                let _ = io::stderr().flush();
+            }
+            eprint!("\r\x1b[2K");
+        });
+        Some(Progress { stop, printer })
+    }
+
+    /// Stop updating and clear the line
+    pub fn finish(self) {
+        let _ = self.stop.send(());
+        let _ = self.printer.join();
+    }
+}
@@ -0,0 +1,178 @@
+//! `treesize snapshot` and `treesize diff`: save every directory's totals to
+//! a JSON file, and compare two such files to see what grew.
+
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::output::Record;
+use crate::tree::{Dir, Metric};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot<'a> {
+    /// The paths that were scanned
+    pub roots: Vec<Cow<'a, str>>,
+    /// When, in seconds since the Unix epoch
+    pub taken_at: u64,
+    /// Every directory under each root, the root first
+    pub dirs: Vec<Record<'a>>,
+}
+
+/// How one directory's size changed between two snapshots; a directory in
+/// only one of them has a size of 0 in the other
+#[derive(Debug, PartialEq)]
+pub struct Change {
+    pub path: String,
+    pub old: u64,
+    pub new: u64,
+}
+
+pub fn save(roots: &[Dir], path: &str) -> io::Result<()> {
+    let snapshot = Snapshot {
+        roots: roots
+            .iter()
+            .map(|root| root.path.to_string_lossy())
+            .collect(),
+        taken_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs()),
+        dirs: roots
+            .iter()
+            .flat_map(|root| root.walk(None))
+            .map(Record::new)
+            .collect(),
+    };
+    let mut out = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(&mut out, &snapshot)?;
+    writeln!(out)?;
+    out.flush()
+}
+
+pub fn load(path: &str) -> io::Result<Snapshot<'static>> {
+    let file = File::open(path).map_err(|e| io::Error::new(e.kind(), format!("{path}: {e}")))?;
+    serde_json::from_reader(BufReader::new(file))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{path}: {e}")))
+}
+
+/// Every directory whose size by `metric` changed from `old` to `new`,
+/// biggest change first
+pub fn diff(old: &Snapshot, new: &Snapshot, metric: Metric) -> Vec<Change> {
+    let size = |record: &Record| match metric {
+        Metric::Disk => record.disk_usage,
+        Metric::Apparent => record.apparent_size,
+    };
+    let mut before: HashMap<&str, u64> = old
+        .dirs
+        .iter()
+        .map(|record| (record.path.as_ref(), size(record)))
+        .collect();
+    let mut changes: Vec<Change> = new
+        .dirs
+        .iter()
+        .map(|record| Change {
+            path: record.path.to_string(),
+            old: before.remove(record.path.as_ref()).unwrap_or(0),
+            new: size(record),
+        })
+        .collect();
+    changes.extend(before.into_iter().map(|(path, old)| Change {
+        path: path.to_string(),
+        old,
+        new: 0,
+    }));
+    changes.retain(|change| change.old != change.new);
+    changes.sort_by(|a, b| {
+        let (a_delta, b_delta) = (a.old.abs_diff(a.new), b.old.abs_diff(b.new));
+        b_delta.cmp(&a_delta).then_with(|| a.path.cmp(&b.path))
+    });
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::{Filter, Links, scan};
+    use std::fs;
+
+    fn record(path: &'static str, size: u64) -> Record<'static> {
+        Record {
+            path: path.into(),
+            apparent_size: size,
+            disk_usage: size * 2,
+            files: 1,
+        }
+    }
+
+    fn snapshot(dirs: Vec<Record<'static>>) -> Snapshot<'static> {
+        Snapshot {
+            roots: vec!["/".into()],
+            taken_at: 0,
+            dirs,
+        }
+    }
+
+    #[test]
+    fn test_diff_orders_by_change() {
+        let old = snapshot(vec![
+            record("/a", 100),
+            record("/b", 50),
+            record("/gone", 30),
+        ]);
+        let new = snapshot(vec![
+            record("/a", 100),
+            record("/b", 10),
+            record("/new", 70),
+        ]);
+        let change = |path: &str, old, new| Change {
+            path: path.to_string(),
+            old,
+            new,
+        };
+        assert_eq!(
+            diff(&old, &new, Metric::Apparent),
+            [
+                change("/new", 0, 70),
+                change("/b", 50, 10),
+                change("/gone", 30, 0),
+            ]
+        );
+        assert_eq!(diff(&old, &new, Metric::Disk)[0], change("/new", 0, 140));
+        assert!(diff(&old, &old, Metric::Apparent).is_empty());
+    }
+
+    #[test]
+    fn test_saved_scans_diff() {
+        let dir = tempfile::tempdir().unwrap();
+        let (data, file) = (dir.path().join("data"), dir.path().join("snap.json"));
+        fs::create_dir_all(data.join("logs")).unwrap();
+        fs::write(data.join("logs/app.log"), vec![0u8; 1000]).unwrap();
+        let scan_data = || {
+            scan(&data, &Filter::default(), &Links::default())
+                .unwrap()
+                .root
+        };
+
+        let before = scan_data();
+        save(std::slice::from_ref(&before), file.to_str().unwrap()).unwrap();
+        let old = load(file.to_str().unwrap()).unwrap();
+        assert_eq!(old.roots, [data.to_string_lossy()]);
+        assert_eq!(old.dirs.len(), 2);
+        assert_eq!(old.dirs[0].apparent_size, before.size);
+        assert_eq!(old.dirs[1].files, 1);
+
+        fs::write(data.join("logs/app.log"), vec![0u8; 5000]).unwrap();
+        let after = scan_data();
+        save(std::slice::from_ref(&after), file.to_str().unwrap()).unwrap();
+        let new = load(file.to_str().unwrap()).unwrap();
+        let changes = diff(&old, &new, Metric::Apparent);
+        let paths: Vec<&str> = changes.iter().map(|change| change.path.as_str()).collect();
+        let logs = data.join("logs");
+        assert_eq!(paths, [data.to_str().unwrap(), logs.to_str().unwrap()]);
+        assert!(changes.iter().all(|change| change.new - change.old == 4000));
+
+        assert!(load(dir.path().join("missing").to_str().unwrap()).is_err());
+    }
+}
@@ -0,0 +1,555 @@
+use clap::ValueEnum;
+use glob::Pattern;
+use rayon::prelude::*;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+use crate::breakdown::{Age, Breakdown, Usage};
+
+pub static FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+pub static DIR_COUNTER: AtomicUsize = AtomicUsize::new(0);
+/// Apparent size of the files counted so far
+pub static BYTES_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+fn finc(size: u64) {
+    FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+    BYTES_COUNTER.fetch_add(size, Ordering::Relaxed);
+}
+
+fn dinc() {
+    DIR_COUNTER.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Which size totals, sorting and text output go by
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Metric {
+    /// Blocks allocated on disk, like `du`
+    Disk,
+    /// File lengths, like `du --apparent-size`
+    Apparent,
+}
+
+/// A scanned directory with the totals of everything under it
+#[derive(Debug)]
+pub struct Dir {
+    pub path: PathBuf,
+    /// Apparent size: the sum of file (and directory) lengths
+    pub size: u64,
+    /// Disk usage: the blocks allocated, which sparse files and small files
+    /// on large blocks make differ from the apparent size
+    pub disk: u64,
+    pub files: u64,
+    pub dirs: Vec<Dir>,
+    pub breakdown: Breakdown,
+}
+
+impl Dir {
+    pub fn total(&self, metric: Metric) -> u64 {
+        match metric {
+            Metric::Disk => self.disk,
+            Metric::Apparent => self.size,
+        }
+    }
+
+    /// This directory and every one below it down to `depth` levels (all of
+    /// them for `None`), parents before children
+    pub fn walk(&self, depth: Option<usize>) -> Vec<&Dir> {
+        let mut found = vec![self];
+        if depth != Some(0) {
+            for dir in &self.dirs {
+                found.extend(dir.walk(depth.map(|d| d - 1)));
+            }
+        }
+        found
+    }
+}
+
+/// How a scan runs: what it leaves out (never the path being scanned
+/// itself), how it treats links, and what it breaks sizes down by
+#[derive(Debug, Default)]
+pub struct Filter {
+    /// Entries whose name or path matches any of these
+    pub exclude: Vec<Pattern>,
+    /// Entries whose name starts with a dot
+    pub skip_hidden: bool,
+    /// Directories on another filesystem than the path, like mount points
+    pub one_file_system: bool,
+    /// Count a hardlinked file every time it is found, not just the first
+    pub count_links: bool,
+    /// Scan what symlinks point to rather than the links themselves
+    pub follow_symlinks: bool,
+    /// Add up each directory's files by extension
+    pub by_extension: bool,
+    /// Add up each directory's files by modification age
+    pub by_age: bool,
+}
+
+impl Filter {
+    /// Whether the scan leaves out `path`
+    pub fn excludes(&self, path: &Path) -> bool {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy())
+            .unwrap_or_default();
+        if self.skip_hidden && name.starts_with('.') {
+            return true;
+        }
+        self.exclude
+            .iter()
+            .any(|pattern| pattern.matches(&name) || pattern.matches_path(path))
+    }
+}
+
+/// The (device, inode) of every hardlinked file counted so far. The scans of
+/// several paths share one, so a file linked under more than one of them is
+/// counted once in all, like `du` does.
+#[derive(Debug, Default)]
+pub struct Links(Mutex<HashSet<(u64, u64)>>);
+
+enum Entry {
+    File {
+        usage: Usage,
+        /// The file's extension and age, if the breakdowns need them
+        extension: Option<String>,
+        age: Option<Age>,
+    },
+    Dir(Dir),
+    Skipped,
+}
+
+/// A path the scan could not read, and so left out of the totals
+#[derive(Debug)]
+pub struct ScanError {
+    pub path: PathBuf,
+    pub error: io::Error,
+}
+
+/// A finished scan: the tree, and every path it had to skip
+#[derive(Debug)]
+pub struct Scanned {
+    pub root: Dir,
+    pub errors: Vec<ScanError>,
+}
+
+struct Scan<'a> {
+    filter: &'a Filter,
+    /// The device the scanned path is on
+    device: u64,
+    links: &'a Links,
+    /// The (device, inode) of every directory entered so far, when following
+    /// symlinks, so a link back up the tree is not followed round and round
+    visited: Mutex<HashSet<(u64, u64)>>,
+    errors: Mutex<Vec<ScanError>>,
+    /// When the scan started, to tell file ages from
+    now: SystemTime,
+}
+
+/// Scan everything under `path` that `filter` lets through. The entries of
+/// each directory are walked in parallel on rayon's pool, whose work
+/// stealing keeps every thread busy however deep or lopsided the tree is.
+///
+/// `path` itself is followed if it is a symlink; below it, symlinks count as
+/// small files unless `filter` says to follow them, in which case every
+/// directory is entered once however many links lead to it.
+///
+/// Hardlinked files already in `links` are not counted again, not even as
+/// `path` itself: a root that is another name of a file counted before comes
+/// back empty.
+///
+/// Only failing to read `path` itself is an error; anything below it that
+/// cannot be read is skipped and listed in [`Scanned::errors`].
+pub fn scan(path: &Path, filter: &Filter, links: &Links) -> Result<Scanned, io::Error> {
+    let metadata = fs::metadata(path)?;
+    if metadata.is_dir() {
+        // Fail now rather than report an unreadable root as a skipped path
+        fs::read_dir(path)?;
+    }
+    let scan = Scan {
+        filter,
+        device: metadata.dev(),
+        links,
+        visited: Mutex::new(HashSet::new()),
+        errors: Mutex::new(Vec::new()),
+        now: SystemTime::now(),
+    };
+    let root = match scan.entry(path, true) {
+        Entry::Dir(dir) => dir,
+        Entry::File {
+            usage,
+            extension,
+            age,
+        } => {
+            let mut breakdown = Breakdown::default();
+            breakdown.add_file(extension.as_deref(), age, &usage);
+            Dir {
+                path: path.to_path_buf(),
+                size: usage.apparent_size,
+                disk: usage.disk_usage,
+                files: 1,
+                dirs: Vec::new(),
+                breakdown,
+            }
+        }
+        // Only a file counted under another name is skipped at the root
+        Entry::Skipped => Dir {
+            path: path.to_path_buf(),
+            size: 0,
+            disk: 0,
+            files: 0,
+            dirs: Vec::new(),
+            breakdown: Breakdown::default(),
+        },
+    };
+    let mut errors = scan.errors.into_inner().unwrap();
+    errors.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(Scanned { root, errors })
+}
+
+impl Scan<'_> {
+    fn entry(&self, path: &Path, top: bool) -> Entry {
+        let metadata = if top || self.filter.follow_symlinks {
+            fs::metadata(path)
+        } else {
+            fs::symlink_metadata(path)
+        };
+        let metadata = match metadata {
+            Ok(metadata) => metadata,
+            Err(error) => return self.skip(path, error),
+        };
+        if !metadata.is_dir() {
+            if metadata.nlink() > 1 && !self.filter.count_links && !self.first_link(&metadata) {
+                return Entry::Skipped;
+            }
+            finc(metadata.size());
+            return Entry::File {
+                usage: Usage {
+                    apparent_size: metadata.size(),
+                    disk_usage: metadata.blocks() * 512,
+                    files: 1,
+                },
+                extension: self.filter.by_extension.then(|| extension(path)),
+                age: self
+                    .filter
+                    .by_age
+                    .then(|| Age::of(metadata.modified().unwrap_or(self.now), self.now)),
+            };
+        }
+        if self.filter.one_file_system && metadata.dev() != self.device {
+            return Entry::Skipped;
+        }
+        if self.filter.follow_symlinks && !self.first_visit(&metadata) {
+            return Entry::Skipped;
+        }
+
+        dinc();
+        let mut paths = Vec::new();
+        match fs::read_dir(path) {
+            Ok(entries) => {
+                for entry in entries {
+                    match entry {
+                        Ok(entry) if self.filter.excludes(&entry.path()) => {}
+                        Ok(entry) => paths.push(entry.path()),
+                        Err(error) => {
+                            self.skip(path, error);
+                        }
+                    }
+                }
+            }
+            Err(error) => {
+                self.skip(path, error);
+            }
+        }
+        let entries = paths
+            .par_iter()
+            .map(|path| self.entry(path, false))
+            .collect::<Vec<_>>();
+        Entry::Dir(gather(path, &metadata, entries))
+    }
+
+    /// Note that `path` could not be read, to carry on without it
+    fn skip(&self, path: &Path, error: io::Error) -> Entry {
+        self.errors.lock().unwrap().push(ScanError {
+            path: path.to_path_buf(),
+            error,
+        });
+        Entry::Skipped
+    }
+
+    /// Whether this is the first time the scan, or another sharing its
+    /// links, finds the file, under any of its names
+    fn first_link(&self, metadata: &fs::Metadata) -> bool {
+        let mut links = self.links.0.lock().unwrap();
+        links.insert((metadata.dev(), metadata.ino()))
+    }
+
+    /// Whether this is the first time the scan enters the directory
+    fn first_visit(&self, metadata: &fs::Metadata) -> bool {
+        let mut visited = self.visited.lock().unwrap();
+        visited.insert((metadata.dev(), metadata.ino()))
+    }
+}
+
+/// A directory's totals: the sums of its `entries`, plus the size of the
+/// directory itself, as `du` counts them
+fn gather(path: &Path, metadata: &fs::Metadata, entries: Vec<Entry>) -> Dir {
+    let mut dir = Dir {
+        path: path.to_path_buf(),
+        size: metadata.len(),
+        disk: metadata.blocks() * 512,
+        files: 0,
+        dirs: Vec::new(),
+        breakdown: Breakdown::default(),
+    };
+    for entry in entries {
+        match entry {
+            Entry::File {
+                usage,
+                extension,
+                age,
+            } => {
+                dir.size += usage.apparent_size;
+                dir.disk += usage.disk_usage;
+                dir.files += 1;
+                dir.breakdown.add_file(extension.as_deref(), age, &usage);
+            }
+            Entry::Dir(child) => {
+                dir.size += child.size;
+                dir.disk += child.disk;
+                dir.files += child.files;
+                dir.breakdown.merge(&child.breakdown);
+                dir.dirs.push(child);
+            }
+            Entry::Skipped => {}
+        }
+    }
+    dir
+}
+
+/// A file's extension for `--by-extension`: lowercase, `(none)` if it has
+/// none
+fn extension(path: &Path) -> String {
+    path.extension().map_or_else(
+        || "(none)".to_string(),
+        |e| e.to_string_lossy().to_lowercase(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use std::time::Duration;
+
+    /// Apparent size of everything under `path`, added up one entry at a
+    /// time
+    fn du(path: &Path) -> u64 {
+        let metadata = fs::symlink_metadata(path).unwrap();
+        if !metadata.is_dir() {
+            return metadata.len();
+        }
+        let entries = fs::read_dir(path).unwrap();
+        metadata.len() + entries.map(|entry| du(&entry.unwrap().path())).sum::<u64>()
+    }
+
+    fn scan_with(path: &Path, filter: &Filter) -> Dir {
+        let scanned = scan(path, filter, &Links::default()).unwrap();
+        assert!(scanned.errors.is_empty());
+        scanned.root
+    }
+
+    fn child<'a>(dir: &'a Dir, name: &str) -> &'a Dir {
+        dir.dirs
+            .iter()
+            .find(|child| child.path.file_name().unwrap() == name)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parallel_walk_adds_up_every_level() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..12 {
+            let sub = dir.path().join(format!("d{i}")).join("deeper");
+            fs::create_dir_all(&sub).unwrap();
+            for j in 0..8 {
+                fs::write(sub.join(format!("f{j}")), vec![0u8; 100 * i + j]).unwrap();
+            }
+            fs::write(sub.parent().unwrap().join("top"), b"x").unwrap();
+        }
+
+        let root = scan_with(dir.path(), &Filter::default());
+        assert_eq!(root.files, 12 * 9);
+        assert_eq!(root.size, du(dir.path()));
+        assert_eq!(root.dirs.len(), 12);
+        assert_eq!(root.walk(None).len(), 1 + 12 * 2);
+        assert_eq!(root.walk(Some(1)).len(), 1 + 12);
+        for i in 0..12 {
+            let d = child(&root, &format!("d{i}"));
+            assert_eq!(d.files, 9);
+            assert_eq!(d.size, du(&d.path));
+            assert_eq!(child(d, "deeper").files, 8);
+        }
+    }
+
+    #[test]
+    fn test_excludes() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("build/out")).unwrap();
+        fs::create_dir_all(dir.path().join("src")).unwrap();
+        for file in [
+            "src/main.rs",
+            "src/debug.log",
+            "build/out/bin",
+            ".env",
+            "notes.log",
+        ] {
+            fs::write(dir.path().join(file), b"data").unwrap();
+        }
+
+        let all = scan_with(dir.path(), &Filter::default());
+        assert_eq!(all.files, 5);
+
+        let filter = Filter {
+            exclude: vec![
+                Pattern::new("*.log").unwrap(),
+                Pattern::new("build").unwrap(),
+            ],
+            skip_hidden: true,
+            ..Filter::default()
+        };
+        let root = scan_with(dir.path(), &filter);
+        assert_eq!(root.files, 1);
+        assert_eq!(root.dirs.len(), 1);
+        assert_eq!(child(&root, "src").files, 1);
+
+        // Patterns also match whole paths
+        let path = Pattern::new(&format!("{}/src/*", dir.path().display())).unwrap();
+        let filter = Filter {
+            exclude: vec![path],
+            ..Filter::default()
+        };
+        assert_eq!(scan_with(dir.path(), &filter).files, 3);
+
+        // The scanned path itself is never left out
+        let filter = Filter {
+            skip_hidden: true,
+            ..Filter::default()
+        };
+        assert_eq!(scan_with(&dir.path().join(".env"), &filter).files, 1);
+    }
+
+    #[test]
+    fn test_hardlinks_count_once() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("a")).unwrap();
+        fs::create_dir(dir.path().join("b")).unwrap();
+        fs::write(dir.path().join("a/f"), vec![0u8; 3000]).unwrap();
+        fs::hard_link(dir.path().join("a/f"), dir.path().join("b/g")).unwrap();
+        fs::hard_link(dir.path().join("a/f"), dir.path().join("a/h")).unwrap();
+
+        let root = scan_with(dir.path(), &Filter::default());
+        assert_eq!(root.files, 1);
+        assert_eq!(root.size, du(dir.path()) - 2 * 3000);
+
+        let filter = Filter {
+            count_links: true,
+            ..Filter::default()
+        };
+        let root = scan_with(dir.path(), &filter);
+        assert_eq!(root.files, 3);
+        assert_eq!(root.size, du(dir.path()));
+    }
+
+    #[test]
+    fn test_root_linked_to_another_root_counts_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let (f, g) = (dir.path().join("f"), dir.path().join("g"));
+        fs::write(&f, vec![0u8; 5000]).unwrap();
+        fs::hard_link(&f, &g).unwrap();
+
+        let (filter, links) = (Filter::default(), Links::default());
+        let first = scan(&f, &filter, &links).unwrap().root;
+        assert_eq!((first.size, first.files), (5000, 1));
+        for again in [&g, &f] {
+            let root = scan(again, &filter, &links).unwrap().root;
+            assert_eq!((root.size, root.disk, root.files), (0, 0, 0));
+        }
+    }
+
+    #[test]
+    fn test_symlink_cycles_end() {
+        let dir = tempfile::tempdir().unwrap();
+        let sub = dir.path().join("sub");
+        fs::create_dir(&sub).unwrap();
+        fs::write(sub.join("f"), vec![0u8; 2000]).unwrap();
+        symlink("..", sub.join("up")).unwrap();
+        symlink(&sub, dir.path().join("again")).unwrap();
+
+        // Links are small files of their own
+        let root = scan_with(dir.path(), &Filter::default());
+        assert_eq!(root.files, 3);
+
+        // Followed, every directory is entered once: `up` leads back to the
+        // root and `again` to `sub`, both already entered
+        let filter = Filter {
+            follow_symlinks: true,
+            ..Filter::default()
+        };
+        let root = scan_with(dir.path(), &filter);
+        assert_eq!(root.files, 1);
+        assert_eq!(root.walk(None).len(), 2);
+    }
+
+    #[test]
+    fn test_breakdowns() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        let now = SystemTime::now();
+        for (file, days) in [
+            ("a.TXT", 0),
+            ("sub/b.txt", 100),
+            ("sub/c.rs", 400),
+            ("d", 0),
+        ] {
+            let path = dir.path().join(file);
+            fs::write(&path, vec![0u8; 10]).unwrap();
+            let modified = now - Duration::from_secs(days * 24 * 60 * 60);
+            fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        }
+
+        let root = scan_with(dir.path(), &Filter::default());
+        assert_eq!(root.breakdown, Breakdown::default());
+
+        let filter = Filter {
+            by_extension: true,
+            by_age: true,
+            ..Filter::default()
+        };
+        let root = scan_with(dir.path(), &filter);
+        let files = |usage: Option<&Usage>| usage.map(|usage| usage.files);
+        let extensions = &root.breakdown.extensions;
+        assert_eq!(extensions.len(), 3);
+        assert_eq!(files(extensions.get("txt")), Some(2));
+        assert_eq!(files(extensions.get("rs")), Some(1));
+        assert_eq!(files(extensions.get("(none)")), Some(1));
+        assert_eq!(extensions["txt"].apparent_size, 20);
+        let ages = &root.breakdown.ages;
+        assert_eq!(files(ages.get(&Age::Recent)), Some(2));
+        assert_eq!(files(ages.get(&Age::Months)), Some(1));
+        assert_eq!(files(ages.get(&Age::Old)), Some(1));
+
+        let sub = &child(&root, "sub").breakdown;
+        assert_eq!(sub.extensions.keys().collect::<Vec<_>>(), ["rs", "txt"]);
+        assert_eq!(files(sub.ages.get(&Age::Recent)), None);
+    }
+}
@@ -0,0 +1,312 @@
+//! `--tui`: browse a scanned tree like ncdu, drawn with ratatui.
+
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{List, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::output::fmt_bytes;
+use crate::tree::{Dir, Filter, Metric};
+
+const HELP: &str = "up/down move, right/enter open, left back, s sort, d delete, q quit";
+
+enum Key {
+    Up,
+    Down,
+    Open,
+    Back,
+    Sort,
+    Delete,
+    Yes,
+    Quit,
+    /// Any other key, or the terminal being resized: just redraw
+    Other,
+}
+
+fn read_key() -> io::Result<Key> {
+    loop {
+        let key = match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => key,
+            Event::Resize(..) => return Ok(Key::Other),
+            _ => continue,
+        };
+        return Ok(match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => Key::Quit,
+            KeyCode::Up | KeyCode::Char('k') => Key::Up,
+            KeyCode::Down | KeyCode::Char('j') => Key::Down,
+            KeyCode::Right | KeyCode::Enter | KeyCode::Char('l') => Key::Open,
+            KeyCode::Left | KeyCode::Backspace | KeyCode::Char('h') => Key::Back,
+            KeyCode::Char('s') => Key::Sort,
+            KeyCode::Char('d') => Key::Delete,
+            KeyCode::Char('y' | 'Y') => Key::Yes,
+            KeyCode::Char('q') | KeyCode::Esc => Key::Quit,
+            _ => Key::Other,
+        });
+    }
+}
+
+/// A row of the listing: a scanned directory or a file beside them
+struct Item {
+    name: String,
+    path: PathBuf,
+    size: u64,
+    disk: u64,
+    /// The directory's index in its parent's `dirs`; `None` for a file
+    dir: Option<usize>,
+}
+
+struct Browser<'a> {
+    root: Dir,
+    metric: Metric,
+    /// What the scan left out, for the files listed from disk
+    filter: &'a Filter,
+    /// Indices into `dirs` from the root down to the directory shown
+    stack: Vec<usize>,
+    /// The selected row, and the first row on screen
+    selected: usize,
+    scroll: usize,
+    by_name: bool,
+    items: Vec<Item>,
+    status: String,
+}
+
+/// The directory `stack` leads to from `root`
+fn dir_at<'a>(root: &'a mut Dir, stack: &[usize]) -> &'a mut Dir {
+    stack.iter().fold(root, |dir, &index| &mut dir.dirs[index])
+}
+
+impl Browser<'_> {
+    fn current(&mut self) -> &mut Dir {
+        dir_at(&mut self.root, &self.stack)
+    }
+
+    /// List the current directory: its scanned subdirectories, and the files
+    /// in it as they are on disk now
+    fn load(&mut self) {
+        let metric = self.metric;
+        let by_name = self.by_name;
+        let filter = self.filter;
+        let dir = self.current();
+        let mut items: Vec<Item> = dir
+            .dirs
+            .iter()
+            .enumerate()
+            .map(|(index, child)| Item {
+                name: format!("{}/", name(child)),
+                path: child.path.clone(),
+                size: child.size,
+                disk: child.disk,
+                dir: Some(index),
+            })
+            .collect();
+        for entry in fs::read_dir(&dir.path).into_iter().flatten().flatten() {
+            if filter.excludes(&entry.path()) {
+                continue;
+            }
+            let Ok(metadata) = entry.path().symlink_metadata() else {
+                continue;
+            };
+            // Directories are listed from the scan, which with -L includes
+            // symlinks to them
+            if metadata.is_dir() || dir.dirs.iter().any(|d| d.path == entry.path()) {
+                continue;
+            }
+            items.push(Item {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                path: entry.path(),
+                size: metadata.len(),
+                disk: metadata.blocks() * 512,
+                dir: None,
+            });
+        }
+        let total = |item: &Item| match metric {
+            Metric::Disk => item.disk,
+            Metric::Apparent => item.size,
+        };
+        if by_name {
+            items.sort_by(|a, b| a.name.cmp(&b.name));
+        } else {
+            items.sort_by(|a, b| total(b).cmp(&total(a)).then_with(|| a.name.cmp(&b.name)));
+        }
+        self.items = items;
+        self.selected = self.selected.min(self.items.len().saturating_sub(1));
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [header, body, footer] = Layout::vertical([
+            Constraint::Length(2),
+            Constraint::Min(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let metric = self.metric;
+        let dir = dir_at(&mut self.root, &self.stack);
+        let total = dir.total(metric);
+        let title = Line::from(vec![
+            Span::from(dir.path.display().to_string()).bold(),
+            Span::from(format!("  {}", fmt_bytes(total))),
+        ]);
+        frame.render_widget(title, header);
+
+        let rows = self.items.iter().map(|item| {
+            let size = match metric {
+                Metric::Disk => item.disk,
+                Metric::Apparent => item.size,
+            };
+            let filled = (size * 10 / total.max(1)) as usize;
+            let bar = format!("{}{}", "#".repeat(filled), " ".repeat(10 - filled.min(10)));
+            format!("{:>10} [{bar}] {}", fmt_bytes(size), item.name)
+        });
+        let list = List::new(rows).highlight_style(Style::new().reversed());
+        let mut state = ListState::default()
+            .with_offset(self.scroll)
+            .with_selected(Some(self.selected));
+        frame.render_stateful_widget(list, body, &mut state);
+        self.scroll = state.offset();
+
+        let status = if self.status.is_empty() {
+            HELP
+        } else {
+            &self.status
+        };
+        frame.render_widget(Paragraph::new(status).dim(), footer);
+    }
+
+    fn open(&mut self) {
+        let Some(index) = self.items.get(self.selected).and_then(|item| item.dir) else {
+            return;
+        };
+        self.stack.push(index);
+        self.selected = 0;
+        self.scroll = 0;
+        self.load();
+    }
+
+    fn back(&mut self) {
+        let Some(index) = self.stack.pop() else {
+            return;
+        };
+        self.load();
+        self.selected = self
+            .items
+            .iter()
+            .position(|item| item.dir == Some(index))
+            .unwrap_or(0);
+    }
+
+    /// Ask before deleting the selected entry, then take it off the disk and
+    /// its size off every directory above it. A symlink followed with `-L`
+    /// is removed, not what it points to, so only the link's own size comes
+    /// off.
+    fn delete(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        let Some(item) = self.items.get(self.selected) else {
+            return Ok(());
+        };
+        self.status = format!("Delete {}? (y/N)", item.path.display());
+        terminal.draw(|frame| self.draw(frame))?;
+        self.status.clear();
+        if !matches!(read_key()?, Key::Yes) {
+            return Ok(());
+        }
+
+        let mut item = self.items.remove(self.selected);
+        let link = fs::symlink_metadata(&item.path)
+            .ok()
+            .filter(|m| m.is_symlink());
+        let removed = match item.dir {
+            Some(_) => fs::remove_dir_all(&item.path),
+            None => fs::remove_file(&item.path),
+        };
+        if let Err(e) = removed {
+            self.status = format!("Cannot delete {}: {e}", item.path.display());
+            self.load();
+            return Ok(());
+        }
+        let files = match item.dir {
+            Some(index) => {
+                let removed = self.current().dirs.remove(index);
+                match &link {
+                    // Only the link is gone; what it led to is still there
+                    Some(link) => {
+                        item.size = link.len();
+                        item.disk = link.blocks() * 512;
+                        0
+                    }
+                    None => removed.files,
+                }
+            }
+            None => 1,
+        };
+        for depth in 0..=self.stack.len() {
+            let dir = dir_at(&mut self.root, &self.stack[..depth]);
+            dir.size = dir.size.saturating_sub(item.size);
+            dir.disk = dir.disk.saturating_sub(item.disk);
+            dir.files = dir.files.saturating_sub(files);
+        }
+        self.status = format!("Deleted {}", item.path.display());
+        self.load();
+        Ok(())
+    }
+}
+
+fn name(dir: &Dir) -> String {
+    dir.path.file_name().map_or_else(
+        || dir.path.display().to_string(),
+        |n| n.to_string_lossy().into_owned(),
+    )
+}
+
+/// Browse `root` until the user quits, showing sizes by `metric` and
+/// leaving out the files `filter` does
+pub fn run(root: Dir, metric: Metric, filter: &Filter) -> io::Result<()> {
+    let mut terminal = ratatui::try_init()?;
+    let result = browse(&mut terminal, root, metric, filter);
+    ratatui::restore();
+    result
+}
+
+fn browse(
+    terminal: &mut DefaultTerminal,
+    root: Dir,
+    metric: Metric,
+    filter: &Filter,
+) -> io::Result<()> {
+    let mut browser = Browser {
+        root,
+        metric,
+        filter,
+        stack: Vec::new(),
+        selected: 0,
+        scroll: 0,
+        by_name: false,
+        items: Vec::new(),
+        status: String::new(),
+    };
+    browser.load();
+    loop {
+        terminal.draw(|frame| browser.draw(frame))?;
+        let key = read_key()?;
+        browser.status.clear();
+        match key {
+            Key::Up => browser.selected = browser.selected.saturating_sub(1),
+            Key::Down if browser.selected + 1 < browser.items.len() => browser.selected += 1,
+            Key::Open => browser.open(),
+            Key::Back => browser.back(),
+            Key::Sort => {
+                browser.by_name = !browser.by_name;
+                browser.load();
+            }
+            Key::Delete => browser.delete(terminal)?,
+            Key::Quit => return Ok(()),
+            _ => {}
+        }
+    }
+}
@@ -1,13 +1,111 @@
 // index.js
-import init, { initThreadPool, mandelbrot } from './pkg/mandelbrot.js';
+
+// The page takes input and passes it to render-worker.js, which renders
+// onto the canvas off the main thread. Input that changes the view first
+// cancels the render in progress, so a pan never waits for a slow one.
 
 const canvas = document.getElementById('canvas');
-const ctx = canvas.getContext('2d');
-const imageData = ctx.createImageData(canvas.width, canvas.height);
+const worker = new Worker('./render-worker.js', { type: 'module' });
+
+// Where to bump the worker's generation counter, once it is ready
+let generation = null;
+
+// Send `message` to the worker, cutting short the render it makes stale.
+// The worker is busy in that render, so the counter is bumped here through
+// the shared memory rather than by a message it would only read afterwards.
+function send(message) {
+    if (!generation) return;
+    Atomics.add(generation.counter, generation.index, 1);
+    worker.postMessage(message);
+}
 
-init().then(async () => {
-    await initThreadPool(navigator.hardwareConcurrency);
-    const pixels = mandelbrot(canvas.width, canvas.height, 1000);
-    imageData.data.set(pixels);
-    ctx.putImageData(imageData, 0, 0);
+// Wheel zooms around the cursor, keeping the point under it in place
+canvas.addEventListener('wheel', (event) => {
+    event.preventDefault();
+    send({ type: 'zoom', x: event.offsetX, y: event.offsetY, factor: Math.exp(event.deltaY * 0.002) });
+}, { passive: false });
+
+// Dragging pans
+let drag = null;
+canvas.addEventListener('pointerdown', (event) => {
+    drag = { x: event.clientX, y: event.clientY };
+    canvas.setPointerCapture(event.pointerId);
+});
+canvas.addEventListener('pointermove', (event) => {
+    if (!drag) return;
+    send({ type: 'pan', dx: drag.x - event.clientX, dy: drag.y - event.clientY });
+    drag = { x: event.clientX, y: event.clientY };
+});
+canvas.addEventListener('pointerup', () => {
+    drag = null;
 });
+
+// Full-resolution PNG of the view, with its parameters in text chunks
+function savePng(png) {
+    const link = document.createElement('a');
+    link.href = URL.createObjectURL(new Blob([png], { type: 'image/png' }));
+    link.download = 'mandelbrot.png';
+    link.click();
+    setTimeout(() => URL.revokeObjectURL(link.href));
+}
+
+function updateOptions() {
+    send({
+        type: 'options',
+        values: {
+            juliaRe: Number(document.getElementById('julia-re').value),
+            juliaIm: Number(document.getElementById('julia-im').value),
+            palette: document.getElementById('palette').value,
+            smooth: document.getElementById('smooth').checked,
+            trap: document.getElementById('trap').value,
+            interior: document.getElementById('interior').value,
+            distance: document.getElementById('distance').checked,
+            borderCheck: document.getElementById('border-check').checked,
+            rowsPerTask: Number(document.getElementById('rows-per-task').value),
+            equalize: document.getElementById('equalize').checked,
+            autoIter: document.getElementById('auto-iter').checked,
+        },
+    });
+}
+document.getElementById('fractal').addEventListener('change', (event) => {
+    send({ type: 'fractal', name: event.target.value });
+});
+document.getElementById('julia-re').addEventListener('input', updateOptions);
+document.getElementById('julia-im').addEventListener('input', updateOptions);
+document.getElementById('palette').addEventListener('change', updateOptions);
+document.getElementById('smooth').addEventListener('change', updateOptions);
+document.getElementById('trap').addEventListener('change', updateOptions);
+document.getElementById('interior').addEventListener('change', updateOptions);
+document.getElementById('distance').addEventListener('change', updateOptions);
+document.getElementById('equalize').addEventListener('change', updateOptions);
+document.getElementById('auto-iter').addEventListener('change', updateOptions);
+document.getElementById('border-check').addEventListener('change', updateOptions);
+document.getElementById('rows-per-task').addEventListener('input', updateOptions);
+// Saving leaves the render in progress alone
+document.getElementById('save').addEventListener('click', () => worker.postMessage({ type: 'save' }));
+
+worker.onmessage = ({ data }) => {
+    switch (data.type) {
+    case 'ready':
+        generation = { counter: new Uint32Array(data.memory.buffer), index: data.generation / 4 };
+        updateOptions();
+        break;
+    case 'status':
+        document.getElementById('position').value = data.position;
+        document.getElementById('stats').value = data.stats;
+        break;
+    case 'png':
+        savePng(data.png);
+        break;
+    }
+};
+
+// The pool can only be started once, so its size comes from the address,
+// e.g. index.html?threads=4
+const threads = Number(new URLSearchParams(location.search).get('threads'));
+const offscreen = canvas.transferControlToOffscreen();
+worker.postMessage({
+    type: 'init',
+    canvas: offscreen,
+    threads: threads > 0 ? threads : navigator.hardwareConcurrency,
+}, [offscreen]);
@@ -0,0 +1,243 @@
+// render-worker.js
+
+// Renders onto the page's canvas, handed over as an OffscreenCanvas, so the
+// page keeps taking input while a render runs. Before sending input that
+// changes the view, the page bumps the generation counter in the shared wasm
+// memory, which cuts short the render in progress here.
+
+// The page's first message, which hands over the canvas, can arrive while
+// the package below is still loading
+const started = new Promise((resolve) => {
+    self.onmessage = ({ data }) => resolve(data);
+});
+
+// The SIMD build is a second package, made next to the plain one with
+//   RUSTFLAGS="-C target-feature=+atomics,+bulk-memory,+simd128" \
+//     wasm-pack build --target web --out-dir pkg-simd -- --features simd
+// and loaded where the browser validates this tiny module using a v128
+// instruction. Without it the plain package is used.
+const SIMD_PROBE = new Uint8Array([
+    0, 97, 115, 109, 1, 0, 0, 0, 1, 5, 1, 96, 0, 1, 123, 3, 2, 1, 0, 10, 10, 1, 8, 0, 65, 0, 253, 15, 253, 98, 11,
+]);
+const wasm = WebAssembly.validate(SIMD_PROBE)
+    ? await import('./pkg-simd/mandelbrot.js').catch(() => import('./pkg/mandelbrot.js'))
+    : await import('./pkg/mandelbrot.js');
+const {
+    default: init, initThreadPool, auto_max_iter, generation_ptr, wasm_memory,
+    DeepView, FractalType, Frame, Interior, Palette, RenderOptions, Trap,
+} = wasm;
+
+const MAX_ITER = 1000;
+
+// Pixels per side of a refined tile, and per side of a preview pixel
+const TILE_SIZE = 64;
+const PREVIEW_BLOCK = 8;
+
+// Time spent on tiles before handling the messages that came in meanwhile
+const TILE_BUDGET_MS = 12;
+
+// RGB stops of the custom palette: deep blue, white, then gold into black
+const OCEAN_LUT = new Uint8Array([
+    0, 7, 100,
+    32, 107, 203,
+    237, 255, 255,
+    255, 170, 0,
+    0, 2, 0,
+]);
+
+// Center and width in the complex plane showing each whole fractal
+const HOME = {
+    Mandelbrot: { x: -0.75, y: 0.0, width: 3.5 },
+    Julia: { x: 0.0, y: 0.0, width: 3.5 },
+    BurningShip: { x: -0.5, y: -0.5, width: 4.0 },
+    Tricorn: { x: -0.3, y: 0.0, width: 4.0 },
+};
+
+// The page's canvas, and the coarse preview rendered small then scaled up
+// onto it; set up by `start`
+let canvas;
+let ctx;
+let preview;
+let previewCtx;
+let previewData;
+let tileData;
+
+// Frames in wasm memory the preview and every tile are rendered into again
+let previewFrame;
+let tileFrame;
+
+let options;
+let equalize = false;
+let autoIter = true;
+
+// Center and complex units per pixel; the center keeps every digit the zoom
+// needs, so the view goes far deeper than f64 coordinates allow
+let view;
+
+// Bumped by every change to the view; tiles of an older one are dropped
+let generation = 0;
+let drawScheduled = false;
+
+// Iterations of the current view: fixed, or more the deeper the zoom
+function currentMaxIter() {
+    return autoIter ? auto_max_iter(view.scale) : MAX_ITER;
+}
+
+function goHome(fractal) {
+    const home = HOME[fractal];
+    // Pending tiles belong to the view about to be freed
+    generation++;
+    view?.free();
+    view = new DeepView(canvas.width, canvas.height, String(home.x), String(home.y),
+        home.width / canvas.width);
+}
+
+// Copy the pixels of `frame` into `imageData`, viewing them where they are
+// in wasm memory. ImageData cannot wrap the shared memory the thread pool
+// runs in, so this one copy stays.
+function copyFrame(frame, imageData) {
+    imageData.data.set(new Uint8ClampedArray(wasm_memory().buffer, frame.ptr(), frame.len()));
+}
+
+// A coarse preview at once, then full-resolution tiles from the center out,
+// a few per task so messages keep flowing. A render the page cancels ends
+// the draw; the message that cancelled it schedules the next one.
+function draw() {
+    drawScheduled = false;
+    const current = generation;
+
+    // The preview's escape times stand in for the whole view's: they feed the
+    // readout and, when equalizing, the colors of the preview and the tiles
+    const coarse = new DeepView(preview.width, preview.height, view.center_x, view.center_y,
+        view.scale * PREVIEW_BLOCK);
+    const maxIter = currentMaxIter();
+    options.clear_equalization();
+    let rendered = coarse.render_into(previewFrame, maxIter, options);
+    const stats = coarse.stats();
+    if (rendered && equalize) {
+        options.equalize(stats);
+        rendered = coarse.render_into(previewFrame, maxIter, options);
+    }
+    coarse.free();
+    if (!rendered) {
+        stats.free();
+        return;
+    }
+    copyFrame(previewFrame, previewData);
+    postMessage({
+        type: 'status',
+        position: `center ${view.center_x}, ${view.center_y}; ${view.scale.toExponential(2)} per pixel; ${maxIter} iterations`,
+        stats: statsText(stats),
+    });
+    stats.free();
+    previewCtx.putImageData(previewData, 0, 0);
+    ctx.imageSmoothingEnabled = false;
+    ctx.drawImage(preview, 0, 0, preview.width * PREVIEW_BLOCK, preview.height * PREVIEW_BLOCK);
+
+    const columns = Math.ceil(canvas.width / TILE_SIZE);
+    const rows = Math.ceil(canvas.height / TILE_SIZE);
+    const tiles = [];
+    for (let y = 0; y < rows; y++) {
+        for (let x = 0; x < columns; x++) {
+            tiles.push([x, y]);
+        }
+    }
+    const distance = ([x, y]) => Math.hypot(x + 0.5 - columns / 2, y + 0.5 - rows / 2);
+    tiles.sort((a, b) => distance(a) - distance(b));
+
+    const refine = () => {
+        if (current !== generation) return;
+        const deadline = performance.now() + TILE_BUDGET_MS;
+        while (tiles.length > 0 && performance.now() < deadline) {
+            const [x, y] = tiles.shift();
+            if (!view.render_tile_into(tileFrame, x, y, TILE_SIZE, maxIter, options)) return;
+            copyFrame(tileFrame, tileData);
+            ctx.putImageData(tileData, x * TILE_SIZE, y * TILE_SIZE);
+        }
+        if (tiles.length > 0) setTimeout(refine, 0);
+    };
+    refine();
+}
+
+function statsText(stats) {
+    const pixels = stats.escaped + stats.interior;
+    const inside = (100 * stats.interior / pixels).toFixed(1);
+    return stats.escaped > 0
+        ? `escaped after ${stats.min} to ${stats.max} iterations, mean ${stats.mean.toFixed(1)}; ${inside}% inside`
+        : `${inside}% inside`;
+}
+
+// Coalesce the messages already waiting into one draw after them
+function redraw() {
+    generation++;
+    if (!drawScheduled) {
+        drawScheduled = true;
+        setTimeout(draw, 0);
+    }
+}
+
+// Take over the canvas, start `threads` threads, and tell the page where the
+// generation counter is
+async function start(message) {
+    canvas = message.canvas;
+    ctx = canvas.getContext('2d');
+    preview = new OffscreenCanvas(Math.ceil(canvas.width / PREVIEW_BLOCK),
+        Math.ceil(canvas.height / PREVIEW_BLOCK));
+    previewCtx = preview.getContext('2d');
+    previewData = previewCtx.createImageData(preview.width, preview.height);
+    tileData = ctx.createImageData(TILE_SIZE, TILE_SIZE);
+
+    await init();
+    await initThreadPool(message.threads);
+    previewFrame = new Frame(preview.width, preview.height);
+    tileFrame = new Frame(TILE_SIZE, TILE_SIZE);
+    options = new RenderOptions();
+    options.set_lut(OCEAN_LUT);
+    goHome('Mandelbrot');
+    postMessage({ type: 'ready', memory: wasm_memory(), generation: generation_ptr() });
+}
+
+const handlers = {
+    // Move the view by dx, dy pixels
+    pan({ dx, dy }) {
+        view.pan(dx, dy);
+        redraw();
+    },
+
+    // Zoom around pixel x, y, keeping the point under it in place
+    zoom({ x, y, factor }) {
+        view.zoom(x, y, factor);
+        redraw();
+    },
+
+    fractal({ name }) {
+        options.fractal_type = FractalType[name];
+        goHome(name);
+        redraw();
+    },
+
+    options({ values }) {
+        options.julia_re = values.juliaRe;
+        options.julia_im = values.juliaIm;
+        options.palette = Palette[values.palette];
+        options.smooth = values.smooth;
+        options.trap = Trap[values.trap];
+        options.interior = Interior[values.interior];
+        options.distance_estimation = values.distance;
+        options.border_check = values.borderCheck;
+        options.rows_per_task = values.rowsPerTask;
+        equalize = values.equalize;
+        autoIter = values.autoIter;
+        redraw();
+    },
+
+    // Full-resolution PNG of the view, with its parameters in text chunks
+    save() {
+        const png = view.export_png(currentMaxIter(), options);
+        postMessage({ type: 'png', png }, [png.buffer]);
+    },
+};
+
+// The page only sends the rest once told the worker is ready
+self.onmessage = ({ data }) => handlers[data.type](data);
+await start(await started);
@@ -0,0 +1,141 @@
+//! Batch renderer on the same core as the web demo
+//!
+//! Renders bands of full-width rows on all cores and streams each into a
+//! PNG, so a 16384x16384 image only ever holds one band in memory:
+//!
+//!     cargo run --release --target x86_64-unknown-linux-gnu --bin render -- \
+//!         --width 16384 --height 16384 --output mandelbrot.png
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::time::Instant;
+
+use clap::Parser;
+use mandelbrot::engine::{self, Viewport};
+use mandelbrot::{FractalType, Palette, RenderOptions, export, pool};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[arg(short, long, default_value_t = 4096)]
+    width: u32,
+    #[arg(long, default_value_t = 4096)]
+    height: u32,
+    /// Center of the image, as decimals
+    #[arg(long, default_value = "-0.75", allow_hyphen_values = true)]
+    center_x: String,
+    #[arg(long, default_value = "0", allow_hyphen_values = true)]
+    center_y: String,
+    /// Complex units per pixel; by default the whole set fits the width
+    #[arg(long)]
+    scale: Option<f64>,
+    #[arg(short, long, default_value_t = 1000)]
+    max_iter: u32,
+    /// Scale the iterations with the zoom instead of using --max-iter
+    #[arg(long)]
+    auto_iter: bool,
+    #[arg(long, default_value = "mandelbrot", value_parser = parse_fractal)]
+    fractal: FractalType,
+    #[arg(long, default_value = "classic", value_parser = parse_palette)]
+    palette: Palette,
+    /// Color by whole escape times, with bands between them
+    #[arg(long)]
+    banded: bool,
+    /// Fade colors into the interior color at the boundary by the estimated
+    /// distance to the set, for a sharp anti-aliased outline
+    #[arg(long)]
+    distance: bool,
+    /// Fill areas whose border is all inside the set without iterating them
+    #[arg(long)]
+    border_check: bool,
+    /// Rows rendered per band
+    #[arg(long, default_value_t = 256)]
+    band: u32,
+    /// Rows each thread renders at a time
+    #[arg(long, default_value_t = 1)]
+    rows_per_task: u32,
+    /// Threads to render on; all cores by default
+    #[arg(short, long, default_value_t = 0)]
+    threads: usize,
+    #[arg(short, long, default_value = "mandelbrot.png")]
+    output: String,
+}
+
+fn parse_fractal(name: &str) -> Result<FractalType, String> {
+    match name.to_lowercase().as_str() {
+        "mandelbrot" => Ok(FractalType::Mandelbrot),
+        "julia" => Ok(FractalType::Julia),
+        "burningship" | "burning-ship" => Ok(FractalType::BurningShip),
+        "tricorn" => Ok(FractalType::Tricorn),
+        _ => Err(format!("unknown fractal {name}")),
+    }
+}
+
+fn parse_palette(name: &str) -> Result<Palette, String> {
+    match name.to_lowercase().as_str() {
+        "classic" => Ok(Palette::Classic),
+        "grayscale" => Ok(Palette::Grayscale),
+        "viridis" => Ok(Palette::Viridis),
+        "fire" => Ok(Palette::Fire),
+        "hsv" => Ok(Palette::Hsv),
+        _ => Err(format!("unknown palette {name}")),
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    let center_x: f64 = args.center_x.parse()?;
+    let center_y: f64 = args.center_y.parse()?;
+    let scale = args.scale.unwrap_or(3.5 / args.width as f64);
+    let mut options = RenderOptions::new();
+    options.fractal_type = args.fractal;
+    options.palette = args.palette;
+    options.smooth = !args.banded;
+    options.distance_estimation = args.distance;
+    options.border_check = args.border_check;
+    options.rows_per_task = args.rows_per_task;
+    pool::set_threads(args.threads)?;
+    let max_iter = if args.auto_iter {
+        engine::auto_max_iter(scale)
+    } else {
+        args.max_iter
+    };
+
+    let file = BufWriter::new(File::create(&args.output)?);
+    let mut encoder = png::Encoder::new(file, args.width, args.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let text = export::parameters(&args.center_x, &args.center_y, scale, max_iter, &options);
+    for (keyword, value) in text {
+        encoder.add_text_chunk(keyword.to_string(), value)?;
+    }
+    let mut stream = encoder.write_header()?.into_stream_writer()?;
+
+    let started = Instant::now();
+    let viewport = Viewport::centered(args.width, args.height, center_x, center_y, scale);
+    let mut pixels = Vec::new();
+    for y in (0..args.height).step_by(args.band.max(1) as usize) {
+        let rows = args.band.max(1).min(args.height - y);
+        pixels.resize((args.width * rows * 4) as usize, 0);
+        let band = viewport.offset(0, y);
+        engine::render_viewport(
+            &mut pixels,
+            args.width,
+            rows,
+            &band,
+            max_iter,
+            &options,
+            None,
+        );
+        stream.write_all(&pixels)?;
+        eprint!("\r{} of {} rows", y + rows, args.height);
+    }
+    stream.finish()?;
+    eprintln!(
+        "\nwrote {} in {:.1?} on {} threads",
+        args.output,
+        started.elapsed(),
+        pool::current_threads()
+    );
+    Ok(())
+}
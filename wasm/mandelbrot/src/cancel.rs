@@ -0,0 +1,53 @@
+//! Abandoning renders in progress
+//!
+//! A render notes the generation when it starts and skips its remaining rows
+//! once the generation moves on, so a pan can cut short a high-iteration
+//! render instead of waiting for the thread pool to finish it.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use wasm_bindgen::prelude::*;
+
+static GENERATION: AtomicU32 = AtomicU32::new(0);
+
+/// Cancel every render in progress; renders started afterwards run normally
+#[wasm_bindgen]
+pub fn cancel_renders() {
+    GENERATION.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Address of the generation counter in wasm memory
+///
+/// A thread blocked waiting on a render cannot call `cancel_renders`, but
+/// another one sharing the memory can bump the counter directly:
+/// `Atomics.add(new Uint32Array(memory.buffer), generation_ptr() / 4, 1)`.
+#[wasm_bindgen]
+pub fn generation_ptr() -> *const u32 {
+    GENERATION.as_ptr()
+}
+
+/// The generation a render started in
+#[derive(Clone, Copy, Debug)]
+pub struct Generation {
+    counter: &'static AtomicU32,
+    start: u32,
+}
+
+impl Generation {
+    /// The generation of a render starting now
+    pub fn current() -> Self {
+        Self::of(&GENERATION)
+    }
+
+    /// The generation of `counter` now, instead of the global one
+    pub fn of(counter: &'static AtomicU32) -> Self {
+        Generation {
+            counter,
+            start: counter.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.counter.load(Ordering::Relaxed) != self.start
+    }
+}
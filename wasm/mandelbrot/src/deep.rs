@@ -0,0 +1,432 @@
+//! Deep zoom by perturbation
+//!
+//! Past a scale of about 1e-13 units per pixel, neighbouring pixels share
+//! one f64 and the image falls apart into blocks. Here only the center is
+//! iterated in high precision (`Fixed`), giving the reference orbit `Z_n`.
+//! Each pixel then iterates its small offset from that orbit in f64:
+//!
+//! δz_{n+1} = 2 Z_n δz_n + δz_n² + δc
+//!
+//! The offset loses precision when the pixel's orbit passes close to zero
+//! or outlives the reference; both are fixed by rebasing, restarting the
+//! reference at `Z_0 = 0` with the whole `z` as the offset. Rebasing also
+//! replaces glitch detection and extra references.
+
+use num_complex::Complex;
+use wasm_bindgen::prelude::*;
+
+use crate::engine::{self, Job, Viewport};
+use crate::fixed::Fixed;
+use crate::shading::Tracer;
+use crate::{FractalType, Frame, RenderOptions, RenderStats, export};
+
+/// Complex units per pixel below which `DeepView` renders by perturbation
+const PERTURBATION_SCALE: f64 = 1e-12;
+
+/// Smallest scale: offsets in units of the scale must stay normal f64s
+const MIN_SCALE: f64 = 1e-290;
+
+/// Bits of the center kept below the pixel size
+const GUARD_BITS: f64 = 64.0;
+
+/// Fractional limbs of a center precise to well below `scale`
+fn precision(scale: f64) -> usize {
+    let bits = (-scale.log2()).max(0.0) + GUARD_BITS;
+    (bits / 32.0).ceil() as usize
+}
+
+/// `Z_0, Z_1, …` of the Mandelbrot orbit of `c_x + c_y i`, rounded to f64,
+/// until it escapes or after `max_iter` iterations
+pub fn reference_orbit(c_x: &Fixed, c_y: &Fixed, max_iter: u32, bailout: f64) -> Vec<Complex<f64>> {
+    let frac = c_x.frac().max(c_y.frac());
+    let (mut x, mut y) = (Fixed::zero(frac), Fixed::zero(frac));
+    let mut orbit = Vec::with_capacity(max_iter as usize + 1);
+    loop {
+        let z = Complex::new(x.to_f64(), y.to_f64());
+        orbit.push(z);
+        if z.norm_sqr() > bailout || orbit.len() > max_iter as usize {
+            return orbit;
+        }
+        // (x + yi)² = x² - y² + 2xy i
+        let xy = x.mul(&y);
+        x = x.mul(&x).sub(&y.mul(&y)).add(c_x);
+        y = xy.mul_pow2(1).add(c_y);
+    }
+}
+
+/// Escape time and last `z` of the pixel `δc` away from the reference
+/// `orbit`, as `fractal::trace` would give for `FractalType::Mandelbrot`
+pub fn perturbed_escape(
+    orbit: &[Complex<f64>],
+    dc: Complex<f64>,
+    max_iter: u32,
+    bailout: f64,
+    mut visit: impl FnMut(Complex<f64>),
+) -> (u32, Complex<f64>) {
+    let mut dz = Complex::new(0.0, 0.0);
+    let mut z = dz;
+    // Index into the reference orbit, which rebasing moves back to the start
+    let mut n = 0;
+    let mut i = 0;
+    while i < max_iter {
+        dz = (orbit[n] * 2.0 + dz) * dz + dc;
+        n += 1;
+        i += 1;
+        z = orbit[n] + dz;
+        visit(z);
+        if z.norm_sqr() > bailout {
+            break;
+        }
+        if z.norm_sqr() < dz.norm_sqr() || n == orbit.len() - 1 {
+            dz = z;
+            n = 0;
+        }
+    }
+    (i, z)
+}
+
+/// A view whose center keeps as many digits as the zoom needs, for zooming
+/// past where `render` turns blocky; built in JS with
+/// `new DeepView(width, height, "-0.75", "0", scale)`
+///
+/// Pan and zoom move the center in full precision. Below a scale of 1e-12
+/// the Mandelbrot set renders by perturbation; other fractal types keep
+/// plain f64 iteration at every depth.
+#[wasm_bindgen]
+pub struct DeepView {
+    width: u32,
+    height: u32,
+    center_x: Fixed,
+    center_y: Fixed,
+    scale: f64,
+    /// Reference orbit of the center with the `max_iter` and bailout it was
+    /// computed for, shared by all tiles of one view
+    orbit: Option<(u32, f64, Vec<Complex<f64>>)>,
+    stats: RenderStats,
+}
+
+#[wasm_bindgen]
+impl DeepView {
+    /// `scale` complex units per pixel around the center given as decimals
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        width: u32,
+        height: u32,
+        center_x: &str,
+        center_y: &str,
+        scale: f64,
+    ) -> Result<DeepView, JsError> {
+        Self::parse(width, height, center_x, center_y, scale)
+            .map_err(|message| JsError::new(&message))
+    }
+
+    /// Real part of the center, with all its digits
+    #[wasm_bindgen(getter)]
+    pub fn center_x(&self) -> String {
+        self.center_x.to_decimal()
+    }
+
+    /// Imaginary part of the center, with all its digits
+    #[wasm_bindgen(getter)]
+    pub fn center_y(&self) -> String {
+        self.center_y.to_decimal()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+
+    /// Move the center by `dx, dy` pixels
+    pub fn pan(&mut self, dx: f64, dy: f64) {
+        let (frac, scale) = (precision(self.scale), self.scale);
+        let offset = |pixels: f64| {
+            Fixed::from_f64(pixels * scale, frac).unwrap_or_else(|| Fixed::zero(frac))
+        };
+        self.center_x = self.center_x.add(&offset(dx)).with_frac(frac);
+        self.center_y = self.center_y.add(&offset(dy)).with_frac(frac);
+        self.orbit = None;
+    }
+
+    /// Multiply the scale by `factor`, keeping the point under pixel `x, y`
+    /// in place
+    pub fn zoom(&mut self, x: f64, y: f64, factor: f64) {
+        let factor = factor.max(MIN_SCALE / self.scale);
+        self.pan(
+            (x - self.width as f64 / 2.0) * (1.0 - factor),
+            (y - self.height as f64 / 2.0) * (1.0 - factor),
+        );
+        self.scale *= factor;
+        // Extend the center to the new depth
+        let frac = precision(self.scale);
+        self.center_x = self.center_x.with_frac(frac);
+        self.center_y = self.center_y.with_frac(frac);
+    }
+
+    /// Escape times of every pixel rendered since the last `clear_stats`
+    pub fn stats(&self) -> RenderStats {
+        self.stats.clone()
+    }
+
+    pub fn clear_stats(&mut self) {
+        self.stats.clear();
+    }
+
+    /// RGBA pixels of the whole view, like `render`
+    pub fn render(&mut self, max_iter: u32, options: &RenderOptions) -> Vec<u8> {
+        let mut frame = Frame::new(self.width, self.height);
+        self.render_into(&mut frame, max_iter, options);
+        frame.into_pixels()
+    }
+
+    /// `render` into `frame`, like `render_into`; false, leaving the frame
+    /// as it was, unless it is the size of the view
+    pub fn render_into(
+        &mut self,
+        frame: &mut Frame,
+        max_iter: u32,
+        options: &RenderOptions,
+    ) -> bool {
+        if (frame.width(), frame.height()) != (self.width, self.height) {
+            return false;
+        }
+        let (width, height) = (self.width, self.height);
+        self.render_part(frame.pixels_mut(), 0, 0, width, height, max_iter, options)
+    }
+
+    /// RGBA pixels of one `tile_size` square tile, like `render_tile`
+    pub fn render_tile(
+        &mut self,
+        tile_x: u32,
+        tile_y: u32,
+        tile_size: u32,
+        max_iter: u32,
+        options: &RenderOptions,
+    ) -> Vec<u8> {
+        let mut frame = Frame::new(tile_size, tile_size);
+        self.render_tile_into(&mut frame, tile_x, tile_y, tile_size, max_iter, options);
+        frame.into_pixels()
+    }
+
+    /// `render_tile` into `frame`; false, leaving the frame as it was, unless
+    /// it is `tile_size` square
+    pub fn render_tile_into(
+        &mut self,
+        frame: &mut Frame,
+        tile_x: u32,
+        tile_y: u32,
+        tile_size: u32,
+        max_iter: u32,
+        options: &RenderOptions,
+    ) -> bool {
+        if (frame.width(), frame.height()) != (tile_size, tile_size) {
+            return false;
+        }
+        self.render_part(
+            frame.pixels_mut(),
+            tile_x * tile_size,
+            tile_y * tile_size,
+            tile_size,
+            tile_size,
+            max_iter,
+            options,
+        )
+    }
+
+    /// PNG of the whole view, like `export_png`, recording the center with
+    /// all its digits
+    pub fn export_png(
+        &mut self,
+        max_iter: u32,
+        options: &RenderOptions,
+    ) -> Result<Vec<u8>, JsError> {
+        let pixels = self.render(max_iter, options);
+        let text = export::parameters(
+            &self.center_x(),
+            &self.center_y(),
+            self.scale,
+            max_iter,
+            options,
+        );
+        export::encode(&pixels, self.width, self.height, &text)
+            .map_err(|message| JsError::new(&message))
+    }
+}
+
+impl DeepView {
+    fn parse(
+        width: u32,
+        height: u32,
+        center_x: &str,
+        center_y: &str,
+        scale: f64,
+    ) -> Result<DeepView, String> {
+        if !(scale.is_finite() && scale > 0.0) {
+            return Err(format!("scale must be positive, not {scale}"));
+        }
+        let scale = scale.max(MIN_SCALE);
+        let frac = precision(scale);
+        let parse = |text| Fixed::parse(text, frac).ok_or(format!("not a decimal: {text}"));
+        Ok(DeepView {
+            width,
+            height,
+            center_x: parse(center_x)?,
+            center_y: parse(center_y)?,
+            scale,
+            orbit: None,
+            stats: RenderStats::default(),
+        })
+    }
+
+    /// `width` x `height` pixels starting at pixel `x, y` of the view
+    #[allow(clippy::too_many_arguments)]
+    fn render_part(
+        &mut self,
+        pixels: &mut [u8],
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        max_iter: u32,
+        options: &RenderOptions,
+    ) -> bool {
+        if self.scale >= PERTURBATION_SCALE || options.fractal_type != FractalType::Mandelbrot {
+            let viewport = Viewport::centered(
+                self.width,
+                self.height,
+                self.center_x.to_f64(),
+                self.center_y.to_f64(),
+                self.scale,
+            )
+            .offset(x, y);
+            let stats = Some(&mut self.stats);
+            return engine::render_viewport(
+                pixels, width, height, &viewport, max_iter, options, stats,
+            );
+        }
+
+        let job = Job::new(Some(&mut self.stats));
+        let bailout = options.bailout();
+        if !matches!(&self.orbit, Some((iter, b, _)) if *iter == max_iter && *b == bailout) {
+            let orbit = reference_orbit(&self.center_x, &self.center_y, max_iter, bailout);
+            self.orbit = Some((max_iter, bailout, orbit));
+        }
+        let Some((_, _, orbit)) = &self.orbit else {
+            unreachable!()
+        };
+
+        // Offsets from the center pixel, exact in pixels before scaling
+        let (half_width, half_height) = (self.width as f64 / 2.0, self.height as f64 / 2.0);
+        let scale = self.scale;
+        let center = Complex::new(self.center_x.to_f64(), self.center_y.to_f64());
+        engine::render_pixels(pixels, width, height, max_iter, options, job, |px, py| {
+            let dc = Complex::new(
+                ((x + px) as f64 - half_width) * scale,
+                ((y + py) as f64 - half_height) * scale,
+            );
+            if !options.shades_orbits() {
+                return perturbed_escape(orbit, dc, max_iter, bailout, |_| {}).into();
+            }
+            let mut tracer = Tracer::new(options, center + dc, scale);
+            let escaped = perturbed_escape(orbit, dc, max_iter, bailout, |z| tracer.visit(z));
+            tracer.finish(escaped)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BAILOUT, Palette, fractal, render};
+
+    #[test]
+    fn test_perturbation_matches_direct_iteration() {
+        let center = Complex::new(-0.74, 0.16);
+        let orbit = reference_orbit(
+            &Fixed::from_f64(center.re, 2).unwrap(),
+            &Fixed::from_f64(center.im, 2).unwrap(),
+            500,
+            BAILOUT,
+        );
+        let mut differing = 0;
+        for k in 0..400 {
+            let dc = Complex::new((k % 20) as f64 - 10.0, (k / 20) as f64 - 10.0) * 1e-3;
+            let direct = fractal::escape(FractalType::Mandelbrot, center + dc, dc, 500, BAILOUT);
+            let perturbed = perturbed_escape(&orbit, dc, 500, BAILOUT, |_| {});
+            if direct.0 != perturbed.0 {
+                differing += 1;
+            }
+        }
+        // Rounding may move a pixel right on an escape boundary
+        assert!(differing <= 4, "{differing} of 400 differ");
+    }
+
+    #[test]
+    fn test_deep_zoom_resolves_detail() {
+        let options = RenderOptions {
+            palette: Palette::Hsv,
+            smooth: true,
+            ..RenderOptions::default()
+        };
+        let distinct = |pixels: &[u8]| {
+            let mut colors = pixels.as_chunks::<4>().0.to_vec();
+            colors.sort();
+            colors.dedup();
+            colors.len()
+        };
+
+        // Around c = i, where the set is a thin dendrite with detail at every
+        // scale; f64 pixels there all collapse onto one point
+        let (x, y, scale) = ("0", "1", 1e-20);
+        let mut view = DeepView::parse(32, 32, x, y, scale).unwrap();
+        let deep = view.render(2000, &options);
+        let flat = render(
+            32,
+            32,
+            x.parse().unwrap(),
+            y.parse().unwrap(),
+            scale,
+            2000,
+            &options,
+        );
+        assert!(distinct(&deep) > 100, "{} colors", distinct(&deep));
+        assert_eq!(distinct(&flat), 1);
+
+        // Tiles share the cached reference and match the whole render
+        let tile = view.render_tile(1, 0, 16, 2000, &options);
+        for row in 0..16 {
+            assert_eq!(tile[row * 64..][..64], deep[(row * 32 + 16) * 4..][..64]);
+        }
+
+        // Frames of another size are left as they were
+        let mut frame = Frame::new(16, 8);
+        assert!(!view.render_into(&mut frame, 2000, &options));
+        assert!(!view.render_tile_into(&mut frame, 0, 0, 16, 2000, &options));
+        assert!(frame.pixels().iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn test_pan_and_zoom_keep_digits() {
+        let mut view = DeepView::parse(100, 100, "-0.75", "0.1", 1e-30).unwrap();
+        view.pan(3.0, -2.0);
+        // Distance between two decimals, exact far beyond f64
+        let moved = |from: &str, to: &str| {
+            let parse = |text| Fixed::parse(text, 8).unwrap();
+            parse(to).sub(&parse(from)).to_f64()
+        };
+        assert!((moved("-0.75", &view.center_x()) - 3e-30).abs() < 1e-40);
+        assert!((moved("0.1", &view.center_y()) + 2e-30).abs() < 1e-40);
+
+        // Zooming at the center only changes the scale
+        let before = view.center_x();
+        view.zoom(50.0, 50.0, 1e-10);
+        assert!((view.scale() / 1e-40 - 1.0).abs() < 1e-12);
+        assert!(moved(&before, &view.center_x()).abs() < 1e-55);
+
+        // Zooming elsewhere keeps the point under the cursor
+        view.zoom(0.0, 50.0, 0.5);
+        assert!((moved(&before, &view.center_x()) + 25e-40).abs() < 1e-50);
+
+        assert!(DeepView::parse(10, 10, "1e-5", "0", 1.0).is_err());
+    }
+}
@@ -0,0 +1,505 @@
+//! The rendering core: viewports, parallel rows and coloring
+//!
+//! Nothing here is exported to JS. The wasm functions in the crate root and
+//! the native renderer in `src/bin/render.rs` are both thin wrappers around
+//! `render_viewport`, so the page and batch renders share every pixel.
+
+use num_complex::Complex;
+use rayon::prelude::*;
+use std::sync::Mutex;
+
+use crate::cancel::Generation;
+use crate::pool;
+use crate::shading::{self, Tracer};
+use crate::{FractalType, RenderOptions, RenderStats, SMOOTH_BAILOUT, fractal, simd};
+
+/// Scale at which the whole Mandelbrot set is about 700 pixels wide, where
+/// `auto_max_iter` starts adding iterations
+const AUTO_HOME_SCALE: f64 = 0.005;
+
+/// Iterations of `auto_max_iter` at and above the home scale
+const AUTO_BASE_ITER: f64 = 200.0;
+
+/// Iterations `auto_max_iter` adds for each halving of the scale
+const AUTO_ITER_PER_OCTAVE: f64 = 50.0;
+
+/// Most iterations `auto_max_iter` asks for
+const AUTO_MAX_ITER: f64 = 50_000.0;
+
+/// Region of the complex plane covered by an image: the point of the top left
+/// pixel and the distance between pixels. The imaginary part grows downwards.
+///
+/// A part of the image (a tile) keeps the same plane and counts its pixels
+/// from `first`, so its points are bit for bit those of the whole image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    pub x_min: f64,
+    pub y_min: f64,
+    pub x_step: f64,
+    pub y_step: f64,
+    /// Pixel of the whole image at the top left
+    pub first: (u32, u32),
+}
+
+/// What coloring needs of a pixel's orbit
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct Escape {
+    /// Iterations until `|z|²` passed the bailout, `max_iter` if it never did
+    pub(crate) i: u32,
+    /// The last `z`
+    pub(crate) z: Complex<f64>,
+    /// Closest approach to the orbit trap, infinite without one
+    pub(crate) trap: f64,
+    /// Period of the cycle an interior orbit settled into, 0 if none was found
+    pub(crate) period: u32,
+    /// Estimated distance to the set in pixels, 0 inside, if estimated
+    pub(crate) distance: Option<f64>,
+}
+
+impl From<(u32, Complex<f64>)> for Escape {
+    fn from((i, z): (u32, Complex<f64>)) -> Self {
+        Escape {
+            i,
+            z,
+            trap: f64::INFINITY,
+            period: 0,
+            distance: None,
+        }
+    }
+}
+
+impl Viewport {
+    /// `scale` complex units per pixel around `center_x + center_y i`
+    pub fn centered(width: u32, height: u32, center_x: f64, center_y: f64, scale: f64) -> Self {
+        Viewport {
+            x_min: center_x - width as f64 / 2.0 * scale,
+            y_min: center_y - height as f64 / 2.0 * scale,
+            x_step: scale,
+            y_step: scale,
+            first: (0, 0),
+        }
+    }
+
+    /// The part of the image starting at pixel `x, y`
+    pub fn offset(&self, x: u32, y: u32) -> Self {
+        Viewport {
+            first: (self.first.0 + x, self.first.1 + y),
+            ..*self
+        }
+    }
+
+    pub fn point(&self, x: u32, y: u32) -> Complex<f64> {
+        Complex::new(
+            self.x_min + (self.first.0 + x) as f64 * self.x_step,
+            self.y_min + (self.first.1 + y) as f64 * self.y_step,
+        )
+    }
+}
+
+/// Iterations enough to resolve the detail at `scale` complex units per
+/// pixel: deeper zooms reach slower escapes, so the count grows with the
+/// octaves zoomed in from the whole set
+pub fn auto_max_iter(scale: f64) -> u32 {
+    let octaves = (AUTO_HOME_SCALE / scale).log2().max(0.0);
+    (AUTO_BASE_ITER + AUTO_ITER_PER_OCTAVE * octaves).min(AUTO_MAX_ITER) as u32
+}
+
+/// Zeroed RGBA pixels to render into
+pub fn image(width: u32, height: u32) -> Vec<u8> {
+    vec![0u8; (width * height * 4) as usize]
+}
+
+/// Fill `pixels`, `width` x `height` RGBA, with the part of the plane
+/// `viewport` covers, adding the escape times to `stats` if given; false if
+/// `cancel_renders` cut the render short
+pub fn render_viewport(
+    pixels: &mut [u8],
+    width: u32,
+    height: u32,
+    viewport: &Viewport,
+    max_iter: u32,
+    options: &RenderOptions,
+    stats: Option<&mut RenderStats>,
+) -> bool {
+    let job = Job::new(stats);
+    let bailout = options.bailout();
+    let julia_c = Complex::new(options.julia_re, options.julia_im);
+    if options.shades_orbits() {
+        return render_pixels(pixels, width, height, max_iter, options, job, |x, y| {
+            trace(viewport, x, y, max_iter, options)
+        });
+    }
+    let escape = |x, y| -> Escape {
+        fractal::escape(
+            options.fractal_type,
+            viewport.point(x, y),
+            julia_c,
+            max_iter,
+            bailout,
+        )
+        .into()
+    };
+    if simd::ENABLED && options.fractal_type == FractalType::Mandelbrot {
+        if let Some(uniform) = uniform_border(width, height, max_iter, options, &escape) {
+            return render_rows(pixels, width, height, max_iter, options, job, |_, row| {
+                row.fill(uniform)
+            });
+        }
+        return render_rows(pixels, width, height, max_iter, options, job, |y, row| {
+            simd::escape_row(row, |x| viewport.point(x, y), max_iter, bailout)
+        });
+    }
+    render_pixels(pixels, width, height, max_iter, options, job, escape)
+}
+
+/// Estimated distance in pixels from each pixel of the `width` x `height`
+/// image `viewport` covers to the set, row by row: 0 inside, NaN for maps
+/// distance estimation is not defined for
+pub fn distances(
+    width: u32,
+    height: u32,
+    viewport: &Viewport,
+    max_iter: u32,
+    options: &RenderOptions,
+) -> Vec<f32> {
+    let options = RenderOptions {
+        distance_estimation: true,
+        ..options.clone()
+    };
+    pool::install(|| {
+        (0..width * height)
+            .into_par_iter()
+            .map(|k| {
+                let escaped = trace(viewport, k % width, k / width, max_iter, &options);
+                escaped
+                    .distance
+                    .map_or(f32::NAN, |distance| distance as f32)
+            })
+            .collect()
+    })
+}
+
+/// The orbit of pixel `x, y` with everything orbit shading needs of it
+fn trace(viewport: &Viewport, x: u32, y: u32, max_iter: u32, options: &RenderOptions) -> Escape {
+    let point = viewport.point(x, y);
+    let mut tracer = Tracer::new(options, point, viewport.x_step);
+    let escaped = fractal::trace(
+        options.fractal_type,
+        point,
+        Complex::new(options.julia_re, options.julia_im),
+        max_iter,
+        options.bailout(),
+        |z| tracer.visit(z),
+    );
+    tracer.finish(escaped)
+}
+
+/// What a render reports to besides its pixels: the generation it may be
+/// cancelled in, and the stats it adds to
+pub(crate) struct Job<'a> {
+    pub(crate) generation: Generation,
+    pub(crate) stats: Option<Mutex<&'a mut RenderStats>>,
+}
+
+impl<'a> Job<'a> {
+    /// A job of the current generation
+    pub(crate) fn new(stats: Option<&'a mut RenderStats>) -> Self {
+        Job {
+            generation: Generation::current(),
+            stats: stats.map(Mutex::new),
+        }
+    }
+}
+
+/// Fill `pixels` with RGBA colored from `escape`, which gives the orbit of
+/// pixel `x, y`
+pub(crate) fn render_pixels(
+    pixels: &mut [u8],
+    width: u32,
+    height: u32,
+    max_iter: u32,
+    options: &RenderOptions,
+    job: Job,
+    escape: impl Fn(u32, u32) -> Escape + Sync,
+) -> bool {
+    if let Some(uniform) = uniform_border(width, height, max_iter, options, &escape) {
+        return render_rows(pixels, width, height, max_iter, options, job, |_, row| {
+            row.fill(uniform)
+        });
+    }
+    render_rows(pixels, width, height, max_iter, options, job, |y, row| {
+        for (x, escaped) in (0..).zip(row) {
+            *escaped = escape(x, y);
+        }
+    })
+}
+
+/// The orbit of every pixel of a `width` x `height` part, when
+/// `options.border_check` is on and the part's border is all inside the set
+///
+/// The Mandelbrot set and filled Julia sets have no holes, so a border
+/// entirely inside one encloses only points inside. A border escaping after
+/// the same number of iterations all round says nothing: escape-time bands
+/// are rings around the set, so such a border can enclose all of it.
+fn uniform_border(
+    width: u32,
+    height: u32,
+    max_iter: u32,
+    options: &RenderOptions,
+    escape: &(impl Fn(u32, u32) -> Escape + Sync),
+) -> Option<Escape> {
+    let hole_free = matches!(
+        options.fractal_type,
+        FractalType::Mandelbrot | FractalType::Julia
+    );
+    if !options.border_check || !hole_free || options.shades_orbits() || width < 3 || height < 3 {
+        return None;
+    }
+    let border: Vec<(u32, u32)> = (0..width)
+        .flat_map(|x| [(x, 0), (x, height - 1)])
+        .chain((1..height - 1).flat_map(|y| [(0, y), (width - 1, y)]))
+        .collect();
+    let escapes: Vec<Escape> = border.par_iter().map(|&(x, y)| escape(x, y)).collect();
+    escapes
+        .iter()
+        .all(|escaped| escaped.i >= max_iter)
+        .then_some(escapes[0])
+}
+
+/// Fill `pixels` with RGBA colored from `escape_row`, which gives the orbits
+/// of row `y`; false if the job's generation was
+/// cancelled, and rows not started by then were skipped
+///
+/// Panics unless `pixels` holds exactly `width * height` pixels.
+pub(crate) fn render_rows(
+    pixels: &mut [u8],
+    width: u32,
+    height: u32,
+    max_iter: u32,
+    options: &RenderOptions,
+    job: Job,
+    escape_row: impl Fn(u32, &mut [Escape]) + Sync,
+) -> bool {
+    assert_eq!(
+        pixels.len(),
+        (width * height * 4) as usize,
+        "buffer does not fit a {width}x{height} RGBA image"
+    );
+
+    // Size of one row in bytes, and of the rows one task renders
+    let bytes_per_row = (width * 4) as usize;
+    let rows_per_task = options.rows_per_task.max(1);
+    let bytes_per_task = bytes_per_row * rows_per_task as usize;
+
+    pool::install(|| {
+        pixels
+            .par_chunks_mut(bytes_per_task)
+            .enumerate()
+            .for_each(|(task, rows)| {
+                let mut escapes = vec![Escape::default(); width as usize];
+                let mut task_stats = RenderStats::default();
+                let first_row = task as u32 * rows_per_task;
+                for (row_idx, chunk) in (first_row..).zip(rows.chunks_mut(bytes_per_row)) {
+                    if job.generation.is_cancelled() {
+                        break;
+                    }
+                    escape_row(row_idx, &mut escapes);
+                    if job.stats.is_some() {
+                        for escape in &escapes {
+                            task_stats.add(escape.i, max_iter);
+                        }
+                    }
+                    for (xi, escape) in escapes.iter().enumerate() {
+                        let idx = xi * 4;
+                        let [r, g, b] = shade(escape, max_iter, options);
+                        chunk[idx] = r;
+                        chunk[idx + 1] = g;
+                        chunk[idx + 2] = b;
+                        chunk[idx + 3] = 255; // A
+                    }
+                }
+                if let Some(stats) = &job.stats {
+                    stats.lock().unwrap().merge(&task_stats);
+                }
+            });
+    });
+    !job.generation.is_cancelled()
+}
+
+/// Color of a pixel's orbit
+fn shade(escape: &Escape, max_iter: u32, options: &RenderOptions) -> [u8; 3] {
+    let color = shade_orbit(escape, max_iter, options);
+    if escape.i >= max_iter {
+        return color;
+    }
+    shading::boundary(color, escape, options)
+}
+
+/// Color of a pixel's orbit before distance estimation fades it
+fn shade_orbit(escape: &Escape, max_iter: u32, options: &RenderOptions) -> [u8; 3] {
+    if let Some(color) = shading::color(escape, max_iter, options) {
+        return color;
+    }
+    if escape.i >= max_iter {
+        return options.palette.interior();
+    }
+    let iterations = if options.smooth {
+        smooth_iterations(escape.i, &escape.z)
+    } else {
+        escape.i as f64
+    };
+    let iterations = options.equalized(iterations, max_iter);
+    options.palette.color(iterations, max_iter, &options.lut)
+}
+
+/// Normalized iteration count of an orbit that escaped to `z` after `i`
+/// iterations: `i + 1 - log2(ln |z| / ln R)` for the smooth escape radius R,
+/// continuous across escape-time bands and within `i..i + 1`
+fn smooth_iterations(i: u32, z: &Complex<f64>) -> f64 {
+    // ln |z| / ln R, taken on the squares
+    let log_ratio = z.norm_sqr().ln() / SMOOTH_BAILOUT.ln();
+    (i as f64 + 1.0 - log_ratio.log2()).max(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn test_centered_viewport() {
+        let viewport = Viewport::centered(200, 100, -0.5, 0.25, 0.01);
+        assert_eq!(viewport.point(100, 50), Complex::new(-0.5, 0.25));
+        assert_eq!(viewport.point(0, 0), Complex::new(-1.5, -0.25));
+    }
+
+    #[test]
+    fn test_cancel_skips_remaining_rows() {
+        static COUNTER: AtomicU32 = AtomicU32::new(0);
+        let job = Job {
+            generation: Generation::of(&COUNTER),
+            stats: None,
+        };
+        let (width, height) = (8, 200);
+        let mut pixels = image(width, height);
+        let options = RenderOptions::default();
+        // The first row rendered cancels the rest
+        let finished = render_rows(&mut pixels, width, height, 10, &options, job, |_, _| {
+            COUNTER.store(1, Ordering::Relaxed);
+        });
+        assert!(!finished);
+        let rendered = pixels
+            .chunks((width * 4) as usize)
+            .filter(|row| row[3] == 255)
+            .count();
+        assert!(
+            rendered >= 1 && rendered < height as usize,
+            "{rendered} rows"
+        );
+
+        // A later render is not affected
+        let job = Job {
+            generation: Generation::of(&COUNTER),
+            stats: None,
+        };
+        assert!(render_rows(
+            &mut pixels,
+            width,
+            height,
+            10,
+            &options,
+            job,
+            |_, _| {}
+        ));
+    }
+
+    #[test]
+    fn test_smooth_iterations_are_continuous() {
+        // Along the real axis escape times step by whole iterations; smooth
+        // values must not jump at those steps
+        let mut last: Option<f64> = None;
+        for k in 0..200 {
+            let c = Complex::new(0.3 + k as f64 * 0.001, 0.0);
+            let (i, z) = fractal::escape(FractalType::Mandelbrot, c, c, 1000, SMOOTH_BAILOUT);
+            let value = smooth_iterations(i, &z);
+            assert!(value >= i as f64 && value < i as f64 + 1.0);
+            if let Some(last) = last {
+                assert!((value - last).abs() < 0.5, "jump at {c}");
+            }
+            last = Some(value);
+        }
+    }
+
+    #[test]
+    fn test_auto_max_iter_grows_with_zoom() {
+        assert_eq!(auto_max_iter(AUTO_HOME_SCALE), AUTO_BASE_ITER as u32);
+        assert_eq!(auto_max_iter(1.0), AUTO_BASE_ITER as u32);
+        // Ten halvings in add ten octaves' worth
+        let deep = auto_max_iter(AUTO_HOME_SCALE / 1024.0);
+        assert_eq!(deep, (AUTO_BASE_ITER + 10.0 * AUTO_ITER_PER_OCTAVE) as u32);
+        // The cap is nearly a thousand octaves in, at the bottom of f64
+        assert_eq!(auto_max_iter(f64::MIN_POSITIVE), AUTO_MAX_ITER as u32);
+    }
+
+    #[test]
+    fn test_border_check_matches_full_render() {
+        let render = |options: &RenderOptions, viewport: &Viewport| {
+            let mut pixels = image(16, 16);
+            render_viewport(&mut pixels, 16, 16, viewport, 200, options, None);
+            pixels
+        };
+        for fractal_type in [FractalType::Mandelbrot, FractalType::Julia] {
+            for smooth in [false, true] {
+                // A Julia set close to the unit disc, filled around 0
+                let plain = RenderOptions {
+                    fractal_type,
+                    julia_re: -0.1,
+                    julia_im: 0.1,
+                    smooth,
+                    ..RenderOptions::default()
+                };
+                let checked = RenderOptions {
+                    border_check: true,
+                    ..plain.clone()
+                };
+                // Inside the main cardioid, well outside the set, across its
+                // edge, and around all of it, where the whole border
+                // escapes at once
+                for (x, y, scale) in [
+                    (-0.1, 0.0, 0.005),
+                    (3.0, 3.0, 0.001),
+                    (-0.75, 0.1, 0.01),
+                    (-0.5, 0.0, 0.5),
+                ] {
+                    let viewport = Viewport::centered(16, 16, x, y, scale);
+                    assert_eq!(render(&checked, &viewport), render(&plain, &viewport));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_task_sizes_and_pools_render_alike() {
+        let viewport = Viewport::centered(40, 30, -0.5, 0.0, 0.08);
+        let render = |options: &RenderOptions| {
+            let mut pixels = image(40, 30);
+            render_viewport(&mut pixels, 40, 30, &viewport, 100, options, None);
+            pixels
+        };
+        let expected = render(&RenderOptions::default());
+        // 7 does not divide the 30 rows, leaving a short last task
+        for rows_per_task in [0, 7, 64] {
+            let options = RenderOptions {
+                rows_per_task,
+                ..RenderOptions::default()
+            };
+            assert_eq!(render(&options), expected, "{rows_per_task} rows per task");
+        }
+
+        pool::set_threads(2).unwrap();
+        assert_eq!(pool::current_threads(), 2);
+        assert_eq!(render(&RenderOptions::default()), expected);
+        pool::set_threads(0).unwrap();
+        assert_eq!(pool::current_threads(), rayon::current_num_threads());
+    }
+}
@@ -0,0 +1,137 @@
+//! Lossless PNG images of a render for the page to download
+//!
+//! The parameters of the render go into tEXt chunks next to the pixels, so a
+//! saved image records how to render it again.
+
+use wasm_bindgen::prelude::*;
+
+use crate::fractal::FractalType;
+use crate::{RenderOptions, Trap, render};
+
+/// PNG of the `render` image with the same arguments
+#[wasm_bindgen]
+pub fn export_png(
+    width: u32,
+    height: u32,
+    center_x: f64,
+    center_y: f64,
+    scale: f64,
+    max_iter: u32,
+    options: &RenderOptions,
+) -> Result<Vec<u8>, JsError> {
+    let pixels = render(width, height, center_x, center_y, scale, max_iter, options);
+    let text = parameters(
+        &center_x.to_string(),
+        &center_y.to_string(),
+        scale,
+        max_iter,
+        options,
+    );
+    encode(&pixels, width, height, &text).map_err(|message| JsError::new(&message))
+}
+
+/// tEXt keywords and values describing a render centered on
+/// `center_x + center_y i`, given as decimals
+pub fn parameters(
+    center_x: &str,
+    center_y: &str,
+    scale: f64,
+    max_iter: u32,
+    options: &RenderOptions,
+) -> Vec<(&'static str, String)> {
+    let mut text = vec![
+        (
+            "Software",
+            format!("mandelbrot {}", env!("CARGO_PKG_VERSION")),
+        ),
+        ("Fractal", format!("{:?}", options.fractal_type)),
+        ("Center", format!("{center_x} {center_y}")),
+        ("Scale", scale.to_string()),
+        ("Max iterations", max_iter.to_string()),
+        ("Palette", format!("{:?}", options.palette)),
+        ("Smooth", options.smooth.to_string()),
+        ("Equalized", (!options.equalization.is_empty()).to_string()),
+        ("Interior", format!("{:?}", options.interior)),
+    ];
+    if options.distance_estimation {
+        text.push(("Distance estimation", "true".to_string()));
+    }
+    if options.trap != Trap::None {
+        text.push((
+            "Trap",
+            format!(
+                "{:?} at {} {} radius {}",
+                options.trap, options.trap_re, options.trap_im, options.trap_radius
+            ),
+        ));
+    }
+    if options.fractal_type == FractalType::Julia {
+        text.push((
+            "Julia c",
+            format!("{} {}", options.julia_re, options.julia_im),
+        ));
+    }
+    text
+}
+
+/// `pixels`, `width` x `height` RGBA, as an 8-bit PNG with a tEXt chunk per
+/// entry of `text`
+pub fn encode(
+    pixels: &[u8],
+    width: u32,
+    height: u32,
+    text: &[(&str, String)],
+) -> Result<Vec<u8>, String> {
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    for (keyword, value) in text {
+        encoder
+            .add_text_chunk(keyword.to_string(), value.clone())
+            .map_err(|error| error.to_string())?;
+    }
+    let mut writer = encoder.write_header().map_err(|error| error.to_string())?;
+    writer
+        .write_image_data(pixels)
+        .map_err(|error| error.to_string())?;
+    writer.finish().map_err(|error| error.to_string())?;
+    Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_png_round_trip() {
+        let options = RenderOptions {
+            fractal_type: FractalType::Julia,
+            smooth: true,
+            ..RenderOptions::default()
+        };
+        let pixels = render(30, 20, 0.1, -0.2, 0.1, 80, &options);
+        let text = parameters("0.1", "-0.2", 0.1, 80, &options);
+        let png = encode(&pixels, 30, 20, &text).unwrap();
+
+        let mut reader = png::Decoder::new(std::io::Cursor::new(png))
+            .read_info()
+            .unwrap();
+        let chunks: Vec<(String, String)> = reader
+            .info()
+            .uncompressed_latin1_text
+            .iter()
+            .map(|chunk| (chunk.keyword.clone(), chunk.text.clone()))
+            .collect();
+        assert!(chunks.contains(&("Center".into(), "0.1 -0.2".into())));
+        assert!(chunks.contains(&("Fractal".into(), "Julia".into())));
+        assert!(chunks.contains(&("Julia c".into(), "-0.8 0.156".into())));
+        assert_eq!(chunks.len(), text.len());
+
+        let mut decoded = vec![0; reader.output_buffer_size().unwrap()];
+        let frame = reader.next_frame(&mut decoded).unwrap();
+        assert_eq!((frame.width, frame.height), (30, 20));
+        assert_eq!(frame.color_type, png::ColorType::Rgba);
+        assert_eq!(decoded, pixels);
+    }
+}
@@ -0,0 +1,353 @@
+//! Signed fixed-point numbers of any precision, for deep zoom centers and
+//! reference orbits
+//!
+//! A `Fixed` is a magnitude in 32-bit limbs, least significant first, with one
+//! integer limb and `frac` fractional limbs, plus a sign. One integer limb
+//! covers everything an escape-time iteration produces before bailout.
+//! Operations on numbers of different precision work at the higher one;
+//! multiplication truncates to it.
+
+use std::cmp::Ordering;
+
+/// 32-bit limbs above the binary point
+const INT_LIMBS: usize = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fixed {
+    negative: bool,
+    /// `frac + INT_LIMBS` limbs, least significant first
+    limbs: Vec<u32>,
+    frac: usize,
+}
+
+impl Fixed {
+    pub fn zero(frac: usize) -> Self {
+        Fixed {
+            negative: false,
+            limbs: vec![0; frac + INT_LIMBS],
+            frac,
+        }
+    }
+
+    /// Fractional limbs
+    pub fn frac(&self) -> usize {
+        self.frac
+    }
+
+    fn is_zero(&self) -> bool {
+        self.limbs.iter().all(|&limb| limb == 0)
+    }
+
+    /// The same value with `frac` fractional limbs, truncated if fewer
+    pub fn with_frac(&self, frac: usize) -> Self {
+        let mut limbs = vec![0; frac + INT_LIMBS];
+        for (i, &limb) in self.limbs.iter().enumerate() {
+            // Position relative to the binary point
+            if let Some(j) = (i + frac).checked_sub(self.frac) {
+                limbs[j] = limb;
+            }
+        }
+        Fixed {
+            negative: self.negative,
+            limbs,
+            frac,
+        }
+        .normalized()
+    }
+
+    /// Zero is never negative
+    fn normalized(mut self) -> Self {
+        if self.is_zero() {
+            self.negative = false;
+        }
+        self
+    }
+
+    /// `value` truncated to `frac` fractional limbs; `None` if it is not
+    /// finite or does not fit the integer limb
+    pub fn from_f64(value: f64, frac: usize) -> Option<Self> {
+        if !value.is_finite() || value.abs() >= 2f64.powi(32 * INT_LIMBS as i32) {
+            return None;
+        }
+        let mut limbs = vec![0; frac + INT_LIMBS];
+        // Peel off 32 bits at a time from the top limb down; exact, since
+        // every step only scales by powers of two
+        let mut rest = value.abs();
+        for i in (0..limbs.len()).rev() {
+            let unit = 2f64.powi(32 * (i as i32 - frac as i32));
+            let digit = (rest / unit).floor();
+            limbs[i] = digit as u32;
+            rest -= digit * unit;
+        }
+        Some(
+            Fixed {
+                negative: value < 0.0,
+                limbs,
+                frac,
+            }
+            .normalized(),
+        )
+    }
+
+    /// Nearest f64 (rounding towards zero beyond the 53 bits it keeps)
+    pub fn to_f64(&self) -> f64 {
+        let magnitude: f64 = self
+            .limbs
+            .iter()
+            .enumerate()
+            .map(|(i, &limb)| limb as f64 * 2f64.powi(32 * (i as i32 - self.frac as i32)))
+            .sum();
+        if self.negative { -magnitude } else { magnitude }
+    }
+
+    /// Parse a decimal like `-0.7436438870371587047521915`, rounded to
+    /// `frac` fractional limbs
+    pub fn parse(text: &str, frac: usize) -> Option<Self> {
+        let text = text.trim();
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text.strip_prefix('+').unwrap_or(text)),
+        };
+        let (integer, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        if integer.is_empty() && fraction.is_empty() {
+            return None;
+        }
+        if !integer
+            .bytes()
+            .chain(fraction.bytes())
+            .all(|b| b.is_ascii_digit())
+        {
+            return None;
+        }
+
+        // 0.d1 d2 ... dk by Horner's rule from the last digit, x = (d + x) / 10,
+        // with a guard limb below the last one to round on
+        let mut limbs = vec![0u32; frac + 1 + INT_LIMBS];
+        for digit in fraction.bytes().rev() {
+            limbs[frac + 1] = (digit - b'0') as u32;
+            div_small(&mut limbs, 10);
+        }
+        let guard = limbs.remove(0);
+        if guard >= 1 << 31 {
+            let mut one = vec![0; limbs.len()];
+            one[0] = 1;
+            add_limbs(&mut limbs, &one);
+        }
+        let integer: u32 = if integer.is_empty() {
+            0
+        } else {
+            integer.parse().ok()?
+        };
+        limbs[frac] = limbs[frac].checked_add(integer)?;
+
+        Some(
+            Fixed {
+                negative,
+                limbs,
+                frac,
+            }
+            .normalized(),
+        )
+    }
+
+    /// Decimal that `parse` reads back to the same value at this precision
+    pub fn to_decimal(&self) -> String {
+        let mut text = String::new();
+        if self.negative {
+            text.push('-');
+        }
+        text.push_str(&self.limbs[self.frac].to_string());
+
+        // Ten digits per limb cut off less than half of its last bit, which
+        // `parse` rounds back
+        let mut fraction = self.limbs[..self.frac].to_vec();
+        let mut digits = String::new();
+        while fraction.iter().any(|&limb| limb != 0) && digits.len() < self.frac * 10 {
+            let carry = mul_small(&mut fraction, 10);
+            digits.push(char::from(b'0' + carry as u8));
+        }
+        if !digits.is_empty() {
+            text.push('.');
+            text.push_str(&digits);
+        }
+        text
+    }
+
+    pub fn add(&self, other: &Fixed) -> Fixed {
+        let frac = self.frac.max(other.frac);
+        let (a, b) = (self.with_frac(frac), other.with_frac(frac));
+        if a.negative == b.negative {
+            let mut limbs = a.limbs.clone();
+            add_limbs(&mut limbs, &b.limbs);
+            return Fixed {
+                negative: a.negative,
+                limbs,
+                frac,
+            }
+            .normalized();
+        }
+        // Opposite signs: the larger magnitude keeps its sign
+        let (larger, smaller) = match cmp_limbs(&a.limbs, &b.limbs) {
+            Ordering::Less => (b, a),
+            _ => (a, b),
+        };
+        let mut limbs = larger.limbs.clone();
+        sub_limbs(&mut limbs, &smaller.limbs);
+        Fixed {
+            negative: larger.negative,
+            limbs,
+            frac,
+        }
+        .normalized()
+    }
+
+    pub fn neg(&self) -> Fixed {
+        Fixed {
+            negative: !self.negative,
+            ..self.clone()
+        }
+        .normalized()
+    }
+
+    pub fn sub(&self, other: &Fixed) -> Fixed {
+        self.add(&other.neg())
+    }
+
+    pub fn mul(&self, other: &Fixed) -> Fixed {
+        let frac = self.frac.max(other.frac);
+        let (a, b) = (self.with_frac(frac), other.with_frac(frac));
+        let mut product = vec![0u32; a.limbs.len() + b.limbs.len()];
+        for (i, &x) in a.limbs.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, &y) in b.limbs.iter().enumerate() {
+                let sum = product[i + j] as u64 + x as u64 * y as u64 + carry;
+                product[i + j] = sum as u32;
+                carry = sum >> 32;
+            }
+            product[i + b.limbs.len()] = carry as u32;
+        }
+        // The product has 2 * frac fractional limbs; drop the lowest frac.
+        // Overflow of the integer limb is dropped too: callers stay in range.
+        Fixed {
+            negative: a.negative != b.negative,
+            limbs: product[frac..frac + frac + INT_LIMBS].to_vec(),
+            frac,
+        }
+        .normalized()
+    }
+
+    /// Multiply by 2^`power` exactly (for `power` ≥ 0, overflow is dropped)
+    pub fn mul_pow2(&self, power: u32) -> Fixed {
+        let mut result = self.clone();
+        for _ in 0..power {
+            let mut carry = 0u32;
+            for limb in result.limbs.iter_mut() {
+                let shifted = (*limb as u64) << 1 | carry as u64;
+                *limb = shifted as u32;
+                carry = (shifted >> 32) as u32;
+            }
+        }
+        result
+    }
+}
+
+fn cmp_limbs(a: &[u32], b: &[u32]) -> Ordering {
+    a.iter().rev().cmp(b.iter().rev())
+}
+
+/// `a += b` for equal lengths, dropping the final carry
+fn add_limbs(a: &mut [u32], b: &[u32]) {
+    let mut carry = 0u64;
+    for (x, &y) in a.iter_mut().zip(b) {
+        let sum = *x as u64 + y as u64 + carry;
+        *x = sum as u32;
+        carry = sum >> 32;
+    }
+}
+
+/// `a -= b` for equal lengths with `a >= b`
+fn sub_limbs(a: &mut [u32], b: &[u32]) {
+    let mut borrow = 0i64;
+    for (x, &y) in a.iter_mut().zip(b) {
+        let difference = *x as i64 - y as i64 - borrow;
+        borrow = i64::from(difference < 0);
+        *x = (difference + (borrow << 32)) as u32;
+    }
+}
+
+/// `limbs /= divisor`, truncating
+fn div_small(limbs: &mut [u32], divisor: u32) {
+    let mut remainder = 0u64;
+    for limb in limbs.iter_mut().rev() {
+        let value = (remainder << 32) | *limb as u64;
+        *limb = (value / divisor as u64) as u32;
+        remainder = value % divisor as u64;
+    }
+}
+
+/// `limbs *= factor`, returning what overflows the top limb
+fn mul_small(limbs: &mut [u32], factor: u32) -> u32 {
+    let mut carry = 0u64;
+    for limb in limbs.iter_mut() {
+        let product = *limb as u64 * factor as u64 + carry;
+        *limb = product as u32;
+        carry = product >> 32;
+    }
+    carry as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixed(text: &str) -> Fixed {
+        Fixed::parse(text, 4).unwrap()
+    }
+
+    #[test]
+    fn test_parse_and_format() {
+        assert_eq!(fixed("1.5").to_f64(), 1.5);
+        assert_eq!(fixed("-0.25").to_f64(), -0.25);
+        assert_eq!(fixed(".5").to_decimal(), "0.5");
+        assert_eq!(fixed("-0").to_decimal(), "0");
+        assert_eq!(fixed("-3.125").to_decimal(), "-3.125");
+        assert!(Fixed::parse("1.2.3", 4).is_none());
+        assert!(Fixed::parse("", 4).is_none());
+        assert!(Fixed::parse("0x10", 4).is_none());
+
+        // Digits far beyond f64 are kept, and survive a round trip
+        let deep = fixed("-0.743643887037158704752191506114774");
+        let tail = deep.sub(&fixed("-0.7436438870371587")).to_f64();
+        assert!((tail + 4.752191506114774e-18).abs() < 1e-30);
+        assert_eq!(Fixed::parse(&deep.to_decimal(), 4), Some(deep.clone()));
+        let third = Fixed::from_f64(1.0 / 3.0, 4).unwrap().mul(&deep);
+        assert_eq!(Fixed::parse(&third.to_decimal(), 4), Some(third));
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(fixed("1.25").add(&fixed("-3.5")).to_f64(), -2.25);
+        assert_eq!(fixed("-1.25").sub(&fixed("-1.25")), Fixed::zero(4));
+        assert_eq!(fixed("-1.5").mul(&fixed("2.25")).to_f64(), -3.375);
+        assert_eq!(fixed("0.75").mul_pow2(2).to_f64(), 3.0);
+
+        // 1e-30 squared would vanish in f64 relative to 1; here it does not
+        let tiny = fixed("1.000000000000000000000000000001");
+        let square = tiny.mul(&tiny).sub(&fixed("1"));
+        assert!((square.to_f64() - 2e-30).abs() < 1e-36);
+    }
+
+    #[test]
+    fn test_precision_changes() {
+        let value = Fixed::from_f64(-1.0 / 3.0, 2).unwrap();
+        assert_eq!(value.to_f64(), -1.0 / 3.0);
+        assert_eq!(value.with_frac(5).to_f64(), -1.0 / 3.0);
+        assert_eq!(
+            value.with_frac(1).to_f64(),
+            -(0x5555_5555 as f64) / 2f64.powi(32)
+        );
+        assert_eq!(value.add(&Fixed::from_f64(0.5, 4).unwrap()).frac(), 4);
+        assert!(Fixed::from_f64(f64::NAN, 2).is_none());
+        assert!(Fixed::from_f64(1e10, 2).is_none());
+    }
+}
@@ -0,0 +1,73 @@
+use num_complex::Complex;
+use wasm_bindgen::prelude::*;
+
+/// Iterated map, all of degree two so smooth coloring applies to each
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FractalType {
+    /// z² + c, starting at 0 with c at the pixel
+    #[default]
+    Mandelbrot,
+    /// z² + c, starting at the pixel with a fixed c
+    Julia,
+    /// (|Re z| + i|Im z|)² + c
+    BurningShip,
+    /// conj(z)² + c
+    Tricorn,
+}
+
+/// Iterations until `|z|²` exceeds `bailout` (`max` if it never does), and
+/// the last `z`, for the pixel at `point`
+///
+/// `julia_c` is the constant of `FractalType::Julia`, unused otherwise.
+pub fn escape(
+    fractal: FractalType,
+    point: Complex<f64>,
+    julia_c: Complex<f64>,
+    max: u32,
+    bailout: f64,
+) -> (u32, Complex<f64>) {
+    trace(fractal, point, julia_c, max, bailout, |_| {})
+}
+
+/// `escape`, also calling `visit` with each `z` of the orbit after the start
+pub fn trace(
+    fractal: FractalType,
+    point: Complex<f64>,
+    julia_c: Complex<f64>,
+    max: u32,
+    bailout: f64,
+    visit: impl FnMut(Complex<f64>),
+) -> (u32, Complex<f64>) {
+    let zero = Complex::new(0.0, 0.0);
+    // One loop per map, so the step is inlined rather than matched per iteration
+    match fractal {
+        FractalType::Mandelbrot => orbit(zero, point, max, bailout, visit, |z| z * z),
+        FractalType::Julia => orbit(point, julia_c, max, bailout, visit, |z| z * z),
+        FractalType::BurningShip => orbit(zero, point, max, bailout, visit, |z| {
+            let folded = Complex::new(z.re.abs(), z.im.abs());
+            folded * folded
+        }),
+        FractalType::Tricorn => orbit(zero, point, max, bailout, visit, |z| {
+            let conjugate = z.conj();
+            conjugate * conjugate
+        }),
+    }
+}
+
+fn orbit(
+    mut z: Complex<f64>,
+    c: Complex<f64>,
+    max: u32,
+    bailout: f64,
+    mut visit: impl FnMut(Complex<f64>),
+    square: impl Fn(Complex<f64>) -> Complex<f64>,
+) -> (u32, Complex<f64>) {
+    let mut i = 0u32;
+    while z.norm_sqr() <= bailout && i < max {
+        z = square(z) + c;
+        visit(z);
+        i += 1;
+    }
+    (i, z)
+}
@@ -0,0 +1,77 @@
+//! Images rendered in place in wasm memory
+//!
+//! A JS array passed as `&mut [u8]` is copied into wasm memory and back out
+//! on every call. A `Frame` keeps its pixels in wasm memory instead, so the
+//! page renders into the same one every frame and reads it where it is.
+
+use wasm_bindgen::prelude::*;
+
+use crate::engine;
+
+/// RGBA pixels of a `width` x `height` image in wasm memory, built in JS
+/// with `new Frame(width, height)`
+///
+/// The page views them with
+/// `new Uint8ClampedArray(wasm_memory().buffer, frame.ptr(), frame.len())`,
+/// made again after each render since growing the memory replaces its
+/// buffer.
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct Frame {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl Frame {
+    #[wasm_bindgen(constructor)]
+    pub fn new(width: u32, height: u32) -> Self {
+        Frame {
+            width,
+            height,
+            pixels: engine::image(width, height),
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Address of the first pixel in wasm memory
+    pub fn ptr(&self) -> *const u8 {
+        self.pixels.as_ptr()
+    }
+
+    /// Size of the pixels in bytes
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.pixels.len()
+    }
+}
+
+impl Frame {
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub(crate) fn pixels_mut(&mut self) -> &mut [u8] {
+        &mut self.pixels
+    }
+
+    pub(crate) fn into_pixels(self) -> Vec<u8> {
+        self.pixels
+    }
+}
+
+/// The module's memory, which frames keep their pixels in
+#[wasm_bindgen]
+pub fn wasm_memory() -> JsValue {
+    wasm_bindgen::memory()
+}
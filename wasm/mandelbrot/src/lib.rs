@@ -1,48 +1,514 @@
+use wasm_bindgen::prelude::*;
 
+use engine::{Viewport, image, render_viewport};
 
+mod cancel;
+mod deep;
+pub mod engine;
+pub mod export;
+mod fixed;
+mod fractal;
+mod frame;
+mod palette;
+pub mod pool;
+mod shading;
+mod simd;
+mod stats;
 
-use wasm_bindgen::prelude::*;
-use rayon::prelude::*;
-use num_complex::Complex;
-
+pub use cancel::{cancel_renders, generation_ptr};
+pub use deep::DeepView;
+pub use export::export_png;
+pub use fractal::FractalType;
+pub use frame::{Frame, wasm_memory};
+pub use palette::Palette;
+pub use shading::{Interior, Trap};
+pub use stats::RenderStats;
+#[cfg(target_arch = "wasm32")]
 pub use wasm_bindgen_rayon::init_thread_pool;
 
+/// Squared escape radius of plain escape-time coloring
+const BAILOUT: f64 = 4.0;
+
+/// Squared escape radius for smooth coloring: the normalized iteration count
+/// only becomes continuous well outside the radius 2 circle
+const SMOOTH_BAILOUT: f64 = 256.0 * 256.0;
+
+/// Fractal and coloring of a render, built in JS with `new RenderOptions()`
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct RenderOptions {
+    pub fractal_type: FractalType,
+    /// Constant c of `FractalType::Julia`
+    pub julia_re: f64,
+    pub julia_im: f64,
+    pub palette: Palette,
+    /// Color by the normalized iteration count instead of whole iterations,
+    /// removing the bands between escape times
+    pub smooth: bool,
+    /// Color by the orbit's closest approach to a shape instead
+    pub trap: Trap,
+    /// Point the trap is placed at
+    pub trap_re: f64,
+    pub trap_im: f64,
+    /// Radius of `Trap::Circle`
+    pub trap_radius: f64,
+    pub interior: Interior,
+    /// Fade escaping pixels into the interior color by their estimated
+    /// distance to the set, for a thin anti-aliased boundary; Mandelbrot and
+    /// Julia sets only
+    pub distance_estimation: bool,
+    /// Check the border of each tile first, and fill the tile without
+    /// iterating its inside when the border is all inside the set
+    pub border_check: bool,
+    /// Rows each task of the thread pool renders; more cut the overhead of
+    /// tiny tasks, fewer keep every thread busy on short images
+    pub rows_per_task: u32,
+    lut: Vec<[u8; 3]>,
+    /// Share of escaped pixels below each escape time, empty unless
+    /// equalizing
+    equalization: Vec<f64>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            fractal_type: FractalType::default(),
+            // A connected Julia set with plenty of spirals
+            julia_re: -0.8,
+            julia_im: 0.156,
+            palette: Palette::default(),
+            smooth: false,
+            trap: Trap::default(),
+            trap_re: 0.0,
+            trap_im: 0.0,
+            trap_radius: 0.5,
+            interior: Interior::default(),
+            distance_estimation: false,
+            border_check: false,
+            rows_per_task: 1,
+            lut: Vec::new(),
+            equalization: Vec::new(),
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl RenderOptions {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Colors of `Palette::Custom` as RGB triplets, spread evenly from escape
+    /// at the first iteration to `max_iter`; a trailing partial triplet is
+    /// ignored
+    pub fn set_lut(&mut self, lut: &[u8]) {
+        self.lut = lut.as_chunks::<3>().0.to_vec();
+    }
+
+    /// Spread the palette evenly over the escape times of `stats` rather than
+    /// linearly up to `max_iter`, so every color covers about as many pixels
+    ///
+    /// Stats of a coarse preview are enough to equalize the full render.
+    pub fn equalize(&mut self, stats: &RenderStats) {
+        self.equalization = stats.cumulative();
+    }
+
+    /// Back to linear coloring
+    pub fn clear_equalization(&mut self) {
+        self.equalization.clear();
+    }
+}
+
+impl RenderOptions {
+    /// Squared escape radius these options iterate to
+    fn bailout(&self) -> f64 {
+        // The distance estimate is only accurate well outside radius 2 too
+        if self.smooth || self.estimates_distance() {
+            SMOOTH_BAILOUT
+        } else {
+            BAILOUT
+        }
+    }
+
+    /// Whether coloring needs more of each orbit than where it ended
+    fn shades_orbits(&self) -> bool {
+        self.trap != Trap::None || self.interior != Interior::Flat || self.estimates_distance()
+    }
+
+    /// Whether to follow the derivative of each orbit: distance estimation
+    /// is on, for a map it is defined for
+    fn estimates_distance(&self) -> bool {
+        self.distance_estimation
+            && matches!(
+                self.fractal_type,
+                FractalType::Mandelbrot | FractalType::Julia
+            )
+    }
+
+    /// `iterations` moved to where its share of pixels puts it when
+    /// equalizing, interpolating between whole escape times
+    fn equalized(&self, iterations: f64, max_iter: u32) -> f64 {
+        let Some(&last) = self.equalization.last() else {
+            return iterations;
+        };
+        let share = |k: usize| self.equalization.get(k).copied().unwrap_or(last);
+        let k = iterations as usize;
+        let fraction = iterations - k as f64;
+        (share(k) + (share(k + 1) - share(k)) * fraction) * max_iter as f64
+    }
+}
+
+/// Iterations that resolve the detail at `scale` complex units per pixel,
+/// growing with every halving of the scale
+#[wasm_bindgen]
+pub fn auto_max_iter(scale: f64) -> u32 {
+    engine::auto_max_iter(scale)
+}
+
+/// The whole set in (-2.5..1.0, -1.0..1.0), stretched to `width` x `height`
 #[wasm_bindgen]
 pub fn mandelbrot(width: u32, height: u32, max_iter: u32) -> Vec<u8> {
-    
-    let mut pixels = vec![0u8; (width * height * 4) as usize];
+    let viewport = Viewport {
+        x_min: -2.5,
+        y_min: -1.0,
+        x_step: 3.5 / width as f64,
+        y_step: 2.0 / height as f64,
+        first: (0, 0),
+    };
+    let mut pixels = image(width, height);
+    let options = RenderOptions::default();
+    render_viewport(
+        &mut pixels,
+        width,
+        height,
+        &viewport,
+        max_iter,
+        &options,
+        None,
+    );
+    pixels
+}
 
-    // Size of one row in bytes
-    let bytes_per_row = (width * 4) as usize;
+/// RGBA pixels of the view centered on `center_x + center_y i`, with `scale`
+/// complex units per pixel
+///
+/// Zoom by dividing `scale`; pan by moving the center `dx * scale` for a drag
+/// of `dx` pixels.
+#[wasm_bindgen]
+pub fn render(
+    width: u32,
+    height: u32,
+    center_x: f64,
+    center_y: f64,
+    scale: f64,
+    max_iter: u32,
+    options: &RenderOptions,
+) -> Vec<u8> {
+    let mut frame = Frame::new(width, height);
+    render_into(&mut frame, center_x, center_y, scale, max_iter, options);
+    frame.into_pixels()
+}
 
+/// `render` into `frame`, at its size
+///
+/// Interactive pages can render into the same frame every time instead of
+/// getting a new image per call. Returns false if `cancel_renders` cut the
+/// render short, leaving some rows as they were.
+#[wasm_bindgen]
+pub fn render_into(
+    frame: &mut Frame,
+    center_x: f64,
+    center_y: f64,
+    scale: f64,
+    max_iter: u32,
+    options: &RenderOptions,
+) -> bool {
+    let (width, height) = (frame.width(), frame.height());
+    let viewport = Viewport::centered(width, height, center_x, center_y, scale);
+    let pixels = frame.pixels_mut();
+    render_viewport(pixels, width, height, &viewport, max_iter, options, None)
+}
 
+/// `render`, also adding the escape times of its pixels to `stats`
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn render_with_stats(
+    width: u32,
+    height: u32,
+    center_x: f64,
+    center_y: f64,
+    scale: f64,
+    max_iter: u32,
+    options: &RenderOptions,
+    stats: &mut RenderStats,
+) -> Vec<u8> {
+    let viewport = Viewport::centered(width, height, center_x, center_y, scale);
+    let mut pixels = image(width, height);
+    render_viewport(
+        &mut pixels,
+        width,
+        height,
+        &viewport,
+        max_iter,
+        options,
+        Some(stats),
+    );
     pixels
-        .par_chunks_mut(bytes_per_row)
-        .enumerate()
-        .for_each(|(row_idx, chunk)| {
-            for xi in 0..width {
-                let idx = (xi as usize * 4) as usize;
-                let c = Complex::new(
-                    (xi as f64 / width as f64 * 3.5) - 2.5,
-                    (row_idx as f64 / height as f64 * 2.0) - 1.0,
-                );
-                let i = iter(&c, max_iter);
-                let color = ((i as f64 / max_iter as f64) * 255.0) as u8;
-                chunk[idx] = 255;      // R
-                chunk[idx + 1] = color; // G
-                chunk[idx + 2] = 0;     // B
-                chunk[idx + 3] = 255;   // A
-            }
-    });
+}
+
+/// Estimated distance from each pixel of the `render` image with the same
+/// arguments to the set, in pixels: 0 inside, NaN unless the fractal is the
+/// Mandelbrot or a Julia set
+///
+/// Estimates whether or not `options.distance_estimation` is on, for the page
+/// to threshold or shade the boundary itself.
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn distance_estimates(
+    width: u32,
+    height: u32,
+    center_x: f64,
+    center_y: f64,
+    scale: f64,
+    max_iter: u32,
+    options: &RenderOptions,
+) -> Vec<f32> {
+    let viewport = Viewport::centered(width, height, center_x, center_y, scale);
+    engine::distances(width, height, &viewport, max_iter, options)
+}
+
+/// RGBA pixels of one `tile_size` square tile of the `render` image with the
+/// same arguments; tile `tile_x, tile_y` starts at pixel
+/// `tile_x * tile_size, tile_y * tile_size`
+///
+/// Tiles are always whole: those on the right and bottom edges run past the
+/// image and the caller clips them. Rendering tile by tile lets the page
+/// show a coarse `render` first and refine it between animation frames.
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn render_tile(
+    tile_x: u32,
+    tile_y: u32,
+    tile_size: u32,
+    width: u32,
+    height: u32,
+    center_x: f64,
+    center_y: f64,
+    scale: f64,
+    max_iter: u32,
+    options: &RenderOptions,
+) -> Vec<u8> {
+    let viewport = Viewport::centered(width, height, center_x, center_y, scale)
+        .offset(tile_x * tile_size, tile_y * tile_size);
+    let mut pixels = image(tile_size, tile_size);
+    render_viewport(
+        &mut pixels,
+        tile_size,
+        tile_size,
+        &viewport,
+        max_iter,
+        options,
+        None,
+    );
     pixels
 }
 
-fn iter(c: &Complex<f64>, max: u32) -> u32 {
-    let mut z = Complex::new(0.0, 0.0);
-    let mut i = 0u32;
-    while z.norm_sqr() <= 4.0 && i < max {
-        z = z * z + *c;
-        i += 1;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num_complex::Complex;
+
+    #[test]
+    fn test_render_matches_mandelbrot() {
+        // The classic view is centered on -0.75 with square pixels at 350x200
+        let classic = mandelbrot(350, 200, 50);
+        let centered = render(350, 200, -0.75, 0.0, 0.01, 50, &RenderOptions::default());
+        assert_eq!(classic.len(), 350 * 200 * 4);
+        assert_eq!(classic, centered);
+    }
+
+    #[test]
+    fn test_render_into_reuses_buffer() {
+        let options = RenderOptions::default();
+        let mut frame = Frame::new(40, 30);
+        render_into(&mut frame, -0.5, 0.0, 0.1, 50, &options);
+        assert_eq!(frame.pixels(), render(40, 30, -0.5, 0.0, 0.1, 50, &options));
+        render_into(&mut frame, 0.3, 0.2, 0.01, 50, &options);
+        assert_eq!(frame.pixels(), render(40, 30, 0.3, 0.2, 0.01, 50, &options));
+    }
+
+    #[test]
+    fn test_render_stats() {
+        let options = RenderOptions::default();
+        let mut stats = RenderStats::new();
+        render_with_stats(60, 40, -0.5, 0.0, 0.06, 100, &options, &mut stats);
+
+        let viewport = Viewport::centered(60, 40, -0.5, 0.0, 0.06);
+        let counts: Vec<u32> = (0..40)
+            .flat_map(|y| (0..60).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                fractal::escape(
+                    FractalType::Mandelbrot,
+                    viewport.point(x, y),
+                    Complex::new(0.0, 0.0),
+                    100,
+                    BAILOUT,
+                )
+                .0
+            })
+            .collect();
+        let escaped: Vec<u32> = counts.iter().copied().filter(|&i| i < 100).collect();
+        assert_eq!(stats.escaped() as usize, escaped.len());
+        assert_eq!(stats.interior() as usize, counts.len() - escaped.len());
+        assert_eq!(stats.min(), *escaped.iter().min().unwrap());
+        assert_eq!(stats.max(), *escaped.iter().max().unwrap());
+        let mean = escaped.iter().sum::<u32>() as f64 / escaped.len() as f64;
+        assert!((stats.mean() - mean).abs() < 1e-9);
+        assert_eq!(
+            stats.histogram()[1],
+            escaped.iter().filter(|&&i| i == 1).count() as u32
+        );
+
+        // Renders add up until cleared
+        render_with_stats(60, 40, -0.5, 0.0, 0.06, 100, &options, &mut stats);
+        assert_eq!(stats.escaped() as usize, 2 * escaped.len());
+        stats.clear();
+        assert_eq!(stats, RenderStats::new());
+        assert!(stats.mean().is_nan());
+    }
+
+    #[test]
+    fn test_equalization_spreads_colors() {
+        let mut options = RenderOptions {
+            palette: Palette::Grayscale,
+            smooth: true,
+            ..RenderOptions::default()
+        };
+        let mut stats = RenderStats::new();
+        let linear = render_with_stats(80, 60, -0.5, 0.0, 0.05, 500, &options, &mut stats);
+        options.equalize(&stats);
+        let equalized = render(80, 60, -0.5, 0.0, 0.05, 500, &options);
+
+        // Most pixels escape within a few of the 500 iterations, so a linear
+        // ramp leaves them near black; equalized, they average mid gray
+        let mean = |pixels: &[u8]| {
+            pixels.iter().step_by(4).map(|&r| r as f64).sum::<f64>() / (80.0 * 60.0)
+        };
+        assert!(mean(&linear) < 40.0, "{}", mean(&linear));
+        assert!(mean(&equalized) > 80.0, "{}", mean(&equalized));
+
+        options.clear_equalization();
+        assert_eq!(render(80, 60, -0.5, 0.0, 0.05, 500, &options), linear);
+    }
+
+    #[test]
+    fn test_tiles_assemble_to_render() {
+        let options = RenderOptions {
+            smooth: true,
+            ..RenderOptions::default()
+        };
+        let (width, height, tile) = (48, 40, 16);
+        let image = render(width, height, -0.5, 0.1, 0.05, 64, &options);
+
+        for tile_y in 0..height.div_ceil(tile) {
+            for tile_x in 0..width.div_ceil(tile) {
+                let pixels = render_tile(
+                    tile_x, tile_y, tile, width, height, -0.5, 0.1, 0.05, 64, &options,
+                );
+                assert_eq!(pixels.len(), (tile * tile * 4) as usize);
+                for (row, tile_row) in pixels.chunks_exact((tile * 4) as usize).enumerate() {
+                    let y = tile_y * tile + row as u32;
+                    let x = tile_x * tile;
+                    if y >= height {
+                        break;
+                    }
+                    let visible = (tile.min(width - x) * 4) as usize;
+                    let start = ((y * width + x) * 4) as usize;
+                    assert_eq!(&tile_row[..visible], &image[start..start + visible]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_fractal_types() {
+        let zero = Complex::new(0.0, 0.0);
+        let escape = |fractal, point| fractal::escape(fractal, point, zero, 100, BAILOUT).0;
+
+        // Julia with c = 0 is the unit disc
+        assert_eq!(escape(FractalType::Julia, Complex::new(0.0, 0.9)), 100);
+        assert!(escape(FractalType::Julia, Complex::new(0.0, 1.1)) < 100);
+
+        // On the real axis every map is z² + c
+        for re in [-1.9, -1.0, 0.2, 0.3] {
+            let c = Complex::new(re, 0.0);
+            let mandelbrot = escape(FractalType::Mandelbrot, c);
+            assert_eq!(escape(FractalType::BurningShip, c), mandelbrot);
+            assert_eq!(escape(FractalType::Tricorn, c), mandelbrot);
+        }
+
+        // Off it they differ: inside the Mandelbrot set but not the others
+        let c = Complex::new(-0.2, 0.7);
+        assert_eq!(escape(FractalType::Mandelbrot, c), 100);
+        assert!(escape(FractalType::BurningShip, c) < 100);
+        assert!(escape(FractalType::Tricorn, c) < 100);
+    }
+
+    #[test]
+    fn test_distance_estimates() {
+        let options = RenderOptions::default();
+        // Pixel 1, 1 is the center: 2 is 1.75 from the tip of the cardioid,
+        // which the estimate gets within a factor of two
+        let outside = distance_estimates(2, 2, 2.0, 0.0, 0.01, 100, &options);
+        assert!(outside[3] > 87.5 && outside[3] < 350.0, "{}", outside[3]);
+        let inside = distance_estimates(2, 2, 0.0, 0.0, 0.01, 100, &options);
+        assert_eq!(inside[3], 0.0);
+        let tricorn = RenderOptions {
+            fractal_type: FractalType::Tricorn,
+            ..options
+        };
+        assert!(distance_estimates(2, 2, 2.0, 0.0, 0.01, 100, &tricorn)[3].is_nan());
+
+        // Far from the set the colors stay as they were; a tenth of a pixel
+        // off the tip of the antenna they fade towards the interior color
+        let plain = RenderOptions {
+            smooth: true,
+            ..RenderOptions::default()
+        };
+        let estimating = RenderOptions {
+            distance_estimation: true,
+            ..plain.clone()
+        };
+        assert_eq!(
+            render(2, 2, 2.0, 0.0, 0.01, 100, &estimating),
+            render(2, 2, 2.0, 0.0, 0.01, 100, &plain)
+        );
+        let tip = -2.0 - 1e-7;
+        let near = distance_estimates(2, 2, tip, 0.0, 1e-6, 100, &estimating)[3];
+        assert!(near > 0.05 && near < 0.2, "{near}");
+        assert_ne!(
+            render(2, 2, tip, 0.0, 1e-6, 100, &estimating),
+            render(2, 2, tip, 0.0, 1e-6, 100, &plain)
+        );
+    }
+
+    #[test]
+    fn test_palettes() {
+        assert_eq!(Palette::Classic.color(0.0, 100, &[]), [255, 0, 0]);
+        assert_eq!(Palette::Grayscale.color(50.0, 100, &[]), [127, 127, 127]);
+        assert_eq!(Palette::Viridis.color(100.0, 100, &[]), [253, 231, 37]);
+        assert_eq!(Palette::Fire.interior(), [0, 0, 0]);
+        // Hue cycles, starting at red
+        assert_eq!(
+            Palette::Hsv.color(0.0, 100, &[]),
+            Palette::Hsv.color(32.0, 100, &[])
+        );
+        assert_eq!(Palette::Hsv.color(0.0, 100, &[])[0], 255);
+
+        let mut options = RenderOptions::new();
+        options.set_lut(&[0, 0, 0, 200, 100, 0, 9]);
+        assert_eq!(options.lut, [[0, 0, 0], [200, 100, 0]]);
+        assert_eq!(Palette::Custom.color(50.0, 100, &options.lut), [100, 50, 0]);
     }
-    i
 }
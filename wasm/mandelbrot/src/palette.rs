@@ -0,0 +1,101 @@
+use wasm_bindgen::prelude::*;
+
+/// How escape times map to colors
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Palette {
+    /// Red to yellow, the original look
+    #[default]
+    Classic,
+    Grayscale,
+    Viridis,
+    /// Black through red and orange to white
+    Fire,
+    /// Hue cycling every `HSV_PERIOD` iterations
+    Hsv,
+    /// RGB lookup table set with `RenderOptions::set_lut`
+    Custom,
+}
+
+/// Iterations per full turn of the hue wheel
+const HSV_PERIOD: f64 = 32.0;
+
+// Matplotlib's viridis, sampled at nine evenly spaced points
+const VIRIDIS: [[u8; 3]; 9] = [
+    [68, 1, 84],
+    [71, 44, 122],
+    [59, 81, 139],
+    [44, 113, 142],
+    [33, 144, 141],
+    [39, 173, 129],
+    [92, 200, 99],
+    [170, 220, 50],
+    [253, 231, 37],
+];
+
+const FIRE: [[u8; 3]; 5] = [
+    [0, 0, 0],
+    [160, 20, 0],
+    [255, 110, 0],
+    [255, 220, 40],
+    [255, 255, 255],
+];
+
+impl Palette {
+    /// Color of a point that escaped after `iterations` (fractional when
+    /// smoothed) out of `max_iter`; `lut` holds the colors of `Custom`
+    pub fn color(self, iterations: f64, max_iter: u32, lut: &[[u8; 3]]) -> [u8; 3] {
+        let t = (iterations / max_iter as f64).clamp(0.0, 1.0);
+        match self {
+            Palette::Classic => [255, (t * 255.0) as u8, 0],
+            Palette::Grayscale => [(t * 255.0) as u8; 3],
+            Palette::Viridis => gradient(&VIRIDIS, t),
+            Palette::Fire => gradient(&FIRE, t),
+            Palette::Hsv => hsv((iterations / HSV_PERIOD).fract(), 0.8, 1.0),
+            Palette::Custom => gradient(lut, t),
+        }
+    }
+
+    /// Color of points that never escaped
+    pub fn interior(self) -> [u8; 3] {
+        match self {
+            // Escape time max_iter in the original coloring
+            Palette::Classic => [255, 255, 0],
+            _ => [0, 0, 0],
+        }
+    }
+}
+
+/// Linear interpolation between evenly spaced `stops` at `t` in 0..=1
+fn gradient(stops: &[[u8; 3]], t: f64) -> [u8; 3] {
+    match stops {
+        [] => [0, 0, 0],
+        [only] => *only,
+        _ => {
+            let position = t * (stops.len() - 1) as f64;
+            let index = (position as usize).min(stops.len() - 2);
+            let fraction = position - index as f64;
+            let (a, b) = (stops[index], stops[index + 1]);
+            [0, 1, 2].map(|i| (a[i] as f64 + (b[i] as f64 - a[i] as f64) * fraction).round() as u8)
+        }
+    }
+}
+
+/// RGB of a hue in 0..1 turns
+fn hsv(hue: f64, saturation: f64, value: f64) -> [u8; 3] {
+    let h = hue * 6.0;
+    let sector = h.floor() as u32 % 6;
+    let f = h - h.floor();
+    let p = value * (1.0 - saturation);
+    let q = value * (1.0 - saturation * f);
+    let t = value * (1.0 - saturation * (1.0 - f));
+    let (r, g, b) = match sector {
+        0 => (value, t, p),
+        1 => (q, value, p),
+        2 => (p, value, t),
+        3 => (p, q, value),
+        4 => (t, p, value),
+        _ => (value, p, q),
+    };
+    [r, g, b].map(|channel| (channel * 255.0).round() as u8)
+}
@@ -0,0 +1,45 @@
+//! The thread pool renders run on
+//!
+//! On the page it is the global pool `init_thread_pool` starts on web
+//! workers, sized once when the page loads. Native builds can swap in a pool
+//! of another size with `set_threads` between renders.
+
+use std::sync::{Arc, Mutex};
+
+use rayon::{ThreadPool, ThreadPoolBuildError, ThreadPoolBuilder};
+
+/// Pool replacing the global one, if `set_threads` made one
+static POOL: Mutex<Option<Arc<ThreadPool>>> = Mutex::new(None);
+
+/// Run later renders on `threads` threads, or back on rayon's global pool if
+/// 0; renders already running finish on the pool they started on
+///
+/// Only native builds can start threads; the page sizes its pool with
+/// `init_thread_pool` instead.
+pub fn set_threads(threads: usize) -> Result<(), ThreadPoolBuildError> {
+    let pool = match threads {
+        0 => None,
+        _ => Some(Arc::new(
+            ThreadPoolBuilder::new().num_threads(threads).build()?,
+        )),
+    };
+    *POOL.lock().unwrap() = pool;
+    Ok(())
+}
+
+/// Threads renders currently run on
+pub fn current_threads() -> usize {
+    match &*POOL.lock().unwrap() {
+        Some(pool) => pool.current_num_threads(),
+        None => rayon::current_num_threads(),
+    }
+}
+
+/// Run `op` on the pool set by `set_threads`, or the global one
+pub(crate) fn install<R: Send>(op: impl FnOnce() -> R + Send) -> R {
+    let pool = POOL.lock().unwrap().clone();
+    match pool {
+        Some(pool) => pool.install(op),
+        None => op(),
+    }
+}
@@ -0,0 +1,249 @@
+//! Coloring by the whole orbit rather than its escape time
+//!
+//! An orbit trap colors a pixel by how close its orbit comes to a shape,
+//! inside the set as well as outside. Interior shading colors the points
+//! that never escape by the period of the cycle their orbit settles into,
+//! which tells the bulbs of the set apart. Distance estimation follows the
+//! derivative of the orbit along with it, giving how far an escaping pixel
+//! is from the set, and fades the colors into the interior color at the
+//! boundary so it comes out anti-aliased.
+
+use num_complex::Complex;
+use wasm_bindgen::prelude::*;
+
+use crate::engine::Escape;
+use crate::{FractalType, RenderOptions};
+
+/// Shape whose distance to the orbit colors a pixel
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Trap {
+    /// No trap: color by escape time
+    #[default]
+    None,
+    /// The point `trap_re + trap_im i`
+    Point,
+    /// The horizontal line through the trap point
+    Line,
+    /// The circle of radius `trap_radius` around the trap point
+    Circle,
+}
+
+/// How points that never escape are colored
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Interior {
+    /// The palette's interior color
+    #[default]
+    Flat,
+    /// A color per period of the cycle the orbit settles into
+    Period,
+}
+
+/// Distance from the trap at which a color has faded to 1/e of the closest
+const TRAP_FALLOFF: f64 = 0.25;
+
+/// Periods colored apart before the colors repeat
+const PERIOD_COLORS: u32 = 8;
+
+/// Distance under which two points of an orbit count as the same
+const PERIOD_EPSILON: f64 = 1e-10;
+
+/// Pixels from the set over which distance estimation fades out the
+/// boundary
+const BOUNDARY_WIDTH: f64 = 1.0;
+
+impl Trap {
+    fn distance(self, z: Complex<f64>, center: Complex<f64>, radius: f64) -> f64 {
+        match self {
+            Trap::None => f64::INFINITY,
+            Trap::Point => (z - center).norm(),
+            Trap::Line => (z.im - center.im).abs(),
+            Trap::Circle => ((z - center).norm() - radius).abs(),
+        }
+    }
+}
+
+/// Follows an orbit for the shading of `options`, one `visit` per iteration
+pub struct Tracer<'a> {
+    options: &'a RenderOptions,
+    /// Closest approach to the trap so far
+    trap: f64,
+    step: u32,
+    /// Brent's cycle detection: the `z` saved at the last power of two
+    /// iterations, compared with every later one
+    saved: Complex<f64>,
+    saved_at: u32,
+    period: u32,
+    /// The `z` before the one visited last, and the derivative of the orbit
+    /// by the pixel's point at it
+    last: Complex<f64>,
+    derivative: Complex<f64>,
+    /// Complex units per pixel
+    pixel: f64,
+}
+
+impl<'a> Tracer<'a> {
+    /// Tracer of the orbit of the pixel at `point`, `pixel` complex units
+    /// wide
+    pub fn new(options: &'a RenderOptions, point: Complex<f64>, pixel: f64) -> Self {
+        // Julia orbits start at the point, so dz/dz₀ starts at 1; Mandelbrot
+        // orbits start at 0 and dz/dc at 0
+        let (last, derivative) = match options.fractal_type {
+            FractalType::Julia => (point, Complex::new(1.0, 0.0)),
+            _ => (Complex::new(0.0, 0.0), Complex::new(0.0, 0.0)),
+        };
+        Tracer {
+            options,
+            trap: f64::INFINITY,
+            step: 0,
+            saved: Complex::new(f64::NAN, f64::NAN),
+            saved_at: 0,
+            period: 0,
+            last,
+            derivative,
+            pixel,
+        }
+    }
+
+    pub fn visit(&mut self, z: Complex<f64>) {
+        let options = self.options;
+        self.step += 1;
+        if options.trap != Trap::None {
+            let center = Complex::new(options.trap_re, options.trap_im);
+            self.trap = self
+                .trap
+                .min(options.trap.distance(z, center, options.trap_radius));
+        }
+        if options.interior == Interior::Period && self.period == 0 {
+            if (z - self.saved).norm_sqr() < PERIOD_EPSILON * PERIOD_EPSILON {
+                self.period = self.step - self.saved_at;
+            } else if self.step.is_power_of_two() {
+                self.saved = z;
+                self.saved_at = self.step;
+            }
+        }
+        if options.estimates_distance() {
+            // d(z² + c) = 2z dz, plus dc when c is the point
+            let dc = match options.fractal_type {
+                FractalType::Julia => 0.0,
+                _ => 1.0,
+            };
+            self.derivative = self.last * self.derivative * 2.0 + dc;
+            self.last = z;
+        }
+    }
+
+    /// The pixel whose orbit ended in `escaped` after the visits
+    pub fn finish(self, (i, z): (u32, Complex<f64>)) -> Escape {
+        Escape {
+            i,
+            z,
+            trap: self.trap,
+            period: self.period,
+            distance: self.distance(z),
+        }
+    }
+
+    /// Distance in pixels from the pixel to the set, `|z| ln |z| / |dz|`,
+    /// for an orbit that ended at `z`; 0 inside
+    fn distance(&self, z: Complex<f64>) -> Option<f64> {
+        if !self.options.estimates_distance() {
+            return None;
+        }
+        if z.norm_sqr() <= self.options.bailout() {
+            return Some(0.0);
+        }
+        let r = z.norm();
+        Some(r * r.ln() / self.derivative.norm() / self.pixel)
+    }
+}
+
+/// Color of a pixel when orbit shading decides it, `None` when its escape
+/// time does
+pub fn color(escape: &Escape, max_iter: u32, options: &RenderOptions) -> Option<[u8; 3]> {
+    let palette = |t: f64| {
+        options
+            .palette
+            .color(t * max_iter as f64, max_iter, &options.lut)
+    };
+    if escape.i >= max_iter && escape.period > 0 {
+        let slot = (escape.period - 1) % PERIOD_COLORS + 1;
+        return Some(palette(slot as f64 / (PERIOD_COLORS + 1) as f64));
+    }
+    if options.trap != Trap::None {
+        return Some(palette((-escape.trap / TRAP_FALLOFF).exp()));
+    }
+    None
+}
+
+/// `color` faded into the palette's interior color as an escaping pixel
+/// nears the set, when its distance was estimated
+pub fn boundary(color: [u8; 3], escape: &Escape, options: &RenderOptions) -> [u8; 3] {
+    let Some(distance) = escape.distance else {
+        return color;
+    };
+    let t = (distance / BOUNDARY_WIDTH).clamp(0.0, 1.0);
+    let interior = options.palette.interior();
+    std::array::from_fn(|k| {
+        (interior[k] as f64 + (color[k] as f64 - interior[k] as f64) * t).round() as u8
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fractal;
+
+    fn trace(options: &RenderOptions, c: Complex<f64>) -> Escape {
+        let mut tracer = Tracer::new(options, c, 1.0);
+        let escaped = fractal::trace(FractalType::Mandelbrot, c, c, 1000, 4.0, |z| {
+            tracer.visit(z)
+        });
+        tracer.finish(escaped)
+    }
+
+    #[test]
+    fn test_period_detection() {
+        let options = RenderOptions {
+            interior: Interior::Period,
+            ..RenderOptions::default()
+        };
+        // Centers of the main cardioid and the period 2, 3 and 4 bulbs
+        assert_eq!(trace(&options, Complex::new(0.0, 0.0)).period, 1);
+        assert_eq!(trace(&options, Complex::new(-1.0, 0.0)).period, 2);
+        assert_eq!(trace(&options, Complex::new(-0.1226, 0.7449)).period, 3);
+        assert_eq!(trace(&options, Complex::new(-1.3107, 0.0)).period, 4);
+        assert_eq!(
+            trace(&RenderOptions::default(), Complex::new(-1.0, 0.0)).period,
+            0
+        );
+    }
+
+    #[test]
+    fn test_trap_distance() {
+        let options = RenderOptions {
+            trap: Trap::Circle,
+            trap_radius: 1.0,
+            ..RenderOptions::default()
+        };
+        // The orbit of -1 alternates between -1 and 0, on and inside the circle
+        assert_eq!(trace(&options, Complex::new(-1.0, 0.0)).trap, 0.0);
+        let options = RenderOptions {
+            trap: Trap::Point,
+            trap_re: 0.5,
+            ..options
+        };
+        assert_eq!(trace(&options, Complex::new(-1.0, 0.0)).trap, 0.5);
+        let options = RenderOptions {
+            trap: Trap::Line,
+            trap_im: 0.25,
+            ..options
+        };
+        assert_eq!(trace(&options, Complex::new(-1.0, 0.0)).trap, 0.25);
+        assert_eq!(
+            trace(&RenderOptions::default(), Complex::new(-1.0, 0.0)).trap,
+            f64::INFINITY
+        );
+    }
+}
@@ -0,0 +1,108 @@
+//! Mandelbrot iteration four pixels at a time in wasm simd128 f64x2 lanes
+//!
+//! Built only with the `simd` feature for a target with `simd128`; other
+//! builds run the same groups through the scalar loop. The lanes do exactly
+//! the scalar operations, so both give bit-identical images.
+
+use num_complex::Complex;
+
+use crate::engine::Escape;
+use crate::fractal::{self, FractalType};
+
+/// Whether `escape_row` uses SIMD in this build
+pub const ENABLED: bool = cfg!(all(
+    feature = "simd",
+    target_arch = "wasm32",
+    target_feature = "simd128"
+));
+
+/// Pixels iterated together: two vectors of two, so one vector's multiplies
+/// overlap the other's instead of waiting on the previous iteration
+const GROUP: usize = 4;
+
+/// Mandelbrot escape time and last `z` of each pixel of a row, where pixel
+/// `x` is at `point(x)`
+pub fn escape_row(row: &mut [Escape], point: impl Fn(u32) -> Complex<f64>, max: u32, bailout: f64) {
+    let (groups, rest) = row.as_chunks_mut::<GROUP>();
+    let mut x = 0;
+    for group in groups {
+        *group =
+            escape_group([0, 1, 2, 3].map(|lane| point(x + lane)), max, bailout).map(Escape::from);
+        x += GROUP as u32;
+    }
+    for last in rest {
+        *last = fractal::escape(FractalType::Mandelbrot, point(x), point(x), max, bailout).into();
+        x += 1;
+    }
+}
+
+#[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+fn escape_group(c: [Complex<f64>; GROUP], max: u32, bailout: f64) -> [(u32, Complex<f64>); GROUP] {
+    use core::arch::wasm32::*;
+
+    let c_re = [f64x2(c[0].re, c[1].re), f64x2(c[2].re, c[3].re)];
+    let c_im = [f64x2(c[0].im, c[1].im), f64x2(c[2].im, c[3].im)];
+    let bailout = f64x2_splat(bailout);
+    let mut re = [f64x2_splat(0.0); 2];
+    let mut im = [f64x2_splat(0.0); 2];
+    let mut count = [i64x2_splat(0); 2];
+    for _ in 0..max {
+        let mut any_inside = i64x2_splat(0);
+        for k in 0..2 {
+            let (re2, im2) = (f64x2_mul(re[k], re[k]), f64x2_mul(im[k], im[k]));
+            // All ones in lanes still inside the radius
+            let inside = f64x2_le(f64x2_add(re2, im2), bailout);
+            // z² + c rounding exactly like `Complex` multiplication, whose
+            // re·im + im·re is the same product twice
+            let re_im = f64x2_mul(re[k], im[k]);
+            let next_re = f64x2_add(f64x2_sub(re2, im2), c_re[k]);
+            let next_im = f64x2_add(f64x2_add(re_im, re_im), c_im[k]);
+            // Escaped lanes keep their last z and count
+            re[k] = v128_bitselect(next_re, re[k], inside);
+            im[k] = v128_bitselect(next_im, im[k], inside);
+            count[k] = i64x2_sub(count[k], inside);
+            any_inside = v128_or(any_inside, inside);
+        }
+        if !v128_any_true(any_inside) {
+            break;
+        }
+    }
+
+    let low = |k: usize| {
+        let z = Complex::new(
+            f64x2_extract_lane::<0>(re[k]),
+            f64x2_extract_lane::<0>(im[k]),
+        );
+        (i64x2_extract_lane::<0>(count[k]) as u32, z)
+    };
+    let high = |k: usize| {
+        let z = Complex::new(
+            f64x2_extract_lane::<1>(re[k]),
+            f64x2_extract_lane::<1>(im[k]),
+        );
+        (i64x2_extract_lane::<1>(count[k]) as u32, z)
+    };
+    [low(0), high(0), low(1), high(1)]
+}
+
+#[cfg(not(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128")))]
+fn escape_group(c: [Complex<f64>; GROUP], max: u32, bailout: f64) -> [(u32, Complex<f64>); GROUP] {
+    c.map(|c| fractal::escape(FractalType::Mandelbrot, c, c, max, bailout))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_row_matches_scalar() {
+        // A width that leaves pixels outside the groups
+        let point = |x: u32| Complex::new(-2.0 + x as f64 * 0.11, 0.3);
+        let mut row = vec![Escape::default(); 27];
+        escape_row(&mut row, point, 200, 4.0);
+        for (x, escaped) in (0..).zip(&row) {
+            let scalar = fractal::escape(FractalType::Mandelbrot, point(x), point(x), 200, 4.0);
+            assert_eq!(*escaped, scalar.into());
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use wasm_bindgen::prelude::*;
+
+/// Escape times of the pixels rendered into it, built in JS with
+/// `new RenderStats()` and added to by every render it is passed to
+#[wasm_bindgen]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RenderStats {
+    /// Pixels escaping after each number of iterations
+    histogram: Vec<u32>,
+    /// Pixels that reached `max_iter` without escaping
+    interior: u32,
+    /// Iterations of all escaped pixels
+    total: u64,
+}
+
+#[wasm_bindgen]
+impl RenderStats {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Escaped pixels per iteration count, up to the slowest to escape
+    #[wasm_bindgen(getter)]
+    pub fn histogram(&self) -> Vec<u32> {
+        self.histogram.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn escaped(&self) -> u32 {
+        self.histogram.iter().sum()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn interior(&self) -> u32 {
+        self.interior
+    }
+
+    /// Fewest iterations to escape, 0 if nothing escaped
+    #[wasm_bindgen(getter)]
+    pub fn min(&self) -> u32 {
+        self.histogram.iter().position(|&n| n > 0).unwrap_or(0) as u32
+    }
+
+    /// Most iterations to escape, 0 if nothing escaped
+    #[wasm_bindgen(getter)]
+    pub fn max(&self) -> u32 {
+        self.histogram.iter().rposition(|&n| n > 0).unwrap_or(0) as u32
+    }
+
+    /// Mean iterations to escape, NaN if nothing escaped
+    #[wasm_bindgen(getter)]
+    pub fn mean(&self) -> f64 {
+        self.total as f64 / self.escaped() as f64
+    }
+}
+
+impl RenderStats {
+    /// Count a pixel that stopped after `i` of `max_iter` iterations
+    pub fn add(&mut self, i: u32, max_iter: u32) {
+        if i >= max_iter {
+            self.interior += 1;
+            return;
+        }
+        let i = i as usize;
+        if self.histogram.len() <= i {
+            self.histogram.resize(i + 1, 0);
+        }
+        self.histogram[i] += 1;
+        self.total += i as u64;
+    }
+
+    pub fn merge(&mut self, other: &RenderStats) {
+        if self.histogram.len() < other.histogram.len() {
+            self.histogram.resize(other.histogram.len(), 0);
+        }
+        for (count, added) in self.histogram.iter_mut().zip(&other.histogram) {
+            *count += added;
+        }
+        self.interior += other.interior;
+        self.total += other.total;
+    }
+
+    /// Share of escaped pixels that escaped in fewer than `k` iterations, for
+    /// `k` from 0 to one past the slowest
+    pub fn cumulative(&self) -> Vec<f64> {
+        let escaped = self.escaped().max(1) as f64;
+        let mut below = 0;
+        let mut shares = vec![0.0];
+        for &count in &self.histogram {
+            below += count;
+            shares.push(below as f64 / escaped);
+        }
+        shares
+    }
+}
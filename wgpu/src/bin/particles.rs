@@ -0,0 +1,713 @@
+//! Compute shader particle system
+//!
+//! A million particles orbit a central attractor. Every frame a compute pass
+//! reads the particles from one storage buffer and writes the next state into
+//! the other (ping-pong), then the freshly written buffer is drawn as points
+//! with additive blending. `--workgroup-size` sets the compute workgroup size
+//! through a pipeline-overridable constant, so its effect on the frame rate
+//! (shown in the title bar) can be compared without editing the shader.
+
+use bytemuck::{Pod, Zeroable};
+use clap::Parser;
+use glam::{Mat4, Vec3};
+use rotating_cube::camera::Camera;
+use rotating_cube::renderer::{self, GpuOptions};
+use std::f32::consts::TAU;
+use std::sync::Arc;
+use std::time::Instant;
+use wgpu::util::DeviceExt;
+use winit::{
+    application::ApplicationHandler,
+    dpi::PhysicalSize,
+    event::WindowEvent,
+    event_loop::{ActiveEventLoop, ControlFlow, EventLoop},
+    window::{Window, WindowId},
+};
+
+const TITLE: &str = "Particles - wgpu + Rust";
+
+/// Longest simulation step, so a stalled frame does not fling particles out
+const MAX_DT: f32 = 1.0 / 30.0;
+
+#[derive(Parser, Debug)]
+#[command(version, about = "wgpu compute shader particle demo")]
+struct Options {
+    /// Number of particles
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1_000_000,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    count: u32,
+
+    /// Invocations per compute workgroup (at most the adapter's limit, usually 256)
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 64,
+        value_parser = clap::value_parser!(u32).range(1..=1024)
+    )]
+    workgroup_size: u32,
+
+    #[command(flatten)]
+    gpu: GpuOptions,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct Particle {
+    /// xyz, w unused
+    position: [f32; 4],
+    /// xyz, w unused
+    velocity: [f32; 4],
+}
+
+impl Particle {
+    const ATTRIBS: [wgpu::VertexAttribute; 2] =
+        wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x4];
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Self>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct SimParams {
+    dt: f32,
+    count: u32,
+    _padding: [u32; 2],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct RenderUniforms {
+    view_proj: [[f32; 4]; 4],
+}
+
+/// xorshift32, enough to scatter the initial particles reproducibly
+struct Rng(u32);
+
+impl Rng {
+    /// Uniform in [0, 1)
+    fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        (self.0 >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
+/// A thin disk of particles on circular orbits around the origin
+fn generate_particles(count: u32) -> Vec<Particle> {
+    let mut rng = Rng(0x9E37_79B9);
+    (0..count)
+        .map(|_| {
+            let radius = 0.5 + 3.5 * rng.next().sqrt();
+            let angle = rng.next() * TAU;
+            let height = (rng.next() - 0.5) * 0.2;
+            let position = Vec3::new(radius * angle.cos(), height, radius * angle.sin());
+
+            // Circular orbit speed for GRAVITY = 1, with a little scatter
+            let speed = (1.0 / radius).sqrt() * (0.9 + 0.2 * rng.next());
+            let tangent = Vec3::new(-angle.sin(), 0.0, angle.cos());
+            Particle {
+                position: position.extend(1.0).to_array(),
+                velocity: (tangent * speed).extend(0.0).to_array(),
+            }
+        })
+        .collect()
+}
+
+/// Workgroups to dispatch as (x, y)
+///
+/// A single dimension holds at most `max_per_dimension` workgroups; beyond
+/// that the rows wrap into y and the shader flattens the index back.
+fn dispatch_size(count: u32, workgroup_size: u32, max_per_dimension: u32) -> (u32, u32) {
+    let groups = count.div_ceil(workgroup_size);
+    if groups <= max_per_dimension {
+        (groups, 1)
+    } else {
+        (max_per_dimension, groups.div_ceil(max_per_dimension))
+    }
+}
+
+struct State {
+    surface: wgpu::Surface<'static>,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    config: wgpu::SurfaceConfiguration,
+    compute_pipeline: wgpu::ComputePipeline,
+    render_pipeline: wgpu::RenderPipeline,
+    params_buffer: wgpu::Buffer,
+    render_buffer: wgpu::Buffer,
+    render_bind_group: wgpu::BindGroup,
+    particle_buffers: [wgpu::Buffer; 2],
+    /// `compute_bind_groups[i]` reads buffer i and writes buffer 1 - i
+    compute_bind_groups: [wgpu::BindGroup; 2],
+    /// Buffer holding the current state
+    current: usize,
+    count: u32,
+    dispatch: (u32, u32),
+    camera: Camera,
+    last_frame: Instant,
+    frames: u32,
+    fps_since: Instant,
+    window: Arc<Window>,
+}
+
+impl State {
+    async fn new(window: Arc<Window>, options: &Options) -> Result<Self, String> {
+        let size = window.inner_size();
+
+        let backends = options.gpu.backends();
+        let instance = renderer::instance(backends);
+        let surface = instance.create_surface(window.clone()).unwrap();
+        let adapter = renderer::request_adapter(
+            &instance,
+            backends,
+            Some(&surface),
+            options.gpu.adapter.as_ref(),
+        )
+        .await?;
+        let (device, queue, _) = renderer::request_device(&adapter).await;
+
+        let limits = device.limits();
+        let max_workgroup_size = limits
+            .max_compute_workgroup_size_x
+            .min(limits.max_compute_invocations_per_workgroup);
+        if options.workgroup_size > max_workgroup_size {
+            return Err(format!(
+                "workgroup size {} exceeds the adapter's limit of {}",
+                options.workgroup_size, max_workgroup_size
+            ));
+        }
+        let particle_size = std::mem::size_of::<Particle>() as u64;
+        let max_count = u64::from(limits.max_storage_buffer_binding_size)
+            .min(limits.max_buffer_size)
+            / particle_size;
+        if u64::from(options.count) > max_count {
+            return Err(format!(
+                "{} particles exceed the adapter's limit of {} per buffer",
+                options.count, max_count
+            ));
+        }
+        let dispatch = dispatch_size(
+            options.count,
+            options.workgroup_size,
+            limits.max_compute_workgroups_per_dimension,
+        );
+        log::info!(
+            "{} particles, workgroup size {}, dispatching {:?} workgroups",
+            options.count,
+            options.workgroup_size,
+            dispatch
+        );
+
+        let surface_caps = surface.get_capabilities(&adapter);
+        let surface_format = surface_caps
+            .formats
+            .iter()
+            .copied()
+            .find(|f| f.is_srgb())
+            .unwrap_or(surface_caps.formats[0]);
+
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: surface_format,
+            width: size.width.max(1),
+            height: size.height.max(1),
+            present_mode: renderer::choose_present_mode(
+                options.gpu.present_mode.into(),
+                &surface_caps.present_modes,
+            ),
+            alpha_mode: surface_caps.alpha_modes[0],
+            view_formats: vec![],
+            desired_maximum_frame_latency: 2,
+        };
+        surface.configure(&device, &config);
+
+        let particles = generate_particles(options.count);
+        let particle_buffers = [0, 1].map(|i| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("Particle Buffer {i}")),
+                contents: bytemuck::cast_slice(&particles),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
+            })
+        });
+
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Simulation Params Buffer"),
+            contents: bytemuck::bytes_of(&SimParams {
+                dt: 0.0,
+                count: options.count,
+                _padding: [0; 2],
+            }),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let compute_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Compute Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_entry(1, true),
+                storage_entry(2, false),
+            ],
+        });
+        let compute_bind_groups = [0, 1].map(|i| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(&format!("Compute Bind Group {i}")),
+                layout: &compute_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: params_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: particle_buffers[i].as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: particle_buffers[1 - i].as_entire_binding(),
+                    },
+                ],
+            })
+        });
+
+        let compute_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Particle Compute Shader"),
+            source: wgpu::ShaderSource::Wgsl(COMPUTE_SHADER.into()),
+        });
+        let compute_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Compute Pipeline Layout"),
+                bind_group_layouts: &[&compute_layout],
+                push_constant_ranges: &[],
+            });
+        let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Particle Compute Pipeline"),
+            layout: Some(&compute_pipeline_layout),
+            module: &compute_shader,
+            entry_point: Some("cs_main"),
+            compilation_options: wgpu::PipelineCompilationOptions {
+                constants: &[("WORKGROUP_SIZE", options.workgroup_size as f64)],
+                ..Default::default()
+            },
+            cache: None,
+        });
+
+        let render_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Render Uniform Buffer"),
+            contents: bytemuck::bytes_of(&RenderUniforms {
+                view_proj: Mat4::IDENTITY.to_cols_array_2d(),
+            }),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let render_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Render Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let render_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Render Bind Group"),
+            layout: &render_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: render_buffer.as_entire_binding(),
+            }],
+        });
+
+        let render_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Particle Render Shader"),
+            source: wgpu::ShaderSource::Wgsl(RENDER_SHADER.into()),
+        });
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Render Pipeline Layout"),
+                bind_group_layouts: &[&render_layout],
+                push_constant_ranges: &[],
+            });
+        let additive = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::SrcAlpha,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Particle Render Pipeline"),
+            layout: Some(&render_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &render_shader,
+                entry_point: Some("vs_main"),
+                buffers: &[Particle::desc()],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &render_shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState {
+                        color: additive,
+                        alpha: additive,
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::PointList,
+                ..Default::default()
+            },
+            // Additive blending does not care about draw order
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        Ok(Self {
+            surface,
+            device,
+            queue,
+            config,
+            compute_pipeline,
+            render_pipeline,
+            params_buffer,
+            render_buffer,
+            render_bind_group,
+            particle_buffers,
+            compute_bind_groups,
+            current: 0,
+            count: options.count,
+            dispatch,
+            camera: Camera::new(8.0),
+            last_frame: Instant::now(),
+            frames: 0,
+            fps_since: Instant::now(),
+            window,
+        })
+    }
+
+    fn resize(&mut self, new_size: PhysicalSize<u32>) {
+        if new_size.width == 0 || new_size.height == 0 {
+            return;
+        }
+        self.config.width = new_size.width;
+        self.config.height = new_size.height;
+        self.surface.configure(&self.device, &self.config);
+    }
+
+    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        let now = Instant::now();
+        let dt = now.duration_since(self.last_frame).as_secs_f32();
+        self.last_frame = now;
+        self.camera.update(dt);
+        self.update_fps(now);
+
+        self.queue.write_buffer(
+            &self.params_buffer,
+            0,
+            bytemuck::bytes_of(&SimParams {
+                dt: dt.min(MAX_DT),
+                count: self.count,
+                _padding: [0; 2],
+            }),
+        );
+        let aspect = self.config.width as f32 / self.config.height as f32;
+        let proj = Mat4::perspective_rh(45.0_f32.to_radians(), aspect, 0.1, 100.0);
+        self.queue.write_buffer(
+            &self.render_buffer,
+            0,
+            bytemuck::bytes_of(&RenderUniforms {
+                view_proj: (proj * self.camera.view()).to_cols_array_2d(),
+            }),
+        );
+
+        let output = self.surface.get_current_texture()?;
+        let view = output
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Particle Encoder"),
+            });
+
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Particle Compute Pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&self.compute_pipeline);
+            compute_pass.set_bind_group(0, &self.compute_bind_groups[self.current], &[]);
+            compute_pass.dispatch_workgroups(self.dispatch.0, self.dispatch.1, 1);
+        }
+        self.current = 1 - self.current;
+
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Particle Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            render_pass.set_pipeline(&self.render_pipeline);
+            render_pass.set_bind_group(0, &self.render_bind_group, &[]);
+            render_pass.set_vertex_buffer(0, self.particle_buffers[self.current].slice(..));
+            render_pass.draw(0..self.count, 0..1);
+        }
+
+        self.queue.submit(std::iter::once(encoder.finish()));
+        output.present();
+
+        Ok(())
+    }
+
+    /// Show the frame rate in the title once a second
+    fn update_fps(&mut self, now: Instant) {
+        self.frames += 1;
+        let elapsed = now.duration_since(self.fps_since).as_secs_f32();
+        if elapsed >= 1.0 {
+            let fps = self.frames as f32 / elapsed;
+            self.window.set_title(&format!("{TITLE} ({fps:.0} FPS)"));
+            log::info!("{:.1} FPS", fps);
+            self.frames = 0;
+            self.fps_since = now;
+        }
+    }
+}
+
+struct App {
+    options: Options,
+    state: Option<State>,
+}
+
+impl ApplicationHandler for App {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        let window_attributes = Window::default_attributes()
+            .with_title(TITLE)
+            .with_inner_size(
+                self.options
+                    .gpu
+                    .size
+                    .unwrap_or(PhysicalSize::new(1024, 768)),
+            );
+
+        let window = Arc::new(event_loop.create_window(window_attributes).unwrap());
+        match pollster::block_on(State::new(window, &self.options)) {
+            Ok(state) => self.state = Some(state),
+            Err(e) => {
+                log::error!("{}", e);
+                event_loop.exit();
+            }
+        }
+    }
+
+    fn window_event(
+        &mut self,
+        event_loop: &ActiveEventLoop,
+        _window_id: WindowId,
+        event: WindowEvent,
+    ) {
+        let Some(state) = self.state.as_mut() else {
+            return;
+        };
+
+        if state.camera.handle_event(&event) {
+            return;
+        }
+
+        match event {
+            WindowEvent::CloseRequested => event_loop.exit(),
+            WindowEvent::Resized(physical_size) => state.resize(physical_size),
+            WindowEvent::RedrawRequested => {
+                match state.render() {
+                    Ok(_) => {}
+                    Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                        let size = state.window.inner_size();
+                        state.resize(size);
+                    }
+                    Err(wgpu::SurfaceError::OutOfMemory) => {
+                        log::error!("Out of memory!");
+                        event_loop.exit();
+                    }
+                    Err(e) => log::error!("Render error: {:?}", e),
+                }
+                state.window.request_redraw();
+            }
+            _ => {}
+        }
+    }
+}
+
+// One invocation per particle: gravity towards the origin, semi-implicit Euler
+const COMPUTE_SHADER: &str = r#"
+struct Particle {
+    position: vec4<f32>,
+    velocity: vec4<f32>,
+};
+
+struct Params {
+    dt: f32,
+    count: u32,
+};
+
+override WORKGROUP_SIZE: u32 = 64u;
+
+const GRAVITY: f32 = 1.0;
+// Keeps particles passing near the center from being flung away
+const SOFTENING: f32 = 0.05;
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> particles_in: array<Particle>;
+@group(0) @binding(2) var<storage, read_write> particles_out: array<Particle>;
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn cs_main(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(num_workgroups) groups: vec3<u32>,
+) {
+    // Dispatches too large for one dimension wrap rows into y
+    let index = id.x + id.y * groups.x * WORKGROUP_SIZE;
+    if (index >= params.count) {
+        return;
+    }
+
+    var particle = particles_in[index];
+    let position = particle.position.xyz;
+    let dist2 = dot(position, position) + SOFTENING;
+    let accel = -position * (GRAVITY / (dist2 * sqrt(dist2)));
+
+    let velocity = particle.velocity.xyz + accel * params.dt;
+    particle.velocity = vec4<f32>(velocity, 0.0);
+    particle.position = vec4<f32>(position + velocity * params.dt, 1.0);
+    particles_out[index] = particle;
+}
+"#;
+
+// Points colored by speed: slow particles blue, fast ones orange
+const RENDER_SHADER: &str = r#"
+struct Uniforms {
+    view_proj: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> uniforms: Uniforms;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+};
+
+@vertex
+fn vs_main(@location(0) position: vec4<f32>, @location(1) velocity: vec4<f32>) -> VertexOutput {
+    let speed = clamp(length(velocity.xyz) * 0.6, 0.0, 1.0);
+    var out: VertexOutput;
+    out.clip_position = uniforms.view_proj * vec4<f32>(position.xyz, 1.0);
+    out.color = vec4<f32>(mix(vec3<f32>(0.2, 0.4, 1.0), vec3<f32>(1.0, 0.6, 0.2), speed), 0.35);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
+"#;
+
+fn main() {
+    env_logger::init();
+    log::info!("Starting Particles application");
+
+    let options = Options::parse();
+    if options.gpu.list_adapters {
+        renderer::list_adapters(options.gpu.backends());
+        return;
+    }
+
+    let event_loop = EventLoop::new().unwrap();
+    event_loop.set_control_flow(ControlFlow::Poll);
+
+    let mut app = App {
+        options,
+        state: None,
+    };
+    event_loop.run_app(&mut app).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dispatch_size() {
+        assert_eq!(dispatch_size(1_000_000, 64, 65_535), (15_625, 1));
+        assert_eq!(dispatch_size(1_000_001, 64, 65_535), (15_626, 1));
+        // 1M single-invocation groups do not fit in x
+        assert_eq!(dispatch_size(1_000_000, 1, 65_535), (65_535, 16));
+    }
+
+    #[test]
+    fn test_options() {
+        let options = Options::parse_from(["particles"]);
+        assert_eq!((options.count, options.workgroup_size), (1_000_000, 64));
+        let options = Options::parse_from(["particles", "--workgroup-size", "256"]);
+        assert_eq!(options.workgroup_size, 256);
+        assert!(Options::try_parse_from(["particles", "--workgroup-size", "0"]).is_err());
+        assert!(Options::try_parse_from(["particles", "--count", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_particles_start_on_circular_orbits() {
+        for particle in generate_particles(1000) {
+            let position = Vec3::from_slice(&particle.position);
+            let velocity = Vec3::from_slice(&particle.velocity);
+            let radius = position.length();
+            assert!((0.5..4.1).contains(&radius));
+            // Tangential: no radial component in the disk plane
+            assert!(position.with_y(0.0).dot(velocity).abs() < 1e-4);
+        }
+    }
+}
@@ -0,0 +1,229 @@
+//! Scene graph demo
+//!
+//! The cube, the golden ring and the emerald dodecahedron in one window, each
+//! with its own material and animation. The ring spins in the middle; a pivot
+//! node carries the cube and the dodecahedron around it, and the dodecahedron
+//! carries a small icosphere moon of its own. The meshes all come from
+//! `geometry`. Everything is drawn by one `SceneRenderer`, with the per-object
+//! uniforms in a single buffer selected by dynamic offsets.
+//!
+//! `--asteroids N` surrounds them with a slowly turning belt of N rocks, to
+//! see frustum culling and the depth prepass (`--depth-prepass`) at work with
+//! tens of thousands of objects; the overlay shows how many were drawn and
+//! culled. The window, `--msaa`, `--headless` and the keys are those of every
+//! other demo (see `renderer`).
+
+use bytemuck::{Pod, Zeroable};
+use clap::Parser;
+use glam::{Mat4, Quat, Vec3};
+use rotating_cube::camera::Camera;
+use rotating_cube::geometry;
+use rotating_cube::overlay;
+use rotating_cube::renderer::{self, Frame, StateBuilder};
+use rotating_cube::scene::{Material, Node, Scene};
+use std::f32::consts::TAU;
+
+#[derive(Parser, Debug)]
+#[command(version, about = "wgpu scene graph demo")]
+struct SceneOptions {
+    /// Rocks in the asteroid belt around the scene
+    #[arg(long, default_value_t = 0)]
+    asteroids: usize,
+
+    /// Fill the depth buffer in a pass of its own before shading
+    #[arg(long)]
+    depth_prepass: bool,
+
+    #[command(flatten)]
+    demo: renderer::Options,
+}
+
+/// The light, adjustable in the overlay; no shader reads it, it only reaches
+/// the scene's uniforms through `light_dir`
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct Light {
+    dir: [f32; 4],
+}
+
+impl renderer::Uniforms for Light {
+    fn update(&mut self, _frame: &Frame) {}
+
+    fn light_dir(&self) -> Vec3 {
+        Vec3::from_slice(&self.dir)
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui) {
+        overlay::position(ui, "light direction", &mut self.dir);
+    }
+}
+
+/// xorshift32, enough to scatter rocks
+struct Rng(u32);
+
+impl Rng {
+    /// Uniform in [0, 1)
+    fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        (self.0 >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
+/// The ring in the middle, orbited by the cube and by the dodecahedron with
+/// its moon, inside a belt of `asteroids` rocks
+fn build_scene(asteroids: usize) -> Scene {
+    let mut scene = Scene::new();
+
+    let cube = scene.add_mesh(geometry::cube());
+    let torus = scene.add_mesh(geometry::torus(0.7, 0.25, 64, 32));
+    let dodecahedron = scene.add_mesh(geometry::dodecahedron());
+    let sphere = scene.add_mesh(geometry::icosphere(0.5, 3));
+    let rock = scene.add_mesh(geometry::icosphere(0.5, 1));
+
+    let gold = scene.add_material(Material {
+        base_color: [0.83, 0.55, 0.1],
+        metallic: 1.0,
+        roughness: 0.25,
+    });
+    let emerald = scene.add_material(Material {
+        base_color: [0.1, 0.75, 0.2],
+        metallic: 0.0,
+        roughness: 0.15,
+    });
+    let ruby = scene.add_material(Material {
+        base_color: [0.8, 0.1, 0.15],
+        metallic: 0.0,
+        roughness: 0.4,
+    });
+    let silver = scene.add_material(Material {
+        base_color: [0.8, 0.8, 0.85],
+        metallic: 1.0,
+        roughness: 0.35,
+    });
+    let stone = scene.add_material(Material {
+        base_color: [0.45, 0.4, 0.35],
+        metallic: 0.0,
+        roughness: 0.9,
+    });
+
+    scene.add(
+        Node::new("ring")
+            .transform(Mat4::from_rotation_x(0.4))
+            .animation(|t| Mat4::from_rotation_y(t) * Mat4::from_rotation_z(t * 0.3))
+            .mesh(torus, gold),
+    );
+    scene.add(
+        Node::new("orbit")
+            .animation(|t| Mat4::from_rotation_y(t * 0.4))
+            .child(
+                Node::new("cube")
+                    .transform(Mat4::from_translation(Vec3::new(2.5, 0.0, 0.0)))
+                    .animation(|t| Mat4::from_rotation_y(t) * Mat4::from_rotation_x(t * 0.7))
+                    .mesh(cube, ruby),
+            )
+            .child(
+                Node::new("dodecahedron")
+                    .transform(Mat4::from_translation(Vec3::new(-2.5, 0.0, 0.0)))
+                    .animation(|t| Mat4::from_rotation_y(t * 0.8))
+                    .mesh(dodecahedron, emerald)
+                    .child(
+                        Node::new("moon")
+                            .animation(|t| {
+                                Mat4::from_rotation_y(t * 2.0)
+                                    * Mat4::from_translation(Vec3::new(0.0, 0.0, 1.0))
+                                    * Mat4::from_scale(Vec3::splat(0.25))
+                            })
+                            .mesh(sphere, silver),
+                    ),
+            ),
+    );
+
+    if asteroids > 0 {
+        let mut rng = Rng(0x9E37_79B9);
+        let mut belt = Node::new("belt").animation(|t| Mat4::from_rotation_y(t * 0.05));
+        for i in 0..asteroids {
+            let radius = 4.0 + 10.0 * rng.next();
+            let angle = rng.next() * TAU;
+            let height = (rng.next() - 0.5) * 1.5;
+            let position = Vec3::new(radius * angle.cos(), height, radius * angle.sin());
+            let axis = Vec3::new(rng.next() - 0.5, rng.next() - 0.5, rng.next() - 0.5);
+            let scale = 0.05 + 0.15 * rng.next();
+            belt = belt.child(
+                Node::new(format!("asteroid {i}"))
+                    .transform(Mat4::from_scale_rotation_translation(
+                        Vec3::splat(scale),
+                        Quat::from_axis_angle(axis.normalize_or(Vec3::Y), rng.next() * TAU),
+                        position,
+                    ))
+                    .mesh(rock, stone),
+            );
+        }
+        scene.add(belt);
+    }
+
+    scene
+}
+
+fn main() {
+    env_logger::init();
+    log::info!("Starting Scene application");
+
+    let options = SceneOptions::parse();
+    // Directional light from the upper right
+    let light = Light {
+        dir: [2.0, 2.5, 1.5, 0.0],
+    };
+    let builder = StateBuilder::scene(build_scene(options.asteroids), light)
+        .title("Scene - wgpu + Rust")
+        .size(1024, 768)
+        .camera(Camera::new(7.0))
+        .clear_color(wgpu::Color {
+            r: 0.1,
+            g: 0.1,
+            b: 0.15,
+            a: 1.0,
+        })
+        .depth_prepass(options.depth_prepass);
+    renderer::run_with(builder, options.demo);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_options() {
+        let options = SceneOptions::parse_from(["scene", "--asteroids", "500", "--msaa", "8"]);
+        assert_eq!(options.asteroids, 500);
+        assert!(!options.depth_prepass);
+        assert_eq!(options.demo.msaa, 8);
+        assert!(SceneOptions::try_parse_from(["scene", "--frames", "2"]).is_err());
+    }
+
+    #[test]
+    fn test_scene_layout() {
+        let scene = build_scene(0);
+        let draws = scene.draws(0.0);
+        // Ring, cube, dodecahedron, moon
+        assert_eq!(draws.len(), 4);
+        // The moon follows the dodecahedron
+        let dodecahedron = draws[2].model.w_axis.truncate();
+        let moon = draws[3].model.w_axis.truncate();
+        assert!((moon.distance(dodecahedron) - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_asteroid_belt() {
+        let scene = build_scene(1000);
+        let draws = scene.draws(0.0);
+        assert_eq!(draws.len(), 1004);
+        for draw in &draws[4..] {
+            let position = draw.model.w_axis.truncate();
+            let radius = position.x.hypot(position.z);
+            assert!((4.0..=14.0).contains(&radius));
+            assert!(position.y.abs() <= 0.75);
+        }
+    }
+}
@@ -0,0 +1,278 @@
+//! Orbit camera driven by mouse and keyboard
+//!
+//! - Left mouse drag orbits around the target
+//! - Scroll wheel zooms towards / away from the target
+//! - WASD pans the target in the view plane
+//!
+//! Input adds velocity rather than moving the camera directly, and velocity
+//! decays exponentially, so motion eases out after the input stops. The decay
+//! is scaled so the total movement is independent of `damping`: a drag of N
+//! pixels always orbits `N * orbit_sensitivity` radians in the end.
+
+use glam::{Mat4, Vec2, Vec3};
+use std::f32::consts::FRAC_PI_2;
+use winit::event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent};
+use winit::keyboard::{KeyCode, PhysicalKey};
+
+/// Keeps the camera from flipping over the poles
+const MAX_PITCH: f32 = FRAC_PI_2 - 0.01;
+
+/// Pixels of touchpad scrolling that count as one wheel line
+const PIXELS_PER_LINE: f32 = 50.0;
+
+/// Below this speed motion stops instead of decaying forever
+const REST_SPEED: f32 = 1e-4;
+
+/// How input maps to camera motion
+#[derive(Debug, Clone, Copy)]
+pub struct CameraConfig {
+    /// Radians of orbit per pixel dragged
+    pub orbit_sensitivity: f32,
+    /// Fraction of the distance zoomed per scroll line
+    pub zoom_sensitivity: f32,
+    /// Pan speed in distances per second while a WASD key is held
+    pub pan_speed: f32,
+    /// Velocity decay rate per second; higher settles faster (less inertia)
+    pub damping: f32,
+    pub min_distance: f32,
+    pub max_distance: f32,
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        Self {
+            orbit_sensitivity: 0.005,
+            zoom_sensitivity: 0.1,
+            pan_speed: 0.5,
+            damping: 8.0,
+            min_distance: 0.5,
+            max_distance: 50.0,
+        }
+    }
+}
+
+/// Camera orbiting `target` at `distance`, looking at it
+#[derive(Debug, Clone)]
+pub struct Camera {
+    config: CameraConfig,
+    target: Vec3,
+    yaw: f32,
+    pitch: f32,
+    distance: f32,
+    /// (yaw, pitch) radians per second
+    orbit_velocity: Vec2,
+    /// ln(distance) per second
+    zoom_velocity: f32,
+    dragging: bool,
+    cursor: Option<Vec2>,
+    /// Held pan keys: x = D - A, y = W - S
+    pan_keys: [bool; 4],
+}
+
+impl Camera {
+    /// Camera on the +Z axis, `distance` away from the origin
+    pub fn new(distance: f32) -> Self {
+        Self {
+            config: CameraConfig::default(),
+            target: Vec3::ZERO,
+            yaw: 0.0,
+            pitch: 0.0,
+            distance,
+            orbit_velocity: Vec2::ZERO,
+            zoom_velocity: 0.0,
+            dragging: false,
+            cursor: None,
+            pan_keys: [false; 4],
+        }
+    }
+
+    pub fn with_config(mut self, config: CameraConfig) -> Self {
+        self.config = config;
+        self.distance = self.clamp_distance(self.distance);
+        self
+    }
+
+    pub fn config(&self) -> &CameraConfig {
+        &self.config
+    }
+
+    /// Feed a window event; returns whether the camera used it
+    pub fn handle_event(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => {
+                self.dragging = *state == ElementState::Pressed;
+                true
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                let position = Vec2::new(position.x as f32, position.y as f32);
+                if let (true, Some(last)) = (self.dragging, self.cursor) {
+                    self.orbit(position - last);
+                }
+                self.cursor = Some(position);
+                self.dragging
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.cursor = None;
+                false
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let lines = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(pos) => pos.y as f32 / PIXELS_PER_LINE,
+                };
+                self.zoom(lines);
+                true
+            }
+            WindowEvent::KeyboardInput { event, .. } => {
+                let PhysicalKey::Code(code) = event.physical_key else {
+                    return false;
+                };
+                let slot = match code {
+                    KeyCode::KeyW => 0,
+                    KeyCode::KeyA => 1,
+                    KeyCode::KeyS => 2,
+                    KeyCode::KeyD => 3,
+                    _ => return false,
+                };
+                self.pan_keys[slot] = event.state == ElementState::Pressed;
+                true
+            }
+            WindowEvent::Focused(false) => {
+                // Releases are not delivered once focus is gone
+                self.dragging = false;
+                self.pan_keys = [false; 4];
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Orbit by a drag of `delta` pixels
+    pub fn orbit(&mut self, delta: Vec2) {
+        self.orbit_velocity += delta * self.config.orbit_sensitivity * self.config.damping;
+    }
+
+    /// Zoom by `lines` scroll lines; positive zooms in
+    pub fn zoom(&mut self, lines: f32) {
+        self.zoom_velocity -= lines * self.config.zoom_sensitivity * self.config.damping;
+    }
+
+    /// Advance the motion by `dt` seconds
+    pub fn update(&mut self, dt: f32) {
+        let decay = (-self.config.damping * dt).exp();
+        // Integral of v * e^(-damping * t) over the step
+        let travel = (1.0 - decay) / self.config.damping;
+
+        self.yaw -= self.orbit_velocity.x * travel;
+        self.pitch = (self.pitch + self.orbit_velocity.y * travel).clamp(-MAX_PITCH, MAX_PITCH);
+        self.distance = self.clamp_distance(self.distance * (self.zoom_velocity * travel).exp());
+
+        self.orbit_velocity *= decay;
+        self.zoom_velocity *= decay;
+        if self.orbit_velocity.length() < REST_SPEED {
+            self.orbit_velocity = Vec2::ZERO;
+        }
+        if self.zoom_velocity.abs() < REST_SPEED {
+            self.zoom_velocity = 0.0;
+        }
+
+        let [w, a, s, d] = self.pan_keys.map(f32::from);
+        let pan = Vec2::new(d - a, w - s);
+        if pan != Vec2::ZERO {
+            let (right, up) = self.basis();
+            let step = self.config.pan_speed * self.distance * dt;
+            self.target += (right * pan.x + up * pan.y) * step;
+        }
+    }
+
+    pub fn eye(&self) -> Vec3 {
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+        self.target + Vec3::new(sin_yaw * cos_pitch, sin_pitch, cos_yaw * cos_pitch) * self.distance
+    }
+
+    pub fn target(&self) -> Vec3 {
+        self.target
+    }
+
+    pub fn view(&self) -> Mat4 {
+        Mat4::look_at_rh(self.eye(), self.target, Vec3::Y)
+    }
+
+    /// Screen-right and screen-up directions in world space
+    fn basis(&self) -> (Vec3, Vec3) {
+        let forward = (self.target - self.eye()).normalize();
+        let right = forward.cross(Vec3::Y).normalize();
+        (right, right.cross(forward))
+    }
+
+    fn clamp_distance(&self, distance: f32) -> f32 {
+        distance.clamp(self.config.min_distance, self.config.max_distance)
+    }
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self::new(4.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settle(camera: &mut Camera) {
+        for _ in 0..600 {
+            camera.update(1.0 / 60.0);
+        }
+    }
+
+    #[test]
+    fn test_default_view_matches_fixed_camera() {
+        let camera = Camera::new(4.0);
+        assert!(camera.eye().abs_diff_eq(Vec3::new(0.0, 0.0, 4.0), 1e-6));
+        let fixed = Mat4::look_at_rh(Vec3::new(0.0, 0.0, 4.0), Vec3::ZERO, Vec3::Y);
+        assert!(camera.view().abs_diff_eq(fixed, 1e-6));
+    }
+
+    #[test]
+    fn test_inertia_travels_the_full_drag() {
+        for damping in [2.0, 8.0, 20.0] {
+            let mut camera = Camera::new(4.0).with_config(CameraConfig {
+                damping,
+                ..Default::default()
+            });
+            camera.orbit(Vec2::new(0.0, 100.0));
+            camera.update(1.0 / 60.0);
+            // Still moving after the first frame...
+            assert!(camera.pitch > 0.0 && camera.pitch < 0.5);
+            settle(&mut camera);
+            // ...and ends up exactly where the drag says
+            assert!((camera.pitch - 0.5).abs() < 1e-3, "damping {damping}");
+            assert_eq!(camera.orbit_velocity, Vec2::ZERO);
+        }
+    }
+
+    #[test]
+    fn test_pitch_and_zoom_are_clamped() {
+        let mut camera = Camera::new(4.0);
+        camera.orbit(Vec2::new(0.0, 10_000.0));
+        camera.zoom(1_000.0);
+        settle(&mut camera);
+        assert_eq!(camera.pitch, MAX_PITCH);
+        assert_eq!(camera.distance, camera.config().min_distance);
+    }
+
+    #[test]
+    fn test_pan_moves_target_in_view_plane() {
+        let mut camera = Camera::new(4.0);
+        camera.pan_keys = [false, false, false, true]; // D
+        camera.update(1.0);
+        // Looking down -Z, screen-right is +X
+        assert!(camera.target().abs_diff_eq(Vec3::new(2.0, 0.0, 0.0), 1e-5));
+    }
+}
@@ -0,0 +1,283 @@
+//! Frame timing for the overlay
+//!
+//! `FrameStats` keeps the last few seconds of frame times and reports their
+//! average and percentiles; a spike shows up in p99 long before it moves the
+//! average. CPU times are measured between frames. When the adapter supports
+//! `TIMESTAMP_QUERY`, a `GpuTimer` also records when the demo's render passes
+//! start and end on the GPU.
+//!
+//! Reading timestamps back must not stall the frame, so the timer has one
+//! readback in flight at a time: a frame whose timestamps arrive while the
+//! previous readback is still mapped is simply not measured.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+/// Frames kept for the statistics, about four seconds at 60 FPS
+const WINDOW: usize = 240;
+
+/// Rolling window of frame times in seconds
+#[derive(Debug, Clone)]
+pub struct FrameTimes {
+    samples: VecDeque<f32>,
+    capacity: usize,
+}
+
+/// Summary of a `FrameTimes` window, in seconds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    pub average: f32,
+    pub p50: f32,
+    pub p95: f32,
+    pub p99: f32,
+}
+
+impl FrameTimes {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            samples: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn push(&mut self, seconds: f32) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(seconds);
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// `None` until the first sample
+    pub fn summary(&self) -> Option<Summary> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<f32> = self.samples.iter().copied().collect();
+        sorted.sort_by(f32::total_cmp);
+        Some(Summary {
+            average: sorted.iter().sum::<f32>() / sorted.len() as f32,
+            p50: percentile(&sorted, 50.0),
+            p95: percentile(&sorted, 95.0),
+            p99: percentile(&sorted, 99.0),
+        })
+    }
+}
+
+/// Nearest-rank percentile of non-empty, ascending `sorted`
+fn percentile(sorted: &[f32], p: f32) -> f32 {
+    let rank = (p / 100.0 * sorted.len() as f32).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// CPU and, when available, GPU frame times
+#[derive(Debug, Clone)]
+pub struct FrameStats {
+    pub cpu: FrameTimes,
+    pub gpu: FrameTimes,
+    /// Whether GPU times can be measured at all
+    gpu_supported: bool,
+}
+
+impl FrameStats {
+    pub fn new(gpu_supported: bool) -> Self {
+        Self {
+            cpu: FrameTimes::new(WINDOW),
+            gpu: FrameTimes::new(WINDOW),
+            gpu_supported,
+        }
+    }
+
+    /// Frame rate from the average CPU frame time
+    pub fn fps(&self) -> Option<f32> {
+        self.cpu
+            .summary()
+            .map(|summary| 1.0 / summary.average.max(f32::EPSILON))
+    }
+
+    /// Labels for the overlay
+    pub fn ui(&self, ui: &mut egui::Ui) {
+        if let Some(fps) = self.fps() {
+            ui.label(format!("{fps:.0} FPS"));
+        }
+        if let Some(summary) = self.cpu.summary() {
+            ui.label(format!("CPU {}", format_summary(&summary)));
+        }
+        match self.gpu.summary() {
+            Some(summary) => ui.label(format!("GPU {}", format_summary(&summary))),
+            None if self.gpu_supported => ui.label("GPU waiting for timestamps"),
+            None => ui.label("GPU timing unsupported"),
+        };
+    }
+}
+
+fn format_summary(summary: &Summary) -> String {
+    format!(
+        "avg {:.2} ms, p95 {:.2} ms, p99 {:.2} ms",
+        summary.average * 1000.0,
+        summary.p95 * 1000.0,
+        summary.p99 * 1000.0
+    )
+}
+
+// Readback buffer states, shared with the map callback
+const IDLE: u8 = 0;
+const RESOLVED: u8 = 1;
+const MAPPING: u8 = 2;
+const MAPPED: u8 = 3;
+
+/// Measures GPU time between two timestamps written by render passes
+///
+/// Write timestamp 0 at the start of the first pass and 1 at the end of the
+/// last (see `timestamp_writes`), call `resolve` before finishing the encoder,
+/// `after_submit` after submitting it and `read` once per frame.
+pub struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    /// Nanoseconds per timestamp tick
+    period: f32,
+    state: Arc<AtomicU8>,
+}
+
+impl GpuTimer {
+    /// Size of the two resolved timestamps
+    const SIZE: wgpu::BufferAddress = 2 * wgpu::QUERY_SIZE as wgpu::BufferAddress;
+
+    /// `None` if the device was created without `TIMESTAMP_QUERY`
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("Frame Timestamps"),
+            ty: wgpu::QueryType::Timestamp,
+            count: 2,
+        });
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Timestamp Resolve Buffer"),
+            size: Self::SIZE,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Timestamp Readback Buffer"),
+            size: Self::SIZE,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            readback_buffer,
+            period: queue.get_timestamp_period(),
+            state: Arc::new(AtomicU8::new(IDLE)),
+        })
+    }
+
+    /// Timestamp writes for a pass that starts and/or ends the measured span
+    pub fn timestamp_writes(&self, start: bool, end: bool) -> wgpu::RenderPassTimestampWrites<'_> {
+        wgpu::RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: start.then_some(0),
+            end_of_pass_write_index: end.then_some(1),
+        }
+    }
+
+    /// Copy this frame's timestamps for reading, unless a readback is pending
+    pub fn resolve(&self, encoder: &mut wgpu::CommandEncoder) {
+        if self.state.load(Ordering::Acquire) != IDLE {
+            return;
+        }
+        encoder.resolve_query_set(&self.query_set, 0..2, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &self.readback_buffer,
+            0,
+            Self::SIZE,
+        );
+        self.state.store(RESOLVED, Ordering::Release);
+    }
+
+    /// Start mapping the timestamps resolved into the submitted encoder
+    pub fn after_submit(&self) {
+        if self.state.load(Ordering::Acquire) != RESOLVED {
+            return;
+        }
+        self.state.store(MAPPING, Ordering::Release);
+        let state = self.state.clone();
+        self.readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let next = if result.is_ok() { MAPPED } else { IDLE };
+                state.store(next, Ordering::Release);
+            });
+    }
+
+    /// GPU seconds of the last measured frame, once its readback arrived
+    pub fn read(&self, device: &wgpu::Device) -> Option<f32> {
+        // Runs the map callback if the copy has finished; never waits
+        let _ = device.poll(wgpu::PollType::Poll);
+        if self.state.load(Ordering::Acquire) != MAPPED {
+            return None;
+        }
+
+        let ticks = {
+            let data = self.readback_buffer.slice(..).get_mapped_range();
+            let timestamps: &[u64] = bytemuck::cast_slice(&data);
+            timestamps[1].saturating_sub(timestamps[0])
+        };
+        self.readback_buffer.unmap();
+        self.state.store(IDLE, Ordering::Release);
+
+        Some(ticks as f32 * self.period / 1e9)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let mut times = FrameTimes::new(100);
+        assert_eq!(times.summary(), None);
+
+        // 1..=100 ms
+        for ms in 1..=100 {
+            times.push(ms as f32 / 1000.0);
+        }
+        let summary = times.summary().unwrap();
+        assert!((summary.average - 0.0505).abs() < 1e-6);
+        assert_eq!(summary.p50, 0.050);
+        assert_eq!(summary.p95, 0.095);
+        assert_eq!(summary.p99, 0.099);
+    }
+
+    #[test]
+    fn test_window_drops_oldest() {
+        let mut times = FrameTimes::new(3);
+        for seconds in [10.0, 1.0, 2.0, 3.0] {
+            times.push(seconds);
+        }
+        assert_eq!(times.len(), 3);
+        assert_eq!(times.summary().unwrap().average, 2.0);
+    }
+
+    #[test]
+    fn test_percentile_of_one_sample() {
+        assert_eq!(percentile(&[0.016], 99.0), 0.016);
+        assert_eq!(percentile(&[0.016], 0.0), 0.016);
+    }
+}
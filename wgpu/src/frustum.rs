@@ -0,0 +1,133 @@
+//! View frustum culling
+//!
+//! A `Frustum` holds the six planes of a view-projection matrix, pointing
+//! inwards, extracted with the Gribb-Hartmann method for wgpu's 0..1 clip
+//! depth. Objects are tested by a bounding `Sphere`: conservative, so a few
+//! objects just outside a corner of the frustum are still drawn, but cheap
+//! enough for tens of thousands of them per frame.
+
+use glam::{Mat4, Vec3, Vec4};
+
+/// Bounding sphere
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl Sphere {
+    /// Sphere around `points`, centered on their bounding box
+    ///
+    /// Not the smallest one, but within a factor of √3 of it. Empty input
+    /// gives a point at the origin.
+    pub fn around(points: impl IntoIterator<Item = Vec3> + Clone) -> Self {
+        let (min, max) = points
+            .clone()
+            .into_iter()
+            .fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(min, max), p| {
+                (min.min(p), max.max(p))
+            });
+        if min.x > max.x {
+            return Self {
+                center: Vec3::ZERO,
+                radius: 0.0,
+            };
+        }
+        let center = (min + max) * 0.5;
+        let radius = points
+            .into_iter()
+            .map(|p| p.distance(center))
+            .fold(0.0, f32::max);
+        Self { center, radius }
+    }
+
+    /// The sphere moved by `model`, grown by its largest axis scale
+    pub fn transformed(&self, model: Mat4) -> Self {
+        let scale = model
+            .x_axis
+            .truncate()
+            .length()
+            .max(model.y_axis.truncate().length())
+            .max(model.z_axis.truncate().length());
+        Self {
+            center: model.transform_point3(self.center),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+/// Planes (normal, distance) whose positive side is inside
+#[derive(Debug, Clone, Copy)]
+pub struct Frustum {
+    planes: [Vec4; 6],
+}
+
+impl Frustum {
+    /// Frustum of a world to clip space matrix (clip depth 0..1)
+    pub fn from_view_proj(view_proj: Mat4) -> Self {
+        let [r0, r1, r2, r3] = [0, 1, 2, 3].map(|i| view_proj.row(i));
+        let planes = [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r2, r3 - r2]
+            .map(|plane| plane / plane.truncate().length());
+        Self { planes }
+    }
+
+    /// Whether any part of `sphere` may be inside
+    pub fn intersects(&self, sphere: &Sphere) -> bool {
+        let center = sphere.center.extend(1.0);
+        self.planes
+            .iter()
+            .all(|plane| plane.dot(center) >= -sphere.radius)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frustum() -> Frustum {
+        // At the origin looking down -z, 90° vertical field of view
+        let proj = Mat4::perspective_rh(std::f32::consts::FRAC_PI_2, 1.0, 0.1, 100.0);
+        Frustum::from_view_proj(proj)
+    }
+
+    fn sphere(x: f32, y: f32, z: f32, radius: f32) -> Sphere {
+        Sphere {
+            center: Vec3::new(x, y, z),
+            radius,
+        }
+    }
+
+    #[test]
+    fn test_frustum_intersects() {
+        let frustum = frustum();
+        assert!(frustum.intersects(&sphere(0.0, 0.0, -5.0, 1.0)));
+        // Behind the camera, beyond the far plane, off to the sides
+        assert!(!frustum.intersects(&sphere(0.0, 0.0, 5.0, 1.0)));
+        assert!(!frustum.intersects(&sphere(0.0, 0.0, -102.0, 1.0)));
+        assert!(!frustum.intersects(&sphere(8.0, 0.0, -5.0, 1.0)));
+        assert!(!frustum.intersects(&sphere(0.0, -8.0, -5.0, 1.0)));
+        // Center outside, but reaching in
+        assert!(frustum.intersects(&sphere(5.5, 0.0, -5.0, 1.0)));
+        assert!(frustum.intersects(&sphere(0.0, 0.0, 0.5, 1.0)));
+    }
+
+    #[test]
+    fn test_sphere_around() {
+        let points = [Vec3::new(1.0, 0.0, 0.0), Vec3::new(3.0, 2.0, 0.0)];
+        let sphere = Sphere::around(points);
+        assert_eq!(sphere.center, Vec3::new(2.0, 1.0, 0.0));
+        assert!((sphere.radius - 2.0_f32.sqrt()).abs() < 1e-6);
+        assert_eq!(Sphere::around(Vec::new()).radius, 0.0);
+    }
+
+    #[test]
+    fn test_sphere_transformed() {
+        let sphere = sphere(1.0, 0.0, 0.0, 0.5);
+        let model = Mat4::from_translation(Vec3::Y)
+            * Mat4::from_scale(Vec3::new(1.0, 4.0, 2.0))
+            * Mat4::from_rotation_y(std::f32::consts::FRAC_PI_2);
+        let moved = sphere.transformed(model);
+        assert!(moved.center.abs_diff_eq(Vec3::new(0.0, 1.0, -2.0), 1e-5));
+        assert!((moved.radius - 2.0).abs() < 1e-5);
+    }
+}
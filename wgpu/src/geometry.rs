@@ -0,0 +1,586 @@
+//! Procedural meshes
+//!
+//! Every generator returns a `Mesh<Vertex>` centered on the origin, with the
+//! y axis up, triangles wound counter-clockwise seen from outside (the demos
+//! cull back faces) and unit normals. Curved surfaces get smooth normals from
+//! the surface itself, not averaged from neighbouring triangles; flat parts
+//! (cube and dodecahedron faces, cylinder caps) get their own vertices so
+//! their edges stay sharp.
+//!
+//! Tessellation is configurable; indices are `u32`, so fine tessellations are
+//! not limited to 65536 vertices.
+
+use crate::renderer::{self, Mesh};
+use bytemuck::{Pod, Zeroable};
+use glam::Vec3;
+use std::collections::HashMap;
+use std::f32::consts::{PI, TAU};
+
+/// Position and normal, at locations 0 and 1
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Pod, Zeroable)]
+pub struct Vertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+}
+
+impl Vertex {
+    pub fn new(position: Vec3, normal: Vec3) -> Self {
+        Self {
+            position: position.to_array(),
+            normal: normal.to_array(),
+        }
+    }
+}
+
+impl renderer::Vertex for Vertex {
+    const ATTRIBS: &'static [wgpu::VertexAttribute] =
+        &wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3];
+}
+
+fn index(i: usize) -> u32 {
+    u32::try_from(i).expect("mesh has more vertices than u32 indices can address")
+}
+
+/// Triangles for a grid of `columns` x `rows` quads whose vertices start at
+/// `base`, row by row with `columns + 1` vertices each
+///
+/// Counter-clockwise when columns run along the first tangent and rows along
+/// the second, with the normal their cross product.
+fn grid_indices(indices: &mut Vec<u32>, base: usize, columns: usize, rows: usize) {
+    for row in 0..rows {
+        for column in 0..columns {
+            let a = base + row * (columns + 1) + column;
+            let b = a + columns + 1;
+            indices.extend([a, a + 1, b, a + 1, b + 1, b].map(index));
+        }
+    }
+}
+
+/// `grid_indices` for a band from pole to pole, where the first and last rows
+/// of vertices each sit in one point
+///
+/// The quads touching a pole are triangles; their other half is left out.
+fn pole_to_pole_indices(indices: &mut Vec<u32>, columns: usize, rows: usize) {
+    for row in 0..rows {
+        for column in 0..columns {
+            let a = row * (columns + 1) + column;
+            let b = a + columns + 1;
+            if row > 0 {
+                indices.extend([a, a + 1, b].map(index));
+            }
+            if row < rows - 1 {
+                indices.extend([a + 1, b + 1, b].map(index));
+            }
+        }
+    }
+}
+
+/// Cube with edge length 1 and one normal per face
+pub fn cube() -> Mesh<Vertex> {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+
+    for normal in [Vec3::X, -Vec3::X, Vec3::Y, -Vec3::Y, Vec3::Z, -Vec3::Z] {
+        // Two axes spanning the face, counter-clockwise seen from outside
+        let u = normal.any_orthonormal_vector();
+        let v = normal.cross(u);
+
+        let base = vertices.len();
+        for (du, dv) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+            vertices.push(Vertex::new((normal + u * du + v * dv) * 0.5, normal));
+        }
+        indices.extend([base, base + 1, base + 2, base, base + 2, base + 3].map(index));
+    }
+
+    Mesh::new(vertices, indices)
+}
+
+/// Torus around the y axis
+///
+/// `major_radius` is the distance from the center to the middle of the tube,
+/// `minor_radius` the radius of the tube. `major_segments` go around the
+/// ring, `minor_segments` around the tube.
+pub fn torus(
+    major_radius: f32,
+    minor_radius: f32,
+    major_segments: u32,
+    minor_segments: u32,
+) -> Mesh<Vertex> {
+    let (major, minor) = (major_segments.max(3), minor_segments.max(3));
+    let mut vertices = Vec::new();
+
+    for i in 0..=major {
+        let u = i as f32 / major as f32 * TAU;
+        for j in 0..=minor {
+            let v = j as f32 / minor as f32 * TAU;
+            let normal = Vec3::new(v.cos() * u.cos(), v.sin(), v.cos() * u.sin());
+            let center = Vec3::new(u.cos(), 0.0, u.sin()) * major_radius;
+            vertices.push(Vertex::new(center + normal * minor_radius, normal));
+        }
+    }
+
+    // Along the tube, then around the ring: their cross product points out
+    let mut indices = Vec::new();
+    grid_indices(&mut indices, 0, minor as usize, major as usize);
+    Mesh::new(vertices, indices)
+}
+
+/// Dodecahedron with flat faces and circumradius √3 / 2
+pub fn dodecahedron() -> Mesh<Vertex> {
+    let phi: f32 = (1.0 + 5.0_f32.sqrt()) / 2.0;
+    let inv_phi = 1.0 / phi;
+
+    #[rustfmt::skip]
+    let corners = [
+        // Cube corners (±1, ±1, ±1)
+        Vec3::new( 1.0,  1.0,  1.0),
+        Vec3::new( 1.0,  1.0, -1.0),
+        Vec3::new( 1.0, -1.0,  1.0),
+        Vec3::new( 1.0, -1.0, -1.0),
+        Vec3::new(-1.0,  1.0,  1.0),
+        Vec3::new(-1.0,  1.0, -1.0),
+        Vec3::new(-1.0, -1.0,  1.0),
+        Vec3::new(-1.0, -1.0, -1.0),
+        // Rectangle in the xy plane (±phi, ±1/phi, 0)
+        Vec3::new( phi,  inv_phi, 0.0),
+        Vec3::new( phi, -inv_phi, 0.0),
+        Vec3::new(-phi,  inv_phi, 0.0),
+        Vec3::new(-phi, -inv_phi, 0.0),
+        // Rectangle in the yz plane (0, ±phi, ±1/phi)
+        Vec3::new(0.0,  phi,  inv_phi),
+        Vec3::new(0.0,  phi, -inv_phi),
+        Vec3::new(0.0, -phi,  inv_phi),
+        Vec3::new(0.0, -phi, -inv_phi),
+        // Rectangle in the xz plane (±1/phi, 0, ±phi)
+        Vec3::new( inv_phi, 0.0,  phi),
+        Vec3::new(-inv_phi, 0.0,  phi),
+        Vec3::new( inv_phi, 0.0, -phi),
+        Vec3::new(-inv_phi, 0.0, -phi),
+    ];
+    // 12 pentagons, each listed around its edge
+    let faces: [[usize; 5]; 12] = [
+        [0, 8, 9, 2, 16],
+        [0, 16, 17, 4, 12],
+        [0, 12, 13, 1, 8],
+        [1, 13, 5, 19, 18],
+        [1, 18, 3, 9, 8],
+        [2, 9, 3, 15, 14],
+        [2, 14, 6, 17, 16],
+        [3, 18, 19, 7, 15],
+        [4, 17, 6, 11, 10],
+        [4, 10, 5, 13, 12],
+        [5, 10, 11, 7, 19],
+        [6, 14, 15, 7, 11],
+    ];
+
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+
+    for face in &faces {
+        let points = face.map(|i| corners[i] * 0.5);
+        let center = points.iter().copied().sum::<Vec3>() / 5.0;
+        let mut normal = (points[1] - points[0])
+            .cross(points[2] - points[0])
+            .normalize();
+
+        // Some faces are listed clockwise seen from outside; flip those
+        let clockwise = normal.dot(center) < 0.0;
+        if clockwise {
+            normal = -normal;
+        }
+
+        let base = vertices.len();
+        vertices.extend(points.iter().map(|&p| Vertex::new(p, normal)));
+        // Fan from the first corner
+        for i in 1..4 {
+            let triangle = if clockwise {
+                [base, base + i + 1, base + i]
+            } else {
+                [base, base + i, base + i + 1]
+            };
+            indices.extend(triangle.map(index));
+        }
+    }
+
+    Mesh::new(vertices, indices)
+}
+
+/// Sphere of `segments` meridians and `rings` bands from pole to pole
+pub fn uv_sphere(radius: f32, segments: u32, rings: u32) -> Mesh<Vertex> {
+    let (segments, rings) = (segments.max(3), rings.max(2));
+    let mut vertices = Vec::new();
+
+    // From the south pole up; the seam and the poles repeat vertices so every
+    // quad has its own corners
+    for ring in 0..=rings {
+        let polar = PI - ring as f32 / rings as f32 * PI;
+        for segment in 0..=segments {
+            let azimuth = segment as f32 / segments as f32 * TAU;
+            let normal = Vec3::new(
+                polar.sin() * azimuth.cos(),
+                polar.cos(),
+                -polar.sin() * azimuth.sin(),
+            );
+            vertices.push(Vertex::new(normal * radius, normal));
+        }
+    }
+
+    // Around (counter-clockwise seen from above), then up: their cross
+    // product points out
+    let mut indices = Vec::new();
+    pole_to_pole_indices(&mut indices, segments as usize, rings as usize);
+    Mesh::new(vertices, indices)
+}
+
+/// Sphere from an icosahedron with each triangle split into four
+/// `subdivisions` times
+///
+/// Triangles are more even than on a `uv_sphere`, without crowding at the
+/// poles.
+pub fn icosphere(radius: f32, subdivisions: u32) -> Mesh<Vertex> {
+    let phi: f32 = (1.0 + 5.0_f32.sqrt()) / 2.0;
+    let mut points: Vec<Vec3> = [
+        (-1.0, phi, 0.0),
+        (1.0, phi, 0.0),
+        (-1.0, -phi, 0.0),
+        (1.0, -phi, 0.0),
+        (0.0, -1.0, phi),
+        (0.0, 1.0, phi),
+        (0.0, -1.0, -phi),
+        (0.0, 1.0, -phi),
+        (phi, 0.0, -1.0),
+        (phi, 0.0, 1.0),
+        (-phi, 0.0, -1.0),
+        (-phi, 0.0, 1.0),
+    ]
+    .into_iter()
+    .map(|(x, y, z)| Vec3::new(x, y, z).normalize())
+    .collect();
+    let mut triangles: Vec<[usize; 3]> = vec![
+        [0, 11, 5],
+        [0, 5, 1],
+        [0, 1, 7],
+        [0, 7, 10],
+        [0, 10, 11],
+        [1, 5, 9],
+        [5, 11, 4],
+        [11, 10, 2],
+        [10, 7, 6],
+        [7, 1, 8],
+        [3, 9, 4],
+        [3, 4, 2],
+        [3, 2, 6],
+        [3, 6, 8],
+        [3, 8, 9],
+        [4, 9, 5],
+        [2, 4, 11],
+        [6, 2, 10],
+        [8, 6, 7],
+        [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        // Edges are shared by two triangles; split each only once
+        let mut midpoints = HashMap::new();
+        let mut midpoint = |a: usize, b: usize| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                points.push((points[a] + points[b]).normalize());
+                points.len() - 1
+            })
+        };
+        triangles = triangles
+            .iter()
+            .flat_map(|&[a, b, c]| {
+                let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+                [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+
+    let vertices = points
+        .iter()
+        .map(|&normal| Vertex::new(normal * radius, normal))
+        .collect();
+    let indices = triangles.into_iter().flatten().map(index).collect();
+    Mesh::new(vertices, indices)
+}
+
+/// Ring of `segments + 1` vertices at height `y`, normals pointing out from
+/// the axis and tilted by `normal_y`, counter-clockwise from above
+fn push_ring(vertices: &mut Vec<Vertex>, radius: f32, y: f32, normal_y: f32, segments: u32) {
+    let horizontal = (1.0 - normal_y * normal_y).max(0.0).sqrt();
+    for segment in 0..=segments {
+        let angle = segment as f32 / segments as f32 * TAU;
+        let (sin, cos) = angle.sin_cos();
+        let out = Vec3::new(cos, 0.0, -sin);
+        vertices.push(Vertex::new(
+            out * radius + Vec3::Y * y,
+            out * horizontal + Vec3::Y * normal_y,
+        ));
+    }
+}
+
+/// Flat disc at height `y` facing up (`up`) or down, as a fan around its
+/// center
+fn push_cap(
+    vertices: &mut Vec<Vertex>,
+    indices: &mut Vec<u32>,
+    radius: f32,
+    y: f32,
+    up: bool,
+    segments: u32,
+) {
+    let normal = if up { Vec3::Y } else { -Vec3::Y };
+    let center = vertices.len();
+    vertices.push(Vertex::new(Vec3::Y * y, normal));
+    for segment in 0..=segments {
+        let angle = segment as f32 / segments as f32 * TAU;
+        let (sin, cos) = angle.sin_cos();
+        vertices.push(Vertex::new(
+            Vec3::new(cos * radius, y, -sin * radius),
+            normal,
+        ));
+    }
+    for segment in 0..segments as usize {
+        let (a, b) = (center + 1 + segment, center + 2 + segment);
+        let triangle = if up { [center, a, b] } else { [center, b, a] };
+        indices.extend(triangle.map(index));
+    }
+}
+
+/// Closed cylinder along the y axis, `height` tall, with `segments` around
+/// and `stacks` bands along the side
+pub fn cylinder(radius: f32, height: f32, segments: u32, stacks: u32) -> Mesh<Vertex> {
+    let (segments, stacks) = (segments.max(3), stacks.max(1));
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+
+    for stack in 0..=stacks {
+        let y = (stack as f32 / stacks as f32 - 0.5) * height;
+        push_ring(&mut vertices, radius, y, 0.0, segments);
+    }
+    grid_indices(&mut indices, 0, segments as usize, stacks as usize);
+
+    push_cap(
+        &mut vertices,
+        &mut indices,
+        radius,
+        height / 2.0,
+        true,
+        segments,
+    );
+    push_cap(
+        &mut vertices,
+        &mut indices,
+        radius,
+        -height / 2.0,
+        false,
+        segments,
+    );
+
+    Mesh::new(vertices, indices)
+}
+
+/// Cylinder along the y axis with hemispherical ends
+///
+/// `height` is the length of the straight part; the capsule is
+/// `height + 2 * radius` tall. `rings` bands make up each hemisphere.
+pub fn capsule(radius: f32, height: f32, segments: u32, rings: u32) -> Mesh<Vertex> {
+    let (segments, rings) = (segments.max(3), rings.max(1));
+    let mut vertices = Vec::new();
+
+    // One band from the south pole to the north pole; the straight part is
+    // the band between the two equators, which share normals with it
+    let half = height / 2.0;
+    for ring in 0..=2 * rings + 1 {
+        let (latitude, y) = if ring <= rings {
+            let latitude = -PI / 2.0 + ring as f32 / rings as f32 * PI / 2.0;
+            (latitude, -half + radius * latitude.sin())
+        } else {
+            let latitude = (ring - rings - 1) as f32 / rings as f32 * PI / 2.0;
+            (latitude, half + radius * latitude.sin())
+        };
+        push_ring(
+            &mut vertices,
+            radius * latitude.cos(),
+            y,
+            latitude.sin(),
+            segments,
+        );
+    }
+
+    let mut indices = Vec::new();
+    pole_to_pole_indices(&mut indices, segments as usize, 2 * rings as usize + 1);
+
+    Mesh::new(vertices, indices)
+}
+
+/// `width` x `depth` plane in xz facing up, split into `columns` x `rows`
+/// quads
+pub fn plane(width: f32, depth: f32, columns: u32, rows: u32) -> Mesh<Vertex> {
+    let (columns, rows) = (columns.max(1), rows.max(1));
+    let mut vertices = Vec::new();
+
+    // Columns along +x, rows along -z: their cross product is +y
+    for row in 0..=rows {
+        let z = (0.5 - row as f32 / rows as f32) * depth;
+        for column in 0..=columns {
+            let x = (column as f32 / columns as f32 - 0.5) * width;
+            vertices.push(Vertex::new(Vec3::new(x, 0.0, z), Vec3::Y));
+        }
+    }
+
+    let mut indices = Vec::new();
+    grid_indices(&mut indices, 0, columns as usize, rows as usize);
+    Mesh::new(vertices, indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(mesh: &Mesh<Vertex>, i: u32) -> Vec3 {
+        Vec3::from(mesh.vertices[i as usize].position)
+    }
+
+    fn normal(mesh: &Mesh<Vertex>, i: u32) -> Vec3 {
+        Vec3::from(mesh.vertices[i as usize].normal)
+    }
+
+    /// Unit normals, and every triangle facing the way its normals point
+    fn assert_along_normals(mesh: &Mesh<Vertex>) {
+        assert_eq!(mesh.indices.len() % 3, 0);
+        for vertex in &mesh.vertices {
+            assert!((Vec3::from(vertex.normal).length() - 1.0).abs() < 1e-5);
+        }
+        for triangle in mesh.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| position(mesh, triangle[i]));
+            let face_normal = (b - a).cross(c - a);
+            assert!(face_normal.length() > 0.0, "degenerate triangle");
+            for &i in triangle {
+                assert!(face_normal.dot(normal(mesh, i)) > 0.0);
+            }
+        }
+    }
+
+    /// Every triangle of a convex mesh around the origin faces away from it
+    fn assert_outward(mesh: &Mesh<Vertex>) {
+        assert_along_normals(mesh);
+        for triangle in mesh.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| position(mesh, triangle[i]));
+            assert!((b - a).cross(c - a).dot(a + b + c) > 0.0);
+        }
+    }
+
+    /// Normals of a sphere centered on the origin point along the position
+    fn assert_spherical(mesh: &Mesh<Vertex>, radius: f32) {
+        for vertex in &mesh.vertices {
+            let position = Vec3::from(vertex.position);
+            assert!((position.length() - radius).abs() < 1e-5);
+            assert!(Vec3::from(vertex.normal).abs_diff_eq(position / radius, 1e-5));
+        }
+    }
+
+    #[test]
+    fn test_polyhedra() {
+        let cube = cube();
+        assert_eq!((cube.vertices.len(), cube.indices.len()), (24, 36));
+        assert_outward(&cube);
+
+        let dodecahedron = dodecahedron();
+        assert_eq!(
+            (dodecahedron.vertices.len(), dodecahedron.indices.len()),
+            (60, 108)
+        );
+        assert_outward(&dodecahedron);
+    }
+
+    #[test]
+    fn test_torus() {
+        let torus = torus(0.5, 0.2, 8, 6);
+        assert_eq!((torus.vertices.len(), torus.indices.len()), (63, 288));
+        assert_along_normals(&torus);
+        // Normals point away from the middle of the tube
+        for vertex in &torus.vertices {
+            let p = Vec3::from(vertex.position);
+            let tube_center = Vec3::new(p.x, 0.0, p.z).normalize() * 0.5;
+            assert!(Vec3::from(vertex.normal).abs_diff_eq((p - tube_center) / 0.2, 1e-5));
+        }
+    }
+
+    #[test]
+    fn test_uv_sphere() {
+        let sphere = uv_sphere(2.0, 16, 8);
+        assert_eq!(sphere.vertices.len(), 17 * 9);
+        // Quads, less one triangle per quad at each pole
+        assert_eq!(sphere.indices.len(), 3 * (2 * 16 * 8 - 2 * 16));
+        assert_outward(&sphere);
+        assert_spherical(&sphere, 2.0);
+
+        // More vertices than 16-bit indices can address
+        let fine = uv_sphere(1.0, 512, 256);
+        assert_eq!(fine.vertices.len(), 513 * 257);
+        assert!(fine.indices.iter().any(|&i| i > u32::from(u16::MAX)));
+    }
+
+    #[test]
+    fn test_icosphere() {
+        let icosahedron = icosphere(1.0, 0);
+        assert_eq!(
+            (icosahedron.vertices.len(), icosahedron.indices.len()),
+            (12, 60)
+        );
+        assert_outward(&icosahedron);
+
+        // Each subdivision quadruples the faces; V = F / 2 + 2
+        let sphere = icosphere(1.5, 3);
+        assert_eq!(sphere.indices.len(), 3 * 20 * 64);
+        assert_eq!(sphere.vertices.len(), 20 * 64 / 2 + 2);
+        assert_outward(&sphere);
+        assert_spherical(&sphere, 1.5);
+    }
+
+    #[test]
+    fn test_cylinder() {
+        let cylinder = cylinder(0.5, 2.0, 12, 3);
+        assert_outward(&cylinder);
+        for vertex in &cylinder.vertices {
+            let p = Vec3::from(vertex.position);
+            let n = Vec3::from(vertex.normal);
+            assert!(p.y.abs() <= 1.0 + 1e-6);
+            if n.y == 0.0 {
+                // Side: smooth, straight out from the axis
+                assert!(n.abs_diff_eq(Vec3::new(p.x, 0.0, p.z) / 0.5, 1e-5));
+            } else {
+                // Caps: flat
+                assert_eq!(n, Vec3::Y * p.y.signum());
+            }
+        }
+    }
+
+    #[test]
+    fn test_capsule() {
+        let capsule = capsule(0.5, 1.0, 12, 4);
+        assert_outward(&capsule);
+        // Normals point away from the nearest point on the axis segment
+        for vertex in &capsule.vertices {
+            let p = Vec3::from(vertex.position);
+            let axis_point = Vec3::Y * p.y.clamp(-0.5, 0.5);
+            assert!(Vec3::from(vertex.normal).abs_diff_eq((p - axis_point) / 0.5, 1e-5));
+        }
+        let height =
+            |f: fn(f32, f32) -> f32| capsule.vertices.iter().map(|v| v.position[1]).fold(0.0, f);
+        assert!((height(f32::max) - 1.0).abs() < 1e-6);
+        assert!((height(f32::min) + 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_plane() {
+        let plane = plane(4.0, 2.0, 4, 2);
+        assert_eq!((plane.vertices.len(), plane.indices.len()), (15, 48));
+        assert_along_normals(&plane);
+        assert_eq!(plane.vertices[0].position, [-2.0, 0.0, 1.0]);
+        assert_eq!(plane.vertices[14].position, [2.0, 0.0, -1.0]);
+    }
+}
@@ -0,0 +1,157 @@
+//! Offscreen rendering to PNG files
+//!
+//! `--headless` draws each frame into a texture instead of a window surface,
+//! copies it into a mappable buffer and writes it out as `frame_NNNN.png`. No
+//! window or surface is created, so this runs on CI machines with a software
+//! adapter: golden-image tests, or batch renders of turntable animations.
+//!
+//! The animation clock advances a fixed `1 / fps` per frame rather than
+//! following the wall clock, so the same command always renders the same
+//! frames.
+
+use crate::camera::Camera;
+use crate::renderer::{Gpu, Uniforms};
+use crate::screenshot;
+use image::{ImageResult, RgbaImage};
+use std::fs;
+use std::path::Path;
+
+/// Color format of the offscreen target (sRGB, like the window surface)
+pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+const BYTES_PER_PIXEL: u32 = 4;
+
+/// Row pitch of the readback buffer; copies need 256-byte aligned rows
+pub fn padded_bytes_per_row(width: u32) -> u32 {
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    (width * BYTES_PER_PIXEL).div_ceil(align) * align
+}
+
+/// File name of frame `index` in the output directory
+pub fn frame_file_name(index: u32) -> String {
+    format!("frame_{index:04}.png")
+}
+
+/// A demo rendering into an offscreen texture
+pub struct Headless<U> {
+    gpu: Gpu<U>,
+    camera: Camera,
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    buffer: wgpu::Buffer,
+}
+
+impl<U: Uniforms> Headless<U> {
+    /// `gpu` must draw in `FORMAT`
+    pub fn new(gpu: Gpu<U>, camera: Camera) -> Self {
+        let size = gpu.size();
+        let texture = gpu.device().create_texture(&wgpu::TextureDescriptor {
+            label: Some("Headless Target"),
+            size: wgpu::Extent3d {
+                width: size.width,
+                height: size.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: gpu.format(),
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let buffer = gpu.device().create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Headless Readback Buffer"),
+            size: (padded_bytes_per_row(size.width) * size.height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            gpu,
+            camera,
+            texture,
+            view,
+            buffer,
+        }
+    }
+
+    /// Render the frame at `time` seconds and read it back
+    pub fn render(&mut self, time: f32) -> RgbaImage {
+        let size = self.gpu.size();
+        let padded = padded_bytes_per_row(size.width);
+        self.gpu.update(time, &self.camera);
+
+        let mut encoder =
+            self.gpu
+                .device()
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Headless Encoder"),
+                });
+        self.gpu.draw(&mut encoder, &self.view);
+        encoder.copy_texture_to_buffer(
+            self.texture.as_image_copy(),
+            wgpu::TexelCopyBufferInfo {
+                buffer: &self.buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded),
+                    rows_per_image: Some(size.height),
+                },
+            },
+            self.texture.size(),
+        );
+        self.gpu.queue().submit(std::iter::once(encoder.finish()));
+
+        let slice = self.buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |result| {
+            result.expect("Failed to map the readback buffer")
+        });
+        self.gpu
+            .device()
+            .poll(wgpu::PollType::wait_indefinitely())
+            .expect("Failed to wait for the GPU");
+
+        let image = screenshot::to_rgba(
+            &slice.get_mapped_range(),
+            size.width,
+            size.height,
+            padded,
+            FORMAT,
+        );
+        self.buffer.unmap();
+
+        image.expect("Readback size mismatch")
+    }
+
+    /// Write `frames` frames, `1 / fps` seconds apart, as PNG files into `out`
+    pub fn render_frames(&mut self, frames: u32, fps: f32, out: &Path) -> ImageResult<()> {
+        fs::create_dir_all(out)?;
+        for index in 0..frames {
+            let path = out.join(frame_file_name(index));
+            self.render(index as f32 / fps).save(&path)?;
+            log::info!("Wrote {}", path.display());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_padded_bytes_per_row() {
+        assert_eq!(padded_bytes_per_row(64), 256);
+        assert_eq!(padded_bytes_per_row(65), 512);
+        assert_eq!(padded_bytes_per_row(800), 3328);
+        assert_eq!(padded_bytes_per_row(1), 256);
+    }
+
+    #[test]
+    fn test_frame_file_name() {
+        assert_eq!(frame_file_name(0), "frame_0000.png");
+        assert_eq!(frame_file_name(123), "frame_0123.png");
+    }
+}
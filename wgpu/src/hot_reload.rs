@@ -0,0 +1,121 @@
+//! Shader hot reload
+//!
+//! `--shader PATH` makes a demo load its WGSL from a file instead of the
+//! built-in source. A `ShaderWatcher` reports when that file changes, and
+//! `Gpu::reload_shader` rebuilds the pipelines from it between frames. A shader
+//! that fails to compile is logged and the last good pipelines keep drawing,
+//! so a typo never takes the window down.
+//!
+//! The watcher watches the file's directory rather than the file itself:
+//! many editors save by writing a new file and renaming it over the old one,
+//! which would end a watch on the original file.
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+
+/// Reports changes to one shader file
+pub struct ShaderWatcher {
+    path: PathBuf,
+    events: Receiver<notify::Result<notify::Event>>,
+    // Stops watching when dropped
+    _watcher: RecommendedWatcher,
+}
+
+impl ShaderWatcher {
+    /// Start watching `path`, which must exist
+    pub fn new(path: impl AsRef<Path>) -> notify::Result<Self> {
+        // Events carry absolute paths
+        let path = path.as_ref().canonicalize()?;
+        let dir = path.parent().unwrap_or(Path::new("/"));
+
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+
+        Ok(Self {
+            path,
+            events,
+            _watcher: watcher,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the file was written or replaced since the last call
+    pub fn changed(&self) -> bool {
+        let mut changed = false;
+        for event in self.events.try_iter() {
+            match event {
+                Ok(event) => changed |= is_change_to(&event, &self.path),
+                Err(e) => log::warn!("Watching {} failed: {}", self.path.display(), e),
+            }
+        }
+        changed
+    }
+}
+
+/// Whether `event` creates or modifies the file at `path`
+fn is_change_to(event: &notify::Event, path: &Path) -> bool {
+    matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_))
+        && event.paths.iter().any(|p| p == path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{AccessKind, CreateKind, ModifyKind, RenameMode};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_is_change_to() {
+        let path = Path::new("/shaders/cube.wgsl");
+        let event = |kind| notify::Event::new(kind).add_path(path.to_path_buf());
+
+        assert!(is_change_to(
+            &event(EventKind::Modify(ModifyKind::Any)),
+            path
+        ));
+        assert!(is_change_to(
+            &event(EventKind::Modify(ModifyKind::Name(RenameMode::To))),
+            path
+        ));
+        assert!(is_change_to(
+            &event(EventKind::Create(CreateKind::File)),
+            path
+        ));
+        assert!(!is_change_to(
+            &event(EventKind::Access(AccessKind::Any)),
+            path
+        ));
+        assert!(!is_change_to(
+            &event(EventKind::Modify(ModifyKind::Any)),
+            Path::new("/shaders/ring.wgsl")
+        ));
+    }
+
+    #[test]
+    fn test_watcher_sees_writes() {
+        let dir = std::env::temp_dir().join(format!("shader-watch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("shader.wgsl");
+        std::fs::write(&path, "// v1").unwrap();
+
+        let watcher = ShaderWatcher::new(&path).unwrap();
+        assert!(!watcher.changed());
+
+        std::fs::write(&path, "// v2").unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut changed = false;
+        while !changed && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(20));
+            changed = watcher.changed();
+        }
+        assert!(changed);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,14 @@
+pub mod camera;
+pub mod frame_stats;
+pub mod frustum;
+pub mod geometry;
+pub mod headless;
+pub mod hot_reload;
+pub mod overlay;
+pub mod renderer;
+pub mod scene;
+pub mod screenshot;
+pub mod shadow;
+pub mod skybox;
+pub mod texture;
+pub mod uniform_ring;
@@ -0,0 +1,144 @@
+//! egui overlay for tweaking a demo while it runs
+//!
+//! The overlay is drawn in its own render pass on top of the resolved frame,
+//! so it is unaffected by MSAA and the depth buffer. Events go to egui first;
+//! whatever egui does not consume falls through to the camera. F1 hides and
+//! shows the panel.
+
+use egui_wgpu::{Renderer, RendererOptions, ScreenDescriptor};
+use winit::event::{ElementState, KeyEvent, WindowEvent};
+use winit::keyboard::{KeyCode, PhysicalKey};
+use winit::window::Window;
+
+pub struct Overlay {
+    context: egui::Context,
+    state: egui_winit::State,
+    renderer: Renderer,
+    visible: bool,
+}
+
+impl Overlay {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, window: &Window) -> Self {
+        let context = egui::Context::default();
+        let state = egui_winit::State::new(
+            context.clone(),
+            egui::ViewportId::ROOT,
+            window,
+            Some(window.scale_factor() as f32),
+            window.theme(),
+            Some(device.limits().max_texture_dimension_2d as usize),
+        );
+        let renderer = Renderer::new(device, format, RendererOptions::default());
+
+        Self {
+            context,
+            state,
+            renderer,
+            visible: true,
+        }
+    }
+
+    /// Feed a window event; returns whether egui consumed it
+    pub fn handle_event(&mut self, window: &Window, event: &WindowEvent) -> bool {
+        if let WindowEvent::KeyboardInput {
+            event:
+                KeyEvent {
+                    physical_key: PhysicalKey::Code(KeyCode::F1),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                },
+            ..
+        } = event
+        {
+            self.visible = !self.visible;
+            return true;
+        }
+
+        let response = self.state.on_window_event(window, event);
+        self.visible && response.consumed
+    }
+
+    /// Run `build_ui` and draw the result on top of `view`
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        window: &Window,
+        view: &wgpu::TextureView,
+        build_ui: impl FnMut(&egui::Context),
+    ) {
+        // Taken even while hidden, or the events queued by `handle_event`
+        // would pile up until the panel is shown again
+        let input = self.state.take_egui_input(window);
+        if !self.visible {
+            return;
+        }
+
+        let output = self.context.run(input, build_ui);
+        self.state
+            .handle_platform_output(window, output.platform_output);
+
+        let paint_jobs = self
+            .context
+            .tessellate(output.shapes, output.pixels_per_point);
+        let size = window.inner_size();
+        let screen = ScreenDescriptor {
+            size_in_pixels: [size.width, size.height],
+            pixels_per_point: output.pixels_per_point,
+        };
+
+        for (id, delta) in &output.textures_delta.set {
+            self.renderer.update_texture(device, queue, *id, delta);
+        }
+        let commands = self
+            .renderer
+            .update_buffers(device, queue, encoder, &paint_jobs, &screen);
+        queue.submit(commands);
+
+        {
+            let render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Overlay Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            self.renderer
+                .render(&mut render_pass.forget_lifetime(), &paint_jobs, &screen);
+        }
+
+        for id in &output.textures_delta.free {
+            self.renderer.free_texture(id);
+        }
+    }
+}
+
+/// Color picker for the RGB part of an RGBA uniform
+pub fn color_rgb(ui: &mut egui::Ui, label: &str, color: &mut [f32; 4]) {
+    ui.horizontal(|ui| {
+        let mut rgb = [color[0], color[1], color[2]];
+        ui.color_edit_button_rgb(&mut rgb);
+        color[..3].copy_from_slice(&rgb);
+        ui.label(label);
+    });
+}
+
+/// Drag fields for the XYZ part of a position uniform
+pub fn position(ui: &mut egui::Ui, label: &str, position: &mut [f32; 4]) {
+    ui.horizontal(|ui| {
+        for value in &mut position[..3] {
+            ui.add(egui::DragValue::new(value).speed(0.05));
+        }
+        ui.label(label);
+    });
+}
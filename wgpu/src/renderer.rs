@@ -0,0 +1,1895 @@
+//! Shared rendering framework for the demos
+//!
+//! Every demo is one indexed mesh drawn by one WGSL shader that reads a single
+//! uniform buffer at `@group(0) @binding(0)`. The surface, device, pipeline,
+//! depth buffer and window plumbing live here; a demo only supplies the mesh,
+//! the shader and a `Uniforms` type, then hands a `StateBuilder` to `run`.
+//!
+//! Window input goes to an orbit `Camera`; its view matrix reaches the demo
+//! through `Frame`.
+//!
+//! Demos are rendered with multisampling (`--msaa N`, 4x by default) into an
+//! offscreen color target that is resolved into the surface texture. A count
+//! the adapter cannot do falls back to the highest supported one below it.
+//!
+//! A demo may also give the builder an image (`--texture PATH` replaces it);
+//! it is bound at `@group(1)` as described in the `texture` module.
+//!
+//! An egui `Overlay` shows frame time statistics (see `frame_stats`) and the
+//! animation speed, plus
+//! whatever parameters the demo's `Uniforms::ui` exposes. Uniforms are uploaded
+//! every frame, so edits take effect immediately.
+//!
+//! Demos lit by a directional light can cast shadows onto a ground plane; see
+//! the `shadow` module. A demo can also sit in a cubemap sky that its shader
+//! samples for reflections (`--skybox DIR` replaces the sky); see `skybox`.
+//!
+//! `--shader PATH` loads the demo's WGSL from a file and reloads it whenever
+//! the file changes; see the `hot_reload` module.
+//!
+//! The backend, adapter, present mode and window size come from `GpuOptions`,
+//! which every binary flattens into its command line (`--list-adapters` shows
+//! what `--adapter` can pick).
+//!
+//! A demo may draw a `Scene` instead of a mesh of its own
+//! (`StateBuilder::scene`). A `SceneRenderer` draws its nodes after the sky and
+//! the ground, with a depth prepass when asked for one, and the overlay counts
+//! the objects drawn and culled. Scene objects cast no shadows.
+//!
+//! F12 saves the window, overlay included, as a PNG in `screenshots/`; see
+//! the `screenshot` module. Space pauses the animation, `+` / `-` change its
+//! speed (`0` resets it), F11 toggles borderless fullscreen and Escape quits.
+//!
+//! The pipeline and render targets live in `Gpu`, which draws into any color
+//! view. The windowed `State` hands it the surface texture; `--headless` hands
+//! it an offscreen texture instead (see the `headless` module).
+
+use crate::camera::Camera;
+use crate::frame_stats::{FrameStats, GpuTimer};
+use crate::headless::{self, Headless};
+use crate::hot_reload::ShaderWatcher;
+use crate::overlay::Overlay;
+use crate::scene::{CullStats, Scene, SceneRenderer, SceneVertex};
+use crate::screenshot::{self, Capture};
+use crate::shadow::{self, ShadowConfig, Shadows, TargetDesc};
+use crate::skybox::{self, Skybox};
+use crate::texture::{self, Texture};
+use bytemuck::Pod;
+use clap::{Args, Parser, ValueEnum};
+use glam::{Mat4, Vec3};
+use image::RgbaImage;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Instant;
+use wgpu::util::DeviceExt;
+use winit::{
+    application::ApplicationHandler,
+    dpi::PhysicalSize,
+    event::{ElementState, KeyEvent, WindowEvent},
+    event_loop::{ActiveEventLoop, ControlFlow, EventLoop},
+    keyboard::{KeyCode, PhysicalKey},
+    window::{Fullscreen, Window, WindowId},
+};
+
+pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+/// Fastest animation speed the keys and the overlay slider allow
+const MAX_SPEED: f32 = 5.0;
+
+/// Animation speed change per `+` / `-` press
+const SPEED_STEP: f32 = 0.25;
+
+/// Frames the surface may queue ahead, and so frames a `SceneRenderer` keeps
+/// uniforms for
+const MAX_FRAME_LATENCY: u32 = 2;
+
+/// Sample counts wgpu knows about, in increasing order
+const SAMPLE_COUNTS: [u32; 5] = [1, 2, 4, 8, 16];
+
+/// Command line options shared by every demo
+#[derive(Parser, Debug)]
+#[command(version, about = "wgpu 3D demo")]
+pub struct Options {
+    /// MSAA sample count (1 disables multisampling)
+    #[arg(long, value_name = "N", default_value_t = 4, value_parser = parse_sample_count)]
+    pub msaa: u32,
+
+    /// Image (PNG or JPEG) to texture the mesh with, for demos that use one
+    #[arg(long, value_name = "PATH")]
+    pub texture: Option<PathBuf>,
+
+    /// Directory of cubemap faces (px, nx, py, ny, pz, nz) for demos with a sky
+    #[arg(long, value_name = "DIR")]
+    pub skybox: Option<PathBuf>,
+
+    /// WGSL file to use instead of the built-in shader; reloaded when it changes
+    #[arg(long, value_name = "PATH")]
+    pub shader: Option<PathBuf>,
+
+    /// Render to PNG files instead of opening a window
+    #[arg(long)]
+    pub headless: bool,
+
+    /// Number of frames to render with --headless
+    #[arg(long, value_name = "N", default_value_t = 1, requires = "headless")]
+    pub frames: u32,
+
+    /// Directory the --headless frames are written to
+    #[arg(
+        long,
+        value_name = "DIR",
+        default_value = "frames",
+        requires = "headless"
+    )]
+    pub out: PathBuf,
+
+    /// Animation frames per second with --headless
+    #[arg(
+        long,
+        value_name = "FPS",
+        default_value_t = 60.0,
+        requires = "headless"
+    )]
+    pub fps: f32,
+
+    #[command(flatten)]
+    pub gpu: GpuOptions,
+}
+
+/// Backend, adapter and presentation options shared by every binary
+#[derive(Args, Debug, Clone, Default)]
+pub struct GpuOptions {
+    /// Graphics backend (default: Vulkan, falling back to GL)
+    #[arg(long, value_enum)]
+    pub backend: Option<Backend>,
+
+    /// Adapter to use, by index in --list-adapters or by part of its name
+    #[arg(long, value_name = "INDEX|NAME", value_parser = parse_adapter)]
+    pub adapter: Option<AdapterSelector>,
+
+    /// How frames are presented (vsync or not)
+    #[arg(long, value_enum, default_value_t)]
+    pub present_mode: PresentMode,
+
+    /// Window size in physical pixels (also the --headless frame size)
+    #[arg(long, value_name = "WxH", value_parser = parse_size)]
+    pub size: Option<PhysicalSize<u32>>,
+
+    /// Print the available adapters and exit
+    #[arg(long)]
+    pub list_adapters: bool,
+}
+
+impl GpuOptions {
+    pub fn backends(&self) -> wgpu::Backends {
+        match self.backend {
+            Some(backend) => backend.into(),
+            None => wgpu::Backends::VULKAN | wgpu::Backends::GL,
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Vulkan,
+    Gl,
+    Dx12,
+    Metal,
+}
+
+impl From<Backend> for wgpu::Backends {
+    fn from(backend: Backend) -> Self {
+        match backend {
+            Backend::Vulkan => wgpu::Backends::VULKAN,
+            Backend::Gl => wgpu::Backends::GL,
+            Backend::Dx12 => wgpu::Backends::DX12,
+            Backend::Metal => wgpu::Backends::METAL,
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PresentMode {
+    /// Vsync (FIFO relaxed where available)
+    #[default]
+    AutoVsync,
+    /// No vsync (immediate or mailbox, whichever is available)
+    AutoNoVsync,
+    Fifo,
+    FifoRelaxed,
+    Immediate,
+    Mailbox,
+}
+
+impl From<PresentMode> for wgpu::PresentMode {
+    fn from(mode: PresentMode) -> Self {
+        match mode {
+            PresentMode::AutoVsync => wgpu::PresentMode::AutoVsync,
+            PresentMode::AutoNoVsync => wgpu::PresentMode::AutoNoVsync,
+            PresentMode::Fifo => wgpu::PresentMode::Fifo,
+            PresentMode::FifoRelaxed => wgpu::PresentMode::FifoRelaxed,
+            PresentMode::Immediate => wgpu::PresentMode::Immediate,
+            PresentMode::Mailbox => wgpu::PresentMode::Mailbox,
+        }
+    }
+}
+
+/// `--adapter`: a position in the adapter list or part of an adapter's name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdapterSelector {
+    Index(usize),
+    /// Matched case-insensitively against the adapter name
+    Name(String),
+}
+
+impl AdapterSelector {
+    /// Position of the selected adapter among `names`
+    fn find(&self, names: &[String]) -> Option<usize> {
+        match self {
+            Self::Index(index) => (*index < names.len()).then_some(*index),
+            Self::Name(name) => {
+                let name = name.to_lowercase();
+                names.iter().position(|n| n.to_lowercase().contains(&name))
+            }
+        }
+    }
+}
+
+fn parse_adapter(value: &str) -> Result<AdapterSelector, String> {
+    if value.is_empty() {
+        return Err("expected an adapter index or name".to_string());
+    }
+    Ok(match value.parse() {
+        Ok(index) => AdapterSelector::Index(index),
+        Err(_) => AdapterSelector::Name(value.to_string()),
+    })
+}
+
+fn parse_size(value: &str) -> Result<PhysicalSize<u32>, String> {
+    let (width, height) = value
+        .split_once(['x', 'X'])
+        .ok_or_else(|| "expected WIDTHxHEIGHT, e.g. 1280x720".to_string())?;
+    let parse = |v: &str| match v.trim().parse::<u32>() {
+        Ok(0) => Err("size must not be zero".to_string()),
+        Ok(n) => Ok(n),
+        Err(e) => Err(format!("{e}")),
+    };
+    Ok(PhysicalSize::new(parse(width)?, parse(height)?))
+}
+
+/// `requested` if the surface supports it, otherwise vsync
+///
+/// The `Auto` modes are always supported.
+pub fn choose_present_mode(
+    requested: wgpu::PresentMode,
+    supported: &[wgpu::PresentMode],
+) -> wgpu::PresentMode {
+    let auto = matches!(
+        requested,
+        wgpu::PresentMode::AutoVsync | wgpu::PresentMode::AutoNoVsync
+    );
+    if auto || supported.contains(&requested) {
+        requested
+    } else {
+        log::warn!(
+            "Present mode {:?} is not supported (supported: {:?}), using vsync",
+            requested,
+            supported
+        );
+        wgpu::PresentMode::AutoVsync
+    }
+}
+
+fn parse_sample_count(value: &str) -> Result<u32, String> {
+    let count = value.parse().map_err(|e| format!("{e}"))?;
+    if SAMPLE_COUNTS.contains(&count) {
+        Ok(count)
+    } else {
+        Err(format!("expected one of {SAMPLE_COUNTS:?}"))
+    }
+}
+
+/// Highest of `supported` not above `requested` (1 is always possible)
+pub fn fallback_sample_count(requested: u32, supported: &[u32]) -> u32 {
+    supported
+        .iter()
+        .copied()
+        .filter(|&count| count <= requested)
+        .max()
+        .unwrap_or(1)
+}
+
+/// A vertex type that can be uploaded to a vertex buffer
+pub trait Vertex: Pod {
+    const ATTRIBS: &'static [wgpu::VertexAttribute];
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Self>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: Self::ATTRIBS,
+        }
+    }
+}
+
+/// Per-frame shader parameters, uploaded before every frame
+pub trait Uniforms: Pod {
+    fn update(&mut self, frame: &Frame);
+
+    /// Widgets for the parameters worth tweaking live (none by default)
+    fn ui(&mut self, _ui: &mut egui::Ui) {}
+
+    /// Direction towards the directional light, for shadow mapping
+    fn light_dir(&self) -> Vec3 {
+        Vec3::Y
+    }
+}
+
+/// Pause state and speed of the animation clock
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Playback {
+    /// Animation speed multiplier
+    pub speed: f32,
+    pub paused: bool,
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Self {
+            speed: 1.0,
+            paused: false,
+        }
+    }
+}
+
+impl Playback {
+    /// Animation seconds that pass in `dt` real seconds
+    pub fn advance(&self, dt: f32) -> f32 {
+        if self.paused {
+            0.0
+        } else {
+            dt * self.speed
+        }
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    pub fn faster(&mut self) {
+        self.speed = (self.speed + SPEED_STEP).min(MAX_SPEED);
+    }
+
+    pub fn slower(&mut self) {
+        self.speed = (self.speed - SPEED_STEP).max(0.0);
+    }
+
+    pub fn reset_speed(&mut self) {
+        self.speed = 1.0;
+    }
+}
+
+/// What a frame knows when it updates the uniforms
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    /// Animation clock in seconds (advances at the playback speed, stops while
+    /// paused)
+    pub time: f32,
+    /// Surface width / height
+    pub aspect: f32,
+    /// Camera view matrix
+    pub view: Mat4,
+    /// Camera position in world space
+    pub eye: Vec3,
+    /// Camera projection (45° vertical field of view)
+    pub proj: Mat4,
+    /// World to light clip space, for shadow mapping (identity without shadows)
+    pub light_view_proj: Mat4,
+}
+
+/// Indexed triangle list, drawn with 32-bit indices
+#[derive(Debug, Clone)]
+pub struct Mesh<V> {
+    pub vertices: Vec<V>,
+    pub indices: Vec<u32>,
+}
+
+impl<V: Vertex> Mesh<V> {
+    pub fn new(vertices: Vec<V>, indices: Vec<u32>) -> Self {
+        Self { vertices, indices }
+    }
+}
+
+/// Depth buffer matching the render target size
+///
+/// Created once with the target and recreated only by `Gpu::resize`.
+pub struct DepthTexture {
+    view: wgpu::TextureView,
+}
+
+impl DepthTexture {
+    pub fn new(device: &wgpu::Device, size: PhysicalSize<u32>, sample_count: u32) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Depth Texture"),
+            size: wgpu::Extent3d {
+                width: size.width.max(1),
+                height: size.height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format: DEPTH_FORMAT,
+            // Not sampled: the GL backend cannot create multisampled depth
+            // textures that are also bindable
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        Self {
+            view: texture.create_view(&wgpu::TextureViewDescriptor::default()),
+        }
+    }
+
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+}
+
+/// Multisampled color target, resolved into the frame's texture every frame
+///
+/// Sized like `DepthTexture` and recreated with it.
+pub struct MultisampleTexture {
+    view: wgpu::TextureView,
+}
+
+impl MultisampleTexture {
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        size: PhysicalSize<u32>,
+        sample_count: u32,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Multisample Texture"),
+            size: wgpu::Extent3d {
+                width: size.width.max(1),
+                height: size.height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        Self {
+            view: texture.create_view(&wgpu::TextureViewDescriptor::default()),
+        }
+    }
+
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+}
+
+/// A `Uniforms` value together with its GPU buffer and bind group
+pub struct UniformBuffer<U> {
+    pub value: U,
+    buffer: wgpu::Buffer,
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+}
+
+impl<U: Uniforms> UniformBuffer<U> {
+    pub fn new(device: &wgpu::Device, value: U) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Uniform Buffer"),
+            contents: bytemuck::bytes_of(&value),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Uniform Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Uniform Bind Group"),
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+
+        Self {
+            value,
+            buffer,
+            layout,
+            bind_group,
+        }
+    }
+
+    /// Update the value for this frame and upload it
+    pub fn update(&mut self, queue: &wgpu::Queue, frame: &Frame) {
+        self.value.update(frame);
+        queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(&self.value));
+    }
+
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+}
+
+/// Everything a demo varies; builds the `State` once the window exists
+pub struct StateBuilder<V, U> {
+    title: String,
+    size: PhysicalSize<u32>,
+    shader_label: &'static str,
+    shader: &'static str,
+    shader_file: Option<PathBuf>,
+    /// `None` for a demo that only draws a scene
+    mesh: Option<Mesh<V>>,
+    scene: Option<Rc<Scene>>,
+    depth_prepass: bool,
+    uniforms: U,
+    camera: Camera,
+    msaa: u32,
+    clear_color: wgpu::Color,
+    blend: wgpu::BlendState,
+    cull_mode: Option<wgpu::Face>,
+    texture: Option<RgbaImage>,
+    shadows: Option<ShadowConfig>,
+    skybox: Option<[RgbaImage; 6]>,
+    gpu_options: GpuOptions,
+}
+
+impl<U: Uniforms> StateBuilder<SceneVertex, U> {
+    /// Draw `scene` instead of a mesh of its own
+    ///
+    /// No shader reads `uniforms`; they supply the light direction and the
+    /// parameters shown in the overlay.
+    pub fn scene(scene: Scene, uniforms: U) -> Self {
+        Self {
+            scene: Some(Rc::new(scene)),
+            ..Self::defaults(uniforms)
+        }
+    }
+}
+
+impl<V: Vertex, U: Uniforms> StateBuilder<V, U> {
+    /// `shader` is WGSL source with `vs_main` and `fs_main` entry points
+    pub fn new(shader: &'static str, mesh: Mesh<V>, uniforms: U) -> Self {
+        Self {
+            shader,
+            mesh: Some(mesh),
+            ..Self::defaults(uniforms)
+        }
+    }
+
+    /// Nothing to draw yet
+    fn defaults(uniforms: U) -> Self {
+        Self {
+            title: "wgpu + Rust".to_string(),
+            size: PhysicalSize::new(800, 600),
+            shader_label: "Shader",
+            shader: "",
+            shader_file: None,
+            mesh: None,
+            scene: None,
+            depth_prepass: false,
+            uniforms,
+            camera: Camera::default(),
+            msaa: 1,
+            clear_color: wgpu::Color::BLACK,
+            blend: wgpu::BlendState::REPLACE,
+            cull_mode: Some(wgpu::Face::Back),
+            texture: None,
+            shadows: None,
+            skybox: None,
+            gpu_options: GpuOptions::default(),
+        }
+    }
+
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = title.into();
+        self
+    }
+
+    pub fn size(mut self, width: u32, height: u32) -> Self {
+        self.size = PhysicalSize::new(width, height);
+        self
+    }
+
+    pub fn shader_label(mut self, label: &'static str) -> Self {
+        self.shader_label = label;
+        self
+    }
+
+    /// Load the shader from `path` and reload it whenever the file changes
+    ///
+    /// The built-in shader is used until the file compiles.
+    pub fn shader_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.shader_file = Some(path.into());
+        self
+    }
+
+    /// Whether the demo draws a mesh with a shader that `--shader` can replace
+    pub fn has_shader(&self) -> bool {
+        self.mesh.is_some()
+    }
+
+    /// With a scene, fill the depth buffer in a pass of its own before shading
+    pub fn depth_prepass(mut self, depth_prepass: bool) -> Self {
+        self.depth_prepass = depth_prepass;
+        self
+    }
+
+    pub fn camera(mut self, camera: Camera) -> Self {
+        self.camera = camera;
+        self
+    }
+
+    /// Requested MSAA sample count; lowered at startup if unsupported
+    pub fn msaa(mut self, sample_count: u32) -> Self {
+        self.msaa = sample_count;
+        self
+    }
+
+    pub fn clear_color(mut self, color: wgpu::Color) -> Self {
+        self.clear_color = color;
+        self
+    }
+
+    pub fn blend(mut self, blend: wgpu::BlendState) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    /// `None` renders both sides (e.g. for transparency)
+    pub fn cull_mode(mut self, cull_mode: Option<wgpu::Face>) -> Self {
+        self.cull_mode = cull_mode;
+        self
+    }
+
+    /// Image sampled by the shader at `@group(1)`
+    pub fn texture(mut self, image: RgbaImage) -> Self {
+        self.texture = Some(image);
+        self
+    }
+
+    pub fn has_texture(&self) -> bool {
+        self.texture.is_some()
+    }
+
+    /// Cast shadows onto a ground plane; the shader needs `vs_shadow`
+    pub fn shadows(mut self, config: ShadowConfig) -> Self {
+        self.shadows = Some(config);
+        self
+    }
+
+    /// Draw a cubemap sky and bind it for reflections, see `skybox`
+    pub fn skybox(mut self, faces: [RgbaImage; 6]) -> Self {
+        self.skybox = Some(faces);
+        self
+    }
+
+    pub fn has_skybox(&self) -> bool {
+        self.skybox.is_some()
+    }
+
+    /// Backend, adapter and present mode; `size` is ignored (see `size`)
+    pub fn gpu_options(mut self, options: GpuOptions) -> Self {
+        self.gpu_options = options;
+        self
+    }
+
+    pub async fn build(&self, window: Arc<Window>) -> Result<State<U>, String> {
+        let size = window.inner_size();
+
+        let backends = self.gpu_options.backends();
+        let instance = instance(backends);
+        let surface = instance.create_surface(window.clone()).unwrap();
+        let adapter = request_adapter(
+            &instance,
+            backends,
+            Some(&surface),
+            self.gpu_options.adapter.as_ref(),
+        )
+        .await?;
+        let (device, queue, features) = request_device(&adapter).await;
+
+        let surface_caps = surface.get_capabilities(&adapter);
+        let surface_format = surface_caps
+            .formats
+            .iter()
+            .copied()
+            .find(|f| f.is_srgb())
+            .unwrap_or(surface_caps.formats[0]);
+
+        // Screenshots copy from the surface texture
+        let usage = wgpu::TextureUsages::RENDER_ATTACHMENT
+            | (surface_caps.usages & wgpu::TextureUsages::COPY_SRC);
+        let config = wgpu::SurfaceConfiguration {
+            usage,
+            format: surface_format,
+            width: size.width.max(1),
+            height: size.height.max(1),
+            present_mode: choose_present_mode(
+                self.gpu_options.present_mode.into(),
+                &surface_caps.present_modes,
+            ),
+            alpha_mode: surface_caps.alpha_modes[0],
+            view_formats: vec![],
+            desired_maximum_frame_latency: MAX_FRAME_LATENCY,
+        };
+        surface.configure(&device, &config);
+
+        let overlay = Overlay::new(&device, config.format, &window);
+        let mut gpu = self.gpu(&adapter, device, queue, features, config.format, size);
+        let shader_watcher = self.shader_file.as_ref().and_then(|path| {
+            load_shader(&mut gpu, path);
+            ShaderWatcher::new(path)
+                .inspect_err(|e| log::warn!("Cannot watch {}: {}", path.display(), e))
+                .ok()
+        });
+
+        let stats = FrameStats::new(gpu.timer().is_some());
+
+        Ok(State {
+            surface,
+            config,
+            gpu,
+            camera: self.camera.clone(),
+            time: 0.0,
+            playback: Playback::default(),
+            last_frame: Instant::now(),
+            stats,
+            overlay,
+            shader_watcher,
+            screenshot_requested: false,
+            exit_requested: false,
+            window,
+        })
+    }
+
+    /// Build for offscreen rendering at the builder's size, without a window
+    pub async fn build_headless(&self) -> Result<Headless<U>, String> {
+        let backends = self.gpu_options.backends();
+        let instance = instance(backends);
+        let adapter =
+            request_adapter(&instance, backends, None, self.gpu_options.adapter.as_ref()).await?;
+        let (device, queue, features) = request_device(&adapter).await;
+        let mut gpu = self.gpu(
+            &adapter,
+            device,
+            queue,
+            features,
+            headless::FORMAT,
+            self.size,
+        );
+        if let Some(path) = &self.shader_file {
+            load_shader(&mut gpu, path);
+        }
+        Ok(Headless::new(gpu, self.camera.clone()))
+    }
+
+    /// Pipeline, buffers and render targets drawing into `format` at `size`
+    fn gpu(
+        &self,
+        adapter: &wgpu::Adapter,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        features: wgpu::Features,
+        format: wgpu::TextureFormat,
+        size: PhysicalSize<u32>,
+    ) -> Gpu<U> {
+        let sample_count = self.sample_count(adapter, features, format);
+
+        let uniforms = UniformBuffer::new(&device, self.uniforms);
+
+        let shadows = self.shadows.map(|config| {
+            Shadows::new(
+                &device,
+                config,
+                &TargetDesc {
+                    format,
+                    sample_count,
+                },
+            )
+        });
+
+        let skybox = self.skybox.as_ref().map(|faces| {
+            Skybox::new(
+                &device,
+                &queue,
+                faces,
+                &TargetDesc {
+                    format,
+                    sample_count,
+                },
+            )
+        });
+
+        // Group 0 is the uniforms, then the texture, the shadow map and the
+        // environment map, in the order of `SHADOW_GROUP` and `ENV_GROUP`. A
+        // group the demo does not use is bound empty if a later one is used.
+        let texture_layout = Texture::bind_group_layout(&device);
+        let empty_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Empty Bind Group Layout"),
+            entries: &[],
+        });
+        let empty_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Empty Bind Group"),
+            layout: &empty_layout,
+            entries: &[],
+        });
+        let groups = [
+            self.texture.as_ref().map(|image| {
+                let texture = Texture::from_image(&device, &queue, image, "Diffuse Texture");
+                (
+                    &texture_layout,
+                    texture.bind_group(&device, &texture_layout),
+                )
+            }),
+            shadows
+                .as_ref()
+                .map(|shadows| (shadows.layout(), shadows.bind_group().clone())),
+            skybox
+                .as_ref()
+                .map(|skybox| (skybox.layout(), skybox.bind_group().clone())),
+        ];
+        let used = groups
+            .iter()
+            .rposition(Option::is_some)
+            .map_or(0, |i| i + 1);
+        let mut bind_group_layouts = vec![uniforms.layout()];
+        let mut bind_groups = Vec::new();
+        for group in groups.into_iter().take(used) {
+            let (layout, bind_group) =
+                group.unwrap_or_else(|| (&empty_layout, empty_bind_group.clone()));
+            bind_group_layouts.push(layout);
+            bind_groups.push(bind_group);
+        }
+
+        let mesh = self.mesh.as_ref().map(|mesh| {
+            let pipeline_desc = PipelineDesc {
+                shader_label: self.shader_label,
+                layout: device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("Render Pipeline Layout"),
+                    bind_group_layouts: &bind_group_layouts,
+                    push_constant_ranges: &[],
+                }),
+                vertex_layout: V::desc(),
+                format,
+                sample_count,
+                blend: self.blend,
+                cull_mode: self.cull_mode,
+                env_mip_level_count: skybox.as_ref().map(Skybox::mip_level_count),
+            };
+            let pipelines =
+                pipeline_desc.build(&device, self.shader, uniforms.layout(), shadows.as_ref());
+
+            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Vertex Buffer"),
+                contents: bytemuck::cast_slice(&mesh.vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+
+            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Index Buffer"),
+                contents: bytemuck::cast_slice(&mesh.indices),
+                usage: wgpu::BufferUsages::INDEX,
+            });
+
+            MeshDraw {
+                pipeline_desc,
+                pipelines,
+                vertex_buffer,
+                index_buffer,
+                num_indices: mesh.indices.len() as u32,
+                bind_groups,
+            }
+        });
+
+        let scene = self.scene.as_ref().map(|scene| {
+            let renderer = SceneRenderer::new(
+                &device,
+                scene,
+                format,
+                sample_count,
+                self.depth_prepass,
+                MAX_FRAME_LATENCY,
+            );
+            (Rc::clone(scene), renderer)
+        });
+
+        let depth_texture = DepthTexture::new(&device, size, sample_count);
+        let msaa_texture = (sample_count > 1)
+            .then(|| MultisampleTexture::new(&device, format, size, sample_count));
+        let timer = GpuTimer::new(&device, &queue);
+
+        Gpu {
+            device,
+            queue,
+            format,
+            size,
+            mesh,
+            scene,
+            uniforms,
+            clear_color: self.clear_color,
+            sample_count,
+            depth_texture,
+            msaa_texture,
+            shadows,
+            skybox,
+            timer,
+        }
+    }
+
+    /// `msaa`, or the best count below it that color and depth both support
+    fn sample_count(
+        &self,
+        adapter: &wgpu::Adapter,
+        features: wgpu::Features,
+        color_format: wgpu::TextureFormat,
+    ) -> u32 {
+        let flags = |format: wgpu::TextureFormat| {
+            if features.contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES) {
+                adapter.get_texture_format_features(format).flags
+            } else {
+                format.guaranteed_format_features(features).flags
+            }
+        };
+        let (color, depth) = (flags(color_format), flags(DEPTH_FORMAT));
+        let supported: Vec<u32> = SAMPLE_COUNTS
+            .into_iter()
+            .filter(|&count| {
+                color.sample_count_supported(count) && depth.sample_count_supported(count)
+            })
+            .collect();
+
+        let sample_count = fallback_sample_count(self.msaa, &supported);
+        if sample_count != self.msaa {
+            log::warn!(
+                "{}x MSAA is not supported (supported: {:?}), using {}x",
+                self.msaa,
+                supported,
+                sample_count
+            );
+        }
+        log::info!("Using {}x MSAA", sample_count);
+        sample_count
+    }
+}
+
+/// Rebuild `gpu`'s pipelines from the WGSL file at `path`, logging the outcome
+fn load_shader<U: Uniforms>(gpu: &mut Gpu<U>, path: &Path) {
+    let result = std::fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|source| gpu.reload_shader(&source).map_err(|e| e.to_string()));
+    match result {
+        Ok(()) => log::info!("Loaded shader {}", path.display()),
+        Err(e) => log::error!(
+            "Shader {} failed, keeping the last good pipeline: {}",
+            path.display(),
+            e
+        ),
+    }
+}
+
+/// Everything besides the shader that the pipelines are built from
+///
+/// Kept by `Gpu` so they can be rebuilt when the shader changes.
+struct PipelineDesc {
+    shader_label: &'static str,
+    layout: wgpu::PipelineLayout,
+    vertex_layout: wgpu::VertexBufferLayout<'static>,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+    blend: wgpu::BlendState,
+    cull_mode: Option<wgpu::Face>,
+    /// Mip levels of the environment map, when the demo has a skybox
+    env_mip_level_count: Option<u32>,
+}
+
+/// The pipelines built from the demo's shader
+struct Pipelines {
+    render: wgpu::RenderPipeline,
+    /// Shadow pass, when the demo casts shadows
+    shadow: Option<wgpu::RenderPipeline>,
+}
+
+impl PipelineDesc {
+    /// Compile `source` and build the pipelines that use it
+    ///
+    /// Errors are reported through the device like any other validation error.
+    fn build(
+        &self,
+        device: &wgpu::Device,
+        source: &str,
+        uniforms_layout: &wgpu::BindGroupLayout,
+        shadows: Option<&Shadows>,
+    ) -> Pipelines {
+        let source = match shadows {
+            Some(_) => shadow::with_shadow_wgsl(source),
+            None => source.to_string(),
+        };
+        let source = match self.env_mip_level_count {
+            Some(mip_level_count) => skybox::with_env_wgsl(&source, mip_level_count),
+            None => source,
+        };
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(self.shader_label),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+
+        let render = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Render Pipeline"),
+            layout: Some(&self.layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: std::slice::from_ref(&self.vertex_layout),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: self.format,
+                    blend: Some(self.blend),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: self.cull_mode,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: self.sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        });
+
+        let shadow = shadows.map(|shadows| {
+            shadows.create_pipeline(
+                device,
+                &shader,
+                self.vertex_layout.clone(),
+                uniforms_layout,
+                self.cull_mode,
+            )
+        });
+
+        Pipelines { render, shadow }
+    }
+}
+
+/// Instance on `backends` (see `GpuOptions::backends`)
+pub fn instance(backends: wgpu::Backends) -> wgpu::Instance {
+    wgpu::Instance::new(&wgpu::InstanceDescriptor {
+        backends,
+        ..Default::default()
+    })
+}
+
+/// The adapter picked by `selector`, or the high-performance one by default
+///
+/// With `compatible_surface`, the adapter must be able to present to it.
+pub async fn request_adapter(
+    instance: &wgpu::Instance,
+    backends: wgpu::Backends,
+    compatible_surface: Option<&wgpu::Surface<'_>>,
+    selector: Option<&AdapterSelector>,
+) -> Result<wgpu::Adapter, String> {
+    let adapter = match selector {
+        Some(selector) => {
+            let mut adapters = instance.enumerate_adapters(backends);
+            let names: Vec<String> = adapters.iter().map(|a| a.get_info().name).collect();
+            let index = selector
+                .find(&names)
+                .ok_or_else(|| format!("no adapter matches {selector:?} (see --list-adapters)"))?;
+            let adapter = adapters.swap_remove(index);
+            if let Some(surface) = compatible_surface {
+                if !adapter.is_surface_supported(surface) {
+                    return Err(format!(
+                        "adapter {} cannot present to the window",
+                        names[index]
+                    ));
+                }
+            }
+            adapter
+        }
+        None => instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                compatible_surface,
+                force_fallback_adapter: false,
+            })
+            .await
+            .map_err(|e| format!("no suitable GPU adapter: {e}"))?,
+    };
+
+    log::info!("Using adapter: {:?}", adapter.get_info());
+    Ok(adapter)
+}
+
+/// Print the adapters on `backends`, numbered for `--adapter`
+pub fn list_adapters(backends: wgpu::Backends) {
+    let adapters = instance(backends).enumerate_adapters(backends);
+    if adapters.is_empty() {
+        println!("No adapters found");
+    }
+    for (index, adapter) in adapters.iter().enumerate() {
+        let info = adapter.get_info();
+        println!(
+            "{index}: {} ({:?}, {:?}, driver {} {})",
+            info.name, info.backend, info.device_type, info.driver, info.driver_info
+        );
+    }
+}
+
+/// The device and the optional features it was created with
+pub async fn request_device(
+    adapter: &wgpu::Adapter,
+) -> (wgpu::Device, wgpu::Queue, wgpu::Features) {
+    // Sample counts beyond the guaranteed 1 and 4, and GPU frame timing
+    let features = adapter.features()
+        & (wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+            | wgpu::Features::TIMESTAMP_QUERY);
+
+    let (device, queue) = adapter
+        .request_device(&wgpu::DeviceDescriptor {
+            label: Some("Main Device"),
+            required_features: features,
+            required_limits: wgpu::Limits::default(),
+            experimental_features: wgpu::ExperimentalFeatures::default(),
+            memory_hints: wgpu::MemoryHints::Performance,
+            trace: wgpu::Trace::Off,
+        })
+        .await
+        .expect("Failed to create device");
+
+    (device, queue, features)
+}
+
+/// The demo's own mesh, with the pipelines built from its shader
+struct MeshDraw {
+    pipeline_desc: PipelineDesc,
+    pipelines: Pipelines,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    num_indices: u32,
+    /// Bind groups 1.., including the shadow and environment maps
+    bind_groups: Vec<wgpu::BindGroup>,
+}
+
+impl MeshDraw {
+    /// Draw the mesh with the pipeline and bind groups already set
+    fn draw(&self, pass: &mut wgpu::RenderPass<'_>) {
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        pass.draw_indexed(0..self.num_indices, 0, 0..1);
+    }
+}
+
+/// Pipeline, buffers and render targets of a demo
+///
+/// Draws a frame into any color view of `format()` and `size()`; the window
+/// and the headless renderer only differ in where that view comes from.
+pub struct Gpu<U> {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    format: wgpu::TextureFormat,
+    size: PhysicalSize<u32>,
+    mesh: Option<MeshDraw>,
+    scene: Option<(Rc<Scene>, SceneRenderer)>,
+    uniforms: UniformBuffer<U>,
+    clear_color: wgpu::Color,
+    sample_count: u32,
+    depth_texture: DepthTexture,
+    msaa_texture: Option<MultisampleTexture>,
+    shadows: Option<Shadows>,
+    skybox: Option<Skybox>,
+    /// Times `draw` when the device supports timestamp queries
+    timer: Option<GpuTimer>,
+}
+
+impl<U: Uniforms> Gpu<U> {
+    pub fn device(&self) -> &wgpu::Device {
+        &self.device
+    }
+
+    pub fn queue(&self) -> &wgpu::Queue {
+        &self.queue
+    }
+
+    pub fn format(&self) -> wgpu::TextureFormat {
+        self.format
+    }
+
+    pub fn size(&self) -> PhysicalSize<u32> {
+        self.size
+    }
+
+    pub fn timer(&self) -> Option<&GpuTimer> {
+        self.timer.as_ref()
+    }
+
+    /// Objects of the scene drawn and culled by the last `update`
+    pub fn scene_stats(&self) -> Option<CullStats> {
+        self.scene.as_ref().map(|(_, renderer)| renderer.stats())
+    }
+
+    /// Recreate the targets sized to the frame
+    pub fn resize(&mut self, size: PhysicalSize<u32>) {
+        self.size = size;
+        self.depth_texture = DepthTexture::new(&self.device, size, self.sample_count);
+        if self.msaa_texture.is_some() {
+            self.msaa_texture = Some(MultisampleTexture::new(
+                &self.device,
+                self.format,
+                size,
+                self.sample_count,
+            ));
+        }
+    }
+
+    /// Rebuild the pipelines from new WGSL source
+    ///
+    /// If the source does not compile or does not fit the pipeline layout, the
+    /// error is returned and the current pipelines stay in use. A demo without
+    /// a mesh has no shader to reload.
+    pub fn reload_shader(&mut self, source: &str) -> Result<(), wgpu::Error> {
+        let Some(mesh) = &mut self.mesh else {
+            return Ok(());
+        };
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let pipelines = mesh.pipeline_desc.build(
+            &self.device,
+            source,
+            self.uniforms.layout(),
+            self.shadows.as_ref(),
+        );
+        match pollster::block_on(self.device.pop_error_scope()) {
+            Some(error) => Err(error),
+            None => {
+                mesh.pipelines = pipelines;
+                Ok(())
+            }
+        }
+    }
+
+    /// Update and upload the uniforms for a frame at `time` seen by `camera`
+    pub fn update(&mut self, time: f32, camera: &Camera) {
+        let aspect = self.size.width.max(1) as f32 / self.size.height.max(1) as f32;
+        let light_dir = self.uniforms.value.light_dir();
+        let frame = Frame {
+            time,
+            aspect,
+            view: camera.view(),
+            eye: camera.eye(),
+            proj: Mat4::perspective_rh(45.0_f32.to_radians(), aspect, 0.1, 100.0),
+            light_view_proj: self.shadows.as_ref().map_or(Mat4::IDENTITY, |shadows| {
+                shadows.config().light_view_proj(light_dir)
+            }),
+        };
+        self.uniforms.update(&self.queue, &frame);
+        if let Some(shadows) = &self.shadows {
+            shadows.update(&self.queue, &frame, light_dir);
+        }
+        if let Some(skybox) = &self.skybox {
+            skybox.update(&self.queue, &frame);
+        }
+        if let Some((scene, renderer)) = &mut self.scene {
+            renderer.update(&self.device, &self.queue, scene, &frame, light_dir);
+        }
+    }
+
+    /// Record the demo's render passes into `view`
+    ///
+    /// With a `timer`, the passes are timed and the timestamps resolved.
+    pub fn draw(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        // The first pass writes the start timestamp, the main pass the end one
+        let mut started = false;
+
+        let shadow_pass = self.shadows.as_ref().zip(
+            self.mesh
+                .as_ref()
+                .and_then(|mesh| Some((mesh, mesh.pipelines.shadow.as_ref()?))),
+        );
+        if let Some((shadows, (mesh, pipeline))) = shadow_pass {
+            let timestamp_writes = self.timer.as_ref().map(|t| t.timestamp_writes(true, false));
+            let mut pass = shadows.begin_shadow_pass(encoder, timestamp_writes);
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, self.uniforms.bind_group(), &[]);
+            mesh.draw(&mut pass);
+            started = true;
+        }
+
+        let prepass = self
+            .scene
+            .as_ref()
+            .map(|(_, renderer)| renderer)
+            .filter(|renderer| renderer.has_depth_prepass());
+        if let Some(renderer) = prepass {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Depth Prepass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: self.depth_texture.view(),
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: self
+                    .timer
+                    .as_ref()
+                    .filter(|_| !started)
+                    .map(|t| t.timestamp_writes(true, false)),
+                occlusion_query_set: None,
+            });
+            renderer.draw_depth(&mut pass);
+            started = true;
+        }
+
+        // With MSAA, draw into the multisampled target and resolve into the frame
+        let (target, resolve_target, store) = match &self.msaa_texture {
+            Some(msaa) => (msaa.view(), Some(view), wgpu::StoreOp::Discard),
+            None => (view, None, wgpu::StoreOp::Store),
+        };
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(self.clear_color),
+                    store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: self.depth_texture.view(),
+                depth_ops: Some(wgpu::Operations {
+                    load: match prepass {
+                        Some(_) => wgpu::LoadOp::Load,
+                        None => wgpu::LoadOp::Clear(1.0),
+                    },
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: self
+                .timer
+                .as_ref()
+                .map(|t| t.timestamp_writes(!started, true)),
+            occlusion_query_set: None,
+        });
+
+        if let Some(skybox) = &self.skybox {
+            skybox.draw(&mut render_pass);
+        }
+        // The ground and the scene are opaque, so they go first for blended
+        // meshes
+        if let Some(shadows) = &self.shadows {
+            shadows.draw_ground(&mut render_pass);
+        }
+        if let Some((_, renderer)) = &self.scene {
+            renderer.draw(&mut render_pass);
+        }
+
+        if let Some(mesh) = &self.mesh {
+            render_pass.set_pipeline(&mesh.pipelines.render);
+            render_pass.set_bind_group(0, self.uniforms.bind_group(), &[]);
+            for (index, bind_group) in mesh.bind_groups.iter().enumerate() {
+                render_pass.set_bind_group(index as u32 + 1, bind_group, &[]);
+            }
+            mesh.draw(&mut render_pass);
+        }
+        drop(render_pass);
+
+        if let Some(timer) = &self.timer {
+            timer.resolve(encoder);
+        }
+    }
+}
+
+/// GPU state of a running demo
+pub struct State<U> {
+    surface: wgpu::Surface<'static>,
+    config: wgpu::SurfaceConfiguration,
+    gpu: Gpu<U>,
+    camera: Camera,
+    time: f32,
+    playback: Playback,
+    last_frame: Instant,
+    stats: FrameStats,
+    overlay: Overlay,
+    /// Set when the shader comes from a file
+    shader_watcher: Option<ShaderWatcher>,
+    /// F12 was pressed; the next frame is saved
+    screenshot_requested: bool,
+    /// Escape was pressed; the app closes the window
+    exit_requested: bool,
+    window: Arc<Window>,
+}
+
+impl<U: Uniforms> State<U> {
+    pub fn window(&self) -> &Window {
+        &self.window
+    }
+
+    pub fn size(&self) -> PhysicalSize<u32> {
+        self.gpu.size()
+    }
+
+    /// Whether Escape asked to quit
+    pub fn exit_requested(&self) -> bool {
+        self.exit_requested
+    }
+
+    /// Offer a window event to the overlay, the camera, then the hotkeys
+    /// Returns whether any of them used it.
+    pub fn handle_event(&mut self, event: &WindowEvent) -> bool {
+        self.overlay.handle_event(&self.window, event)
+            || self.camera.handle_event(event)
+            || self.handle_key(event)
+    }
+
+    fn handle_key(&mut self, event: &WindowEvent) -> bool {
+        let WindowEvent::KeyboardInput {
+            event:
+                KeyEvent {
+                    physical_key: PhysicalKey::Code(code),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                },
+            ..
+        } = event
+        else {
+            return false;
+        };
+        match code {
+            KeyCode::F12 => self.screenshot_requested = true,
+            KeyCode::Escape => self.exit_requested = true,
+            KeyCode::F11 => self.toggle_fullscreen(),
+            KeyCode::Space => self.playback.toggle_pause(),
+            KeyCode::Equal | KeyCode::NumpadAdd => self.playback.faster(),
+            KeyCode::Minus | KeyCode::NumpadSubtract => self.playback.slower(),
+            KeyCode::Digit0 | KeyCode::Numpad0 => self.playback.reset_speed(),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Switch between the window and borderless fullscreen on its monitor
+    fn toggle_fullscreen(&self) {
+        let fullscreen = match self.window.fullscreen() {
+            Some(_) => None,
+            None => Some(Fullscreen::Borderless(None)),
+        };
+        self.window.set_fullscreen(fullscreen);
+    }
+
+    /// Reconfigure the surface and everything sized to it
+    ///
+    /// The one path for window resizes and for a lost or outdated surface.
+    /// A zero size (minimized window) keeps the current configuration.
+    pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
+        if new_size.width == 0 || new_size.height == 0 {
+            return;
+        }
+        self.config.width = new_size.width;
+        self.config.height = new_size.height;
+        self.surface.configure(self.gpu.device(), &self.config);
+        self.gpu.resize(new_size);
+    }
+
+    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        if let Some(watcher) = &self.shader_watcher {
+            if watcher.changed() {
+                load_shader(&mut self.gpu, watcher.path());
+            }
+        }
+
+        let now = Instant::now();
+        let dt = now.duration_since(self.last_frame).as_secs_f32();
+        self.last_frame = now;
+        self.camera.update(dt);
+        self.time += self.playback.advance(dt);
+        self.stats.cpu.push(dt);
+        if let Some(seconds) = self.gpu.timer().and_then(|t| t.read(self.gpu.device())) {
+            self.stats.gpu.push(seconds);
+        }
+        self.gpu.update(self.time, &self.camera);
+
+        let output = self.surface.get_current_texture()?;
+        let view = output
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        let mut encoder =
+            self.gpu
+                .device()
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Render Encoder"),
+                });
+
+        self.gpu.draw(&mut encoder, &view);
+
+        let stats = &self.stats;
+        let scene_stats = self.gpu.scene_stats();
+        let playback = &mut self.playback;
+        let uniforms = &mut self.gpu.uniforms.value;
+        self.overlay.render(
+            &self.gpu.device,
+            &self.gpu.queue,
+            &mut encoder,
+            &self.window,
+            &view,
+            |ctx| {
+                egui::Window::new("Parameters")
+                    .default_width(240.0)
+                    .show(ctx, |ui| {
+                        stats.ui(ui);
+                        if let Some(scene) = scene_stats {
+                            ui.label(format!(
+                                "{} objects drawn, {} culled",
+                                scene.drawn, scene.culled
+                            ));
+                        }
+                        ui.horizontal(|ui| {
+                            ui.add(
+                                egui::Slider::new(&mut playback.speed, 0.0..=MAX_SPEED)
+                                    .text("rotation speed"),
+                            );
+                            ui.checkbox(&mut playback.paused, "paused");
+                        });
+                        ui.separator();
+                        uniforms.ui(ui);
+                    });
+            },
+        );
+
+        let capture = std::mem::take(&mut self.screenshot_requested)
+            .then(|| Capture::copy(self.gpu.device(), &mut encoder, &output.texture));
+
+        self.gpu.queue().submit(std::iter::once(encoder.finish()));
+        if let Some(timer) = self.gpu.timer() {
+            timer.after_submit();
+        }
+        output.present();
+
+        match capture.map(|capture| capture.and_then(|c| c.read(self.gpu.device()))) {
+            Some(Ok(image)) => screenshot::save(image, Path::new(screenshot::DIR)),
+            Some(Err(e)) => log::error!("Screenshot failed: {}", e),
+            None => {}
+        }
+
+        Ok(())
+    }
+}
+
+/// winit application driving one demo window
+pub struct App<V, U> {
+    builder: StateBuilder<V, U>,
+    state: Option<State<U>>,
+}
+
+impl<V: Vertex, U: Uniforms> App<V, U> {
+    pub fn new(builder: StateBuilder<V, U>) -> Self {
+        Self {
+            builder,
+            state: None,
+        }
+    }
+}
+
+impl<V: Vertex, U: Uniforms> ApplicationHandler for App<V, U> {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        let window_attributes = Window::default_attributes()
+            .with_title(self.builder.title.clone())
+            .with_inner_size(self.builder.size);
+
+        let window = Arc::new(event_loop.create_window(window_attributes).unwrap());
+        match pollster::block_on(self.builder.build(window)) {
+            Ok(state) => self.state = Some(state),
+            Err(e) => {
+                log::error!("{}", e);
+                event_loop.exit();
+            }
+        }
+    }
+
+    fn window_event(
+        &mut self,
+        event_loop: &ActiveEventLoop,
+        _window_id: WindowId,
+        event: WindowEvent,
+    ) {
+        let Some(state) = self.state.as_mut() else {
+            return;
+        };
+
+        if state.handle_event(&event) {
+            if state.exit_requested() {
+                log::info!("Escape pressed, exiting...");
+                event_loop.exit();
+            }
+            return;
+        }
+
+        match event {
+            WindowEvent::CloseRequested => {
+                log::info!("Close requested, exiting...");
+                event_loop.exit();
+            }
+            WindowEvent::Resized(physical_size) => {
+                log::info!("Resized to {:?}", physical_size);
+                state.resize(physical_size);
+            }
+            WindowEvent::RedrawRequested => {
+                match state.render() {
+                    Ok(_) => {}
+                    // The surface no longer matches the window: configure it again
+                    Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                        let size = state.window().inner_size();
+                        log::info!("Surface lost or outdated, reconfiguring at {:?}", size);
+                        state.resize(size);
+                    }
+                    Err(wgpu::SurfaceError::OutOfMemory) => {
+                        log::error!("Out of memory!");
+                        event_loop.exit();
+                    }
+                    Err(e) => log::error!("Render error: {:?}", e),
+                }
+                state.window().request_redraw();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Open the window and run the demo until it is closed
+///
+/// Command line `Options` override the builder's settings. With `--headless`
+/// the frames are written to PNG files instead and no window is opened.
+pub fn run<V: Vertex, U: Uniforms>(builder: StateBuilder<V, U>) {
+    run_with(builder, Options::parse());
+}
+
+/// `run` with `options` the demo parsed itself, for a demo whose command line
+/// flattens `Options` into options of its own
+pub fn run_with<V: Vertex, U: Uniforms>(builder: StateBuilder<V, U>, options: Options) {
+    if options.gpu.list_adapters {
+        list_adapters(options.gpu.backends());
+        return;
+    }
+
+    let mut builder = builder.msaa(options.msaa);
+    if let Some(size) = options.gpu.size {
+        builder = builder.size(size.width, size.height);
+    }
+    builder = builder.gpu_options(options.gpu);
+
+    if let Some(path) = &options.texture {
+        if !builder.has_texture() {
+            log::warn!(
+                "This demo does not use a texture, ignoring {}",
+                path.display()
+            );
+        } else {
+            match texture::load(path) {
+                Ok(image) => builder = builder.texture(image),
+                Err(e) => log::error!("Failed to load {}: {}", path.display(), e),
+            }
+        }
+    }
+
+    if let Some(dir) = &options.skybox {
+        if !builder.has_skybox() {
+            log::warn!("This demo has no sky, ignoring {}", dir.display());
+        } else {
+            match skybox::load_faces(dir) {
+                Ok(faces) => builder = builder.skybox(faces),
+                Err(e) => log::error!("Failed to load the skybox: {}", e),
+            }
+        }
+    }
+
+    if let Some(path) = options.shader {
+        if !builder.has_shader() {
+            log::warn!(
+                "This demo has no shader of its own, ignoring {}",
+                path.display()
+            );
+        } else {
+            builder = builder.shader_file(path);
+        }
+    }
+
+    if options.headless {
+        let result = pollster::block_on(builder.build_headless()).and_then(|mut headless| {
+            headless
+                .render_frames(options.frames, options.fps, &options.out)
+                .map_err(|e| e.to_string())
+        });
+        if let Err(e) = result {
+            log::error!("Headless rendering failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let event_loop = EventLoop::new().unwrap();
+    event_loop.set_control_flow(ControlFlow::Poll);
+
+    let mut app = App::new(builder);
+    event_loop.run_app(&mut app).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_playback() {
+        let mut playback = Playback::default();
+        assert_eq!(playback.advance(0.5), 0.5);
+
+        playback.faster();
+        assert_eq!(playback.advance(1.0), 1.25);
+        playback.toggle_pause();
+        assert_eq!(playback.advance(1.0), 0.0);
+        playback.toggle_pause();
+
+        for _ in 0..100 {
+            playback.faster();
+        }
+        assert_eq!(playback.speed, MAX_SPEED);
+        for _ in 0..100 {
+            playback.slower();
+        }
+        assert_eq!(playback.speed, 0.0);
+        playback.reset_speed();
+        assert_eq!(playback, Playback::default());
+    }
+
+    #[test]
+    fn test_fallback_sample_count() {
+        assert_eq!(fallback_sample_count(8, &[1, 2, 4, 8]), 8);
+        assert_eq!(fallback_sample_count(8, &[1, 4]), 4);
+        assert_eq!(fallback_sample_count(16, &[1, 2, 4, 8]), 8);
+        assert_eq!(fallback_sample_count(4, &[]), 1);
+    }
+
+    #[test]
+    fn test_msaa_option() {
+        assert_eq!(Options::parse_from(["demo"]).msaa, 4);
+        assert_eq!(Options::parse_from(["demo", "--msaa", "8"]).msaa, 8);
+        assert!(Options::try_parse_from(["demo", "--msaa", "3"]).is_err());
+    }
+
+    #[test]
+    fn test_headless_options() {
+        let options = Options::parse_from(["demo"]);
+        assert!(!options.headless);
+        assert_eq!(options.frames, 1);
+
+        let options =
+            Options::parse_from(["demo", "--headless", "--frames", "90", "--out", "turntable"]);
+        assert!(options.headless);
+        assert_eq!(options.frames, 90);
+        assert_eq!(options.out, PathBuf::from("turntable"));
+
+        // Only meaningful without a window
+        assert!(Options::try_parse_from(["demo", "--frames", "90"]).is_err());
+    }
+
+    #[test]
+    fn test_gpu_options() {
+        let options = Options::parse_from(["demo"]).gpu;
+        assert_eq!(
+            options.backends(),
+            wgpu::Backends::VULKAN | wgpu::Backends::GL
+        );
+        assert_eq!(options.present_mode, PresentMode::AutoVsync);
+        assert_eq!((options.adapter, options.size), (None, None));
+
+        let options = Options::parse_from([
+            "demo",
+            "--backend",
+            "gl",
+            "--present-mode",
+            "immediate",
+            "--adapter",
+            "1",
+            "--size",
+            "1280x720",
+        ])
+        .gpu;
+        assert_eq!(options.backends(), wgpu::Backends::GL);
+        assert_eq!(options.present_mode, PresentMode::Immediate);
+        assert_eq!(options.adapter, Some(AdapterSelector::Index(1)));
+        assert_eq!(options.size, Some(PhysicalSize::new(1280, 720)));
+
+        assert!(Options::try_parse_from(["demo", "--backend", "webgpu"]).is_err());
+        assert!(Options::try_parse_from(["demo", "--size", "1280"]).is_err());
+        assert!(Options::try_parse_from(["demo", "--size", "0x720"]).is_err());
+    }
+
+    #[test]
+    fn test_adapter_selector() {
+        let names = [
+            "NVIDIA GeForce RTX 3080",
+            "llvmpipe (LLVM 17.0.6, 256 bits)",
+        ]
+        .map(String::from);
+        assert_eq!(parse_adapter("1"), Ok(AdapterSelector::Index(1)));
+        assert_eq!(AdapterSelector::Index(1).find(&names), Some(1));
+        assert_eq!(AdapterSelector::Index(2).find(&names), None);
+        assert_eq!(parse_adapter("rtx").unwrap().find(&names), Some(0));
+        assert_eq!(parse_adapter("LLVMpipe").unwrap().find(&names), Some(1));
+        assert_eq!(parse_adapter("radeon").unwrap().find(&names), None);
+        assert!(parse_adapter("").is_err());
+    }
+
+    #[test]
+    fn test_choose_present_mode() {
+        use wgpu::PresentMode::*;
+        assert_eq!(choose_present_mode(Mailbox, &[Fifo, Mailbox]), Mailbox);
+        assert_eq!(choose_present_mode(Immediate, &[Fifo]), AutoVsync);
+        assert_eq!(choose_present_mode(AutoNoVsync, &[Fifo]), AutoNoVsync);
+    }
+
+    #[test]
+    fn test_skybox_option() {
+        assert_eq!(Options::parse_from(["demo"]).skybox, None);
+        let options = Options::parse_from(["demo", "--skybox", "assets/sky"]);
+        assert_eq!(options.skybox, Some(PathBuf::from("assets/sky")));
+    }
+
+    #[test]
+    fn test_shader_option() {
+        assert_eq!(Options::parse_from(["demo"]).shader, None);
+        let options = Options::parse_from(["demo", "--shader", "src/shader.wgsl"]);
+        assert_eq!(options.shader, Some(PathBuf::from("src/shader.wgsl")));
+    }
+}
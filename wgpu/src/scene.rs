@@ -0,0 +1,687 @@
+//! Scene graph: several meshes with their own materials and transforms
+//!
+//! A `Scene` owns meshes, materials and a tree of `Node`s. Every node has a
+//! transform relative to its parent and an optional animation on top of it;
+//! nodes that draw something name a mesh and a material. `Scene::draws` walks
+//! the tree for a point in time and returns the world transform of every
+//! drawn node, so moving a parent moves its whole subtree.
+//!
+//! `SceneRenderer` draws the result with one pipeline. The camera and light
+//! are bound at `@group(0)`; the per-object model matrix and material share a
+//! single uniform buffer at `@group(1)` that holds one slot per draw, and each
+//! draw selects its slot with a dynamic offset. A frame therefore costs one
+//! buffer upload however many objects the scene has. Both buffers are
+//! `UniformRing`s with a region per frame in flight, so that upload never
+//! lands on uniforms a queued frame is still drawing with.
+//!
+//! Draws whose bounding sphere lies outside the camera's view frustum are
+//! dropped on the CPU before the upload (see `frustum`); `CullStats` counts
+//! what was drawn and what was culled. With a depth prepass the visible draws
+//! first fill the depth buffer in a depth-only pass (`draw_depth`), so the
+//! shaded pass runs the fragment shader once per pixel however much the
+//! objects overlap.
+
+use crate::frustum::{Frustum, Sphere};
+use crate::geometry;
+use crate::renderer::{Frame, Mesh, Vertex, DEPTH_FORMAT};
+use crate::uniform_ring::{self, UniformRing};
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat3, Mat4, Vec3};
+use wgpu::util::DeviceExt;
+
+/// Vertex layout shared by every mesh in a scene, as made by `geometry`
+pub type SceneVertex = geometry::Vertex;
+
+/// Surface parameters of the scene shader
+#[derive(Debug, Clone, Copy)]
+pub struct Material {
+    pub base_color: [f32; 3],
+    /// 0 is a dielectric (white highlights), 1 a metal (tinted highlights)
+    pub metallic: f32,
+    /// 0 is mirror-like, 1 fully diffuse
+    pub roughness: f32,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            base_color: [0.8, 0.8, 0.8],
+            metallic: 0.0,
+            roughness: 0.5,
+        }
+    }
+}
+
+/// Index of a mesh added with `Scene::add_mesh`
+pub type MeshId = usize;
+/// Index of a material added with `Scene::add_material`
+pub type MaterialId = usize;
+
+/// Local transform at a point in time (seconds), applied after the node's own
+pub type Animation = Box<dyn Fn(f32) -> Mat4>;
+
+/// A node in the scene tree
+pub struct Node {
+    pub name: String,
+    /// Relative to the parent
+    pub transform: Mat4,
+    pub animation: Option<Animation>,
+    /// What the node draws, if anything
+    pub mesh: Option<(MeshId, MaterialId)>,
+    pub children: Vec<Node>,
+}
+
+impl Node {
+    /// An empty node at its parent's origin; use it to group children
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            transform: Mat4::IDENTITY,
+            animation: None,
+            mesh: None,
+            children: Vec::new(),
+        }
+    }
+
+    pub fn transform(mut self, transform: Mat4) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    pub fn animation(mut self, animation: impl Fn(f32) -> Mat4 + 'static) -> Self {
+        self.animation = Some(Box::new(animation));
+        self
+    }
+
+    pub fn mesh(mut self, mesh: MeshId, material: MaterialId) -> Self {
+        self.mesh = Some((mesh, material));
+        self
+    }
+
+    pub fn child(mut self, child: Node) -> Self {
+        self.children.push(child);
+        self
+    }
+
+    /// Transform relative to the parent at `time`
+    pub fn local_transform(&self, time: f32) -> Mat4 {
+        match &self.animation {
+            Some(animation) => self.transform * animation(time),
+            None => self.transform,
+        }
+    }
+}
+
+/// One mesh to draw with one material, placed in the world
+#[derive(Debug, Clone, Copy)]
+pub struct Draw {
+    pub mesh: MeshId,
+    pub material: MaterialId,
+    pub model: Mat4,
+}
+
+/// Meshes, materials and the node tree that places them
+pub struct Scene {
+    meshes: Vec<Mesh<SceneVertex>>,
+    materials: Vec<Material>,
+    pub root: Node,
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        Self {
+            meshes: Vec::new(),
+            materials: Vec::new(),
+            root: Node::new("root"),
+        }
+    }
+
+    pub fn add_mesh(&mut self, mesh: Mesh<SceneVertex>) -> MeshId {
+        self.meshes.push(mesh);
+        self.meshes.len() - 1
+    }
+
+    pub fn add_material(&mut self, material: Material) -> MaterialId {
+        self.materials.push(material);
+        self.materials.len() - 1
+    }
+
+    /// Add `node` under the root
+    pub fn add(&mut self, node: Node) {
+        self.root.children.push(node);
+    }
+
+    pub fn meshes(&self) -> &[Mesh<SceneVertex>] {
+        &self.meshes
+    }
+
+    pub fn materials(&self) -> &[Material] {
+        &self.materials
+    }
+
+    pub fn material_mut(&mut self, id: MaterialId) -> &mut Material {
+        &mut self.materials[id]
+    }
+
+    /// Every drawn node with its world transform at `time`, parents first
+    pub fn draws(&self, time: f32) -> Vec<Draw> {
+        let mut draws = Vec::new();
+        collect_draws(&self.root, Mat4::IDENTITY, time, &mut draws);
+        draws
+    }
+}
+
+fn collect_draws(node: &Node, parent: Mat4, time: f32, draws: &mut Vec<Draw>) {
+    let world = parent * node.local_transform(time);
+    if let Some((mesh, material)) = node.mesh {
+        draws.push(Draw {
+            mesh,
+            material,
+            model: world,
+        });
+    }
+    for child in &node.children {
+        collect_draws(child, world, time, draws);
+    }
+}
+
+/// Camera and light, shared by every draw
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct SceneUniforms {
+    view_proj: [[f32; 4]; 4],
+    eye: [f32; 4],
+    /// Towards the light
+    light_dir: [f32; 4],
+}
+
+/// One draw's slot in the object buffer
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct ObjectUniforms {
+    model: [[f32; 4]; 4],
+    /// Inverse transpose of the model matrix, as three padded columns
+    normal: [[f32; 4]; 3],
+    base_color: [f32; 4],
+    metallic: f32,
+    roughness: f32,
+    _padding: [f32; 2],
+}
+
+impl ObjectUniforms {
+    fn new(model: Mat4, material: &Material) -> Self {
+        let normal = Mat3::from_mat4(model).inverse().transpose();
+        Self {
+            model: model.to_cols_array_2d(),
+            normal: [
+                normal.x_axis.extend(0.0).to_array(),
+                normal.y_axis.extend(0.0).to_array(),
+                normal.z_axis.extend(0.0).to_array(),
+            ],
+            base_color: Vec3::from(material.base_color).extend(1.0).to_array(),
+            metallic: material.metallic,
+            roughness: material.roughness,
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+/// Distance between object slots: the uniform size rounded up to the
+/// device's dynamic offset alignment
+pub fn object_stride(alignment: u32) -> wgpu::BufferAddress {
+    uniform_ring::stride(
+        std::mem::size_of::<ObjectUniforms>() as wgpu::BufferAddress,
+        alignment,
+    )
+}
+
+struct GpuMesh {
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    num_indices: u32,
+}
+
+/// Objects of the last `SceneRenderer::update`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CullStats {
+    /// In the view frustum
+    pub drawn: usize,
+    /// Outside of it, not uploaded or drawn
+    pub culled: usize,
+}
+
+/// The draws whose `bounds` (indexed by mesh) reach into `frustum`
+fn cull(draws: Vec<Draw>, bounds: &[Sphere], frustum: &Frustum) -> Vec<Draw> {
+    draws
+        .into_iter()
+        .filter(|draw| frustum.intersects(&bounds[draw.mesh].transformed(draw.model)))
+        .collect()
+}
+
+/// Pipeline and buffers drawing a `Scene`
+pub struct SceneRenderer {
+    pipeline: wgpu::RenderPipeline,
+    /// Depth-only pipeline, with a depth prepass
+    depth_pipeline: Option<wgpu::RenderPipeline>,
+    meshes: Vec<GpuMesh>,
+    /// Bounding spheres of `meshes`, in model space
+    bounds: Vec<Sphere>,
+    /// Camera and light, one slot
+    scene_uniforms: UniformRing,
+    /// One slot per visible draw
+    objects: UniformRing,
+    /// Object uniforms of the current frame, laid out at the ring's stride
+    staging: Vec<u8>,
+    /// Visible draws of the current frame
+    draws: Vec<Draw>,
+    stats: CullStats,
+}
+
+impl SceneRenderer {
+    /// Upload the scene's meshes and build a pipeline drawing into `format`
+    ///
+    /// With `depth_prepass`, every frame must run `draw_depth` in a pass of
+    /// its own before `draw`, on the same depth buffer. `max_frame_latency` is
+    /// the surface's `desired_maximum_frame_latency`.
+    pub fn new(
+        device: &wgpu::Device,
+        scene: &Scene,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth_prepass: bool,
+        max_frame_latency: u32,
+    ) -> Self {
+        let meshes = scene
+            .meshes()
+            .iter()
+            .map(|mesh| GpuMesh {
+                vertex_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Scene Vertex Buffer"),
+                    contents: bytemuck::cast_slice(&mesh.vertices),
+                    usage: wgpu::BufferUsages::VERTEX,
+                }),
+                index_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Scene Index Buffer"),
+                    contents: bytemuck::cast_slice(&mesh.indices),
+                    usage: wgpu::BufferUsages::INDEX,
+                }),
+                num_indices: mesh.indices.len() as u32,
+            })
+            .collect();
+        let bounds = scene
+            .meshes()
+            .iter()
+            .map(|mesh| Sphere::around(mesh.vertices.iter().map(|v| Vec3::from(v.position))))
+            .collect();
+
+        let frames = uniform_ring::frames_in_flight(max_frame_latency);
+        let scene_uniforms = UniformRing::new(
+            device,
+            "Scene Uniform Buffer",
+            std::mem::size_of::<SceneUniforms>() as wgpu::BufferAddress,
+            1,
+            frames,
+        );
+        let objects = UniformRing::new(
+            device,
+            "Object Uniform Buffer",
+            std::mem::size_of::<ObjectUniforms>() as wgpu::BufferAddress,
+            scene.draws(0.0).len(),
+            frames,
+        );
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Scene Shader"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Scene Pipeline Layout"),
+            bind_group_layouts: &[scene_uniforms.layout(), objects.layout()],
+            push_constant_ranges: &[],
+        });
+
+        // After a prepass the depth buffer already holds the nearest
+        // surfaces: shade only those, without writing depth again
+        let depth_stencil = |depth_write_enabled, depth_compare| wgpu::DepthStencilState {
+            format: DEPTH_FORMAT,
+            depth_write_enabled,
+            depth_compare,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        };
+        let pipeline = create_pipeline(
+            device,
+            &pipeline_layout,
+            &shader,
+            Some(format),
+            sample_count,
+            if depth_prepass {
+                depth_stencil(false, wgpu::CompareFunction::LessEqual)
+            } else {
+                depth_stencil(true, wgpu::CompareFunction::Less)
+            },
+        );
+        let depth_pipeline = depth_prepass.then(|| {
+            create_pipeline(
+                device,
+                &pipeline_layout,
+                &shader,
+                None,
+                sample_count,
+                depth_stencil(true, wgpu::CompareFunction::Less),
+            )
+        });
+
+        Self {
+            pipeline,
+            depth_pipeline,
+            meshes,
+            bounds,
+            scene_uniforms,
+            objects,
+            staging: Vec::new(),
+            draws: Vec::new(),
+            stats: CullStats::default(),
+        }
+    }
+
+    /// Lay out the scene at `frame.time`, cull it to the view and upload it
+    ///
+    /// `light_dir` points towards the light. The uniforms go to the next
+    /// region of each ring; the object ring grows if more objects are visible
+    /// than ever before.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        scene: &Scene,
+        frame: &Frame,
+        light_dir: Vec3,
+    ) {
+        let view_proj = frame.proj * frame.view;
+        self.scene_uniforms.write(
+            device,
+            queue,
+            bytemuck::bytes_of(&SceneUniforms {
+                view_proj: view_proj.to_cols_array_2d(),
+                eye: frame.eye.extend(1.0).to_array(),
+                light_dir: light_dir.normalize_or(Vec3::Y).extend(0.0).to_array(),
+            }),
+        );
+
+        let draws = scene.draws(frame.time);
+        let total = draws.len();
+        self.draws = cull(draws, &self.bounds, &Frustum::from_view_proj(view_proj));
+        self.stats = CullStats {
+            drawn: self.draws.len(),
+            culled: total - self.draws.len(),
+        };
+        let stride = self.objects.stride() as usize;
+        self.staging.clear();
+        self.staging.resize(self.draws.len() * stride, 0);
+        for (slot, draw) in self.staging.chunks_exact_mut(stride).zip(&self.draws) {
+            let uniforms = ObjectUniforms::new(draw.model, &scene.materials()[draw.material]);
+            slot[..std::mem::size_of::<ObjectUniforms>()]
+                .copy_from_slice(bytemuck::bytes_of(&uniforms));
+        }
+        self.objects.write(device, queue, &self.staging);
+    }
+
+    /// Draw what the last `update` laid out
+    pub fn draw(&self, pass: &mut wgpu::RenderPass<'_>) {
+        pass.set_pipeline(&self.pipeline);
+        self.draw_objects(pass);
+    }
+
+    /// Fill the depth buffer with what the last `update` laid out
+    ///
+    /// For a depth-only pass before `draw`; does nothing without a prepass.
+    pub fn draw_depth(&self, pass: &mut wgpu::RenderPass<'_>) {
+        if let Some(pipeline) = &self.depth_pipeline {
+            pass.set_pipeline(pipeline);
+            self.draw_objects(pass);
+        }
+    }
+
+    fn draw_objects(&self, pass: &mut wgpu::RenderPass<'_>) {
+        pass.set_bind_group(
+            0,
+            self.scene_uniforms.bind_group(),
+            &[self.scene_uniforms.offset(0)],
+        );
+        for (index, draw) in self.draws.iter().enumerate() {
+            pass.set_bind_group(1, self.objects.bind_group(), &[self.objects.offset(index)]);
+
+            let mesh = &self.meshes[draw.mesh];
+            pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            pass.draw_indexed(0..mesh.num_indices, 0, 0..1);
+        }
+    }
+
+    /// Number of objects drawn by the last `update`
+    pub fn draw_count(&self) -> usize {
+        self.draws.len()
+    }
+
+    /// Drawn and culled objects of the last `update`
+    pub fn stats(&self) -> CullStats {
+        self.stats
+    }
+
+    pub fn has_depth_prepass(&self) -> bool {
+        self.depth_pipeline.is_some()
+    }
+}
+
+/// Scene pipeline; without a `format` it only writes depth
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    format: Option<wgpu::TextureFormat>,
+    sample_count: u32,
+    depth_stencil: wgpu::DepthStencilState,
+) -> wgpu::RenderPipeline {
+    let targets = [format.map(|format| wgpu::ColorTargetState {
+        format,
+        blend: Some(wgpu::BlendState::REPLACE),
+        write_mask: wgpu::ColorWrites::ALL,
+    })];
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(if format.is_some() {
+            "Scene Pipeline"
+        } else {
+            "Scene Depth Pipeline"
+        }),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: Some("vs_main"),
+            buffers: &[SceneVertex::desc()],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        },
+        fragment: format.map(|_| wgpu::FragmentState {
+            module: shader,
+            entry_point: Some("fs_main"),
+            targets: &targets,
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState {
+            cull_mode: Some(wgpu::Face::Back),
+            ..Default::default()
+        },
+        depth_stencil: Some(depth_stencil),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            ..Default::default()
+        },
+        multiview: None,
+        cache: None,
+    })
+}
+
+// Blinn-Phong with metallic tinting and a fresnel rim, in the style of the
+// single-mesh demos
+const SHADER: &str = r#"
+struct SceneUniforms {
+    view_proj: mat4x4<f32>,
+    eye: vec4<f32>,
+    light_dir: vec4<f32>,
+};
+
+struct ObjectUniforms {
+    model: mat4x4<f32>,
+    normal: mat3x3<f32>,
+    base_color: vec4<f32>,
+    metallic: f32,
+    roughness: f32,
+};
+
+@group(0) @binding(0) var<uniform> scene: SceneUniforms;
+@group(1) @binding(0) var<uniform> object: ObjectUniforms;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_pos: vec3<f32>,
+    @location(1) world_normal: vec3<f32>,
+};
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    let world_pos = object.model * vec4<f32>(in.position, 1.0);
+    var out: VertexOutput;
+    out.world_pos = world_pos.xyz;
+    out.world_normal = object.normal * in.normal;
+    out.clip_position = scene.view_proj * world_pos;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let normal = normalize(in.world_normal);
+    let view_dir = normalize(scene.eye.xyz - in.world_pos);
+    let light_dir = scene.light_dir.xyz;
+    let base = object.base_color.rgb;
+
+    // Diffuse, dimmed for metals whose color is mostly in the highlights
+    let n_dot_l = max(dot(normal, light_dir), 0.0);
+    let diffuse = n_dot_l * base * (1.0 - 0.5 * object.metallic);
+
+    // Specular (Blinn-Phong), tinted by the base color for metals
+    let halfway = normalize(light_dir + view_dir);
+    let spec_power = (1.0 - object.roughness) * 128.0 + 8.0;
+    let spec_color = mix(vec3<f32>(1.0), base, object.metallic);
+    let specular = pow(max(dot(normal, halfway), 0.0), spec_power) * spec_color * (1.0 - object.roughness);
+
+    // Weak cool fill light from below
+    let fill = max(dot(normal, normalize(vec3<f32>(-1.0, -0.5, 0.3))), 0.0) * base * vec3<f32>(0.1, 0.13, 0.17);
+
+    let fresnel = pow(1.0 - max(dot(normal, view_dir), 0.0), 5.0);
+    let rim = fresnel * spec_color * 0.3;
+
+    var color = diffuse + specular + fill + rim;
+
+    // Tone mapping
+    color = color / (color + vec3<f32>(1.0));
+
+    // Gamma
+    color = pow(color, vec3<f32>(1.0 / 2.2));
+
+    return vec4<f32>(color, 1.0);
+}
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translation(draw: &Draw) -> Vec3 {
+        draw.model.w_axis.truncate()
+    }
+
+    #[test]
+    fn test_draws_compose_parent_transforms() {
+        let mut scene = Scene::new();
+        let mesh = scene.add_mesh(Mesh::new(Vec::new(), Vec::new()));
+        let material = scene.add_material(Material::default());
+
+        // The pivot turns a quarter per second, carrying its child around
+        scene.add(
+            Node::new("pivot")
+                .transform(Mat4::from_translation(Vec3::Y))
+                .animation(|time| Mat4::from_rotation_y(time * std::f32::consts::FRAC_PI_2))
+                .child(
+                    Node::new("planet")
+                        .transform(Mat4::from_translation(Vec3::X * 2.0))
+                        .mesh(mesh, material),
+                ),
+        );
+
+        let draws = scene.draws(0.0);
+        assert_eq!(draws.len(), 1);
+        assert!(translation(&draws[0]).abs_diff_eq(Vec3::new(2.0, 1.0, 0.0), 1e-5));
+
+        let draws = scene.draws(1.0);
+        assert!(translation(&draws[0]).abs_diff_eq(Vec3::new(0.0, 1.0, -2.0), 1e-5));
+    }
+
+    #[test]
+    fn test_draws_skip_empty_nodes() {
+        let mut scene = Scene::new();
+        let mesh = scene.add_mesh(Mesh::new(Vec::new(), Vec::new()));
+        let material = scene.add_material(Material::default());
+        scene.add(Node::new("group").child(Node::new("a").mesh(mesh, material)));
+        scene.add(Node::new("b").mesh(mesh, material));
+
+        let draws = scene.draws(0.0);
+        assert_eq!(draws.len(), 2);
+    }
+
+    #[test]
+    fn test_cull() {
+        let bounds = [Sphere {
+            center: Vec3::ZERO,
+            radius: 0.5,
+        }];
+        let draw = |x: f32, z: f32| Draw {
+            mesh: 0,
+            material: 0,
+            model: Mat4::from_translation(Vec3::new(x, 0.0, z)),
+        };
+        let proj = Mat4::perspective_rh(45.0_f32.to_radians(), 1.0, 0.1, 100.0);
+        let frustum = Frustum::from_view_proj(proj);
+
+        // In view, behind the camera, far to the side, grazing the edge
+        let draws = vec![
+            draw(0.0, -5.0),
+            draw(0.0, 5.0),
+            draw(10.0, -5.0),
+            draw(2.5, -5.0),
+        ];
+        let visible = cull(draws, &bounds, &frustum);
+        let xs: Vec<f32> = visible.iter().map(|d| translation(d).x).collect();
+        assert_eq!(xs, [0.0, 2.5]);
+    }
+
+    #[test]
+    fn test_object_stride() {
+        let size = std::mem::size_of::<ObjectUniforms>() as u64;
+        assert_eq!(size, 144);
+        assert_eq!(object_stride(256), 256);
+        assert_eq!(object_stride(16), size);
+        assert_eq!(object_stride(64), 192);
+    }
+}
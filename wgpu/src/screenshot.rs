@@ -0,0 +1,288 @@
+//! F12 screenshots
+//!
+//! `Capture::copy` records a copy of the frame's surface texture into a
+//! mappable buffer, in the same encoder that drew it; `Capture::read` waits for
+//! that copy after the frame is submitted and converts the rows to an
+//! `RgbaImage`. The surface must be configured with `COPY_SRC` usage.
+//!
+//! Copy rows are padded to 256 bytes, surfaces are often BGRA, and half-float
+//! surfaces hold linear color, so `to_rgba` drops the padding, swaps channels
+//! and applies the sRGB transfer function as needed. 8-bit surfaces already
+//! store what the display shows, sRGB or not, and are copied as they are.
+//!
+//! Reading back stalls that one frame; encoding and writing the PNG happen on
+//! a separate thread.
+
+use image::RgbaImage;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Directory screenshots are written to, relative to the working directory
+pub const DIR: &str = "screenshots";
+
+/// Whether `to_rgba` can convert texels of `format`
+pub fn is_supported(format: wgpu::TextureFormat) -> bool {
+    use wgpu::TextureFormat::*;
+    matches!(
+        format,
+        Rgba8Unorm | Rgba8UnormSrgb | Bgra8Unorm | Bgra8UnormSrgb | Rgba16Float
+    )
+}
+
+/// Copy of one frame on its way to the CPU
+pub struct Capture {
+    buffer: wgpu::Buffer,
+    width: u32,
+    height: u32,
+    padded_bytes_per_row: u32,
+    format: wgpu::TextureFormat,
+}
+
+impl Capture {
+    /// Record a copy of `texture` into `encoder`
+    pub fn copy(
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+    ) -> Result<Self, String> {
+        let format = texture.format();
+        if !is_supported(format) {
+            return Err(format!(
+                "Screenshots of {format:?} surfaces are not supported"
+            ));
+        }
+        if !texture.usage().contains(wgpu::TextureUsages::COPY_SRC) {
+            return Err("The surface cannot be copied from".to_string());
+        }
+
+        let bytes_per_pixel = format.block_copy_size(None).unwrap_or(4);
+        let width = texture.width();
+        let height = texture.height();
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded = (width * bytes_per_pixel).div_ceil(align) * align;
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Screenshot Buffer"),
+            size: (padded * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded),
+                    rows_per_image: Some(height),
+                },
+            },
+            texture.size(),
+        );
+
+        Ok(Self {
+            buffer,
+            width,
+            height,
+            padded_bytes_per_row: padded,
+            format,
+        })
+    }
+
+    /// Wait for the submitted copy and convert it
+    pub fn read(self, device: &wgpu::Device) -> Result<RgbaImage, String> {
+        let slice = self.buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device
+            .poll(wgpu::PollType::wait_indefinitely())
+            .map_err(|e| e.to_string())?;
+        receiver
+            .recv()
+            .map_err(|e| e.to_string())?
+            .map_err(|e| e.to_string())?;
+
+        let image = to_rgba(
+            &slice.get_mapped_range(),
+            self.width,
+            self.height,
+            self.padded_bytes_per_row,
+            self.format,
+        );
+        self.buffer.unmap();
+        image.ok_or_else(|| "Screenshot size mismatch".to_string())
+    }
+}
+
+/// Convert padded rows of `format` texels to 8-bit sRGB RGBA
+///
+/// `None` for unsupported formats or too little data.
+pub fn to_rgba(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    padded_bytes_per_row: u32,
+    format: wgpu::TextureFormat,
+) -> Option<RgbaImage> {
+    use wgpu::TextureFormat::*;
+    let bytes_per_pixel = match format {
+        Rgba8Unorm | Rgba8UnormSrgb | Bgra8Unorm | Bgra8UnormSrgb => 4,
+        Rgba16Float => 8,
+        _ => return None,
+    };
+    let row = (width * bytes_per_pixel) as usize;
+    let padded = padded_bytes_per_row as usize;
+    if padded < row || data.len() < padded * (height as usize).saturating_sub(1) + row {
+        return None;
+    }
+
+    let mut pixels = Vec::with_capacity(4 * (width * height) as usize);
+    for y in 0..height as usize {
+        let texels = &data[y * padded..y * padded + row];
+        match format {
+            Bgra8Unorm | Bgra8UnormSrgb => {
+                for bgra in texels.chunks_exact(4) {
+                    pixels.extend_from_slice(&[bgra[2], bgra[1], bgra[0], bgra[3]]);
+                }
+            }
+            Rgba16Float => {
+                for rgba in texels.chunks_exact(8) {
+                    let channel = |i: usize| f16_to_f32(u16::from_le_bytes([rgba[i], rgba[i + 1]]));
+                    pixels.extend_from_slice(&[
+                        linear_to_srgb(channel(0)),
+                        linear_to_srgb(channel(2)),
+                        linear_to_srgb(channel(4)),
+                        (channel(6).clamp(0.0, 1.0) * 255.0).round() as u8,
+                    ]);
+                }
+            }
+            _ => pixels.extend_from_slice(texels),
+        }
+    }
+    RgbaImage::from_raw(width, height, pixels)
+}
+
+/// Encode a linear channel value as an 8-bit sRGB value
+pub fn linear_to_srgb(linear: f32) -> u8 {
+    let linear = linear.clamp(0.0, 1.0);
+    let srgb = if linear <= 0.003_130_8 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    };
+    (srgb * 255.0).round() as u8
+}
+
+/// Decode an IEEE 754 half-precision float
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = (bits >> 10) & 0x1f;
+    let mantissa = (bits & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent as i32 - 15),
+    }
+}
+
+/// `screenshot_YYYYMMDD_HHMMSS_mmm.png` for `time`, in UTC
+pub fn file_name(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86_400) as i64);
+    let seconds_of_day = seconds % 86_400;
+    format!(
+        "screenshot_{year:04}{month:02}{day:02}_{:02}{:02}{:02}_{:03}.png",
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Gregorian date of a day count since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    // Howard Hinnant's algorithm, with eras of 400 years starting in March
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Write `image` into `dir` under a timestamped name on a background thread
+pub fn save(image: RgbaImage, dir: &Path) {
+    let path = dir.join(file_name(SystemTime::now()));
+    std::thread::spawn(move || {
+        let result = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .map_err(image::ImageError::IoError)
+            .and_then(|_| image.save(&path));
+        match result {
+            Ok(()) => log::info!("Saved screenshot {}", path.display()),
+            Err(e) => log::error!("Failed to save {}: {}", path.display(), e),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_to_rgba_drops_padding_and_swizzles() {
+        // 2x2 BGRA with rows padded to 12 bytes
+        let data = [
+            1, 2, 3, 4, 5, 6, 7, 8, 0, 0, 0, 0, //
+            9, 10, 11, 12, 13, 14, 15, 16, 0, 0, 0, 0,
+        ];
+        let image = to_rgba(&data, 2, 2, 12, wgpu::TextureFormat::Bgra8UnormSrgb).unwrap();
+        assert_eq!(
+            image.into_raw(),
+            [3, 2, 1, 4, 7, 6, 5, 8, 11, 10, 9, 12, 15, 14, 13, 16]
+        );
+
+        let image = to_rgba(&data, 2, 2, 12, wgpu::TextureFormat::Rgba8Unorm).unwrap();
+        assert_eq!(image.get_pixel(1, 1).0, [13, 14, 15, 16]);
+
+        assert!(to_rgba(&data[..16], 2, 2, 12, wgpu::TextureFormat::Rgba8Unorm).is_none());
+        assert!(to_rgba(&data, 2, 2, 12, wgpu::TextureFormat::R8Unorm).is_none());
+    }
+
+    #[test]
+    fn test_half_float_is_encoded_as_srgb() {
+        // 1.0, 0.5, 0.0, 1.0 as halves
+        let halves: [u16; 4] = [0x3c00, 0x3800, 0x0000, 0x3c00];
+        let data: Vec<u8> = halves.iter().flat_map(|h| h.to_le_bytes()).collect();
+        let image = to_rgba(&data, 1, 1, 8, wgpu::TextureFormat::Rgba16Float).unwrap();
+        assert_eq!(image.get_pixel(0, 0).0, [255, 188, 0, 255]);
+    }
+
+    #[test]
+    fn test_linear_to_srgb() {
+        assert_eq!(linear_to_srgb(0.0), 0);
+        assert_eq!(linear_to_srgb(1.0), 255);
+        assert_eq!(linear_to_srgb(0.2), 124);
+        assert_eq!(linear_to_srgb(-1.0), 0);
+        assert_eq!(linear_to_srgb(2.0), 255);
+    }
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(file_name(UNIX_EPOCH), "screenshot_19700101_000000_000.png");
+        // 2024-02-29 13:45:07.250 UTC
+        let time = UNIX_EPOCH + Duration::from_millis(1_709_214_307_250);
+        assert_eq!(file_name(time), "screenshot_20240229_134507_250.png");
+    }
+}
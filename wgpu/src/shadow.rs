@@ -0,0 +1,535 @@
+//! Shadow mapping for demos lit by a directional light
+//!
+//! Every frame starts with a depth-only pass that draws the mesh from the
+//! light's point of view into a shadow map. The main pass then samples the map
+//! through a comparison sampler with 3x3 PCF, both in the demo's own shader and
+//! on a ground plane drawn under the mesh.
+//!
+//! With shadows enabled the demo shader is prefixed with `SHADOW_WGSL`, which
+//! binds the map at `@group(2)` and provides `shadow_factor`. The demo stores
+//! `Frame::light_view_proj` in its uniforms and uses it in two places:
+//!
+//! ```wgsl
+//! // Depth-only entry point for the shadow pass
+//! @vertex
+//! fn vs_shadow(in: VertexInput) -> @builtin(position) vec4<f32> {
+//!     return uniforms.light_view_proj * uniforms.model * vec4<f32>(in.position, 1.0);
+//! }
+//!
+//! // In fs_main: 1.0 is lit, 0.0 is fully shadowed
+//! let shadow = shadow_factor(uniforms.light_view_proj * vec4<f32>(in.world_pos, 1.0));
+//! ```
+
+use crate::renderer::{Frame, DEPTH_FORMAT};
+use glam::{Mat4, Vec3};
+use wgpu::util::DeviceExt;
+
+/// Bind group index of the shadow map in the demo shader
+pub const SHADOW_GROUP: u32 = 2;
+
+/// Distance of the light's eye from the origin, along the light direction
+const LIGHT_DISTANCE: f32 = 20.0;
+
+/// Shadow map and ground plane settings
+#[derive(Debug, Clone, Copy)]
+pub struct ShadowConfig {
+    /// Width and height of the shadow map in texels
+    pub map_size: u32,
+    /// Height of the ground plane (y)
+    pub ground_height: f32,
+    /// Edge length of the square ground plane, centered under the origin
+    pub ground_size: f32,
+    pub ground_color: [f32; 3],
+}
+
+impl Default for ShadowConfig {
+    fn default() -> Self {
+        Self {
+            map_size: 2048,
+            ground_height: -1.2,
+            ground_size: 10.0,
+            ground_color: [0.35, 0.35, 0.38],
+        }
+    }
+}
+
+impl ShadowConfig {
+    /// Orthographic view-projection looking along `-light_dir` at the origin
+    ///
+    /// `light_dir` points towards the light. The frustum is just wide enough
+    /// to cover the ground plane from any direction.
+    pub fn light_view_proj(&self, light_dir: Vec3) -> Mat4 {
+        let dir = light_dir.try_normalize().unwrap_or(Vec3::Y);
+        // look_at needs an up vector that is not parallel to the view
+        let up = if dir.y.abs() > 0.99 { Vec3::Z } else { Vec3::Y };
+        let view = Mat4::look_at_rh(dir * LIGHT_DISTANCE, Vec3::ZERO, up);
+
+        let radius = (self.ground_size * std::f32::consts::FRAC_1_SQRT_2)
+            .max(self.ground_height.abs())
+            .max(1.0);
+        let proj = Mat4::orthographic_rh(
+            -radius,
+            radius,
+            -radius,
+            radius,
+            LIGHT_DISTANCE - 2.0 * radius,
+            LIGHT_DISTANCE + 2.0 * radius,
+        );
+        proj * view
+    }
+}
+
+/// Shadow bindings and `shadow_factor`, prepended to the demo shader
+pub const SHADOW_WGSL: &str = r#"
+@group(2) @binding(0) var shadow_map: texture_depth_2d;
+@group(2) @binding(1) var shadow_sampler: sampler_comparison;
+
+// Fraction of the light reaching a point, given its position in light clip
+// space: 1.0 lit, 0.0 fully shadowed, averaged over 3x3 texels (PCF)
+fn shadow_factor(light_clip: vec4<f32>) -> f32 {
+    let ndc = light_clip.xyz / light_clip.w;
+    let uv = ndc.xy * vec2<f32>(0.5, -0.5) + vec2<f32>(0.5);
+    // Outside the light's frustum nothing casts a shadow
+    if (any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || ndc.z > 1.0) {
+        return 1.0;
+    }
+
+    let texel = 1.0 / vec2<f32>(textureDimensions(shadow_map));
+    var lit = 0.0;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let offset = vec2<f32>(f32(x), f32(y)) * texel;
+            lit += textureSampleCompareLevel(shadow_map, shadow_sampler, uv + offset, ndc.z);
+        }
+    }
+    return lit / 9.0;
+}
+"#;
+
+/// Prefix `shader` with the shadow bindings
+pub fn with_shadow_wgsl(shader: &str) -> String {
+    format!("{SHADOW_WGSL}\n{shader}")
+}
+
+/// Shadow map and the ground plane
+///
+/// The shadow pass pipeline comes from the demo's shader, so it is built with
+/// `create_pipeline` alongside the main pipeline, and the caller draws the mesh
+/// into the pass from `begin_shadow_pass`.
+pub struct Shadows {
+    config: ShadowConfig,
+    view: wgpu::TextureView,
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    ground: Ground,
+}
+
+/// How the main pass renders, for pipelines drawn into it
+pub struct TargetDesc {
+    pub format: wgpu::TextureFormat,
+    pub sample_count: u32,
+}
+
+impl Shadows {
+    pub fn new(device: &wgpu::Device, config: ShadowConfig, target: &TargetDesc) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Shadow Map"),
+            size: wgpu::Extent3d {
+                width: config.map_size,
+                height: config.map_size,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Shadow Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Shadow Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    count: None,
+                },
+            ],
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Shadow Bind Group"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        let ground = Ground::new(device, &config, &layout, target);
+
+        Self {
+            config,
+            view,
+            layout,
+            bind_group,
+            ground,
+        }
+    }
+
+    /// Depth-only pipeline for the shadow pass
+    ///
+    /// `shader` must have a `vs_shadow` entry point reading `uniforms_layout`.
+    pub fn create_pipeline(
+        &self,
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        vertex_layout: wgpu::VertexBufferLayout<'_>,
+        uniforms_layout: &wgpu::BindGroupLayout,
+        cull_mode: Option<wgpu::Face>,
+    ) -> wgpu::RenderPipeline {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout"),
+            bind_group_layouts: &[uniforms_layout],
+            push_constant_ranges: &[],
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Shadow Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: Some("vs_shadow"),
+                buffers: &[vertex_layout],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: None,
+            primitive: wgpu::PrimitiveState {
+                cull_mode,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                // Keeps lit surfaces from shadowing themselves (acne)
+                bias: wgpu::DepthBiasState {
+                    constant: 2,
+                    slope_scale: 2.0,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    pub fn config(&self) -> &ShadowConfig {
+        &self.config
+    }
+
+    /// Layout of the bind group at `SHADOW_GROUP`
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    /// Upload the ground plane's uniforms for this frame
+    pub fn update(&self, queue: &wgpu::Queue, frame: &Frame, light_dir: Vec3) {
+        self.ground.update(queue, &self.config, frame, light_dir);
+    }
+
+    /// Begin the shadow pass, which clears the shadow map
+    ///
+    /// The caller draws the mesh into it with the pipeline from
+    /// `create_pipeline`.
+    pub fn begin_shadow_pass<'a>(
+        &'a self,
+        encoder: &'a mut wgpu::CommandEncoder,
+        timestamp_writes: Option<wgpu::RenderPassTimestampWrites<'a>>,
+    ) -> wgpu::RenderPass<'a> {
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Shadow Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes,
+            occlusion_query_set: None,
+        })
+    }
+
+    /// Draw the ground plane into the main pass
+    pub fn draw_ground(&self, pass: &mut wgpu::RenderPass<'_>) {
+        self.ground.draw(pass, &self.bind_group);
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct GroundUniforms {
+    view_proj: [[f32; 4]; 4],
+    light_view_proj: [[f32; 4]; 4],
+    light_dir: [f32; 4],
+    color: [f32; 4],
+    /// Half the edge length, height
+    plane: [f32; 4],
+}
+
+/// Square plane under the mesh, lit by the light and receiving its shadow
+struct Ground {
+    pipeline: wgpu::RenderPipeline,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    // Stands in for the texture group the ground does not use
+    empty_bind_group: wgpu::BindGroup,
+}
+
+impl Ground {
+    fn new(
+        device: &wgpu::Device,
+        config: &ShadowConfig,
+        shadow_layout: &wgpu::BindGroupLayout,
+        target: &TargetDesc,
+    ) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Ground Uniform Buffer"),
+            contents: bytemuck::bytes_of(&GroundUniforms::new(config)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Ground Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Ground Bind Group"),
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+
+        let empty_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Empty Bind Group Layout"),
+            entries: &[],
+        });
+        let empty_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Empty Bind Group"),
+            layout: &empty_layout,
+            entries: &[],
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Ground Shader"),
+            source: wgpu::ShaderSource::Wgsl(with_shadow_wgsl(GROUND_SHADER).into()),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Ground Pipeline Layout"),
+            bind_group_layouts: &[&layout, &empty_layout, shadow_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Ground Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(target.format.into())],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: target.sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            pipeline,
+            buffer,
+            bind_group,
+            empty_bind_group,
+        }
+    }
+
+    fn update(&self, queue: &wgpu::Queue, config: &ShadowConfig, frame: &Frame, light_dir: Vec3) {
+        let uniforms = GroundUniforms {
+            view_proj: (frame.proj * frame.view).to_cols_array_2d(),
+            light_view_proj: frame.light_view_proj.to_cols_array_2d(),
+            light_dir: light_dir.normalize_or(Vec3::Y).extend(0.0).to_array(),
+            ..GroundUniforms::new(config)
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(&uniforms));
+    }
+
+    fn draw(&self, pass: &mut wgpu::RenderPass<'_>, shadow_bind_group: &wgpu::BindGroup) {
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_bind_group(1, &self.empty_bind_group, &[]);
+        pass.set_bind_group(SHADOW_GROUP, shadow_bind_group, &[]);
+        pass.draw(0..6, 0..1);
+    }
+}
+
+impl GroundUniforms {
+    fn new(config: &ShadowConfig) -> Self {
+        let [r, g, b] = config.ground_color;
+        Self {
+            view_proj: Mat4::IDENTITY.to_cols_array_2d(),
+            light_view_proj: Mat4::IDENTITY.to_cols_array_2d(),
+            light_dir: [0.0, 1.0, 0.0, 0.0],
+            color: [r, g, b, 1.0],
+            plane: [config.ground_size / 2.0, config.ground_height, 0.0, 0.0],
+        }
+    }
+}
+
+// Two triangles spanning the plane, lit by the directional light
+const GROUND_SHADER: &str = r#"
+struct Ground {
+    view_proj: mat4x4<f32>,
+    light_view_proj: mat4x4<f32>,
+    light_dir: vec4<f32>,
+    color: vec4<f32>,
+    plane: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> ground: Ground;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_pos: vec3<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    // Counter-clockwise seen from above
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, 1.0), vec2<f32>(1.0, 1.0), vec2<f32>(1.0, -1.0),
+        vec2<f32>(-1.0, 1.0), vec2<f32>(1.0, -1.0), vec2<f32>(-1.0, -1.0),
+    );
+    let corner = corners[index] * ground.plane.x;
+    let world_pos = vec3<f32>(corner.x, ground.plane.y, corner.y);
+
+    var out: VertexOutput;
+    out.clip_position = ground.view_proj * vec4<f32>(world_pos, 1.0);
+    out.world_pos = world_pos;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let shadow = shadow_factor(ground.light_view_proj * vec4<f32>(in.world_pos, 1.0));
+    let n_dot_l = max(ground.light_dir.y, 0.0);
+    let color = ground.color.rgb * (0.15 + 0.85 * n_dot_l * shadow);
+    return vec4<f32>(color, 1.0);
+}
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_ndc(m: Mat4, p: Vec3) -> Vec3 {
+        m.project_point3(p)
+    }
+
+    #[test]
+    fn test_light_view_proj_covers_the_ground() {
+        let config = ShadowConfig::default();
+        let half = config.ground_size / 2.0;
+        let y = config.ground_height;
+        for dir in [Vec3::Y, Vec3::new(2.5, 2.5, 2.0), Vec3::new(-1.0, 0.2, 0.0)] {
+            let m = config.light_view_proj(dir);
+            // The origin is in the middle of the map
+            let center = to_ndc(m, Vec3::ZERO);
+            assert!(center.x.abs() < 1e-5 && center.y.abs() < 1e-5, "{dir}");
+            for corner in [
+                Vec3::new(-half, y, -half),
+                Vec3::new(half, y, -half),
+                Vec3::new(-half, y, half),
+                Vec3::new(half, y, half),
+            ] {
+                let ndc = to_ndc(m, corner);
+                assert!(ndc.x.abs() <= 1.0 && ndc.y.abs() <= 1.0, "{dir} {corner}");
+                assert!((0.0..=1.0).contains(&ndc.z), "{dir} {corner}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_closer_to_the_light_is_shallower() {
+        let config = ShadowConfig::default();
+        let dir = Vec3::new(1.0, 1.0, 0.5);
+        let m = config.light_view_proj(dir);
+        let near = to_ndc(m, dir.normalize());
+        let far = to_ndc(m, Vec3::ZERO);
+        assert!(near.z < far.z);
+    }
+}
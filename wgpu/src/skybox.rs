@@ -0,0 +1,506 @@
+//! Cubemap skybox and environment reflections
+//!
+//! A `Skybox` draws a cubemap behind everything else in the main pass and
+//! lends the same cubemap to the demo's shader for reflections. The cubemap
+//! is mipmapped, and sampling a blurrier level for a rougher surface is a
+//! cheap stand-in for a prefiltered environment map. The levels are averaged
+//! on the CPU in linear color: the GL backend cannot render into a single
+//! face of a cubemap the way `texture`'s GPU blit would need.
+//!
+//! With a skybox the demo shader is prefixed with the output of
+//! `with_env_wgsl`, which binds the map at `@group(3)` and provides
+//! `env_radiance`:
+//!
+//! ```wgsl
+//! let reflected = reflect(-view_dir, normal);
+//! let reflection = env_radiance(reflected, roughness) * fresnel;
+//! ```
+//!
+//! The cubemap is six square images in `+X, -X, +Y, -Y, +Z, -Z` order, with
+//! the usual cubemap orientation (see `face_direction`). `procedural` paints a
+//! sky with a sun; `load_faces` reads `px`, `nx`, `py`, `ny`, `pz` and `nz`
+//! images from a directory.
+
+use crate::renderer::{Frame, DEPTH_FORMAT};
+use crate::screenshot::linear_to_srgb;
+use crate::shadow::TargetDesc;
+use crate::texture::{self, TEXTURE_FORMAT};
+use glam::{Mat3, Mat4, Vec3};
+use image::RgbaImage;
+use std::path::Path;
+use wgpu::util::DeviceExt;
+
+/// Bind group index of the environment map in the demo shader
+pub const ENV_GROUP: u32 = 3;
+
+/// File names (without extension) of the faces, in layer order
+pub const FACE_NAMES: [&str; 6] = ["px", "nx", "py", "ny", "pz", "nz"];
+
+/// Direction through texel coordinates `s`, `t` (-1..1, `t` down) of `face`
+pub fn face_direction(face: usize, s: f32, t: f32) -> Vec3 {
+    match face {
+        0 => Vec3::new(1.0, -t, -s),
+        1 => Vec3::new(-1.0, -t, s),
+        2 => Vec3::new(s, 1.0, t),
+        3 => Vec3::new(s, -1.0, -t),
+        4 => Vec3::new(s, -t, 1.0),
+        _ => Vec3::new(-s, -t, -1.0),
+    }
+    .normalize()
+}
+
+/// Linear color of the procedural sky in `dir`
+fn sky_color(dir: Vec3, sun_dir: Vec3) -> Vec3 {
+    let zenith = Vec3::new(0.1, 0.25, 0.6);
+    let horizon = Vec3::new(0.75, 0.82, 0.9);
+    let near_ground = Vec3::new(0.3, 0.27, 0.24);
+    let ground = Vec3::new(0.08, 0.07, 0.06);
+
+    let color = if dir.y >= 0.0 {
+        horizon.lerp(zenith, dir.y.sqrt())
+    } else {
+        near_ground.lerp(ground, (-dir.y).powf(0.4))
+    };
+
+    // Sun disk and its glow
+    let toward_sun = dir.dot(sun_dir).max(0.0);
+    let sun =
+        Vec3::new(1.0, 0.95, 0.85) * (toward_sun.powf(2000.0) * 20.0 + toward_sun.powf(12.0) * 0.4);
+    color + sun
+}
+
+/// Six `size` x `size` faces of a sky with the sun towards `sun_dir`
+pub fn procedural(size: u32, sun_dir: Vec3) -> [RgbaImage; 6] {
+    let sun_dir = sun_dir.try_normalize().unwrap_or(Vec3::Y);
+    std::array::from_fn(|face| {
+        RgbaImage::from_fn(size, size, |x, y| {
+            let s = (x as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+            let t = (y as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+            let color = sky_color(face_direction(face, s, t), sun_dir);
+            let [r, g, b] = color.to_array().map(linear_to_srgb);
+            image::Rgba([r, g, b, 255])
+        })
+    })
+}
+
+fn srgb_to_linear(srgb: u8) -> f32 {
+    let srgb = srgb as f32 / 255.0;
+    if srgb <= 0.04045 {
+        srgb / 12.92
+    } else {
+        ((srgb + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// The next mip level of a square sRGB image: each 2x2 block averaged in
+/// linear color
+fn downsample(image: &RgbaImage) -> RgbaImage {
+    let size = (image.width() / 2).max(1);
+    let last = image.width() - 1;
+    RgbaImage::from_fn(size, size, |x, y| {
+        let mut sum = [0.0; 4];
+        for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            let texel = image
+                .get_pixel((2 * x + dx).min(last), (2 * y + dy).min(last))
+                .0;
+            for channel in 0..3 {
+                sum[channel] += srgb_to_linear(texel[channel]);
+            }
+            sum[3] += texel[3] as f32 / 255.0;
+        }
+        let [r, g, b, a] = sum.map(|total| total / 4.0);
+        image::Rgba([
+            linear_to_srgb(r),
+            linear_to_srgb(g),
+            linear_to_srgb(b),
+            (a * 255.0).round() as u8,
+        ])
+    })
+}
+
+/// Load `px`, `nx`, `py`, `ny`, `pz` and `nz` (PNG or JPEG) from `dir`
+///
+/// The faces must be square and all the same size.
+pub fn load_faces(dir: &Path) -> Result<[RgbaImage; 6], String> {
+    let mut faces = Vec::with_capacity(6);
+    for name in FACE_NAMES {
+        let path = ["png", "jpg", "jpeg"]
+            .iter()
+            .map(|extension| dir.join(name).with_extension(extension))
+            .find(|path| path.exists())
+            .ok_or_else(|| format!("{} has no {name}.png or {name}.jpg", dir.display()))?;
+        let image = texture::load(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        faces.push(image);
+    }
+
+    let size = faces[0].width();
+    if faces.iter().any(|face| face.dimensions() != (size, size)) {
+        return Err(format!(
+            "The faces in {} must be square and the same size",
+            dir.display()
+        ));
+    }
+    Ok(faces.try_into().expect("six faces"))
+}
+
+/// Environment bindings and `env_radiance`, prepended to the demo shader
+///
+/// `mip_level_count` is that of the cubemap, so roughness 1 samples its
+/// smallest level.
+pub fn with_env_wgsl(shader: &str, mip_level_count: u32) -> String {
+    format!(
+        r#"
+@group(3) @binding(0) var env_map: texture_cube<f32>;
+@group(3) @binding(1) var env_sampler: sampler;
+
+const ENV_MAX_LOD: f32 = {max_lod:.1};
+
+// Light arriving from `dir`, blurred more the rougher the surface
+fn env_radiance(dir: vec3<f32>, roughness: f32) -> vec3<f32> {{
+    let lod = clamp(roughness, 0.0, 1.0) * ENV_MAX_LOD;
+    return textureSampleLevel(env_map, env_sampler, dir, lod).rgb;
+}}
+
+{shader}"#,
+        max_lod = mip_level_count.saturating_sub(1) as f32
+    )
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SkyUniforms {
+    /// Clip space to world directions (the view without its translation)
+    inv_view_proj: [[f32; 4]; 4],
+}
+
+/// Cubemap, its bind group for the demo shader and the sky pass
+pub struct Skybox {
+    mip_level_count: u32,
+    pipeline: wgpu::RenderPipeline,
+    buffer: wgpu::Buffer,
+    sky_bind_group: wgpu::BindGroup,
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Skybox {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        faces: &[RgbaImage; 6],
+        target: &TargetDesc,
+    ) -> Self {
+        let size = faces[0].width();
+        let mip_level_count = texture::mip_level_count(size, size);
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Skybox Cubemap"),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 6,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: TEXTURE_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        for (layer, face) in faces.iter().enumerate() {
+            let mut level = face.clone();
+            for mip_level in 0..mip_level_count {
+                if mip_level > 0 {
+                    level = downsample(&level);
+                }
+                let size = level.width();
+                queue.write_texture(
+                    wgpu::TexelCopyTextureInfo {
+                        texture: &texture,
+                        mip_level,
+                        origin: wgpu::Origin3d {
+                            x: 0,
+                            y: 0,
+                            z: layer as u32,
+                        },
+                        aspect: wgpu::TextureAspect::All,
+                    },
+                    level.as_raw(),
+                    wgpu::TexelCopyBufferLayout {
+                        offset: 0,
+                        bytes_per_row: Some(4 * size),
+                        rows_per_image: Some(size),
+                    },
+                    wgpu::Extent3d {
+                        width: size,
+                        height: size,
+                        depth_or_array_layers: 1,
+                    },
+                );
+            }
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Skybox View"),
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Skybox Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let cube_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::Cube,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let sampler_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Environment Bind Group Layout"),
+            entries: &[cube_entry(0), sampler_entry(1)],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Environment Bind Group"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Skybox Uniform Buffer"),
+            contents: bytemuck::bytes_of(&SkyUniforms {
+                inv_view_proj: Mat4::IDENTITY.to_cols_array_2d(),
+            }),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let sky_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Skybox Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                cube_entry(1),
+                sampler_entry(2),
+            ],
+        });
+        let sky_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Skybox Bind Group"),
+            layout: &sky_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Skybox Shader"),
+            source: wgpu::ShaderSource::Wgsl(SKY_SHADER.into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Skybox Pipeline Layout"),
+            bind_group_layouts: &[&sky_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Skybox Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(target.format.into())],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            // Drawn first and behind everything: no depth test or write
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: target.sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            mip_level_count,
+            pipeline,
+            buffer,
+            sky_bind_group,
+            layout,
+            bind_group,
+        }
+    }
+
+    /// Mip levels of the cubemap, for `with_env_wgsl`
+    pub fn mip_level_count(&self) -> u32 {
+        self.mip_level_count
+    }
+
+    /// Layout of the bind group at `ENV_GROUP`
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    /// Upload the sky's view for this frame
+    pub fn update(&self, queue: &wgpu::Queue, frame: &Frame) {
+        let rotation = Mat4::from_mat3(Mat3::from_mat4(frame.view));
+        let uniforms = SkyUniforms {
+            inv_view_proj: (frame.proj * rotation).inverse().to_cols_array_2d(),
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(&uniforms));
+    }
+
+    /// Draw the sky into the main pass, before anything else
+    pub fn draw(&self, pass: &mut wgpu::RenderPass<'_>) {
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.sky_bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+// Fullscreen triangle; each pixel looks up the direction it sees
+const SKY_SHADER: &str = r#"
+struct SkyUniforms {
+    inv_view_proj: mat4x4<f32>,
+};
+
+@group(0) @binding(0) var<uniform> sky: SkyUniforms;
+@group(0) @binding(1) var sky_map: texture_cube<f32>;
+@group(0) @binding(2) var sky_sampler: sampler;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.ndc = uv * 2.0 - 1.0;
+    out.position = vec4<f32>(out.ndc, 1.0, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let far = sky.inv_view_proj * vec4<f32>(in.ndc, 1.0, 1.0);
+    let dir = normalize(far.xyz / far.w);
+    return vec4<f32>(textureSampleLevel(sky_map, sky_sampler, dir, 0.0).rgb, 1.0);
+}
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_face_centers_point_along_axes() {
+        let axes = [Vec3::X, -Vec3::X, Vec3::Y, -Vec3::Y, Vec3::Z, -Vec3::Z];
+        for (face, axis) in axes.iter().enumerate() {
+            assert!(face_direction(face, 0.0, 0.0).abs_diff_eq(*axis, 1e-6));
+        }
+    }
+
+    #[test]
+    fn test_faces_meet_at_edges() {
+        // Right edge of +Z is the left edge of +X; top of +Z is the bottom of +Y
+        assert!(face_direction(4, 1.0, 0.0).abs_diff_eq(face_direction(0, -1.0, 0.0), 1e-6));
+        assert!(face_direction(4, 0.0, -1.0).abs_diff_eq(face_direction(2, 0.0, 1.0), 1e-6));
+    }
+
+    #[test]
+    fn test_procedural_sky() {
+        let sun = Vec3::new(0.0, 1.0, 0.0);
+        let faces = procedural(16, sun);
+        assert!(faces.iter().all(|face| face.dimensions() == (16, 16)));
+
+        // The sun is overhead, the ground darker than the sky
+        let center = |face: &RgbaImage| face.get_pixel(8, 8).0;
+        assert!(center(&faces[2])[0] > center(&faces[4])[0]);
+        assert!(center(&faces[3])[2] < center(&faces[4])[2]);
+        assert_eq!(center(&faces[4])[3], 255);
+    }
+
+    #[test]
+    fn test_downsample_averages_linear_color() {
+        let black = image::Rgba([0, 0, 0, 255]);
+        let white = image::Rgba([255, 255, 255, 255]);
+        let image = RgbaImage::from_fn(4, 4, |x, _| if x % 2 == 0 { black } else { white });
+
+        let level = downsample(&image);
+        assert_eq!(level.dimensions(), (2, 2));
+        // Half the light of white, not the sRGB midpoint 128
+        assert_eq!(level.get_pixel(0, 0).0, [188, 188, 188, 255]);
+        assert_eq!(downsample(&downsample(&level)).dimensions(), (1, 1));
+    }
+
+    #[test]
+    fn test_with_env_wgsl() {
+        let shader = with_env_wgsl("fn main() {}", 9);
+        assert!(shader.contains("@group(3) @binding(0) var env_map: texture_cube<f32>;"));
+        assert!(shader.contains("const ENV_MAX_LOD: f32 = 8.0;"));
+        assert!(shader.ends_with("fn main() {}"));
+    }
+
+    #[test]
+    fn test_load_faces_reports_missing_files() {
+        let error = load_faces(Path::new("/nonexistent")).unwrap_err();
+        assert!(error.contains("px.png"));
+    }
+}
@@ -0,0 +1,190 @@
+//! Uniform buffer with one region per frame in flight
+//!
+//! With a single buffer, every frame overwrites uniforms that the frames still
+//! queued on the GPU may be reading, and the upload has to wait until they are
+//! done. A `UniformRing` instead splits its buffer into one region per frame
+//! that can be in flight: the surface's `desired_maximum_frame_latency` queued
+//! frames plus the one being recorded. Each `write` moves on to the next
+//! region, which the GPU finished with frames ago.
+//!
+//! A region holds `capacity` slots of one uniform struct, `stride` bytes
+//! apart; draws select their slot with a dynamic offset from `offset`.
+
+use wgpu::util::align_to;
+
+/// Regions needed for a surface configured with `max_frame_latency`
+pub fn frames_in_flight(max_frame_latency: u32) -> usize {
+    max_frame_latency.max(1) as usize + 1
+}
+
+/// Distance between slots of `size` bytes for a device's dynamic offset
+/// `alignment`
+pub fn stride(size: wgpu::BufferAddress, alignment: u32) -> wgpu::BufferAddress {
+    align_to(size, alignment as wgpu::BufferAddress)
+}
+
+/// Byte offset of `slot` in the region of `frame`
+fn slot_offset(
+    stride: wgpu::BufferAddress,
+    capacity: usize,
+    frame: usize,
+    slot: usize,
+) -> wgpu::BufferAddress {
+    (frame * capacity + slot) as wgpu::BufferAddress * stride
+}
+
+/// Ring of per-frame regions in one uniform buffer, bound at `@binding(0)`
+pub struct UniformRing {
+    label: &'static str,
+    layout: wgpu::BindGroupLayout,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    /// Size of the uniform struct
+    size: wgpu::BufferAddress,
+    /// Distance between slots: `size` rounded up to the offset alignment
+    stride: wgpu::BufferAddress,
+    /// Slots per region
+    capacity: usize,
+    frames: usize,
+    /// Region written by the current frame
+    frame: usize,
+}
+
+impl UniformRing {
+    /// `frames` regions of `capacity` slots of `size` bytes each
+    pub fn new(
+        device: &wgpu::Device,
+        label: &'static str,
+        size: wgpu::BufferAddress,
+        capacity: usize,
+        frames: usize,
+    ) -> Self {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some(label),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: wgpu::BufferSize::new(size),
+                },
+                count: None,
+            }],
+        });
+        let stride = stride(size, device.limits().min_uniform_buffer_offset_alignment);
+        let capacity = capacity.max(1);
+        let frames = frames.max(1);
+        let (buffer, bind_group) = create_buffer(
+            device,
+            &layout,
+            label,
+            size,
+            stride * (capacity * frames) as u64,
+        );
+
+        Self {
+            label,
+            layout,
+            buffer,
+            bind_group,
+            size,
+            stride,
+            capacity,
+            frames,
+            frame: 0,
+        }
+    }
+
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    pub fn stride(&self) -> wgpu::BufferAddress {
+        self.stride
+    }
+
+    /// Move to the next region and upload `data`, slots laid out at `stride`
+    ///
+    /// The buffer grows if `data` holds more slots than a region.
+    pub fn write(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, data: &[u8]) {
+        self.frame = (self.frame + 1) % self.frames;
+
+        let slots = (data.len() as wgpu::BufferAddress).div_ceil(self.stride) as usize;
+        if slots > self.capacity {
+            // The old buffer stays alive until the frames using it are done
+            self.capacity = slots.next_power_of_two();
+            (self.buffer, self.bind_group) = create_buffer(
+                device,
+                &self.layout,
+                self.label,
+                self.size,
+                self.stride * (self.capacity * self.frames) as u64,
+            );
+        }
+
+        if !data.is_empty() {
+            queue.write_buffer(&self.buffer, self.offset(0) as wgpu::BufferAddress, data);
+        }
+    }
+
+    /// Dynamic offset of `slot` in the current frame's region
+    pub fn offset(&self, slot: usize) -> u32 {
+        slot_offset(self.stride, self.capacity, self.frame, slot) as u32
+    }
+}
+
+/// Buffer of `total` bytes, bound one `size` slot at a time
+fn create_buffer(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    label: &'static str,
+    size: wgpu::BufferAddress,
+    total: wgpu::BufferAddress,
+) -> (wgpu::Buffer, wgpu::BindGroup) {
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size: total,
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some(label),
+        layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                buffer: &buffer,
+                offset: 0,
+                size: wgpu::BufferSize::new(size),
+            }),
+        }],
+    });
+    (buffer, bind_group)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_in_flight() {
+        assert_eq!(frames_in_flight(2), 3);
+        assert_eq!(frames_in_flight(1), 2);
+        // wgpu treats 0 as 1
+        assert_eq!(frames_in_flight(0), 2);
+    }
+
+    #[test]
+    fn test_slot_offset() {
+        assert_eq!(slot_offset(256, 4, 0, 0), 0);
+        assert_eq!(slot_offset(256, 4, 0, 3), 768);
+        // Regions follow each other without overlapping
+        assert_eq!(slot_offset(256, 4, 1, 0), 1024);
+        assert_eq!(slot_offset(256, 4, 2, 1), 2304);
+    }
+}